@@ -0,0 +1,32 @@
+// `cargo bench --features bench [-- <filter>]` on the first GPU `HeadlessContext` finds.
+// Results are compared with the last run in target/vulky-bench.
+
+use vulky::{
+    bench::{self, BenchSettings, Bencher},
+    headless::HeadlessContext,
+};
+
+/// Slowdown against the last run that is reported as a regression.
+const REGRESSION_THRESHOLD: f64 = 0.1;
+
+fn main() {
+    // cargo passes --bench, the first other argument filters by name
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let mut bencher = Bencher::new(BenchSettings::default()).filter(filter);
+
+    unsafe {
+        let context = match HeadlessContext::new(false) {
+            Ok(context) => context,
+            Err(e) => panic!("No device to benchmark on: {e}"),
+        };
+        let result = bench::run_all(&mut bencher, &context);
+        context.destroy();
+        if let Err(e) = result {
+            panic!("{e}");
+        }
+    }
+
+    for regression in bencher.regressions(REGRESSION_THRESHOLD) {
+        println!("regressed: {}", regression.name);
+    }
+}
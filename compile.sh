@@ -1,2 +1,38 @@
 glslc shaders/shader.vert -o shaders/spv/vert.spv
-glslc shaders/shader.frag -o shaders/spv/frag.spv
\ No newline at end of file
+glslc shaders/shader.frag -o shaders/spv/frag.spv
+glslc shaders/ui_sprite.vert -o shaders/spv/ui_sprite_vert.spv
+glslc shaders/ui_sprite.frag -o shaders/spv/ui_sprite_frag.spv
+glslc shaders/ui_rect.vert -o shaders/spv/ui_rect_vert.spv
+glslc shaders/ui_rect.frag -o shaders/spv/ui_rect_frag.spv
+glslc shaders/pbr.vert -o shaders/spv/pbr_vert.spv
+glslc shaders/pbr.frag -o shaders/spv/pbr_frag.spv
+mkdir -p shaders/spv/variants
+glslc shaders/pbr.vert -o shaders/spv/variants/pbr_vert-65975e9ea1b4cdef.spv
+glslc -DNORMAL_MAP shaders/pbr.vert -o shaders/spv/variants/pbr_vert-65975e9ea1b4cdef-NORMAL_MAP.spv
+glslc -DSKINNED shaders/pbr.vert -o shaders/spv/variants/pbr_vert-65975e9ea1b4cdef-SKINNED.spv
+glslc -DNORMAL_MAP -DSKINNED shaders/pbr.vert -o shaders/spv/variants/pbr_vert-65975e9ea1b4cdef-NORMAL_MAP-SKINNED.spv
+glslc shaders/pbr.frag -o shaders/spv/variants/pbr_frag-2352e81acb8fc220.spv
+glslc -DNORMAL_MAP shaders/pbr.frag -o shaders/spv/variants/pbr_frag-2352e81acb8fc220-NORMAL_MAP.spv
+glslc -DSKINNED shaders/pbr.frag -o shaders/spv/variants/pbr_frag-2352e81acb8fc220-SKINNED.spv
+glslc -DNORMAL_MAP -DSKINNED shaders/pbr.frag -o shaders/spv/variants/pbr_frag-2352e81acb8fc220-NORMAL_MAP-SKINNED.spv
+glslc shaders/fullscreen.vert -o shaders/spv/fullscreen_vert.spv
+glslc shaders/blit.frag -o shaders/spv/blit_frag.spv
+glslc shaders/checkerboard_mask.frag -o shaders/spv/checkerboard_mask_frag.spv
+glslc shaders/checkerboard_resolve.frag -o shaders/spv/checkerboard_resolve_frag.spv
+glslc shaders/depth_downsample.frag -o shaders/spv/depth_downsample_frag.spv
+glslc shaders/bilateral_upsample.frag -o shaders/spv/bilateral_upsample_frag.spv
+glslc shaders/grid.vert -o shaders/spv/grid_vert.spv
+glslc shaders/grid.frag -o shaders/spv/grid_frag.spv
+glslc shaders/toon.frag -o shaders/spv/toon_frag.spv
+glslc shaders/weather_particles.vert -o shaders/spv/weather_particles_vert.spv
+glslc shaders/weather_particles.frag -o shaders/spv/weather_particles_frag.spv
+glslc shaders/weather_droplets.frag -o shaders/spv/weather_droplets_frag.spv
+glslc shaders/lens_flare.frag -o shaders/spv/lens_flare_frag.spv
+glslc shaders/chromatic_aberration.frag -o shaders/spv/chromatic_aberration_frag.spv
+glslc shaders/vignette.frag -o shaders/spv/vignette_frag.spv
+glslc shaders/film_grain.frag -o shaders/spv/film_grain_frag.spv
+glslc shaders/outline.frag -o shaders/spv/outline_frag.spv
+glslc shaders/custom_surface.frag -o shaders/spv/custom_surface_frag.spv
+glslc shaders/crowd.vert -o shaders/spv/crowd_vert.spv
+glslc -DMATERIAL_SET=2 -DNORMAL_MAP shaders/pbr.frag -o shaders/spv/pbr_crowd_frag.spv
+glslc shaders/frame_hash.comp -o shaders/spv/frame_hash_comp.spv
\ No newline at end of file
@@ -0,0 +1,52 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+layout(set = 0, binding = 2) uniform sampler2D halfEffect;
+layout(set = 0, binding = 3) uniform sampler2D halfDepth;
+
+layout(push_constant) uniform Push {
+    // 0 replace, 1 multiply, 2 add, 3 alpha blend
+    uint composite;
+    // depth difference at which a half resolution sample loses most of its weight
+    float depthSigma;
+} push;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    ivec2 halfSize = textureSize(halfEffect, 0);
+    float fullDepth = texelFetch(depth, ivec2(gl_FragCoord.xy), 0).r;
+
+    // the four half resolution texels around this pixel, weighted bilinearly and by depth similarity
+    vec2 halfPosition = fragUv * vec2(halfSize) - 0.5;
+    ivec2 base = ivec2(floor(halfPosition));
+    vec2 f = fract(halfPosition);
+
+    vec4 sum = vec4(0.0);
+    float weightSum = 0.0;
+    for (int y = 0; y < 2; y++) {
+        for (int x = 0; x < 2; x++) {
+            ivec2 p = clamp(base + ivec2(x, y), ivec2(0), halfSize - 1);
+            float bilinear = (x == 0 ? 1.0 - f.x : f.x) * (y == 0 ? 1.0 - f.y : f.y);
+            float difference = abs(texelFetch(halfDepth, p, 0).r - fullDepth);
+            float weight = bilinear / (1e-4 + difference / push.depthSigma);
+            sum += texelFetch(halfEffect, p, 0) * weight;
+            weightSum += weight;
+        }
+    }
+    vec4 effect = sum / max(weightSum, 1e-6);
+
+    vec4 scene = texelFetch(color, ivec2(gl_FragCoord.xy), 0);
+    if (push.composite == 0u) {
+        outColor = effect;
+    } else if (push.composite == 1u) {
+        outColor = scene * effect;
+    } else if (push.composite == 2u) {
+        outColor = vec4(scene.rgb + effect.rgb, scene.a);
+    } else {
+        outColor = vec4(mix(scene.rgb, effect.rgb, effect.a), scene.a);
+    }
+}
@@ -0,0 +1,19 @@
+#version 450
+
+layout(push_constant) uniform Push {
+    // pixels with (x + y) % 2 == parity are rendered this frame
+    uint parity;
+} push;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    if (uint((pixel.x + pixel.y) & 1) == push.parity) {
+        discard;
+    }
+
+    // the skipped half gets the nearest depth, so the depth test rejects every later fragment there
+    gl_FragDepth = 0.0;
+    outColor = vec4(0.0);
+}
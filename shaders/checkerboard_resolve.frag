@@ -0,0 +1,51 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D current;
+layout(set = 0, binding = 1) uniform sampler2D history;
+
+layout(push_constant) uniform Push {
+    uint parity;
+    uint historyValid;
+} push;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+ivec2 pixel;
+ivec2 size;
+
+// mirrored at the borders, so the fetched pixel always has the rendered parity
+vec4 neighbour(ivec2 offset) {
+    ivec2 p = pixel + offset;
+    if (any(lessThan(p, ivec2(0))) || any(greaterThanEqual(p, size))) {
+        p = pixel - offset;
+    }
+    return texelFetch(current, p, 0);
+}
+
+void main() {
+    pixel = ivec2(gl_FragCoord.xy);
+    size = textureSize(current, 0);
+
+    if (uint((pixel.x + pixel.y) & 1) == push.parity) {
+        outColor = texelFetch(current, pixel, 0);
+        return;
+    }
+
+    // the four direct neighbours were rendered this frame
+    vec4 left = neighbour(ivec2(-1, 0));
+    vec4 right = neighbour(ivec2(1, 0));
+    vec4 up = neighbour(ivec2(0, -1));
+    vec4 down = neighbour(ivec2(0, 1));
+
+    if (push.historyValid == 0u) {
+        outColor = (left + right + up + down) * 0.25;
+        return;
+    }
+
+    // last frame rendered this pixel, clamping to the neighbours hides most ghosting of moving content
+    vec4 minColor = min(min(left, right), min(up, down));
+    vec4 maxColor = max(max(left, right), max(up, down));
+    outColor = clamp(texelFetch(history, pixel, 0), minColor, maxColor);
+}
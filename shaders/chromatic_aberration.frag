@@ -0,0 +1,23 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform Aberration {
+    // uv offset of red and blue at the corners
+    float strength;
+    // 0 shifts evenly, 1 in proportion to the distance from the center, higher values keep the center sharp
+    float falloff;
+} aberration;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 fromCenter = fragUv - vec2(0.5);
+    float amount = pow(clamp(length(fromCenter) / 0.7071, 0.0, 1.0), aberration.falloff);
+    vec2 offset = normalize(fromCenter + 1e-6) * aberration.strength * amount;
+    vec4 center = texture(color, fragUv);
+    outColor = vec4(texture(color, fragUv - offset).r, center.g, texture(color, fragUv + offset).b, center.a);
+}
@@ -0,0 +1,98 @@
+#version 450
+
+// skinned instancing, every instance plays a baked clip at its own time
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+struct Clip {
+    uint first_frame;
+    uint frame_count;
+    float frames_per_second;
+    uint looping;
+};
+
+// the rows of a 3x4 matrix per joint and frame, frame after frame
+layout(set = 1, binding = 0) readonly buffer JointMatrices {
+    vec4 rows[];
+} joint_matrices;
+
+layout(set = 1, binding = 1) readonly buffer Clips {
+    Clip clips[];
+} clips;
+
+layout(push_constant) uniform Skeleton {
+    uint joint_count;
+} skeleton;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUv;
+layout(location = 3) in vec2 inUv1;
+layout(location = 4) in vec2 inUv2;
+layout(location = 5) in vec2 inUv3;
+layout(location = 6) in vec4 inColor;
+layout(location = 7) in uvec4 inJoints;
+layout(location = 8) in vec4 inWeights;
+
+// per instance
+layout(location = 9) in mat4 inModel;
+layout(location = 13) in uint inClip;
+layout(location = 14) in float inTime;
+
+layout(location = 0) out vec3 fragWorldPosition;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec2 fragUv;
+layout(location = 3) out vec2 fragUv1;
+layout(location = 4) out vec2 fragUv2;
+layout(location = 5) out vec2 fragUv3;
+layout(location = 6) out vec4 fragColor;
+
+mat4 joint_matrix(uint frame_index, uint joint) {
+    uint row = (frame_index * skeleton.joint_count + joint) * 3;
+    return transpose(mat4(
+        joint_matrices.rows[row],
+        joint_matrices.rows[row + 1],
+        joint_matrices.rows[row + 2],
+        vec4(0.0, 0.0, 0.0, 1.0)
+    ));
+}
+
+void main() {
+    Clip clip = clips.clips[inClip];
+    // the last baked frame is the end of the clip, for looping clips the same pose as the first one
+    float duration = float(clip.frame_count - 1) / clip.frames_per_second;
+    float time = clip.looping != 0 ? mod(inTime, duration) : clamp(inTime, 0.0, duration);
+    float frame_position = time * clip.frames_per_second;
+    uint frame_a = min(uint(frame_position), clip.frame_count - 1);
+    uint frame_b = min(frame_a + 1, clip.frame_count - 1);
+    float blend = frame_position - float(frame_a);
+
+    mat4 skin = mat4(0.0);
+    for (int i = 0; i < 4; i++) {
+        uint joint = inJoints[i];
+        mat4 a = joint_matrix(clip.first_frame + frame_a, joint);
+        mat4 b = joint_matrix(clip.first_frame + frame_b, joint);
+        skin += inWeights[i] * mix(a, b, blend);
+    }
+
+    mat4 model = inModel * skin;
+    vec4 world_position = model * vec4(inPosition, 1.0);
+
+    fragWorldPosition = world_position.xyz;
+    fragNormal = mat3(transpose(inverse(model))) * inNormal;
+    fragUv = inUv;
+    fragUv1 = inUv1;
+    fragUv2 = inUv2;
+    fragUv3 = inUv3;
+    fragColor = inColor;
+
+    gl_Position = frame.projection * frame.view * world_position;
+}
@@ -0,0 +1,43 @@
+#version 450
+#extension GL_GOOGLE_include_directive : require
+
+// an example custom shading model: wrapped diffuse with a fresnel tinted sheen, like cloth
+#include "surface.glsl"
+
+layout(set = 1, binding = 0) uniform MaterialParams {
+    vec4 base_color_factor;
+    // rgb, w strength
+    vec4 sheen_color;
+    // how far the light wraps past the terminator, 0..1
+    float wrap;
+    float alpha_cutoff;
+    int base_color_uv_set;
+} params;
+
+layout(set = 1, binding = 1) uniform sampler2D base_color_texture;
+
+vec4 shade_surface(Surface surface) {
+    vec2 uv = surface.uvs[clamp(params.base_color_uv_set, 0, 3)];
+    vec4 base_color = texture(base_color_texture, uv) * params.base_color_factor * surface.color;
+    // derivatives are only defined before the discard
+    float alpha_width = max(fwidth(base_color.a), 0.0001);
+    if (base_color.a < params.alpha_cutoff) {
+        discard;
+    }
+    if (params.alpha_cutoff > 0.0) {
+        // sharpened to a one pixel ramp at the cutoff, alpha to coverage turns it into an antialiased edge
+        base_color.a = clamp((base_color.a - params.alpha_cutoff) / alpha_width + 0.5, 0.0, 1.0);
+    }
+
+    Light light = main_light();
+    float n_dot_l = dot(surface.normal, light.direction);
+    float diffuse = max((n_dot_l + params.wrap) / (1.0 + params.wrap), 0.0);
+    float shadow = light_shadow(light, surface.position);
+
+    float fresnel = pow(1.0 - max(dot(surface.normal, surface.view_dir), 0.0), 5.0);
+    vec3 sheen = params.sheen_color.rgb * params.sheen_color.w * fresnel;
+
+    vec3 color = (base_color.rgb * diffuse + sheen) * light.radiance * shadow;
+    color += base_color.rgb * ambient_irradiance(surface.normal);
+    return vec4(tonemap(color), base_color.a);
+}
@@ -0,0 +1,22 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D depth;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outDepth;
+
+// keeps the closest depth of each 2x2 block, so thin foreground edges survive
+void main() {
+    ivec2 size = textureSize(depth, 0);
+    ivec2 base = ivec2(gl_FragCoord.xy) * 2;
+
+    float closest = 1.0;
+    for (int y = 0; y < 2; y++) {
+        for (int x = 0; x < 2; x++) {
+            ivec2 p = min(base + ivec2(x, y), size - 1);
+            closest = min(closest, texelFetch(depth, p, 0).r);
+        }
+    }
+    outDepth = vec4(closest);
+}
@@ -0,0 +1,33 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform Grain {
+    float intensity;
+    // pixels per grain
+    float size;
+    // 0 grains everything the same, 1 keeps the highlights clean like film
+    float luminanceResponse;
+    // changes every frame so the grain moves
+    float seed;
+} grain;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+float hash(vec2 p) {
+    vec3 p3 = fract(vec3(p.xyx) * 0.1031);
+    p3 += dot(p3, p3.yzx + 33.33);
+    return fract((p3.x + p3.y) * p3.z);
+}
+
+void main() {
+    vec4 c = texture(color, fragUv);
+    vec2 cell = floor(gl_FragCoord.xy / max(grain.size, 1.0));
+    float noise = hash(cell + grain.seed * 17.0) - 0.5;
+    float luminance = dot(c.rgb, vec3(0.2126, 0.7152, 0.0722));
+    float weight = mix(1.0, 1.0 - clamp(luminance, 0.0, 1.0), grain.luminanceResponse);
+    outColor = vec4(max(c.rgb + noise * grain.intensity * weight, 0.0), c.a);
+}
@@ -0,0 +1,75 @@
+#version 450
+
+// world space grid on the y = 0 plane, lines stay one pixel wide at any distance
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+layout(push_constant) uniform Grid {
+    vec4 minor_color;
+    vec4 major_color;
+    vec4 x_axis_color;
+    vec4 z_axis_color;
+    float cell_size;
+    float major_every;
+    float fade_distance;
+    float line_width;
+} grid;
+
+layout(location = 0) in vec3 nearPoint;
+layout(location = 1) in vec3 farPoint;
+
+layout(location = 0) out vec4 outColor;
+
+// coverage of the closest line of a grid with `spacing`, antialiased over `line_width` pixels
+float lines(vec2 position, float spacing) {
+    vec2 coord = position / spacing;
+    vec2 derivative = fwidth(coord);
+    vec2 distance = abs(fract(coord - 0.5) - 0.5) / derivative;
+    return 1.0 - min(min(distance.x, distance.y) / grid.line_width, 1.0);
+}
+
+float axis(float coord) {
+    float derivative = fwidth(coord);
+    return 1.0 - min(abs(coord) / (derivative * grid.line_width), 1.0);
+}
+
+void main() {
+    float t = -nearPoint.y / (farPoint.y - nearPoint.y);
+    if (t <= 0.0) {
+        discard;
+    }
+    vec3 position = nearPoint + t * (farPoint - nearPoint);
+
+    vec4 clip = frame.projection * frame.view * vec4(position, 1.0);
+    gl_FragDepth = clip.z / clip.w;
+
+    float minor = lines(position.xz, grid.cell_size) * grid.minor_color.a;
+    float major = lines(position.xz, grid.cell_size * grid.major_every) * grid.major_color.a;
+    vec4 color = vec4(mix(grid.minor_color.rgb, grid.major_color.rgb, major), max(minor, major));
+
+    // the x axis runs along z = 0, an alpha of 0 hides an axis
+    float x_axis = axis(position.z) * grid.x_axis_color.a;
+    float z_axis = axis(position.x) * grid.z_axis_color.a;
+    color.rgb = mix(color.rgb, grid.x_axis_color.rgb, x_axis);
+    color.rgb = mix(color.rgb, grid.z_axis_color.rgb, z_axis);
+    color.a = max(color.a, max(x_axis, z_axis));
+
+    float distance = length(position.xz - frame.camera_position.xz);
+    float fade = 1.0 - smoothstep(grid.fade_distance * 0.5, grid.fade_distance, distance);
+    // grazing angles alias no matter what, fade them out too
+    vec3 view_direction = normalize(farPoint - nearPoint);
+    fade *= smoothstep(0.0, 0.15, abs(view_direction.y));
+
+    outColor = vec4(color.rgb, color.a * fade);
+    if (outColor.a <= 0.0) {
+        discard;
+    }
+}
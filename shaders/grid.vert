@@ -0,0 +1,29 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+layout(location = 0) out vec3 nearPoint;
+layout(location = 1) out vec3 farPoint;
+
+vec3 unproject(vec2 ndc, float depth, mat4 inverseViewProjection) {
+    vec4 point = inverseViewProjection * vec4(ndc, depth, 1.0);
+    return point.xyz / point.w;
+}
+
+// fullscreen triangle, every pixel marches a ray from the near to the far plane
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    vec2 ndc = uv * 2.0 - 1.0;
+    mat4 inverseViewProjection = inverse(frame.projection * frame.view);
+    nearPoint = unproject(ndc, 0.0, inverseViewProjection);
+    farPoint = unproject(ndc, 1.0, inverseViewProjection);
+    gl_Position = vec4(ndc, 0.0, 1.0);
+}
@@ -0,0 +1,55 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform Flare {
+    float intensity;
+    // brightness above which pixels cause flares
+    float threshold;
+    // distance between ghosts as a share of the way to the center
+    float ghostSpacing;
+    int ghostCount;
+    float haloRadius;
+    float haloWidth;
+    // uv offset between the color channels of ghosts and halo
+    float chromaticDistortion;
+    float pad;
+} flare;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 bright(vec2 uv) {
+    vec3 c = texture(color, uv).rgb;
+    float peak = max(c.r, max(c.g, c.b));
+    return c * max(peak - flare.threshold, 0.0) / max(peak, 1e-4);
+}
+
+vec3 bright_distorted(vec2 uv, vec2 direction) {
+    vec2 offset = direction * flare.chromaticDistortion;
+    return vec3(bright(uv + offset).r, bright(uv).g, bright(uv - offset).b);
+}
+
+// screen space ghosts and a halo, bright spots are mirrored through the center like in a real lens
+void main() {
+    vec2 uv = vec2(1.0) - fragUv;
+    vec2 ghostVector = (vec2(0.5) - uv) * flare.ghostSpacing;
+    vec2 direction = length(ghostVector) > 1e-4 ? normalize(ghostVector) : vec2(0.0);
+
+    vec3 result = vec3(0.0);
+    for (int i = 0; i < flare.ghostCount; i++) {
+        vec2 position = fract(uv + ghostVector * float(i));
+        // only ghosts of the middle of the screen are strong, which hides the wrap around
+        float weight = pow(1.0 - clamp(length(vec2(0.5) - position) / 0.7071, 0.0, 1.0), 10.0);
+        result += bright_distorted(position, direction) * weight;
+    }
+
+    vec2 haloPosition = fract(uv + direction * flare.haloRadius);
+    float haloDistance = length(vec2(0.5) - haloPosition) / 0.7071;
+    float haloWeight = pow(1.0 - clamp(abs(haloDistance - flare.haloRadius) / max(flare.haloWidth, 1e-4), 0.0, 1.0), 5.0);
+    result += bright_distorted(haloPosition, direction) * haloWeight;
+
+    outColor = vec4(result * flare.intensity, 1.0);
+}
@@ -0,0 +1,72 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform Outline {
+    mat4 inverseProjection;
+    // rgb, w opacity
+    vec4 color;
+    // x thickness in pixels, y relative depth difference, z normal difference as 1 - cos, w fade distance or 0
+    vec4 params;
+} outline;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+vec3 view_position(vec2 uv) {
+    float z = texture(depth, uv).r;
+    vec4 p = outline.inverseProjection * vec4(uv * 2.0 - 1.0, z, 1.0);
+    return p.xyz / p.w;
+}
+
+// the normal of the surface at uv from the closer neighbour on each axis, so it doesn't bend over edges
+vec3 view_normal(vec2 uv, vec3 center, vec2 texel) {
+    vec3 left = center - view_position(uv - vec2(texel.x, 0.0));
+    vec3 right = view_position(uv + vec2(texel.x, 0.0)) - center;
+    vec3 up = center - view_position(uv - vec2(0.0, texel.y));
+    vec3 down = view_position(uv + vec2(0.0, texel.y)) - center;
+    vec3 dx = abs(left.z) < abs(right.z) ? left : right;
+    vec3 dy = abs(up.z) < abs(down.z) ? up : down;
+    return normalize(cross(dy, dx));
+}
+
+void main() {
+    vec4 c = texture(color, fragUv);
+    vec2 texel = 1.0 / vec2(textureSize(depth, 0));
+    float center_depth = texture(depth, fragUv).r;
+    vec3 center = view_position(fragUv);
+    bool center_sky = center_depth >= 1.0;
+    vec3 center_normal = view_normal(fragUv, center, texel);
+
+    vec2 offsets[4] = vec2[](vec2(1.0, 0.0), vec2(-1.0, 0.0), vec2(0.0, 1.0), vec2(0.0, -1.0));
+    float edge = 0.0;
+    // the nearest surface of the edge decides how far away the outline is
+    float nearest = center_sky ? 1e30 : -center.z;
+    for (int i = 0; i < 4; i++) {
+        vec2 uv = fragUv + offsets[i] * texel * outline.params.x;
+        bool sky = texture(depth, uv).r >= 1.0;
+        if (sky && center_sky) {
+            continue;
+        }
+        vec3 neighbour = view_position(uv);
+        if (!sky) {
+            nearest = min(nearest, -neighbour.z);
+        }
+        // the sky behind an object always makes an edge
+        if (sky != center_sky) {
+            edge = 1.0;
+            continue;
+        }
+        float depth_difference = abs(neighbour.z - center.z) / max(abs(center.z), 1e-4);
+        edge = max(edge, step(outline.params.y, depth_difference));
+        vec3 neighbour_normal = view_normal(uv, neighbour, texel);
+        edge = max(edge, step(outline.params.z, 1.0 - dot(center_normal, neighbour_normal)));
+    }
+
+    if (outline.params.w > 0.0) {
+        edge *= 1.0 - smoothstep(outline.params.w * 0.75, outline.params.w, nearest);
+    }
+    outColor = vec4(mix(c.rgb, outline.color.rgb, edge * outline.color.w), c.a);
+}
@@ -0,0 +1,182 @@
+#version 450
+
+// Cook-Torrance with the GGX distribution, Smith-Schlick geometry and Schlick fresnel,
+// metallic roughness workflow as defined by glTF 2.0
+
+const float PI = 3.14159265359;
+
+// keywords, see PbrMaterialDesc::keywords:
+// NORMAL_MAP perturbs the normal with normal_texture, without it the texture is bound but never sampled
+// SKINNED moves the material set behind the joint matrices of shaders/pbr.vert
+
+// crowds bind their animation data at set 1 and compile this with -DMATERIAL_SET=2
+#ifndef MATERIAL_SET
+#ifdef SKINNED
+#define MATERIAL_SET 2
+#else
+#define MATERIAL_SET 1
+#endif
+#endif
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+layout(set = MATERIAL_SET, binding = 0) uniform MaterialParams {
+    vec4 base_color_factor;
+    vec3 emissive_factor;
+    float metallic_factor;
+    float roughness_factor;
+    float normal_scale;
+    float occlusion_strength;
+    float alpha_cutoff;
+    int base_color_uv_set;
+    int metallic_roughness_uv_set;
+    int normal_uv_set;
+    int occlusion_uv_set;
+    int emissive_uv_set;
+} params;
+
+layout(set = MATERIAL_SET, binding = 1) uniform sampler2D base_color_texture;
+layout(set = MATERIAL_SET, binding = 2) uniform sampler2D metallic_roughness_texture;
+layout(set = MATERIAL_SET, binding = 3) uniform sampler2D normal_texture;
+layout(set = MATERIAL_SET, binding = 4) uniform sampler2D occlusion_texture;
+layout(set = MATERIAL_SET, binding = 5) uniform sampler2D emissive_texture;
+
+layout(location = 0) in vec3 fragWorldPosition;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragUv;
+layout(location = 3) in vec2 fragUv1;
+layout(location = 4) in vec2 fragUv2;
+layout(location = 5) in vec2 fragUv3;
+layout(location = 6) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+// the uv set a texture is sampled with, the *_uv_set parameters
+vec2 uv_set(int set) {
+    switch (set) {
+        case 1: return fragUv1;
+        case 2: return fragUv2;
+        case 3: return fragUv3;
+        default: return fragUv;
+    }
+}
+
+#ifdef NORMAL_MAP
+// the meshes have no tangents, so the tangent frame is built from screen space derivatives
+vec3 perturb_normal(vec3 normal, vec3 view_dir) {
+    vec2 uv = uv_set(params.normal_uv_set);
+    vec3 tangent_normal = texture(normal_texture, uv).xyz * 2.0 - 1.0;
+    tangent_normal.xy *= params.normal_scale;
+
+    vec3 dp1 = dFdx(-view_dir);
+    vec3 dp2 = dFdy(-view_dir);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+
+    float inv_max = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    if (isinf(inv_max) || isnan(inv_max)) {
+        return normal;
+    }
+    mat3 tbn = mat3(tangent * inv_max, bitangent * inv_max, normal);
+    return normalize(tbn * tangent_normal);
+}
+#endif
+
+float distribution_ggx(float n_dot_h, float alpha) {
+    float alpha2 = alpha * alpha;
+    float d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    return alpha2 / (PI * d * d);
+}
+
+float geometry_smith(float n_dot_v, float n_dot_l, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float gv = n_dot_v / (n_dot_v * (1.0 - k) + k);
+    float gl = n_dot_l / (n_dot_l * (1.0 - k) + k);
+    return gv * gl;
+}
+
+vec3 fresnel_schlick(float cos_theta, vec3 f0) {
+    return f0 + (1.0 - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+// water darkens porous surfaces and smooths them into a thin film, snow settles on the ones facing up
+void apply_weather(vec3 normal, inout vec3 base_color, inout float metallic, inout float roughness) {
+    float up = clamp(normal.y, 0.0, 1.0);
+    float wetness = frame.weather.x * mix(0.4, 1.0, up);
+    base_color *= mix(1.0, 0.55, wetness * (1.0 - metallic));
+    roughness = mix(roughness, 0.05, wetness);
+
+    float snow = frame.weather.y * smoothstep(0.4, 0.8, up);
+    base_color = mix(base_color, vec3(0.9), snow);
+    metallic = mix(metallic, 0.0, snow);
+    roughness = mix(roughness, 0.8, snow);
+}
+
+void main() {
+    vec4 base_color = texture(base_color_texture, uv_set(params.base_color_uv_set)) * params.base_color_factor * fragColor;
+    // derivatives are only defined before the discard
+    float alpha_width = max(fwidth(base_color.a), 0.0001);
+    if (base_color.a < params.alpha_cutoff) {
+        discard;
+    }
+    if (params.alpha_cutoff > 0.0) {
+        // sharpened to a one pixel ramp at the cutoff, alpha to coverage turns it into an antialiased edge
+        base_color.a = clamp((base_color.a - params.alpha_cutoff) / alpha_width + 0.5, 0.0, 1.0);
+    }
+
+    vec4 metallic_roughness = texture(metallic_roughness_texture, uv_set(params.metallic_roughness_uv_set));
+    float metallic = clamp(metallic_roughness.b * params.metallic_factor, 0.0, 1.0);
+    float roughness = clamp(metallic_roughness.g * params.roughness_factor, 0.04, 1.0);
+
+    vec3 view_dir = normalize(frame.camera_position.xyz - fragWorldPosition);
+    vec3 normal = normalize(fragNormal);
+    // double sided materials show the back face with a flipped normal
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+#ifdef NORMAL_MAP
+    normal = perturb_normal(normal, view_dir);
+#endif
+    apply_weather(normal, base_color.rgb, metallic, roughness);
+
+    vec3 light_dir = normalize(-frame.light_direction.xyz);
+    vec3 half_dir = normalize(view_dir + light_dir);
+
+    float n_dot_v = max(dot(normal, view_dir), 1e-4);
+    float n_dot_l = max(dot(normal, light_dir), 0.0);
+    float n_dot_h = max(dot(normal, half_dir), 0.0);
+    float v_dot_h = max(dot(view_dir, half_dir), 0.0);
+
+    vec3 f0 = mix(vec3(0.04), base_color.rgb, metallic);
+    vec3 fresnel = fresnel_schlick(v_dot_h, f0);
+    float d = distribution_ggx(n_dot_h, roughness * roughness);
+    float g = geometry_smith(n_dot_v, n_dot_l, roughness);
+
+    vec3 specular = d * g * fresnel / (4.0 * n_dot_v * n_dot_l + 1e-4);
+    vec3 diffuse = (1.0 - fresnel) * (1.0 - metallic) * base_color.rgb / PI;
+    vec3 radiance = frame.light_color.rgb * frame.light_color.w;
+    vec3 color = (diffuse + specular) * radiance * n_dot_l;
+
+    float occlusion = mix(1.0, texture(occlusion_texture, uv_set(params.occlusion_uv_set)).r, params.occlusion_strength);
+    vec3 ambient = frame.ambient_color.rgb * frame.ambient_color.w * base_color.rgb * occlusion;
+    vec3 emissive = texture(emissive_texture, uv_set(params.emissive_uv_set)).rgb * params.emissive_factor;
+
+    color += ambient + emissive;
+    // reinhard, the swapchain is sRGB so no gamma correction is needed
+    color = color / (color + vec3(1.0));
+
+    outColor = vec4(color, base_color.a);
+}
@@ -0,0 +1,73 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+layout(push_constant) uniform Transform {
+    mat4 model;
+} transform;
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec3 inNormal;
+layout(location = 2) in vec2 inUv;
+layout(location = 3) in vec2 inUv1;
+layout(location = 4) in vec2 inUv2;
+layout(location = 5) in vec2 inUv3;
+layout(location = 6) in vec4 inColor;
+
+#ifdef SKINNED
+// the current pose, the rows of a 3x4 matrix per joint like the baked frames of shaders/crowd.vert
+layout(set = 1, binding = 0) readonly buffer JointMatrices {
+    vec4 rows[];
+} joint_matrices;
+
+layout(location = 7) in uvec4 inJoints;
+layout(location = 8) in vec4 inWeights;
+
+mat4 joint_matrix(uint joint) {
+    uint row = joint * 3;
+    return transpose(mat4(
+        joint_matrices.rows[row],
+        joint_matrices.rows[row + 1],
+        joint_matrices.rows[row + 2],
+        vec4(0.0, 0.0, 0.0, 1.0)
+    ));
+}
+#endif
+
+layout(location = 0) out vec3 fragWorldPosition;
+layout(location = 1) out vec3 fragNormal;
+layout(location = 2) out vec2 fragUv;
+layout(location = 3) out vec2 fragUv1;
+layout(location = 4) out vec2 fragUv2;
+layout(location = 5) out vec2 fragUv3;
+layout(location = 6) out vec4 fragColor;
+
+void main() {
+    mat4 model = transform.model;
+#ifdef SKINNED
+    mat4 skin = mat4(0.0);
+    for (int i = 0; i < 4; i++) {
+        skin += inWeights[i] * joint_matrix(inJoints[i]);
+    }
+    model = model * skin;
+#endif
+    vec4 world_position = model * vec4(inPosition, 1.0);
+
+    fragWorldPosition = world_position.xyz;
+    fragNormal = mat3(transpose(inverse(model))) * inNormal;
+    fragUv = inUv;
+    fragUv1 = inUv1;
+    fragUv2 = inUv2;
+    fragUv3 = inUv3;
+    fragColor = inColor;
+
+    gl_Position = frame.projection * frame.view * world_position;
+}
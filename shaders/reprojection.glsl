@@ -0,0 +1,18 @@
+// include with #extension GL_GOOGLE_include_directive : require
+// the matrix is ReprojectionUniforms::current_to_previous
+
+// where a point at uv (0..1, y down) with depth in this frame was on screen last frame
+vec2 reprojectUv(vec2 uv, float depth, mat4 currentToPrevious) {
+    vec4 previous = currentToPrevious * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    return previous.xy / previous.w * 0.5 + 0.5;
+}
+
+// reprojected points outside of the screen have no history
+bool insideScreen(vec2 uv) {
+    return all(greaterThanEqual(uv, vec2(0.0))) && all(lessThanEqual(uv, vec2(1.0)));
+}
+
+// clamps a history sample to the range of the current neighbourhood, which hides most ghosting
+vec4 clipHistory(vec4 history, vec4 neighbourhoodMin, vec4 neighbourhoodMax) {
+    return clamp(history, neighbourhoodMin, neighbourhoodMax);
+}
@@ -0,0 +1,104 @@
+// the interface of custom shading models, see CustomShadingDesc in src/pbr.rs
+// include with #extension GL_GOOGLE_include_directive : require, then define
+//
+//     vec4 shade_surface(Surface surface)
+//
+// which returns the tonemapped color and alpha of the pixel. The material's own `MaterialParams` block is
+// set 1 binding 0 and its textures follow from binding 1 in the order of CustomShadingDesc::textures.
+// Define SURFACE_CUSTOM_MAIN before including to write main() yourself.
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+// written by shaders/pbr.vert
+layout(location = 0) in vec3 fragWorldPosition;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragUv;
+layout(location = 3) in vec2 fragUv1;
+layout(location = 4) in vec2 fragUv2;
+layout(location = 5) in vec2 fragUv3;
+layout(location = 6) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+struct Surface {
+    vec3 position;
+    // interpolated and flipped on back faces, normal maps are up to the shading model
+    vec3 normal;
+    // from the surface towards the camera
+    vec3 view_dir;
+    // the first uv set, `uvs` has all of them for the *_uv_set parameters
+    vec2 uv;
+    vec2 uvs[4];
+    // vertex color, white when the mesh has none
+    vec4 color;
+    bool front_facing;
+};
+
+// the sun, the only punctual light so far
+struct Light {
+    // from the surface towards the light
+    vec3 direction;
+    // color times intensity
+    vec3 radiance;
+};
+
+Light main_light() {
+    return Light(normalize(-frame.light_direction.xyz), frame.light_color.rgb * frame.light_color.w);
+}
+
+// 0 in shadow, 1 lit. There are no shadow maps yet so everything is lit,
+// shaders calling it pick shadows up once they exist.
+float light_shadow(Light light, vec3 position) {
+    return 1.0;
+}
+
+// diffuse environment light from `normal`, the ambient color until an irradiance probe is bound
+vec3 ambient_irradiance(vec3 normal) {
+    return frame.ambient_color.rgb * frame.ambient_color.w;
+}
+
+// specular environment light along `reflected`, the ambient color until a prefiltered probe is bound
+vec3 environment_specular(vec3 reflected, float roughness) {
+    return frame.ambient_color.rgb * frame.ambient_color.w;
+}
+
+// x wetness, y snow cover, z precipitation intensity, all 0..1, w seconds, see src/weather.rs
+vec4 weather() {
+    return frame.weather;
+}
+
+// what the built-in models do, the swapchain is sRGB so no gamma correction is needed
+vec3 tonemap(vec3 color) {
+    return color / (color + vec3(1.0));
+}
+
+Surface current_surface() {
+    Surface surface;
+    surface.position = fragWorldPosition;
+    surface.normal = normalize(fragNormal);
+    surface.front_facing = gl_FrontFacing;
+    if (!gl_FrontFacing) {
+        surface.normal = -surface.normal;
+    }
+    surface.view_dir = normalize(frame.camera_position.xyz - fragWorldPosition);
+    surface.uv = fragUv;
+    surface.uvs = vec2[4](fragUv, fragUv1, fragUv2, fragUv3);
+    surface.color = fragColor;
+    return surface;
+}
+
+vec4 shade_surface(Surface surface);
+
+#ifndef SURFACE_CUSTOM_MAIN
+void main() {
+    outColor = shade_surface(current_surface());
+}
+#endif
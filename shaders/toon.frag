@@ -0,0 +1,119 @@
+#version 450
+
+// cel shading: the light is looked up in a ramp instead of integrated, with a hard edged highlight and a rim light.
+// uses the same frame data and vertex shader as pbr.frag
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+layout(set = 1, binding = 0) uniform MaterialParams {
+    vec4 base_color_factor;
+    // rgb the base color is multiplied with where the ramp is 0
+    vec4 shadow_color;
+    // rgb, w strength
+    vec4 specular_color;
+    // rgb, w strength
+    vec4 rim_color;
+    vec3 emissive_factor;
+    // angular radius of the highlight in radians
+    float specular_size;
+    // share of the silhouette covered by the rim, 0..1
+    float rim_width;
+    float alpha_cutoff;
+    int base_color_uv_set;
+    int emissive_uv_set;
+} params;
+
+layout(set = 1, binding = 1) uniform sampler2D base_color_texture;
+// lighting by half lambert from left to right, sampled along v = 0.5
+layout(set = 1, binding = 2) uniform sampler2D ramp_texture;
+layout(set = 1, binding = 3) uniform sampler2D emissive_texture;
+
+layout(location = 0) in vec3 fragWorldPosition;
+layout(location = 1) in vec3 fragNormal;
+layout(location = 2) in vec2 fragUv;
+layout(location = 3) in vec2 fragUv1;
+layout(location = 4) in vec2 fragUv2;
+layout(location = 5) in vec2 fragUv3;
+layout(location = 6) in vec4 fragColor;
+
+layout(location = 0) out vec4 outColor;
+
+// the uv set a texture is sampled with, the *_uv_set parameters
+vec2 uv_set(int set) {
+    switch (set) {
+        case 1: return fragUv1;
+        case 2: return fragUv2;
+        case 3: return fragUv3;
+        default: return fragUv;
+    }
+}
+
+// the flat color version of pbr.frag's weather, wet surfaces darken and snow covers the ones facing up
+vec3 apply_weather(vec3 normal, vec3 base_color) {
+    float up = clamp(normal.y, 0.0, 1.0);
+    float wetness = frame.weather.x * mix(0.4, 1.0, up);
+    base_color *= mix(1.0, 0.7, wetness);
+    float snow = frame.weather.y * smoothstep(0.4, 0.8, up);
+    return mix(base_color, vec3(0.9), snow);
+}
+
+void main() {
+    vec4 base_color = texture(base_color_texture, uv_set(params.base_color_uv_set)) * params.base_color_factor * fragColor;
+    // derivatives are only defined before the discard
+    float alpha_width = max(fwidth(base_color.a), 0.0001);
+    if (base_color.a < params.alpha_cutoff) {
+        discard;
+    }
+    if (params.alpha_cutoff > 0.0) {
+        // sharpened to a one pixel ramp at the cutoff, alpha to coverage turns it into an antialiased edge
+        base_color.a = clamp((base_color.a - params.alpha_cutoff) / alpha_width + 0.5, 0.0, 1.0);
+    }
+
+    vec3 view_dir = normalize(frame.camera_position.xyz - fragWorldPosition);
+    vec3 normal = normalize(fragNormal);
+    if (!gl_FrontFacing) {
+        normal = -normal;
+    }
+    base_color.rgb = apply_weather(normal, base_color.rgb);
+
+    vec3 light_dir = normalize(-frame.light_direction.xyz);
+    vec3 half_dir = normalize(view_dir + light_dir);
+    float n_dot_l = dot(normal, light_dir);
+    float n_dot_v = max(dot(normal, view_dir), 0.0);
+    float n_dot_h = max(dot(normal, half_dir), 0.0);
+    vec3 radiance = frame.light_color.rgb * frame.light_color.w;
+
+    float half_lambert = n_dot_l * 0.5 + 0.5;
+    vec3 ramp = texture(ramp_texture, vec2(half_lambert, 0.5)).rgb;
+    vec3 light = mix(params.shadow_color.rgb, vec3(1.0), ramp);
+    vec3 color = base_color.rgb * light * radiance;
+
+    // a few pixels of antialiasing at the edge of the highlight and the rim
+    float lit = step(0.0, n_dot_l);
+    float cutoff = cos(params.specular_size);
+    float edge = fwidth(n_dot_h);
+    float specular = smoothstep(cutoff - edge, cutoff + edge, n_dot_h) * lit;
+    color += params.specular_color.rgb * params.specular_color.w * specular * radiance;
+
+    float rim_cutoff = 1.0 - params.rim_width;
+    float rim_edge = fwidth(n_dot_v);
+    float rim = smoothstep(rim_cutoff - rim_edge, rim_cutoff + rim_edge, 1.0 - n_dot_v);
+    // stronger towards the light, like light wrapping around the silhouette
+    color += params.rim_color.rgb * params.rim_color.w * rim * half_lambert * radiance;
+
+    vec3 ambient = frame.ambient_color.rgb * frame.ambient_color.w * base_color.rgb;
+    vec3 emissive = texture(emissive_texture, uv_set(params.emissive_uv_set)).rgb * params.emissive_factor;
+
+    color += ambient + emissive;
+    color = color / (color + vec3(1.0));
+
+    outColor = vec4(color, base_color.a);
+}
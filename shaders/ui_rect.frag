@@ -0,0 +1,40 @@
+#version 450
+
+layout(location = 0) in vec2 fragLocal;
+layout(location = 1) flat in vec2 fragHalfSize;
+layout(location = 2) flat in vec4 fragFillColor;
+layout(location = 3) flat in vec4 fragBorderColor;
+layout(location = 4) flat in vec4 fragShadowColor;
+layout(location = 5) flat in vec4 fragShadow;
+layout(location = 6) flat in vec2 fragParams;
+
+layout(location = 0) out vec4 outColor;
+
+// negative inside the box, positive outside
+float rounded_box_sdf(vec2 p, vec2 half_size, float radius) {
+    radius = min(radius, min(half_size.x, half_size.y));
+    vec2 q = abs(p) - half_size + radius;
+    return length(max(q, 0.0)) + min(max(q.x, q.y), 0.0) - radius;
+}
+
+void main() {
+    float radius = fragParams.x;
+    float border_width = fragParams.y;
+
+    float dist = rounded_box_sdf(fragLocal, fragHalfSize, radius);
+    float coverage = clamp(0.5 - dist, 0.0, 1.0);
+    float inner = border_width > 0.0 ? clamp(0.5 - (dist + border_width), 0.0, 1.0) : 1.0;
+
+    vec4 body = mix(fragBorderColor, fragFillColor, inner);
+    body.a *= coverage;
+
+    float blur = max(fragShadow.z, 1.0);
+    float spread = fragShadow.w;
+    float shadow_dist = rounded_box_sdf(fragLocal - fragShadow.xy, fragHalfSize + spread, radius + spread);
+    float shadow_alpha = fragShadowColor.a * (1.0 - smoothstep(-blur, blur, shadow_dist));
+
+    // body over shadow
+    float alpha = body.a + shadow_alpha * (1.0 - body.a);
+    vec3 color = (body.rgb * body.a + fragShadowColor.rgb * shadow_alpha * (1.0 - body.a)) / max(alpha, 0.0001);
+    outColor = vec4(color, alpha);
+}
@@ -0,0 +1,44 @@
+#version 450
+
+layout(push_constant) uniform Push {
+    vec2 screen_size;
+} push;
+
+// per instance
+layout(location = 0) in vec4 inRect; // x, y, width, height
+layout(location = 1) in vec4 inFillColor;
+layout(location = 2) in vec4 inBorderColor;
+layout(location = 3) in vec4 inShadowColor;
+layout(location = 4) in vec4 inShadow; // offset x, offset y, blur, spread
+layout(location = 5) in vec2 inParams; // radius, border width
+
+layout(location = 0) out vec2 fragLocal;
+layout(location = 1) flat out vec2 fragHalfSize;
+layout(location = 2) flat out vec4 fragFillColor;
+layout(location = 3) flat out vec4 fragBorderColor;
+layout(location = 4) flat out vec4 fragShadowColor;
+layout(location = 5) flat out vec4 fragShadow;
+layout(location = 6) flat out vec2 fragParams;
+
+const vec2 corners[6] = vec2[](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(1.0, 1.0), vec2(-1.0, 1.0), vec2(-1.0, -1.0)
+);
+
+void main() {
+    vec2 half_size = inRect.zw * 0.5;
+    vec2 center = inRect.xy + half_size;
+
+    // grow the quad so the shadow fits inside it
+    float margin = inShadow.z + inShadow.w + max(abs(inShadow.x), abs(inShadow.y)) + 1.0;
+    vec2 local = corners[gl_VertexIndex] * (half_size + margin);
+
+    gl_Position = vec4((center + local) / push.screen_size * 2.0 - 1.0, 0.0, 1.0);
+    fragLocal = local;
+    fragHalfSize = half_size;
+    fragFillColor = inFillColor;
+    fragBorderColor = inBorderColor;
+    fragShadowColor = inShadowColor;
+    fragShadow = inShadow;
+    fragParams = inParams;
+}
@@ -0,0 +1,19 @@
+#version 450
+
+layout(push_constant) uniform Push {
+    vec2 screen_size;
+} push;
+
+layout(location = 0) in vec2 inPosition;
+layout(location = 1) in vec2 inUv;
+layout(location = 2) in vec4 inColor;
+
+layout(location = 0) out vec2 fragUv;
+layout(location = 1) out vec4 fragColor;
+
+void main() {
+    // pixels to normalized device coordinates, vulkan has y pointing down so no flip is needed
+    gl_Position = vec4(inPosition / push.screen_size * 2.0 - 1.0, 0.0, 1.0);
+    fragUv = inUv;
+    fragColor = inColor;
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform Vignette {
+    // rgb the edges fade to, w intensity
+    vec4 color;
+    // distance from the center where the fade starts, 1 is the middle of an edge
+    float radius;
+    float softness;
+    // 0 follows the screen shape, 1 is a circle
+    float roundness;
+    float aspect;
+} vignette;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    vec2 fromCenter = (fragUv - vec2(0.5)) * 2.0;
+    fromCenter.x *= mix(1.0, vignette.aspect, vignette.roundness);
+    float edge = length(fromCenter);
+    float inside = 1.0 - smoothstep(vignette.radius, vignette.radius + max(vignette.softness, 1e-4), edge);
+    vec4 c = texture(color, fragUv);
+    outColor = vec4(mix(vignette.color.rgb, c.rgb, mix(1.0, inside, vignette.color.w)), c.a);
+}
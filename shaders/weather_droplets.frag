@@ -0,0 +1,64 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform sampler2D color;
+layout(set = 0, binding = 1) uniform sampler2D depth;
+
+layout(push_constant) uniform Droplets {
+    float time;
+    // 0..1, share of the cells holding a drop
+    float intensity;
+    // cells across the height of the screen
+    float scale;
+    // width over height
+    float aspect;
+} droplets;
+
+layout(location = 0) in vec2 fragUv;
+
+layout(location = 0) out vec4 outColor;
+
+float hash(vec2 p) {
+    p = fract(p * vec2(123.34, 456.21));
+    p += dot(p, p + 45.32);
+    return fract(p.x * p.y);
+}
+
+// offset a drop in the cell grid refracts the screen by, drops live for a few seconds and slide down slowly
+vec2 drop_layer(vec2 uv, float scale, float time) {
+    vec2 grid = vec2(droplets.aspect, 1.0) * scale;
+    vec2 p = uv * grid;
+    // columns slide at their own speed
+    float column = floor(p.x);
+    p.y -= time * (0.05 + hash(vec2(column, 7.0)) * 0.1);
+    vec2 cell = floor(p);
+    vec2 local = fract(p) - 0.5;
+
+    float seed = hash(cell);
+    float life = fract(time * (0.1 + seed * 0.15) + seed);
+    if (hash(cell + 3.1) > droplets.intensity) {
+        return vec2(0.0);
+    }
+    vec2 center = (vec2(hash(cell + 1.7), hash(cell + 5.3)) - 0.5) * 0.6;
+    float radius = mix(0.12, 0.3, hash(cell + 9.1)) * (1.0 - life * life);
+    vec2 to_center = local - center;
+    float d = length(to_center / vec2(1.0, 1.3));
+    if (d > radius) {
+        return vec2(0.0);
+    }
+    // a drop works like a small lens, strongest at its rim
+    float bulge = 1.0 - d / radius;
+    return -to_center / grid * (1.0 - bulge * bulge) * 2.5;
+}
+
+void main() {
+    if (droplets.intensity <= 0.0) {
+        outColor = texture(color, fragUv);
+        return;
+    }
+    vec2 offset = drop_layer(fragUv, droplets.scale, droplets.time);
+    offset += drop_layer(fragUv + 0.37, droplets.scale * 2.3, droplets.time * 1.3) * 0.6;
+    vec3 refracted = texture(color, fragUv + offset).rgb;
+    // the water absorbs a little, which outlines the drops
+    float darkening = clamp(length(offset) * 40.0, 0.0, 1.0) * 0.15;
+    outColor = vec4(refracted * (1.0 - darkening), 1.0);
+}
@@ -0,0 +1,28 @@
+#version 450
+
+layout(push_constant) uniform Particles {
+    vec4 color;
+    vec4 velocity;
+    vec4 area;
+    vec4 sway;
+} particles;
+
+layout(location = 0) in vec2 fragCorner;
+layout(location = 1) in float fragFade;
+
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    float shape;
+    if (particles.velocity.w > 0.0) {
+        // thin streak, brightest along its middle
+        shape = (1.0 - abs(fragCorner.x)) * (1.0 - fragCorner.y * fragCorner.y);
+    } else {
+        shape = 1.0 - smoothstep(0.5, 1.0, length(fragCorner));
+    }
+    float alpha = particles.color.a * shape * fragFade;
+    if (alpha <= 0.002) {
+        discard;
+    }
+    outColor = vec4(particles.color.rgb, alpha);
+}
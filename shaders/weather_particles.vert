@@ -0,0 +1,69 @@
+#version 450
+
+layout(set = 0, binding = 0) uniform Frame {
+    mat4 view;
+    mat4 projection;
+    vec4 camera_position;
+    vec4 light_direction;
+    vec4 light_color;
+    vec4 ambient_color;
+    vec4 weather;
+} frame;
+
+layout(push_constant) uniform Particles {
+    vec4 color;
+    // xyz velocity with the wind, w streak length, 0 draws camera facing flakes
+    vec4 velocity;
+    // x horizontal radius, y height of the box around the camera, z particle size
+    vec4 area;
+    // x amplitude, y frequency of the flutter
+    vec4 sway;
+} particles;
+
+layout(location = 0) out vec2 fragCorner;
+layout(location = 1) out float fragFade;
+
+const vec2 CORNERS[6] = vec2[](vec2(-1, -1), vec2(1, -1), vec2(1, 1), vec2(-1, -1), vec2(1, 1), vec2(-1, 1));
+
+float hash(uint n) {
+    n = (n << 13u) ^ n;
+    n = n * (n * n * 15731u + 789221u) + 1376312589u;
+    return float(n & 0x7fffffffu) / float(0x7fffffff);
+}
+
+// every instance is one particle, its position only depends on its index and the time,
+// so nothing has to be simulated or stored
+void main() {
+    uint id = uint(gl_InstanceIndex);
+    vec3 seed = vec3(hash(id * 3u), hash(id * 3u + 1u), hash(id * 3u + 2u));
+    float radius = particles.area.x;
+    float height = particles.area.y;
+    float time = frame.weather.w;
+    vec3 extent = vec3(radius * 2.0, height, radius * 2.0);
+
+    // the particles tile the world, the box around the camera picks the tile that's inside it,
+    // so they stay put in the world while the camera moves through the weather
+    vec3 world = seed * extent + particles.velocity.xyz * time;
+    world.x += sin(time * particles.sway.y + seed.y * 6.2832) * particles.sway.x;
+    world.z += cos(time * particles.sway.y * 0.8 + seed.x * 6.2832) * particles.sway.x;
+    vec3 origin = frame.camera_position.xyz - vec3(radius, height * 0.5, radius);
+    world = origin + mod(world - origin, extent);
+
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec3 to_camera = frame.camera_position.xyz - world;
+    if (particles.velocity.w > 0.0) {
+        vec3 axis = normalize(particles.velocity.xyz);
+        vec3 side = normalize(cross(axis, to_camera));
+        world += axis * corner.y * particles.velocity.w * 0.5 + side * corner.x * particles.area.z * 0.5;
+    } else {
+        vec3 right = vec3(frame.view[0][0], frame.view[1][0], frame.view[2][0]);
+        vec3 up = vec3(frame.view[0][1], frame.view[1][1], frame.view[2][1]);
+        world += (right * corner.x + up * corner.y) * particles.area.z * 0.5;
+    }
+
+    // fade out towards the walls of the box and right in front of the camera
+    float horizontal = length(to_camera.xz);
+    fragFade = (1.0 - smoothstep(radius * 0.6, radius, horizontal)) * smoothstep(0.3, 1.5, length(to_camera));
+    fragCorner = corner;
+    gl_Position = frame.projection * frame.view * vec4(world, 1.0);
+}
@@ -0,0 +1,1060 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::error::{Error, Result};
+use nalgebra as glm;
+
+use crate::{
+    gltf_import::GltfScene,
+    scene::{NodeId, Scene, Transform},
+};
+
+/// Compressed key times count in these, exact for 24, 25, 30 and 60 frames per second.
+pub const TICKS_PER_SECOND: f32 = 1200.0;
+
+const STREAM_MAGIC: &[u8; 8] = b"VKYANIM\0";
+const STREAM_VERSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// holds each key until the next one
+    Step,
+}
+
+/// Keys of one property of one node.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    /// index into `GltfScene::nodes`
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    /// seconds, ascending
+    pub times: Vec<f32>,
+    /// xyz for translation and scale, a xyzw quaternion for rotation
+    pub values: Vec<glm::Vector4<f32>>,
+}
+
+impl Channel {
+    pub fn sample(&self, time: f32) -> Option<glm::Vector4<f32>> {
+        sample_keys(
+            self.times.len(),
+            |key| self.times[key],
+            |key| self.values[key],
+            time,
+            self.property,
+            self.interpolation,
+        )
+    }
+}
+
+/// A named point on the timeline of a clip, `AnimationPlayer::advance` reports it when playback crosses it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationEvent {
+    /// seconds
+    pub time: f32,
+    pub name: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimationClip {
+    pub name: Option<String>,
+    /// seconds, the time of the last key
+    pub duration: f32,
+    pub channels: Vec<Channel>,
+    /// sorted by time, add them with `add_event`
+    pub events: Vec<AnimationEvent>,
+}
+
+impl AnimationClip {
+    /// Writes the channels at `time` into `pose`, indexed by node. Time is clamped to the clip.
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in &self.channels {
+            if let (Some(transform), Some(value)) = (pose.get_mut(channel.node), channel.sample(time)) {
+                apply_value(transform, channel.property, value);
+            }
+        }
+    }
+
+    /// Events at the same time are reported in the order they were added.
+    pub fn add_event(&mut self, time: f32, name: &str) {
+        let index = self.events.partition_point(|event| event.time <= time);
+        self.events.insert(
+            index,
+            AnimationEvent {
+                time,
+                name: name.to_owned(),
+            },
+        );
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.channels.iter().map(|channel| channel.times.len()).sum()
+    }
+
+    /// Memory taken by the keys.
+    pub fn size_bytes(&self) -> usize {
+        self.key_count() * (std::mem::size_of::<f32>() + std::mem::size_of::<glm::Vector4<f32>>())
+    }
+}
+
+/// The local transforms of the glTF nodes, what channels that don't animate a property leave alone.
+pub fn rest_pose(gltf: &GltfScene) -> Vec<Transform> {
+    gltf.nodes
+        .iter()
+        .map(|node| Transform::from_matrix(&node.local_transform))
+        .collect()
+}
+
+/// Sets the local transforms of the scene nodes `Scene::from_gltf` created, `nodes` is its second result.
+pub fn apply_pose(scene: &mut Scene, nodes: &[Option<NodeId>], pose: &[Transform]) {
+    for (node, transform) in nodes.iter().zip(pose) {
+        if let Some(node) = node {
+            scene.set_local_transform(*node, *transform);
+        }
+    }
+}
+
+/// How far curve fitting may move a value away from the source keys.
+/// Quantizing adds up to 1/131070 of the range a channel covers on top.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompressionSettings {
+    /// in scene units
+    pub translation_tolerance: f32,
+    /// in radians
+    pub rotation_tolerance: f32,
+    pub scale_tolerance: f32,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        Self {
+            translation_tolerance: 0.001,
+            rotation_tolerance: 0.001,
+            scale_tolerance: 0.001,
+        }
+    }
+}
+
+impl CompressionSettings {
+    fn tolerance(&self, property: Property) -> f32 {
+        match property {
+            Property::Translation => self.translation_tolerance,
+            Property::Rotation => self.rotation_tolerance,
+            Property::Scale => self.scale_tolerance,
+        }
+    }
+}
+
+/// What a compressed channel is, without its keys.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ChannelInfo {
+    node: usize,
+    property: Property,
+    interpolation: Interpolation,
+    /// the quantized values span min to min + extent per component
+    min: [f32; 4],
+    extent: [f32; 4],
+}
+
+impl ChannelInfo {
+    fn dequantize(&self, value: [u16; 4]) -> glm::Vector4<f32> {
+        glm::Vector4::from_fn(|i, _| self.min[i] + value[i] as f32 / u16::MAX as f32 * self.extent[i])
+    }
+
+    fn quantize(&self, value: &glm::Vector4<f32>) -> [u16; 4] {
+        std::array::from_fn(|i| {
+            if self.extent[i] > 0.0 {
+                ((value[i] - self.min[i]) / self.extent[i] * u16::MAX as f32).round() as u16
+            } else {
+                0
+            }
+        })
+    }
+}
+
+/// Quantized keys, times in `TICKS_PER_SECOND` and values in 16 bits per component.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Keys {
+    times: Vec<u32>,
+    values: Vec<[u16; 4]>,
+}
+
+impl Keys {
+    fn sample(&self, info: &ChannelInfo, time: f32) -> Option<glm::Vector4<f32>> {
+        sample_keys(
+            self.times.len(),
+            |key| self.times[key] as f32 / TICKS_PER_SECOND,
+            |key| info.dequantize(self.values[key]),
+            time,
+            info.property,
+            info.interpolation,
+        )
+    }
+
+    /// The keys needed to sample anywhere in `start..=end` ticks.
+    fn range(&self, start: u32, end: u32) -> Keys {
+        if self.times.is_empty() {
+            return Keys::default();
+        }
+        let first = self.times.partition_point(|time| *time <= start).saturating_sub(1);
+        let last = self.times.partition_point(|time| *time < end).min(self.times.len() - 1);
+        Keys {
+            times: self.times[first..=last].to_vec(),
+            values: self.values[first..=last].to_vec(),
+        }
+    }
+
+    fn size_bytes(&self) -> usize {
+        self.times.len() * (std::mem::size_of::<u32>() + std::mem::size_of::<[u16; 4]>())
+    }
+}
+
+/// An `AnimationClip` with the keys linear interpolation reproduces removed and the rest quantized.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompressedClip {
+    pub name: Option<String>,
+    pub duration: f32,
+    pub events: Vec<AnimationEvent>,
+    channels: Vec<(ChannelInfo, Keys)>,
+}
+
+impl CompressedClip {
+    pub fn compress(clip: &AnimationClip, settings: &CompressionSettings) -> CompressedClip {
+        let channels = clip
+            .channels
+            .iter()
+            .filter(|channel| !channel.times.is_empty())
+            .map(|channel| compress_channel(channel, settings.tolerance(channel.property)))
+            .collect();
+        CompressedClip {
+            name: clip.name.clone(),
+            duration: clip.duration,
+            events: clip.events.clone(),
+            channels,
+        }
+    }
+
+    /// Like `AnimationClip::sample`, decoding only the two keys around `time`.
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for (info, keys) in &self.channels {
+            if let (Some(transform), Some(value)) = (pose.get_mut(info.node), keys.sample(info, time)) {
+                apply_value(transform, info.property, value);
+            }
+        }
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.channels.iter().map(|(_, keys)| keys.times.len()).sum()
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.channels.iter().map(|(_, keys)| keys.size_bytes()).sum()
+    }
+
+    /// Writes the clip for `ClipStream`, split into chunks of `chunk_duration` seconds.
+    pub fn write_stream<P: AsRef<Path>>(&self, path: P, chunk_duration: f32) -> Result<()> {
+        if chunk_duration <= 0.0 {
+            return Err(Error::msg(format!("Chunk duration {} isn't positive", chunk_duration)));
+        }
+        let chunk_count = ((self.duration / chunk_duration).ceil() as usize).max(1);
+        let chunks: Vec<Vec<u8>> = (0..chunk_count)
+            .map(|chunk| {
+                let start = (chunk as f32 * chunk_duration * TICKS_PER_SECOND) as u32;
+                let end = ((chunk + 1) as f32 * chunk_duration * TICKS_PER_SECOND).ceil() as u32;
+                let mut bytes = vec![];
+                for (_, keys) in &self.channels {
+                    write_keys(&mut bytes, &keys.range(start, end));
+                }
+                bytes
+            })
+            .collect();
+
+        let mut header = vec![];
+        header.extend_from_slice(STREAM_MAGIC);
+        header.extend_from_slice(&STREAM_VERSION.to_le_bytes());
+        header.extend_from_slice(&self.duration.to_le_bytes());
+        header.extend_from_slice(&chunk_duration.to_le_bytes());
+        let name = self.name.as_deref().unwrap_or("");
+        header.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        header.extend_from_slice(name.as_bytes());
+        header.extend_from_slice(&(self.events.len() as u32).to_le_bytes());
+        for event in &self.events {
+            header.extend_from_slice(&event.time.to_le_bytes());
+            header.extend_from_slice(&(event.name.len() as u32).to_le_bytes());
+            header.extend_from_slice(event.name.as_bytes());
+        }
+        header.extend_from_slice(&(self.channels.len() as u32).to_le_bytes());
+        for (info, _) in &self.channels {
+            write_channel_info(&mut header, info);
+        }
+        header.extend_from_slice(&(chunk_count as u32).to_le_bytes());
+        // chunk table entries are an u64 offset and an u32 size
+        let mut offset = (header.len() + chunk_count * 12) as u64;
+        for chunk in &chunks {
+            header.extend_from_slice(&offset.to_le_bytes());
+            header.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            offset += chunk.len() as u64;
+        }
+
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header)?;
+        for chunk in &chunks {
+            file.write_all(chunk)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// A clip written by `CompressedClip::write_stream`, read from disk a chunk at a time.
+/// At most `max_resident_chunks` stay loaded, the least recently sampled one is dropped first.
+pub struct ClipStream {
+    file: BufReader<File>,
+    name: Option<String>,
+    duration: f32,
+    chunk_duration: f32,
+    events: Vec<AnimationEvent>,
+    channels: Vec<ChannelInfo>,
+    /// (offset, size) in the file
+    chunk_table: Vec<(u64, u32)>,
+    /// (chunk, keys per channel), most recently used last
+    resident: Vec<(usize, Vec<Keys>)>,
+    max_resident_chunks: usize,
+}
+
+impl ClipStream {
+    /// Reads the header, no chunks are loaded until they're sampled. Keeps at least one chunk resident.
+    pub fn open<P: AsRef<Path>>(path: P, max_resident_chunks: usize) -> Result<ClipStream> {
+        let path = path.as_ref();
+        let mut file = BufReader::new(File::open(path)?);
+        if &read_array::<8>(&mut file)? != STREAM_MAGIC {
+            return Err(Error::asset(path, "Not an animation stream"));
+        }
+        let version = read_u32(&mut file)?;
+        if version != STREAM_VERSION {
+            return Err(Error::asset(
+                path,
+                format!("Stream version {}, expected {}", version, STREAM_VERSION),
+            ));
+        }
+        let duration = read_f32(&mut file)?;
+        let chunk_duration = read_f32(&mut file)?;
+        let name = read_string(&mut file)?;
+        let events = (0..read_u32(&mut file)?)
+            .map(|_| {
+                Ok(AnimationEvent {
+                    time: read_f32(&mut file)?,
+                    name: read_string(&mut file)?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let channels = (0..read_u32(&mut file)?)
+            .map(|_| read_channel_info(&mut file))
+            .collect::<Result<Vec<_>>>()?;
+        let chunk_table = (0..read_u32(&mut file)?)
+            .map(|_| Ok((read_u64(&mut file)?, read_u32(&mut file)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(ClipStream {
+            file,
+            name: (!name.is_empty()).then_some(name),
+            duration,
+            chunk_duration,
+            events,
+            channels,
+            chunk_table,
+            resident: vec![],
+            max_resident_chunks: max_resident_chunks.max(1),
+        })
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_table.len()
+    }
+
+    /// Memory taken by the keys of the loaded chunks.
+    pub fn resident_bytes(&self) -> usize {
+        self.resident
+            .iter()
+            .flat_map(|(_, keys)| keys.iter().map(Keys::size_bytes))
+            .sum()
+    }
+
+    /// Like `AnimationClip::sample`, loads the chunk holding `time` when it isn't resident.
+    pub fn sample(&mut self, time: f32, pose: &mut [Transform]) -> Result<()> {
+        let chunk = self.chunk_at(time);
+        self.load(chunk)?;
+        let (_, keys) = self.resident.last().unwrap();
+        for (info, keys) in self.channels.iter().zip(keys) {
+            if let (Some(transform), Some(value)) = (pose.get_mut(info.node), keys.sample(info, time)) {
+                apply_value(transform, info.property, value);
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the chunk holding `time` ahead of sampling it, call it with the time a chunk from now.
+    pub fn prefetch(&mut self, time: f32) -> Result<()> {
+        let chunk = self.chunk_at(time);
+        self.load(chunk)
+    }
+
+    fn chunk_at(&self, time: f32) -> usize {
+        let chunk = (time.max(0.0) / self.chunk_duration) as usize;
+        chunk.min(self.chunk_table.len().saturating_sub(1))
+    }
+
+    /// Makes `chunk` the last resident one.
+    fn load(&mut self, chunk: usize) -> Result<()> {
+        match self.resident.iter().position(|(resident, _)| *resident == chunk) {
+            Some(index) => {
+                let entry = self.resident.remove(index);
+                self.resident.push(entry);
+            }
+            None => {
+                let (offset, size) = *self
+                    .chunk_table
+                    .get(chunk)
+                    .ok_or_else(|| Error::msg(format!("Animation stream has no chunk {}", chunk)))?;
+                self.file.seek(SeekFrom::Start(offset))?;
+                let mut bytes = vec![0; size as usize];
+                self.file.read_exact(&mut bytes)?;
+                let mut reader = &bytes[..];
+                let keys = (0..self.channels.len())
+                    .map(|_| read_keys(&mut reader))
+                    .collect::<Result<Vec<_>>>()?;
+
+                if self.resident.len() >= self.max_resident_chunks {
+                    self.resident.remove(0);
+                }
+                self.resident.push((chunk, keys));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Clips `AnimationPlayer` can play.
+pub trait AnimationSource {
+    fn duration(&self) -> f32;
+    /// sorted by time
+    fn events(&self) -> &[AnimationEvent];
+    /// Writes the channels at `time` into `pose`, indexed by node.
+    fn sample_pose(&mut self, time: f32, pose: &mut [Transform]) -> Result<()>;
+}
+
+impl AnimationSource for AnimationClip {
+    fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn events(&self) -> &[AnimationEvent] {
+        &self.events
+    }
+
+    fn sample_pose(&mut self, time: f32, pose: &mut [Transform]) -> Result<()> {
+        self.sample(time, pose);
+        Ok(())
+    }
+}
+
+impl AnimationSource for CompressedClip {
+    fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn events(&self) -> &[AnimationEvent] {
+        &self.events
+    }
+
+    fn sample_pose(&mut self, time: f32, pose: &mut [Transform]) -> Result<()> {
+        self.sample(time, pose);
+        Ok(())
+    }
+}
+
+impl AnimationSource for ClipStream {
+    fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    fn events(&self) -> &[AnimationEvent] {
+        &self.events
+    }
+
+    fn sample_pose(&mut self, time: f32, pose: &mut [Transform]) -> Result<()> {
+        self.sample(time, pose)
+    }
+}
+
+/// Moves a node's motion out of the pose, `AnimationPlayer::advance` reports it instead so a
+/// character controller can move the character, which keeps the root where the clip started.
+/// Streamed clips keep their first chunk resident for it, open them with room for one more chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RootMotion {
+    /// index into `GltfScene::nodes`, usually the hips or a dedicated root bone
+    pub node: usize,
+    /// leaves the vertical movement in the pose, for jumps and crouches
+    pub keep_vertical: bool,
+    /// extracts the turning around y too
+    pub rotation: bool,
+}
+
+impl RootMotion {
+    pub fn new(node: usize) -> Self {
+        Self {
+            node,
+            keep_vertical: true,
+            rotation: false,
+        }
+    }
+}
+
+/// How far the root moved during one `AnimationPlayer::advance`, in the space of its parent.
+/// With rotation extraction the translation is relative to the facing before the advance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RootMotionDelta {
+    pub translation: glm::Vector3<f32>,
+    pub rotation: glm::UnitQuaternion<f32>,
+}
+
+impl Default for RootMotionDelta {
+    fn default() -> Self {
+        Self {
+            translation: glm::Vector3::zeros(),
+            rotation: glm::UnitQuaternion::identity(),
+        }
+    }
+}
+
+/// Playback state of one clip. Events exactly at 0 fire when playback starts there or a loop wraps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AnimationPlayer {
+    /// seconds into the clip
+    pub time: f32,
+    /// 1 is realtime, negative plays backwards
+    pub speed: f32,
+    pub looping: bool,
+    pub root_motion: Option<RootMotion>,
+}
+
+impl AnimationPlayer {
+    pub fn new(looping: bool) -> Self {
+        Self {
+            time: 0.0,
+            speed: 1.0,
+            looping,
+            root_motion: None,
+        }
+    }
+
+    pub fn with_root_motion(mut self, root_motion: RootMotion) -> Self {
+        self.root_motion = Some(root_motion);
+        self
+    }
+
+    /// Whether a clip that doesn't loop reached its end in the direction it plays.
+    pub fn finished(&self, duration: f32) -> bool {
+        let at_end = if self.speed < 0.0 {
+            self.time <= 0.0
+        } else {
+            self.time >= duration
+        };
+        !self.looping && at_end
+    }
+
+    /// Moves `delta_seconds` along `clip`, calls `on_event` for every event crossed in playback order
+    /// and samples the new time into `pose`.
+    pub fn advance<A: AnimationSource + ?Sized, F: FnMut(&AnimationEvent)>(
+        &mut self,
+        clip: &mut A,
+        delta_seconds: f32,
+        pose: &mut [Transform],
+        mut on_event: F,
+    ) -> Result<RootMotionDelta> {
+        let duration = clip.duration();
+        let segments = self.segments(duration, delta_seconds * self.speed);
+        for (index, (start, end)) in segments.iter().enumerate() {
+            // a segment after a wrap starts on an event at the clip's end or start
+            let include_start = index > 0 || self.time == if start <= end { 0.0 } else { duration };
+            for event in crossed_events(clip.events(), *start, *end, include_start) {
+                on_event(event);
+            }
+        }
+
+        let mut delta = RootMotionDelta::default();
+        if let Some(root_motion) = self.root_motion {
+            for (start, end) in &segments {
+                let from = root_sample(clip, root_motion.node, *start)?;
+                let to = root_sample(clip, root_motion.node, *end)?;
+                let (from_facing, to_facing) = if root_motion.rotation {
+                    (yaw(&from.rotation), yaw(&to.rotation))
+                } else {
+                    (glm::UnitQuaternion::identity(), glm::UnitQuaternion::identity())
+                };
+                let mut moved = from_facing.inverse() * (to.translation - from.translation);
+                if root_motion.keep_vertical {
+                    moved.y = 0.0;
+                }
+                delta.translation += delta.rotation * moved;
+                delta.rotation *= to_facing * from_facing.inverse();
+            }
+        }
+
+        self.time = segments.last().map_or(self.time, |(_, end)| *end);
+        clip.sample_pose(self.time, pose)?;
+        if let Some(root_motion) = self.root_motion.filter(|root_motion| root_motion.node < pose.len()) {
+            let start = root_sample(clip, root_motion.node, 0.0)?;
+            let root = &mut pose[root_motion.node];
+            if root_motion.rotation {
+                root.rotation = yaw(&start.rotation) * yaw(&root.rotation).inverse() * root.rotation;
+            }
+            root.translation.x = start.translation.x;
+            root.translation.z = start.translation.z;
+            if !root_motion.keep_vertical {
+                root.translation.y = start.translation.y;
+            }
+        }
+        Ok(delta)
+    }
+
+    /// The stretches of the timeline `step` seconds from `time` covers, split where a loop wraps.
+    fn segments(&self, duration: f32, step: f32) -> Vec<(f32, f32)> {
+        let from = self.time.clamp(0.0, duration);
+        if step == 0.0 {
+            return vec![];
+        }
+        if !self.looping || duration <= 0.0 {
+            return vec![(from, (from + step).clamp(0.0, duration))];
+        }
+        // a step over whole loops is cut to the rest plus one loop, which still passes every event once
+        let mut remaining = step;
+        if step.abs() >= duration {
+            remaining = (step.abs().rem_euclid(duration) + duration).copysign(step);
+        }
+        let mut segments = vec![];
+        let mut start = from;
+        // up to the first wrap, a whole loop and the rest at most, a rounding leftover after that is dropped
+        for _ in 0..3 {
+            if remaining == 0.0 {
+                break;
+            }
+            let (bound, restart) = if remaining > 0.0 { (duration, 0.0) } else { (0.0, duration) };
+            let to_bound = bound - start;
+            if remaining.abs() < to_bound.abs() {
+                segments.push((start, start + remaining));
+                break;
+            }
+            segments.push((start, bound));
+            remaining -= to_bound;
+            start = restart;
+        }
+        segments
+    }
+}
+
+/// Events in `start..=end` in the order playback meets them, `start` itself only with `include_start`.
+fn crossed_events(events: &[AnimationEvent], start: f32, end: f32, include_start: bool) -> Vec<&AnimationEvent> {
+    let (low, high) = if start <= end { (start, end) } else { (end, start) };
+    let mut crossed: Vec<&AnimationEvent> = events
+        .iter()
+        .filter(|event| event.time >= low && event.time <= high && (include_start || event.time != start))
+        .collect();
+    if start > end {
+        crossed.reverse();
+    }
+    crossed
+}
+
+fn root_sample<A: AnimationSource + ?Sized>(clip: &mut A, node: usize, time: f32) -> Result<Transform> {
+    let mut pose = vec![Transform::IDENTITY; node + 1];
+    clip.sample_pose(time, &mut pose)?;
+    Ok(pose[node])
+}
+
+/// The twist of `rotation` around y.
+fn yaw(rotation: &glm::UnitQuaternion<f32>) -> glm::UnitQuaternion<f32> {
+    let twist = glm::Quaternion::new(rotation.w, 0.0, rotation.j, 0.0);
+    if twist.norm() > 0.0 {
+        glm::UnitQuaternion::from_quaternion(twist)
+    } else {
+        glm::UnitQuaternion::identity()
+    }
+}
+
+fn sample_keys<T: Fn(usize) -> f32, V: Fn(usize) -> glm::Vector4<f32>>(
+    count: usize,
+    time_of: T,
+    value_of: V,
+    time: f32,
+    property: Property,
+    interpolation: Interpolation,
+) -> Option<glm::Vector4<f32>> {
+    if count == 0 {
+        return None;
+    }
+    // first key after `time`
+    let (mut low, mut high) = (0, count);
+    while low < high {
+        let middle = (low + high) / 2;
+        if time_of(middle) <= time {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+    if low == 0 {
+        return Some(value_of(0));
+    }
+    if low == count || interpolation == Interpolation::Step {
+        return Some(value_of(low - 1));
+    }
+    let (from, to) = (time_of(low - 1), time_of(low));
+    let t = if to > from { (time - from) / (to - from) } else { 0.0 };
+    Some(blend(property, &value_of(low - 1), &value_of(low), t))
+}
+
+/// Rotations are normalized lerped along the shorter arc, the keys are close enough for it to match slerp.
+fn blend(property: Property, from: &glm::Vector4<f32>, to: &glm::Vector4<f32>, t: f32) -> glm::Vector4<f32> {
+    match property {
+        Property::Rotation => {
+            let to = if from.dot(to) < 0.0 { -to } else { *to };
+            let blended = from.lerp(&to, t);
+            let norm = blended.norm();
+            if norm > 0.0 {
+                blended / norm
+            } else {
+                *from
+            }
+        }
+        Property::Translation | Property::Scale => from.lerp(to, t),
+    }
+}
+
+fn value_error(property: Property, a: &glm::Vector4<f32>, b: &glm::Vector4<f32>) -> f32 {
+    match property {
+        Property::Rotation => 2.0 * a.normalize().dot(&b.normalize()).abs().min(1.0).acos(),
+        Property::Translation | Property::Scale => (a - b).xyz().amax(),
+    }
+}
+
+fn apply_value(transform: &mut Transform, property: Property, value: glm::Vector4<f32>) {
+    match property {
+        Property::Translation => transform.translation = value.xyz(),
+        Property::Rotation => {
+            transform.rotation = glm::UnitQuaternion::from_quaternion(glm::Quaternion::from(value));
+        }
+        Property::Scale => transform.scale = value.xyz(),
+    }
+}
+
+/// Indices of the keys to keep, every dropped key is within `tolerance` of the kept ones around it.
+fn fit_keys(channel: &Channel, values: &[glm::Vector4<f32>], tolerance: f32) -> Vec<usize> {
+    let count = values.len();
+    let mut kept = vec![0];
+    let mut anchor = 0;
+    for end in 2..count {
+        let fits = (anchor + 1..end).all(|key| {
+            let fitted = match channel.interpolation {
+                Interpolation::Step => values[anchor],
+                Interpolation::Linear => {
+                    let span = channel.times[end] - channel.times[anchor];
+                    let t = if span > 0.0 {
+                        (channel.times[key] - channel.times[anchor]) / span
+                    } else {
+                        0.0
+                    };
+                    blend(channel.property, &values[anchor], &values[end], t)
+                }
+            };
+            value_error(channel.property, &fitted, &values[key]) <= tolerance
+        });
+        if !fits {
+            anchor = end - 1;
+            kept.push(anchor);
+        }
+    }
+    if count > 1 {
+        kept.push(count - 1);
+    }
+    kept
+}
+
+fn compress_channel(channel: &Channel, tolerance: f32) -> (ChannelInfo, Keys) {
+    let mut values = channel.values.clone();
+    // q and -q are the same rotation, keeping neighbours in one hemisphere keeps the quantized range small
+    if channel.property == Property::Rotation {
+        for key in 1..values.len() {
+            if values[key].dot(&values[key - 1]) < 0.0 {
+                values[key] = -values[key];
+            }
+        }
+    }
+    let kept = fit_keys(channel, &values, tolerance);
+
+    let mut min = [f32::MAX; 4];
+    let mut max = [f32::MIN; 4];
+    for key in &kept {
+        for i in 0..4 {
+            min[i] = min[i].min(values[*key][i]);
+            max[i] = max[i].max(values[*key][i]);
+        }
+    }
+    let info = ChannelInfo {
+        node: channel.node,
+        property: channel.property,
+        interpolation: channel.interpolation,
+        min,
+        extent: std::array::from_fn(|i| max[i] - min[i]),
+    };
+    let keys = Keys {
+        times: kept
+            .iter()
+            .map(|key| (channel.times[*key].max(0.0) * TICKS_PER_SECOND).round() as u32)
+            .collect(),
+        values: kept.iter().map(|key| info.quantize(&values[*key])).collect(),
+    };
+    (info, keys)
+}
+
+fn write_channel_info(bytes: &mut Vec<u8>, info: &ChannelInfo) {
+    bytes.extend_from_slice(&(info.node as u32).to_le_bytes());
+    bytes.push(match info.property {
+        Property::Translation => 0,
+        Property::Rotation => 1,
+        Property::Scale => 2,
+    });
+    bytes.push(match info.interpolation {
+        Interpolation::Linear => 0,
+        Interpolation::Step => 1,
+    });
+    for value in info.min.iter().chain(&info.extent) {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn read_channel_info<R: Read>(reader: &mut R) -> Result<ChannelInfo> {
+    let node = read_u32(reader)? as usize;
+    let [property, interpolation] = read_array::<2>(reader)?;
+    let property = match property {
+        0 => Property::Translation,
+        1 => Property::Rotation,
+        2 => Property::Scale,
+        other => return Err(Error::msg(format!("Unknown animated property {}", other))),
+    };
+    let interpolation = match interpolation {
+        0 => Interpolation::Linear,
+        1 => Interpolation::Step,
+        other => return Err(Error::msg(format!("Unknown interpolation {}", other))),
+    };
+    let mut floats = [0.0; 8];
+    for value in floats.iter_mut() {
+        *value = read_f32(reader)?;
+    }
+    Ok(ChannelInfo {
+        node,
+        property,
+        interpolation,
+        min: [floats[0], floats[1], floats[2], floats[3]],
+        extent: [floats[4], floats[5], floats[6], floats[7]],
+    })
+}
+
+fn write_keys(bytes: &mut Vec<u8>, keys: &Keys) {
+    bytes.extend_from_slice(&(keys.times.len() as u32).to_le_bytes());
+    for (time, value) in keys.times.iter().zip(&keys.values) {
+        bytes.extend_from_slice(&time.to_le_bytes());
+        for component in value {
+            bytes.extend_from_slice(&component.to_le_bytes());
+        }
+    }
+}
+
+fn read_keys<R: Read>(reader: &mut R) -> Result<Keys> {
+    let count = read_u32(reader)? as usize;
+    let mut keys = Keys {
+        times: Vec::with_capacity(count),
+        values: Vec::with_capacity(count),
+    };
+    for _ in 0..count {
+        keys.times.push(read_u32(reader)?);
+        let value = read_array::<8>(reader)?;
+        keys.values
+            .push(std::array::from_fn(|i| u16::from_le_bytes([value[i * 2], value[i * 2 + 1]])));
+    }
+    Ok(keys)
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_f32(reader: &mut impl Read) -> Result<f32> {
+    Ok(f32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_string(reader: &mut impl Read) -> Result<String> {
+    let mut bytes = vec![0; read_u32(reader)? as usize];
+    reader.read_exact(&mut bytes)?;
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A walk cycle like clip, two nodes with a key every frame at 30 frames per second.
+    fn clip() -> AnimationClip {
+        let times: Vec<f32> = (0..=60).map(|frame| frame as f32 / 30.0).collect();
+        let translation = times
+            .iter()
+            .map(|time| glm::Vector4::new(time.sin(), (time * 3.0).cos() * 0.5, *time, 0.0))
+            .collect();
+        let rotation = times
+            .iter()
+            .map(|time| glm::UnitQuaternion::from_euler_angles(0.0, time * 2.0, 0.0).coords)
+            .collect();
+        let scale = times
+            .iter()
+            .map(|time| glm::Vector4::new(1.0 + time.floor(), 1.0, 1.0, 0.0))
+            .collect();
+
+        let mut clip = AnimationClip {
+            name: Some("walk".to_owned()),
+            duration: 2.0,
+            channels: vec![
+                Channel {
+                    node: 0,
+                    property: Property::Translation,
+                    interpolation: Interpolation::Linear,
+                    times: times.clone(),
+                    values: translation,
+                },
+                Channel {
+                    node: 1,
+                    property: Property::Rotation,
+                    interpolation: Interpolation::Linear,
+                    times: times.clone(),
+                    values: rotation,
+                },
+                Channel {
+                    node: 1,
+                    property: Property::Scale,
+                    interpolation: Interpolation::Step,
+                    times,
+                    values: scale,
+                },
+            ],
+            events: vec![],
+        };
+        clip.add_event(0.5, "left foot");
+        clip.add_event(1.5, "right foot");
+        clip
+    }
+
+    #[test]
+    fn stream_round_trip_matches_compressed_clip() {
+        let settings = CompressionSettings::default();
+        let compressed = CompressedClip::compress(&clip(), &settings);
+        let path = std::env::temp_dir().join(format!("vulky-clip-stream-{}.anim", std::process::id()));
+        compressed.write_stream(&path, 0.3).unwrap();
+
+        let mut stream = ClipStream::open(&path, 2).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(stream.name(), Some("walk"));
+        assert_eq!(stream.duration(), 2.0);
+        assert_eq!(stream.events, compressed.events);
+        assert_eq!(stream.chunk_count(), 7);
+
+        // every few milliseconds, right before, on and after each chunk boundary too
+        let mut times: Vec<f32> = (0..=400).map(|step| step as f32 * 0.005).collect();
+        for chunk in 1..7 {
+            let boundary = chunk as f32 * 0.3;
+            times.extend([boundary - 0.0001, boundary, boundary + 0.0001]);
+        }
+        for time in times {
+            let mut expected = [Transform::IDENTITY; 2];
+            compressed.sample(time, &mut expected);
+            let mut pose = [Transform::IDENTITY; 2];
+            stream.sample(time, &mut pose).unwrap();
+
+            for (pose, expected) in pose.iter().zip(&expected) {
+                let translation = (pose.translation - expected.translation).norm();
+                assert!(translation <= settings.translation_tolerance, "translation at {}", time);
+                let rotation = pose.rotation.angle_to(&expected.rotation);
+                assert!(rotation <= settings.rotation_tolerance, "rotation at {}", time);
+                let scale = (pose.scale - expected.scale).norm();
+                assert!(scale <= settings.scale_tolerance, "scale at {}", time);
+            }
+            assert!(stream.resident.len() <= 2);
+        }
+    }
+
+    #[test]
+    fn stream_rejects_other_files() {
+        let path = std::env::temp_dir().join(format!("vulky-not-a-stream-{}.anim", std::process::id()));
+        std::fs::write(&path, b"not an animation stream").unwrap();
+        let result = ClipStream::open(&path, 2);
+        std::fs::remove_file(&path).unwrap();
+        assert!(result.is_err());
+    }
+
+    fn player_at(time: f32) -> AnimationPlayer {
+        AnimationPlayer {
+            time,
+            ..AnimationPlayer::new(true)
+        }
+    }
+
+    #[test]
+    fn segments_drop_the_loops_a_step_repeats() {
+        let segments = player_at(0.25).segments(1.0, 2.5);
+        assert_eq!(segments, vec![(0.25, 1.0), (0.0, 0.75)]);
+        let segments = player_at(1.0).segments(1.0, 3.0);
+        assert_eq!(segments, vec![(1.0, 1.0), (0.0, 1.0)]);
+
+        let segments = player_at(0.25).segments(1.0, -0.5);
+        assert_eq!(segments, vec![(0.25, 0.0), (1.0, 0.75)]);
+    }
+
+    #[test]
+    fn segments_of_a_huge_step_over_a_tiny_clip_stay_bounded() {
+        for step in [1.0e9, -1.0e9, f32::MAX] {
+            let segments = player_at(0.004).segments(0.01, step);
+            assert!((1..=3).contains(&segments.len()), "{:?} for {}", segments, step);
+            for (start, end) in segments {
+                assert!((0.0..=0.01).contains(&start) && (0.0..=0.01).contains(&end));
+            }
+        }
+    }
+}
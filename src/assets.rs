@@ -0,0 +1,702 @@
+use std::{
+    cell::Cell,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Weak,
+    },
+};
+
+use crate::error::{Error, Result};
+use ash::vk;
+
+use crate::{
+    buffer::{OneTimeCommands, MAX_FRAMES_IN_FLIGHT},
+    material::{Material, MaterialInstance},
+    mesh::{GpuMesh, Mesh},
+    texture::Texture,
+};
+
+/// GPU objects that can be owned by `Assets`.
+pub trait GpuResource {
+    /// # Safety
+    /// `device` has to be the device the resource was created on and the GPU has to be done with it.
+    unsafe fn destroy(&self, device: &ash::Device);
+
+    /// Device memory counted against the residency budget, 0 for assets that can't be evicted.
+    ///
+    /// # Safety
+    /// `device` has to be the device the resource was created on.
+    unsafe fn memory_size(&self, _device: &ash::Device) -> vk::DeviceSize {
+        0
+    }
+}
+
+impl GpuResource for Texture {
+    unsafe fn destroy(&self, device: &ash::Device) {
+        Texture::destroy(self, device)
+    }
+
+    unsafe fn memory_size(&self, device: &ash::Device) -> vk::DeviceSize {
+        device.get_image_memory_requirements(self.image).size
+    }
+}
+
+impl GpuResource for GpuMesh {
+    unsafe fn destroy(&self, device: &ash::Device) {
+        GpuMesh::destroy(self, device)
+    }
+
+    unsafe fn memory_size(&self, device: &ash::Device) -> vk::DeviceSize {
+        device.get_buffer_memory_requirements(self.vertex_buffer).size
+            + device.get_buffer_memory_requirements(self.index_buffer).size
+    }
+}
+
+impl GpuResource for Material {
+    unsafe fn destroy(&self, device: &ash::Device) {
+        Material::destroy(self, device)
+    }
+}
+
+impl GpuResource for MaterialInstance {
+    unsafe fn destroy(&self, device: &ash::Device) {
+        MaterialInstance::destroy(self, device)
+    }
+}
+
+struct HandleInner {
+    index: u32,
+    generation: u32,
+    dropped: Sender<(u32, u32)>,
+}
+
+impl Drop for HandleInner {
+    fn drop(&mut self) {
+        // the storage may already be gone
+        let _ = self.dropped.send((self.index, self.generation));
+    }
+}
+
+/// Reference counted handle to an asset in `Assets<T>`, the asset is destroyed after the last clone drops.
+pub struct Handle<T> {
+    inner: Arc<HandleInner>,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(inner: Arc<HandleInner>) -> Self {
+        Self {
+            inner,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn strong_count(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.inner.clone())
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner.index == other.inner.index && self.inner.generation == other.inner.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.inner.index.hash(state);
+        self.inner.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.inner.index, self.inner.generation)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum LoadState {
+    Loading,
+    Ready,
+    /// what the loader failed with
+    Failed(Arc<Error>),
+    /// dropped to stay in the memory budget, loading the path again streams it back in
+    Evicted,
+}
+
+/// Order in which assets are evicted when the memory budget is exceeded, lower priorities go first.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResidencyPriority {
+    /// scenery and other assets that are cheap to lose
+    Low,
+    #[default]
+    Normal,
+    High,
+    /// never evicted, for UI and hero assets
+    Resident,
+}
+
+struct Slot<T> {
+    generation: u32,
+    asset: Option<T>,
+    state: LoadState,
+    path: Option<PathBuf>,
+    priority: ResidencyPriority,
+    /// `Assets::frame` of the last `try_get`
+    last_used: Cell<u64>,
+}
+
+/// An asset that `evict` may drop.
+#[derive(Clone, Copy, Debug)]
+pub struct EvictionCandidate {
+    pub priority: ResidencyPriority,
+    pub last_used: u64,
+    pub size: vk::DeviceSize,
+    index: u32,
+}
+
+/// Storage of one asset type with generational slots.
+/// Assets loaded from the same path share one slot as long as a handle to it is alive.
+pub struct Assets<T: GpuResource> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    by_path: HashMap<PathBuf, Weak<HandleInner>>,
+    dropped_sender: Sender<(u32, u32)>,
+    dropped: Receiver<(u32, u32)>,
+    /// assets without handles and the number of `maintain` calls until no frame in flight can use them
+    pending_destroy: Vec<(T, u32)>,
+    /// number of `maintain` calls, assets used in the current frame are never evicted
+    frame: u64,
+}
+
+impl<T: GpuResource> Default for Assets<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: GpuResource> Assets<T> {
+    pub fn new() -> Self {
+        let (dropped_sender, dropped) = mpsc::channel();
+        Self {
+            slots: vec![],
+            free: vec![],
+            by_path: HashMap::new(),
+            dropped_sender,
+            dropped,
+            pending_destroy: vec![],
+            frame: 0,
+        }
+    }
+
+    pub fn add(&mut self, asset: T) -> Handle<T> {
+        self.insert(Some(asset), None)
+    }
+
+    fn insert(&mut self, asset: Option<T>, path: Option<PathBuf>) -> Handle<T> {
+        let state = match asset {
+            Some(_) => LoadState::Ready,
+            None => LoadState::Loading,
+        };
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.asset = asset;
+                slot.state = state;
+                slot.path = path;
+                slot.priority = ResidencyPriority::default();
+                slot.last_used.set(self.frame);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    asset,
+                    state,
+                    path,
+                    priority: ResidencyPriority::default(),
+                    last_used: Cell::new(self.frame),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+
+        Handle::new(Arc::new(HandleInner {
+            index,
+            generation: self.slots[index as usize].generation,
+            dropped: self.dropped_sender.clone(),
+        }))
+    }
+
+    fn path_key(path: &Path) -> PathBuf {
+        // different spellings of the same file share the asset
+        path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+    }
+
+    /// Returns the existing asset for `path` or loads it with `load`.
+    pub fn get_or_load<P: AsRef<Path>, F: FnOnce(&Path) -> Result<T>>(&mut self, path: P, load: F) -> Result<Handle<T>> {
+        let path = path.as_ref();
+        let key = Self::path_key(path);
+
+        if let Some(inner) = self.by_path.get(&key).and_then(|weak| weak.upgrade()) {
+            return Ok(Handle::new(inner));
+        }
+
+        let asset = load(path)?;
+        let handle = self.insert(Some(asset), Some(key.clone()));
+        self.by_path.insert(key, Arc::downgrade(&handle.inner));
+        Ok(handle)
+    }
+
+    /// Handle to the asset of `path` without loading it, true if the asset is new or evicted and has to be loaded.
+    /// Those assets are `LoadState::Loading` until `finish_load` is called.
+    pub fn reserve<P: AsRef<Path>>(&mut self, path: P) -> (Handle<T>, bool) {
+        let key = Self::path_key(path.as_ref());
+
+        if let Some(inner) = self.by_path.get(&key).and_then(|weak| weak.upgrade()) {
+            let slot = &mut self.slots[inner.index as usize];
+            let reload = matches!(slot.state, LoadState::Evicted);
+            if reload {
+                slot.state = LoadState::Loading;
+            }
+            return (Handle::new(inner), reload);
+        }
+
+        let handle = self.insert(None, Some(key.clone()));
+        self.by_path.insert(key, Arc::downgrade(&handle.inner));
+        (handle, true)
+    }
+
+    pub fn finish_load(&mut self, handle: &Handle<T>, result: Result<T>) {
+        let slot = &mut self.slots[handle.inner.index as usize];
+        match result {
+            Ok(asset) => {
+                slot.asset = Some(asset);
+                slot.state = LoadState::Ready;
+            }
+            Err(e) => {
+                log::error!("Failed to load {:?}: {}", slot.path, e);
+                slot.state = LoadState::Failed(Arc::new(e));
+            }
+        }
+    }
+
+    pub fn load_state(&self, handle: &Handle<T>) -> &LoadState {
+        &self.slots[handle.inner.index as usize].state
+    }
+
+    /// None while the asset is loading, failed or evicted, show a placeholder instead.
+    /// Marks the asset as used this frame, which keeps it from being evicted.
+    pub fn try_get(&self, handle: &Handle<T>) -> Option<&T> {
+        let slot = &self.slots[handle.inner.index as usize];
+        slot.last_used.set(self.frame);
+        slot.asset.as_ref()
+    }
+
+    /// `handle` has to come from this storage and the asset has to be `LoadState::Ready`.
+    pub fn get(&self, handle: &Handle<T>) -> &T {
+        self.try_get(handle).expect("Asset isn't loaded")
+    }
+
+    pub fn get_mut(&mut self, handle: &Handle<T>) -> &mut T {
+        self.slots[handle.inner.index as usize]
+            .asset
+            .as_mut()
+            .expect("Asset isn't loaded")
+    }
+
+    pub fn path(&self, handle: &Handle<T>) -> Option<&Path> {
+        self.slots[handle.inner.index as usize].path.as_deref()
+    }
+
+    pub fn set_priority(&mut self, handle: &Handle<T>, priority: ResidencyPriority) {
+        self.slots[handle.inner.index as usize].priority = priority;
+    }
+
+    pub fn priority(&self, handle: &Handle<T>) -> ResidencyPriority {
+        self.slots[handle.inner.index as usize].priority
+    }
+
+    /// Device memory of the loaded assets.
+    ///
+    /// # Safety
+    /// `device` has to be the device the assets were loaded on.
+    pub unsafe fn resident_size(&self, device: &ash::Device) -> vk::DeviceSize {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.asset.as_ref())
+            .map(|asset| asset.memory_size(device))
+            .sum()
+    }
+
+    /// Loaded assets that can be streamed back in from their path, aren't `ResidencyPriority::Resident`
+    /// and weren't used this frame.
+    ///
+    /// # Safety
+    /// As for `resident_size`.
+    pub unsafe fn eviction_candidates(&self, device: &ash::Device) -> Vec<EvictionCandidate> {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| {
+                slot.path.is_some() && slot.priority != ResidencyPriority::Resident && slot.last_used.get() < self.frame
+            })
+            .filter_map(|(index, slot)| {
+                let size = slot.asset.as_ref()?.memory_size(device);
+                Some(EvictionCandidate {
+                    priority: slot.priority,
+                    last_used: slot.last_used.get(),
+                    size,
+                    index: index as u32,
+                })
+            })
+            .collect()
+    }
+
+    /// Drops the GPU data of `candidate`, it's destroyed once no frame in flight can use it.
+    /// The handle stays valid and is `LoadState::Evicted` until its path is loaded again.
+    pub fn evict(&mut self, candidate: &EvictionCandidate) {
+        let slot = &mut self.slots[candidate.index as usize];
+        if let Some(asset) = slot.asset.take() {
+            self.pending_destroy.push((asset, MAX_FRAMES_IN_FLIGHT as u32));
+            slot.state = LoadState::Evicted;
+        }
+    }
+
+    /// Evicts the lowest priority and least recently used assets until at most `budget` bytes are resident.
+    /// Returns the number of evicted bytes.
+    ///
+    /// # Safety
+    /// As for `resident_size`, evicted assets are only destroyed by a later `maintain`.
+    pub unsafe fn evict_to_budget(&mut self, device: &ash::Device, budget: vk::DeviceSize) -> vk::DeviceSize {
+        let resident = self.resident_size(device);
+        let mut candidates = self.eviction_candidates(device);
+        candidates.sort_by_key(|candidate| (candidate.priority, candidate.last_used));
+
+        let mut evicted = 0;
+        for candidate in candidates.iter() {
+            if resident - evicted <= budget {
+                break;
+            }
+            self.evict(candidate);
+            evicted += candidate.size;
+        }
+        evicted
+    }
+
+    /// Number of live assets, assets waiting to be destroyed aren't counted.
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Destroys assets whose last handle dropped at least `MAX_FRAMES_IN_FLIGHT` calls ago.
+    /// Call it once per frame after waiting on the frame's fence.
+    ///
+    /// # Safety
+    /// The frame fence has to be waited on, a dropped asset may still be in use by the frame before.
+    pub unsafe fn maintain(&mut self, device: &ash::Device) {
+        for asset in self.advance_frame() {
+            asset.destroy(device);
+        }
+    }
+
+    /// Frees the slots of dropped handles and returns the assets no frame in flight can use anymore.
+    fn advance_frame(&mut self) -> Vec<T> {
+        self.frame += 1;
+        let mut expired = vec![];
+        for (asset, frames_left) in std::mem::take(&mut self.pending_destroy) {
+            match frames_left - 1 {
+                0 => expired.push(asset),
+                frames_left => self.pending_destroy.push((asset, frames_left)),
+            }
+        }
+
+        while let Ok((index, generation)) = self.dropped.try_recv() {
+            let slot = &mut self.slots[index as usize];
+            if slot.generation != generation {
+                continue;
+            }
+
+            if let Some(path) = slot.path.take() {
+                // the path may already point to a newer load of the same file
+                if self.by_path.get(&path).is_some_and(|weak| weak.strong_count() == 0) {
+                    self.by_path.remove(&path);
+                }
+            }
+            if let Some(asset) = slot.asset.take() {
+                self.pending_destroy.push((asset, MAX_FRAMES_IN_FLIGHT as u32));
+            }
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(index);
+        }
+        expired
+    }
+
+    /// Destroys every asset, still alive handles must not be used afterwards.
+    /// The device has to be idle.
+    ///
+    /// # Safety
+    /// Nothing may be executing on `device` that uses one of the assets.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for (asset, _) in self.pending_destroy.drain(..) {
+            asset.destroy(device);
+        }
+        for slot in self.slots.iter_mut() {
+            if let Some(asset) = slot.asset.take() {
+                asset.destroy(device);
+            }
+        }
+        self.by_path.clear();
+    }
+}
+
+/// The asset storages of a renderer.
+#[derive(Default)]
+pub struct AssetManager {
+    pub textures: Assets<Texture>,
+    pub meshes: Assets<GpuMesh>,
+    pub materials: Assets<Material>,
+    pub material_instances: Assets<MaterialInstance>,
+}
+
+impl AssetManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Textures are deduplicated by path only, loading the same file with another format returns the first one.
+    /// `upload` has to belong to the graphics family.
+    ///
+    /// # Safety
+    /// Blocks on the queue of `upload` for a newly loaded texture.
+    pub unsafe fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        upload: OneTimeCommands,
+        path: P,
+        format: vk::Format,
+    ) -> Result<Handle<Texture>> {
+        self.textures.get_or_load(path, |path| {
+            Texture::load(device, instance, physical_device, upload, path, format)
+        })
+    }
+
+    /// `command_pool` and `queue` are used for the staging copy and have to belong to the transfer family.
+    ///
+    /// # Safety
+    /// Blocks on `queue` until the staging copy of a newly loaded mesh finished.
+    pub unsafe fn load_obj<P: AsRef<Path>>(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        path: P,
+    ) -> Result<Handle<GpuMesh>> {
+        self.meshes.get_or_load(path, |path| {
+            let mesh = Mesh::load_obj(path)?;
+            Ok(mesh.upload(device, instance, physical_device, command_pool, queue)?)
+        })
+    }
+
+    /// Device memory of the textures and meshes.
+    ///
+    /// # Safety
+    /// `device` has to be the device of the storages.
+    pub unsafe fn resident_size(&self, device: &ash::Device) -> vk::DeviceSize {
+        self.textures.resident_size(device) + self.meshes.resident_size(device)
+    }
+
+    /// Evicts textures and meshes until at most `budget` bytes are resident, call it when the streaming
+    /// system runs out of memory. Lower `ResidencyPriority` goes first, within a priority the least recently
+    /// used asset. Returns the number of evicted bytes.
+    ///
+    /// # Safety
+    /// As for `Assets::evict_to_budget`.
+    pub unsafe fn evict_to_budget(&mut self, device: &ash::Device, budget: vk::DeviceSize) -> vk::DeviceSize {
+        enum Kind {
+            Texture,
+            Mesh,
+        }
+
+        let resident = self.resident_size(device);
+        let mut candidates: Vec<_> = self
+            .textures
+            .eviction_candidates(device)
+            .into_iter()
+            .map(|candidate| (Kind::Texture, candidate))
+            .chain(
+                self.meshes
+                    .eviction_candidates(device)
+                    .into_iter()
+                    .map(|candidate| (Kind::Mesh, candidate)),
+            )
+            .collect();
+        candidates.sort_by_key(|(_, candidate)| (candidate.priority, candidate.last_used));
+
+        let mut evicted = 0;
+        for (kind, candidate) in candidates.iter() {
+            if resident - evicted <= budget {
+                break;
+            }
+            match kind {
+                Kind::Texture => self.textures.evict(candidate),
+                Kind::Mesh => self.meshes.evict(candidate),
+            }
+            evicted += candidate.size;
+        }
+        evicted
+    }
+
+    /// # Safety
+    /// As for `Assets::maintain`, call it once per frame after the fence of the frame was waited on.
+    pub unsafe fn maintain(&mut self, device: &ash::Device) {
+        // instances reference materials, so they go first
+        self.material_instances.maintain(device);
+        self.materials.maintain(device);
+        self.meshes.maintain(device);
+        self.textures.maintain(device);
+    }
+
+    /// # Safety
+    /// The device has to be idle, no handle of the storages may be used afterwards.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        self.material_instances.destroy(device);
+        self.materials.destroy(device);
+        self.meshes.destroy(device);
+        self.textures.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Fake(u32);
+
+    impl GpuResource for Fake {
+        unsafe fn destroy(&self, _device: &ash::Device) {
+            unreachable!("The tests take expired assets from advance_frame")
+        }
+    }
+
+    /// Runs `advance_frame` until it returns something, the number of calls with what it returned.
+    fn frames_until_destroyed(assets: &mut Assets<Fake>) -> (u8, Vec<Fake>) {
+        for frame in 1..=MAX_FRAMES_IN_FLIGHT + 1 {
+            let expired = assets.advance_frame();
+            if !expired.is_empty() {
+                return (frame, expired);
+            }
+        }
+        (0, vec![])
+    }
+
+    #[test]
+    fn the_last_dropped_handle_destroys_after_the_frames_in_flight() {
+        let mut assets = Assets::new();
+        let handle = assets.add(Fake(1));
+        let clone = handle.clone();
+        assert_eq!(handle.strong_count(), 2);
+        drop(handle);
+        assert!(assets.advance_frame().is_empty());
+        assert_eq!(assets.get(&clone), &Fake(1));
+
+        drop(clone);
+        assert_eq!(frames_until_destroyed(&mut assets), (MAX_FRAMES_IN_FLIGHT + 1, vec![Fake(1)]));
+        assert!(assets.is_empty());
+    }
+
+    #[test]
+    fn freed_slots_are_reused_with_a_new_generation() {
+        let mut assets = Assets::new();
+        let first = assets.add(Fake(1));
+        let (index, generation) = (first.inner.index, first.inner.generation);
+        drop(first);
+        assets.advance_frame();
+
+        let second = assets.add(Fake(2));
+        assert_eq!(second.inner.index, index);
+        assert_eq!(second.inner.generation, generation + 1);
+        assert_eq!(assets.get(&second), &Fake(2));
+        assert_eq!(assets.len(), 1);
+
+        // a drop notice of the old generation doesn't free the new asset
+        assets.dropped_sender.send((index, generation)).unwrap();
+        assets.advance_frame();
+        assert_eq!(assets.len(), 1);
+    }
+
+    #[test]
+    fn loads_of_one_path_share_the_asset_while_a_handle_lives() {
+        let mut assets = Assets::new();
+        let mut loads = 0;
+        let mut load = |_: &Path| {
+            loads += 1;
+            Ok(Fake(loads))
+        };
+        let a = assets.get_or_load("missing/rock.png", &mut load).unwrap();
+        let b = assets.get_or_load("missing/rock.png", &mut load).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(assets.path(&a), Some(Path::new("missing/rock.png")));
+
+        drop((a, b));
+        assets.advance_frame();
+        let c = assets.get_or_load("missing/rock.png", &mut load).unwrap();
+        assert_eq!(assets.get(&c), &Fake(2));
+        assert!(assets
+            .get_or_load("missing/other.png", |_| Err(Error::msg("no file")))
+            .is_err());
+    }
+
+    #[test]
+    fn reserved_and_evicted_assets_report_their_state() {
+        let mut assets = Assets::new();
+        let (handle, load) = assets.reserve("missing/tree.png");
+        assert!(load);
+        let (same, load) = assets.reserve("missing/tree.png");
+        assert!(!load && same == handle);
+        assert!(matches!(assets.load_state(&handle), LoadState::Loading));
+        assert_eq!(assets.try_get(&handle), None);
+
+        assets.finish_load(&handle, Ok(Fake(1)));
+        assert!(matches!(assets.load_state(&handle), LoadState::Ready));
+        let candidate = EvictionCandidate {
+            priority: assets.priority(&handle),
+            last_used: 0,
+            size: 0,
+            index: handle.inner.index,
+        };
+        assets.evict(&candidate);
+        assert!(matches!(assets.load_state(&handle), LoadState::Evicted));
+        assert_eq!(assets.try_get(&handle), None);
+        assert_eq!(frames_until_destroyed(&mut assets), (MAX_FRAMES_IN_FLIGHT, vec![Fake(1)]));
+
+        // loading the path again streams it back into the same handle
+        let (reloaded, load) = assets.reserve("missing/tree.png");
+        assert!(load && reloaded == handle);
+        assets.finish_load(&handle, Err(Error::msg("corrupt")));
+        assert!(matches!(assets.load_state(&handle), LoadState::Failed(_)));
+    }
+}
@@ -0,0 +1,1254 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use ash::vk;
+
+use crate::{
+    features::DeviceFeatures,
+    format,
+    sync::QueueTransfer,
+    texture::{DepthBuffer, Texture},
+};
+
+const WRITE_ACCESS: vk::AccessFlags2 = vk::AccessFlags2::from_raw(
+    vk::AccessFlags2::SHADER_WRITE.as_raw()
+        | vk::AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | vk::AccessFlags2::TRANSFER_WRITE.as_raw()
+        | vk::AccessFlags2::HOST_WRITE.as_raw()
+        | vk::AccessFlags2::MEMORY_WRITE.as_raw(),
+);
+
+/// `vkCmdPipelineBarrier2` is core in Vulkan 1.3 but only usable with this feature enabled.
+pub fn required_features() -> DeviceFeatures {
+    DeviceFeatures {
+        synchronization2: true,
+        ..Default::default()
+    }
+}
+
+/// How a command uses a resource, `layout` is ignored for buffers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub stages: vk::PipelineStageFlags2,
+    pub access: vk::AccessFlags2,
+    pub layout: vk::ImageLayout,
+}
+
+impl Access {
+    /// Before the first use, the content is discarded.
+    pub const NONE: Access = Access::new(
+        vk::PipelineStageFlags2::NONE,
+        vk::AccessFlags2::NONE,
+        vk::ImageLayout::UNDEFINED,
+    );
+    pub const TRANSFER_READ: Access = Access::new(
+        vk::PipelineStageFlags2::TRANSFER,
+        vk::AccessFlags2::TRANSFER_READ,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+    );
+    pub const TRANSFER_WRITE: Access = Access::new(
+        vk::PipelineStageFlags2::TRANSFER,
+        vk::AccessFlags2::TRANSFER_WRITE,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+    );
+    pub const FRAGMENT_SAMPLED: Access = Access::new(
+        vk::PipelineStageFlags2::FRAGMENT_SHADER,
+        vk::AccessFlags2::SHADER_SAMPLED_READ,
+        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    );
+    pub const COMPUTE_READ: Access = Access::new(
+        vk::PipelineStageFlags2::COMPUTE_SHADER,
+        vk::AccessFlags2::SHADER_STORAGE_READ,
+        vk::ImageLayout::GENERAL,
+    );
+    pub const COMPUTE_WRITE: Access = Access::new(
+        vk::PipelineStageFlags2::COMPUTE_SHADER,
+        vk::AccessFlags2::SHADER_STORAGE_WRITE,
+        vk::ImageLayout::GENERAL,
+    );
+    pub const COLOR_ATTACHMENT: Access = Access::new(
+        vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
+        vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::COLOR_ATTACHMENT_READ.as_raw() | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw(),
+        ),
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    );
+    pub const DEPTH_ATTACHMENT: Access = Access::new(
+        vk::PipelineStageFlags2::from_raw(
+            vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS.as_raw() | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS.as_raw(),
+        ),
+        vk::AccessFlags2::from_raw(
+            vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+                | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+        ),
+        vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    );
+    /// The presentation engine waits on a semaphore, the barrier only changes the layout.
+    pub const PRESENT: Access = Access::new(
+        vk::PipelineStageFlags2::NONE,
+        vk::AccessFlags2::NONE,
+        vk::ImageLayout::PRESENT_SRC_KHR,
+    );
+
+    pub const fn new(stages: vk::PipelineStageFlags2, access: vk::AccessFlags2, layout: vk::ImageLayout) -> Self {
+        Self { stages, access, layout }
+    }
+
+    pub fn writes(&self) -> bool {
+        self.access.intersects(WRITE_ACCESS)
+    }
+}
+
+/// What the tracker knows about one resource since its last barrier.
+#[derive(Clone, Copy, Debug)]
+struct ResourceState {
+    layout: vk::ImageLayout,
+    /// the last write, later accesses have to wait for it
+    write_stages: vk::PipelineStageFlags2,
+    write_access: vk::AccessFlags2,
+    /// reads since the last write, a write has to wait for them
+    read_stages: vk::PipelineStageFlags2,
+    /// stages and accesses the last write is already visible to
+    visible_stages: vk::PipelineStageFlags2,
+    visible_access: vk::AccessFlags2,
+}
+
+impl ResourceState {
+    fn new(access: Access) -> Self {
+        let mut state = Self {
+            layout: access.layout,
+            write_stages: vk::PipelineStageFlags2::NONE,
+            write_access: vk::AccessFlags2::NONE,
+            read_stages: vk::PipelineStageFlags2::NONE,
+            visible_stages: vk::PipelineStageFlags2::NONE,
+            visible_access: vk::AccessFlags2::NONE,
+        };
+        if access.writes() {
+            state.write_stages = access.stages;
+            state.write_access = access.access & WRITE_ACCESS;
+        } else {
+            state.read_stages = access.stages;
+            state.visible_stages = access.stages;
+            state.visible_access = access.access;
+        }
+        state
+    }
+
+    /// The source scope of the barrier `next` needs, None when it needs none.
+    fn transition(&mut self, next: Access, has_layout: bool) -> Option<(vk::PipelineStageFlags2, vk::AccessFlags2)> {
+        let layout_changes = has_layout && next.layout != self.layout;
+        if !layout_changes && !next.writes() {
+            // read after read only needs the last write to be visible already
+            let seen = self.visible_stages.contains(next.stages) && self.visible_access.contains(next.access);
+            if seen || (self.write_access.is_empty() && self.write_stages.is_empty()) {
+                self.read_stages |= next.stages;
+                return None;
+            }
+            let src = (self.write_stages, self.write_access);
+            self.read_stages |= next.stages;
+            self.visible_stages |= next.stages;
+            self.visible_access |= next.access;
+            return Some(src);
+        }
+
+        let src = (self.write_stages | self.read_stages, self.write_access);
+        self.layout = next.layout;
+        if next.writes() {
+            self.write_stages = next.stages;
+            self.write_access = next.access & WRITE_ACCESS;
+            self.read_stages = vk::PipelineStageFlags2::NONE;
+            self.visible_stages = vk::PipelineStageFlags2::NONE;
+            self.visible_access = vk::AccessFlags2::NONE;
+        } else {
+            // the layout transition is a write its destination scope already sees
+            self.write_stages = next.stages;
+            self.write_access = vk::AccessFlags2::NONE;
+            self.read_stages = next.stages;
+            self.visible_stages = next.stages;
+            self.visible_access = next.access;
+        }
+        Some(src)
+    }
+}
+
+/// Where a tracked resource is after the barriers declared so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TrackedState {
+    /// UNDEFINED for buffers
+    pub layout: vk::ImageLayout,
+    /// the last write, NONE when the content was never written through the tracker
+    pub write_stages: vk::PipelineStageFlags2,
+    pub write_access: vk::AccessFlags2,
+    /// stages that read it since the last write
+    pub read_stages: vk::PipelineStageFlags2,
+}
+
+impl From<ResourceState> for TrackedState {
+    fn from(state: ResourceState) -> Self {
+        Self {
+            layout: state.layout,
+            write_stages: state.write_stages,
+            write_access: state.write_access,
+            read_stages: state.read_stages,
+        }
+    }
+}
+
+/// Barriers asked for, recorded and dropped since the last `BarrierBatch::end_frame`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct BarrierStats {
+    pub requested: usize,
+    pub recorded: usize,
+    /// needed no barrier at all
+    pub redundant: usize,
+    /// folded into a barrier of the same resource waiting in the batch
+    pub merged: usize,
+    /// `vkCmdPipelineBarrier2` calls
+    pub calls: usize,
+}
+
+impl BarrierStats {
+    pub fn eliminated(&self) -> usize {
+        self.redundant + self.merged
+    }
+}
+
+/// A use declared since the last flush.
+#[derive(Clone, Copy, Debug)]
+struct BatchedUse {
+    /// the state before the batch, another use of the batch recomputes the barrier from it
+    before: ResourceState,
+    access: Access,
+    /// index of its barrier in the batch
+    barrier: Option<usize>,
+}
+
+/// What `Tracker::declare` found the use needs.
+#[derive(Debug, PartialEq)]
+enum Step {
+    Redundant,
+    /// widens barrier `index` to the combined access of the batch
+    Merge(usize, (vk::PipelineStageFlags2, vk::AccessFlags2), Access),
+    New(vk::ImageLayout, (vk::PipelineStageFlags2, vk::AccessFlags2), Access),
+}
+
+struct Tracker<K> {
+    states: HashMap<K, ResourceState>,
+    batch: HashMap<K, BatchedUse>,
+}
+
+impl<K> Default for Tracker<K> {
+    fn default() -> Self {
+        Self {
+            states: HashMap::new(),
+            batch: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Copy + Eq + Hash + fmt::Debug> Tracker<K> {
+    fn declare(&mut self, key: K, next: Access, has_layout: bool) -> Step {
+        let state = self.states.entry(key).or_insert_with(|| ResourceState::new(Access::NONE));
+        let Some(batched) = self.batch.get_mut(&key) else {
+            let before = *state;
+            let src = state.transition(next, has_layout);
+            self.batch.insert(
+                key,
+                BatchedUse {
+                    before,
+                    access: next,
+                    barrier: None,
+                },
+            );
+            return match src {
+                Some(src) => Step::New(before.layout, src, next),
+                None => Step::Redundant,
+            };
+        };
+
+        batched.access.stages |= next.stages;
+        batched.access.access |= next.access;
+        let mut combined = batched.before;
+        let src = combined.transition(batched.access, has_layout);
+        *state = combined;
+        match (src, batched.barrier) {
+            (None, _) => Step::Redundant,
+            (Some(src), Some(index)) => Step::Merge(index, src, batched.access),
+            (Some(src), None) => Step::New(batched.before.layout, src, batched.access),
+        }
+    }
+
+    /// Layout the image has for the commands after the next flush, a second use in the batch has to agree.
+    fn batched_layout(&self, key: K) -> Option<vk::ImageLayout> {
+        self.batch.get(&key).map(|batched| batched.access.layout)
+    }
+
+    fn set_barrier(&mut self, key: K, index: usize) {
+        if let Some(batched) = self.batch.get_mut(&key) {
+            batched.barrier = Some(index);
+        }
+    }
+}
+
+/// Kept per image in debug builds, oldest first.
+pub const HISTORY_LENGTH: usize = 32;
+
+#[derive(Clone, Debug)]
+struct LayoutEvent {
+    frame: u64,
+    /// flushes earlier in the frame
+    batch: usize,
+    old_layout: Option<vk::ImageLayout>,
+    access: Access,
+}
+
+impl fmt::Display for LayoutEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "frame {} batch {}: ", self.frame, self.batch)?;
+        match self.old_layout {
+            Some(old_layout) => write!(f, "{:?} -> {:?}", old_layout, self.access.layout)?,
+            None => write!(f, "imported as {:?}", self.access.layout)?,
+        }
+        if self.access.stages.is_empty() {
+            Ok(())
+        } else {
+            write!(f, " for {:?} {:?}", self.access.stages, self.access.access)
+        }
+    }
+}
+
+/// Names and layout transitions of the tracked images, for panics that show how an image got into its layout.
+#[derive(Default)]
+struct LayoutHistory {
+    names: HashMap<vk::Image, String>,
+    events: HashMap<vk::Image, VecDeque<LayoutEvent>>,
+    frame: u64,
+}
+
+impl LayoutHistory {
+    fn push(&mut self, image: vk::Image, batch: usize, old_layout: Option<vk::ImageLayout>, access: Access) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let events = self.events.entry(image).or_default();
+        if events.len() == HISTORY_LENGTH {
+            events.pop_front();
+        }
+        events.push_back(LayoutEvent {
+            frame: self.frame,
+            batch,
+            old_layout,
+            access,
+        });
+    }
+
+    fn forget(&mut self, image: vk::Image) {
+        self.names.remove(&image);
+        self.events.remove(&image);
+    }
+
+    fn report(&self, image: vk::Image, problem: &str) -> String {
+        let mut report = match self.names.get(&image) {
+            Some(name) => format!("image {:?} \"{}\" {}", image, name, problem),
+            None => format!("image {:?} {}", image, problem),
+        };
+        match self.events.get(&image) {
+            Some(events) if !events.is_empty() => {
+                report.push_str(", its transitions:");
+                for event in events.iter() {
+                    report.push_str(&format!("\n  {}", event));
+                }
+            }
+            _ => report.push_str(", it was never transitioned by the tracker"),
+        }
+        report
+    }
+}
+
+/// Tracks the last access of images and buffers and turns declared uses into barriers.
+/// Barriers wait in the batch until `flush`, which records all of them with one `vkCmdPipelineBarrier2`,
+/// so declare every use of the next commands first. Uses of one resource in a batch combine into one access,
+/// they have to agree on the layout. Images are tracked as a whole, every mip and layer.
+/// Needs `required_features`.
+#[derive(Default)]
+pub struct BarrierBatch {
+    images: Tracker<vk::Image>,
+    buffers: Tracker<vk::Buffer>,
+    image_barriers: Vec<vk::ImageMemoryBarrier2>,
+    buffer_barriers: Vec<vk::BufferMemoryBarrier2>,
+    stats: BarrierStats,
+    history: LayoutHistory,
+}
+
+impl BarrierBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking `image` as last used by `access`, for images written outside the batch.
+    pub fn import_image(&mut self, image: vk::Image, access: Access) {
+        self.images.states.insert(image, ResourceState::new(access));
+        self.history.push(image, self.stats.calls, None, access);
+    }
+
+    pub fn import_buffer(&mut self, buffer: vk::Buffer, access: Access) {
+        self.buffers.states.insert(buffer, ResourceState::new(access));
+    }
+
+    /// Stops tracking, for destroyed resources and swapchain images after the swapchain was recreated.
+    pub fn forget_image(&mut self, image: vk::Image) {
+        self.images.states.remove(&image);
+        self.history.forget(image);
+    }
+
+    /// Shows up in the layout panics of debug builds.
+    pub fn name_image(&mut self, image: vk::Image, name: &str) {
+        if cfg!(debug_assertions) {
+            self.history.names.insert(image, name.to_owned());
+        }
+    }
+
+    /// The layout the image is in after the barriers in the batch, None when it isn't tracked.
+    pub fn layout(&self, image: vk::Image) -> Option<vk::ImageLayout> {
+        self.images.states.get(&image).map(|state| state.layout)
+    }
+
+    /// Debug builds panic with the transition history when a tracked image isn't in `expected`,
+    /// for cross checking the layouts written into descriptors, render passes and copy commands.
+    pub fn validate_layout(&self, image: vk::Image, expected: vk::ImageLayout, usage: &str) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        if let Some(layout) = self.layout(image).filter(|layout| *layout != expected) {
+            let problem = format!("is in {:?} but {} expects {:?}", layout, usage, expected);
+            panic!("{}", self.history.report(image, &problem));
+        }
+    }
+
+    pub fn forget_buffer(&mut self, buffer: vk::Buffer) {
+        self.buffers.states.remove(&buffer);
+    }
+
+    pub fn image_state(&self, image: vk::Image) -> Option<TrackedState> {
+        self.images.states.get(&image).map(|state| TrackedState::from(*state))
+    }
+
+    pub fn buffer_state(&self, buffer: vk::Buffer) -> Option<TrackedState> {
+        self.buffers.states.get(&buffer).map(|state| TrackedState::from(*state))
+    }
+
+    /// Declares that the next commands use `image` like `next`, untracked images start at `Access::NONE`.
+    pub fn use_image(&mut self, image: vk::Image, aspect_mask: vk::ImageAspectFlags, next: Access) {
+        // the commands after the flush do both uses, without a barrier between them
+        if let Some(layout) = self.images.batched_layout(image).filter(|layout| *layout != next.layout) {
+            let problem = format!(
+                "is used as {:?} and {:?} by the same commands, flush the barriers between",
+                layout, next.layout
+            );
+            panic!("{}", self.history.report(image, &problem));
+        }
+        self.stats.requested += 1;
+        match self.images.declare(image, next, true) {
+            Step::Redundant => self.stats.redundant += 1,
+            Step::Merge(index, (src_stages, src_access), combined) => {
+                let barrier = &mut self.image_barriers[index];
+                barrier.src_stage_mask = src_stages;
+                barrier.src_access_mask = src_access;
+                barrier.dst_stage_mask = combined.stages;
+                barrier.dst_access_mask = combined.access;
+                barrier.subresource_range.aspect_mask |= aspect_mask;
+                self.stats.merged += 1;
+            }
+            Step::New(old_layout, (src_stages, src_access), combined) => {
+                if old_layout != combined.layout {
+                    self.history.push(image, self.stats.calls, Some(old_layout), combined);
+                }
+                self.images.set_barrier(image, self.image_barriers.len());
+                self.image_barriers.push(vk::ImageMemoryBarrier2 {
+                    src_stage_mask: src_stages,
+                    src_access_mask: src_access,
+                    dst_stage_mask: combined.stages,
+                    dst_access_mask: combined.access,
+                    old_layout,
+                    new_layout: combined.layout,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    image,
+                    subresource_range: vk::ImageSubresourceRange {
+                        aspect_mask,
+                        base_mip_level: 0,
+                        level_count: vk::REMAINING_MIP_LEVELS,
+                        base_array_layer: 0,
+                        layer_count: vk::REMAINING_ARRAY_LAYERS,
+                    },
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    /// Declares that the next commands use all of `buffer` like `next`.
+    pub fn use_buffer(&mut self, buffer: vk::Buffer, next: Access) {
+        self.stats.requested += 1;
+        match self.buffers.declare(buffer, next, false) {
+            Step::Redundant => self.stats.redundant += 1,
+            Step::Merge(index, (src_stages, src_access), combined) => {
+                let barrier = &mut self.buffer_barriers[index];
+                barrier.src_stage_mask = src_stages;
+                barrier.src_access_mask = src_access;
+                barrier.dst_stage_mask = combined.stages;
+                barrier.dst_access_mask = combined.access;
+                self.stats.merged += 1;
+            }
+            Step::New(_, (src_stages, src_access), combined) => {
+                self.buffers.set_barrier(buffer, self.buffer_barriers.len());
+                self.buffer_barriers.push(vk::BufferMemoryBarrier2 {
+                    src_stage_mask: src_stages,
+                    src_access_mask: src_access,
+                    dst_stage_mask: combined.stages,
+                    dst_access_mask: combined.access,
+                    src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                    buffer,
+                    offset: 0,
+                    size: vk::WHOLE_SIZE,
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.image_barriers.is_empty() && self.buffer_barriers.is_empty()
+    }
+
+    /// Records the waiting barriers with a single call and starts the next batch, nothing is recorded
+    /// when none are waiting.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording, outside of a render pass.
+    pub unsafe fn flush(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        self.images.batch.clear();
+        self.buffers.batch.clear();
+        if self.is_empty() {
+            return;
+        }
+        let dependency_info = vk::DependencyInfo::builder()
+            .image_memory_barriers(&self.image_barriers)
+            .buffer_memory_barriers(&self.buffer_barriers);
+        device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+
+        self.stats.recorded += self.image_barriers.len() + self.buffer_barriers.len();
+        self.stats.calls += 1;
+        self.image_barriers.clear();
+        self.buffer_barriers.clear();
+    }
+
+    pub fn stats(&self) -> BarrierStats {
+        self.stats
+    }
+
+    /// Returns the counters of the frame and starts new ones, logged at trace level in debug builds.
+    pub fn end_frame(&mut self) -> BarrierStats {
+        let stats = std::mem::take(&mut self.stats);
+        self.history.frame += 1;
+        if cfg!(debug_assertions) {
+            log::trace!(
+                "barriers: {} recorded in {} calls, {} of {} eliminated",
+                stats.recorded,
+                stats.calls,
+                stats.eliminated(),
+                stats.requested
+            );
+        }
+        stats
+    }
+}
+
+/// The uses of one pass, see `ResourceTracker::begin_pass`.
+#[derive(Clone, Debug, Default)]
+pub struct PassUsage {
+    images: Vec<(vk::Image, Access)>,
+    buffers: Vec<(vk::Buffer, Access)>,
+}
+
+impl PassUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn image(mut self, image: vk::Image, access: Access) -> Self {
+        self.images.push((image, access));
+        self
+    }
+
+    pub fn buffer(mut self, buffer: vk::Buffer, access: Access) -> Self {
+        self.buffers.push((buffer, access));
+        self
+    }
+
+    pub fn sampled(self, texture: &Texture, stages: vk::PipelineStageFlags2) -> Self {
+        let access = Access::new(
+            stages,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        self.image(texture.image, access)
+    }
+
+    pub fn color_attachment(self, texture: &Texture) -> Self {
+        self.image(texture.image, Access::COLOR_ATTACHMENT)
+    }
+
+    pub fn depth_attachment(self, depth: &DepthBuffer) -> Self {
+        self.image(depth.image, Access::DEPTH_ATTACHMENT)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Owner {
+    Family(u32),
+    /// released by the source family, the destination acquires it with its first use
+    Released(QueueTransfer),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct OwnedResource {
+    owner: Owner,
+    /// images only, the transfer keeps the layout
+    layout: vk::ImageLayout,
+    aspect_mask: vk::ImageAspectFlags,
+}
+
+#[derive(Default)]
+struct OwnershipState {
+    images: HashMap<vk::Image, OwnedResource>,
+    buffers: HashMap<vk::Buffer, OwnedResource>,
+}
+
+/// Queue family owning each EXCLUSIVE resource, shared by the `ResourceTracker`s of every queue so a resource
+/// released by one is acquired by the other on its first use. CONCURRENT resources are never added.
+#[derive(Clone, Default)]
+pub struct QueueOwnership(Arc<Mutex<OwnershipState>>);
+
+impl QueueOwnership {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// None when `image` isn't owned through the trackers or was released and not acquired yet.
+    pub fn image_owner(&self, image: vk::Image) -> Option<u32> {
+        let state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.images.get(&image).and_then(|owned| match owned.owner {
+            Owner::Family(family) => Some(family),
+            Owner::Released(_) => None,
+        })
+    }
+
+    pub fn buffer_owner(&self, buffer: vk::Buffer) -> Option<u32> {
+        let state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.buffers.get(&buffer).and_then(|owned| match owned.owner {
+            Owner::Family(family) => Some(family),
+            Owner::Released(_) => None,
+        })
+    }
+
+    /// For destroyed resources.
+    pub fn forget_image(&self, image: vk::Image) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).images.remove(&image);
+    }
+
+    pub fn forget_buffer(&self, buffer: vk::Buffer) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).buffers.remove(&buffer);
+    }
+}
+
+/// Acquire or release halves of queue family transfers, recorded with one `vkCmdPipelineBarrier2`.
+#[derive(Default)]
+struct OwnershipBarriers {
+    images: Vec<vk::ImageMemoryBarrier2>,
+    buffers: Vec<vk::BufferMemoryBarrier2>,
+}
+
+impl OwnershipBarriers {
+    unsafe fn record(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        if self.images.is_empty() && self.buffers.is_empty() {
+            return;
+        }
+        let dependency_info = vk::DependencyInfo::builder()
+            .image_memory_barriers(&self.images)
+            .buffer_memory_barriers(&self.buffers);
+        device.cmd_pipeline_barrier2(command_buffer, &dependency_info);
+    }
+}
+
+/// Layout and access tracking for passes that declare what they use instead of writing barriers.
+/// `begin_pass` records the barriers the declared uses need, all of them in one `vkCmdPipelineBarrier2` and
+/// none for uses the previous pass already made visible. Images are registered with their format, which
+/// picks the aspects of the barriers, unregistered ones are COLOR. Needs `required_features`.
+///
+/// Trackers made with `for_queue` also move EXCLUSIVE resources between queue families: `finish` releases
+/// the owned resources the submission used to their next family, and the tracker of that family acquires
+/// them in the `begin_pass` using them first. Its submission has to wait for the releasing one.
+#[derive(Default)]
+pub struct ResourceTracker {
+    batch: BarrierBatch,
+    aspects: HashMap<vk::Image, vk::ImageAspectFlags>,
+    /// family of the queue recording, with the ownership shared with the trackers of other queues
+    queue: Option<(u32, QueueOwnership)>,
+    /// where `finish` releases owned resources to without an explicit destination
+    release_to: Option<u32>,
+    /// owned resources used since the last `finish`, with their explicit destination
+    used_images: HashMap<vk::Image, Option<u32>>,
+    used_buffers: HashMap<vk::Buffer, Option<u32>>,
+}
+
+impl ResourceTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracker recording on a queue of `family`.
+    pub fn for_queue(family: u32, ownership: QueueOwnership) -> Self {
+        Self {
+            queue: Some((family, ownership)),
+            ..Self::default()
+        }
+    }
+
+    /// `finish` hands every owned resource used since the last one to `family`, an upload queue releasing
+    /// to graphics for example.
+    pub fn release_to(mut self, family: u32) -> Self {
+        self.release_to = Some(family);
+        self
+    }
+
+    fn ownership(&self) -> (u32, QueueOwnership) {
+        self.queue
+            .clone()
+            .expect("Queue ownership needs a tracker made with ResourceTracker::for_queue")
+    }
+
+    /// Marks EXCLUSIVE `image`, usually just created, as owned by the family of this tracker.
+    pub fn own_image(&mut self, image: vk::Image) {
+        let (family, ownership) = self.ownership();
+        let owned = OwnedResource {
+            owner: Owner::Family(family),
+            layout: self.batch.layout(image).unwrap_or(vk::ImageLayout::UNDEFINED),
+            aspect_mask: self.aspects.get(&image).copied().unwrap_or(vk::ImageAspectFlags::COLOR),
+        };
+        ownership
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .images
+            .insert(image, owned);
+    }
+
+    pub fn own_buffer(&mut self, buffer: vk::Buffer) {
+        let (family, ownership) = self.ownership();
+        let owned = OwnedResource {
+            owner: Owner::Family(family),
+            layout: vk::ImageLayout::UNDEFINED,
+            aspect_mask: vk::ImageAspectFlags::empty(),
+        };
+        ownership
+            .0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .buffers
+            .insert(buffer, owned);
+    }
+
+    /// Releases `image` to `family` with the next `finish` instead of the `release_to` family.
+    pub fn release_image(&mut self, image: vk::Image, family: u32) {
+        self.used_images.insert(image, Some(family));
+    }
+
+    pub fn release_buffer(&mut self, buffer: vk::Buffer, family: u32) {
+        self.used_buffers.insert(buffer, Some(family));
+    }
+
+    /// Starts tracking `image` in the state `current` left it, `Access::NONE` for new images.
+    pub fn register_image(&mut self, image: vk::Image, format: vk::Format, current: Access, name: &str) {
+        self.aspects.insert(image, format::aspect_flags(format));
+        self.batch.import_image(image, current);
+        self.batch.name_image(image, name);
+    }
+
+    pub fn register_texture(&mut self, texture: &Texture, current: Access, name: &str) {
+        self.register_image(texture.image, texture.format, current, name);
+    }
+
+    pub fn register_depth(&mut self, depth: &DepthBuffer, current: Access, name: &str) {
+        self.register_image(depth.image, depth.format, current, name);
+    }
+
+    pub fn register_buffer(&mut self, buffer: vk::Buffer, current: Access) {
+        self.batch.import_buffer(buffer, current);
+    }
+
+    /// For destroyed images, recreated ones have to be registered again.
+    pub fn forget_image(&mut self, image: vk::Image) {
+        self.aspects.remove(&image);
+        self.batch.forget_image(image);
+    }
+
+    pub fn forget_buffer(&mut self, buffer: vk::Buffer) {
+        self.batch.forget_buffer(buffer);
+    }
+
+    pub fn image_state(&self, image: vk::Image) -> Option<TrackedState> {
+        self.batch.image_state(image)
+    }
+
+    pub fn buffer_state(&self, buffer: vk::Buffer) -> Option<TrackedState> {
+        self.batch.buffer_state(buffer)
+    }
+
+    /// Records the barriers `usage` needs, before the render pass or dispatch of the pass.
+    /// Render passes built with `pipeline::create_offscreen_render_pass` transition their attachments
+    /// themselves, declare the layout they leave the attachments in with `assume` afterwards.
+    ///
+    /// # Safety
+    /// As for `flush`, the tracked state has to match what the command buffer was recorded after.
+    pub unsafe fn begin_pass(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer, usage: &PassUsage) {
+        let acquire = self.declare_pass(usage);
+        acquire.record(device, command_buffer);
+        self.batch.flush(device, command_buffer);
+    }
+
+    /// Declares the uses of `usage` in the batch, returns the acquire halves that go before its barriers.
+    fn declare_pass(&mut self, usage: &PassUsage) -> OwnershipBarriers {
+        let acquire = match self.queue {
+            Some(_) => self.acquire(usage),
+            None => OwnershipBarriers::default(),
+        };
+        for &(image, access) in usage.images.iter() {
+            let aspect_mask = self.aspects.get(&image).copied().unwrap_or(vk::ImageAspectFlags::COLOR);
+            self.batch.use_image(image, aspect_mask, access);
+        }
+        for &(buffer, access) in usage.buffers.iter() {
+            self.batch.use_buffer(buffer, access);
+        }
+        acquire
+    }
+
+    /// Acquire halves for the resources of `usage` released to this family, recorded before the other barriers
+    /// of the pass as the acquire has to keep the layout of the release.
+    fn acquire(&mut self, usage: &PassUsage) -> OwnershipBarriers {
+        let (family, ownership) = self.ownership();
+        let mut state = ownership.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut image_barriers = vec![];
+        let mut buffer_barriers = vec![];
+        let mut acquired = vec![];
+        for &(image, access) in usage.images.iter() {
+            let Some(owned) = state.images.get_mut(&image) else {
+                continue;
+            };
+            if let Some(transfer) = acquirable(owned.owner, family, "image", &image) {
+                image_barriers.push(vk::ImageMemoryBarrier2 {
+                    dst_stage_mask: access.stages,
+                    dst_access_mask: access.access,
+                    old_layout: owned.layout,
+                    new_layout: owned.layout,
+                    src_queue_family_index: transfer.src_family,
+                    dst_queue_family_index: transfer.dst_family,
+                    image,
+                    subresource_range: whole_image(owned.aspect_mask),
+                    ..Default::default()
+                });
+                owned.owner = Owner::Family(family);
+                self.aspects.entry(image).or_insert(owned.aspect_mask);
+                acquired.push((image, Access::new(access.stages, vk::AccessFlags2::NONE, owned.layout)));
+            }
+            self.used_images.entry(image).or_insert(None);
+        }
+        for &(buffer, access) in usage.buffers.iter() {
+            let Some(owned) = state.buffers.get_mut(&buffer) else {
+                continue;
+            };
+            if let Some(transfer) = acquirable(owned.owner, family, "buffer", &buffer) {
+                buffer_barriers.push(vk::BufferMemoryBarrier2 {
+                    dst_stage_mask: access.stages,
+                    dst_access_mask: access.access,
+                    src_queue_family_index: transfer.src_family,
+                    dst_queue_family_index: transfer.dst_family,
+                    buffer,
+                    offset: 0,
+                    size: vk::WHOLE_SIZE,
+                    ..Default::default()
+                });
+                owned.owner = Owner::Family(family);
+                // the acquire made the content visible to the pass, no barrier is left to record
+                self.batch.import_buffer(buffer, access);
+            }
+            self.used_buffers.entry(buffer).or_insert(None);
+        }
+        drop(state);
+
+        // no access is visible yet, a layout change of the pass waits for the acquire in the same stages
+        for (image, access) in acquired {
+            self.batch.import_image(image, access);
+        }
+        OwnershipBarriers {
+            images: image_barriers,
+            buffers: buffer_barriers,
+        }
+    }
+
+    /// Records the release halves of the owned resources used since the last `finish`, to their
+    /// `release_image`/`release_buffer` family or the `release_to` one, at the end of the command buffer.
+    /// Resources without either stay owned by this family.
+    ///
+    /// # Safety
+    /// As for `flush`, the acquire halves have to be recorded on the receiving families.
+    pub unsafe fn finish(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        self.release().record(device, command_buffer);
+    }
+
+    fn release(&mut self) -> OwnershipBarriers {
+        let Some((family, ownership)) = self.queue.clone() else {
+            return OwnershipBarriers::default();
+        };
+        let mut state = ownership.0.lock().unwrap_or_else(|e| e.into_inner());
+        let mut image_barriers = vec![];
+        let mut buffer_barriers = vec![];
+        for (image, destination) in std::mem::take(&mut self.used_images) {
+            let Some(dst_family) = destination.or(self.release_to).filter(|dst| *dst != family) else {
+                continue;
+            };
+            let Some(owned) = state
+                .images
+                .get_mut(&image)
+                .filter(|owned| owned.owner == Owner::Family(family))
+            else {
+                continue;
+            };
+            let tracked = self.batch.image_state(image);
+            let layout = tracked.map_or(owned.layout, |tracked| tracked.layout);
+            let (src_stages, src_access) = release_scope(tracked);
+            image_barriers.push(vk::ImageMemoryBarrier2 {
+                src_stage_mask: src_stages,
+                src_access_mask: src_access,
+                old_layout: layout,
+                new_layout: layout,
+                src_queue_family_index: family,
+                dst_queue_family_index: dst_family,
+                image,
+                subresource_range: whole_image(owned.aspect_mask),
+                ..Default::default()
+            });
+            owned.owner = Owner::Released(QueueTransfer::new(family, dst_family));
+            owned.layout = layout;
+            self.batch.forget_image(image);
+        }
+        for (buffer, destination) in std::mem::take(&mut self.used_buffers) {
+            let Some(dst_family) = destination.or(self.release_to).filter(|dst| *dst != family) else {
+                continue;
+            };
+            let Some(owned) = state
+                .buffers
+                .get_mut(&buffer)
+                .filter(|owned| owned.owner == Owner::Family(family))
+            else {
+                continue;
+            };
+            let (src_stages, src_access) = release_scope(self.batch.buffer_state(buffer));
+            buffer_barriers.push(vk::BufferMemoryBarrier2 {
+                src_stage_mask: src_stages,
+                src_access_mask: src_access,
+                src_queue_family_index: family,
+                dst_queue_family_index: dst_family,
+                buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
+            });
+            owned.owner = Owner::Released(QueueTransfer::new(family, dst_family));
+            self.batch.forget_buffer(buffer);
+        }
+        OwnershipBarriers {
+            images: image_barriers,
+            buffers: buffer_barriers,
+        }
+    }
+
+    /// Tells the tracker commands outside of it, like a render pass with layout transitions, left `image` in
+    /// the state of `access`.
+    pub fn assume(&mut self, image: vk::Image, access: Access) {
+        self.batch.import_image(image, access);
+    }
+
+    /// The batch underneath, for declaring uses of a pass one by one.
+    pub fn batch(&mut self) -> &mut BarrierBatch {
+        &mut self.batch
+    }
+
+    pub fn end_frame(&mut self) -> BarrierStats {
+        self.batch.end_frame()
+    }
+}
+
+/// The transfer to acquire a resource owned by `owner` on `family` with, None when `family` owns it already.
+fn acquirable<H: fmt::Debug>(owner: Owner, family: u32, kind: &str, handle: &H) -> Option<QueueTransfer> {
+    match owner {
+        Owner::Family(owner) if owner == family => None,
+        Owner::Released(transfer) if transfer.dst_family == family => Some(transfer),
+        Owner::Family(owner) => panic!(
+            "{} {:?} is used on queue family {} while family {} owns it, release it with ResourceTracker::finish first",
+            kind, handle, family, owner
+        ),
+        Owner::Released(transfer) => panic!(
+            "{} {:?} is used on queue family {} but was released to family {}",
+            kind, handle, family, transfer.dst_family
+        ),
+    }
+}
+
+/// The last use the release has to wait for, everything when the tracker doesn't know it.
+fn release_scope(tracked: Option<TrackedState>) -> (vk::PipelineStageFlags2, vk::AccessFlags2) {
+    match tracked {
+        Some(tracked) => (tracked.write_stages | tracked.read_stages, tracked.write_access),
+        None => (vk::PipelineStageFlags2::ALL_COMMANDS, vk::AccessFlags2::MEMORY_WRITE),
+    }
+}
+
+fn whole_image(aspect_mask: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    /// What `flush` does to the batch, without recording anything.
+    fn end_batch(batch: &mut BarrierBatch) {
+        batch.images.batch.clear();
+        batch.buffers.batch.clear();
+        batch.image_barriers.clear();
+        batch.buffer_barriers.clear();
+    }
+
+    fn stats(requested: usize, redundant: usize, merged: usize) -> BarrierStats {
+        BarrierStats {
+            requested,
+            redundant,
+            merged,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn read_after_write_waits_for_the_write_once() {
+        let image = vk::Image::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_image(image, Access::COLOR_ATTACHMENT);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, Access::FRAGMENT_SAMPLED);
+        assert_eq!(batch.image_barriers.len(), 1);
+        let barrier = batch.image_barriers[0];
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(barrier.old_layout, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        end_batch(&mut batch);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, Access::FRAGMENT_SAMPLED);
+        assert!(batch.is_empty());
+        assert_eq!(batch.stats(), stats(2, 1, 0));
+    }
+
+    #[test]
+    fn repeated_read_after_write_waits_again_for_unseen_stages() {
+        let buffer = vk::Buffer::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_buffer(buffer, Access::COMPUTE_WRITE);
+        batch.use_buffer(buffer, Access::COMPUTE_READ);
+        assert_eq!(batch.buffer_barriers.len(), 1);
+        end_batch(&mut batch);
+        batch.use_buffer(buffer, Access::COMPUTE_READ);
+        assert!(batch.is_empty());
+        end_batch(&mut batch);
+
+        // the first barrier made the write visible to compute reads only
+        batch.use_buffer(buffer, Access::TRANSFER_READ);
+        assert_eq!(batch.buffer_barriers.len(), 1);
+        let barrier = batch.buffer_barriers[0];
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::COMPUTE_SHADER);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::SHADER_STORAGE_WRITE);
+        assert_eq!(barrier.dst_stage_mask, vk::PipelineStageFlags2::TRANSFER);
+        assert_eq!(batch.stats(), stats(3, 1, 0));
+    }
+
+    #[test]
+    fn write_after_read_only_waits_for_the_read_stages() {
+        let buffer = vk::Buffer::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_buffer(buffer, Access::COMPUTE_READ);
+        batch.use_buffer(buffer, Access::COMPUTE_WRITE);
+        assert_eq!(batch.buffer_barriers.len(), 1);
+        assert_eq!(
+            batch.buffer_barriers[0].src_stage_mask,
+            vk::PipelineStageFlags2::COMPUTE_SHADER
+        );
+        assert_eq!(batch.buffer_barriers[0].src_access_mask, vk::AccessFlags2::NONE);
+        assert_eq!(batch.stats(), stats(1, 0, 0));
+    }
+
+    #[test]
+    fn read_after_read_in_the_same_layout_is_redundant() {
+        let image = vk::Image::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_image(image, Access::FRAGMENT_SAMPLED);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, Access::FRAGMENT_SAMPLED);
+        assert!(batch.is_empty());
+        assert_eq!(batch.stats(), stats(1, 1, 0));
+    }
+
+    #[test]
+    fn read_after_read_in_another_layout_transitions() {
+        let image = vk::Image::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_image(image, Access::FRAGMENT_SAMPLED);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, Access::TRANSFER_READ);
+        assert_eq!(batch.image_barriers.len(), 1);
+        assert_eq!(
+            batch.image_barriers[0].src_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(batch.image_barriers[0].new_layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(batch.layout(image), Some(vk::ImageLayout::TRANSFER_SRC_OPTIMAL));
+        assert_eq!(batch.stats(), stats(1, 0, 0));
+    }
+
+    #[test]
+    fn layout_change_without_access_change_transitions() {
+        let storage_sampled = Access::new(
+            vk::PipelineStageFlags2::FRAGMENT_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            vk::ImageLayout::GENERAL,
+        );
+        let image = vk::Image::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_image(image, Access::FRAGMENT_SAMPLED);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, storage_sampled);
+        assert_eq!(batch.image_barriers.len(), 1);
+        let barrier = batch.image_barriers[0];
+        assert_eq!(barrier.old_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::GENERAL);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::NONE);
+        assert_eq!(barrier.dst_access_mask, vk::AccessFlags2::SHADER_SAMPLED_READ);
+
+        // the transition is a write, the present transition after it waits for its stage
+        end_batch(&mut batch);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, Access::PRESENT);
+        assert_eq!(batch.image_barriers.len(), 1);
+        assert_eq!(
+            batch.image_barriers[0].src_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(batch.image_barriers[0].new_layout, vk::ImageLayout::PRESENT_SRC_KHR);
+    }
+
+    #[test]
+    fn two_uses_in_one_batch_merge_into_one_barrier() {
+        let vertex_sampled = Access::new(
+            vk::PipelineStageFlags2::VERTEX_SHADER,
+            vk::AccessFlags2::SHADER_SAMPLED_READ,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        let image = vk::Image::from_raw(1);
+        let mut batch = BarrierBatch::new();
+        batch.import_image(image, Access::TRANSFER_WRITE);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, Access::FRAGMENT_SAMPLED);
+        batch.use_image(image, vk::ImageAspectFlags::COLOR, vertex_sampled);
+        assert_eq!(batch.image_barriers.len(), 1);
+        let barrier = batch.image_barriers[0];
+        assert_eq!(barrier.src_stage_mask, vk::PipelineStageFlags2::TRANSFER);
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::TRANSFER_WRITE);
+        assert_eq!(
+            barrier.dst_stage_mask,
+            vk::PipelineStageFlags2::VERTEX_SHADER | vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(batch.stats(), stats(2, 0, 1));
+        assert_eq!(batch.stats().eliminated(), 1);
+    }
+
+    #[test]
+    fn queue_family_handoff_keeps_the_layout() {
+        const GRAPHICS: u32 = 0;
+        const TRANSFER: u32 = 1;
+        let image = vk::Image::from_raw(1);
+        let buffer = vk::Buffer::from_raw(2);
+        let ownership = QueueOwnership::new();
+        let mut upload = ResourceTracker::for_queue(TRANSFER, ownership.clone()).release_to(GRAPHICS);
+        let mut graphics = ResourceTracker::for_queue(GRAPHICS, ownership.clone());
+
+        upload.register_image(image, vk::Format::R8G8B8A8_SRGB, Access::NONE, "upload");
+        upload.own_image(image);
+        upload.own_buffer(buffer);
+        let copy = PassUsage::new()
+            .image(image, Access::TRANSFER_WRITE)
+            .buffer(buffer, Access::TRANSFER_WRITE);
+        assert!(upload.declare_pass(&copy).images.is_empty());
+        end_batch(upload.batch());
+
+        let release = upload.release();
+        assert_eq!(release.images.len(), 1);
+        assert_eq!(release.buffers.len(), 1);
+        let barrier = release.images[0];
+        assert_eq!(
+            (barrier.src_queue_family_index, barrier.dst_queue_family_index),
+            (TRANSFER, GRAPHICS)
+        );
+        assert_eq!(barrier.src_access_mask, vk::AccessFlags2::TRANSFER_WRITE);
+        assert_eq!(barrier.old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(barrier.new_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(ownership.image_owner(image), None);
+        assert!(upload.image_state(image).is_none());
+
+        let vertex_read = Access::new(
+            vk::PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT,
+            vk::AccessFlags2::VERTEX_ATTRIBUTE_READ,
+            vk::ImageLayout::UNDEFINED,
+        );
+        let draw = PassUsage::new()
+            .image(image, Access::FRAGMENT_SAMPLED)
+            .buffer(buffer, vertex_read);
+        let acquire = graphics.declare_pass(&draw);
+        assert_eq!(acquire.images.len(), 1);
+        assert_eq!(acquire.buffers.len(), 1);
+        let barrier = acquire.images[0];
+        assert_eq!(
+            (barrier.src_queue_family_index, barrier.dst_queue_family_index),
+            (TRANSFER, GRAPHICS)
+        );
+        assert_eq!(barrier.new_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(ownership.image_owner(image), Some(GRAPHICS));
+        assert_eq!(ownership.buffer_owner(buffer), Some(GRAPHICS));
+
+        // the acquire made the buffer visible, only the image still changes its layout after it
+        let batch = graphics.batch();
+        assert!(batch.buffer_barriers.is_empty());
+        assert_eq!(batch.image_barriers.len(), 1);
+        assert_eq!(
+            batch.image_barriers[0].src_stage_mask,
+            vk::PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(batch.image_barriers[0].old_layout, vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(batch.image_barriers[0].new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }
+}
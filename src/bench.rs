@@ -0,0 +1,459 @@
+use std::{
+    fmt, fs,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use crate::error::Result;
+use ash::vk;
+
+use crate::{buffer, headless::HeadlessContext, memory::GpuAllocator};
+
+/// Directory the results of the last run are kept in, the next run reports the change against them.
+pub const BASELINE_DIR: &str = "target/vulky-bench";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchSettings {
+    /// runs before measuring, to get caches, clocks and driver pools warm
+    pub warm_up: Duration,
+    pub samples: usize,
+    /// a sample repeats the routine until it took this long
+    pub sample_time: Duration,
+}
+
+impl Default for BenchSettings {
+    fn default() -> Self {
+        Self {
+            warm_up: Duration::from_millis(300),
+            samples: 20,
+            sample_time: Duration::from_millis(50),
+        }
+    }
+}
+
+/// What one run of a routine processed, to report a rate next to the time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Throughput {
+    Bytes(u64),
+    Elements(u64),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchResult {
+    pub name: String,
+    /// seconds per run of the routine, one entry per sample
+    pub samples: Vec<f64>,
+    pub throughput: Throughput,
+    /// median of the last run with this name
+    pub baseline: Option<f64>,
+}
+
+impl BenchResult {
+    pub fn median(&self) -> f64 {
+        let mut sorted = self.samples.clone();
+        sorted.sort_by(f64::total_cmp);
+        match sorted.len() {
+            0 => 0.0,
+            len if len % 2 == 0 => (sorted[len / 2 - 1] + sorted[len / 2]) / 2.0,
+            len => sorted[len / 2],
+        }
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.samples.iter().sum::<f64>() / self.samples.len().max(1) as f64
+    }
+
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let variance =
+            self.samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / self.samples.len().max(1) as f64;
+        variance.sqrt()
+    }
+
+    /// Relative change of the median against the baseline, positive is slower.
+    pub fn change(&self) -> Option<f64> {
+        self.baseline.map(|baseline| self.median() / baseline - 1.0)
+    }
+
+    fn rate(&self) -> String {
+        let per_second = |amount: u64| amount as f64 / self.median().max(f64::MIN_POSITIVE);
+        match self.throughput {
+            Throughput::Bytes(bytes) => format!("{:.1} MiB/s", per_second(bytes) / (1024.0 * 1024.0)),
+            Throughput::Elements(elements) => format!("{:.0} /s", per_second(elements)),
+        }
+    }
+}
+
+fn format_time(seconds: f64) -> String {
+    if seconds >= 1.0 {
+        format!("{:.3} s", seconds)
+    } else if seconds >= 1e-3 {
+        format!("{:.3} ms", seconds * 1e3)
+    } else {
+        format!("{:.3} us", seconds * 1e6)
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:<32} {:>12} +- {:>10}  {:>16}",
+            self.name,
+            format_time(self.median()),
+            format_time(self.std_dev()),
+            self.rate()
+        )?;
+        if let Some(change) = self.change() {
+            write!(f, "  {:+.1}%", change * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs routines like criterion does, warm up, then timed samples, then a comparison with the last run.
+pub struct Bencher {
+    pub settings: BenchSettings,
+    /// only benchmarks whose name contains it run
+    pub filter: Option<String>,
+    pub results: Vec<BenchResult>,
+}
+
+impl Bencher {
+    pub fn new(settings: BenchSettings) -> Self {
+        Self {
+            settings,
+            filter: None,
+            results: vec![],
+        }
+    }
+
+    pub fn filter(mut self, filter: Option<String>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Times `routine`, which processes `throughput` per run. Prints and returns the result.
+    pub fn bench<F: FnMut() -> Result<()>>(
+        &mut self,
+        name: &str,
+        throughput: Throughput,
+        mut routine: F,
+    ) -> Result<Option<&BenchResult>> {
+        if self.filter.as_ref().is_some_and(|filter| !name.contains(filter.as_str())) {
+            return Ok(None);
+        }
+
+        let warm_up = Instant::now();
+        let mut runs_per_sample = 0u32;
+        while warm_up.elapsed() < self.settings.warm_up || runs_per_sample == 0 {
+            routine()?;
+            runs_per_sample += 1;
+        }
+        // as many runs per sample as fit in the sample time
+        let per_run = warm_up.elapsed().as_secs_f64() / runs_per_sample as f64;
+        let runs_per_sample = ((self.settings.sample_time.as_secs_f64() / per_run) as u32).max(1);
+
+        let mut samples = Vec::with_capacity(self.settings.samples);
+        for _ in 0..self.settings.samples.max(1) {
+            let start = Instant::now();
+            for _ in 0..runs_per_sample {
+                routine()?;
+            }
+            samples.push(start.elapsed().as_secs_f64() / runs_per_sample as f64);
+        }
+
+        let result = BenchResult {
+            name: name.to_owned(),
+            samples,
+            throughput,
+            baseline: read_baseline(name),
+        };
+        write_baseline(name, result.median());
+        println!("{}", result);
+        self.results.push(result);
+        Ok(self.results.last())
+    }
+
+    /// Benchmarks slower than the baseline by more than `threshold`, 0.1 is 10%.
+    pub fn regressions(&self, threshold: f64) -> Vec<&BenchResult> {
+        self.results
+            .iter()
+            .filter(|result| result.change().is_some_and(|change| change > threshold))
+            .collect()
+    }
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    let file_name: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    PathBuf::from(BASELINE_DIR).join(file_name)
+}
+
+fn read_baseline(name: &str) -> Option<f64> {
+    fs::read_to_string(baseline_path(name)).ok()?.trim().parse().ok()
+}
+
+fn write_baseline(name: &str, median: f64) {
+    let path = baseline_path(name);
+    let written = fs::create_dir_all(BASELINE_DIR).and_then(|_| fs::write(&path, median.to_string()));
+    if let Err(e) = written {
+        log::warn!("Failed to write the benchmark baseline {}: {}", path.display(), e);
+    }
+}
+
+/// Allocates and frees `count` 64 KiB ranges through `GpuAllocator`, after the first run from existing blocks.
+///
+/// # Safety
+/// `context` has to be idle, the benchmark waits for its own work.
+pub unsafe fn allocator_throughput(bencher: &mut Bencher, context: &HeadlessContext, count: usize) -> Result<()> {
+    let device = &context.device;
+    let mut allocator = GpuAllocator::new(&context.instance, context.physical_device);
+    let requirements = vk::MemoryRequirements {
+        size: 64 * 1024,
+        alignment: 256,
+        memory_type_bits: device_local_types(context),
+    };
+    let mut allocations = Vec::with_capacity(count);
+    let result = bencher.bench(
+        &format!("allocator/{}x64KiB", count),
+        Throughput::Elements(count as u64),
+        || {
+            for _ in 0..count {
+                let allocation = allocator.allocate(
+                    device,
+                    &context.instance,
+                    context.physical_device,
+                    requirements,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )?;
+                allocations.push(allocation.id);
+            }
+            for id in allocations.drain(..) {
+                allocator.free(id);
+            }
+            Ok(())
+        },
+    );
+    allocator.destroy(device);
+    result.map(|_| ())
+}
+
+fn device_local_types(context: &HeadlessContext) -> u32 {
+    let memory = unsafe {
+        context
+            .instance
+            .get_physical_device_memory_properties(context.physical_device)
+    };
+    (0..memory.memory_type_count)
+        .filter(|index| {
+            memory.memory_types[*index as usize]
+                .property_flags
+                .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
+        })
+        .fold(0, |bits, index| bits | 1 << index)
+}
+
+/// Uploads `size` bytes into a new device local buffer through a staging buffer and waits for the copy.
+///
+/// # Safety
+/// As for `allocator_throughput`.
+pub unsafe fn upload_bandwidth(bencher: &mut Bencher, context: &HeadlessContext, size: usize) -> Result<()> {
+    let data = vec![0x5au8; size];
+    let device = &context.device;
+    bencher
+        .bench(&format!("upload/{}KiB", size / 1024), Throughput::Bytes(size as u64), || {
+            let (buffer, memory) = buffer::create_device_local_buffer(
+                device,
+                &context.instance,
+                context.physical_device,
+                context.command_pool,
+                context.queue,
+                &data,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+            )?;
+            device.destroy_buffer(buffer, None);
+            device.free_memory(memory, None);
+            Ok(())
+        })
+        .map(|_| ())
+}
+
+/// Writes a uniform buffer into `count` descriptor sets with one `vkUpdateDescriptorSets`.
+///
+/// # Safety
+/// As for `allocator_throughput`.
+pub unsafe fn descriptor_updates(bencher: &mut Bencher, context: &HeadlessContext, count: u32) -> Result<()> {
+    let device = &context.device;
+    let binding = vk::DescriptorSetLayoutBinding {
+        binding: 0,
+        descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: 1,
+        stage_flags: vk::ShaderStageFlags::ALL,
+        ..Default::default()
+    };
+    let layout_info = vk::DescriptorSetLayoutCreateInfo {
+        binding_count: 1,
+        p_bindings: &binding,
+        ..Default::default()
+    };
+    let set_layout = device.create_descriptor_set_layout(&layout_info, None)?;
+    let pool_size = vk::DescriptorPoolSize {
+        ty: vk::DescriptorType::UNIFORM_BUFFER,
+        descriptor_count: count,
+    };
+    let pool_info = vk::DescriptorPoolCreateInfo {
+        max_sets: count,
+        pool_size_count: 1,
+        p_pool_sizes: &pool_size,
+        ..Default::default()
+    };
+    let pool = device.create_descriptor_pool(&pool_info, None)?;
+    let (uniform_buffer, uniform_memory) = buffer::create_buffer(
+        device,
+        &context.instance,
+        context.physical_device,
+        256,
+        vk::BufferUsageFlags::UNIFORM_BUFFER,
+        vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+
+    let result = allocate_sets(device, pool, set_layout, count).and_then(|sets| {
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: uniform_buffer,
+            offset: 0,
+            range: 256,
+        };
+        let writes: Vec<vk::WriteDescriptorSet> = sets
+            .iter()
+            .map(|set| vk::WriteDescriptorSet {
+                dst_set: *set,
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                p_buffer_info: &buffer_info,
+                ..Default::default()
+            })
+            .collect();
+        bencher
+            .bench(
+                &format!("descriptors/{}writes", count),
+                Throughput::Elements(count as u64),
+                || {
+                    device.update_descriptor_sets(&writes, &[]);
+                    Ok(())
+                },
+            )
+            .map(|_| ())
+    });
+
+    device.destroy_descriptor_pool(pool, None);
+    device.destroy_descriptor_set_layout(set_layout, None);
+    device.destroy_buffer(uniform_buffer, None);
+    device.free_memory(uniform_memory, None);
+    result
+}
+
+unsafe fn allocate_sets(
+    device: &ash::Device,
+    pool: vk::DescriptorPool,
+    set_layout: vk::DescriptorSetLayout,
+    count: u32,
+) -> Result<Vec<vk::DescriptorSet>> {
+    let set_layouts = vec![set_layout; count as usize];
+    let alloc_info = vk::DescriptorSetAllocateInfo {
+        descriptor_pool: pool,
+        descriptor_set_count: count,
+        p_set_layouts: set_layouts.as_ptr(),
+        ..Default::default()
+    };
+    Ok(device.allocate_descriptor_sets(&alloc_info)?)
+}
+
+/// Records `count` small fills and barriers into a command buffer of a reset pool, CPU cost only.
+///
+/// # Safety
+/// As for `allocator_throughput`.
+pub unsafe fn command_recording(bencher: &mut Bencher, context: &HeadlessContext, count: u32) -> Result<()> {
+    let device = &context.device;
+    let pool_info = vk::CommandPoolCreateInfo {
+        flags: vk::CommandPoolCreateFlags::TRANSIENT,
+        queue_family_index: context.queue_family,
+        ..Default::default()
+    };
+    let pool = device.create_command_pool(&pool_info, None)?;
+    let (target, target_memory) = buffer::create_buffer(
+        device,
+        &context.instance,
+        context.physical_device,
+        4096,
+        vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let alloc_info = vk::CommandBufferAllocateInfo {
+        command_pool: pool,
+        level: vk::CommandBufferLevel::PRIMARY,
+        command_buffer_count: 1,
+        ..Default::default()
+    };
+
+    let result = device
+        .allocate_command_buffers(&alloc_info)
+        .map_err(crate::error::Error::from)
+        .and_then(|command_buffers| {
+            let command_buffer = command_buffers[0];
+            let barrier = vk::MemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                ..Default::default()
+            };
+            bencher
+                .bench(
+                    &format!("recording/{}commands", count * 2),
+                    Throughput::Elements(count as u64 * 2),
+                    || {
+                        device.reset_command_pool(pool, vk::CommandPoolResetFlags::empty())?;
+                        let begin_info = vk::CommandBufferBeginInfo {
+                            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+                            ..Default::default()
+                        };
+                        device.begin_command_buffer(command_buffer, &begin_info)?;
+                        for index in 0..count {
+                            device.cmd_fill_buffer(command_buffer, target, (index as u64 % 1024) * 4, 4, index);
+                            device.cmd_pipeline_barrier(
+                                command_buffer,
+                                vk::PipelineStageFlags::TRANSFER,
+                                vk::PipelineStageFlags::TRANSFER,
+                                vk::DependencyFlags::empty(),
+                                &[barrier],
+                                &[],
+                                &[],
+                            );
+                        }
+                        device.end_command_buffer(command_buffer)?;
+                        Ok(())
+                    },
+                )
+                .map(|_| ())
+        });
+
+    device.destroy_command_pool(pool, None);
+    device.destroy_buffer(target, None);
+    device.free_memory(target_memory, None);
+    result
+}
+
+/// Every benchmark with sizes that take a few seconds in total on a desktop GPU.
+///
+/// # Safety
+/// As for `allocator_throughput`.
+pub unsafe fn run_all(bencher: &mut Bencher, context: &HeadlessContext) -> Result<()> {
+    allocator_throughput(bencher, context, 256)?;
+    upload_bandwidth(bencher, context, 64 * 1024)?;
+    upload_bandwidth(bencher, context, 16 * 1024 * 1024)?;
+    descriptor_updates(bencher, context, 256)?;
+    command_recording(bencher, context, 1000)?;
+    Ok(())
+}
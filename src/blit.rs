@@ -0,0 +1,582 @@
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    ptr,
+};
+
+use crate::error::Result;
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::{
+    pipeline::{self, PipelineBuilder},
+    texture::{self, SamplerDesc},
+};
+
+pub const MAX_FULLSCREEN_SOURCES: u32 = 64;
+
+/// Whether `cmd_blit_image` can be used between the two formats with `filter`.
+///
+/// # Safety
+/// `physical_device` has to be enumerated from `instance`.
+pub unsafe fn supports_blit(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    src_format: vk::Format,
+    dst_format: vk::Format,
+    filter: vk::Filter,
+) -> bool {
+    let src = instance
+        .get_physical_device_format_properties(physical_device, src_format)
+        .optimal_tiling_features;
+    let dst = instance
+        .get_physical_device_format_properties(physical_device, dst_format)
+        .optimal_tiling_features;
+
+    let mut src_required = vk::FormatFeatureFlags::BLIT_SRC;
+    if filter == vk::Filter::LINEAR {
+        src_required |= vk::FormatFeatureFlags::SAMPLED_IMAGE_FILTER_LINEAR;
+    }
+    src.contains(src_required) && dst.contains(vk::FormatFeatureFlags::BLIT_DST)
+}
+
+/// Scaled copy of the first mip of a color image, has to be recorded outside of a render pass.
+/// `src` has to be in TRANSFER_SRC_OPTIMAL and `dst` in TRANSFER_DST_OPTIMAL layout.
+///
+/// # Safety
+/// `command_buffer` has to be recording on a queue with graphics support and both images need the
+/// TRANSFER usages.
+pub unsafe fn cmd_blit_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    (src, src_extent): (vk::Image, vk::Extent2D),
+    (dst, dst_extent): (vk::Image, vk::Extent2D),
+    filter: vk::Filter,
+) {
+    let subresource = vk::ImageSubresourceLayers {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        mip_level: 0,
+        base_array_layer: 0,
+        layer_count: 1,
+    };
+    let corner = |extent: vk::Extent2D| vk::Offset3D {
+        x: extent.width as i32,
+        y: extent.height as i32,
+        z: 1,
+    };
+    let region = vk::ImageBlit {
+        src_subresource: subresource,
+        src_offsets: [vk::Offset3D::default(), corner(src_extent)],
+        dst_subresource: subresource,
+        dst_offsets: [vk::Offset3D::default(), corner(dst_extent)],
+    };
+
+    device.cmd_blit_image(
+        command_buffer,
+        src,
+        vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+        dst,
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        &[region],
+        filter,
+    );
+}
+
+/// Draws the triangle of shaders/fullscreen.vert, the bound pipeline must not have vertex inputs.
+///
+/// # Safety
+/// `command_buffer` has to be inside a render pass with a pipeline of `FullscreenPass` or one like it bound.
+pub unsafe fn cmd_draw_fullscreen_triangle(device: &ash::Device, command_buffer: vk::CommandBuffer) {
+    device.cmd_draw(command_buffer, 3, 1, 0, 0);
+}
+
+/// Fullscreen triangle pipeline sampling its source images at set 0, binding 0 onwards.
+/// Works for any source and target format and can run a custom fragment shader, use it when
+/// `cmd_blit_image` isn't supported or the pass has to happen inside a render pass.
+pub struct FullscreenPass {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    source_count: u32,
+    push_constant_size: u32,
+}
+
+impl FullscreenPass {
+    /// `fragment_shader` defaults to shaders/spv/blit_frag.spv, a plain copy.
+    /// Custom shaders get the fullscreen uv at location 0 and `push_constant_size` bytes of fragment push constants.
+    ///
+    /// # Safety
+    /// The pass has to be destroyed with `destroy` before `render_pass` goes away.
+    pub unsafe fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        fragment_shader: Option<&str>,
+        push_constant_size: u32,
+    ) -> Result<FullscreenPass> {
+        Self::with_sources(device, render_pass, fragment_shader, 1, push_constant_size)
+    }
+
+    /// Pass whose fragment shader samples `source_count` images at bindings 0 to `source_count - 1`.
+    ///
+    /// # Safety
+    /// As for `new`.
+    pub unsafe fn with_sources(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        fragment_shader: Option<&str>,
+        source_count: u32,
+        push_constant_size: u32,
+    ) -> Result<FullscreenPass> {
+        let bindings: Vec<vk::DescriptorSetLayoutBinding> = (0..source_count)
+            .map(|binding| vk::DescriptorSetLayoutBinding {
+                binding,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: ptr::null(),
+            })
+            .collect();
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+        };
+        let set_layout = device.create_descriptor_set_layout(&set_layout_info, None)?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_FULLSCREEN_SOURCES * source_count,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            max_sets: MAX_FULLSCREEN_SOURCES,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+        };
+        let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+        let mut builder = PipelineBuilder::new(
+            "shaders/spv/fullscreen_vert.spv",
+            fragment_shader.unwrap_or("shaders/spv/blit_frag.spv"),
+        )
+        .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+        .descriptor_set_layouts(&[set_layout]);
+        if push_constant_size > 0 {
+            builder = builder.push_constant_range(vk::ShaderStageFlags::FRAGMENT, 0, push_constant_size);
+        }
+        let (pipeline, pipeline_layout) = builder.build(device, render_pass)?;
+
+        Ok(FullscreenPass {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            source_count,
+            push_constant_size,
+        })
+    }
+
+    /// Descriptor set sampling `image_view`, which has to be in SHADER_READ_ONLY_OPTIMAL layout when drawn.
+    ///
+    /// # Safety
+    /// `image_view` and `sampler` have to outlive the set, `free_source_set` gives it back.
+    pub unsafe fn create_source_set(
+        &self,
+        device: &ash::Device,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) -> VkResult<vk::DescriptorSet> {
+        self.create_sources_set(device, &[(image_view, sampler)])
+    }
+
+    /// Like `create_source_set` for passes with several sources, one (view, sampler) per binding.
+    ///
+    /// # Safety
+    /// As for `create_source_set`, `sources` needs one entry per source of the pass.
+    pub unsafe fn create_sources_set(
+        &self,
+        device: &ash::Device,
+        sources: &[(vk::ImageView, vk::Sampler)],
+    ) -> VkResult<vk::DescriptorSet> {
+        assert_eq!(
+            sources.len() as u32,
+            self.source_count,
+            "Wrong number of fullscreen pass sources"
+        );
+
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &self.set_layout,
+        };
+        let descriptor_set = device.allocate_descriptor_sets(&alloc_info)?[0];
+
+        let image_infos: Vec<vk::DescriptorImageInfo> = sources
+            .iter()
+            .map(|(image_view, sampler)| vk::DescriptorImageInfo {
+                sampler: *sampler,
+                image_view: *image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect();
+        let writes: Vec<vk::WriteDescriptorSet> = image_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, image_info)| vk::WriteDescriptorSet {
+                s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                p_next: ptr::null(),
+                dst_set: descriptor_set,
+                dst_binding: binding as u32,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: image_info,
+                p_buffer_info: ptr::null(),
+                p_texel_buffer_view: ptr::null(),
+            })
+            .collect();
+        device.update_descriptor_sets(&writes, &[]);
+
+        Ok(descriptor_set)
+    }
+
+    /// # Safety
+    /// `descriptor_set` has to come from this pass and no pending command buffer may still bind it.
+    pub unsafe fn free_source_set(&self, device: &ash::Device, descriptor_set: vk::DescriptorSet) -> VkResult<()> {
+        device.free_descriptor_sets(self.descriptor_pool, &[descriptor_set])
+    }
+
+    /// Draws over the whole `extent` of the current render pass.
+    /// `push_constants` has to match the size given to `new`.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside a render pass compatible with the one of `new`.
+    pub unsafe fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        source_set: vk::DescriptorSet,
+        extent: vk::Extent2D,
+        push_constants: &[u8],
+    ) {
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[source_set],
+            &[],
+        );
+        if self.push_constant_size > 0 {
+            device.cmd_push_constants(
+                command_buffer,
+                self.pipeline_layout,
+                vk::ShaderStageFlags::FRAGMENT,
+                0,
+                push_constants,
+            );
+        }
+
+        cmd_draw_fullscreen_triangle(device, command_buffer);
+    }
+
+    /// # Safety
+    /// The GPU has to be done with the pipeline and every source set of the pass.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
+
+/// Layout of an image with the stage and access of its last or next use.
+pub type ImageState = (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags);
+
+/// Sampled by fragment shaders, where `BlitImage` expects its images by default.
+pub const SHADER_READ: ImageState = (
+    vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    vk::PipelineStageFlags::FRAGMENT_SHADER,
+    vk::AccessFlags::SHADER_READ,
+);
+
+/// A color image `Blitter::blit_fullscreen` reads or writes, the first mip and layer of it.
+#[derive(Clone, Copy, Debug)]
+pub struct BlitImage {
+    pub image: vk::Image,
+    pub view: vk::ImageView,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    /// state the image is in when the blit is recorded, UNDEFINED discards a destination
+    pub before: ImageState,
+    /// state the image is left in, not UNDEFINED
+    pub after: ImageState,
+}
+
+impl BlitImage {
+    pub fn new(image: vk::Image, view: vk::ImageView, format: vk::Format, extent: vk::Extent2D) -> Self {
+        Self {
+            image,
+            view,
+            format,
+            extent,
+            before: SHADER_READ,
+            after: SHADER_READ,
+        }
+    }
+
+    pub fn before(mut self, before: ImageState) -> Self {
+        self.before = before;
+        self
+    }
+
+    pub fn after(mut self, after: ImageState) -> Self {
+        self.after = after;
+        self
+    }
+}
+
+/// What `Blitter::blit_fullscreen` copies and how.
+#[derive(Clone, Copy, Debug)]
+pub struct BlitDesc<'a> {
+    pub src: BlitImage,
+    pub dst: BlitImage,
+    pub sampler: SamplerDesc,
+    /// a fragment shader like the ones of `FullscreenPass::new`, without push constants, which always draws
+    pub shader: Option<&'a str>,
+}
+
+impl<'a> BlitDesc<'a> {
+    /// A linear clamped blit without a shader.
+    pub fn new(src: BlitImage, dst: BlitImage) -> Self {
+        Self {
+            src,
+            dst,
+            sampler: SamplerDesc::default(),
+            shader: None,
+        }
+    }
+
+    pub fn sampler(mut self, sampler: SamplerDesc) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn shader(mut self, shader: &'a str) -> Self {
+        self.shader = Some(shader);
+        self
+    }
+}
+
+/// Copies one image into another with `cmd_blit_image` where the formats allow it and with a `FullscreenPass`
+/// otherwise or when a shader is given. The render passes, framebuffers, pipelines, samplers and source sets
+/// the draws need are created on first use and kept, `forget_view` drops the ones of a destroyed view.
+#[derive(Default)]
+pub struct Blitter {
+    render_passes: HashMap<vk::Format, vk::RenderPass>,
+    framebuffers: HashMap<(vk::ImageView, u32, u32), vk::Framebuffer>,
+    passes: HashMap<(vk::Format, Option<String>), FullscreenPass>,
+    source_sets: HashMap<(vk::Format, Option<String>, vk::ImageView, SamplerDesc), vk::DescriptorSet>,
+    samplers: HashMap<SamplerDesc, vk::Sampler>,
+    supported: HashMap<(vk::Format, vk::Format, vk::Filter), bool>,
+}
+
+impl Blitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records `blit.src` scaled over all of `blit.dst`, outside of a render pass, and moves both images from
+    /// their `before` to their `after` state. Returns whether `cmd_blit_image` was used.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording on a graphics queue and both images have to be in their `before`
+    /// state when it executes.
+    pub unsafe fn blit_fullscreen(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        command_buffer: vk::CommandBuffer,
+        blit: &BlitDesc,
+    ) -> Result<bool> {
+        let BlitDesc {
+            src,
+            dst,
+            ref sampler,
+            shader,
+        } = *blit;
+        let filter = sampler.mag_filter;
+        let blit = shader.is_none()
+            && *self
+                .supported
+                .entry((src.format, dst.format, filter))
+                .or_insert_with(|| supports_blit(instance, physical_device, src.format, dst.format, filter));
+
+        if blit {
+            let transfer_src = (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            );
+            let transfer_dst = (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            );
+            texture::cmd_transition_image(device, command_buffer, src.image, src.before, transfer_src);
+            texture::cmd_transition_image(device, command_buffer, dst.image, dst.before, transfer_dst);
+            cmd_blit_image(
+                device,
+                command_buffer,
+                (src.image, src.extent),
+                (dst.image, dst.extent),
+                filter,
+            );
+            texture::cmd_transition_image(device, command_buffer, src.image, transfer_src, src.after);
+            texture::cmd_transition_image(device, command_buffer, dst.image, transfer_dst, dst.after);
+            return Ok(true);
+        }
+
+        let source_set = self.source_set(device, dst.format, shader, src.view, sampler)?;
+        let framebuffer = self.framebuffer(device, &dst)?;
+        let render_pass = self.render_passes[&dst.format];
+        let pass = &self.passes[&(dst.format, shader.map(str::to_owned))];
+
+        texture::cmd_transition_image(device, command_buffer, src.image, src.before, SHADER_READ);
+        // the render pass discards the old contents, the barrier only orders the draw after earlier uses
+        let attachment = (
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        texture::cmd_transition_image(device, command_buffer, dst.image, dst.before, attachment);
+
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+            p_next: ptr::null(),
+            render_pass,
+            framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: dst.extent,
+            },
+            clear_value_count: 0,
+            p_clear_values: ptr::null(),
+        };
+        device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        pass.record(device, command_buffer, source_set, dst.extent, &[]);
+        device.cmd_end_render_pass(command_buffer);
+
+        // the render pass leaves the destination ready to be sampled
+        texture::cmd_transition_image(device, command_buffer, dst.image, SHADER_READ, dst.after);
+        texture::cmd_transition_image(device, command_buffer, src.image, SHADER_READ, src.after);
+        Ok(false)
+    }
+
+    unsafe fn source_set(
+        &mut self,
+        device: &ash::Device,
+        format: vk::Format,
+        shader: Option<&str>,
+        view: vk::ImageView,
+        sampler: &SamplerDesc,
+    ) -> Result<vk::DescriptorSet> {
+        let key = (format, shader.map(str::to_owned), view, *sampler);
+        if let Some(&set) = self.source_sets.get(&key) {
+            return Ok(set);
+        }
+        let render_pass = match self.render_passes.entry(format) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => {
+                *entry.insert(pipeline::create_offscreen_render_pass(device, format, None, false, false)?)
+            }
+        };
+        let pass_key = (format, key.1.clone());
+        if let Entry::Vacant(entry) = self.passes.entry(pass_key.clone()) {
+            entry.insert(FullscreenPass::new(device, render_pass, shader, 0)?);
+        }
+        let sampler_handle = match self.samplers.entry(*sampler) {
+            Entry::Occupied(entry) => *entry.get(),
+            Entry::Vacant(entry) => *entry.insert(texture::create_sampler(device, sampler)?),
+        };
+        let set = self.passes[&pass_key].create_source_set(device, view, sampler_handle)?;
+        self.source_sets.insert(key, set);
+        Ok(set)
+    }
+
+    unsafe fn framebuffer(&mut self, device: &ash::Device, dst: &BlitImage) -> Result<vk::Framebuffer> {
+        let key = (dst.view, dst.extent.width, dst.extent.height);
+        if let Some(&framebuffer) = self.framebuffers.get(&key) {
+            return Ok(framebuffer);
+        }
+        let render_pass = self.render_passes[&dst.format];
+        let framebuffer = pipeline::create_framebuffer(device, render_pass, &[dst.view], dst.extent)?;
+        self.framebuffers.insert(key, framebuffer);
+        Ok(framebuffer)
+    }
+
+    /// Drops the framebuffers and source sets of `view`, call it before destroying the view once the GPU is done
+    /// with the blits reading or writing it.
+    ///
+    /// # Safety
+    /// No pending command buffer may still blit from or to `view`.
+    pub unsafe fn forget_view(&mut self, device: &ash::Device, view: vk::ImageView) {
+        self.framebuffers.retain(|&(framebuffer_view, ..), &mut framebuffer| {
+            if framebuffer_view == view {
+                device.destroy_framebuffer(framebuffer, None);
+            }
+            framebuffer_view != view
+        });
+        let passes = &self.passes;
+        self.source_sets.retain(|(format, shader, set_view, _), &mut set| {
+            if *set_view == view {
+                let _ = passes[&(*format, shader.clone())].free_source_set(device, set);
+            }
+            *set_view != view
+        });
+    }
+
+    /// # Safety
+    /// Every command buffer with a blit of the blitter has to be finished.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for &framebuffer in self.framebuffers.values() {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        // the sets go with the descriptor pools of their passes
+        for pass in self.passes.values() {
+            pass.destroy(device);
+        }
+        for &render_pass in self.render_passes.values() {
+            device.destroy_render_pass(render_pass, None);
+        }
+        for &sampler in self.samplers.values() {
+            device.destroy_sampler(sampler, None);
+        }
+    }
+}
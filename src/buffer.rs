@@ -1,38 +1,34 @@
-use std::{
-    ffi::c_void,
-    mem::{size_of, size_of_val},
-    ptr,
-};
+use std::{mem::size_of_val, ptr};
 
-use anyhow::Result;
+use crate::error::Result;
 use ash::{
     prelude::VkResult,
     vk::{self, BufferUsageFlags, ImageCreateFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
 };
 
-use crate::{
-    constant::{Index, Vertex, INDICES, VERTICES},
-    QueueFamilyIndices,
-};
+use crate::constant::{INDICES, VERTICES};
 
 pub const MAX_FRAMES_IN_FLIGHT: u8 = 2;
 
+/// # Safety
+/// The views have to outlive the framebuffers and match the attachments of `render_pass`.
 pub unsafe fn create_frame_buffer(
     device: &ash::Device,
-    swapchain_image_views: &Vec<vk::ImageView>,
+    swapchain_image_views: &[vk::ImageView],
+    depth_view: vk::ImageView,
     render_pass: vk::RenderPass,
     swapchain_extent: vk::Extent2D,
 ) -> VkResult<Vec<vk::Framebuffer>> {
     let mut frame_buffer = vec![];
-    println!("frame_buffer length = {}", swapchain_image_views.len());
-    for index in 0..swapchain_image_views.len() {
-        let attachments = [swapchain_image_views[index]];
+    log::debug!("frame_buffer length = {}", swapchain_image_views.len());
+    for &image_view in swapchain_image_views {
+        let attachments = [image_view, depth_view];
         let mut info = vk::FramebufferCreateInfo {
             s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
             p_next: ptr::null(),
             flags: vk::FramebufferCreateFlags::empty(),
             render_pass,
-            attachment_count: 1,
+            attachment_count: attachments.len() as u32,
             p_attachments: attachments.as_ptr(),
             width: swapchain_extent.width,
             height: swapchain_extent.height,
@@ -49,6 +45,8 @@ pub unsafe fn create_frame_buffer(
     Ok(frame_buffer)
 }
 
+/// # Safety
+/// `command_pool` has to be a pool of `device`, the buffers are freed with it.
 pub unsafe fn create_command_buffers(device: &ash::Device, command_pool: vk::CommandPool) -> Result<Vec<vk::CommandBuffer>> {
     let alloc_info = vk::CommandBufferAllocateInfo {
         s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
@@ -62,16 +60,24 @@ pub unsafe fn create_command_buffers(device: &ash::Device, command_pool: vk::Com
     Ok(command_buffer)
 }
 
+/// The vertex and index buffer of the quad `record_command_buffer` draws.
+#[derive(Clone, Copy, Debug)]
+pub struct QuadBuffers {
+    pub vertex_buffer: vk::Buffer,
+    pub index_buffer: vk::Buffer,
+}
+
+/// # Safety
+/// `command_buffer` has to be in the initial state, `pipeline` built for `render_pass` and `quad` holding
+/// `constant::VERTICES` and `constant::INDICES`.
 pub unsafe fn record_command_buffer(
     device: &ash::Device,
     command_buffer: vk::CommandBuffer,
     render_pass: vk::RenderPass,
-    swap_chain_framebuffer: &Vec<vk::Framebuffer>,
-    image_index: u32,
+    framebuffer: vk::Framebuffer,
     swapchain_extent: vk::Extent2D,
     pipeline: vk::Pipeline,
-    vertex_buffer: vk::Buffer,
-    index_buffer: vk::Buffer,
+    quad: QuadBuffers,
 ) -> VkResult<()> {
     let begin_info = vk::CommandBufferBeginInfo {
         s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
@@ -82,23 +88,28 @@ pub unsafe fn record_command_buffer(
 
     device.begin_command_buffer(command_buffer, &begin_info)?;
 
-    let clear_values = [vk::ClearValue {
-        // draw the frame black before drawing the scene
-        color: vk::ClearColorValue {
-            float32: [0.0, 0.0, 0.0, 1.0],
+    let clear_values = [
+        vk::ClearValue {
+            // draw the frame black before drawing the scene
+            color: vk::ClearColorValue {
+                float32: [0.0, 0.0, 0.0, 1.0],
+            },
         },
-    }];
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+        },
+    ];
 
     let render_pass_info = vk::RenderPassBeginInfo {
         s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
         p_next: ptr::null(),
         render_pass,
-        framebuffer: swap_chain_framebuffer[image_index as usize],
+        framebuffer,
         render_area: vk::Rect2D {
             offset: vk::Offset2D { x: 0, y: 0 },
             extent: swapchain_extent,
         },
-        clear_value_count: 1,
+        clear_value_count: clear_values.len() as u32,
         p_clear_values: clear_values.as_ptr(),
     };
 
@@ -108,13 +119,14 @@ pub unsafe fn record_command_buffer(
 
     device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
 
-    let mut viewport = vk::Viewport::default();
-    viewport.x = 0.0; // bottom left corner
-    viewport.y = 0.0;
-    viewport.width = swapchain_extent.width as f32;
-    viewport.height = swapchain_extent.height as f32;
-    viewport.min_depth = 0.0;
-    viewport.max_depth = 1.0;
+    let viewport = vk::Viewport {
+        x: 0.0, // bottom left corner
+        y: 0.0,
+        width: swapchain_extent.width as f32,
+        height: swapchain_extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    };
     device.cmd_set_viewport(command_buffer, 0, &[viewport]);
 
     // clip area of pixels, in this instance, we use the whole window
@@ -125,10 +137,10 @@ pub unsafe fn record_command_buffer(
     }];
     device.cmd_set_scissor(command_buffer, 0, &scissor);
 
-    let vertex_buffers = [vertex_buffer];
+    let vertex_buffers = [quad.vertex_buffer];
     let offsets = [0];
     device.cmd_bind_vertex_buffers(command_buffer, 0, &vertex_buffers, &offsets);
-    device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT16);
+    device.cmd_bind_index_buffer(command_buffer, quad.index_buffer, 0, vk::IndexType::UINT16);
 
     device.cmd_draw_indexed(command_buffer, INDICES.len() as u32, 1, 0, 0, 0);
 
@@ -140,6 +152,8 @@ pub unsafe fn record_command_buffer(
     Ok(())
 }
 
+/// # Safety
+/// `queue_family` has to be Some and a family the device was created with.
 pub unsafe fn create_command_pool(device: &ash::Device, queue_family: &Option<u32>) -> Result<vk::CommandPool> {
     let pool_info = vk::CommandPoolCreateInfo {
         s_type: vk::StructureType::COMMAND_POOL_CREATE_INFO,
@@ -151,6 +165,8 @@ pub unsafe fn create_command_pool(device: &ash::Device, queue_family: &Option<u3
     Ok(device.create_command_pool(&pool_info, None)?)
 }
 
+/// # Safety
+/// The fences are created signaled, every object has to be destroyed on `device`.
 pub unsafe fn create_sync_objects(
     device: &ash::Device,
 ) -> VkResult<(Vec<vk::Fence>, Vec<vk::Semaphore>, Vec<vk::Semaphore>)> {
@@ -188,6 +204,8 @@ pub unsafe fn create_sync_objects(
     Ok((inflight_fences, image_available_semaphores, render_finished_semaphores))
 }
 
+/// # Safety
+/// `transfer_queue` has to be of the family of `transfer_pool`, it's waited on for the copy.
 pub unsafe fn create_index_buffer(
     device: &ash::Device,
     instance: &ash::Instance,
@@ -195,90 +213,93 @@ pub unsafe fn create_index_buffer(
     transfer_pool: vk::CommandPool,
     transfer_queue: vk::Queue,
 ) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
-    let buffer_size = std::mem::size_of_val(&INDICES) as u64;
-
-    let (staging_buffer, stage_memory) = create_buffer(
+    create_device_local_buffer(
         device,
         instance,
         physical_device,
-        buffer_size,
-        BufferUsageFlags::TRANSFER_SRC,
-        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-    )?;
-
-    let data = device.map_memory(stage_memory, 0, buffer_size, MemoryMapFlags::empty())? as *mut Index;
-    data.copy_from_nonoverlapping(INDICES.as_ptr(), INDICES.len());
-    device.unmap_memory(stage_memory);
+        transfer_pool,
+        transfer_queue,
+        &INDICES,
+        BufferUsageFlags::INDEX_BUFFER,
+    )
+}
 
-    let (index_buffer, index_buffer_memory) = create_buffer(
+/// # Safety
+/// As for `create_index_buffer`.
+pub unsafe fn create_vertex_buffer(
+    device: &ash::Device,
+    physical_device: vk::PhysicalDevice,
+    instance: &ash::Instance,
+    transfer_pool: vk::CommandPool,
+    transfer_queue: vk::Queue,
+) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
+    create_device_local_buffer(
         device,
         instance,
         physical_device,
-        buffer_size,
-        BufferUsageFlags::INDEX_BUFFER | BufferUsageFlags::TRANSFER_DST,
-        MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
-
-    copy_buffer(
-        device,
-        staging_buffer,
-        index_buffer,
-        buffer_size,
         transfer_pool,
         transfer_queue,
-    )?;
-
-    device.destroy_buffer(staging_buffer, None);
-    device.free_memory(stage_memory, None);
-    Ok((index_buffer, index_buffer_memory))
+        &VERTICES,
+        BufferUsageFlags::VERTEX_BUFFER,
+    )
 }
 
-pub unsafe fn create_vertex_buffer(
+/// Uploads `data` into a new device local buffer through a staging buffer.
+/// TRANSFER_DST is added to `usage` automatically.
+///
+/// # Safety
+/// As for `create_index_buffer`, `T` has to be plain data the shaders can read.
+pub unsafe fn create_device_local_buffer<T>(
     device: &ash::Device,
-    physical_device: vk::PhysicalDevice,
     instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
     transfer_pool: vk::CommandPool,
     transfer_queue: vk::Queue,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
 ) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
-    let properties_vertex = MemoryPropertyFlags::DEVICE_LOCAL;
-    let properties_staging = MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT;
-    let buffer_size = (size_of_val(&VERTICES)) as u64;
+    let buffer_size = size_of_val(data) as u64;
+    let (staging_buffer, stage_memory) = create_staging_buffer(device, instance, physical_device, data)?;
 
-    let (stage_buffer, stage_buffer_memory) = create_buffer(
+    let (buffer, buffer_memory) = create_buffer(
         device,
         instance,
         physical_device,
         buffer_size,
-        vk::BufferUsageFlags::TRANSFER_SRC,
-        properties_staging,
+        usage | BufferUsageFlags::TRANSFER_DST,
+        MemoryPropertyFlags::DEVICE_LOCAL,
     )?;
 
-    let data = device.map_memory(stage_buffer_memory, 0, buffer_size, MemoryMapFlags::empty())? as *mut Vertex;
-    data.copy_from_nonoverlapping(VERTICES.as_ptr(), VERTICES.len());
-    device.unmap_memory(stage_buffer_memory);
+    copy_buffer(device, staging_buffer, buffer, buffer_size, transfer_pool, transfer_queue)?;
 
-    let (vertex_buffer, vertex_buffer_memory) = create_buffer(
+    device.destroy_buffer(staging_buffer, None);
+    device.free_memory(stage_memory, None);
+    Ok((buffer, buffer_memory))
+}
+
+/// Host visible buffer holding a copy of `data`, the source of a transfer.
+pub(crate) unsafe fn create_staging_buffer<T>(
+    device: &ash::Device,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    data: &[T],
+) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
+    let buffer_size = size_of_val(data) as u64;
+
+    let (staging_buffer, stage_memory) = create_buffer(
         device,
         instance,
         physical_device,
         buffer_size,
-        vk::BufferUsageFlags::VERTEX_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
-        properties_vertex,
-    )?;
-
-    copy_buffer(
-        device,
-        stage_buffer,
-        vertex_buffer,
-        buffer_size,
-        transfer_pool,
-        transfer_queue,
+        BufferUsageFlags::TRANSFER_SRC,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
     )?;
 
-    device.destroy_buffer(stage_buffer, None);
-    device.free_memory(stage_buffer_memory, None);
+    let mapped = device.map_memory(stage_memory, 0, buffer_size, MemoryMapFlags::empty())? as *mut T;
+    mapped.copy_from_nonoverlapping(data.as_ptr(), data.len());
+    device.unmap_memory(stage_memory);
 
-    Ok((vertex_buffer, vertex_buffer_memory))
+    Ok((staging_buffer, stage_memory))
 }
 
 unsafe fn copy_buffer(
@@ -304,7 +325,7 @@ unsafe fn copy_buffer(
     Ok(())
 }
 
-unsafe fn find_memory_type(
+pub(crate) unsafe fn find_memory_type(
     type_filter: u32,
     properties: vk::MemoryPropertyFlags,
     physical_device: vk::PhysicalDevice,
@@ -326,7 +347,7 @@ unsafe fn find_memory_type(
     panic!("failed to fidnd suitable memory type!");
 }
 
-unsafe fn create_buffer(
+pub(crate) unsafe fn create_buffer(
     device: &ash::Device,
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
@@ -361,84 +382,31 @@ unsafe fn create_buffer(
     Ok((buffer, device_memory))
 }
 
-use stb_image::image::{self, LoadResult};
-
-fn load_texture(path: &str) -> Result<(Vec<u8>, u32, u32), String> {
-    let (width, height, data) = match image::load(path) {
-        LoadResult::Error(_) => todo!(),
-        LoadResult::ImageU8(x) => (x.width as u32, x.height as u32, x.data),
-        LoadResult::ImageF32(x) => panic!("no "),
-    };
-
-    Ok((data, width, height))
-}
-
-// Create Vulkan image from loaded texture data
-unsafe fn create_texture_image(
+pub(crate) unsafe fn create_image(
     device: &ash::Device,
     instance: &ash::Instance,
     physical_device: vk::PhysicalDevice,
-    texture_data: &[u8],
-    width: u32,
-    height: u32,
-) -> VkResult<(vk::Image, vk::DeviceMemory)> {
-    let image_size = (width * height * 4) as vk::DeviceSize;
-
-    let (stage_buffer, stage_memory) = create_buffer(
-        device,
-        instance,
-        physical_device,
-        image_size,
-        BufferUsageFlags::TRANSFER_SRC,
-        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
-    )?;
-
-    let data = device.map_memory(stage_memory, 0, image_size, MemoryMapFlags::empty())? as *mut u8;
-    data.copy_from_nonoverlapping(texture_data.as_ptr(), VERTICES.len());
-    device.unmap_memory(stage_memory);
-
-    let (image, image_memory) = create_image(
-        device,
-        instance,
-        physical_device,
-        texture_data,
-        width,
-        height,
-        vk::Format::R8G8B8A8_SRGB,
-        vk::ImageTiling::OPTIMAL,
-        vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
-        MemoryPropertyFlags::DEVICE_LOCAL,
-    )?;
-
-    Ok((image, image_memory))
-}
-
-unsafe fn create_image(
-    device: &ash::Device,
-    instance: &ash::Instance,
-    physical_device: vk::PhysicalDevice,
-    texture_data: &[u8],
-    width: u32,
-    height: u32,
+    extent: vk::Extent2D,
     format: vk::Format,
-    tiling: vk::ImageTiling,
     usage: vk::ImageUsageFlags,
     properties: MemoryPropertyFlags,
 ) -> VkResult<(vk::Image, vk::DeviceMemory)> {
-    let image_size = (width * height * 4) as vk::DeviceSize;
-
-    let extent = vk::Extent3D { width, height, depth: 1 };
+    let extent = vk::Extent3D {
+        width: extent.width,
+        height: extent.height,
+        depth: 1,
+    };
     let image_info = vk::ImageCreateInfo {
         s_type: StructureType::IMAGE_CREATE_INFO,
         p_next: ptr::null(),
         flags: ImageCreateFlags::empty(),
         image_type: vk::ImageType::TYPE_2D,
         format,
-        extent: extent,
+        extent,
         mip_levels: 1,
         array_layers: 1,
         samples: vk::SampleCountFlags::TYPE_1,
-        tiling,
+        tiling: vk::ImageTiling::OPTIMAL,
         usage,
         sharing_mode: vk::SharingMode::EXCLUSIVE,
         queue_family_index_count: 0,
@@ -462,11 +430,22 @@ unsafe fn create_image(
     Ok((image, image_memory))
 }
 
-unsafe fn begin_single_commands(device: &ash::Device, command_pool: vk::CommandPool) -> VkResult<(vk::CommandBuffer)> {
+/// Pool the one time command buffers of an upload are allocated from and the queue they're submitted to,
+/// `queue` has to belong to the family of `command_pool`.
+#[derive(Clone, Copy, Debug)]
+pub struct OneTimeCommands {
+    pub command_pool: vk::CommandPool,
+    pub queue: vk::Queue,
+}
+
+pub(crate) unsafe fn begin_single_commands(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+) -> VkResult<vk::CommandBuffer> {
     let alloc_info = vk::CommandBufferAllocateInfo {
         s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
         p_next: ptr::null(),
-        command_pool: command_pool,
+        command_pool,
         level: vk::CommandBufferLevel::PRIMARY,
         command_buffer_count: 1,
     };
@@ -481,10 +460,10 @@ unsafe fn begin_single_commands(device: &ash::Device, command_pool: vk::CommandP
 
     device.begin_command_buffer(command_buffer[0], &begin_info)?;
 
-    Ok((command_buffer[0]))
+    Ok(command_buffer[0])
 }
 
-unsafe fn end_single_time_command(
+pub(crate) unsafe fn end_single_time_command(
     device: &ash::Device,
     command_buffer: vk::CommandBuffer,
     command_pool: vk::CommandPool,
@@ -511,45 +490,3 @@ unsafe fn end_single_time_command(
 
     Ok(())
 }
-
-unsafe fn transition_image_layout(
-    device: &ash::Device,
-    command_pool: vk::CommandPool,
-    image: vk::Image,
-    format: vk::Format,
-    old_layout: vk::ImageLayout,
-    new_layout: vk::ImageLayout,
-) -> VkResult<()> {
-    let command_buffer = begin_single_commands(device, command_pool)?;
-
-    let barrier = vk::ImageMemoryBarrier {
-        s_type: StructureType::IMAGE_MEMORY_BARRIER,
-        p_next: ptr::null(),
-        src_access_mask: vk::AccessFlags::empty(),
-        dst_access_mask: vk::AccessFlags::empty(),
-        old_layout,
-        new_layout,
-        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
-        image,
-        subresource_range: vk::ImageSubresourceRange {
-            aspect_mask: vk::ImageAspectFlags::COLOR,
-            base_mip_level: 0,
-            level_count: 1,
-            base_array_layer: 0,
-            layer_count: 1,
-        },
-    };
-
-    // device.cmd_pipeline_barrier(
-    //     command_buffer,
-    //     vk::PipelineStageFlags::empty(),
-    //     vk::PipelineStageFlags::empty(),
-    //     vk::DependencyFlags::empty(),
-    //     ptr::null(),
-    //     ptr::null(),
-    //     &[barrier],
-    // );
-
-    Ok(())
-}
@@ -0,0 +1,391 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    ptr,
+    time::{Duration, Instant},
+};
+
+use crate::error::Result;
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::{
+    buffer::MAX_FRAMES_IN_FLIGHT,
+    texture::{self, SamplerDesc},
+};
+
+/// A Vulkan object a cache owns, destroyed once it went unused long enough.
+pub trait CachedObject {
+    /// # Safety
+    /// `device` has to be the device the object was created on and no pending command buffer may use it.
+    unsafe fn destroy(self, device: &ash::Device);
+}
+
+impl CachedObject for vk::Sampler {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_sampler(self, None);
+    }
+}
+
+impl CachedObject for vk::Framebuffer {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_framebuffer(self, None);
+    }
+}
+
+impl CachedObject for vk::DescriptorSetLayout {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_descriptor_set_layout(self, None);
+    }
+}
+
+impl CachedObject for (vk::Pipeline, vk::PipelineLayout) {
+    unsafe fn destroy(self, device: &ash::Device) {
+        device.destroy_pipeline(self.0, None);
+        device.destroy_pipeline_layout(self.1, None);
+    }
+}
+
+/// Limits of one `ResourceCache::collect` call, so cleaning up never causes a hitch.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GcBudget {
+    /// entries unused for this many frames are destroyed, never less than `MAX_FRAMES_IN_FLIGHT`
+    pub unused_frames: u64,
+    /// entries looked at per call, the scan continues where the last call stopped
+    pub max_checks: usize,
+    pub max_destroys: usize,
+    /// checked between entries, so a single slow destroy can overshoot it
+    pub max_time: Duration,
+}
+
+impl Default for GcBudget {
+    fn default() -> Self {
+        Self {
+            unused_frames: 300,
+            max_checks: 64,
+            max_destroys: 8,
+            max_time: Duration::from_micros(250),
+        }
+    }
+}
+
+/// What a `collect` call did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct GcStats {
+    pub checked: usize,
+    pub destroyed: usize,
+}
+
+struct Slot<K, V> {
+    key: K,
+    value: V,
+    last_used: u64,
+}
+
+/// Objects created on first use and reused while their key is requested,
+/// the scan order is a vec, so collection can resume at a cursor.
+pub struct ObjectCache<K, V> {
+    slots: Vec<Slot<K, V>>,
+    index: HashMap<K, usize>,
+    cursor: usize,
+}
+
+impl<K: Hash + Eq + Clone, V: CachedObject + Copy> Default for ObjectCache<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: CachedObject + Copy> ObjectCache<K, V> {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![],
+            index: HashMap::new(),
+            cursor: 0,
+        }
+    }
+
+    /// The object for `key`, `create` runs when there is none yet. Marks it used in `frame`.
+    pub fn get_or_create<E, F: FnOnce() -> Result<V, E>>(&mut self, key: &K, frame: u64, create: F) -> Result<V, E> {
+        if let Some(&index) = self.index.get(key) {
+            let slot = &mut self.slots[index];
+            slot.last_used = frame;
+            return Ok(slot.value);
+        }
+        let value = create()?;
+        self.index.insert(key.clone(), self.slots.len());
+        self.slots.push(Slot {
+            key: key.clone(),
+            value,
+            last_used: frame,
+        });
+        Ok(value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    fn remove(&mut self, index: usize) -> V {
+        let slot = self.slots.swap_remove(index);
+        self.index.remove(&slot.key);
+        if let Some(moved) = self.slots.get(index) {
+            self.index.insert(moved.key.clone(), index);
+        }
+        slot.value
+    }
+
+    /// Checks entries from the cursor on, stops when `budget` or `deadline` runs out.
+    unsafe fn collect(
+        &mut self,
+        device: &ash::Device,
+        oldest_kept: u64,
+        budget: &GcBudget,
+        deadline: Instant,
+        stats: &mut GcStats,
+    ) {
+        let mut remaining = self.slots.len();
+        while remaining > 0
+            && stats.checked < budget.max_checks
+            && stats.destroyed < budget.max_destroys
+            && Instant::now() < deadline
+        {
+            if self.cursor >= self.slots.len() {
+                self.cursor = 0;
+            }
+            stats.checked += 1;
+            remaining -= 1;
+            if self.slots[self.cursor].last_used < oldest_kept {
+                // the last slot moves into the cursor, which is checked next
+                self.remove(self.cursor).destroy(device);
+                stats.destroyed += 1;
+            } else {
+                self.cursor += 1;
+            }
+        }
+    }
+
+    /// Destroys every entry `predicate` matches right away, the device has to be idle.
+    ///
+    /// # Safety
+    /// No pending command buffer may use the entries `predicate` matches.
+    pub unsafe fn remove_where<P: FnMut(&K) -> bool>(&mut self, device: &ash::Device, mut predicate: P) -> usize {
+        let mut removed = 0;
+        let mut index = 0;
+        while index < self.slots.len() {
+            if predicate(&self.slots[index].key) {
+                self.remove(index).destroy(device);
+                removed += 1;
+            } else {
+                index += 1;
+            }
+        }
+        removed
+    }
+
+    /// # Safety
+    /// The device has to be idle.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for slot in self.slots.drain(..) {
+            slot.value.destroy(device);
+        }
+        self.index.clear();
+        self.cursor = 0;
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FramebufferKey {
+    pub render_pass: vk::RenderPass,
+    pub attachments: Vec<vk::ImageView>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The parts of a `vk::DescriptorSetLayoutBinding` that identify it, immutable samplers aren't supported.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SetLayoutKey {
+    /// binding, type, count and stages
+    pub bindings: Vec<(u32, vk::DescriptorType, u32, vk::ShaderStageFlags)>,
+}
+
+impl SetLayoutKey {
+    pub fn new(bindings: &[vk::DescriptorSetLayoutBinding]) -> Self {
+        let mut bindings: Vec<_> = bindings
+            .iter()
+            .map(|binding| {
+                (
+                    binding.binding,
+                    binding.descriptor_type,
+                    binding.descriptor_count,
+                    binding.stage_flags,
+                )
+            })
+            .collect();
+        bindings.sort_unstable_by_key(|binding| binding.0);
+        Self { bindings }
+    }
+}
+
+/// Pipelines are keyed by a name the caller picks for the variant and the render pass they were built for.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PipelineKey {
+    pub name: String,
+    pub render_pass: vk::RenderPass,
+}
+
+/// Shared samplers, framebuffers, set layouts and pipelines.
+/// Call `begin_frame` once per frame and `collect` after recording, entries that weren't requested for
+/// `GcBudget::unused_frames` frames are destroyed a few at a time, which keeps long sessions lean.
+/// Handles from the cache are only valid for the frame they were requested in, request them again every frame.
+pub struct ResourceCache {
+    pub samplers: ObjectCache<SamplerDesc, vk::Sampler>,
+    pub framebuffers: ObjectCache<FramebufferKey, vk::Framebuffer>,
+    pub set_layouts: ObjectCache<SetLayoutKey, vk::DescriptorSetLayout>,
+    pub pipelines: ObjectCache<PipelineKey, (vk::Pipeline, vk::PipelineLayout)>,
+    pub budget: GcBudget,
+    frame: u64,
+    /// cache that `collect` starts with, rotates so every cache gets its turn
+    next_cache: usize,
+}
+
+impl Default for ResourceCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResourceCache {
+    pub fn new() -> Self {
+        Self {
+            samplers: ObjectCache::new(),
+            framebuffers: ObjectCache::new(),
+            set_layouts: ObjectCache::new(),
+            pipelines: ObjectCache::new(),
+            budget: GcBudget::default(),
+            frame: 0,
+            next_cache: 0,
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// # Safety
+    /// `device` has to be the same for every call on the cache.
+    pub unsafe fn sampler(&mut self, device: &ash::Device, desc: &SamplerDesc) -> VkResult<vk::Sampler> {
+        self.samplers
+            .get_or_create(desc, self.frame, || texture::create_sampler(device, desc))
+    }
+
+    /// # Safety
+    /// As for `sampler`, the attachments and render pass of `key` have to outlive the framebuffer.
+    pub unsafe fn framebuffer(&mut self, device: &ash::Device, key: &FramebufferKey) -> VkResult<vk::Framebuffer> {
+        self.framebuffers.get_or_create(key, self.frame, || {
+            let info = vk::FramebufferCreateInfo {
+                s_type: StructureType::FRAMEBUFFER_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::FramebufferCreateFlags::empty(),
+                render_pass: key.render_pass,
+                attachment_count: key.attachments.len() as u32,
+                p_attachments: key.attachments.as_ptr(),
+                width: key.width,
+                height: key.height,
+                layers: 1,
+            };
+            device.create_framebuffer(&info, None)
+        })
+    }
+
+    /// # Safety
+    /// As for `sampler`.
+    pub unsafe fn set_layout(
+        &mut self,
+        device: &ash::Device,
+        bindings: &[vk::DescriptorSetLayoutBinding],
+    ) -> VkResult<vk::DescriptorSetLayout> {
+        let key = SetLayoutKey::new(bindings);
+        self.set_layouts.get_or_create(&key, self.frame, || {
+            let info = vk::DescriptorSetLayoutCreateInfo {
+                s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+                binding_count: bindings.len() as u32,
+                p_bindings: bindings.as_ptr(),
+            };
+            device.create_descriptor_set_layout(&info, None)
+        })
+    }
+
+    /// `build` runs on the first request of `key`, with a `PipelineBuilder` for example.
+    pub fn pipeline<F: FnOnce() -> Result<(vk::Pipeline, vk::PipelineLayout)>>(
+        &mut self,
+        key: &PipelineKey,
+        build: F,
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
+        self.pipelines.get_or_create(key, self.frame, build)
+    }
+
+    /// Destroys the framebuffers that use `views` right away, call it with the old swapchain views
+    /// while the device is idle during a swapchain recreation.
+    ///
+    /// # Safety
+    /// No pending command buffer may still use the framebuffers of `views`.
+    pub unsafe fn remove_framebuffers_of(&mut self, device: &ash::Device, views: &[vk::ImageView]) -> usize {
+        self.framebuffers.remove_where(device, |key| {
+            key.attachments.iter().any(|attachment| views.contains(attachment))
+        })
+    }
+
+    /// Destroys entries unused for `budget.unused_frames`, at most what `budget` allows this frame.
+    ///
+    /// # Safety
+    /// `begin_frame` has to be called once per frame, entries of the frames in flight are kept.
+    pub unsafe fn collect(&mut self, device: &ash::Device) -> GcStats {
+        let budget = self.budget;
+        // anything newer could still be used by a frame in flight
+        let unused_frames = budget.unused_frames.max(MAX_FRAMES_IN_FLIGHT as u64);
+        let oldest_kept = self.frame.saturating_sub(unused_frames);
+        let deadline = Instant::now() + budget.max_time;
+
+        let mut stats = GcStats::default();
+        for offset in 0..4 {
+            match (self.next_cache + offset) % 4 {
+                0 => self.samplers.collect(device, oldest_kept, &budget, deadline, &mut stats),
+                1 => self.framebuffers.collect(device, oldest_kept, &budget, deadline, &mut stats),
+                2 => self.set_layouts.collect(device, oldest_kept, &budget, deadline, &mut stats),
+                _ => self.pipelines.collect(device, oldest_kept, &budget, deadline, &mut stats),
+            }
+        }
+        self.next_cache = (self.next_cache + 1) % 4;
+        stats
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len() + self.framebuffers.len() + self.set_layouts.len() + self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// # Safety
+    /// The device has to be idle, the objects handed out by the cache are invalid afterwards.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        self.samplers.destroy(device);
+        self.framebuffers.destroy(device);
+        self.set_layouts.destroy(device);
+        self.pipelines.destroy(device);
+    }
+}
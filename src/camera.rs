@@ -0,0 +1,638 @@
+#[cfg(feature = "winit")]
+use gilrs::Axis;
+use nalgebra as glm;
+#[cfg(feature = "winit")]
+use winit::{
+    event::{DeviceEvent, VirtualKeyCode, WindowEvent},
+    window::{CursorGrabMode, Window},
+};
+
+#[cfg(feature = "winit")]
+use crate::input::{ActionMap, InputSource};
+use crate::{frame::FrameUniforms, layers::RenderLayers};
+
+pub const MOVE_FORWARD: &str = "camera_move_forward";
+pub const MOVE_RIGHT: &str = "camera_move_right";
+pub const MOVE_UP: &str = "camera_move_up";
+pub const LOOK_RIGHT: &str = "camera_look_right";
+pub const LOOK_UP: &str = "camera_look_up";
+
+/// Keeps the camera from flipping over when looking straight up or down.
+#[cfg(feature = "winit")]
+const MAX_PITCH: f32 = 89.0_f32 * std::f32::consts::PI / 180.0;
+
+/// Right handed perspective projection for Vulkan clip space, y points down and depth is 0..1.
+pub fn perspective(fov_y: f32, aspect_ratio: f32, near: f32, far: f32) -> glm::Matrix4<f32> {
+    // nalgebra follows OpenGL, y up and depth in -1..1
+    #[rustfmt::skip]
+    let gl_to_vulkan = glm::Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, -1.0, 0.0, 0.0,
+        0.0, 0.0, 0.5, 0.5,
+        0.0, 0.0, 0.0, 1.0,
+    );
+    gl_to_vulkan * glm::Matrix4::new_perspective(aspect_ratio, fov_y, near, far)
+}
+
+/// Orthographic projection `height` units tall for Vulkan clip space, same conventions as `perspective`.
+pub fn orthographic(height: f32, aspect_ratio: f32, near: f32, far: f32) -> glm::Matrix4<f32> {
+    #[rustfmt::skip]
+    let gl_to_vulkan = glm::Matrix4::new(
+        1.0, 0.0, 0.0, 0.0,
+        0.0, -1.0, 0.0, 0.0,
+        0.0, 0.0, 0.5, 0.5,
+        0.0, 0.0, 0.0, 1.0,
+    );
+    let half_height = height * 0.5;
+    let half_width = half_height * aspect_ratio;
+    gl_to_vulkan * glm::Matrix4::new_orthographic(-half_width, half_width, -half_height, half_height, near, far)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// uses `Camera::fov_y`
+    Perspective,
+    /// world units visible from the bottom to the top of the screen
+    Orthographic { height: f32 },
+}
+
+/// Perspective or orthographic camera, looks down -z with y up when `orientation` is the identity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub position: glm::Vector3<f32>,
+    pub orientation: glm::UnitQuaternion<f32>,
+    pub projection: Projection,
+    /// vertical field of view in radians
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    /// objects on none of these layers aren't drawn by this camera
+    pub layer_mask: RenderLayers,
+    /// bumped by `notify_cut`
+    cuts: u64,
+}
+
+impl Default for Camera {
+    fn default() -> Self {
+        Self::new(glm::Vector3::zeros())
+    }
+}
+
+impl Camera {
+    pub fn new(position: glm::Vector3<f32>) -> Self {
+        Self {
+            position,
+            orientation: glm::UnitQuaternion::identity(),
+            projection: Projection::Perspective,
+            fov_y: 60.0_f32.to_radians(),
+            near: 0.1,
+            far: 1000.0,
+            layer_mask: RenderLayers::ALL,
+            cuts: 0,
+        }
+    }
+
+    /// True when objects on `layers` are drawn by this camera.
+    pub fn renders(&self, layers: RenderLayers) -> bool {
+        self.layer_mask.intersects(layers)
+    }
+
+    /// Tells temporal effects that the view jumped, for teleports and scene switches.
+    /// Their history is dropped the next time they see the camera, so nothing ghosts from the old view.
+    pub fn notify_cut(&mut self) {
+        self.cuts = self.cuts.wrapping_add(1);
+    }
+
+    /// Number of `notify_cut` calls, effects compare it with the count they last saw.
+    pub fn cut_count(&self) -> u64 {
+        self.cuts
+    }
+
+    /// Turns the camera towards `target`, keeping `up` upwards on screen.
+    pub fn look_at(&mut self, target: &glm::Vector3<f32>, up: &glm::Vector3<f32>) {
+        let direction = target - self.position;
+        if direction.norm_squared() > 0.0 {
+            // face_towards points +z at the direction, the camera looks down -z
+            self.orientation = glm::UnitQuaternion::face_towards(&-direction, up);
+        }
+    }
+
+    pub fn forward(&self) -> glm::Vector3<f32> {
+        self.orientation * -glm::Vector3::z()
+    }
+
+    pub fn right(&self) -> glm::Vector3<f32> {
+        self.orientation * glm::Vector3::x()
+    }
+
+    pub fn up(&self) -> glm::Vector3<f32> {
+        self.orientation * glm::Vector3::y()
+    }
+
+    pub fn view_matrix(&self) -> glm::Matrix4<f32> {
+        let world = glm::Isometry3::from_parts(glm::Translation3::from(self.position), self.orientation);
+        world.inverse().to_homogeneous()
+    }
+
+    /// Already converted to Vulkan clip space, see `perspective`.
+    pub fn projection_matrix(&self, aspect_ratio: f32) -> glm::Matrix4<f32> {
+        match self.projection {
+            Projection::Perspective => perspective(self.fov_y, aspect_ratio, self.near, self.far),
+            Projection::Orthographic { height } => orthographic(height, aspect_ratio, self.near, self.far),
+        }
+    }
+
+    pub fn view_projection(&self, aspect_ratio: f32) -> glm::Matrix4<f32> {
+        self.projection_matrix(aspect_ratio) * self.view_matrix()
+    }
+
+    /// Writes the camera fields of the per frame uniforms, upload them with `FrameData::update`.
+    pub fn write_uniforms(&self, uniforms: &mut FrameUniforms, aspect_ratio: f32) {
+        uniforms.view = self.view_matrix();
+        uniforms.projection = self.projection_matrix(aspect_ratio);
+        uniforms.camera_position = self.position.push(1.0);
+    }
+}
+
+/// Axis aligned views of CAD and editor applications, `Perspective` looks from the front, right and above.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ViewPreset {
+    /// looks down -y, -z is up on screen
+    Top,
+    Bottom,
+    /// looks down -z
+    Front,
+    Back,
+    /// looks down -x from +x
+    Right,
+    Left,
+    Perspective,
+}
+
+impl ViewPreset {
+    pub const ALL: [ViewPreset; 7] = [
+        ViewPreset::Top,
+        ViewPreset::Bottom,
+        ViewPreset::Front,
+        ViewPreset::Back,
+        ViewPreset::Right,
+        ViewPreset::Left,
+        ViewPreset::Perspective,
+    ];
+
+    /// Direction from the target to the camera.
+    pub fn offset_direction(self) -> glm::Vector3<f32> {
+        match self {
+            ViewPreset::Top => glm::Vector3::y(),
+            ViewPreset::Bottom => -glm::Vector3::y(),
+            ViewPreset::Front => glm::Vector3::z(),
+            ViewPreset::Back => -glm::Vector3::z(),
+            ViewPreset::Right => glm::Vector3::x(),
+            ViewPreset::Left => -glm::Vector3::x(),
+            ViewPreset::Perspective => glm::Vector3::new(1.0, 1.0, 1.0).normalize(),
+        }
+    }
+
+    fn up(self) -> glm::Vector3<f32> {
+        match self {
+            ViewPreset::Top => -glm::Vector3::z(),
+            ViewPreset::Bottom => glm::Vector3::z(),
+            _ => glm::Vector3::y(),
+        }
+    }
+
+    pub fn is_orthographic(self) -> bool {
+        self != ViewPreset::Perspective
+    }
+
+    /// Camera state looking at `target` from `distance`, orthographic views show `distance` units vertically.
+    pub fn view(self, camera: &Camera, target: &glm::Vector3<f32>, distance: f32) -> CameraView {
+        let mut view = Camera {
+            position: target + self.offset_direction() * distance,
+            projection: if self.is_orthographic() {
+                Projection::Orthographic { height: distance }
+            } else {
+                Projection::Perspective
+            },
+            ..*camera
+        };
+        view.look_at(target, &self.up());
+        CameraView::of(&view)
+    }
+}
+
+/// The parts of a camera bookmarks and transitions restore.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraView {
+    pub position: glm::Vector3<f32>,
+    pub orientation: glm::UnitQuaternion<f32>,
+    pub projection: Projection,
+    pub fov_y: f32,
+}
+
+impl CameraView {
+    pub fn of(camera: &Camera) -> Self {
+        Self {
+            position: camera.position,
+            orientation: camera.orientation,
+            projection: camera.projection,
+            fov_y: camera.fov_y,
+        }
+    }
+
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.orientation = self.orientation;
+        camera.projection = self.projection;
+        camera.fov_y = self.fov_y;
+    }
+
+    /// Blend towards `other`, the projection switches where the change is least visible:
+    /// at the start when leaving an orthographic view, at the end when entering one.
+    pub fn interpolate(&self, other: &CameraView, t: f32) -> CameraView {
+        let t = t.clamp(0.0, 1.0);
+        let projection = match (self.projection, other.projection) {
+            (Projection::Orthographic { height: from }, Projection::Orthographic { height: to }) => {
+                Projection::Orthographic {
+                    height: from + (to - from) * t,
+                }
+            }
+            (_, Projection::Orthographic { .. }) if t < 1.0 => self.projection,
+            _ => other.projection,
+        };
+        CameraView {
+            position: self.position.lerp(&other.position, t),
+            orientation: self
+                .orientation
+                .try_slerp(&other.orientation, t, 1.0e-6)
+                .unwrap_or(if t < 0.5 { self.orientation } else { other.orientation }),
+            projection,
+            fov_y: self.fov_y + (other.fov_y - self.fov_y) * t,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub view: CameraView,
+}
+
+struct CameraTransition {
+    from: CameraView,
+    to: CameraView,
+    elapsed: f32,
+    duration: f32,
+}
+
+/// Saved camera views and smooth flights between them, call `update` every frame with the camera to move.
+#[derive(Default)]
+pub struct CameraBookmarks {
+    bookmarks: Vec<CameraBookmark>,
+    transition: Option<CameraTransition>,
+}
+
+impl CameraBookmarks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Saves the current view of `camera` as `name`, replacing a bookmark with the same name.
+    pub fn save(&mut self, name: &str, camera: &Camera) {
+        let view = CameraView::of(camera);
+        match self.bookmarks.iter_mut().find(|bookmark| bookmark.name == name) {
+            Some(bookmark) => bookmark.view = view,
+            None => self.bookmarks.push(CameraBookmark {
+                name: name.to_owned(),
+                view,
+            }),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<CameraBookmark> {
+        let index = self.bookmarks.iter().position(|bookmark| bookmark.name == name)?;
+        Some(self.bookmarks.remove(index))
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CameraBookmark> {
+        self.bookmarks.iter().find(|bookmark| bookmark.name == name)
+    }
+
+    /// Bookmarks in the order they were first saved.
+    pub fn bookmarks(&self) -> &[CameraBookmark] {
+        &self.bookmarks
+    }
+
+    /// Starts flying `camera` to the bookmark `name` over `duration` seconds, false if there is no such bookmark.
+    pub fn go_to(&mut self, name: &str, camera: &Camera, duration: f32) -> bool {
+        match self.get(name) {
+            Some(bookmark) => {
+                let view = bookmark.view;
+                self.transition_to(camera, view, duration);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts flying `camera` to one of the axis views around `target`.
+    pub fn go_to_preset(
+        &mut self,
+        preset: ViewPreset,
+        camera: &Camera,
+        target: &glm::Vector3<f32>,
+        distance: f32,
+        duration: f32,
+    ) {
+        let view = preset.view(camera, target, distance);
+        self.transition_to(camera, view, duration);
+    }
+
+    /// Starts flying `camera` to `view`, a zero duration jumps on the next `update`.
+    pub fn transition_to(&mut self, camera: &Camera, view: CameraView, duration: f32) {
+        self.transition = Some(CameraTransition {
+            from: CameraView::of(camera),
+            to: view,
+            elapsed: 0.0,
+            duration: duration.max(0.0),
+        });
+    }
+
+    pub fn is_transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    /// Stops a flight where it is, call it when the user takes over the camera.
+    pub fn cancel(&mut self) {
+        self.transition = None;
+    }
+
+    /// Moves `camera` along the current flight, eased in and out. Returns true while a flight is running.
+    pub fn update(&mut self, camera: &mut Camera, delta_seconds: f32) -> bool {
+        let Some(transition) = self.transition.as_mut() else {
+            return false;
+        };
+        transition.elapsed += delta_seconds;
+        let t = if transition.duration > 0.0 {
+            (transition.elapsed / transition.duration).min(1.0)
+        } else {
+            1.0
+        };
+        let eased = t * t * (3.0 - 2.0 * t);
+        transition.from.interpolate(&transition.to, eased).apply(camera);
+        if t >= 1.0 {
+            self.transition = None;
+        }
+        true
+    }
+}
+
+/// First person look controller driven by raw mouse motion.
+/// Raw device deltas keep coming when the cursor is held at the window edge, so the view never jumps.
+#[cfg(feature = "winit")]
+pub struct FpsCameraController {
+    pub position: glm::Vector3<f32>,
+    /// radians, 0 looks down -z
+    pub yaw: f32,
+    /// radians, positive looks up
+    pub pitch: f32,
+    /// radians per mouse count
+    pub sensitivity: f32,
+    /// units per second
+    pub speed: f32,
+    /// radians per second at full stick deflection
+    pub look_speed: f32,
+    cursor_locked: bool,
+}
+
+#[cfg(feature = "winit")]
+impl FpsCameraController {
+    pub fn new(position: glm::Vector3<f32>) -> Self {
+        Self {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+            sensitivity: 0.002,
+            speed: 4.0,
+            look_speed: 2.5,
+            cursor_locked: false,
+        }
+    }
+
+    /// WASD with space and left shift for up and down, left stick moves and right stick looks.
+    pub fn bind_default_actions(actions: &mut ActionMap) {
+        actions.bind(MOVE_FORWARD, InputSource::Key(VirtualKeyCode::W));
+        actions.bind_scaled(MOVE_FORWARD, InputSource::Key(VirtualKeyCode::S), -1.0);
+        actions.bind(MOVE_FORWARD, InputSource::GamepadAxis(Axis::LeftStickY));
+        actions.bind(MOVE_RIGHT, InputSource::Key(VirtualKeyCode::D));
+        actions.bind_scaled(MOVE_RIGHT, InputSource::Key(VirtualKeyCode::A), -1.0);
+        actions.bind(MOVE_RIGHT, InputSource::GamepadAxis(Axis::LeftStickX));
+        actions.bind(MOVE_UP, InputSource::Key(VirtualKeyCode::Space));
+        actions.bind_scaled(MOVE_UP, InputSource::Key(VirtualKeyCode::LShift), -1.0);
+        actions.bind(LOOK_RIGHT, InputSource::GamepadAxis(Axis::RightStickX));
+        actions.bind(LOOK_UP, InputSource::GamepadAxis(Axis::RightStickY));
+    }
+
+    /// Moves and turns the camera from the actions bound by `bind_default_actions`.
+    pub fn update(&mut self, actions: &ActionMap, delta_seconds: f32) {
+        let look = self.look_speed * delta_seconds;
+        self.yaw -= actions.value(LOOK_RIGHT) * look;
+        self.pitch = (self.pitch + actions.value(LOOK_UP) * look).clamp(-MAX_PITCH, MAX_PITCH);
+
+        let movement = self.forward() * actions.value(MOVE_FORWARD)
+            + self.right() * actions.value(MOVE_RIGHT)
+            + glm::Vector3::y() * actions.value(MOVE_UP);
+        // diagonal movement isn't faster, analog input below full deflection stays slower
+        let movement = if movement.norm() > 1.0 {
+            movement.normalize()
+        } else {
+            movement
+        };
+        self.position += movement * self.speed * delta_seconds;
+    }
+
+    pub fn is_cursor_locked(&self) -> bool {
+        self.cursor_locked
+    }
+
+    /// Grabs and hides the cursor, falls back to confining it on platforms without locking (X11).
+    pub fn lock_cursor(&mut self, window: &Window) {
+        let grabbed = window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+
+        match grabbed {
+            Ok(_) => {
+                window.set_cursor_visible(false);
+                self.cursor_locked = true;
+            }
+            Err(e) => log::warn!("Failed to grab the cursor: {}", e),
+        }
+    }
+
+    pub fn unlock_cursor(&mut self, window: &Window) {
+        let _ = window.set_cursor_grab(CursorGrabMode::None);
+        window.set_cursor_visible(true);
+        self.cursor_locked = false;
+    }
+
+    /// Releases the cursor when the window loses focus, otherwise it stays trapped in the window.
+    pub fn handle_window_event(&mut self, window: &Window, event: &WindowEvent) {
+        if let WindowEvent::Focused(false) = event {
+            if self.cursor_locked {
+                self.unlock_cursor(window);
+            }
+        }
+    }
+
+    /// Mouse motion only turns the camera while the cursor is locked.
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event {
+            if self.cursor_locked {
+                self.rotate(*x as f32, *y as f32);
+            }
+        }
+    }
+
+    pub fn rotate(&mut self, delta_x: f32, delta_y: f32) {
+        self.yaw -= delta_x * self.sensitivity;
+        self.pitch = (self.pitch - delta_y * self.sensitivity).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn forward(&self) -> glm::Vector3<f32> {
+        glm::Vector3::new(
+            -self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            -self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
+    pub fn right(&self) -> glm::Vector3<f32> {
+        glm::Vector3::new(self.yaw.cos(), 0.0, -self.yaw.sin())
+    }
+
+    pub fn view_matrix(&self) -> glm::Matrix4<f32> {
+        let eye = glm::Point3::from(self.position);
+        glm::Matrix4::look_at_rh(&eye, &(eye + self.forward()), &glm::Vector3::y())
+    }
+
+    /// Copies position and look direction to `camera`, lens settings are kept.
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.position = self.position;
+        camera.orientation = glm::UnitQuaternion::from_euler_angles(0.0, self.yaw, 0.0)
+            * glm::UnitQuaternion::from_euler_angles(self.pitch, 0.0, 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(matrix: &glm::Matrix4<f32>, point: glm::Vector3<f32>) -> glm::Vector3<f32> {
+        let projected = matrix * point.push(1.0);
+        projected.xyz() / projected.w
+    }
+
+    fn assert_near(a: glm::Vector3<f32>, b: glm::Vector3<f32>) {
+        assert!((a - b).norm() < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn projections_map_to_vulkan_clip_space() {
+        let projection = perspective(90.0_f32.to_radians(), 1.0, 1.0, 10.0);
+        assert_near(
+            project(&projection, glm::Vector3::new(0.0, 0.0, -1.0)),
+            glm::Vector3::new(0.0, 0.0, 0.0),
+        );
+        assert_near(
+            project(&projection, glm::Vector3::new(0.0, 0.0, -10.0)),
+            glm::Vector3::new(0.0, 0.0, 1.0),
+        );
+        // y up in view space is the top of the screen, which is -1 in Vulkan
+        assert_near(
+            project(&projection, glm::Vector3::new(1.0, 1.0, -1.0)),
+            glm::Vector3::new(1.0, -1.0, 0.0),
+        );
+
+        let projection = orthographic(4.0, 2.0, 0.0, 10.0);
+        assert_near(
+            project(&projection, glm::Vector3::new(4.0, 2.0, -5.0)),
+            glm::Vector3::new(1.0, -1.0, 0.5),
+        );
+    }
+
+    #[test]
+    fn look_at_points_forward_at_the_target() {
+        let mut camera = Camera::new(glm::Vector3::new(0.0, 0.0, 5.0));
+        camera.look_at(&glm::Vector3::new(5.0, 0.0, 5.0), &glm::Vector3::y());
+        assert_near(camera.forward(), glm::Vector3::x());
+        assert_near(camera.up(), glm::Vector3::y());
+        assert_near(camera.right(), glm::Vector3::z());
+        assert_near(
+            project(&camera.view_matrix(), glm::Vector3::new(7.0, 0.0, 5.0)),
+            glm::Vector3::new(0.0, 0.0, -7.0),
+        );
+
+        // looking at its own position keeps the orientation
+        let orientation = camera.orientation;
+        camera.look_at(&camera.position.clone(), &glm::Vector3::y());
+        assert_eq!(camera.orientation, orientation);
+    }
+
+    #[test]
+    fn flights_end_exactly_on_the_bookmark() {
+        let mut camera = Camera::new(glm::Vector3::zeros());
+        let mut bookmarks = CameraBookmarks::new();
+        camera.position = glm::Vector3::new(10.0, 0.0, 0.0);
+        bookmarks.save("far", &camera);
+        camera.position = glm::Vector3::new(20.0, 0.0, 0.0);
+        bookmarks.save("far", &camera);
+        assert_eq!(bookmarks.bookmarks().len(), 1);
+
+        camera.position = glm::Vector3::zeros();
+        assert!(!bookmarks.go_to("missing", &camera, 1.0));
+        assert!(bookmarks.go_to("far", &camera, 1.0));
+        assert!(bookmarks.update(&mut camera, 0.5));
+        assert_near(camera.position, glm::Vector3::new(10.0, 0.0, 0.0));
+        assert!(bookmarks.update(&mut camera, 0.75));
+        assert_near(camera.position, glm::Vector3::new(20.0, 0.0, 0.0));
+        assert!(!bookmarks.is_transitioning());
+        assert!(!bookmarks.update(&mut camera, 0.1));
+    }
+
+    #[test]
+    fn projection_switches_on_the_perspective_side_of_a_flight() {
+        let camera = Camera::new(glm::Vector3::new(0.0, 0.0, 5.0));
+        let perspective = CameraView::of(&camera);
+        let top = ViewPreset::Top.view(&camera, &glm::Vector3::zeros(), 8.0);
+        assert_eq!(top.projection, Projection::Orthographic { height: 8.0 });
+        assert_near(top.position, glm::Vector3::new(0.0, 8.0, 0.0));
+        assert_near(top.orientation * -glm::Vector3::z(), -glm::Vector3::y());
+
+        assert_eq!(perspective.interpolate(&top, 0.9).projection, Projection::Perspective);
+        assert_eq!(perspective.interpolate(&top, 1.0).projection, top.projection);
+        assert_eq!(top.interpolate(&perspective, 0.1).projection, Projection::Perspective);
+        let zoomed = ViewPreset::Top.view(&camera, &glm::Vector3::zeros(), 4.0);
+        assert_eq!(
+            top.interpolate(&zoomed, 0.5).projection,
+            Projection::Orthographic { height: 6.0 }
+        );
+    }
+
+    #[cfg(feature = "winit")]
+    #[test]
+    fn fps_controller_clamps_the_pitch() {
+        let mut controller = FpsCameraController::new(glm::Vector3::zeros());
+        controller.rotate(0.0, -1.0e6);
+        assert_eq!(controller.pitch, MAX_PITCH);
+        controller.rotate(0.0, 1.0e6);
+        assert_eq!(controller.pitch, -MAX_PITCH);
+
+        let mut camera = Camera::default();
+        controller.pitch = 0.0;
+        controller.yaw = std::f32::consts::FRAC_PI_2;
+        controller.apply(&mut camera);
+        assert_near(camera.forward(), controller.forward());
+        assert_near(camera.forward(), -glm::Vector3::x());
+    }
+}
@@ -0,0 +1,310 @@
+use std::fmt;
+
+use ash::vk;
+
+use crate::{
+    extensions::EnabledExtensions,
+    features::{DeviceFeatures, FeatureChain},
+    utility,
+};
+
+/// Formats the report lists the support of, the ones apps usually pick between.
+pub const COMMON_FORMATS: [vk::Format; 18] = [
+    vk::Format::R8G8B8A8_UNORM,
+    vk::Format::R8G8B8A8_SRGB,
+    vk::Format::B8G8R8A8_UNORM,
+    vk::Format::B8G8R8A8_SRGB,
+    vk::Format::A2B10G10R10_UNORM_PACK32,
+    vk::Format::B10G11R11_UFLOAT_PACK32,
+    vk::Format::R16G16B16A16_SFLOAT,
+    vk::Format::R32G32B32A32_SFLOAT,
+    vk::Format::R32_SFLOAT,
+    vk::Format::D32_SFLOAT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::BC1_RGBA_SRGB_BLOCK,
+    vk::Format::BC3_SRGB_BLOCK,
+    vk::Format::BC5_UNORM_BLOCK,
+    vk::Format::BC7_SRGB_BLOCK,
+    vk::Format::ETC2_R8G8B8A8_SRGB_BLOCK,
+    vk::Format::ASTC_4X4_SRGB_BLOCK,
+];
+
+const NVIDIA_VENDOR_ID: u32 = 0x10de;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryHeap {
+    pub size: u64,
+    pub flags: vk::MemoryHeapFlags,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MemoryType {
+    pub heap_index: u32,
+    pub flags: vk::MemoryPropertyFlags,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueFamily {
+    pub index: u32,
+    pub flags: vk::QueueFlags,
+    pub queue_count: u32,
+    /// 0 when the family can't write timestamps
+    pub timestamp_valid_bits: u32,
+    /// copies on the family have to be multiples of this, zero means whole images only
+    pub min_image_transfer_granularity: vk::Extent3D,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FormatSupport {
+    pub format: vk::Format,
+    pub linear_tiling: vk::FormatFeatureFlags,
+    pub optimal_tiling: vk::FormatFeatureFlags,
+    pub buffer: vk::FormatFeatureFlags,
+}
+
+/// What a physical device is and what it can do, for adapting to the hardware and for bug reports.
+/// `Display` writes a readable multi line summary.
+#[derive(Clone, Debug)]
+pub struct Capabilities {
+    pub device_name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    pub vendor_id: u32,
+    pub device_id: u32,
+    /// `vk::make_api_version` of the Vulkan version the device supports
+    pub api_version: u32,
+    /// vendor specific encoding, `driver_version_string` decodes it
+    pub driver_version: u32,
+    pub limits: vk::PhysicalDeviceLimits,
+    pub supported_features: DeviceFeatures,
+    pub enabled_features: DeviceFeatures,
+    pub enabled_extensions: Vec<String>,
+    pub memory_heaps: Vec<MemoryHeap>,
+    pub memory_types: Vec<MemoryType>,
+    pub queue_families: Vec<QueueFamily>,
+    /// one entry per `COMMON_FORMATS`
+    pub formats: Vec<FormatSupport>,
+}
+
+impl Capabilities {
+    /// Queries `physical_device`, `enabled_features` and `enabled_extensions` are what its device was created with.
+    ///
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance`.
+    pub unsafe fn query(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        enabled_features: &DeviceFeatures,
+        enabled_extensions: &EnabledExtensions,
+    ) -> Capabilities {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let supported_features = DeviceFeatures::supported_by(&FeatureChain::supported(instance, physical_device));
+
+        let memory = instance.get_physical_device_memory_properties(physical_device);
+        let memory_heaps = memory.memory_heaps[..memory.memory_heap_count as usize]
+            .iter()
+            .map(|heap| MemoryHeap {
+                size: heap.size,
+                flags: heap.flags,
+            })
+            .collect();
+        let memory_types = memory.memory_types[..memory.memory_type_count as usize]
+            .iter()
+            .map(|memory_type| MemoryType {
+                heap_index: memory_type.heap_index,
+                flags: memory_type.property_flags,
+            })
+            .collect();
+
+        let queue_families = instance
+            .get_physical_device_queue_family_properties(physical_device)
+            .iter()
+            .enumerate()
+            .map(|(index, family)| QueueFamily {
+                index: index as u32,
+                flags: family.queue_flags,
+                queue_count: family.queue_count,
+                timestamp_valid_bits: family.timestamp_valid_bits,
+                min_image_transfer_granularity: family.min_image_transfer_granularity,
+            })
+            .collect();
+
+        let formats = COMMON_FORMATS
+            .iter()
+            .map(|format| {
+                let properties = instance.get_physical_device_format_properties(physical_device, *format);
+                FormatSupport {
+                    format: *format,
+                    linear_tiling: properties.linear_tiling_features,
+                    optimal_tiling: properties.optimal_tiling_features,
+                    buffer: properties.buffer_features,
+                }
+            })
+            .collect();
+
+        Capabilities {
+            device_name: utility::vk_to_string(&properties.device_name),
+            device_type: properties.device_type,
+            vendor_id: properties.vendor_id,
+            device_id: properties.device_id,
+            api_version: properties.api_version,
+            driver_version: properties.driver_version,
+            limits: properties.limits,
+            supported_features,
+            enabled_features: *enabled_features,
+            enabled_extensions: enabled_extensions.names().map(str::to_owned).collect(),
+            memory_heaps,
+            memory_types,
+            queue_families,
+            formats,
+        }
+    }
+
+    /// Support of `format` if it is one of `COMMON_FORMATS`.
+    pub fn format(&self, format: vk::Format) -> Option<&FormatSupport> {
+        self.formats.iter().find(|support| support.format == format)
+    }
+
+    /// Whether optimally tiled images of `format` have all of `features`, None for formats outside `COMMON_FORMATS`.
+    pub fn supports_format(&self, format: vk::Format, features: vk::FormatFeatureFlags) -> Option<bool> {
+        self.format(format).map(|support| support.optimal_tiling.contains(features))
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.enabled_extensions.iter().any(|extension| extension == name)
+    }
+
+    /// Bytes in the device local heaps.
+    pub fn device_local_memory(&self) -> u64 {
+        self.memory_heaps
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum()
+    }
+
+    /// NVIDIA packs 10.8.8.6 bits, everyone else uses the Vulkan version encoding.
+    pub fn driver_version_string(&self) -> String {
+        let version = self.driver_version;
+        if self.vendor_id == NVIDIA_VENDOR_ID {
+            format!(
+                "{}.{}.{}.{}",
+                version >> 22,
+                (version >> 14) & 0xff,
+                (version >> 6) & 0xff,
+                version & 0x3f
+            )
+        } else {
+            format!(
+                "{}.{}.{}",
+                vk::api_version_major(version),
+                vk::api_version_minor(version),
+                vk::api_version_patch(version)
+            )
+        }
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} ({:?}), vendor 0x{:04x}, device 0x{:04x}",
+            self.device_name, self.device_type, self.vendor_id, self.device_id
+        )?;
+        writeln!(
+            f,
+            "Vulkan {}.{}.{}, driver {}",
+            vk::api_version_major(self.api_version),
+            vk::api_version_minor(self.api_version),
+            vk::api_version_patch(self.api_version),
+            self.driver_version_string()
+        )?;
+
+        let limits = &self.limits;
+        writeln!(f, "limits:")?;
+        writeln!(f, "  image 2D: {}", limits.max_image_dimension2_d)?;
+        writeln!(f, "  image 3D: {}", limits.max_image_dimension3_d)?;
+        writeln!(f, "  image array layers: {}", limits.max_image_array_layers)?;
+        writeln!(f, "  uniform buffer range: {}", limits.max_uniform_buffer_range)?;
+        writeln!(f, "  storage buffer range: {}", limits.max_storage_buffer_range)?;
+        writeln!(f, "  push constants: {} bytes", limits.max_push_constants_size)?;
+        writeln!(f, "  memory allocations: {}", limits.max_memory_allocation_count)?;
+        writeln!(f, "  bound descriptor sets: {}", limits.max_bound_descriptor_sets)?;
+        writeln!(f, "  samplers per stage: {}", limits.max_per_stage_descriptor_samplers)?;
+        writeln!(
+            f,
+            "  sampled images per stage: {}",
+            limits.max_per_stage_descriptor_sampled_images
+        )?;
+        writeln!(f, "  vertex input attributes: {}", limits.max_vertex_input_attributes)?;
+        writeln!(f, "  color attachments: {}", limits.max_color_attachments)?;
+        writeln!(f, "  framebuffer color samples: {:?}", limits.framebuffer_color_sample_counts)?;
+        writeln!(f, "  framebuffer depth samples: {:?}", limits.framebuffer_depth_sample_counts)?;
+        writeln!(f, "  compute work group count: {:?}", limits.max_compute_work_group_count)?;
+        writeln!(f, "  compute work group size: {:?}", limits.max_compute_work_group_size)?;
+        writeln!(f, "  compute invocations: {}", limits.max_compute_work_group_invocations)?;
+        writeln!(f, "  compute shared memory: {} bytes", limits.max_compute_shared_memory_size)?;
+        writeln!(f, "  sampler anisotropy: {}", limits.max_sampler_anisotropy)?;
+        writeln!(f, "  viewports: {}", limits.max_viewports)?;
+        writeln!(
+            f,
+            "  uniform buffer offset alignment: {}",
+            limits.min_uniform_buffer_offset_alignment
+        )?;
+        writeln!(
+            f,
+            "  storage buffer offset alignment: {}",
+            limits.min_storage_buffer_offset_alignment
+        )?;
+        writeln!(f, "  non coherent atom size: {}", limits.non_coherent_atom_size)?;
+        writeln!(f, "  timestamp period: {} ns", limits.timestamp_period)?;
+
+        let list = |names: Vec<&str>| {
+            if names.is_empty() {
+                "none".to_owned()
+            } else {
+                names.join(", ")
+            }
+        };
+        writeln!(f, "supported features: {}", list(self.supported_features.names()))?;
+        writeln!(f, "enabled features: {}", list(self.enabled_features.names()))?;
+        writeln!(
+            f,
+            "enabled extensions: {}",
+            list(self.enabled_extensions.iter().map(String::as_str).collect())
+        )?;
+
+        writeln!(f, "memory heaps:")?;
+        for (index, heap) in self.memory_heaps.iter().enumerate() {
+            writeln!(f, "  {}: {} MiB {:?}", index, heap.size >> 20, heap.flags)?;
+        }
+        writeln!(f, "memory types:")?;
+        for (index, memory_type) in self.memory_types.iter().enumerate() {
+            writeln!(f, "  {}: heap {} {:?}", index, memory_type.heap_index, memory_type.flags)?;
+        }
+        writeln!(f, "queue families:")?;
+        for family in self.queue_families.iter() {
+            writeln!(
+                f,
+                "  {}: {} x {:?}, {} timestamp bits, transfer granularity {}x{}x{}",
+                family.index,
+                family.queue_count,
+                family.flags,
+                family.timestamp_valid_bits,
+                family.min_image_transfer_granularity.width,
+                family.min_image_transfer_granularity.height,
+                family.min_image_transfer_granularity.depth
+            )?;
+        }
+        writeln!(f, "formats, optimal tiling:")?;
+        for support in self.formats.iter() {
+            if support.optimal_tiling.is_empty() {
+                writeln!(f, "  {:?}: unsupported", support.format)?;
+            } else {
+                writeln!(f, "  {:?}: {:?}", support.format, support.optimal_tiling)?;
+            }
+        }
+        Ok(())
+    }
+}
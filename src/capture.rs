@@ -0,0 +1,140 @@
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "renderdoc")]
+use renderdoc::{RenderDoc, V141};
+
+/// Frame captures through the RenderDoc in-application API.
+/// It only attaches when the application was launched from RenderDoc or RenderDoc was injected into it,
+/// and never loads RenderDoc itself. Without the `renderdoc` feature, or when RenderDoc isn't there,
+/// every call does nothing, so the calls can stay in release builds.
+pub struct FrameCapture {
+    #[cfg(feature = "renderdoc")]
+    api: Option<RenderDoc<V141>>,
+    /// open capture from `begin`
+    capturing: bool,
+}
+
+impl Default for FrameCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameCapture {
+    pub fn new() -> Self {
+        #[cfg(feature = "renderdoc")]
+        {
+            let api = RenderDoc::<V141>::new().ok();
+            if let Some(api) = api.as_ref() {
+                let (major, minor, patch) = api.get_api_version();
+                log::info!("RenderDoc {}.{}.{} is attached", major, minor, patch);
+            }
+            Self { api, capturing: false }
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        Self { capturing: false }
+    }
+
+    /// True when captures actually reach RenderDoc.
+    pub fn is_available(&self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        {
+            self.api.is_some()
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        false
+    }
+
+    /// Captures the next presented frame of the active window, what RenderDoc's capture key does.
+    pub fn trigger_capture(&mut self) {
+        self.trigger_captures(1);
+    }
+
+    /// Captures the next `frames` presented frames, each into its own file.
+    pub fn trigger_captures(&mut self, frames: u32) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_mut() {
+            log::info!("Capturing the next {} frame(s) with RenderDoc", frames);
+            api.trigger_multi_frame_capture(frames);
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        let _ = frames;
+    }
+
+    /// Starts capturing everything submitted until `end`, for work that is never presented
+    /// like headless rendering or a compute dispatch. Captures any device and window.
+    pub fn begin(&mut self) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_mut() {
+            if !self.capturing {
+                api.start_frame_capture(std::ptr::null(), std::ptr::null());
+                self.capturing = true;
+            }
+        }
+    }
+
+    /// Ends the capture of `begin` and writes it, returns false when there was nothing to end.
+    pub fn end(&mut self) -> bool {
+        if !self.capturing {
+            return false;
+        }
+        self.capturing = false;
+        // only set while RenderDoc is attached
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_mut() {
+            api.end_frame_capture(std::ptr::null(), std::ptr::null());
+        }
+        if let Some(path) = self.latest_capture() {
+            log::info!("Wrote RenderDoc capture {}", path.display());
+        }
+        true
+    }
+
+    /// Runs `work` between `begin` and `end`.
+    pub fn capture<R>(&mut self, work: impl FnOnce() -> R) -> R {
+        self.begin();
+        let result = work();
+        self.end();
+        result
+    }
+
+    /// True while RenderDoc is capturing, from a trigger, `begin` or its own UI.
+    pub fn is_capturing(&self) -> bool {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_ref() {
+            return api.is_frame_capturing();
+        }
+        self.capturing
+    }
+
+    /// Where captures are written, RenderDoc appends the frame number and `.rdc`, for example `captures/vulky`.
+    pub fn set_path_template(&mut self, template: &Path) {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_mut() {
+            api.set_capture_file_path_template(template);
+        }
+        #[cfg(not(feature = "renderdoc"))]
+        let _ = template;
+    }
+
+    pub fn capture_count(&self) -> u32 {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_ref() {
+            return api.get_num_captures();
+        }
+        0
+    }
+
+    /// File of the newest capture of this run.
+    pub fn latest_capture(&self) -> Option<PathBuf> {
+        #[cfg(feature = "renderdoc")]
+        if let Some(api) = self.api.as_ref() {
+            let count = api.get_num_captures();
+            return count
+                .checked_sub(1)
+                .and_then(|index| api.get_capture(index))
+                .map(|(path, _)| path);
+        }
+        None
+    }
+}
@@ -0,0 +1,336 @@
+use std::ptr;
+
+use crate::error::Result;
+use ash::vk::{self, StructureType};
+
+use crate::{
+    blit::{self, FullscreenPass},
+    camera::Camera,
+    format,
+    pipeline::{self, PipelineBuilder},
+    texture::{DepthBuffer, Texture},
+    utility,
+};
+
+/// Push constants of shaders/checkerboard_resolve.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ResolvePush {
+    parity: u32,
+    history_valid: u32,
+}
+
+/// Images that change with the extent.
+struct Targets {
+    color: Texture,
+    depth: DepthBuffer,
+    /// the resolved frames, written and read alternately
+    history: [Texture; 2],
+    scene_framebuffer: vk::Framebuffer,
+    history_framebuffers: [vk::Framebuffer; 2],
+    /// set `i` reads the color and `history[1 - i]`
+    resolve_sets: [vk::DescriptorSet; 2],
+}
+
+/// The passes `Targets` are created for, the framebuffers and resolve sets belong to them.
+#[derive(Clone, Copy)]
+struct TargetPasses<'a> {
+    render_pass: vk::RenderPass,
+    resolve_render_pass: vk::RenderPass,
+    resolve: &'a FullscreenPass,
+}
+
+/// Experimental checkerboard rendering, each frame only shades the pixels of one checker color and the
+/// other half is reconstructed from the previous frame. Roughly halves the fragment work of heavy scenes.
+///
+/// The skipped pixels are masked with the depth buffer, so every pipeline drawn between `begin` and `end`
+/// has to be built for `render_pass`, or a compatible one, with depth testing enabled. The resolved image is
+/// `output`, draw it to the swapchain with a `FullscreenPass`.
+pub struct CheckerboardRenderer {
+    /// render pass of the scene, color and `format::depth_format` depth
+    pub render_pass: vk::RenderPass,
+    resolve_render_pass: vk::RenderPass,
+    mask_pipeline: vk::Pipeline,
+    mask_pipeline_layout: vk::PipelineLayout,
+    resolve: FullscreenPass,
+    targets: Targets,
+    format: vk::Format,
+    extent: vk::Extent2D,
+    frame: u32,
+    history_valid: bool,
+    /// `Camera::cut_count` seen by the last `sync_camera`
+    camera_cuts: Option<u64>,
+}
+
+impl CheckerboardRenderer {
+    /// `extent` is the full output resolution and `format` the color format of the scene, `format::resolve` picks a
+    /// fallback when the device lacks it.
+    ///
+    /// # Safety
+    /// `physical_device` has to be the device of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<CheckerboardRenderer> {
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+        let format = format::resolve(instance, physical_device, format, usage)?;
+        let depth_format = format::depth_format(instance, physical_device)?;
+        let render_pass = pipeline::create_offscreen_render_pass(device, format, Some(depth_format), true, false)?;
+        let resolve_render_pass = pipeline::create_offscreen_render_pass(device, format, None, false, false)?;
+
+        let (mask_pipeline, mask_pipeline_layout) =
+            PipelineBuilder::new("shaders/spv/fullscreen_vert.spv", "shaders/spv/checkerboard_mask_frag.spv")
+                .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+                .depth_test(true, true)
+                .push_constant_range(vk::ShaderStageFlags::FRAGMENT, 0, std::mem::size_of::<u32>() as u32)
+                .build(device, render_pass)?;
+
+        let resolve = FullscreenPass::with_sources(
+            device,
+            resolve_render_pass,
+            Some("shaders/spv/checkerboard_resolve_frag.spv"),
+            2,
+            std::mem::size_of::<ResolvePush>() as u32,
+        )?;
+
+        let passes = TargetPasses {
+            render_pass,
+            resolve_render_pass,
+            resolve: &resolve,
+        };
+        let targets = Self::create_targets(device, instance, physical_device, &passes, extent, format)?;
+
+        Ok(CheckerboardRenderer {
+            render_pass,
+            resolve_render_pass,
+            mask_pipeline,
+            mask_pipeline_layout,
+            resolve,
+            targets,
+            format,
+            extent,
+            frame: 0,
+            history_valid: false,
+            camera_cuts: None,
+        })
+    }
+
+    unsafe fn create_targets(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        passes: &TargetPasses,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Targets> {
+        let TargetPasses {
+            render_pass,
+            resolve_render_pass,
+            resolve,
+        } = *passes;
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        let color = Texture::new(device, instance, physical_device, extent, format, usage)?;
+        let depth = DepthBuffer::new(device, instance, physical_device, extent)?;
+        let history = [
+            Texture::new(device, instance, physical_device, extent, format, usage)?,
+            Texture::new(device, instance, physical_device, extent, format, usage)?,
+        ];
+
+        let scene_framebuffer = pipeline::create_framebuffer(device, render_pass, &[color.view, depth.view], extent)?;
+        let history_framebuffers = [
+            pipeline::create_framebuffer(device, resolve_render_pass, &[history[0].view], extent)?,
+            pipeline::create_framebuffer(device, resolve_render_pass, &[history[1].view], extent)?,
+        ];
+
+        // the shader uses texelFetch, so the sampler filters don't matter
+        let resolve_sets = [
+            resolve.create_sources_set(device, &[(color.view, color.sampler), (history[1].view, history[1].sampler)])?,
+            resolve.create_sources_set(device, &[(color.view, color.sampler), (history[0].view, history[0].sampler)])?,
+        ];
+
+        Ok(Targets {
+            color,
+            depth,
+            history,
+            scene_framebuffer,
+            history_framebuffers,
+            resolve_sets,
+        })
+    }
+
+    unsafe fn destroy_targets(&self, device: &ash::Device) {
+        let targets = &self.targets;
+        for set in targets.resolve_sets {
+            let _ = self.resolve.free_source_set(device, set);
+        }
+        device.destroy_framebuffer(targets.scene_framebuffer, None);
+        for framebuffer in targets.history_framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        targets.color.destroy(device);
+        targets.depth.destroy(device);
+        for history in targets.history.iter() {
+            history.destroy(device);
+        }
+    }
+
+    /// Recreates the targets for a new output resolution, the device has to be idle.
+    ///
+    /// # Safety
+    /// The device has to be idle.
+    pub unsafe fn resize(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        self.destroy_targets(device);
+        let passes = TargetPasses {
+            render_pass: self.render_pass,
+            resolve_render_pass: self.resolve_render_pass,
+            resolve: &self.resolve,
+        };
+        self.targets = Self::create_targets(device, instance, physical_device, &passes, extent, self.format)?;
+        self.extent = extent;
+        self.history_valid = false;
+        Ok(())
+    }
+
+    /// The next frame is reconstructed from its own pixels only.
+    pub fn reset_history(&mut self) {
+        self.history_valid = false;
+    }
+
+    /// Resets the history when `camera` was cut since the last call, call it once per frame before `begin`.
+    pub fn sync_camera(&mut self, camera: &Camera) {
+        if self.camera_cuts.is_some_and(|cuts| cuts != camera.cut_count()) {
+            self.reset_history();
+        }
+        self.camera_cuts = Some(camera.cut_count());
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    fn parity(&self) -> u32 {
+        self.frame & 1
+    }
+
+    /// Begins the scene render pass and masks the pixels skipped this frame.
+    /// Viewport and scissor are set to the whole extent.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording, outside of a render pass.
+    pub unsafe fn begin(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, clear_color: [f32; 4]) {
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: clear_color },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+            },
+        ];
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+            p_next: ptr::null(),
+            render_pass: self.render_pass,
+            framebuffer: self.targets.scene_framebuffer,
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+        };
+        device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        self.set_viewport(device, command_buffer);
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.mask_pipeline);
+        device.cmd_push_constants(
+            command_buffer,
+            self.mask_pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            utility::as_bytes(&self.parity()),
+        );
+        blit::cmd_draw_fullscreen_triangle(device, command_buffer);
+    }
+
+    unsafe fn set_viewport(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+    }
+
+    /// Ends the scene render pass and reconstructs the full frame into `output`.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside the render pass `begin` started.
+    pub unsafe fn end(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        device.cmd_end_render_pass(command_buffer);
+
+        let parity = self.parity() as usize;
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+            p_next: ptr::null(),
+            render_pass: self.resolve_render_pass,
+            framebuffer: self.targets.history_framebuffers[parity],
+            render_area: vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: self.extent,
+            },
+            clear_value_count: 0,
+            p_clear_values: ptr::null(),
+        };
+        device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+
+        let push = ResolvePush {
+            parity: parity as u32,
+            history_valid: self.history_valid as u32,
+        };
+        self.resolve.record(
+            device,
+            command_buffer,
+            self.targets.resolve_sets[parity],
+            self.extent,
+            utility::as_bytes(&push),
+        );
+        device.cmd_end_render_pass(command_buffer);
+
+        self.frame = self.frame.wrapping_add(1);
+        self.history_valid = true;
+    }
+
+    /// The frame resolved by the last `end`, in SHADER_READ_ONLY_OPTIMAL layout.
+    pub fn output(&self) -> &Texture {
+        // `end` already advanced the frame
+        &self.targets.history[(self.parity() ^ 1) as usize]
+    }
+
+    /// # Safety
+    /// No frame in flight may use the renderer.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        self.destroy_targets(device);
+        self.resolve.destroy(device);
+        device.destroy_pipeline(self.mask_pipeline, None);
+        device.destroy_pipeline_layout(self.mask_pipeline_layout, None);
+        device.destroy_render_pass(self.render_pass, None);
+        device.destroy_render_pass(self.resolve_render_pass, None);
+    }
+}
@@ -0,0 +1,237 @@
+use std::time::Duration;
+
+use ash::vk;
+
+use crate::{
+    constant::validation,
+    device::{self, DeviceSelection},
+    SwapchainConfig, SwapchainImageCount,
+};
+
+/// `1`/`0`, also `true`/`false`, `on`/`off` and `yes`/`no`.
+pub const VALIDATION_ENV: &str = "VULKY_VALIDATION";
+/// `fifo`, `fifo_relaxed`, `mailbox` or `immediate`.
+pub const PRESENT_MODE_ENV: &str = "VULKY_PRESENT_MODE";
+/// `double`, `triple` or an exact image count.
+pub const SWAPCHAIN_IMAGES_ENV: &str = "VULKY_SWAPCHAIN_IMAGES";
+/// Timeout of the GPU watchdog in milliseconds, `0` or `off` turns it off.
+pub const WATCHDOG_ENV: &str = "VULKY_WATCHDOG";
+
+/// Settings picked when the app starts instead of when it is compiled, so release builds can turn on validation
+/// or switch GPUs in the field.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AppConfig {
+    /// loads the Khronos layer when it is installed, defaults to `validation::ENABLED`, on in debug builds
+    pub validation: bool,
+    pub gpu: DeviceSelection,
+    /// of the first window, later windows start with it too
+    pub swapchain: SwapchainConfig,
+    /// timeout of the `GpuWatchdog`, None runs without one
+    pub watchdog: Option<Duration>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            validation: validation::ENABLED,
+            gpu: DeviceSelection::default(),
+            swapchain: SwapchainConfig::default(),
+            watchdog: None,
+        }
+    }
+}
+
+impl AppConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The defaults with the environment variables applied.
+    pub fn from_env() -> Self {
+        Self::default().with_env()
+    }
+
+    /// Overrides the settings whose variable is set, values that don't parse are logged and ignored.
+    pub fn with_env(mut self) -> Self {
+        if let Some(value) = var(VALIDATION_ENV) {
+            match parse_bool(&value) {
+                Some(enabled) => self.validation = enabled,
+                None => log::warn!("{} should be 0 or 1, not {:?}", VALIDATION_ENV, value),
+            }
+        }
+        if let Some(value) = var(device::GPU_ENV) {
+            self.gpu = DeviceSelection::parse(&value);
+        }
+        if let Some(value) = var(PRESENT_MODE_ENV) {
+            match parse_present_mode(&value) {
+                Some(present_mode) => self.swapchain.present_mode = Some(present_mode),
+                None => log::warn!("{} {:?} is not a present mode, use fifo or mailbox", PRESENT_MODE_ENV, value),
+            }
+        }
+        if let Some(value) = var(SWAPCHAIN_IMAGES_ENV) {
+            match parse_image_count(&value) {
+                Some(image_count) => self.swapchain.image_count = image_count,
+                None => log::warn!("{} {:?} is not an image count", SWAPCHAIN_IMAGES_ENV, value),
+            }
+        }
+        if let Some(value) = var(WATCHDOG_ENV) {
+            match parse_watchdog(&value) {
+                Some(watchdog) => self.watchdog = watchdog,
+                None => log::warn!("{} should be a timeout in milliseconds, not {:?}", WATCHDOG_ENV, value),
+            }
+        }
+        self
+    }
+
+    pub fn validation(mut self, enabled: bool) -> Self {
+        self.validation = enabled;
+        self
+    }
+
+    pub fn gpu(mut self, selection: DeviceSelection) -> Self {
+        self.gpu = selection;
+        self
+    }
+
+    pub fn swapchain(mut self, swapchain: SwapchainConfig) -> Self {
+        self.swapchain = swapchain;
+        self
+    }
+
+    pub fn present_mode(mut self, present_mode: vk::PresentModeKHR) -> Self {
+        self.swapchain.present_mode = Some(present_mode);
+        self
+    }
+
+    pub fn image_count(mut self, image_count: SwapchainImageCount) -> Self {
+        self.swapchain.image_count = image_count;
+        self
+    }
+
+    pub fn watchdog(mut self, timeout: Option<Duration>) -> Self {
+        self.watchdog = timeout;
+        self
+    }
+}
+
+/// Unset and empty variables are both None.
+fn var(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.trim().is_empty())
+}
+
+pub fn parse_bool(text: &str) -> Option<bool> {
+    match text.trim().to_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => Some(true),
+        "0" | "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// `vsync` is FIFO too.
+pub fn parse_present_mode(text: &str) -> Option<vk::PresentModeKHR> {
+    match text.trim().to_lowercase().replace('-', "_").as_str() {
+        "fifo" | "vsync" => Some(vk::PresentModeKHR::FIFO),
+        "fifo_relaxed" => Some(vk::PresentModeKHR::FIFO_RELAXED),
+        "mailbox" => Some(vk::PresentModeKHR::MAILBOX),
+        "immediate" => Some(vk::PresentModeKHR::IMMEDIATE),
+        _ => None,
+    }
+}
+
+pub fn parse_image_count(text: &str) -> Option<SwapchainImageCount> {
+    match text.trim().to_lowercase().as_str() {
+        "default" => Some(SwapchainImageCount::Default),
+        "double" => Some(SwapchainImageCount::Double),
+        "triple" => Some(SwapchainImageCount::Triple),
+        count => count.parse().ok().filter(|&count| count > 0).map(SwapchainImageCount::Exact),
+    }
+}
+
+/// Milliseconds, `off` and `0` are Some(None).
+pub fn parse_watchdog(text: &str) -> Option<Option<Duration>> {
+    match text.trim().to_lowercase().as_str() {
+        "off" | "0" => Some(None),
+        millis => millis.parse().ok().map(|millis| Some(Duration::from_millis(millis))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bools() {
+        let cases = [
+            ("1", Some(true)),
+            ("true", Some(true)),
+            (" On ", Some(true)),
+            ("yes", Some(true)),
+            ("0", Some(false)),
+            ("false", Some(false)),
+            ("off", Some(false)),
+            ("NO", Some(false)),
+            ("", None),
+            ("2", None),
+            ("enabled", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_bool(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn present_modes() {
+        let cases = [
+            ("fifo", Some(vk::PresentModeKHR::FIFO)),
+            ("vsync", Some(vk::PresentModeKHR::FIFO)),
+            ("VSync", Some(vk::PresentModeKHR::FIFO)),
+            ("fifo-relaxed", Some(vk::PresentModeKHR::FIFO_RELAXED)),
+            ("fifo_relaxed", Some(vk::PresentModeKHR::FIFO_RELAXED)),
+            (" mailbox ", Some(vk::PresentModeKHR::MAILBOX)),
+            ("immediate", Some(vk::PresentModeKHR::IMMEDIATE)),
+            ("0", None),
+            ("off", None),
+            ("relaxed", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_present_mode(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn image_counts() {
+        let cases = [
+            ("default", Some(SwapchainImageCount::Default)),
+            ("Double", Some(SwapchainImageCount::Double)),
+            ("triple", Some(SwapchainImageCount::Triple)),
+            ("4", Some(SwapchainImageCount::Exact(4))),
+            (" 2 ", Some(SwapchainImageCount::Exact(2))),
+            ("0", None),
+            ("off", None),
+            ("-1", None),
+            ("three", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_image_count(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn watchdogs() {
+        let cases = [
+            ("0", Some(None)),
+            ("off", Some(None)),
+            ("OFF", Some(None)),
+            ("250", Some(Some(Duration::from_millis(250)))),
+            (" 5000 ", Some(Some(Duration::from_millis(5000)))),
+            ("vsync", None),
+            ("-5", None),
+            ("1.5", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(parse_watchdog(text), expected, "{:?}", text);
+        }
+    }
+}
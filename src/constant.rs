@@ -1,7 +1,6 @@
-use std::mem::{offset_of, size_of};
+use std::mem::offset_of;
 
 use ash::vk;
-use glm::Vector3;
 
 extern crate nalgebra as glm;
 
@@ -13,8 +12,9 @@ pub mod version {
     pub const API_VERSION: u32 = vk::make_api_version(0, 1, 3, 0);
 }
 pub mod validation {
+    /// default of `config::AppConfig::validation`, `VULKY_VALIDATION` overrides it at startup
     pub const ENABLED: bool = cfg!(debug_assertions);
-    pub const LAYER_NAME: &'static str = "VK_LAYER_KHRONOS_validation";
+    pub const LAYER_NAME: &str = "VK_LAYER_KHRONOS_validation";
     pub const LAYER_NAME_BYTES: &[u8; 28] = b"VK_LAYER_KHRONOS_validation\0";
 }
 
@@ -22,10 +22,10 @@ pub mod support {
     use std::ffi::CStr;
 
     pub const EXTENSION_SUPPORT_ARRAY_BYTES: &[&[u8]] = &[ash::extensions::khr::Swapchain::name().to_bytes()];
-    pub const EXTENSION_SUPPORT_ARRAY_NAME: &[&'static CStr] = &[ash::extensions::khr::Swapchain::name()];
+    pub const EXTENSION_SUPPORT_ARRAY_NAME: &[&CStr] = &[ash::extensions::khr::Swapchain::name()];
 }
 
-pub mod Window_Info {
+pub mod window_info {
     pub const HEIGHT: u32 = 900;
     pub const WIDTH: u32 = 900;
 }
@@ -33,7 +33,7 @@ pub mod Window_Info {
 lazy_static::lazy_static! {
     pub static ref PATH_TO_PROJECT: String = {
         // Retrieve the project path at runtime and store it as a static variable.
-        format!("{}/", std::env::current_dir().unwrap().to_string_lossy().to_string())
+        format!("{}/", std::env::current_dir().unwrap().to_string_lossy())
     };
 }
 
@@ -67,7 +67,7 @@ impl Vertex {
                 binding: 0,
                 location: 1,
                 format: vk::Format::R32G32B32_SFLOAT,
-                offset: offset_of!(Vertex, color) as u32 as u32,
+                offset: offset_of!(Vertex, color) as u32,
             },
         ]
     }
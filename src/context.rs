@@ -0,0 +1,465 @@
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    ops::Deref,
+    sync::{Arc, Mutex, MutexGuard},
+    thread::{self, ThreadId},
+};
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    buffer::{begin_single_commands, MAX_FRAMES_IN_FLIGHT},
+    deletion::DeletionQueue,
+    memory::{AllocationId, BufferDesc, GpuAllocator},
+    owned::Instance,
+    sync_pool::FencePool,
+};
+
+struct DeviceInner {
+    instance: ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    device: ash::Device,
+    allocator: Mutex<GpuAllocator>,
+    /// locked before the allocator when both are needed
+    deletions: Mutex<DeletionQueue>,
+    /// one `Queue` per queue, so every clone locks the same mutex
+    queues: Mutex<HashMap<(u32, u32), Queue>>,
+    /// released after the device is destroyed
+    _instance: Option<Instance>,
+}
+
+impl Drop for DeviceInner {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = self.device.device_wait_idle();
+            let allocator = self.allocator.get_mut().unwrap_or_else(|e| e.into_inner());
+            let deletions = self.deletions.get_mut().unwrap_or_else(|e| e.into_inner());
+            deletions.flush(&self.device, allocator);
+            allocator.destroy(&self.device);
+            self.device.destroy_device(None);
+        }
+    }
+}
+
+/// A logical device that can be cloned into asset loading threads.
+/// Derefs to `ash::Device` for everything Vulkan doesn't need external synchronization for, the allocator,
+/// queues and command pools lock internally. The last clone destroys the allocator memory and the device,
+/// the instance has to outlive it.
+#[derive(Clone)]
+pub struct Device {
+    inner: Arc<DeviceInner>,
+}
+
+impl Device {
+    /// Takes ownership of `device`, created from `physical_device`.
+    ///
+    /// # Safety
+    /// `device` has to be a live device of `physical_device`, nothing else may destroy it afterwards.
+    pub unsafe fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice, device: ash::Device) -> Self {
+        Self::create(instance, physical_device, device, None)
+    }
+
+    /// Like `new`, keeps `instance` alive until the device is destroyed.
+    ///
+    /// # Safety
+    /// As for `new`, `instance` has to be the instance `physical_device` was enumerated from.
+    pub unsafe fn with_instance(instance: &Instance, physical_device: vk::PhysicalDevice, device: ash::Device) -> Self {
+        Self::create(instance, physical_device, device, Some(instance.clone()))
+    }
+
+    unsafe fn create(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: ash::Device,
+        owned_instance: Option<Instance>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(DeviceInner {
+                instance: instance.clone(),
+                physical_device,
+                allocator: Mutex::new(GpuAllocator::new(instance, physical_device)),
+                deletions: Mutex::new(DeletionQueue::new()),
+                device,
+                queues: Mutex::new(HashMap::new()),
+                _instance: owned_instance,
+            }),
+        }
+    }
+
+    pub fn raw(&self) -> &ash::Device {
+        &self.inner.device
+    }
+
+    pub fn instance(&self) -> &ash::Instance {
+        &self.inner.instance
+    }
+
+    pub fn physical_device(&self) -> vk::PhysicalDevice {
+        self.inner.physical_device
+    }
+
+    /// Locks the allocator, other threads block on it until the guard is dropped.
+    pub fn allocator(&self) -> MutexGuard<'_, GpuAllocator> {
+        self.inner.allocator.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// `GpuAllocator::create_buffer` under the allocator lock.
+    ///
+    /// # Safety
+    /// The buffer has to be destroyed with `destroy_buffer` of this device before the device goes away.
+    pub unsafe fn create_buffer(
+        &self,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+        movable: bool,
+    ) -> VkResult<(vk::Buffer, AllocationId)> {
+        self.allocator().create_buffer(
+            &self.inner.device,
+            &self.inner.instance,
+            self.inner.physical_device,
+            BufferDesc {
+                size,
+                usage,
+                properties,
+                movable,
+            },
+        )
+    }
+
+    /// # Safety
+    /// `buffer` and `id` have to come from one `create_buffer` and the GPU has to be done with the buffer.
+    pub unsafe fn destroy_buffer(&self, buffer: vk::Buffer, id: AllocationId) {
+        self.allocator().destroy_buffer(&self.inner.device, buffer, id);
+    }
+
+    /// Destroys with `destroy` once the frames in flight are done, the owned wrappers drop through it.
+    ///
+    /// # Safety
+    /// `destroy` runs on a later frame or on drop of the device, it may only destroy objects of this device.
+    pub unsafe fn defer<F>(&self, destroy: F)
+    where
+        F: FnOnce(&ash::Device, &mut GpuAllocator) + Send + 'static,
+    {
+        let mut deletions = self.inner.deletions.lock().unwrap_or_else(|e| e.into_inner());
+        deletions.defer(&self.inner.device, &mut self.allocator(), destroy);
+    }
+
+    /// Ends the frame whose submissions signal `fence`, see `DeletionQueue::end_frame`.
+    /// The fence has to stay alive until it signaled.
+    pub fn end_frame(&self, fence: vk::Fence) {
+        let mut deletions = self.inner.deletions.lock().unwrap_or_else(|e| e.into_inner());
+        deletions.end_frame(fence);
+    }
+
+    /// Destroys the resources of finished frames, once per frame.
+    ///
+    /// # Safety
+    /// The frames whose deferred deletions are collected have to be finished on the GPU.
+    pub unsafe fn collect_deletions(&self) {
+        let mut deletions = self.inner.deletions.lock().unwrap_or_else(|e| e.into_inner());
+        deletions.collect(&self.inner.device, &mut self.allocator());
+    }
+
+    /// Queue `index` of `family`, which has to be one the device was created with.
+    ///
+    /// # Safety
+    /// The device has to be created with at least `index + 1` queues of `family`.
+    pub unsafe fn queue(&self, family: u32, index: u32) -> Queue {
+        let mut queues = self.inner.queues.lock().unwrap_or_else(|e| e.into_inner());
+        queues
+            .entry((family, index))
+            .or_insert_with(|| Queue {
+                inner: Arc::new(QueueInner {
+                    device: self.inner.device.clone(),
+                    family,
+                    queue: Mutex::new(self.inner.device.get_device_queue(family, index)),
+                }),
+            })
+            .clone()
+    }
+
+    /// `vkDeviceWaitIdle` needs every queue, it waits until no thread is submitting.
+    ///
+    /// # Safety
+    /// Nothing may be recording into a pool of the device that gets submitted while waiting.
+    pub unsafe fn wait_idle(&self) -> VkResult<()> {
+        let queues = self.inner.queues.lock().unwrap_or_else(|e| e.into_inner());
+        let _guards: Vec<MutexGuard<'_, vk::Queue>> = queues.values().map(|queue| queue.lock()).collect();
+        self.inner.device.device_wait_idle()
+    }
+
+    /// # Safety
+    /// `family` has to be a queue family the device was created with.
+    pub unsafe fn create_command_pool(&self, family: u32) -> VkResult<CommandPool> {
+        let pool_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER | vk::CommandPoolCreateFlags::TRANSIENT,
+            queue_family_index: family,
+            ..Default::default()
+        };
+        let pool = self.inner.device.create_command_pool(&pool_info, None)?;
+        Ok(CommandPool {
+            device: self.clone(),
+            family,
+            pool: Mutex::new(pool),
+            fences: Mutex::new(FencePool::new("submit_and_wait fence", None)),
+        })
+    }
+
+    pub fn create_command_pool_per_thread(&self, family: u32) -> CommandPoolPerThread {
+        CommandPoolPerThread {
+            device: self.clone(),
+            family,
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl Deref for Device {
+    type Target = ash::Device;
+
+    fn deref(&self) -> &ash::Device {
+        &self.inner.device
+    }
+}
+
+struct QueueInner {
+    device: ash::Device,
+    family: u32,
+    queue: Mutex<vk::Queue>,
+}
+
+/// A queue shared between threads, submits and presents lock it.
+#[derive(Clone)]
+pub struct Queue {
+    inner: Arc<QueueInner>,
+}
+
+impl Queue {
+    pub fn family(&self) -> u32 {
+        self.inner.family
+    }
+
+    /// Locks the queue for calls that aren't wrapped, like sparse binding.
+    pub fn lock(&self) -> MutexGuard<'_, vk::Queue> {
+        self.inner.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// # Safety
+    /// `submits` have to be valid for this queue and `fence` unsignaled, or null.
+    pub unsafe fn submit(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) -> VkResult<()> {
+        let queue = self.lock();
+        self.inner.device.queue_submit(*queue, submits, fence)
+    }
+
+    /// # Safety
+    /// `swapchain_loader` has to belong to the device, `present_info` has to be valid for this queue.
+    pub unsafe fn present(
+        &self,
+        swapchain_loader: &ash::extensions::khr::Swapchain,
+        present_info: &vk::PresentInfoKHR,
+    ) -> VkResult<bool> {
+        let queue = self.lock();
+        swapchain_loader.queue_present(*queue, present_info)
+    }
+
+    /// # Safety
+    /// The raw queue may not be used outside of `lock` while waiting.
+    pub unsafe fn wait_idle(&self) -> VkResult<()> {
+        let queue = self.lock();
+        self.inner.device.queue_wait_idle(*queue)
+    }
+}
+
+/// A command pool shared between threads. Vulkan needs the pool locked while any of its command buffers
+/// is allocated, recorded, reset or freed, so long recordings on several threads want a `CommandPoolPerThread`.
+pub struct CommandPool {
+    device: Device,
+    family: u32,
+    pool: Mutex<vk::CommandPool>,
+    /// recycled by `submit_and_wait`, locked after the pool
+    fences: Mutex<FencePool>,
+}
+
+impl CommandPool {
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    /// Keep the guard while recording into command buffers of the pool.
+    pub fn lock(&self) -> MutexGuard<'_, vk::CommandPool> {
+        self.pool.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records `record` into a one time command buffer, submits it to `queue` and waits for it.
+    /// The pool stays locked while recording, the queue only while submitting.
+    ///
+    /// # Safety
+    /// `queue` has to be of the family of the pool and `record` may only record valid commands.
+    pub unsafe fn submit_and_wait<F: FnOnce(vk::CommandBuffer)>(&self, queue: &Queue, record: F) -> VkResult<()> {
+        let device = self.device.raw();
+        let fence = self.fences().acquire(device)?;
+        let command_buffer = match self.record_and_submit(queue, fence, record) {
+            Ok(command_buffer) => command_buffer,
+            Err(e) => {
+                self.fences().release(device, fence)?;
+                return Err(e);
+            }
+        };
+        let waited = device.wait_for_fences(&[fence], true, u64::MAX);
+        let pool = self.lock();
+        device.free_command_buffers(*pool, &[command_buffer]);
+        drop(pool);
+        // a fence whose wait failed can still be pending, it stays in use until the pool is destroyed
+        waited.and_then(|_| self.fences().release(device, fence))
+    }
+
+    fn fences(&self) -> MutexGuard<'_, FencePool> {
+        self.fences.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    unsafe fn record_and_submit<F: FnOnce(vk::CommandBuffer)>(
+        &self,
+        queue: &Queue,
+        fence: vk::Fence,
+        record: F,
+    ) -> VkResult<vk::CommandBuffer> {
+        let device = self.device.raw();
+        let pool = self.lock();
+        let command_buffer = begin_single_commands(device, *pool)?;
+        record(command_buffer);
+        let submitted = device.end_command_buffer(command_buffer).and_then(|_| {
+            let submit_info = vk::SubmitInfo {
+                command_buffer_count: 1,
+                p_command_buffers: &command_buffer,
+                ..Default::default()
+            };
+            queue.submit(&[submit_info], fence)
+        });
+        if let Err(e) = submitted {
+            device.free_command_buffers(*pool, &[command_buffer]);
+            return Err(e);
+        }
+        Ok(command_buffer)
+    }
+}
+
+impl Drop for CommandPool {
+    fn drop(&mut self) {
+        unsafe {
+            let pool = *self.pool.get_mut().unwrap_or_else(|e| e.into_inner());
+            self.device.raw().destroy_command_pool(pool, None);
+            let fences = self.fences.get_mut().unwrap_or_else(|e| e.into_inner());
+            fences.destroy(self.device.raw());
+        }
+    }
+}
+
+/// A pool of one thread for one frame in flight, with the command buffers it handed out since its last reset.
+struct ThreadPool {
+    pool: vk::CommandPool,
+    /// reset together with the pool and handed out again in order
+    primary: Vec<vk::CommandBuffer>,
+    secondary: Vec<vk::CommandBuffer>,
+    used_primary: usize,
+    used_secondary: usize,
+}
+
+/// Command pools of one queue family per recording thread and frame in flight, so worker threads record
+/// without locking a shared pool. Pools are created on the first `allocate` of a thread and frame.
+/// `begin_frame` resets every pool of the frame, its command buffers are handed out again instead of freed.
+pub struct CommandPoolPerThread {
+    device: Device,
+    family: u32,
+    pools: Mutex<HashMap<(ThreadId, usize), ThreadPool>>,
+}
+
+impl CommandPoolPerThread {
+    pub fn family(&self) -> u32 {
+        self.family
+    }
+
+    fn pools(&self) -> MutexGuard<'_, HashMap<(ThreadId, usize), ThreadPool>> {
+        self.pools.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Resets the pools of `frame` on every thread, once the submissions of the frame finished and before any
+    /// thread allocates for it again.
+    ///
+    /// # Safety
+    /// The submissions of `frame` have to be finished, the command buffers of its pools are reset.
+    pub unsafe fn begin_frame(&self, frame: usize) -> VkResult<()> {
+        let device = self.device.raw();
+        for ((_, pool_frame), pool) in self.pools().iter_mut() {
+            if *pool_frame != frame {
+                continue;
+            }
+            device.reset_command_pool(pool.pool, vk::CommandPoolResetFlags::empty())?;
+            pool.used_primary = 0;
+            pool.used_secondary = 0;
+        }
+        Ok(())
+    }
+
+    /// A command buffer in the initial state from the pool of the calling thread for `frame`.
+    /// Only record it on this thread, it stays valid until the next `begin_frame` of the frame.
+    ///
+    /// # Safety
+    /// The command buffer may only be submitted until the next `begin_frame` of `frame`.
+    pub unsafe fn allocate(&self, frame: usize, level: vk::CommandBufferLevel) -> VkResult<vk::CommandBuffer> {
+        assert!(
+            frame < MAX_FRAMES_IN_FLIGHT as usize,
+            "Frame {} is out of the frames in flight",
+            frame
+        );
+        let device = self.device.raw();
+        let mut pools = self.pools();
+        let pool = match pools.entry((thread::current().id(), frame)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let pool_info = vk::CommandPoolCreateInfo {
+                    flags: vk::CommandPoolCreateFlags::TRANSIENT,
+                    queue_family_index: self.family,
+                    ..Default::default()
+                };
+                entry.insert(ThreadPool {
+                    pool: device.create_command_pool(&pool_info, None)?,
+                    primary: vec![],
+                    secondary: vec![],
+                    used_primary: 0,
+                    used_secondary: 0,
+                })
+            }
+        };
+        let (buffers, used) = match level {
+            vk::CommandBufferLevel::SECONDARY => (&mut pool.secondary, &mut pool.used_secondary),
+            _ => (&mut pool.primary, &mut pool.used_primary),
+        };
+        if *used == buffers.len() {
+            let alloc_info = vk::CommandBufferAllocateInfo {
+                command_pool: pool.pool,
+                level,
+                command_buffer_count: 1,
+                ..Default::default()
+            };
+            buffers.push(device.allocate_command_buffers(&alloc_info)?[0]);
+        }
+        *used += 1;
+        Ok(buffers[*used - 1])
+    }
+
+    /// Threads with a pool, counted once however many frames they recorded.
+    pub fn thread_count(&self) -> usize {
+        let threads: HashSet<ThreadId> = self.pools().keys().map(|(thread, _)| *thread).collect();
+        threads.len()
+    }
+}
+
+impl Drop for CommandPoolPerThread {
+    fn drop(&mut self) {
+        let pools = self.pools.get_mut().unwrap_or_else(|e| e.into_inner());
+        for (_, pool) in pools.drain() {
+            unsafe { self.device.raw().destroy_command_pool(pool.pool, None) };
+        }
+    }
+}
@@ -0,0 +1,661 @@
+use std::{
+    mem::{offset_of, size_of},
+    ptr,
+    sync::Arc,
+};
+
+use crate::error::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+use nalgebra as glm;
+
+use crate::{
+    animation::AnimationSource,
+    buffer::{self, MAX_FRAMES_IN_FLIGHT},
+    gltf_import::{GltfScene, PbrMaterialDesc, ShadingModel},
+    material::{Material, MaterialInstance, MaterialLayout},
+    mesh::{GpuMesh, Mesh, MeshVertex, SkinVertex},
+    pbr::{self, PbrMaterials, BASE_COLOR_SLOT, EMISSIVE_SLOT, METALLIC_ROUGHNESS_SLOT, NORMAL_SLOT, OCCLUSION_SLOT},
+    pipeline::PipelineBuilder,
+    scene::Transform,
+    texture::Texture,
+    utility,
+};
+
+const CROWD_VERTEX_SHADER: &str = "shaders/spv/crowd_vert.spv";
+/// shaders/pbr.frag with its material set moved behind the crowd set
+const CROWD_FRAGMENT_SHADER: &str = "shaders/spv/pbr_crowd_frag.spv";
+
+/// Vertex buffer bindings of the crowd pipeline.
+const MESH_BINDING: u32 = 0;
+const SKIN_BINDING: u32 = 1;
+const INSTANCE_BINDING: u32 = 2;
+
+/// The joints of a glTF skin and the node hierarchy above them.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    /// node indices in skin order
+    joints: Vec<usize>,
+    inverse_bind_matrices: Vec<glm::Matrix4<f32>>,
+    parents: Vec<Option<usize>>,
+    /// every node, parents before their children
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn from_gltf(gltf: &GltfScene, skin: usize) -> Result<Skeleton> {
+        let skin = gltf
+            .skins
+            .get(skin)
+            .ok_or_else(|| Error::msg(format!("The scene has no skin {}", skin)))?;
+        if skin.joints.len() > u16::MAX as usize + 1 {
+            return Err(Error::msg("A skin can have at most 65536 joints"));
+        }
+
+        let mut order: Vec<usize> = (0..gltf.nodes.len())
+            .filter(|node| gltf.nodes[*node].parent.is_none())
+            .collect();
+        let mut next = 0;
+        while next < order.len() {
+            order.extend(gltf.nodes[order[next]].children.iter().copied());
+            next += 1;
+        }
+
+        Ok(Skeleton {
+            joints: skin.joints.clone(),
+            inverse_bind_matrices: skin.inverse_bind_matrices.clone(),
+            parents: gltf.nodes.iter().map(|node| node.parent).collect(),
+            order,
+        })
+    }
+
+    pub fn joint_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    /// Joint matrices of `pose`, the local transforms of every node. They move skinned vertices from the space the
+    /// skin was bound in to the space above the roots of the hierarchy, which the crowd instance places.
+    pub fn skinning_matrices(&self, pose: &[Transform]) -> Vec<glm::Matrix4<f32>> {
+        let mut world = vec![glm::Matrix4::identity(); self.parents.len()];
+        for node in self.order.iter().copied() {
+            let local = pose.get(node).map_or(glm::Matrix4::identity(), Transform::matrix);
+            world[node] = match self.parents[node] {
+                Some(parent) => world[parent] * local,
+                None => local,
+            };
+        }
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(joint, inverse_bind)| world[*joint] * inverse_bind)
+            .collect()
+    }
+}
+
+/// A clip sampled into `BakedAnimations`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BakedClip {
+    pub name: String,
+    pub first_frame: u32,
+    /// the last frame is the end of the clip
+    pub frame_count: u32,
+    pub looping: bool,
+}
+
+/// Skinning matrices of clips sampled at a fixed rate, so crowds can play them without evaluating animations.
+/// The vertex shader blends the two frames around an instance's time.
+#[derive(Clone, Debug)]
+pub struct BakedAnimations {
+    pub frames_per_second: f32,
+    joint_count: usize,
+    clips: Vec<BakedClip>,
+    /// the rows of a 3x4 matrix per joint and frame
+    rows: Vec<[f32; 4]>,
+}
+
+impl BakedAnimations {
+    pub fn new(skeleton: &Skeleton, frames_per_second: f32) -> Self {
+        Self {
+            frames_per_second: frames_per_second.max(1.0),
+            joint_count: skeleton.joint_count(),
+            clips: vec![],
+            rows: vec![],
+        }
+    }
+
+    /// Samples `clip` from its start to its end on top of `rest_pose`,
+    /// returns the index crowd instances play it with.
+    pub fn add_clip(
+        &mut self,
+        skeleton: &Skeleton,
+        rest_pose: &[Transform],
+        name: &str,
+        clip: &mut dyn AnimationSource,
+        looping: bool,
+    ) -> Result<u32> {
+        if skeleton.joint_count() != self.joint_count {
+            return Err(Error::msg("The clip is baked for another skeleton"));
+        }
+        let duration = clip.duration().max(0.0);
+        let frame_count = (duration * self.frames_per_second).ceil() as u32 + 1;
+        let first_frame = self.frame_count() as u32;
+
+        let mut pose = rest_pose.to_vec();
+        for frame in 0..frame_count {
+            let time = (frame as f32 / self.frames_per_second).min(duration);
+            pose.copy_from_slice(rest_pose);
+            clip.sample_pose(time, &mut pose)?;
+            for matrix in skeleton.skinning_matrices(&pose) {
+                for row in 0..3 {
+                    let row = matrix.row(row);
+                    self.rows.push([row[0], row[1], row[2], row[3]]);
+                }
+            }
+        }
+
+        self.clips.push(BakedClip {
+            name: name.to_owned(),
+            first_frame,
+            frame_count,
+            looping,
+        });
+        Ok(self.clips.len() as u32 - 1)
+    }
+
+    pub fn clips(&self) -> &[BakedClip] {
+        &self.clips
+    }
+
+    pub fn clip_index(&self, name: &str) -> Option<u32> {
+        self.clips.iter().position(|clip| clip.name == name).map(|index| index as u32)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.rows.len() / (self.joint_count * 3).max(1)
+    }
+
+    /// Size of the joint buffer.
+    pub fn size_bytes(&self) -> usize {
+        size_of::<[f32; 4]>() * self.rows.len()
+    }
+}
+
+/// `Clip` of shaders/crowd.vert.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GpuClip {
+    first_frame: u32,
+    frame_count: u32,
+    frames_per_second: f32,
+    looping: u32,
+}
+
+/// One character of a crowd, read per instance by shaders/crowd.vert.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CrowdInstance {
+    pub model: glm::Matrix4<f32>,
+    /// index returned by `BakedAnimations::add_clip`
+    pub clip: u32,
+    /// seconds into the clip, wrapped for looping clips and clamped for the others
+    pub time: f32,
+}
+
+impl CrowdInstance {
+    pub fn new(model: glm::Matrix4<f32>, clip: u32, time: f32) -> Self {
+        Self { model, clip, time }
+    }
+
+    pub const fn binding_description(binding: u32) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding,
+            stride: size_of::<CrowdInstance>() as u32,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }
+    }
+
+    /// The model matrix takes four locations from `location` on, the clip and time follow.
+    pub fn input_attribute_descriptions(binding: u32, location: u32) -> Vec<vk::VertexInputAttributeDescription> {
+        let column = size_of::<glm::Vector4<f32>>() as u32;
+        let mut attributes: Vec<_> = (0..4)
+            .map(|index| vk::VertexInputAttributeDescription {
+                location: location + index,
+                binding,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(CrowdInstance, model) as u32 + index * column,
+            })
+            .collect();
+        attributes.push(vk::VertexInputAttributeDescription {
+            location: location + 4,
+            binding,
+            format: vk::Format::R32_UINT,
+            offset: offset_of!(CrowdInstance, clip) as u32,
+        });
+        attributes.push(vk::VertexInputAttributeDescription {
+            location: location + 5,
+            binding,
+            format: vk::Format::R32_SFLOAT,
+            offset: offset_of!(CrowdInstance, time) as u32,
+        });
+        attributes
+    }
+}
+
+/// The joints and weights of a mesh on the GPU, bound next to its `GpuMesh`.
+pub struct GpuSkin {
+    pub buffer: vk::Buffer,
+    pub memory: vk::DeviceMemory,
+}
+
+impl GpuSkin {
+    /// # Safety
+    /// `transfer_queue` has to be of the family of `transfer_pool`, it's waited on for the copy.
+    pub unsafe fn upload(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        transfer_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        mesh: &Mesh,
+    ) -> Result<GpuSkin> {
+        if mesh.skin.len() != mesh.vertices.len() {
+            return Err(Error::msg("The mesh isn't skinned"));
+        }
+        let (buffer, memory) = buffer::create_device_local_buffer(
+            device,
+            instance,
+            physical_device,
+            transfer_pool,
+            transfer_queue,
+            &mesh.skin,
+            BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        Ok(GpuSkin { buffer, memory })
+    }
+
+    /// # Safety
+    /// No pending draw may still read the skin.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_buffer(self.buffer, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// `BakedAnimations` on the GPU with the descriptor set crowds bind at set 1.
+pub struct CrowdAnimations {
+    joint_count: u32,
+    joint_buffer: (vk::Buffer, vk::DeviceMemory),
+    clip_buffer: (vk::Buffer, vk::DeviceMemory),
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: vk::DescriptorSet,
+}
+
+impl CrowdAnimations {
+    /// # Safety
+    /// As for `GpuSkin::upload`, the animations have to be destroyed before `renderer`.
+    pub unsafe fn upload(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        transfer_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+        renderer: &CrowdRenderer,
+        baked: &BakedAnimations,
+    ) -> Result<CrowdAnimations> {
+        if baked.clips.is_empty() {
+            return Err(Error::msg("No clips were baked"));
+        }
+        let clips: Vec<GpuClip> = baked
+            .clips
+            .iter()
+            .map(|clip| GpuClip {
+                first_frame: clip.first_frame,
+                frame_count: clip.frame_count,
+                frames_per_second: baked.frames_per_second,
+                looping: clip.looping as u32,
+            })
+            .collect();
+        let joint_buffer = buffer::create_device_local_buffer(
+            device,
+            instance,
+            physical_device,
+            transfer_pool,
+            transfer_queue,
+            &baked.rows,
+            BufferUsageFlags::STORAGE_BUFFER,
+        )?;
+        let clip_buffer = buffer::create_device_local_buffer(
+            device,
+            instance,
+            physical_device,
+            transfer_pool,
+            transfer_queue,
+            &clips,
+            BufferUsageFlags::STORAGE_BUFFER,
+        )?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 2,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::empty(),
+            max_sets: 1,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+        };
+        let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &renderer.set_layout,
+        };
+        let descriptor_set = device.allocate_descriptor_sets(&alloc_info)?[0];
+
+        let buffer_infos = [joint_buffer.0, clip_buffer.0].map(|buffer| vk::DescriptorBufferInfo {
+            buffer,
+            offset: 0,
+            range: vk::WHOLE_SIZE,
+        });
+        let writes: Vec<vk::WriteDescriptorSet> = buffer_infos
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer_info)| vk::WriteDescriptorSet {
+                s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                p_next: ptr::null(),
+                dst_set: descriptor_set,
+                dst_binding: binding as u32,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_image_info: ptr::null(),
+                p_buffer_info: buffer_info,
+                p_texel_buffer_view: ptr::null(),
+            })
+            .collect();
+        device.update_descriptor_sets(&writes, &[]);
+
+        Ok(CrowdAnimations {
+            joint_count: baked.joint_count as u32,
+            joint_buffer,
+            clip_buffer,
+            descriptor_pool,
+            descriptor_set,
+        })
+    }
+
+    /// # Safety
+    /// No pending draw may still bind the joint or clip buffers.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        for (buffer, memory) in [self.joint_buffer, self.clip_buffer] {
+            device.destroy_buffer(buffer, None);
+            device.free_memory(memory, None);
+        }
+    }
+}
+
+/// Instances of one skinned mesh, each playing its own clip. The instance data is copied into a buffer per frame
+/// in flight by `flush`.
+pub struct Crowd {
+    pub instances: Vec<CrowdInstance>,
+    capacity: usize,
+    /// buffer, memory and mapping per frame in flight
+    buffers: Vec<(vk::Buffer, vk::DeviceMemory, *mut CrowdInstance)>,
+    /// instances copied for each frame
+    counts: [u32; MAX_FRAMES_IN_FLIGHT as usize],
+}
+
+// the mappings are only written by `flush`, one frame at a time
+unsafe impl Send for Crowd {}
+unsafe impl Sync for Crowd {}
+
+impl Crowd {
+    /// Room for `capacity` drawn instances, the ones past it are skipped.
+    ///
+    /// # Safety
+    /// `device` has to be created from `physical_device`, the crowd goes with `destroy`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        capacity: usize,
+    ) -> VkResult<Crowd> {
+        let capacity = capacity.max(1);
+        let size = (size_of::<CrowdInstance>() * capacity) as u64;
+        let mut buffers = vec![];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, memory) = buffer::create_buffer(
+                device,
+                instance,
+                physical_device,
+                size,
+                BufferUsageFlags::VERTEX_BUFFER,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped = device.map_memory(memory, 0, size, MemoryMapFlags::empty())? as *mut CrowdInstance;
+            buffers.push((buffer, memory, mapped));
+        }
+        Ok(Crowd {
+            instances: vec![],
+            capacity,
+            buffers,
+            counts: [0; MAX_FRAMES_IN_FLIGHT as usize],
+        })
+    }
+
+    /// Moves every instance `delta_seconds` further into its clip.
+    pub fn advance(&mut self, delta_seconds: f32) {
+        for instance in self.instances.iter_mut() {
+            instance.time += delta_seconds;
+        }
+    }
+
+    /// Copies the instances for `current_frame`, whose fence has to be waited on.
+    ///
+    /// # Safety
+    /// A crowd drawn in `current_frame` before its fence was waited on reads torn instances.
+    pub unsafe fn flush(&mut self, current_frame: usize) {
+        let count = self.instances.len().min(self.capacity);
+        let (_, _, mapped) = self.buffers[current_frame];
+        mapped.copy_from_nonoverlapping(self.instances.as_ptr(), count);
+        self.counts[current_frame] = count as u32;
+    }
+
+    /// # Safety
+    /// The frames in flight that drew the crowd have to be finished.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for (buffer, memory, _) in self.buffers.iter() {
+            device.unmap_memory(*memory);
+            device.destroy_buffer(*buffer, None);
+            device.free_memory(*memory, None);
+        }
+    }
+}
+
+/// What `CrowdRenderer::record` draws, `skin` belongs to `mesh`.
+#[derive(Clone, Copy)]
+pub struct CrowdDraw<'a> {
+    pub animations: &'a CrowdAnimations,
+    pub crowd: &'a Crowd,
+    pub mesh: &'a GpuMesh,
+    pub skin: &'a GpuSkin,
+    pub material: &'a MaterialInstance,
+}
+
+/// Draws crowds with instanced skinning and the metallic roughness shading of `PbrMaterials`.
+/// Set 0 is the `FrameData` set, set 1 the `CrowdAnimations` and set 2 the material.
+pub struct CrowdRenderer {
+    material: Arc<Material>,
+    set_layout: vk::DescriptorSetLayout,
+}
+
+impl CrowdRenderer {
+    /// # Safety
+    /// `render_pass` and `frame_set_layout` have to outlive the renderer, `pbr` has to be of the same device.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        frame_set_layout: vk::DescriptorSetLayout,
+        pbr: &PbrMaterials,
+    ) -> Result<CrowdRenderer> {
+        let bindings = [0, 1].map(|binding| vk::DescriptorSetLayoutBinding {
+            binding,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            p_immutable_samplers: ptr::null(),
+        });
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+        };
+        let set_layout = device.create_descriptor_set_layout(&set_layout_info, None)?;
+
+        let mut attributes = MeshVertex::get_input_attribute_description().to_vec();
+        attributes.extend(SkinVertex::input_attribute_descriptions(SKIN_BINDING, 7));
+        attributes.extend(CrowdInstance::input_attribute_descriptions(INSTANCE_BINDING, 9));
+        let pipeline = PipelineBuilder::new(CROWD_VERTEX_SHADER, CROWD_FRAGMENT_SHADER)
+            .vertex_input(
+                &[
+                    MeshVertex::get_binding_description(),
+                    SkinVertex::binding_description(SKIN_BINDING),
+                    CrowdInstance::binding_description(INSTANCE_BINDING),
+                ],
+                &attributes,
+            )
+            .cull_mode(vk::CullModeFlags::BACK, vk::FrontFace::COUNTER_CLOCKWISE)
+            .depth_test(true, true);
+
+        let white = pbr.white_texture();
+        let textures = [
+            (BASE_COLOR_SLOT, white),
+            (METALLIC_ROUGHNESS_SLOT, white),
+            (NORMAL_SLOT, pbr.flat_normal_texture()),
+            (OCCLUSION_SLOT, white),
+            (EMISSIVE_SLOT, white),
+        ];
+        let material = Material::new(
+            device,
+            instance,
+            physical_device,
+            render_pass,
+            pipeline,
+            MaterialLayout {
+                shared_set_layouts: &[frame_set_layout, set_layout],
+                parameters: pbr::parameter_layout(),
+                textures: &textures,
+            },
+        );
+        let mut material = match material {
+            Ok(material) => material,
+            Err(e) => {
+                device.destroy_descriptor_set_layout(set_layout, None);
+                return Err(e);
+            }
+        };
+        pbr::set_defaults(&mut material, false)?;
+
+        Ok(CrowdRenderer {
+            material: Arc::new(material),
+            set_layout,
+        })
+    }
+
+    /// Instance with the factors and textures of a metallic roughness glTF material,
+    /// `textures` are the ones returned by `pbr::upload_gltf_textures`.
+    ///
+    /// # Safety
+    /// `textures` have to outlive the instance.
+    pub unsafe fn create_instance(
+        &self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        desc: &PbrMaterialDesc,
+        textures: &[Texture],
+    ) -> Result<MaterialInstance> {
+        if desc.shading != ShadingModel::Pbr {
+            return Err(Error::msg(format!("Crowds can't be drawn with {:?} shading", desc.shading)));
+        }
+        PbrMaterials::create_instance_of(device, instance, physical_device, self.material.clone(), desc, textures)
+    }
+
+    /// Draws every flushed instance of `draw.crowd` inside the current render pass.
+    /// `draw.material` has to be created by `create_instance` and flushed for `current_frame`.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside a render pass compatible with the one of `new`.
+    pub unsafe fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        frame_set: vk::DescriptorSet,
+        draw: &CrowdDraw,
+    ) {
+        let CrowdDraw {
+            animations,
+            crowd,
+            mesh,
+            skin,
+            material,
+        } = *draw;
+        let instance_count = crowd.counts[current_frame];
+        if instance_count == 0 {
+            return;
+        }
+        let layout = self.material.pipeline_layout;
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.material.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            0,
+            &[frame_set, animations.descriptor_set, material.descriptor_set(current_frame)],
+            &[],
+        );
+        device.cmd_push_constants(
+            command_buffer,
+            layout,
+            vk::ShaderStageFlags::VERTEX,
+            0,
+            utility::as_bytes(&animations.joint_count),
+        );
+        device.cmd_bind_vertex_buffers(
+            command_buffer,
+            MESH_BINDING,
+            &[mesh.vertex_buffer, skin.buffer, crowd.buffers[current_frame].0],
+            &[0, 0, 0],
+        );
+        device.cmd_bind_index_buffer(command_buffer, mesh.index_buffer, 0, vk::IndexType::UINT32);
+        for submesh in mesh.submeshes.iter() {
+            device.cmd_draw_indexed(
+                command_buffer,
+                submesh.index_count,
+                instance_count,
+                submesh.index_offset,
+                0,
+                0,
+            );
+        }
+    }
+
+    /// # Safety
+    /// The instances and animations of the renderer have to be destroyed first.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        self.material.destroy(device);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
@@ -0,0 +1,453 @@
+use std::{
+    borrow::Cow,
+    ffi::{c_void, CStr, CString},
+    fmt, ptr,
+    sync::Mutex,
+};
+
+use ash::vk::{self, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT};
+
+/// Log target of every message the validation layers and drivers report.
+pub const LOG_TARGET: &str = "vulkan";
+/// Log target of `debugPrintfEXT` output from shaders, see `ShaderPrintf`.
+pub const PRINTF_LOG_TARGET: &str = "shader";
+
+/// Log level a debug utils message is reported with.
+pub fn log_level(severity: DebugUtilsMessageSeverityFlagsEXT) -> log::Level {
+    if severity.contains(DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::Level::Error
+    } else if severity.contains(DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::Level::Warn
+    } else if severity.contains(DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::Level::Info
+    } else {
+        log::Level::Trace
+    }
+}
+
+/// Name of the message type, "general", "validation" or "performance".
+pub fn message_type_name(message_type: DebugUtilsMessageTypeFlagsEXT) -> &'static str {
+    if message_type.contains(DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+        "validation"
+    } else if message_type.contains(DebugUtilsMessageTypeFlagsEXT::PERFORMANCE) {
+        "performance"
+    } else {
+        "general"
+    }
+}
+
+unsafe fn lossy(ptr: *const std::os::raw::c_char) -> Cow<'static, str> {
+    if ptr.is_null() {
+        Cow::Borrowed("")
+    } else {
+        Cow::Owned(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+}
+
+/// A message the callback drops without logging it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Suppression {
+    /// `messageIdNumber`, the hash the validation layers print next to the VUID
+    Id(i32),
+    /// `pMessageIdName`, a VUID like "VUID-vkCmdDraw-None-02859" or a layer specific name
+    Name(String),
+    /// any message containing the text
+    Substring(String),
+}
+
+impl Suppression {
+    fn matches(&self, id: i32, id_name: &str, message: &str) -> bool {
+        match self {
+            Suppression::Id(suppressed) => *suppressed == id,
+            Suppression::Name(name) => name == id_name,
+            Suppression::Substring(text) => message.contains(text.as_str()),
+        }
+    }
+
+    /// Numbers, decimal or 0x hex, become ids, "VUID-" and "UNASSIGNED-" prefixes names, anything else substrings.
+    pub fn parse(text: &str) -> Suppression {
+        let parsed = match text.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok().map(|id| id as i32),
+            None => text.parse::<i32>().ok(),
+        };
+        match parsed {
+            Some(id) => Suppression::Id(id),
+            None if text.starts_with("VUID-") || text.starts_with("UNASSIGNED-") => Suppression::Name(text.to_owned()),
+            None => Suppression::Substring(text.to_owned()),
+        }
+    }
+}
+
+impl fmt::Display for Suppression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Suppression::Id(id) => write!(f, "id {:#x}", id),
+            Suppression::Name(name) => write!(f, "{}", name),
+            Suppression::Substring(text) => write!(f, "{:?}", text),
+        }
+    }
+}
+
+/// Suppressions with the number of messages each dropped, shared by every messenger of the process.
+static SUPPRESSIONS: Mutex<Vec<(Suppression, u64)>> = Mutex::new(Vec::new());
+
+/// Drops matching messages from now on, registering the same suppression twice has no effect.
+pub fn suppress(suppression: Suppression) {
+    let mut suppressions = SUPPRESSIONS.lock().unwrap();
+    if !suppressions.iter().any(|(existing, _)| *existing == suppression) {
+        suppressions.push((suppression, 0));
+    }
+}
+
+pub fn unsuppress(suppression: &Suppression) {
+    SUPPRESSIONS.lock().unwrap().retain(|(existing, _)| existing != suppression);
+}
+
+/// Every suppression with how many messages it dropped so far.
+pub fn suppressed_counts() -> Vec<(Suppression, u64)> {
+    SUPPRESSIONS.lock().unwrap().clone()
+}
+
+/// Logs how often each suppression fired, at info level so the dropped messages stay noticeable.
+pub fn log_suppression_summary() {
+    let counts = suppressed_counts();
+    let total: u64 = counts.iter().map(|(_, count)| count).sum();
+    if total == 0 {
+        return;
+    }
+    log::info!(target: LOG_TARGET, "{} messages were suppressed", total);
+    for (suppression, count) in counts.iter().filter(|(_, count)| *count > 0) {
+        log::info!(target: LOG_TARGET, "  {:>6} x {}", count, suppression);
+    }
+}
+
+/// Counts the message for the first matching suppression, false when none matches.
+fn is_suppressed(id: i32, id_name: &str, message: &str) -> bool {
+    let mut suppressions = SUPPRESSIONS.lock().unwrap();
+    match suppressions
+        .iter_mut()
+        .find(|(suppression, _)| suppression.matches(id, id_name, message))
+    {
+        Some((_, count)) => {
+            *count += 1;
+            true
+        }
+        None => false,
+    }
+}
+
+/// The printed text of a debug printf message, None for every other message.
+/// Older layers put the command buffer and message id in front of the text, separated by " | ".
+pub fn shader_printf_text<'a>(id_name: &str, message: &'a str) -> Option<&'a str> {
+    if !id_name.contains("DEBUG-PRINTF") {
+        return None;
+    }
+    Some(message.rsplit(" | ").next().unwrap_or(message).trim_end())
+}
+
+/// Forwards debug utils messages to the `log` facade under `LOG_TARGET`, except the suppressed ones.
+/// Shader printf output goes to `PRINTF_LOG_TARGET` at info level instead.
+///
+/// # Safety
+/// Only the loader may call it, with `p_callback_data` pointing to valid callback data.
+pub unsafe extern "system" fn debug_callback(
+    message_severity: DebugUtilsMessageSeverityFlagsEXT,
+    message_type: DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    _p_user_data: *mut c_void,
+) -> vk::Bool32 {
+    if p_callback_data.is_null() {
+        return vk::FALSE;
+    }
+    let data = &*p_callback_data;
+    let id_name = lossy(data.p_message_id_name);
+    let message = lossy(data.p_message);
+    // counted even when the level is filtered, so the summary is the same for every log setup
+    if let Some(text) = shader_printf_text(&id_name, &message) {
+        if !is_suppressed(data.message_id_number, &id_name, &message) {
+            log::info!(target: PRINTF_LOG_TARGET, "{}", text);
+        }
+        return vk::FALSE;
+    }
+    let level = log_level(message_severity);
+    if is_suppressed(data.message_id_number, &id_name, &message) || !log::log_enabled!(target: LOG_TARGET, level) {
+        return vk::FALSE;
+    }
+    log::log!(
+        target: LOG_TARGET,
+        level,
+        "[{}] {} ({:#x}): {}",
+        message_type_name(message_type),
+        id_name,
+        data.message_id_number,
+        message
+    );
+
+    // returning true would abort the call that triggered the message
+    vk::FALSE
+}
+
+/// Which debug utils messages the messenger asks for, messages it filters out never reach the callback.
+/// Built with the setters or from the environment:
+/// `VULKY_DEBUG_SEVERITY=error,warning,info,verbose` and `VULKY_DEBUG_TYPES=general,validation,performance`.
+/// `VULKY_DEBUG_SUPPRESS` takes a comma separated list for `Suppression::parse`,
+/// `VULKY_SHADER_PRINTF=1` turns on `ShaderPrintf` and `VULKY_SHADER_PRINTF_BUFFER_SIZE` sets its buffer size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DebugConfig {
+    pub severities: DebugUtilsMessageSeverityFlagsEXT,
+    pub types: DebugUtilsMessageTypeFlagsEXT,
+    /// drops INFO and VERBOSE in release builds, even when `severities` asks for them
+    pub suppress_verbose_in_release: bool,
+    pub shader_printf: Option<ShaderPrintf>,
+}
+
+/// The validation layer's debug printf, `debugPrintfEXT` calls in shaders (GL_EXT_debug_printf) are
+/// logged under `PRINTF_LOG_TARGET`. It replaces GPU assisted validation while it is on and slows down
+/// every draw, so it is meant for a debugging session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShaderPrintf {
+    /// bytes per draw or dispatch the layer keeps for the output, the rest of the prints are lost
+    pub buffer_size: u32,
+    /// the layer adds the shader stage and the command of every print
+    pub verbose: bool,
+}
+
+impl Default for ShaderPrintf {
+    fn default() -> Self {
+        Self {
+            buffer_size: 1024,
+            verbose: false,
+        }
+    }
+}
+
+impl ShaderPrintf {
+    /// Sets the layer settings through their environment variables, which have to exist before the instance.
+    /// Variables that are already set win, so they can still be changed from outside.
+    pub fn apply_layer_settings(&self) {
+        let settings = [
+            ("VK_LAYER_PRINTF_BUFFER_SIZE", self.buffer_size.to_string()),
+            ("VK_LAYER_PRINTF_VERBOSE", self.verbose.to_string()),
+            // through the messenger only, not printed to stdout a second time
+            ("VK_LAYER_PRINTF_TO_STDOUT", false.to_string()),
+        ];
+        for (variable, value) in settings {
+            if std::env::var_os(variable).is_none() {
+                std::env::set_var(variable, value);
+            }
+        }
+    }
+}
+
+impl Default for DebugConfig {
+    /// Warnings and errors, plus verbose messages in debug builds, of every type.
+    fn default() -> Self {
+        Self {
+            severities: DebugUtilsMessageSeverityFlagsEXT::VERBOSE
+                | DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | DebugUtilsMessageSeverityFlagsEXT::ERROR,
+            types: DebugUtilsMessageTypeFlagsEXT::GENERAL
+                | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
+                | DebugUtilsMessageTypeFlagsEXT::VALIDATION,
+            suppress_verbose_in_release: true,
+            shader_printf: None,
+        }
+    }
+}
+
+pub const SEVERITY_ENV: &str = "VULKY_DEBUG_SEVERITY";
+pub const TYPES_ENV: &str = "VULKY_DEBUG_TYPES";
+pub const SUPPRESS_ENV: &str = "VULKY_DEBUG_SUPPRESS";
+pub const SHADER_PRINTF_ENV: &str = "VULKY_SHADER_PRINTF";
+pub const SHADER_PRINTF_BUFFER_SIZE_ENV: &str = "VULKY_SHADER_PRINTF_BUFFER_SIZE";
+
+impl DebugConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The default with the severities and types of the environment variables that are set.
+    /// Unknown names are ignored with a warning, the suppressions are registered globally with `suppress`.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(value) = std::env::var(SEVERITY_ENV) {
+            config.severities = parse_list(&value, SEVERITY_ENV, parse_severity);
+        }
+        if let Ok(value) = std::env::var(TYPES_ENV) {
+            config.types = parse_list(&value, TYPES_ENV, parse_type);
+        }
+        if let Ok(value) = std::env::var(SUPPRESS_ENV) {
+            for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+                suppress(Suppression::parse(entry));
+            }
+        }
+        let printf_enabled = std::env::var(SHADER_PRINTF_ENV)
+            .map(|value| !matches!(value.trim(), "" | "0" | "false" | "off"))
+            .unwrap_or(false);
+        if printf_enabled {
+            let mut printf = ShaderPrintf::default();
+            if let Ok(value) = std::env::var(SHADER_PRINTF_BUFFER_SIZE_ENV) {
+                match value.trim().parse() {
+                    Ok(size) => printf.buffer_size = size,
+                    Err(_) => log::warn!("{} is not a size: {:?}", SHADER_PRINTF_BUFFER_SIZE_ENV, value),
+                }
+            }
+            config.shader_printf = Some(printf);
+        }
+        config
+    }
+
+    pub fn severities(mut self, severities: DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        self.severities = severities;
+        self
+    }
+
+    pub fn types(mut self, types: DebugUtilsMessageTypeFlagsEXT) -> Self {
+        self.types = types;
+        self
+    }
+
+    /// Everything from `min` up, `WARNING` also enables `ERROR`.
+    pub fn min_severity(mut self, min: DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        self.severities = [
+            DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
+            DebugUtilsMessageSeverityFlagsEXT::INFO,
+            DebugUtilsMessageSeverityFlagsEXT::WARNING,
+            DebugUtilsMessageSeverityFlagsEXT::ERROR,
+        ]
+        .into_iter()
+        .filter(|severity| severity.as_raw() >= min.as_raw())
+        .fold(DebugUtilsMessageSeverityFlagsEXT::empty(), |flags, severity| flags | severity);
+        self
+    }
+
+    pub fn suppress_verbose_in_release(mut self, suppress: bool) -> Self {
+        self.suppress_verbose_in_release = suppress;
+        self
+    }
+
+    pub fn shader_printf(mut self, printf: Option<ShaderPrintf>) -> Self {
+        self.shader_printf = printf;
+        self
+    }
+
+    /// The severities the messenger is created with after the release build suppression.
+    /// Printf output arrives as INFO, which stays on with `shader_printf`.
+    pub fn effective_severities(&self) -> DebugUtilsMessageSeverityFlagsEXT {
+        let severities = if self.suppress_verbose_in_release && !cfg!(debug_assertions) {
+            self.severities & (DebugUtilsMessageSeverityFlagsEXT::WARNING | DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        } else {
+            self.severities
+        };
+        match self.shader_printf {
+            Some(_) => severities | DebugUtilsMessageSeverityFlagsEXT::INFO,
+            None => severities,
+        }
+    }
+
+    /// Validation features to enable through `validation_features_info`, empty when the config needs none.
+    /// Also applies the printf layer settings.
+    pub fn enabled_validation_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        match self.shader_printf {
+            Some(printf) => {
+                printf.apply_layer_settings();
+                vec![vk::ValidationFeatureEnableEXT::DEBUG_PRINTF]
+            }
+            None => vec![],
+        }
+    }
+
+    /// Messenger forwarding to `log` under `LOG_TARGET`.
+    /// Chain it into `vk::InstanceCreateInfo::p_next` to also see messages from instance creation.
+    pub fn messenger_create_info(&self) -> vk::DebugUtilsMessengerCreateInfoEXT {
+        vk::DebugUtilsMessengerCreateInfoEXT {
+            s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
+            p_next: ptr::null(),
+            flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
+            message_severity: self.effective_severities(),
+            message_type: self.types,
+            pfn_user_callback: Some(debug_callback),
+            p_user_data: ptr::null_mut(),
+        }
+    }
+}
+
+/// VK_EXT_validation_features info for `vk::InstanceCreateInfo::p_next`, before the messenger create info in `p_next`.
+/// The instance has to enable `vk::ExtValidationFeaturesFn::name()`, the validation layer provides it.
+pub fn validation_features_info(
+    enabled: &[vk::ValidationFeatureEnableEXT],
+    p_next: *const c_void,
+) -> vk::ValidationFeaturesEXT {
+    vk::ValidationFeaturesEXT {
+        p_next,
+        enabled_validation_feature_count: enabled.len() as u32,
+        p_enabled_validation_features: enabled.as_ptr(),
+        ..Default::default()
+    }
+}
+
+/// Names `handle` for validation messages and RenderDoc, failures are only logged.
+///
+/// # Safety
+/// `handle` has to be an object of `device`, which needs VK_EXT_debug_utils on its instance.
+pub unsafe fn set_object_name<H: vk::Handle>(
+    debug_utils: &ash::extensions::ext::DebugUtils,
+    device: &ash::Device,
+    handle: H,
+    name: &str,
+) {
+    let name = CString::new(name).unwrap_or_default();
+    let name_info = vk::DebugUtilsObjectNameInfoEXT {
+        object_type: H::TYPE,
+        object_handle: handle.as_raw(),
+        p_object_name: name.as_ptr(),
+        ..Default::default()
+    };
+    if let Err(e) = debug_utils.set_debug_utils_object_name(device.handle(), &name_info) {
+        log::warn!("Naming {:?} failed: {}", H::TYPE, e);
+    }
+}
+
+fn parse_severity(name: &str) -> Option<DebugUtilsMessageSeverityFlagsEXT> {
+    match name {
+        "error" => Some(DebugUtilsMessageSeverityFlagsEXT::ERROR),
+        "warning" | "warn" => Some(DebugUtilsMessageSeverityFlagsEXT::WARNING),
+        "info" => Some(DebugUtilsMessageSeverityFlagsEXT::INFO),
+        "verbose" => Some(DebugUtilsMessageSeverityFlagsEXT::VERBOSE),
+        _ => None,
+    }
+}
+
+fn parse_type(name: &str) -> Option<DebugUtilsMessageTypeFlagsEXT> {
+    match name {
+        "general" => Some(DebugUtilsMessageTypeFlagsEXT::GENERAL),
+        "validation" => Some(DebugUtilsMessageTypeFlagsEXT::VALIDATION),
+        "performance" => Some(DebugUtilsMessageTypeFlagsEXT::PERFORMANCE),
+        _ => None,
+    }
+}
+
+/// Comma separated, case insensitive names, "all" and "none" are accepted too.
+fn parse_list<F>(value: &str, variable: &str, parse: fn(&str) -> Option<F>) -> F
+where
+    F: std::ops::BitOr<Output = F> + Copy + Default,
+{
+    let all = ["error", "warning", "info", "verbose", "general", "validation", "performance"];
+    let mut flags = F::default();
+    for name in value.split(',').map(|name| name.trim().to_ascii_lowercase()) {
+        match name.as_str() {
+            "" | "none" => {}
+            "all" => {
+                flags = all
+                    .iter()
+                    .filter_map(|name| parse(name))
+                    .fold(flags, |flags, flag| flags | flag);
+            }
+            name => match parse(name) {
+                Some(flag) => flags = flags | flag,
+                None => log::warn!("{} has an unknown entry {:?}", variable, name),
+            },
+        }
+    }
+    flags
+}
@@ -0,0 +1,158 @@
+use std::collections::VecDeque;
+
+use ash::vk;
+
+use crate::memory::GpuAllocator;
+
+type Deletion = Box<dyn FnOnce(&ash::Device, &mut GpuAllocator) + Send>;
+
+/// Keeps dropped resources alive until the GPU finished every frame that could still use them.
+/// A resource dropped while frame N is recorded is destroyed once the fence of frame N signaled,
+/// with no frame in flight it is destroyed right away.
+#[derive(Default)]
+pub struct DeletionQueue {
+    /// the frame being recorded
+    frame: u64,
+    /// every frame before this one is done on the GPU
+    completed: u64,
+    in_flight: VecDeque<(u64, vk::Fence)>,
+    pending: VecDeque<(u64, Deletion)>,
+}
+
+impl DeletionQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Runs `destroy` once no frame in flight can use the resource.
+    ///
+    /// # Safety
+    /// `destroy` may only destroy objects of `device` and `allocator`.
+    pub unsafe fn defer<F>(&mut self, device: &ash::Device, allocator: &mut GpuAllocator, destroy: F)
+    where
+        F: FnOnce(&ash::Device, &mut GpuAllocator) + Send + 'static,
+    {
+        if self.in_flight.is_empty() {
+            destroy(device, allocator);
+        } else {
+            self.pending.push_back((self.frame, Box::new(destroy)));
+        }
+    }
+
+    /// Ends the recording of the current frame, `fence` signals when the GPU finished its submissions.
+    /// A fence that is reused has been waited on before the reset, so the frame it belonged to is done.
+    pub fn end_frame(&mut self, fence: vk::Fence) {
+        while let Some(index) = self.in_flight.iter().position(|(_, in_flight)| *in_flight == fence) {
+            let (frame, _) = self.in_flight.remove(index).unwrap();
+            self.completed = self.completed.max(frame + 1);
+        }
+        self.in_flight.push_back((self.frame, fence));
+        self.frame += 1;
+    }
+
+    /// Destroys what the finished frames held, call it once per frame.
+    ///
+    /// # Safety
+    /// Every in flight fence given to `end_frame` has to be alive.
+    pub unsafe fn collect(&mut self, device: &ash::Device, allocator: &mut GpuAllocator) {
+        let completed = self.take_completed(|fence| device.get_fence_status(fence).unwrap_or(false));
+        for destroy in completed {
+            destroy(device, allocator);
+        }
+    }
+
+    /// Retires the oldest frames while `signaled` says their fence is, returns what they held.
+    fn take_completed(&mut self, mut signaled: impl FnMut(vk::Fence) -> bool) -> Vec<Deletion> {
+        while let Some((frame, fence)) = self.in_flight.front().copied() {
+            if !signaled(fence) {
+                break;
+            }
+            self.in_flight.pop_front();
+            self.completed = self.completed.max(frame + 1);
+        }
+        let mut completed = vec![];
+        while self.pending.front().is_some_and(|(frame, _)| *frame < self.completed) {
+            completed.push(self.pending.pop_front().unwrap().1);
+        }
+        completed
+    }
+
+    /// Destroys everything, the device has to be idle.
+    ///
+    /// # Safety
+    /// The device has to be idle.
+    pub unsafe fn flush(&mut self, device: &ash::Device, allocator: &mut GpuAllocator) {
+        self.in_flight.clear();
+        self.completed = self.frame;
+        for (_, destroy) in self.pending.drain(..) {
+            destroy(device, allocator);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    /// What `defer` does while a frame is in flight, the deletion itself is never run.
+    fn defer(queue: &mut DeletionQueue) {
+        assert!(!queue.in_flight.is_empty());
+        queue.pending.push_back((queue.frame, Box::new(|_, _| unreachable!())));
+    }
+
+    #[test]
+    fn deletions_wait_for_the_fence_of_their_frame() {
+        let (a, b, c) = (vk::Fence::from_raw(1), vk::Fence::from_raw(2), vk::Fence::from_raw(3));
+        let mut queue = DeletionQueue::new();
+        queue.end_frame(a);
+        defer(&mut queue);
+        queue.end_frame(b);
+        defer(&mut queue);
+        defer(&mut queue);
+        queue.end_frame(c);
+        assert_eq!(queue.len(), 3);
+
+        assert!(queue.take_completed(|_| false).is_empty());
+        // the first frame deferred nothing
+        assert!(queue.take_completed(|fence| fence == a).is_empty());
+        assert_eq!(queue.take_completed(|fence| fence != c).len(), 1);
+        assert!(queue.take_completed(|fence| fence != c).is_empty());
+        assert_eq!(queue.take_completed(|_| true).len(), 2);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn a_reused_fence_completes_its_old_frame() {
+        let (a, b) = (vk::Fence::from_raw(1), vk::Fence::from_raw(2));
+        let mut queue = DeletionQueue::new();
+        queue.end_frame(a);
+        defer(&mut queue);
+        queue.end_frame(b);
+        defer(&mut queue);
+        queue.end_frame(a);
+        assert_eq!(queue.frame(), 3);
+
+        // every reuse was waited on before, so it finishes the frame the fence last belonged to
+        assert!(queue.take_completed(|_| false).is_empty());
+        queue.end_frame(b);
+        assert_eq!(queue.take_completed(|_| false).len(), 1);
+        queue.end_frame(a);
+        assert_eq!(queue.take_completed(|_| false).len(), 1);
+        assert!(queue.is_empty());
+        assert_eq!(queue.in_flight.len(), 2);
+    }
+}
@@ -0,0 +1,459 @@
+use std::path::PathBuf;
+
+use ash::vk;
+use nalgebra as glm;
+use vulky::error::{Error, Result};
+
+use vulky::{
+    buffer::{OneTimeCommands, MAX_FRAMES_IN_FLIGHT},
+    camera::Camera,
+    frame::{FrameData, FrameUniforms},
+    gltf_import::{self, AlphaMode, PbrMaterialDesc, ShadingModel},
+    material::{DrawList, MaterialInstance},
+    mesh::{GpuMesh, Mesh, MeshVertex, SubMesh},
+    pbr::{self, PbrMaterials},
+    scene::Scene,
+    texture::Texture,
+    weather::{Precipitation, Weather, WeatherPass, WeatherSettings},
+};
+
+/// Directory the downloadable demo assets are looked up in, `assets` when unset.
+pub const ASSETS_ENV: &str = "VULKY_ASSETS";
+
+/// The built-in scenes, `cargo run -- <name>` opens one in the window.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Demo {
+    /// the Khronos Sponza sample, downloaded by hand into the assets directory
+    Sponza,
+    /// spheres from dielectric to metal and from smooth to rough, generated
+    PbrSpheres,
+    /// snow falling on a few spheres, generated
+    Particles,
+}
+
+impl Demo {
+    pub const ALL: [Demo; 3] = [Demo::Sponza, Demo::PbrSpheres, Demo::Particles];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Demo::Sponza => "sponza",
+            Demo::PbrSpheres => "pbr-spheres",
+            Demo::Particles => "particles",
+        }
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            Demo::Sponza => "the Sponza atrium from the glTF sample models, textured PBR",
+            Demo::PbrSpheres => "a grid of spheres, metallic left to right and rough bottom to top",
+            Demo::Particles => "snowfall with the weather particles",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Demo> {
+        Demo::ALL.into_iter().find(|demo| demo.name() == name)
+    }
+}
+
+/// What the command line asked for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Launch {
+    /// the window with the demo quad, with `headless` the quad is rendered into headless.ppm
+    Default {
+        headless: bool,
+    },
+    Help,
+    /// with `headless` every demo renders `frames` frames offscreen into `<name>.ppm`,
+    /// otherwise the first one opens in the window
+    Demos {
+        demos: Vec<Demo>,
+        headless: bool,
+        frames: u32,
+        /// logs a GPU hash of the last headless frame instead of writing it
+        hash: bool,
+    },
+}
+
+impl Launch {
+    /// `[<demo>|all] [--headless] [--frames <n>] [--hash]`, without a demo `--headless` renders the quad.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Launch> {
+        let mut demos = vec![];
+        let mut headless = false;
+        let mut hash = false;
+        let mut frames = MAX_FRAMES_IN_FLIGHT as u32 * 2;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => headless = true,
+                "--hash" => hash = true,
+                "--frames" => {
+                    let value = args.next().ok_or_else(|| Error::msg("--frames needs a count"))?;
+                    frames = value
+                        .parse()
+                        .map_err(|_| Error::msg(format!("{} isn't a frame count", value)))?;
+                }
+                "-h" | "--help" | "help" | "list" => return Ok(Launch::Help),
+                "all" => demos.extend(Demo::ALL),
+                name => demos.push(
+                    Demo::parse(name)
+                        .ok_or_else(|| Error::msg(format!("There is no demo {}, the demos are:\n{}", name, usage())))?,
+                ),
+            }
+        }
+        if demos.is_empty() {
+            return Ok(Launch::Default { headless });
+        }
+        Ok(Launch::Demos {
+            demos,
+            headless,
+            frames: frames.max(1),
+            hash,
+        })
+    }
+}
+
+pub fn usage() -> String {
+    let mut text = String::from("cargo run -- [<demo>|all] [--headless] [--frames <n>] [--hash]\n");
+    for demo in Demo::ALL {
+        text.push_str(&format!("  {:<12} {}\n", demo.name(), demo.description()));
+    }
+    text.push_str("  --headless   renders offscreen into <demo>.ppm and fails when nothing was drawn\n");
+    text.push_str("  --hash       with --headless, logs a GPU hash of the last frame instead of writing it\n");
+    text
+}
+
+fn assets_dir() -> PathBuf {
+    std::env::var_os(ASSETS_ENV).map_or_else(|| PathBuf::from("assets"), PathBuf::from)
+}
+
+fn material_desc(base_color: [f32; 3], metallic: f32, roughness: f32) -> PbrMaterialDesc {
+    PbrMaterialDesc {
+        name: None,
+        base_color_factor: [base_color[0], base_color[1], base_color[2], 1.0],
+        base_color_texture: None,
+        metallic_factor: metallic,
+        roughness_factor: roughness,
+        metallic_roughness_texture: None,
+        normal_texture: None,
+        normal_scale: 1.0,
+        occlusion_texture: None,
+        occlusion_strength: 1.0,
+        emissive_texture: None,
+        emissive_factor: [0.0; 3],
+        alpha_mode: AlphaMode::Opaque,
+        double_sided: false,
+        shading: ShadingModel::Pbr,
+        keywords: vec![],
+    }
+}
+
+/// The whole mesh as one submesh without a material.
+fn single_submesh(vertices: Vec<MeshVertex>, indices: Vec<u32>) -> Mesh {
+    Mesh {
+        submeshes: vec![SubMesh {
+            index_offset: 0,
+            index_count: indices.len() as u32,
+            material: None,
+        }],
+        vertices,
+        indices,
+        ..Default::default()
+    }
+}
+
+/// Sphere of `radius` around the origin, counter clockwise seen from outside.
+fn uv_sphere(radius: f32, segments: u32, rings: u32) -> Mesh {
+    let mut vertices = vec![];
+    for ring in 0..=rings {
+        let v = ring as f32 / rings as f32;
+        let polar = v * std::f32::consts::PI;
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let azimuth = u * std::f32::consts::TAU;
+            let normal = glm::Vector3::new(polar.sin() * azimuth.cos(), polar.cos(), -polar.sin() * azimuth.sin());
+            vertices.push(MeshVertex::new(normal * radius, normal, glm::Vector2::new(u, v)));
+        }
+    }
+    let mut indices = vec![];
+    let row = segments + 1;
+    for ring in 0..rings {
+        for segment in 0..segments {
+            let top = ring * row + segment;
+            let bottom = top + row;
+            indices.extend([top, bottom, top + 1, top + 1, bottom, bottom + 1]);
+        }
+    }
+    single_submesh(vertices, indices)
+}
+
+/// Square of `size` on the xz plane facing up.
+fn ground(size: f32) -> Mesh {
+    let half = size * 0.5;
+    let up = glm::Vector3::y();
+    let vertices = vec![
+        MeshVertex::new(glm::Vector3::new(-half, 0.0, -half), up, glm::Vector2::new(0.0, 0.0)),
+        MeshVertex::new(glm::Vector3::new(-half, 0.0, half), up, glm::Vector2::new(0.0, 1.0)),
+        MeshVertex::new(glm::Vector3::new(half, 0.0, half), up, glm::Vector2::new(1.0, 1.0)),
+        MeshVertex::new(glm::Vector3::new(half, 0.0, -half), up, glm::Vector2::new(1.0, 0.0)),
+    ];
+    single_submesh(vertices, vec![0, 1, 2, 2, 3, 0])
+}
+
+/// One submesh of a mesh placed in the world.
+struct DemoDraw {
+    mesh: usize,
+    submesh: usize,
+    instance: usize,
+    transform: glm::Matrix4<f32>,
+}
+
+/// The GPU resources of a demo, drawn inside any render pass compatible with the one it was built for.
+pub struct DemoScene {
+    demo: Demo,
+    frame_data: FrameData,
+    pbr: PbrMaterials,
+    meshes: Vec<GpuMesh>,
+    textures: Vec<Texture>,
+    instances: Vec<MaterialInstance>,
+    draws: Vec<DemoDraw>,
+    camera: Camera,
+    /// only the particles demo has weather
+    weather: Option<(Weather, WeatherPass)>,
+    seconds: f32,
+}
+
+impl DemoScene {
+    /// `command_pool` and `queue` have to belong to the graphics family.
+    pub unsafe fn new(
+        demo: Demo,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        render_pass: vk::RenderPass,
+    ) -> Result<DemoScene> {
+        let frame_data = FrameData::new(device, instance, physical_device)?;
+        let upload = OneTimeCommands { command_pool, queue };
+        let pbr = PbrMaterials::new(device, instance, physical_device, upload, render_pass, frame_data.set_layout)?;
+        let mut scene = DemoScene {
+            demo,
+            frame_data,
+            pbr,
+            meshes: vec![],
+            textures: vec![],
+            instances: vec![],
+            draws: vec![],
+            camera: Camera::new(glm::Vector3::zeros()),
+            weather: None,
+            seconds: 0.0,
+        };
+
+        let built = scene.build(device, instance, physical_device, command_pool, queue, render_pass);
+        match built {
+            Ok(()) => {
+                log::info!("Loaded the {} demo, {} draws", demo.name(), scene.draws.len());
+                Ok(scene)
+            }
+            Err(e) => {
+                scene.destroy(device);
+                Err(Error::msg(format!("The {} demo: {}", demo.name(), e)))
+            }
+        }
+    }
+
+    /// Uploads the meshes, textures and material instances of the demo and places the camera.
+    unsafe fn build(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        render_pass: vk::RenderPass,
+    ) -> Result<()> {
+        let upload = |mesh: &Mesh, scene: &mut DemoScene| -> Result<usize> {
+            scene
+                .meshes
+                .push(mesh.upload(device, instance, physical_device, command_pool, queue)?);
+            Ok(scene.meshes.len() - 1)
+        };
+        let add_instance = |desc: &PbrMaterialDesc, scene: &mut DemoScene| -> Result<usize> {
+            let material_instance = scene
+                .pbr
+                .create_instance(device, instance, physical_device, desc, &scene.textures)?;
+            scene.instances.push(material_instance);
+            Ok(scene.instances.len() - 1)
+        };
+
+        match self.demo {
+            Demo::Sponza => {
+                let path = assets_dir().join("Sponza/glTF/Sponza.gltf");
+                if !path.exists() {
+                    return Err(Error::asset(
+                        &path,
+                        format!(
+                            "Missing, download the Sponza folder of \
+                             https://github.com/KhronosGroup/glTF-Sample-Models/tree/master/2.0 \
+                             into the assets directory or point {} at it",
+                            ASSETS_ENV
+                        ),
+                    ));
+                }
+                let gltf = gltf_import::load_gltf(&path)?;
+                let commands = OneTimeCommands { command_pool, queue };
+                self.textures = pbr::upload_gltf_textures(device, instance, physical_device, commands, &gltf)?;
+                for desc in gltf.materials.iter() {
+                    add_instance(desc, self)?;
+                }
+                // for submeshes without a material
+                let fallback = add_instance(&material_desc([0.8; 3], 0.0, 0.8), self)?;
+                for mesh in gltf.meshes.iter() {
+                    upload(mesh, self)?;
+                }
+
+                let (hierarchy, ids) = Scene::from_gltf(&gltf);
+                for (desc, id) in gltf.nodes.iter().zip(ids) {
+                    let (Some(mesh), Some(id)) = (desc.mesh, id) else {
+                        continue;
+                    };
+                    for (submesh, range) in gltf.meshes[mesh].submeshes.iter().enumerate() {
+                        self.draws.push(DemoDraw {
+                            mesh,
+                            submesh,
+                            instance: range.material.unwrap_or(fallback),
+                            transform: *hierarchy.node(id).world_transform(),
+                        });
+                    }
+                }
+                self.camera.position = glm::Vector3::new(-9.0, 2.0, -0.5);
+                self.camera.look_at(&glm::Vector3::new(5.0, 3.5, 0.0), &glm::Vector3::y());
+            }
+            Demo::PbrSpheres => {
+                let sphere = upload(&uv_sphere(0.45, 48, 24), self)?;
+                let count = 7;
+                for row in 0..count {
+                    for column in 0..count {
+                        let metallic = column as f32 / (count - 1) as f32;
+                        // fully smooth surfaces show a single pixel highlight
+                        let roughness = (row as f32 / (count - 1) as f32).max(0.05);
+                        let desc = material_desc([0.9, 0.15, 0.1], metallic, roughness);
+                        let instance = add_instance(&desc, self)?;
+                        let position = glm::Vector3::new(
+                            column as f32 - (count - 1) as f32 * 0.5,
+                            row as f32 - (count - 1) as f32 * 0.5,
+                            0.0,
+                        );
+                        self.draws.push(DemoDraw {
+                            mesh: sphere,
+                            submesh: 0,
+                            instance,
+                            transform: glm::Matrix4::new_translation(&position),
+                        });
+                    }
+                }
+                self.camera.position = glm::Vector3::new(0.0, 0.0, 9.0);
+            }
+            Demo::Particles => {
+                let floor = upload(&ground(40.0), self)?;
+                let floor_material = add_instance(&material_desc([0.3, 0.32, 0.35], 0.0, 0.9), self)?;
+                self.draws.push(DemoDraw {
+                    mesh: floor,
+                    submesh: 0,
+                    instance: floor_material,
+                    transform: glm::Matrix4::identity(),
+                });
+                let sphere = upload(&uv_sphere(1.0, 32, 16), self)?;
+                let sphere_material = add_instance(&material_desc([0.7, 0.7, 0.75], 0.0, 0.5), self)?;
+                for x in [-3.0, 0.0, 3.0] {
+                    self.draws.push(DemoDraw {
+                        mesh: sphere,
+                        submesh: 0,
+                        instance: sphere_material,
+                        transform: glm::Matrix4::new_translation(&glm::Vector3::new(x, 1.0, 0.0)),
+                    });
+                }
+
+                let mut weather = Weather::new(WeatherSettings::default());
+                weather.set_immediately(Precipitation::Snow, 1.0);
+                let pass = WeatherPass::new(device, render_pass, self.frame_data.set_layout)?;
+                self.weather = Some((weather, pass));
+                self.camera.position = glm::Vector3::new(0.0, 3.0, 10.0);
+                self.camera.look_at(&glm::Vector3::new(0.0, 1.0, 0.0), &glm::Vector3::y());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn demo(&self) -> Demo {
+        self.demo
+    }
+
+    /// Animates the scene by `dt` seconds.
+    pub fn update(&mut self, dt: f32) {
+        self.seconds += dt;
+        if self.demo == Demo::PbrSpheres {
+            // sways the camera so the highlights slide over the spheres
+            let angle = self.seconds * 0.5;
+            self.camera.position = glm::Vector3::new(angle.sin() * 2.0, 0.0, 9.0);
+            self.camera.look_at(&glm::Vector3::zeros(), &glm::Vector3::y());
+        }
+        if let Some((weather, _)) = &mut self.weather {
+            weather.update(dt);
+        }
+    }
+
+    /// Draws inside the current render pass with viewport and scissor set to `extent`.
+    /// The fence of `current_frame` has to be waited on first.
+    pub unsafe fn record(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        extent: vk::Extent2D,
+    ) {
+        let mut uniforms = FrameUniforms::default();
+        let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+        self.camera.write_uniforms(&mut uniforms, aspect_ratio);
+        if let Some((weather, _)) = &self.weather {
+            weather.write_uniforms(&mut uniforms);
+        }
+        self.frame_data.update(current_frame, &uniforms);
+
+        for material_instance in self.instances.iter() {
+            material_instance.flush(device, current_frame);
+        }
+        let mut draws = DrawList::new();
+        for draw in self.draws.iter() {
+            draws.push_submesh(
+                &self.meshes[draw.mesh],
+                draw.submesh,
+                &self.instances[draw.instance],
+                draw.transform,
+            );
+        }
+        let frame_set = self.frame_data.descriptor_set(current_frame);
+        draws.record(device, command_buffer, current_frame, &[frame_set]);
+
+        if let Some((weather, pass)) = &self.weather {
+            pass.record(device, command_buffer, frame_set, weather);
+        }
+    }
+
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        if let Some((_, pass)) = &self.weather {
+            pass.destroy(device);
+        }
+        for material_instance in self.instances.iter() {
+            material_instance.destroy(device);
+        }
+        for mesh in self.meshes.iter() {
+            mesh.destroy(device);
+        }
+        for texture in self.textures.iter() {
+            texture.destroy(device);
+        }
+        self.pbr.destroy(device);
+        self.frame_data.destroy(device);
+    }
+}
@@ -1,14 +1,15 @@
 use std::collections::HashSet;
-use std::ffi::CStr;
-use std::os::raw::c_char;
+use std::ffi::{c_void, CStr};
 use std::ptr;
 
-use anyhow::Error;
-use anyhow::Result;
+use crate::error::Error;
+use crate::error::Result;
 use ash::prelude::VkResult;
 use ash::{vk, Instance};
 
-use crate::constant;
+use crate::device_lost::DiagnosticExtensions;
+use crate::extensions::EnabledExtensions;
+use crate::features::{DeviceFeatures, DeviceRequirements, FeatureChain};
 use crate::SwapChainSupportDetails;
 
 use crate::{constant::support, utility, QueueFamilyIndices};
@@ -37,8 +38,8 @@ unsafe fn is_device_suitable(
     let device_name = utility::vk_to_string(&device_properties.properties.device_name);
     let driver_version = get_version_api(device_properties.properties.driver_version);
 
-    println!(
-        "\tDevice Name: {}, id: {}, type: {}, driver version: {}.{}.{}.",
+    log::info!(
+        "Device Name: {}, id: {}, type: {}, driver version: {}.{}.{}.",
         device_name,
         device_properties.properties.device_id,
         device_type,
@@ -49,12 +50,12 @@ unsafe fn is_device_suitable(
 
     let (variant, major, minior, patch) = get_version_api(device_properties.properties.api_version);
 
-    println!("\tVersion:, {}.{}.{}.{}", variant, major, minior, patch); // supported vulkan
+    log::info!("Version: {}.{}.{}.{}", variant, major, minior, patch); // supported vulkan
 
     let _ = device_extension_support(instance, physical_device)?;
-    let _ = SwapChainSupportDetails::query_swapchain_support(surface_loader, surface.clone(), physical_device)?;
+    let _ = SwapChainSupportDetails::query_swapchain_support(surface_loader, *surface, physical_device)?;
 
-    return QueueFamilyIndices::find_queue_family(physical_device, instance, surface_loader, surface);
+    QueueFamilyIndices::find_queue_family(physical_device, instance, surface_loader, surface)
 
     //println!("{:?}", device_properties.properties.device_type);
     // if device_properties.properties.device_type == ash::vk::PhysicalDeviceType::DISCRETE_GPU {}
@@ -83,38 +84,223 @@ unsafe fn device_extension_support(instance: &Instance, physical_device: vk::Phy
             }
         }
     }
-    if vec_names.len() > 0 {
+    if !vec_names.is_empty() {
         return Err(vk::Result::ERROR_EXTENSION_NOT_PRESENT);
     }
 
     Ok(true)
 }
 
-pub unsafe fn pick_physical_device(
+/// Overrides the device choice, an index in enumeration order or a part of the device name.
+pub const GPU_ENV: &str = "VULKY_GPU";
+
+/// Which physical device `pick_physical_device_with` takes.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum DeviceSelection {
+    /// the suitable device with the highest `DeviceCandidate::score`
+    #[default]
+    Best,
+    /// index in `vkEnumeratePhysicalDevices` order, the one the log lists devices in
+    Index(usize),
+    /// the first device whose name contains this, ignoring case
+    Name(String),
+}
+
+impl DeviceSelection {
+    /// Numbers are indices and anything else a name, empty text is `Best`.
+    pub fn parse(text: &str) -> Self {
+        let text = text.trim();
+        if text.is_empty() {
+            DeviceSelection::Best
+        } else if let Ok(index) = text.parse() {
+            DeviceSelection::Index(index)
+        } else {
+            DeviceSelection::Name(text.to_owned())
+        }
+    }
+
+    /// `VULKY_GPU`, `Best` when it isn't set.
+    pub fn from_env() -> Self {
+        std::env::var(GPU_ENV).map(|value| Self::parse(&value)).unwrap_or_default()
+    }
+
+    fn matches(&self, candidate: &DeviceCandidate) -> bool {
+        match self {
+            DeviceSelection::Best => true,
+            DeviceSelection::Index(index) => candidate.index == *index,
+            DeviceSelection::Name(name) => candidate.name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+/// A device that has the required extensions, can present to the surface and has the queues.
+#[derive(Clone, Debug)]
+pub struct DeviceCandidate {
+    pub physical_device: vk::PhysicalDevice,
+    /// in `vkEnumeratePhysicalDevices` order
+    pub index: usize,
+    pub name: String,
+    pub device_type: vk::PhysicalDeviceType,
+    /// bytes in the device local heaps
+    pub device_local_memory: u64,
+    /// the device type first, then the memory size
+    pub score: u64,
+}
+
+pub(crate) fn type_score(device_type: vk::PhysicalDeviceType) -> u64 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 4,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 3,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    }
+}
+
+/// The suitable devices with the best first, devices with equal scores stay in enumeration order.
+///
+/// # Safety
+/// `surface` has to be a surface of `instance`.
+pub unsafe fn rank_physical_devices(
     instance: &ash::Instance,
     surface_loader: &ash::extensions::khr::Surface,
     surface: &vk::SurfaceKHR,
-) -> Result<(vk::PhysicalDevice)> {
-    let devices = instance.enumerate_physical_devices()?;
-    for device in devices {
-        let dev_ret = is_device_suitable(device, instance, surface_loader, surface);
-
-        match dev_ret {
-            Ok(x) => return Ok(device),
-            Err(e) => eprintln!("Error: {}", e),
+) -> Result<Vec<DeviceCandidate>> {
+    let mut candidates = vec![];
+    for (index, physical_device) in instance.enumerate_physical_devices()?.into_iter().enumerate() {
+        if let Err(e) = is_device_suitable(physical_device, instance, surface_loader, surface) {
+            log::warn!("Skipping unsuitable device {}: {}", index, e);
+            continue;
         }
+        let properties = instance.get_physical_device_properties(physical_device);
+        let memory = instance.get_physical_device_memory_properties(physical_device);
+        let device_local_memory: u64 = memory.memory_heaps[..memory.memory_heap_count as usize]
+            .iter()
+            .filter(|heap| heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL))
+            .map(|heap| heap.size)
+            .sum();
+        // a MiB is fine enough, and the type always outweighs the memory
+        let memory_score = (device_local_memory >> 20).min(999_999);
+        candidates.push(DeviceCandidate {
+            physical_device,
+            index,
+            name: utility::vk_to_string(&properties.device_name),
+            device_type: properties.device_type,
+            device_local_memory,
+            score: type_score(properties.device_type) * 1_000_000 + memory_score,
+        });
     }
-    Err(Error::msg("No Vulkan Supported GPU"))
+    candidates.sort_by_key(|candidate| std::cmp::Reverse(candidate.score));
+    Ok(candidates)
+}
+
+/// The best suitable device, or the one `VULKY_GPU` asks for.
+///
+/// # Safety
+/// As for `rank_physical_devices`.
+pub unsafe fn pick_physical_device(
+    instance: &ash::Instance,
+    surface_loader: &ash::extensions::khr::Surface,
+    surface: &vk::SurfaceKHR,
+) -> Result<vk::PhysicalDevice> {
+    pick_physical_device_with(instance, surface_loader, surface, &DeviceSelection::from_env())
+}
+
+/// Like `pick_physical_device` with an explicit `selection`.
+/// A selection without a suitable match falls back to the best device with a warning.
+///
+/// # Safety
+/// As for `rank_physical_devices`.
+pub unsafe fn pick_physical_device_with(
+    instance: &ash::Instance,
+    surface_loader: &ash::extensions::khr::Surface,
+    surface: &vk::SurfaceKHR,
+    selection: &DeviceSelection,
+) -> Result<vk::PhysicalDevice> {
+    let candidates = rank_physical_devices(instance, surface_loader, surface)?;
+    let best = candidates
+        .first()
+        .ok_or_else(|| Error::NoSuitableDevice("No Vulkan Supported GPU".to_owned()))?;
+    let chosen = match candidates.iter().find(|candidate| selection.matches(candidate)) {
+        Some(candidate) => candidate,
+        None => {
+            log::warn!("No suitable device matches {:?}, using the best one", selection);
+            best
+        }
+    };
+    log::info!(
+        "Using device {} {} ({:?}, {} MiB device local)",
+        chosen.index,
+        chosen.name,
+        chosen.device_type,
+        chosen.device_local_memory >> 20
+    );
+    Ok(chosen.physical_device)
 }
 
+/// # Safety
+/// `physical_device` has to be enumerated from `instance` and support `surface`.
 pub unsafe fn create_logical_device(
     physical_device: vk::PhysicalDevice,
     instance: &ash::Instance,
     surface: vk::SurfaceKHR,
     surface_loader: &ash::extensions::khr::Surface,
 ) -> Result<(ash::Device, QueueFamilyIndices)> {
+    create_logical_device_with_diagnostics(
+        physical_device,
+        instance,
+        surface,
+        surface_loader,
+        &DiagnosticExtensions::default(),
+    )
+}
+
+/// Like `create_logical_device`, also enables the device lost `diagnostics`, see `DiagnosticExtensions::supported`.
+///
+/// # Safety
+/// As for `create_logical_device`, `diagnostics` have to be supported by `physical_device`.
+pub unsafe fn create_logical_device_with_diagnostics(
+    physical_device: vk::PhysicalDevice,
+    instance: &ash::Instance,
+    surface: vk::SurfaceKHR,
+    surface_loader: &ash::extensions::khr::Surface,
+    diagnostics: &DiagnosticExtensions,
+) -> Result<(ash::Device, QueueFamilyIndices)> {
+    let (device, indices, _, _) = create_logical_device_with(
+        physical_device,
+        instance,
+        surface,
+        surface_loader,
+        &DeviceRequirements::default(),
+        diagnostics,
+    )?;
+    Ok((device, indices))
+}
+
+/// Creates the device with the features and extensions of `requirements`, fails naming every missing required one.
+/// Returns the features and extensions that were enabled, the required ones and the supported optional ones.
+///
+/// # Safety
+/// As for `create_logical_device_with_diagnostics`.
+pub unsafe fn create_logical_device_with(
+    physical_device: vk::PhysicalDevice,
+    instance: &ash::Instance,
+    surface: vk::SurfaceKHR,
+    surface_loader: &ash::extensions::khr::Surface,
+    requirements: &DeviceRequirements,
+    diagnostics: &DiagnosticExtensions,
+) -> Result<(ash::Device, QueueFamilyIndices, DeviceFeatures, EnabledExtensions)> {
+    let enabled_features = requirements.check(instance, physical_device)?;
+    let mut extensions = requirements.extensions.clone();
+    for name in support::EXTENSION_SUPPORT_ARRAY_NAME
+        .iter()
+        .chain(&diagnostics.extension_names())
+    {
+        extensions = extensions.require_name(name);
+    }
+    let enabled_extensions = extensions.negotiate_device(instance, physical_device)?;
     let queue_priorities = [1.0];
-    let indices = QueueFamilyIndices::find_queue_family(physical_device, instance, &surface_loader, &surface)?;
+    let indices = QueueFamilyIndices::find_queue_family(physical_device, instance, surface_loader, &surface)?;
     // Create the queue info with the correct queue priorities
     let mut queues_infos = vec![];
 
@@ -136,36 +322,49 @@ pub unsafe fn create_logical_device(
         queues_infos.push(queue_info);
     }
 
-    let feature_info = vk::PhysicalDeviceFeatures::default();
+    let mut feature_chain = FeatureChain {
+        api_version: instance.get_physical_device_properties(physical_device).api_version,
+        ..Default::default()
+    };
+    enabled_features.enable_in(&mut feature_chain);
 
-    let mut extension_names = vec![];
-    for extension_required in constant::support::EXTENSION_SUPPORT_ARRAY_BYTES {
-        extension_names.push(CStr::from_bytes_with_nul_unchecked(*extension_required));
-    }
-    let extension_names_raw: Vec<*const c_char> = extension_names.iter().map(|raw_name| raw_name.as_ptr()).collect();
+    let extension_names_raw = enabled_extensions.as_ptrs();
 
+    let mut fault_features = diagnostics.fault_features();
+    let fault_next = if diagnostics.device_fault {
+        &mut fault_features as *mut _ as *mut c_void
+    } else {
+        ptr::null_mut()
+    };
+    // PhysicalDeviceFeatures2 in the chain replaces p_enabled_features
+    let features2 = feature_chain.link(fault_next);
     let device_info = vk::DeviceCreateInfo {
         s_type: vk::StructureType::DEVICE_CREATE_INFO,
-        p_next: ptr::null(),
+        p_next: &features2 as *const _ as *const c_void,
         flags: vk::DeviceCreateFlags::empty(),
         queue_create_info_count: queues_infos.len() as u32,
         p_queue_create_infos: queues_infos.as_ptr(),
-        enabled_layer_count: 0,
-        pp_enabled_layer_names: ptr::null(),
         enabled_extension_count: extension_names_raw.len() as u32,
         pp_enabled_extension_names: extension_names_raw.as_ptr(),
-        p_enabled_features: &feature_info,
+        p_enabled_features: ptr::null(),
+        // the deprecated device layer fields stay zero
+        ..Default::default()
     };
 
-    let device = instance.create_device(physical_device, &device_info, None)?;
-    Ok((device, indices))
+    let device = instance
+        .create_device(physical_device, &device_info, None)
+        .map_err(Error::DeviceCreation)?;
+    if enabled_features != DeviceFeatures::default() {
+        log::info!("Enabled device features: {}", enabled_features.names().join(", "));
+    }
+    Ok((device, indices, enabled_features, enabled_extensions))
 }
 
 pub fn get_version_api(api: u32) -> (u32, u32, u32, u32) {
     let variant = api >> 29;
     let major = api >> 22;
-    let minor = (api >> 12) & (0x3FF as u32);
-    let patch = api & (0xFF as u32);
+    let minor = (api >> 12) & 0x3FF;
+    let patch = api & 0xFF;
 
     (variant, major, minor, patch)
 }
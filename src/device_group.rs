@@ -0,0 +1,425 @@
+use std::{ffi::c_void, ptr};
+
+use crate::error::{Error, Result};
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    device,
+    extensions::EnabledExtensions,
+    features::{DeviceFeatures, DeviceRequirements, FeatureChain},
+    utility,
+};
+
+/// Physical devices the driver can drive as one logical device, like GPUs linked with a bridge.
+/// Most systems have one group per GPU.
+#[derive(Clone, Debug)]
+pub struct DeviceGroup {
+    /// in `vkEnumeratePhysicalDeviceGroups` order
+    pub index: usize,
+    pub physical_devices: Vec<vk::PhysicalDevice>,
+    pub names: Vec<String>,
+    /// whether memory can be allocated on some of the devices only
+    pub subset_allocation: bool,
+}
+
+impl DeviceGroup {
+    pub fn device_count(&self) -> u32 {
+        self.physical_devices.len() as u32
+    }
+
+    pub fn is_multi_gpu(&self) -> bool {
+        self.physical_devices.len() > 1
+    }
+
+    /// Device mask with every device of the group.
+    pub fn all_devices_mask(&self) -> u32 {
+        (1 << self.device_count()) - 1
+    }
+}
+
+/// The device groups, logged with their devices.
+///
+/// # Safety
+/// `instance` has to be created with Vulkan 1.1 or VK_KHR_device_group_creation.
+pub unsafe fn enumerate_device_groups(instance: &ash::Instance) -> Result<Vec<DeviceGroup>> {
+    let mut properties =
+        vec![vk::PhysicalDeviceGroupProperties::default(); instance.enumerate_physical_device_groups_len()?];
+    instance.enumerate_physical_device_groups(&mut properties)?;
+
+    let groups: Vec<DeviceGroup> = properties
+        .iter()
+        .enumerate()
+        .map(|(index, group)| {
+            let physical_devices = group.physical_devices[..group.physical_device_count as usize].to_vec();
+            let names = physical_devices
+                .iter()
+                .map(|physical_device| {
+                    utility::vk_to_string(&instance.get_physical_device_properties(*physical_device).device_name)
+                })
+                .collect();
+            DeviceGroup {
+                index,
+                physical_devices,
+                names,
+                subset_allocation: group.subset_allocation == vk::TRUE,
+            }
+        })
+        .collect();
+    for group in &groups {
+        log::info!("Device group {}: {}", group.index, group.names.join(", "));
+    }
+    Ok(groups)
+}
+
+/// How `local_device` can use memory of heap `heap_index` that lives on `remote_device`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerMemory {
+    pub heap_index: u32,
+    pub local_device: u32,
+    pub remote_device: u32,
+    pub features: vk::PeerMemoryFeatureFlags,
+}
+
+/// Peer memory features of every heap between every two devices of `group`, empty for single GPU groups.
+/// `device` has to be created from the whole group, like with `GroupDevice::new`.
+///
+/// # Safety
+/// `group` has to be a group of `instance`.
+pub unsafe fn peer_memory_features(instance: &ash::Instance, device: &ash::Device, group: &DeviceGroup) -> Vec<PeerMemory> {
+    let Some(first) = group.physical_devices.first() else {
+        return vec![];
+    };
+    let heap_count = instance.get_physical_device_memory_properties(*first).memory_heap_count;
+    let mut peers = vec![];
+    for heap_index in 0..heap_count {
+        for local_device in 0..group.device_count() {
+            for remote_device in (0..group.device_count()).filter(|remote| *remote != local_device) {
+                let features = device.get_device_group_peer_memory_features(heap_index, local_device, remote_device);
+                log::info!(
+                    "Heap {} of device {} used by device {}: {:?}",
+                    heap_index,
+                    remote_device,
+                    local_device,
+                    features
+                );
+                peers.push(PeerMemory {
+                    heap_index,
+                    local_device,
+                    remote_device,
+                    features,
+                });
+            }
+        }
+    }
+    peers
+}
+
+/// The family with `flags` and the fewest other capabilities, so compute finds a dedicated compute family first.
+///
+/// # Safety
+/// `physical_device` has to be enumerated from `instance`.
+pub unsafe fn find_queue_family(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    flags: vk::QueueFlags,
+) -> Option<u32> {
+    instance
+        .get_physical_device_queue_family_properties(physical_device)
+        .iter()
+        .enumerate()
+        .filter(|(_, family)| family.queue_count > 0 && family.queue_flags.contains(flags))
+        .min_by_key(|(_, family)| family.queue_flags.as_raw().count_ones())
+        .map(|(index, _)| index as u32)
+}
+
+/// Creates a device over `physical_devices` with one queue of `queue_family`, a device group when there are more.
+unsafe fn create_device(
+    instance: &ash::Instance,
+    physical_devices: &[vk::PhysicalDevice],
+    queue_family: u32,
+    requirements: &DeviceRequirements,
+) -> Result<(ash::Device, DeviceFeatures, EnabledExtensions)> {
+    let physical_device = physical_devices[0];
+    let enabled_features = requirements.check(instance, physical_device)?;
+    let enabled_extensions = requirements.extensions.negotiate_device(instance, physical_device)?;
+
+    let queue_priorities = [1.0];
+    let queue_info = vk::DeviceQueueCreateInfo {
+        s_type: vk::StructureType::DEVICE_QUEUE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::DeviceQueueCreateFlags::empty(),
+        queue_family_index: queue_family,
+        queue_count: 1,
+        p_queue_priorities: queue_priorities.as_ptr(),
+    };
+
+    let mut group_info = vk::DeviceGroupDeviceCreateInfo {
+        s_type: vk::StructureType::DEVICE_GROUP_DEVICE_CREATE_INFO,
+        p_next: ptr::null(),
+        physical_device_count: physical_devices.len() as u32,
+        p_physical_devices: physical_devices.as_ptr(),
+    };
+    let group_next = if physical_devices.len() > 1 {
+        &mut group_info as *mut _ as *mut c_void
+    } else {
+        ptr::null_mut()
+    };
+
+    let mut feature_chain = FeatureChain {
+        api_version: instance.get_physical_device_properties(physical_device).api_version,
+        ..Default::default()
+    };
+    enabled_features.enable_in(&mut feature_chain);
+    let features2 = feature_chain.link(group_next);
+
+    let extension_names_raw = enabled_extensions.as_ptrs();
+    let device_info = vk::DeviceCreateInfo {
+        s_type: vk::StructureType::DEVICE_CREATE_INFO,
+        p_next: &features2 as *const _ as *const c_void,
+        flags: vk::DeviceCreateFlags::empty(),
+        queue_create_info_count: 1,
+        p_queue_create_infos: &queue_info,
+        enabled_extension_count: extension_names_raw.len() as u32,
+        pp_enabled_extension_names: extension_names_raw.as_ptr(),
+        p_enabled_features: ptr::null(),
+        // layers are instance wide
+        ..Default::default()
+    };
+    let device = instance
+        .create_device(physical_device, &device_info, None)
+        .map_err(Error::DeviceCreation)?;
+    Ok((device, enabled_features, enabled_extensions))
+}
+
+/// One logical device over every GPU of a group, device masks pick which of them run a command buffer.
+pub struct GroupDevice {
+    pub group: DeviceGroup,
+    pub device: ash::Device,
+    pub queue_family: u32,
+    pub queue: vk::Queue,
+    pub features: DeviceFeatures,
+    pub extensions: EnabledExtensions,
+}
+
+impl GroupDevice {
+    /// Creates the device with a queue that has `queue_flags`, checking `requirements` on the first device.
+    ///
+    /// # Safety
+    /// `group` has to be from `enumerate_device_groups` of `instance`, the device goes with `destroy`.
+    pub unsafe fn new(
+        instance: &ash::Instance,
+        group: &DeviceGroup,
+        queue_flags: vk::QueueFlags,
+        requirements: &DeviceRequirements,
+    ) -> Result<Self> {
+        let physical_device = *group
+            .physical_devices
+            .first()
+            .ok_or_else(|| Error::NoSuitableDevice(format!("The device group {} has no devices", group.index)))?;
+        let queue_family = find_queue_family(instance, physical_device, queue_flags).ok_or_else(|| {
+            Error::NoSuitableDevice(format!("The device group {} has no {:?} queue", group.index, queue_flags))
+        })?;
+        let (device, features, extensions) = create_device(instance, &group.physical_devices, queue_family, requirements)?;
+        log::info!("Created a device over {} GPUs of group {}", group.device_count(), group.index);
+        Ok(Self {
+            group: group.clone(),
+            queue: device.get_device_queue(queue_family, 0),
+            device,
+            queue_family,
+            features,
+            extensions,
+        })
+    }
+
+    /// # Safety
+    /// `instance` has to be the one the group was enumerated from.
+    pub unsafe fn peer_memory_features(&self, instance: &ash::Instance) -> Vec<PeerMemory> {
+        peer_memory_features(instance, &self.device, &self.group)
+    }
+
+    /// # Safety
+    /// Every object of the device has to be destroyed and its queues idle.
+    pub unsafe fn destroy(&self) {
+        self.device.destroy_device(None);
+    }
+}
+
+/// Alternate frame rendering over the devices of a `GroupDevice`, frame n runs on device n modulo the count.
+/// Only the work is split: presenting from other devices than the first one needs device group swapchains,
+/// which aren't set up here, so copy the results to device 0 or use it for offscreen work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AlternateFrames {
+    device_count: u32,
+}
+
+impl AlternateFrames {
+    pub fn new(device_count: u32) -> Self {
+        Self {
+            device_count: device_count.max(1),
+        }
+    }
+
+    pub fn device_count(&self) -> u32 {
+        self.device_count
+    }
+
+    pub fn device_index(&self, frame: u64) -> u32 {
+        (frame % self.device_count as u64) as u32
+    }
+
+    pub fn device_mask(&self, frame: u64) -> u32 {
+        1 << self.device_index(frame)
+    }
+
+    /// Begins `command_buffer` so it only runs on the device of `frame`.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be a primary command buffer of the device in the initial state.
+    pub unsafe fn begin(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: u64,
+        flags: vk::CommandBufferUsageFlags,
+    ) -> VkResult<()> {
+        begin_on_devices(device, command_buffer, self.device_mask(frame), flags)
+    }
+
+    /// Submit info for command buffers begun with `begin`, waiting and signaling on the same device.
+    pub fn submit(&self, frame: u64, command_buffers: usize, waits: usize, signals: usize) -> DeviceGroupSubmit {
+        DeviceGroupSubmit::on_device(self.device_index(frame), command_buffers, waits, signals)
+    }
+}
+
+/// Begins `command_buffer` to run on the devices in `device_mask`.
+///
+/// # Safety
+/// As for `AlternateFrames::begin`, `device_mask` may only name devices of the group.
+pub unsafe fn begin_on_devices(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    device_mask: u32,
+    flags: vk::CommandBufferUsageFlags,
+) -> VkResult<()> {
+    let group_info = vk::DeviceGroupCommandBufferBeginInfo {
+        s_type: vk::StructureType::DEVICE_GROUP_COMMAND_BUFFER_BEGIN_INFO,
+        p_next: ptr::null(),
+        device_mask,
+    };
+    let begin_info = vk::CommandBufferBeginInfo {
+        s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+        p_next: &group_info as *const _ as *const c_void,
+        flags,
+        p_inheritance_info: ptr::null(),
+    };
+    device.begin_command_buffer(command_buffer, &begin_info)
+}
+
+/// Which devices a group submission waits, runs and signals on, one entry per semaphore and command buffer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceGroupSubmit {
+    pub wait_device_indices: Vec<u32>,
+    pub command_buffer_device_masks: Vec<u32>,
+    pub signal_device_indices: Vec<u32>,
+}
+
+impl DeviceGroupSubmit {
+    /// Everything on device `device_index`.
+    pub fn on_device(device_index: u32, command_buffers: usize, waits: usize, signals: usize) -> Self {
+        Self {
+            wait_device_indices: vec![device_index; waits],
+            command_buffer_device_masks: vec![1 << device_index; command_buffers],
+            signal_device_indices: vec![device_index; signals],
+        }
+    }
+
+    /// Chain into the `p_next` of `vk::SubmitInfo`, `self` must outlive the submission call.
+    pub fn info(&self) -> vk::DeviceGroupSubmitInfo {
+        vk::DeviceGroupSubmitInfo {
+            s_type: vk::StructureType::DEVICE_GROUP_SUBMIT_INFO,
+            p_next: ptr::null(),
+            wait_semaphore_count: self.wait_device_indices.len() as u32,
+            p_wait_semaphore_device_indices: self.wait_device_indices.as_ptr(),
+            command_buffer_count: self.command_buffer_device_masks.len() as u32,
+            p_command_buffer_device_masks: self.command_buffer_device_masks.as_ptr(),
+            signal_semaphore_count: self.signal_device_indices.len() as u32,
+            p_signal_semaphore_device_indices: self.signal_device_indices.as_ptr(),
+        }
+    }
+}
+
+/// A device of its own on another GPU than the rendering one, for compute work like simulation or baking.
+/// It shares no memory with the rendering device, results travel through host memory.
+pub struct ComputeDevice {
+    pub physical_device: vk::PhysicalDevice,
+    pub name: String,
+    pub device: ash::Device,
+    pub queue_family: u32,
+    pub queue: vk::Queue,
+    pub features: DeviceFeatures,
+    pub extensions: EnabledExtensions,
+}
+
+impl ComputeDevice {
+    /// Creates a device with one compute queue on `physical_device`.
+    ///
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance`, the device goes with `destroy`.
+    pub unsafe fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        requirements: &DeviceRequirements,
+    ) -> Result<Self> {
+        let name = utility::vk_to_string(&instance.get_physical_device_properties(physical_device).device_name);
+        let queue_family = find_queue_family(instance, physical_device, vk::QueueFlags::COMPUTE)
+            .ok_or_else(|| Error::NoSuitableDevice(format!("The device {} has no compute queue", name)))?;
+        let (device, features, extensions) = create_device(instance, &[physical_device], queue_family, requirements)?;
+        log::info!("Using {} for compute, queue family {}", name, queue_family);
+        Ok(Self {
+            physical_device,
+            name,
+            queue: device.get_device_queue(queue_family, 0),
+            device,
+            queue_family,
+            features,
+            extensions,
+        })
+    }
+
+    /// The best other GPU than `primary` that meets `requirements`, None on single GPU systems.
+    ///
+    /// # Safety
+    /// As for `new`, the device that's returned has to be destroyed before `instance`.
+    pub unsafe fn secondary(
+        instance: &ash::Instance,
+        primary: vk::PhysicalDevice,
+        requirements: &DeviceRequirements,
+    ) -> Result<Option<Self>> {
+        let mut others: Vec<vk::PhysicalDevice> = instance
+            .enumerate_physical_devices()?
+            .into_iter()
+            .filter(|physical_device| *physical_device != primary)
+            .filter(|physical_device| {
+                instance.get_physical_device_properties(*physical_device).device_type != vk::PhysicalDeviceType::CPU
+            })
+            .collect();
+        others.sort_by_key(|physical_device| {
+            std::cmp::Reverse(device::type_score(
+                instance.get_physical_device_properties(*physical_device).device_type,
+            ))
+        });
+        for physical_device in others {
+            match Self::new(instance, physical_device, requirements) {
+                Ok(compute) => return Ok(Some(compute)),
+                Err(e) => log::warn!("Skipping device for compute: {}", e),
+            }
+        }
+        Ok(None)
+    }
+
+    /// # Safety
+    /// Nothing may still run on the compute queue and every object of the device has to be destroyed.
+    pub unsafe fn destroy(&self) {
+        self.device.destroy_device(None);
+    }
+}
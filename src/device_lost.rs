@@ -0,0 +1,552 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    ffi::{c_void, CStr, CString},
+    fmt, fs, mem,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::Result;
+use ash::{
+    extensions::{ext::DebugUtils, nv::DeviceDiagnosticCheckpoints},
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags},
+};
+
+use crate::{buffer, device::get_version_api, utility};
+
+/// Number of breadcrumbs kept for the report, older ones are dropped.
+pub const HISTORY_LENGTH: usize = 256;
+/// Recoveries allowed per `RecoveryPolicy::window`, the default of 0 turns recovery off.
+pub const RECOVERIES_ENV: &str = "VULKY_DEVICE_LOST_RECOVERIES";
+
+pub fn is_device_lost(result: vk::Result) -> bool {
+    result == vk::Result::ERROR_DEVICE_LOST
+}
+
+/// Device extensions that tell more about a lost device, all of them are optional.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DiagnosticExtensions {
+    /// VK_EXT_device_fault, the driver describes the fault and the faulting addresses
+    pub device_fault: bool,
+    /// VK_NV_device_diagnostic_checkpoints, the last marker every queue passed
+    pub nv_checkpoints: bool,
+    /// VK_AMD_buffer_marker, markers the GPU writes into host visible memory as it passes them
+    pub amd_buffer_marker: bool,
+}
+
+impl DiagnosticExtensions {
+    /// The diagnostic extensions `physical_device` has.
+    ///
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance`.
+    pub unsafe fn supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> VkResult<Self> {
+        let names: HashSet<String> = instance
+            .enumerate_device_extension_properties(physical_device)?
+            .iter()
+            .map(|extension| utility::vk_to_string(&extension.extension_name))
+            .collect();
+        let has = |name: &CStr| names.contains(name.to_str().unwrap_or_default());
+        Ok(Self {
+            device_fault: has(vk::ExtDeviceFaultFn::name()),
+            nv_checkpoints: has(DeviceDiagnosticCheckpoints::name()),
+            amd_buffer_marker: has(vk::AmdBufferMarkerFn::name()),
+        })
+    }
+
+    /// Names to enable at device creation, `device_fault` also needs `fault_features` in the create info chain.
+    pub fn extension_names(&self) -> Vec<&'static CStr> {
+        let mut names = vec![];
+        if self.device_fault {
+            names.push(vk::ExtDeviceFaultFn::name());
+        }
+        if self.nv_checkpoints {
+            names.push(DeviceDiagnosticCheckpoints::name());
+        }
+        if self.amd_buffer_marker {
+            names.push(vk::AmdBufferMarkerFn::name());
+        }
+        names
+    }
+
+    pub fn fault_features(&self) -> vk::PhysicalDeviceFaultFeaturesEXT {
+        vk::PhysicalDeviceFaultFeaturesEXT {
+            device_fault: self.device_fault as vk::Bool32,
+            ..Default::default()
+        }
+    }
+}
+
+/// Something the application did shortly before the device was lost.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Breadcrumb {
+    BeginLabel(String),
+    EndLabel(String),
+    Submit { queue: String, passes: Vec<String> },
+    FrameEnd(u64),
+}
+
+impl fmt::Display for Breadcrumb {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breadcrumb::BeginLabel(name) => write!(f, "begin {}", name),
+            Breadcrumb::EndLabel(name) => write!(f, "end {}", name),
+            Breadcrumb::Submit { queue, passes } => write!(f, "submit to {}: {}", queue, passes.join(", ")),
+            Breadcrumb::FrameEnd(frame) => write!(f, "end of frame {}", frame),
+        }
+    }
+}
+
+/// Marker values are `(name index + 1) * 2`, plus one for the end of a label, so none is 0 or null.
+fn encode_marker(index: usize, end: bool) -> u32 {
+    (index as u32 + 1) * 2 + end as u32
+}
+
+/// VK_AMD_buffer_marker state, two markers: the last label the GPU started and the last one it finished.
+struct AmdMarkers {
+    fp: vk::AmdBufferMarkerFn,
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *const u32,
+}
+
+unsafe impl Send for AmdMarkers {}
+unsafe impl Sync for AmdMarkers {}
+
+/// Collects what a device lost report needs: debug labels, submitted passes and whatever the
+/// diagnostic extensions record. Label command buffers through it instead of calling DebugUtils directly.
+pub struct DeviceLostTracker {
+    extensions: DiagnosticExtensions,
+    device_description: String,
+    debug_utils: Option<DebugUtils>,
+    checkpoints: Option<DeviceDiagnosticCheckpoints>,
+    amd_markers: Option<AmdMarkers>,
+    /// label names by marker index, and index by name
+    names: Mutex<(Vec<String>, HashMap<String, usize>)>,
+    history: Mutex<VecDeque<Breadcrumb>>,
+    /// labels begun but not ended yet in recording order
+    open_labels: Mutex<Vec<String>>,
+    frame: AtomicU64,
+}
+
+impl DeviceLostTracker {
+    /// `extensions` have to be enabled on `device`, `debug_utils` needs VK_EXT_debug_utils on the instance.
+    ///
+    /// # Safety
+    /// `device` has to be created from `physical_device` with `extensions`.
+    pub unsafe fn new(
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        device: &ash::Device,
+        extensions: DiagnosticExtensions,
+        debug_utils: Option<DebugUtils>,
+    ) -> VkResult<DeviceLostTracker> {
+        let properties = instance.get_physical_device_properties(physical_device);
+        let (_, major, minor, patch) = get_version_api(properties.driver_version);
+        let device_description = format!(
+            "{}, vendor 0x{:04x}, device 0x{:04x}, driver {}.{}.{}",
+            utility::vk_to_string(&properties.device_name),
+            properties.vendor_id,
+            properties.device_id,
+            major,
+            minor,
+            patch
+        );
+
+        let checkpoints = extensions
+            .nv_checkpoints
+            .then(|| DeviceDiagnosticCheckpoints::new(instance, device));
+        let amd_markers = if extensions.amd_buffer_marker {
+            let fp = vk::AmdBufferMarkerFn::load(|name| {
+                mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr()))
+            });
+            let size = (2 * mem::size_of::<u32>()) as vk::DeviceSize;
+            let (buffer, memory) = buffer::create_buffer(
+                device,
+                instance,
+                physical_device,
+                size,
+                BufferUsageFlags::TRANSFER_DST,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let mapped = device.map_memory(memory, 0, size, MemoryMapFlags::empty())? as *mut u32;
+            ptr::write_bytes(mapped, 0, 2);
+            Some(AmdMarkers {
+                fp,
+                buffer,
+                memory,
+                mapped,
+            })
+        } else {
+            None
+        };
+
+        Ok(DeviceLostTracker {
+            extensions,
+            device_description,
+            debug_utils,
+            checkpoints,
+            amd_markers,
+            names: Mutex::new((vec![], HashMap::new())),
+            history: Mutex::new(VecDeque::with_capacity(HISTORY_LENGTH)),
+            open_labels: Mutex::new(vec![]),
+            frame: AtomicU64::new(0),
+        })
+    }
+
+    pub fn extensions(&self) -> DiagnosticExtensions {
+        self.extensions
+    }
+
+    fn push(&self, breadcrumb: Breadcrumb) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_LENGTH {
+            history.pop_front();
+        }
+        history.push_back(breadcrumb);
+    }
+
+    fn marker_index(&self, name: &str) -> usize {
+        let mut names = self.names.lock().unwrap();
+        if let Some(&index) = names.1.get(name) {
+            return index;
+        }
+        let index = names.0.len();
+        names.0.push(name.to_owned());
+        names.1.insert(name.to_owned(), index);
+        index
+    }
+
+    fn marker_name(&self, marker: u32) -> Option<String> {
+        let index = (marker / 2).checked_sub(1)? as usize;
+        let names = self.names.lock().unwrap();
+        let name = names.0.get(index)?;
+        Some(if marker % 2 == 1 {
+            format!("end of {}", name)
+        } else {
+            name.clone()
+        })
+    }
+
+    unsafe fn write_marker(&self, command_buffer: vk::CommandBuffer, index: usize, end: bool) {
+        let marker = encode_marker(index, end);
+        if let Some(checkpoints) = self.checkpoints.as_ref() {
+            checkpoints.cmd_set_checkpoint(command_buffer, marker as usize as *const c_void);
+        }
+        if let Some(amd) = self.amd_markers.as_ref() {
+            let (stage, offset) = if end {
+                (
+                    vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                    mem::size_of::<u32>() as vk::DeviceSize,
+                )
+            } else {
+                (vk::PipelineStageFlags::TOP_OF_PIPE, 0)
+            };
+            (amd.fp.cmd_write_buffer_marker_amd)(command_buffer, stage, amd.buffer, offset, marker);
+        }
+    }
+
+    /// Opens a debug label that RenderDoc and validation messages show, and marks it for the report.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording.
+    pub unsafe fn begin_label(&self, command_buffer: vk::CommandBuffer, name: &str) {
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            let label_name = CString::new(name).unwrap_or_default();
+            let label = vk::DebugUtilsLabelEXT {
+                p_label_name: label_name.as_ptr(),
+                ..Default::default()
+            };
+            debug_utils.cmd_begin_debug_utils_label(command_buffer, &label);
+        }
+        let index = self.marker_index(name);
+        self.write_marker(command_buffer, index, false);
+        self.open_labels.lock().unwrap().push(name.to_owned());
+        self.push(Breadcrumb::BeginLabel(name.to_owned()));
+    }
+
+    /// Closes the newest label of `begin_label`.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording with an open label.
+    pub unsafe fn end_label(&self, command_buffer: vk::CommandBuffer) {
+        let Some(name) = self.open_labels.lock().unwrap().pop() else {
+            return;
+        };
+        if let Some(debug_utils) = self.debug_utils.as_ref() {
+            debug_utils.cmd_end_debug_utils_label(command_buffer);
+        }
+        let index = self.marker_index(&name);
+        self.write_marker(command_buffer, index, true);
+        self.push(Breadcrumb::EndLabel(name));
+    }
+
+    /// Notes the passes of a submission to `queue`, call it right before `vkQueueSubmit`.
+    pub fn submitted(&self, queue: &str, passes: &[&str]) {
+        self.push(Breadcrumb::Submit {
+            queue: queue.to_owned(),
+            passes: passes.iter().map(|pass| pass.to_string()).collect(),
+        });
+    }
+
+    pub fn end_frame(&self) {
+        let frame = self.frame.fetch_add(1, Ordering::Relaxed);
+        self.push(Breadcrumb::FrameEnd(frame));
+    }
+
+    /// Everything known about the loss, `queues` are the named queues to read NV checkpoints of.
+    ///
+    /// # Safety
+    /// The queues have to be queues of `device`.
+    pub unsafe fn report(
+        &self,
+        instance: &ash::Instance,
+        device: &ash::Device,
+        error: vk::Result,
+        queues: &[(&str, vk::Queue)],
+    ) -> CrashReport {
+        let mut checkpoints = vec![];
+        if let Some(nv) = self.checkpoints.as_ref() {
+            for &(queue_name, queue) in queues {
+                let mut data = vec![vk::CheckpointDataNV::default(); nv.get_queue_checkpoint_data_len(queue)];
+                nv.get_queue_checkpoint_data(queue, &mut data);
+                for checkpoint in data {
+                    let marker = checkpoint.p_checkpoint_marker as usize as u32;
+                    checkpoints.push(Checkpoint {
+                        queue: queue_name.to_owned(),
+                        stage: checkpoint.stage,
+                        marker: self
+                            .marker_name(marker)
+                            .unwrap_or_else(|| format!("unknown marker {}", marker)),
+                    });
+                }
+            }
+        }
+
+        let gpu_markers = self.amd_markers.as_ref().map(|amd| {
+            let started = ptr::read_volatile(amd.mapped);
+            let finished = ptr::read_volatile(amd.mapped.add(1));
+            (self.marker_name(started), self.marker_name(finished))
+        });
+
+        CrashReport {
+            error,
+            time: SystemTime::now(),
+            device: self.device_description.clone(),
+            history: self.history.lock().unwrap().iter().cloned().collect(),
+            open_labels: self.open_labels.lock().unwrap().clone(),
+            checkpoints,
+            last_started: gpu_markers.as_ref().and_then(|markers| markers.0.clone()),
+            last_finished: gpu_markers.and_then(|markers| markers.1),
+            fault: if self.extensions.device_fault {
+                query_device_fault(instance, device)
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Forgets the labels and history, after recreating the device.
+    pub fn clear(&self) {
+        self.history.lock().unwrap().clear();
+        self.open_labels.lock().unwrap().clear();
+    }
+
+    /// # Safety
+    /// No submission may use the markers of the tracker anymore.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        if let Some(amd) = self.amd_markers.as_ref() {
+            device.unmap_memory(amd.memory);
+            device.destroy_buffer(amd.buffer, None);
+            device.free_memory(amd.memory, None);
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    pub queue: String,
+    pub stage: vk::PipelineStageFlags,
+    pub marker: String,
+}
+
+/// What VK_EXT_device_fault reported, the vendor binary isn't requested.
+#[derive(Clone, Debug)]
+pub struct DeviceFault {
+    pub description: String,
+    pub addresses: Vec<vk::DeviceFaultAddressInfoEXT>,
+    /// description, fault code and fault data of every vendor specific record
+    pub vendor_infos: Vec<(String, u64, u64)>,
+}
+
+unsafe fn query_device_fault(instance: &ash::Instance, device: &ash::Device) -> Option<DeviceFault> {
+    let fp =
+        vk::ExtDeviceFaultFn::load(|name| mem::transmute(instance.get_device_proc_addr(device.handle(), name.as_ptr())));
+    let mut counts = vk::DeviceFaultCountsEXT::default();
+    let result = (fp.get_device_fault_info_ext)(device.handle(), &mut counts, ptr::null_mut());
+    if result != vk::Result::SUCCESS {
+        return None;
+    }
+    let mut addresses = vec![vk::DeviceFaultAddressInfoEXT::default(); counts.address_info_count as usize];
+    let mut vendor_infos = vec![vk::DeviceFaultVendorInfoEXT::default(); counts.vendor_info_count as usize];
+    counts.vendor_binary_size = 0;
+    let mut info = vk::DeviceFaultInfoEXT {
+        p_address_infos: addresses.as_mut_ptr(),
+        p_vendor_infos: vendor_infos.as_mut_ptr(),
+        ..Default::default()
+    };
+    // INCOMPLETE still fills what fits
+    let result = (fp.get_device_fault_info_ext)(device.handle(), &mut counts, &mut info);
+    if result != vk::Result::SUCCESS && result != vk::Result::INCOMPLETE {
+        return None;
+    }
+    addresses.truncate(counts.address_info_count as usize);
+    vendor_infos.truncate(counts.vendor_info_count as usize);
+    Some(DeviceFault {
+        description: utility::vk_to_string(&info.description),
+        addresses,
+        vendor_infos: vendor_infos
+            .iter()
+            .map(|vendor| {
+                (
+                    utility::vk_to_string(&vendor.description),
+                    vendor.vendor_fault_code,
+                    vendor.vendor_fault_data,
+                )
+            })
+            .collect(),
+    })
+}
+
+/// Everything collected when the device was lost, `Display` formats it for a log or a file.
+#[derive(Clone, Debug)]
+pub struct CrashReport {
+    pub error: vk::Result,
+    pub time: SystemTime,
+    pub device: String,
+    /// oldest first
+    pub history: Vec<Breadcrumb>,
+    /// labels recorded but not ended, the innermost last
+    pub open_labels: Vec<String>,
+    /// NV checkpoints every queue passed last
+    pub checkpoints: Vec<Checkpoint>,
+    /// AMD buffer markers, the label the GPU started last and the label it finished last
+    pub last_started: Option<String>,
+    pub last_finished: Option<String>,
+    pub fault: Option<DeviceFault>,
+}
+
+impl CrashReport {
+    /// Writes the report to `vulky-crash-<unix seconds>.txt` in `directory` and returns its path.
+    pub fn write(&self, directory: &Path) -> Result<PathBuf> {
+        let seconds = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        fs::create_dir_all(directory)?;
+        let path = directory.join(format!("vulky-crash-{}.txt", seconds));
+        fs::write(&path, self.to_string())?;
+        Ok(path)
+    }
+}
+
+impl fmt::Display for CrashReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let seconds = self.time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        writeln!(f, "{:?} at unix time {}", self.error, seconds)?;
+        writeln!(f, "device: {}", self.device)?;
+
+        if let Some(fault) = self.fault.as_ref() {
+            writeln!(f, "device fault: {}", fault.description)?;
+            for address in fault.addresses.iter() {
+                writeln!(
+                    f,
+                    "  {:?} at 0x{:016x} +- 0x{:x}",
+                    address.address_type, address.reported_address, address.address_precision
+                )?;
+            }
+            for (description, code, data) in fault.vendor_infos.iter() {
+                writeln!(f, "  vendor: {} code 0x{:x} data 0x{:x}", description, code, data)?;
+            }
+        }
+        if self.last_started.is_some() || self.last_finished.is_some() {
+            let or_none = |marker: &Option<String>| marker.clone().unwrap_or_else(|| "none".to_owned());
+            writeln!(
+                f,
+                "GPU markers: last started {}, last finished {}",
+                or_none(&self.last_started),
+                or_none(&self.last_finished)
+            )?;
+        }
+        if !self.checkpoints.is_empty() {
+            writeln!(f, "checkpoints:")?;
+            for checkpoint in self.checkpoints.iter() {
+                writeln!(
+                    f,
+                    "  {} passed {} at {:?}",
+                    checkpoint.queue, checkpoint.marker, checkpoint.stage
+                )?;
+            }
+        }
+        if !self.open_labels.is_empty() {
+            writeln!(f, "open labels: {}", self.open_labels.join(" > "))?;
+        }
+        writeln!(f, "last {} events:", self.history.len())?;
+        for breadcrumb in self.history.iter() {
+            writeln!(f, "  {}", breadcrumb)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether to rebuild the device after losing it. Drivers reset after a hang, so one loss can be survived,
+/// a device that keeps getting lost means the application itself hangs the GPU and should stop.
+#[derive(Clone, Debug)]
+pub struct RecoveryPolicy {
+    /// recoveries allowed within `window`, 0 never recovers
+    pub max_recoveries: usize,
+    pub window: Duration,
+    recoveries: Vec<Instant>,
+}
+
+impl Default for RecoveryPolicy {
+    fn default() -> Self {
+        Self::new(0, Duration::from_secs(600))
+    }
+}
+
+impl RecoveryPolicy {
+    pub fn new(max_recoveries: usize, window: Duration) -> Self {
+        Self {
+            max_recoveries,
+            window,
+            recoveries: vec![],
+        }
+    }
+
+    /// Allows `VULKY_DEVICE_LOST_RECOVERIES` recoveries every 10 minutes, none when it isn't set.
+    pub fn from_env() -> Self {
+        let max_recoveries = std::env::var(RECOVERIES_ENV)
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(0);
+        Self {
+            max_recoveries,
+            ..Self::default()
+        }
+    }
+
+    /// Counts a recovery when one is allowed now.
+    pub fn should_recover(&mut self) -> bool {
+        let now = Instant::now();
+        let window = self.window;
+        self.recoveries.retain(|&recovery| now.duration_since(recovery) < window);
+        if self.recoveries.len() >= self.max_recoveries {
+            return false;
+        }
+        self.recoveries.push(now);
+        true
+    }
+}
@@ -0,0 +1,184 @@
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    buffer::MAX_FRAMES_IN_FLIGHT,
+    material::DrawList,
+    secondary::{self, Inheritance},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrawCacheSettings {
+    /// draws per secondary command buffer, smaller chunks re-record less when a single object changes
+    pub chunk_size: usize,
+}
+
+impl Default for DrawCacheSettings {
+    fn default() -> Self {
+        Self { chunk_size: 64 }
+    }
+}
+
+/// Where the secondary command buffers execute, a change re-records every chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PassTarget {
+    pub render_pass: vk::RenderPass,
+    pub subpass: u32,
+    /// null when it isn't known while recording
+    pub framebuffer: vk::Framebuffer,
+    /// viewport and scissor cover it
+    pub extent: vk::Extent2D,
+}
+
+/// Chunks recorded and reused by the last `DrawCache::record`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawCacheStats {
+    pub recorded: usize,
+    pub reused: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct ChunkKey {
+    target: PassTarget,
+    shared_sets: u64,
+    draws: u64,
+}
+
+struct Chunk {
+    command_buffers: [vk::CommandBuffer; MAX_FRAMES_IN_FLIGHT as usize],
+    /// what each frame's command buffer holds, None when it has to be recorded
+    keys: [Option<ChunkKey>; MAX_FRAMES_IN_FLIGHT as usize],
+}
+
+/// Keeps a draw list in secondary command buffers and re-records only the chunks whose draws changed,
+/// so mostly static scenes cost little CPU per frame.
+/// The render pass has to be begun with `vk::SubpassContents::SECONDARY_COMMAND_BUFFERS`.
+pub struct DrawCache {
+    settings: DrawCacheSettings,
+    command_pool: vk::CommandPool,
+    chunks: Vec<Chunk>,
+    stats: DrawCacheStats,
+}
+
+impl DrawCache {
+    /// `queue_family_index` is the family the primary command buffers are submitted to.
+    ///
+    /// # Safety
+    /// `queue_family_index` has to be a family of `device`.
+    pub unsafe fn new(device: &ash::Device, queue_family_index: u32, settings: DrawCacheSettings) -> VkResult<Self> {
+        let pool_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index,
+            ..Default::default()
+        };
+        Ok(Self {
+            settings: DrawCacheSettings {
+                chunk_size: settings.chunk_size.max(1),
+            },
+            command_pool: device.create_command_pool(&pool_info, None)?,
+            chunks: vec![],
+            stats: DrawCacheStats::default(),
+        })
+    }
+
+    /// Sorts `draws` and brings the secondary command buffers of `current_frame` up to date with them.
+    /// Returns the ones to pass to `cmd_execute_commands`, the fence of `current_frame` has to be waited on first.
+    ///
+    /// # Safety
+    /// `target` has to name live objects of `device` and the draws have to use pipelines compatible with it.
+    pub unsafe fn record(
+        &mut self,
+        device: &ash::Device,
+        current_frame: usize,
+        draws: &mut DrawList,
+        shared_sets: &[vk::DescriptorSet],
+        target: &PassTarget,
+    ) -> VkResult<Vec<vk::CommandBuffer>> {
+        draws.sort();
+        let chunk_count = draws.len().div_ceil(self.settings.chunk_size);
+        self.grow(device, chunk_count)?;
+
+        let shared_sets_key = shared_sets
+            .iter()
+            .fold(0u64, |hash, set| hash.rotate_left(17) ^ vk::Handle::as_raw(*set));
+        self.stats = DrawCacheStats::default();
+        let mut command_buffers = Vec::with_capacity(chunk_count);
+        for index in 0..chunk_count {
+            let start = index * self.settings.chunk_size;
+            let range = start..(start + self.settings.chunk_size).min(draws.len());
+            let key = ChunkKey {
+                target: *target,
+                shared_sets: shared_sets_key,
+                draws: draws.signature(current_frame, range.clone()),
+            };
+
+            let chunk = &mut self.chunks[index];
+            let command_buffer = chunk.command_buffers[current_frame];
+            if chunk.keys[current_frame] == Some(key) {
+                self.stats.reused += 1;
+            } else {
+                chunk.keys[current_frame] = None;
+                record_chunk(device, command_buffer, target, |command_buffer| {
+                    draws.record_range(device, command_buffer, current_frame, shared_sets, range)
+                })?;
+                chunk.keys[current_frame] = Some(key);
+                self.stats.recorded += 1;
+            }
+            command_buffers.push(command_buffer);
+        }
+        Ok(command_buffers)
+    }
+
+    pub fn stats(&self) -> DrawCacheStats {
+        self.stats
+    }
+
+    /// Records every chunk again on its next `record`, needed when a pipeline, mesh or descriptor set
+    /// was destroyed and a new one might have gotten the same handle.
+    pub fn invalidate(&mut self) {
+        for chunk in self.chunks.iter_mut() {
+            chunk.keys = [None; MAX_FRAMES_IN_FLIGHT as usize];
+        }
+    }
+
+    unsafe fn grow(&mut self, device: &ash::Device, chunk_count: usize) -> VkResult<()> {
+        while self.chunks.len() < chunk_count {
+            let alloc_info = vk::CommandBufferAllocateInfo {
+                command_pool: self.command_pool,
+                level: vk::CommandBufferLevel::SECONDARY,
+                command_buffer_count: MAX_FRAMES_IN_FLIGHT as u32,
+                ..Default::default()
+            };
+            let allocated = device.allocate_command_buffers(&alloc_info)?;
+            let mut command_buffers = [vk::CommandBuffer::null(); MAX_FRAMES_IN_FLIGHT as usize];
+            command_buffers.copy_from_slice(&allocated);
+            self.chunks.push(Chunk {
+                command_buffers,
+                keys: [None; MAX_FRAMES_IN_FLIGHT as usize],
+            });
+        }
+        Ok(())
+    }
+
+    /// The device has to be idle.
+    ///
+    /// # Safety
+    /// No primary command buffer executing the cached ones may be pending.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_command_pool(self.command_pool, None);
+    }
+}
+
+pub(crate) unsafe fn record_chunk<F: FnOnce(vk::CommandBuffer)>(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    target: &PassTarget,
+    record: F,
+) -> VkResult<()> {
+    secondary::record_secondary(
+        device,
+        command_buffer,
+        &Inheritance::RenderPass(*target),
+        vk::CommandBufferUsageFlags::empty(),
+        record,
+    )
+}
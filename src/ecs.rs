@@ -0,0 +1,243 @@
+use std::sync::Arc;
+
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{
+    camera::Camera,
+    frame::FrameUniforms,
+    layers::RenderLayers,
+    material::{DrawList, MaterialInstance},
+    mesh::GpuMesh,
+    scene::Transform,
+    selection::{self, SelectionMode, SelectionRect},
+};
+
+/// Draws a mesh with a material at the entity's `Transform`.
+#[derive(Clone)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct Renderable {
+    pub mesh: Arc<GpuMesh>,
+    /// None draws every submesh
+    pub submesh: Option<usize>,
+    pub material: Arc<MaterialInstance>,
+}
+
+/// Shines along the -z axis of the entity's `Transform`.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct DirectionalLight {
+    pub color: glm::Vector3<f32>,
+    pub intensity: f32,
+}
+
+/// Looks along the -z axis of the entity's `Transform`, `extract` uses the first active camera.
+/// Draws the renderables with a `RenderLayers` component that intersects `layer_mask`,
+/// entities without one are on `RenderLayers::DEFAULT`.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct CameraComponent {
+    /// radians
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+    pub active: bool,
+    pub layer_mask: RenderLayers,
+}
+
+impl Default for CameraComponent {
+    fn default() -> Self {
+        Self {
+            fov_y: 60.0_f32.to_radians(),
+            near: 0.1,
+            far: 1000.0,
+            active: true,
+            layer_mask: RenderLayers::ALL,
+        }
+    }
+}
+
+pub struct ExtractedDraw {
+    pub mesh: Arc<GpuMesh>,
+    pub submesh: Option<usize>,
+    pub material: Arc<MaterialInstance>,
+    pub transform: glm::Matrix4<f32>,
+}
+
+/// Everything the renderer needs from the world for one frame.
+pub struct ExtractedFrame {
+    pub draws: Vec<ExtractedDraw>,
+    /// the camera and light fields are only set when the world has them
+    pub uniforms: FrameUniforms,
+    pub has_camera: bool,
+}
+
+impl ExtractedFrame {
+    /// Flushes the material instances of `current_frame` and returns the draws ready to be recorded.
+    ///
+    /// # Safety
+    /// The fence of `current_frame` has to be waited on.
+    pub unsafe fn draw_list(&self, device: &ash::Device, current_frame: usize) -> DrawList<'_> {
+        let mut draw_list = DrawList::new();
+        for draw in self.draws.iter() {
+            draw.material.flush(device, current_frame);
+            match draw.submesh {
+                Some(submesh) => draw_list.push_submesh(&draw.mesh, submesh, &draw.material, draw.transform),
+                None => draw_list.push(&draw.mesh, &draw.material, draw.transform),
+            }
+        }
+        draw_list
+    }
+}
+
+/// Transforms are read as world space, hierarchies are up to the game.
+/// Everything is drawn from the first active camera, see `extract_camera` for the others.
+#[cfg(feature = "hecs")]
+pub fn extract(world: &hecs::World, aspect_ratio: f32) -> ExtractedFrame {
+    let camera = world
+        .query::<(&CameraComponent, &Transform)>()
+        .iter()
+        .find(|(_, (camera_component, _))| camera_component.active)
+        .map(|(entity, _)| entity);
+    extract_view(world, camera, aspect_ratio)
+}
+
+/// Like `extract`, from `camera` and with only the renderables on its layers,
+/// for overlays and editor viewports next to the game camera.
+#[cfg(feature = "hecs")]
+pub fn extract_camera(world: &hecs::World, camera: hecs::Entity, aspect_ratio: f32) -> ExtractedFrame {
+    extract_view(world, Some(camera), aspect_ratio)
+}
+
+#[cfg(feature = "hecs")]
+fn extract_view(world: &hecs::World, camera_entity: Option<hecs::Entity>, aspect_ratio: f32) -> ExtractedFrame {
+    let mut camera_query = camera_entity.and_then(|entity| world.query_one::<(&CameraComponent, &Transform)>(entity).ok());
+    let camera = camera_query.as_mut().and_then(|query| query.get());
+    let mut renderables = world.query::<(&Renderable, &Transform, Option<&RenderLayers>)>();
+    let mut lights = world.query::<(&DirectionalLight, &Transform)>();
+    build_frame(
+        camera,
+        renderables.iter().map(|(_, components)| components),
+        lights.iter().next().map(|(_, components)| components),
+        aspect_ratio,
+    )
+}
+
+/// Entities with a `Renderable` whose mesh bounds project into `rect`, see `selection::select_in_rect`.
+#[cfg(feature = "hecs")]
+pub fn select_in_rect(
+    world: &hecs::World,
+    rect: &SelectionRect,
+    view_projection: &glm::Matrix4<f32>,
+    extent: vk::Extent2D,
+    mode: SelectionMode,
+) -> Vec<hecs::Entity> {
+    let mut query = world.query::<(&Renderable, &Transform)>();
+    let items = query
+        .iter()
+        .map(|(entity, (renderable, transform))| (entity, renderable.mesh.bounds.transformed(&transform.matrix())));
+    selection::select_in_rect(rect, view_projection, extent, mode, items)
+}
+
+/// The same extraction for a bevy_ecs `World`, `Transform` and `RenderLayers` are components there too.
+/// Queries register their components, so these take the world mutably.
+#[cfg(feature = "bevy_ecs")]
+pub mod bevy {
+    use bevy_ecs::{entity::Entity, world::World};
+
+    use super::{
+        build_frame, glm, selection, vk, CameraComponent, DirectionalLight, ExtractedFrame, RenderLayers, Renderable,
+        SelectionMode, SelectionRect, Transform,
+    };
+
+    /// See `ecs::extract`.
+    pub fn extract(world: &mut World, aspect_ratio: f32) -> ExtractedFrame {
+        let camera = world
+            .query::<(Entity, &CameraComponent, &Transform)>()
+            .iter(world)
+            .find(|(_, camera_component, _)| camera_component.active)
+            .map(|(entity, ..)| entity);
+        extract_view(world, camera, aspect_ratio)
+    }
+
+    /// See `ecs::extract_camera`.
+    pub fn extract_camera(world: &mut World, camera: Entity, aspect_ratio: f32) -> ExtractedFrame {
+        extract_view(world, Some(camera), aspect_ratio)
+    }
+
+    fn extract_view(world: &mut World, camera_entity: Option<Entity>, aspect_ratio: f32) -> ExtractedFrame {
+        let mut renderables = world.query::<(&Renderable, &Transform, Option<&RenderLayers>)>();
+        let mut lights = world.query::<(&DirectionalLight, &Transform)>();
+        let mut cameras = world.query::<(&CameraComponent, &Transform)>();
+        let world = &*world;
+        build_frame(
+            camera_entity.and_then(|entity| cameras.get(world, entity).ok()),
+            renderables.iter(world),
+            lights.iter(world).next(),
+            aspect_ratio,
+        )
+    }
+
+    /// See `ecs::select_in_rect`.
+    pub fn select_in_rect(
+        world: &mut World,
+        rect: &SelectionRect,
+        view_projection: &glm::Matrix4<f32>,
+        extent: vk::Extent2D,
+        mode: SelectionMode,
+    ) -> Vec<Entity> {
+        let mut query = world.query::<(Entity, &Renderable, &Transform)>();
+        let items = query
+            .iter(world)
+            .map(|(entity, renderable, transform)| (entity, renderable.mesh.bounds.transformed(&transform.matrix())));
+        selection::select_in_rect(rect, view_projection, extent, mode, items)
+    }
+}
+
+fn build_frame<'a>(
+    camera: Option<(&CameraComponent, &Transform)>,
+    renderables: impl Iterator<Item = (&'a Renderable, &'a Transform, Option<&'a RenderLayers>)>,
+    light: Option<(&DirectionalLight, &Transform)>,
+    aspect_ratio: f32,
+) -> ExtractedFrame {
+    let mut uniforms = FrameUniforms::default();
+
+    let mut layer_mask = RenderLayers::ALL;
+    let mut has_camera = false;
+    if let Some((camera_component, transform)) = camera {
+        let mut camera = Camera::new(transform.translation);
+        camera.orientation = transform.rotation;
+        camera.fov_y = camera_component.fov_y;
+        camera.near = camera_component.near;
+        camera.far = camera_component.far;
+        camera.layer_mask = camera_component.layer_mask;
+        camera.write_uniforms(&mut uniforms, aspect_ratio);
+        layer_mask = camera.layer_mask;
+        has_camera = true;
+    }
+
+    let mut draws = vec![];
+    for (renderable, transform, layers) in renderables {
+        if !layer_mask.intersects(layers.copied().unwrap_or_default()) {
+            continue;
+        }
+        draws.push(ExtractedDraw {
+            mesh: renderable.mesh.clone(),
+            submesh: renderable.submesh,
+            material: renderable.material.clone(),
+            transform: transform.matrix(),
+        });
+    }
+
+    if let Some((light, transform)) = light {
+        let direction = transform.rotation * -glm::Vector3::z();
+        uniforms.light_direction = direction.push(0.0);
+        uniforms.light_color = light.color.push(light.intensity);
+    }
+
+    ExtractedFrame {
+        draws,
+        uniforms,
+        has_camera,
+    }
+}
@@ -0,0 +1,118 @@
+use std::{fmt, path::Path};
+
+use ash::vk;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors of vulky, the ones Vulkan reported carry its `vk::Result`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to create the Vulkan instance: {0}")]
+    InstanceCreation(vk::Result),
+    #[error("Failed to create the device: {0}")]
+    DeviceCreation(vk::Result),
+    /// no GPU, or none with the requested queues
+    #[error("{0}")]
+    NoSuitableDevice(String),
+    #[error("{what} doesn't support the required extensions {}", .missing.join(", "))]
+    MissingExtensions { what: String, missing: Vec<String> },
+    #[error("{what} doesn't support the required features {}", .missing.join(", "))]
+    MissingFeatures { what: String, missing: Vec<&'static str> },
+    /// loading, reflecting or creating the module of a spv file failed
+    #[error("Shader {path}: {message}")]
+    Shader {
+        path: String,
+        message: String,
+        result: Option<vk::Result>,
+    },
+    /// a format vulky can't use or convert for what it was asked to do
+    #[error("{message}")]
+    Format { format: vk::Format, message: String },
+    /// reading or decoding an image, scene, animation or other file failed
+    #[error("{path}: {message}")]
+    Asset { path: String, message: String },
+    /// the surface changed or was lost, the swapchain has to be recreated
+    #[error("Swapchain: {0}")]
+    Swapchain(vk::Result),
+    /// out of device, host or descriptor pool memory
+    #[error("Allocation failed: {0}")]
+    Allocation(vk::Result),
+    #[error("Vulkan: {0}")]
+    Vulkan(vk::Result),
+    #[error("Failed to load the Vulkan library: {0}")]
+    Loading(#[from] ash::LoadingError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Utf8(#[from] std::string::FromUtf8Error),
+    #[error(transparent)]
+    Gltf(#[from] gltf::Error),
+    #[error(transparent)]
+    Obj(#[from] tobj::LoadError),
+    #[error(transparent)]
+    TomlParse(#[from] toml::de::Error),
+    #[error(transparent)]
+    TomlSerialize(#[from] toml::ser::Error),
+    #[error("{0}")]
+    Message(String),
+}
+
+impl Error {
+    pub fn msg(message: impl fmt::Display) -> Self {
+        Error::Message(message.to_string())
+    }
+
+    pub fn shader(path: &str, message: impl fmt::Display) -> Self {
+        Error::Shader {
+            path: path.to_owned(),
+            message: message.to_string(),
+            result: None,
+        }
+    }
+
+    pub fn format(format: vk::Format, message: impl fmt::Display) -> Self {
+        Error::Format {
+            format,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn asset(path: impl AsRef<Path>, message: impl fmt::Display) -> Self {
+        Error::Asset {
+            path: path.as_ref().display().to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    /// The result Vulkan returned, None for errors vulky detected itself.
+    pub fn vk_result(&self) -> Option<vk::Result> {
+        match self {
+            Error::InstanceCreation(result)
+            | Error::DeviceCreation(result)
+            | Error::Swapchain(result)
+            | Error::Allocation(result)
+            | Error::Vulkan(result) => Some(*result),
+            Error::Shader { result, .. } => *result,
+            _ => None,
+        }
+    }
+}
+
+/// Sorts out of memory and surface results into their own variants, the rest is `Error::Vulkan`.
+impl From<vk::Result> for Error {
+    fn from(result: vk::Result) -> Self {
+        match result {
+            vk::Result::ERROR_OUT_OF_HOST_MEMORY
+            | vk::Result::ERROR_OUT_OF_DEVICE_MEMORY
+            | vk::Result::ERROR_OUT_OF_POOL_MEMORY
+            | vk::Result::ERROR_FRAGMENTED_POOL
+            | vk::Result::ERROR_FRAGMENTATION => Error::Allocation(result),
+            vk::Result::ERROR_OUT_OF_DATE_KHR
+            | vk::Result::SUBOPTIMAL_KHR
+            | vk::Result::ERROR_SURFACE_LOST_KHR
+            | vk::Result::ERROR_NATIVE_WINDOW_IN_USE_KHR
+            | vk::Result::ERROR_FULL_SCREEN_EXCLUSIVE_MODE_LOST_EXT => Error::Swapchain(result),
+            _ => Error::Vulkan(result),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+};
+
+use crate::error::{Error, Result};
+use ash::vk;
+
+use crate::utility;
+
+/// Instance or device extensions to enable, the available optional ones are turned on with the required ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ExtensionRequest {
+    required: Vec<String>,
+    optional: Vec<String>,
+}
+
+impl ExtensionRequest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Negotiating fails without `name`, like "VK_KHR_swapchain".
+    pub fn require(mut self, name: &str) -> Self {
+        self.optional.retain(|optional| optional != name);
+        if !self.required.iter().any(|required| required == name) {
+            self.required.push(name.to_owned());
+        }
+        self
+    }
+
+    /// Enabled when available, check `EnabledExtensions::has_extension` for it.
+    pub fn request(mut self, name: &str) -> Self {
+        if !self.required.iter().chain(&self.optional).any(|known| known == name) {
+            self.optional.push(name.to_owned());
+        }
+        self
+    }
+
+    /// `require` for the names ash has, like `ash::extensions::khr::Swapchain::name()`.
+    pub fn require_name(self, name: &CStr) -> Self {
+        self.require(&name.to_string_lossy())
+    }
+
+    pub fn request_name(self, name: &CStr) -> Self {
+        self.request(&name.to_string_lossy())
+    }
+
+    /// Both requests, what either requires stays required.
+    pub fn merge(mut self, other: &ExtensionRequest) -> Self {
+        for name in &other.required {
+            self = self.require(name);
+        }
+        for name in &other.optional {
+            self = self.request(name);
+        }
+        self
+    }
+
+    pub fn required(&self) -> &[String] {
+        &self.required
+    }
+
+    pub fn optional(&self) -> &[String] {
+        &self.optional
+    }
+
+    /// The required extensions and the optional ones in `available`, or an error naming every missing required one.
+    /// `what` names the instance or device in messages.
+    pub fn negotiate(&self, available: &[vk::ExtensionProperties], what: &str) -> Result<EnabledExtensions> {
+        let available: Vec<String> = available
+            .iter()
+            .map(|extension| utility::vk_to_string(&extension.extension_name))
+            .collect();
+        let is_available = |name: &String| available.contains(name);
+
+        let missing: Vec<String> = self.required.iter().filter(|name| !is_available(name)).cloned().collect();
+        if !missing.is_empty() {
+            return Err(Error::MissingExtensions {
+                what: what.to_owned(),
+                missing,
+            });
+        }
+        let (enabled, skipped): (Vec<&String>, Vec<&String>) = self.optional.iter().partition(|name| is_available(name));
+        if !skipped.is_empty() {
+            let skipped: Vec<&str> = skipped.into_iter().map(String::as_str).collect();
+            log::info!("{} lacks the optional extensions {}", what, skipped.join(", "));
+        }
+
+        let names = self
+            .required
+            .iter()
+            .chain(enabled)
+            .map(|name| CString::new(name.as_str()).unwrap())
+            .collect();
+        Ok(EnabledExtensions { names })
+    }
+
+    /// Negotiates against the extensions of the loader and of the instance `layers`, which have to be enabled too.
+    ///
+    /// # Safety
+    /// `entry` has to be the loader the instance is created with.
+    pub unsafe fn negotiate_instance(&self, entry: &ash::Entry, layers: &[&CStr]) -> Result<EnabledExtensions> {
+        let mut available = entry.enumerate_instance_extension_properties(None)?;
+        for layer in layers {
+            available.extend(entry.enumerate_instance_extension_properties(Some(layer))?);
+        }
+        self.negotiate(&available, "The Vulkan instance")
+    }
+
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance`.
+    pub unsafe fn negotiate_device(
+        &self,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<EnabledExtensions> {
+        let available = instance.enumerate_device_extension_properties(physical_device)?;
+        let name = utility::vk_to_string(&instance.get_physical_device_properties(physical_device).device_name);
+        self.negotiate(&available, &name)
+    }
+}
+
+/// The outcome of `ExtensionRequest::negotiate`, what the instance or device was created with.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EnabledExtensions {
+    names: Vec<CString>,
+}
+
+impl EnabledExtensions {
+    /// Lets higher level code pick render paths, like `has_extension("VK_KHR_ray_tracing_pipeline")`.
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.names.iter().any(|enabled| enabled.as_bytes() == name.as_bytes())
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(|name| name.to_str().unwrap())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// For `pp_enabled_extension_names`, valid while `self` is.
+    pub fn as_ptrs(&self) -> Vec<*const c_char> {
+        self.names.iter().map(|name| name.as_ptr()).collect()
+    }
+}
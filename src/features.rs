@@ -0,0 +1,229 @@
+use std::{ffi::c_void, ptr};
+
+use crate::error::{Error, Result};
+use ash::vk;
+
+use crate::{extensions::ExtensionRequest, utility};
+
+/// The feature structs of Vulkan 1.0 to 1.3, the ones a device doesn't have stay all false.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FeatureChain {
+    pub core: vk::PhysicalDeviceFeatures,
+    pub v11: vk::PhysicalDeviceVulkan11Features,
+    pub v12: vk::PhysicalDeviceVulkan12Features,
+    pub v13: vk::PhysicalDeviceVulkan13Features,
+    /// `vk::make_api_version` of the device, newer structs than this must not be chained
+    pub api_version: u32,
+}
+
+// the structs only hold p_next pointers while `link` has them chained
+unsafe impl Send for FeatureChain {}
+unsafe impl Sync for FeatureChain {}
+
+impl FeatureChain {
+    /// What `physical_device` supports.
+    ///
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance`.
+    pub unsafe fn supported(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> FeatureChain {
+        let api_version = instance.get_physical_device_properties(physical_device).api_version;
+        let mut chain = FeatureChain {
+            api_version,
+            ..Default::default()
+        };
+        let mut features2 = chain.link(ptr::null_mut());
+        instance.get_physical_device_features2(physical_device, &mut features2);
+        chain.core = features2.features;
+        chain.unlink();
+        chain
+    }
+
+    /// Links the structs the device knows into `vk::PhysicalDeviceFeatures2`, with `next` after the last one.
+    /// `self` must not move while the result is in use, `unlink` clears the pointers again.
+    pub fn link(&mut self, next: *mut c_void) -> vk::PhysicalDeviceFeatures2 {
+        let mut next = next;
+        if self.api_version >= vk::API_VERSION_1_3 {
+            self.v13.p_next = next;
+            next = &mut self.v13 as *mut _ as *mut c_void;
+        }
+        if self.api_version >= vk::API_VERSION_1_2 {
+            self.v12.p_next = next;
+            next = &mut self.v12 as *mut _ as *mut c_void;
+            self.v11.p_next = next;
+            next = &mut self.v11 as *mut _ as *mut c_void;
+        }
+        vk::PhysicalDeviceFeatures2 {
+            p_next: next,
+            features: self.core,
+            ..Default::default()
+        }
+    }
+
+    pub fn unlink(&mut self) {
+        self.v11.p_next = ptr::null_mut();
+        self.v12.p_next = ptr::null_mut();
+        self.v13.p_next = ptr::null_mut();
+    }
+}
+
+macro_rules! device_features {
+    ($($(#[$doc:meta])* $field:ident => [$($part:ident . $member:ident as $vk_name:literal),+ $(,)?],)*) => {
+        /// Optional device features, each one turns on the Vulkan features next to it.
+        #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+        pub struct DeviceFeatures {
+            $($(#[$doc])* pub $field: bool,)*
+        }
+
+        impl DeviceFeatures {
+            /// The features `chain` has every Vulkan feature of.
+            pub fn supported_by(chain: &FeatureChain) -> DeviceFeatures {
+                DeviceFeatures {
+                    $($field: true $(&& chain.$part.$member == vk::TRUE)+,)*
+                }
+            }
+
+            /// Vulkan names of the features `self` asks for and `chain` lacks, like "samplerAnisotropy".
+            pub fn missing_from(&self, chain: &FeatureChain) -> Vec<&'static str> {
+                let mut missing = vec![];
+                $(if self.$field {
+                    $(if chain.$part.$member != vk::TRUE {
+                        missing.push($vk_name);
+                    })+
+                })*
+                missing
+            }
+
+            /// Turns on the Vulkan features of `self` in `chain`.
+            pub fn enable_in(&self, chain: &mut FeatureChain) {
+                $(if self.$field {
+                    $(chain.$part.$member = vk::TRUE;)+
+                })*
+            }
+
+            /// Names of the fields that are set.
+            pub fn names(&self) -> Vec<&'static str> {
+                let mut names = vec![];
+                $(if self.$field {
+                    names.push(stringify!($field));
+                })*
+                names
+            }
+
+            pub fn union(&self, other: &DeviceFeatures) -> DeviceFeatures {
+                DeviceFeatures {
+                    $($field: self.$field || other.$field,)*
+                }
+            }
+
+            pub fn intersection(&self, other: &DeviceFeatures) -> DeviceFeatures {
+                DeviceFeatures {
+                    $($field: self.$field && other.$field,)*
+                }
+            }
+        }
+    };
+}
+
+device_features! {
+    sampler_anisotropy => [core.sampler_anisotropy as "samplerAnisotropy"],
+    /// wireframe and point polygon modes
+    fill_mode_non_solid => [core.fill_mode_non_solid as "fillModeNonSolid"],
+    wide_lines => [core.wide_lines as "wideLines"],
+    large_points => [core.large_points as "largePoints"],
+    geometry_shader => [core.geometry_shader as "geometryShader"],
+    tessellation_shader => [core.tessellation_shader as "tessellationShader"],
+    multi_draw_indirect => [core.multi_draw_indirect as "multiDrawIndirect"],
+    depth_clamp => [core.depth_clamp as "depthClamp"],
+    depth_bounds => [core.depth_bounds as "depthBounds"],
+    sample_rate_shading => [core.sample_rate_shading as "sampleRateShading"],
+    independent_blend => [core.independent_blend as "independentBlend"],
+    shader_int64 => [core.shader_int64 as "shaderInt64"],
+    texture_compression_bc => [core.texture_compression_bc as "textureCompressionBC"],
+    pipeline_statistics_query => [core.pipeline_statistics_query as "pipelineStatisticsQuery"],
+    /// writes to storage buffers and images from vertex, tessellation and geometry shaders
+    vertex_pipeline_stores_and_atomics => [core.vertex_pipeline_stores_and_atomics as "vertexPipelineStoresAndAtomics"],
+    fragment_stores_and_atomics => [core.fragment_stores_and_atomics as "fragmentStoresAndAtomics"],
+    multiview => [v11.multiview as "multiview"],
+    shader_draw_parameters => [v11.shader_draw_parameters as "shaderDrawParameters"],
+    /// bindless textures: unsized, partially bound arrays indexed non uniformly and updated after binding
+    descriptor_indexing => [
+        v12.descriptor_indexing as "descriptorIndexing",
+        v12.runtime_descriptor_array as "runtimeDescriptorArray",
+        v12.descriptor_binding_partially_bound as "descriptorBindingPartiallyBound",
+        v12.descriptor_binding_variable_descriptor_count as "descriptorBindingVariableDescriptorCount",
+        v12.shader_sampled_image_array_non_uniform_indexing as "shaderSampledImageArrayNonUniformIndexing",
+        v12.descriptor_binding_sampled_image_update_after_bind as "descriptorBindingSampledImageUpdateAfterBind",
+    ],
+    buffer_device_address => [v12.buffer_device_address as "bufferDeviceAddress"],
+    timeline_semaphore => [v12.timeline_semaphore as "timelineSemaphore"],
+    scalar_block_layout => [v12.scalar_block_layout as "scalarBlockLayout"],
+    draw_indirect_count => [v12.draw_indirect_count as "drawIndirectCount"],
+    host_query_reset => [v12.host_query_reset as "hostQueryReset"],
+    shader_float16 => [v12.shader_float16 as "shaderFloat16"],
+    dynamic_rendering => [v13.dynamic_rendering as "dynamicRendering"],
+    synchronization2 => [v13.synchronization2 as "synchronization2"],
+    maintenance4 => [v13.maintenance4 as "maintenance4"],
+}
+
+/// What a device has to support to be used, and what is turned on when it happens to be there.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DeviceRequirements {
+    /// device creation fails when one is missing
+    pub required: DeviceFeatures,
+    /// enabled when supported, check `enabled_features` to see which were
+    pub optional: DeviceFeatures,
+    /// on top of the swapchain and the device lost diagnostics the engine enables itself
+    pub extensions: ExtensionRequest,
+}
+
+impl DeviceRequirements {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn require(mut self, features: DeviceFeatures) -> Self {
+        self.required = self.required.union(&features);
+        self
+    }
+
+    pub fn request(mut self, features: DeviceFeatures) -> Self {
+        self.optional = self.optional.union(&features);
+        self
+    }
+
+    pub fn require_extension(mut self, name: &str) -> Self {
+        self.extensions = self.extensions.require(name);
+        self
+    }
+
+    pub fn request_extension(mut self, name: &str) -> Self {
+        self.extensions = self.extensions.request(name);
+        self
+    }
+
+    /// The features to enable on a device supporting `supported`, or an error naming every missing Vulkan feature.
+    pub fn enabled_features(&self, supported: &FeatureChain, device_name: &str) -> Result<DeviceFeatures> {
+        let missing = self.required.missing_from(supported);
+        if !missing.is_empty() {
+            return Err(Error::MissingFeatures {
+                what: device_name.to_owned(),
+                missing,
+            });
+        }
+        let optional = self.optional.intersection(&DeviceFeatures::supported_by(supported));
+        let skipped = self.optional.missing_from(supported);
+        if !skipped.is_empty() {
+            log::info!("{} lacks the optional features {}", device_name, skipped.join(", "));
+        }
+        Ok(self.required.union(&optional))
+    }
+
+    /// Like `enabled_features` for `physical_device`, whose name the error shows.
+    ///
+    /// # Safety
+    /// As for `FeatureChain::supported`.
+    pub unsafe fn check(&self, instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Result<DeviceFeatures> {
+        let name = utility::vk_to_string(&instance.get_physical_device_properties(physical_device).device_name);
+        self.enabled_features(&FeatureChain::supported(instance, physical_device), &name)
+    }
+}
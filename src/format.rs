@@ -0,0 +1,251 @@
+use std::borrow::Cow;
+
+use ash::{prelude::VkResult, vk};
+
+use crate::texture::DEPTH_FORMAT;
+
+/// Formats tried in order when `format` isn't supported, the closest match first.
+/// Block compressed formats fall back to uncompressed ones, their texels have to be decompressed by the caller.
+pub fn fallbacks(format: vk::Format) -> &'static [vk::Format] {
+    use vk::Format as F;
+    match format {
+        F::D32_SFLOAT => &[F::D32_SFLOAT_S8_UINT, F::D24_UNORM_S8_UINT, F::D16_UNORM],
+        F::D24_UNORM_S8_UINT => &[F::D32_SFLOAT_S8_UINT, F::D16_UNORM_S8_UINT],
+        F::D32_SFLOAT_S8_UINT => &[F::D24_UNORM_S8_UINT, F::D16_UNORM_S8_UINT],
+        F::D16_UNORM => &[F::D32_SFLOAT, F::D24_UNORM_S8_UINT],
+        F::X8_D24_UNORM_PACK32 => &[F::D32_SFLOAT, F::D24_UNORM_S8_UINT],
+
+        F::R8G8B8_UNORM => &[F::R8G8B8A8_UNORM],
+        F::R8G8B8_SRGB => &[F::R8G8B8A8_SRGB],
+        F::B8G8R8_UNORM => &[F::B8G8R8A8_UNORM, F::R8G8B8A8_UNORM],
+        F::B8G8R8_SRGB => &[F::B8G8R8A8_SRGB, F::R8G8B8A8_SRGB],
+        F::R8G8B8A8_UNORM => &[F::B8G8R8A8_UNORM],
+        F::R8G8B8A8_SRGB => &[F::B8G8R8A8_SRGB],
+        F::B8G8R8A8_UNORM => &[F::R8G8B8A8_UNORM],
+        F::B8G8R8A8_SRGB => &[F::R8G8B8A8_SRGB],
+        F::R16G16B16_SFLOAT => &[F::R16G16B16A16_SFLOAT, F::R32G32B32A32_SFLOAT],
+        F::R32G32B32_SFLOAT => &[F::R32G32B32A32_SFLOAT],
+        F::B10G11R11_UFLOAT_PACK32 => &[F::R16G16B16A16_SFLOAT],
+        F::A2B10G10R10_UNORM_PACK32 => &[F::R16G16B16A16_UNORM, F::R16G16B16A16_SFLOAT],
+
+        F::BC1_RGBA_UNORM_BLOCK | F::BC3_UNORM_BLOCK | F::BC7_UNORM_BLOCK => &[F::R8G8B8A8_UNORM],
+        F::BC1_RGBA_SRGB_BLOCK | F::BC3_SRGB_BLOCK | F::BC7_SRGB_BLOCK => &[F::R8G8B8A8_SRGB],
+        F::BC4_UNORM_BLOCK => &[F::R8_UNORM],
+        F::BC5_UNORM_BLOCK => &[F::R8G8_UNORM],
+        F::BC6H_UFLOAT_BLOCK | F::BC6H_SFLOAT_BLOCK => &[F::R16G16B16A16_SFLOAT],
+        F::ETC2_R8G8B8A8_UNORM_BLOCK | F::ASTC_4X4_UNORM_BLOCK => &[F::BC7_UNORM_BLOCK, F::R8G8B8A8_UNORM],
+        F::ETC2_R8G8B8A8_SRGB_BLOCK | F::ASTC_4X4_SRGB_BLOCK => &[F::BC7_SRGB_BLOCK, F::R8G8B8A8_SRGB],
+        _ => &[],
+    }
+}
+
+/// Format features an optimally tiled image needs for `usage`.
+pub fn features_for(usage: vk::ImageUsageFlags) -> vk::FormatFeatureFlags {
+    let pairs = [
+        (vk::ImageUsageFlags::SAMPLED, vk::FormatFeatureFlags::SAMPLED_IMAGE),
+        (vk::ImageUsageFlags::STORAGE, vk::FormatFeatureFlags::STORAGE_IMAGE),
+        (
+            vk::ImageUsageFlags::COLOR_ATTACHMENT,
+            vk::FormatFeatureFlags::COLOR_ATTACHMENT,
+        ),
+        (
+            vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT,
+        ),
+        (vk::ImageUsageFlags::TRANSFER_SRC, vk::FormatFeatureFlags::TRANSFER_SRC),
+        (vk::ImageUsageFlags::TRANSFER_DST, vk::FormatFeatureFlags::TRANSFER_DST),
+    ];
+    pairs
+        .iter()
+        .filter(|(flag, _)| usage.contains(*flag))
+        .fold(vk::FormatFeatureFlags::empty(), |features, (_, feature)| features | *feature)
+}
+
+/// # Safety
+/// `physical_device` has to be enumerated from `instance`.
+pub unsafe fn supports(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    format: vk::Format,
+    tiling: vk::ImageTiling,
+    features: vk::FormatFeatureFlags,
+) -> bool {
+    let properties = instance.get_physical_device_format_properties(physical_device, format);
+    match tiling {
+        vk::ImageTiling::LINEAR => properties.linear_tiling_features.contains(features),
+        _ => properties.optimal_tiling_features.contains(features),
+    }
+}
+
+/// `requested` when the device supports it for an optimally tiled image with `usage`, the first supported
+/// entry of `fallbacks` otherwise. ERROR_FORMAT_NOT_SUPPORTED when none of them is.
+///
+/// # Safety
+/// As for `supports`.
+pub unsafe fn resolve(
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    requested: vk::Format,
+    usage: vk::ImageUsageFlags,
+) -> VkResult<vk::Format> {
+    let features = features_for(usage);
+    let tiling = vk::ImageTiling::OPTIMAL;
+    if supports(instance, physical_device, requested, tiling, features) {
+        return Ok(requested);
+    }
+    match fallbacks(requested)
+        .iter()
+        .copied()
+        .find(|format| supports(instance, physical_device, *format, tiling, features))
+    {
+        Some(format) => {
+            log::info!("{:?} doesn't support {:?}, using {:?}", requested, usage, format);
+            Ok(format)
+        }
+        None => {
+            log::error!("Neither {:?} nor a fallback of it supports {:?}", requested, usage);
+            Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)
+        }
+    }
+}
+
+/// The format `DepthBuffer` uses on the device, render passes drawing into one have to be created with it.
+///
+/// # Safety
+/// As for `supports`.
+pub unsafe fn depth_format(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> VkResult<vk::Format> {
+    resolve(
+        instance,
+        physical_device,
+        DEPTH_FORMAT,
+        vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+    )
+}
+
+/// Aspects of a view or barrier covering all of an image in `format`.
+pub fn aspect_flags(format: vk::Format) -> vk::ImageAspectFlags {
+    use vk::Format as F;
+    match format {
+        F::D16_UNORM | F::X8_D24_UNORM_PACK32 | F::D32_SFLOAT => vk::ImageAspectFlags::DEPTH,
+        F::S8_UINT => vk::ImageAspectFlags::STENCIL,
+        F::D16_UNORM_S8_UINT | F::D24_UNORM_S8_UINT | F::D32_SFLOAT_S8_UINT => {
+            vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+        }
+        _ => vk::ImageAspectFlags::COLOR,
+    }
+}
+
+/// `pixels` in `from` repacked into `to`, for the fallbacks that only add an alpha channel or swap red and blue.
+/// None when the conversion needs more than that, like decompressing blocks.
+pub fn convert_texels<'a>(from: vk::Format, to: vk::Format, pixels: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+    use vk::Format as F;
+    if from == to {
+        return Some(Cow::Borrowed(pixels));
+    }
+    let one_f16 = 0x3c00u16.to_le_bytes();
+    let one_f32 = 1.0f32.to_le_bytes();
+    let converted = match (from, to) {
+        (F::R8G8B8_UNORM, F::R8G8B8A8_UNORM)
+        | (F::R8G8B8_SRGB, F::R8G8B8A8_SRGB)
+        | (F::B8G8R8_UNORM, F::B8G8R8A8_UNORM)
+        | (F::B8G8R8_SRGB, F::B8G8R8A8_SRGB) => add_alpha(pixels, 3, &[u8::MAX]),
+        (F::B8G8R8_UNORM, F::R8G8B8A8_UNORM) | (F::B8G8R8_SRGB, F::R8G8B8A8_SRGB) => {
+            let mut texels = add_alpha(pixels, 3, &[u8::MAX]);
+            swap_red_blue(&mut texels);
+            texels
+        }
+        (F::R8G8B8A8_UNORM, F::B8G8R8A8_UNORM)
+        | (F::R8G8B8A8_SRGB, F::B8G8R8A8_SRGB)
+        | (F::B8G8R8A8_UNORM, F::R8G8B8A8_UNORM)
+        | (F::B8G8R8A8_SRGB, F::R8G8B8A8_SRGB) => {
+            let mut texels = pixels.to_vec();
+            swap_red_blue(&mut texels);
+            texels
+        }
+        (F::R16G16B16_SFLOAT, F::R16G16B16A16_SFLOAT) => add_alpha(pixels, 6, &one_f16),
+        (F::R32G32B32_SFLOAT, F::R32G32B32A32_SFLOAT) => add_alpha(pixels, 12, &one_f32),
+        _ => return None,
+    };
+    Some(Cow::Owned(converted))
+}
+
+fn add_alpha(pixels: &[u8], texel_size: usize, alpha: &[u8]) -> Vec<u8> {
+    let mut texels = Vec::with_capacity(pixels.len() / texel_size * (texel_size + alpha.len()));
+    for texel in pixels.chunks_exact(texel_size) {
+        texels.extend_from_slice(texel);
+        texels.extend_from_slice(alpha);
+    }
+    texels
+}
+
+/// 4 byte texels with 8 bit channels
+fn swap_red_blue(texels: &mut [u8]) {
+    for texel in texels.chunks_exact_mut(4) {
+        texel.swap(0, 2);
+    }
+}
+
+/// Bytes per texel of uncompressed color formats, None for depth, stencil, compressed and planar formats.
+/// Views may only reinterpret an image as a format of the same size.
+pub fn texel_size(format: vk::Format) -> Option<u32> {
+    use vk::Format as F;
+    let size = match format {
+        F::R8_UNORM | F::R8_SNORM | F::R8_UINT | F::R8_SINT | F::R8_SRGB => 1,
+        F::R8G8_UNORM
+        | F::R8G8_SNORM
+        | F::R8G8_UINT
+        | F::R8G8_SINT
+        | F::R8G8_SRGB
+        | F::R16_UNORM
+        | F::R16_SNORM
+        | F::R16_UINT
+        | F::R16_SINT
+        | F::R16_SFLOAT
+        | F::R5G6B5_UNORM_PACK16
+        | F::B5G6R5_UNORM_PACK16
+        | F::R4G4B4A4_UNORM_PACK16
+        | F::B4G4R4A4_UNORM_PACK16
+        | F::R5G5B5A1_UNORM_PACK16
+        | F::B5G5R5A1_UNORM_PACK16
+        | F::A1R5G5B5_UNORM_PACK16 => 2,
+        F::R8G8B8_UNORM | F::R8G8B8_SRGB | F::B8G8R8_UNORM | F::B8G8R8_SRGB => 3,
+        F::R8G8B8A8_UNORM
+        | F::R8G8B8A8_SNORM
+        | F::R8G8B8A8_UINT
+        | F::R8G8B8A8_SINT
+        | F::R8G8B8A8_SRGB
+        | F::B8G8R8A8_UNORM
+        | F::B8G8R8A8_SNORM
+        | F::B8G8R8A8_UINT
+        | F::B8G8R8A8_SINT
+        | F::B8G8R8A8_SRGB
+        | F::A8B8G8R8_UNORM_PACK32
+        | F::A8B8G8R8_SRGB_PACK32
+        | F::A2R10G10B10_UNORM_PACK32
+        | F::A2B10G10R10_UNORM_PACK32
+        | F::A2B10G10R10_UINT_PACK32
+        | F::R16G16_UNORM
+        | F::R16G16_SNORM
+        | F::R16G16_UINT
+        | F::R16G16_SINT
+        | F::R16G16_SFLOAT
+        | F::R32_UINT
+        | F::R32_SINT
+        | F::R32_SFLOAT
+        | F::B10G11R11_UFLOAT_PACK32
+        | F::E5B9G9R9_UFLOAT_PACK32 => 4,
+        F::R16G16B16_UNORM | F::R16G16B16_SFLOAT => 6,
+        F::R16G16B16A16_UNORM
+        | F::R16G16B16A16_SNORM
+        | F::R16G16B16A16_UINT
+        | F::R16G16B16A16_SINT
+        | F::R16G16B16A16_SFLOAT
+        | F::R32G32_UINT
+        | F::R32G32_SINT
+        | F::R32G32_SFLOAT => 8,
+        F::R32G32B32_UINT | F::R32G32B32_SINT | F::R32G32B32_SFLOAT => 12,
+        F::R32G32B32A32_UINT | F::R32G32B32A32_SINT | F::R32G32B32A32_SFLOAT => 16,
+        _ => return None,
+    };
+    Some(size)
+}
@@ -0,0 +1,176 @@
+use std::{mem::size_of, ptr};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+use nalgebra as glm;
+
+use crate::{
+    buffer::{self, MAX_FRAMES_IN_FLIGHT},
+    utility,
+};
+
+/// Per frame values shared by every material, bound at set 0 binding 0.
+/// Matches the `Frame` block of the built-in shaders.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct FrameUniforms {
+    pub view: glm::Matrix4<f32>,
+    pub projection: glm::Matrix4<f32>,
+    /// w is unused
+    pub camera_position: glm::Vector4<f32>,
+    /// direction the light travels in, w is unused
+    pub light_direction: glm::Vector4<f32>,
+    /// rgb color, w is the intensity
+    pub light_color: glm::Vector4<f32>,
+    /// rgb color, w is the intensity
+    pub ambient_color: glm::Vector4<f32>,
+    /// x wetness, y snow cover, z precipitation intensity, all 0..1, w seconds for weather animation
+    pub weather: glm::Vector4<f32>,
+}
+
+impl Default for FrameUniforms {
+    fn default() -> Self {
+        Self {
+            view: glm::Matrix4::identity(),
+            projection: glm::Matrix4::identity(),
+            camera_position: glm::Vector4::new(0.0, 0.0, 0.0, 1.0),
+            light_direction: glm::Vector4::new(-0.3, -1.0, -0.5, 0.0),
+            light_color: glm::Vector4::new(1.0, 1.0, 1.0, 3.0),
+            ambient_color: glm::Vector4::new(1.0, 1.0, 1.0, 0.03),
+            weather: glm::Vector4::zeros(),
+        }
+    }
+}
+
+/// Uniform buffer and descriptor set of every frame in flight for `FrameUniforms`.
+pub struct FrameData {
+    pub set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    uniform_buffers: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    mapped: Vec<*mut u8>,
+}
+
+impl FrameData {
+    /// # Safety
+    /// `physical_device` has to be the device of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> VkResult<FrameData> {
+        let binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: ptr::null(),
+        };
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: 1,
+            p_bindings: &binding,
+        };
+        let set_layout = device.create_descriptor_set_layout(&set_layout_info, None)?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: MAX_FRAMES_IN_FLIGHT as u32,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::empty(),
+            max_sets: MAX_FRAMES_IN_FLIGHT as u32,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+        };
+        let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+        let set_layouts = [set_layout; MAX_FRAMES_IN_FLIGHT as usize];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool,
+            descriptor_set_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+        };
+        let descriptor_sets = device.allocate_descriptor_sets(&alloc_info)?;
+
+        let size = size_of::<FrameUniforms>() as u64;
+        let mut uniform_buffers = vec![];
+        let mut mapped = vec![];
+        for descriptor_set in descriptor_sets.iter() {
+            let (buffer, memory) = buffer::create_buffer(
+                device,
+                instance,
+                physical_device,
+                size,
+                BufferUsageFlags::UNIFORM_BUFFER,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            mapped.push(device.map_memory(memory, 0, size, MemoryMapFlags::empty())? as *mut u8);
+            uniform_buffers.push((buffer, memory));
+
+            let buffer_info = vk::DescriptorBufferInfo {
+                buffer,
+                offset: 0,
+                range: size,
+            };
+            let write = vk::WriteDescriptorSet {
+                s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                p_next: ptr::null(),
+                dst_set: *descriptor_set,
+                dst_binding: 0,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                p_image_info: ptr::null(),
+                p_buffer_info: &buffer_info,
+                p_texel_buffer_view: ptr::null(),
+            };
+            device.update_descriptor_sets(&[write], &[]);
+        }
+
+        let frame_data = FrameData {
+            set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            uniform_buffers,
+            mapped,
+        };
+        for frame in 0..MAX_FRAMES_IN_FLIGHT as usize {
+            frame_data.update(frame, &FrameUniforms::default());
+        }
+        Ok(frame_data)
+    }
+
+    /// The fence of `current_frame` has to be waited on first.
+    ///
+    /// # Safety
+    /// `current_frame` has to be below MAX_FRAMES_IN_FLIGHT.
+    pub unsafe fn update(&self, current_frame: usize, uniforms: &FrameUniforms) {
+        let bytes = utility::as_bytes(uniforms);
+        self.mapped[current_frame].copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+    }
+
+    pub fn descriptor_set(&self, current_frame: usize) -> vk::DescriptorSet {
+        self.descriptor_sets[current_frame]
+    }
+
+    /// # Safety
+    /// No frame in flight may use the sets.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for (buffer, memory) in self.uniform_buffers.iter() {
+            device.unmap_memory(*memory);
+            device.destroy_buffer(*buffer, None);
+            device.free_memory(*memory, None);
+        }
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
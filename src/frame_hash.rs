@@ -0,0 +1,295 @@
+use std::{ffi::CString, fmt, ptr};
+
+use crate::error::Result;
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags},
+};
+
+use crate::{
+    buffer::{self, MAX_FRAMES_IN_FLIGHT},
+    pipeline, utility,
+};
+
+pub const FRAME_HASH_SHADER: &str = "shaders/spv/frame_hash_comp.spv";
+const WORKGROUP_SIZE: u32 = 16;
+/// sum and xor of the texel hashes
+const HASH_SIZE: vk::DeviceSize = 8;
+
+/// Checksum of an image, equal images give equal hashes on the same driver. Float formats hash their bits,
+/// so another GPU or driver can round differently and still produce a visually identical image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FrameHash(pub u64);
+
+impl fmt::Display for FrameHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Hashes the final image on the GPU with a compute reduction, only 8 bytes are read back instead of the
+/// whole image. Automated tests and replays compare the hashes to find frames that diverged.
+/// Each frame in flight has its own result slot, so hashing doesn't stall the frame after it.
+pub struct FrameHasher {
+    pipeline: vk::Pipeline,
+    pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    sampler: vk::Sampler,
+    result_buffer: vk::Buffer,
+    result_memory: vk::DeviceMemory,
+}
+
+impl FrameHasher {
+    /// # Safety
+    /// `physical_device` has to be the device of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<FrameHasher> {
+        let bindings = [
+            vk::DescriptorSetLayoutBinding {
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: ptr::null(),
+            },
+            vk::DescriptorSetLayoutBinding {
+                binding: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::COMPUTE,
+                p_immutable_samplers: ptr::null(),
+            },
+        ];
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+            ..Default::default()
+        };
+        let set_layout = device.create_descriptor_set_layout(&set_layout_info, None)?;
+
+        let push_constant_range = vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::COMPUTE,
+            offset: 0,
+            size: 2 * std::mem::size_of::<u32>() as u32,
+        };
+        let layout_info = vk::PipelineLayoutCreateInfo {
+            set_layout_count: 1,
+            p_set_layouts: &set_layout,
+            push_constant_range_count: 1,
+            p_push_constant_ranges: &push_constant_range,
+            ..Default::default()
+        };
+        let pipeline_layout = device.create_pipeline_layout(&layout_info, None)?;
+
+        let shader = pipeline::create_shader_module(device, utility::read_file(FRAME_HASH_SHADER)?)?;
+        let entry_name = CString::new("main").unwrap();
+        let pipeline_info = vk::ComputePipelineCreateInfo {
+            stage: vk::PipelineShaderStageCreateInfo {
+                stage: vk::ShaderStageFlags::COMPUTE,
+                module: shader,
+                p_name: entry_name.as_ptr(),
+                ..Default::default()
+            },
+            layout: pipeline_layout,
+            ..Default::default()
+        };
+        let pipelines = device.create_compute_pipelines(vk::PipelineCache::null(), &[pipeline_info], None);
+        device.destroy_shader_module(shader, None);
+        let pipeline = pipelines.map_err(|(_, e)| e)?[0];
+
+        let slots = MAX_FRAMES_IN_FLIGHT as u32;
+        let pool_sizes = [
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: slots,
+            },
+            vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::STORAGE_BUFFER,
+                descriptor_count: slots,
+            },
+        ];
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            max_sets: slots,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+        let set_layouts = vec![set_layout; slots as usize];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            descriptor_pool,
+            descriptor_set_count: slots,
+            p_set_layouts: set_layouts.as_ptr(),
+            ..Default::default()
+        };
+        let descriptor_sets = device.allocate_descriptor_sets(&alloc_info)?;
+
+        // texelFetch ignores the filter, any sampler works for every format
+        let sampler_info = vk::SamplerCreateInfo {
+            mag_filter: vk::Filter::NEAREST,
+            min_filter: vk::Filter::NEAREST,
+            ..Default::default()
+        };
+        let sampler = device.create_sampler(&sampler_info, None)?;
+
+        let (result_buffer, result_memory) = buffer::create_buffer(
+            device,
+            instance,
+            physical_device,
+            HASH_SIZE * slots as u64,
+            BufferUsageFlags::STORAGE_BUFFER | BufferUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        Ok(FrameHasher {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            descriptor_sets,
+            sampler,
+            result_buffer,
+            result_memory,
+        })
+    }
+
+    /// Records the hash of `view` into the slot of `frame`, outside of a render pass.
+    /// The image has to be in SHADER_READ_ONLY_OPTIMAL with its writes visible to the compute shader,
+    /// and the last hash of the slot has to be read already.
+    ///
+    /// # Safety
+    /// `view` has to be an `extent` big view that lives until the submission finished.
+    pub unsafe fn cmd_hash(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+        view: vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        let set = self.descriptor_sets[frame];
+        let offset = HASH_SIZE * frame as u64;
+        let image_info = vk::DescriptorImageInfo {
+            sampler: self.sampler,
+            image_view: view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: self.result_buffer,
+            offset,
+            range: HASH_SIZE,
+        };
+        let writes = [
+            vk::WriteDescriptorSet {
+                dst_set: set,
+                dst_binding: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: &image_info,
+                ..Default::default()
+            },
+            vk::WriteDescriptorSet {
+                dst_set: set,
+                dst_binding: 1,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                p_buffer_info: &buffer_info,
+                ..Default::default()
+            },
+        ];
+        device.update_descriptor_sets(&writes, &[]);
+
+        device.cmd_fill_buffer(command_buffer, self.result_buffer, offset, HASH_SIZE, 0);
+        let cleared = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: self.result_buffer,
+            offset,
+            size: HASH_SIZE,
+            ..Default::default()
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[cleared],
+            &[],
+        );
+
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::COMPUTE, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::COMPUTE,
+            self.pipeline_layout,
+            0,
+            &[set],
+            &[],
+        );
+        let push_constants = [extent.width, extent.height];
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::COMPUTE,
+            0,
+            std::slice::from_raw_parts(push_constants.as_ptr() as *const u8, std::mem::size_of_val(&push_constants)),
+        );
+        device.cmd_dispatch(
+            command_buffer,
+            extent.width.div_ceil(WORKGROUP_SIZE),
+            extent.height.div_ceil(WORKGROUP_SIZE),
+            1,
+        );
+
+        let hashed = vk::BufferMemoryBarrier {
+            src_access_mask: vk::AccessFlags::SHADER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            ..cleared
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[hashed],
+            &[],
+        );
+    }
+
+    /// The hash `cmd_hash` recorded for `frame`, once the submission of it finished.
+    ///
+    /// # Safety
+    /// The submission of the hash of `frame` has to be finished.
+    pub unsafe fn read(&self, device: &ash::Device, frame: usize) -> VkResult<FrameHash> {
+        let mapped = device.map_memory(
+            self.result_memory,
+            HASH_SIZE * frame as u64,
+            HASH_SIZE,
+            MemoryMapFlags::empty(),
+        )? as *const u32;
+        let (sum, mixed) = (ptr::read(mapped), ptr::read(mapped.add(1)));
+        device.unmap_memory(self.result_memory);
+        Ok(FrameHash((mixed as u64) << 32 | sum as u64))
+    }
+
+    /// # Safety
+    /// No submission hashing into the slots may be pending.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_buffer(self.result_buffer, None);
+        device.free_memory(self.result_memory, None);
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
@@ -0,0 +1,519 @@
+use std::path::Path;
+
+use crate::error::Result;
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{
+    animation::{AnimationClip, Channel, Interpolation, Property},
+    mesh::{self, Mesh, MeshVertex, SkinVertex, SubMesh},
+    pbr,
+    texture::SamplerDesc,
+};
+
+/// Everything needed to build the engine side representation of a glTF/GLB file.
+pub struct GltfScene {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<PbrMaterialDesc>,
+    pub textures: Vec<TextureDesc>,
+    pub images: Vec<ImageData>,
+    pub cameras: Vec<CameraDesc>,
+    pub nodes: Vec<NodeDesc>,
+    /// root nodes of the default scene
+    pub roots: Vec<usize>,
+    pub animations: Vec<AnimationClip>,
+    pub skins: Vec<SkinDesc>,
+}
+
+/// Decoded image, always converted to RGBA8.
+pub struct ImageData {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextureDesc {
+    /// index into `GltfScene::images`
+    pub image: usize,
+    pub sampler: SamplerDesc,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextureRef {
+    /// index into `GltfScene::textures`
+    pub texture: usize,
+    /// which uv set is used, sets past `mesh::UV_SETS` fall back to the first
+    pub tex_coord: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask(f32),
+    Blend,
+}
+
+/// Parameters of the toon shading model, the colors are linear.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ToonParams {
+    /// what the base color is multiplied with in the dark band of the ramp
+    pub shadow_color: [f32; 3],
+    pub specular_color: [f32; 3],
+    pub specular_strength: f32,
+    /// angular radius of the highlight in radians, 0 turns it off
+    pub specular_size: f32,
+    pub rim_color: [f32; 3],
+    pub rim_strength: f32,
+    /// share of the silhouette covered by the rim, 0..1
+    pub rim_width: f32,
+}
+
+impl Default for ToonParams {
+    fn default() -> Self {
+        Self {
+            shadow_color: [0.45, 0.45, 0.6],
+            specular_color: [1.0; 3],
+            specular_strength: 0.5,
+            specular_size: 0.2,
+            rim_color: [1.0; 3],
+            rim_strength: 0.3,
+            rim_width: 0.3,
+        }
+    }
+}
+
+/// A shading model registered with `PbrMaterials::register_shading`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShadingId(pub(crate) u32);
+
+/// How a material is lit. glTF only knows metallic roughness, so imported materials are `Pbr`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ShadingModel {
+    #[default]
+    Pbr,
+    /// uses the base color, emissive and alpha of the material, metallic, roughness, normal and occlusion are ignored
+    Toon(ToonParams),
+    /// gets the factors and textures its parameters and slots are named after
+    Custom(ShadingId),
+}
+
+/// Metallic roughness material, factors are multiplied with the texture when both exist.
+#[derive(Clone, Debug)]
+pub struct PbrMaterialDesc {
+    pub name: Option<String>,
+    pub base_color_factor: [f32; 4],
+    /// sRGB
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// linear, roughness in green and metallic in blue
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    /// linear, occlusion in red
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    /// sRGB
+    pub emissive_texture: Option<TextureRef>,
+    pub emissive_factor: [f32; 3],
+    pub alpha_mode: AlphaMode,
+    pub double_sided: bool,
+    pub shading: ShadingModel,
+    /// keywords of `pbr::permutations` the metallic roughness shaders are compiled with, the import adds
+    /// NORMAL_MAP to materials with a normal texture
+    pub keywords: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CameraProjection {
+    Perspective {
+        /// radians
+        yfov: f32,
+        aspect_ratio: Option<f32>,
+        znear: f32,
+        /// None means an infinite projection
+        zfar: Option<f32>,
+    },
+    Orthographic {
+        xmag: f32,
+        ymag: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct CameraDesc {
+    pub name: Option<String>,
+    pub projection: CameraProjection,
+}
+
+/// The joints skinned meshes are deformed with, `SkinVertex::joints` index into `joints`.
+#[derive(Clone, Debug)]
+pub struct SkinDesc {
+    pub name: Option<String>,
+    /// node indices
+    pub joints: Vec<usize>,
+    /// from mesh space to the space of each joint at bind time, identity when the file has none
+    pub inverse_bind_matrices: Vec<glm::Matrix4<f32>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NodeDesc {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub local_transform: glm::Matrix4<f32>,
+    /// index into `GltfScene::meshes`
+    pub mesh: Option<usize>,
+    /// index into `GltfScene::cameras`
+    pub camera: Option<usize>,
+    /// index into `GltfScene::skins` the mesh is deformed with
+    pub skin: Option<usize>,
+}
+
+/// Loads a .gltf or .glb file including its buffers and images.
+pub fn load_gltf<P: AsRef<Path>>(path: P) -> Result<GltfScene> {
+    let (document, buffers, images) = gltf::import(path)?;
+
+    let mut meshes = vec![];
+    for gltf_mesh in document.meshes() {
+        let mut mesh = Mesh::default();
+
+        for primitive in gltf_mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                log::warn!("Skipping primitive with unsupported mode {:?}", primitive.mode());
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions.collect(),
+                None => continue,
+            };
+            let normals: Option<Vec<[f32; 3]>> = reader.read_normals().map(|normals| normals.collect());
+            let uv_sets: Vec<Vec<[f32; 2]>> = (0..mesh::UV_SETS as u32)
+                .map_while(|set| reader.read_tex_coords(set).map(|uvs| uvs.into_f32().collect()))
+                .collect();
+            let colors: Option<Vec<[f32; 4]>> = reader.read_colors(0).map(|colors| colors.into_rgba_f32().collect());
+            let joints: Option<Vec<[u16; 4]>> = reader.read_joints(0).map(|joints| joints.into_u16().collect());
+            let weights: Option<Vec<[f32; 4]>> = reader.read_weights(0).map(|weights| weights.into_f32().collect());
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect(),
+            };
+
+            let base_vertex = mesh.vertices.len();
+            for (i, position) in positions.iter().enumerate() {
+                let uv = uv_sets
+                    .first()
+                    .map_or(glm::Vector2::zeros(), |uvs| glm::Vector2::from(uvs[i]));
+                let mut vertex = MeshVertex::new(
+                    glm::Vector3::from(*position),
+                    normals.as_ref().map_or(glm::Vector3::zeros(), |n| glm::Vector3::from(n[i])),
+                    uv,
+                );
+                for (set, uvs) in uv_sets.iter().enumerate().skip(1) {
+                    vertex.uvs[set] = glm::Vector2::from(uvs[i]);
+                }
+                if let Some(colors) = &colors {
+                    vertex.color = glm::Vector4::from(colors[i]);
+                }
+                mesh.vertices.push(vertex);
+            }
+            if normals.is_none() {
+                mesh::generate_normals(&mut mesh.vertices[base_vertex..], &indices);
+            }
+            if let (Some(joints), Some(weights)) = (&joints, &weights) {
+                mesh.skin.resize(base_vertex, SkinVertex::default());
+                mesh.skin
+                    .extend(joints.iter().zip(weights).map(|(joints, weights)| SkinVertex {
+                        joints: *joints,
+                        weights: *weights,
+                    }));
+            }
+
+            mesh.submeshes.push(SubMesh {
+                index_offset: mesh.indices.len() as u32,
+                index_count: indices.len() as u32,
+                material: primitive.material().index(),
+            });
+            mesh.indices.extend(indices.iter().map(|index| index + base_vertex as u32));
+        }
+        if !mesh.skin.is_empty() {
+            mesh.skin.resize(mesh.vertices.len(), SkinVertex::default());
+        }
+        meshes.push(mesh);
+    }
+
+    let texture_ref = |info: gltf::texture::Info| TextureRef {
+        texture: info.texture().index(),
+        tex_coord: info.tex_coord(),
+    };
+
+    let materials = document
+        .materials()
+        .map(|material| {
+            let pbr = material.pbr_metallic_roughness();
+            PbrMaterialDesc {
+                name: material.name().map(str::to_owned),
+                base_color_factor: pbr.base_color_factor(),
+                base_color_texture: pbr.base_color_texture().map(texture_ref),
+                metallic_factor: pbr.metallic_factor(),
+                roughness_factor: pbr.roughness_factor(),
+                metallic_roughness_texture: pbr.metallic_roughness_texture().map(texture_ref),
+                normal_texture: material.normal_texture().map(|normal| TextureRef {
+                    texture: normal.texture().index(),
+                    tex_coord: normal.tex_coord(),
+                }),
+                normal_scale: material.normal_texture().map_or(1.0, |normal| normal.scale()),
+                occlusion_texture: material.occlusion_texture().map(|occlusion| TextureRef {
+                    texture: occlusion.texture().index(),
+                    tex_coord: occlusion.tex_coord(),
+                }),
+                occlusion_strength: material.occlusion_texture().map_or(1.0, |occlusion| occlusion.strength()),
+                emissive_texture: material.emissive_texture().map(texture_ref),
+                emissive_factor: material.emissive_factor(),
+                alpha_mode: match material.alpha_mode() {
+                    gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                    gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff().unwrap_or(0.5)),
+                    gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+                },
+                double_sided: material.double_sided(),
+                shading: ShadingModel::Pbr,
+                keywords: match material.normal_texture() {
+                    Some(_) => vec![pbr::NORMAL_MAP.to_owned()],
+                    None => vec![],
+                },
+            }
+        })
+        .collect();
+
+    let textures = document
+        .textures()
+        .map(|texture| TextureDesc {
+            image: texture.source().index(),
+            sampler: convert_sampler(&texture.sampler()),
+        })
+        .collect();
+
+    let images = images.into_iter().map(convert_image).collect();
+
+    let cameras = document
+        .cameras()
+        .map(|camera| CameraDesc {
+            name: camera.name().map(str::to_owned),
+            projection: match camera.projection() {
+                gltf::camera::Projection::Perspective(perspective) => CameraProjection::Perspective {
+                    yfov: perspective.yfov(),
+                    aspect_ratio: perspective.aspect_ratio(),
+                    znear: perspective.znear(),
+                    zfar: perspective.zfar(),
+                },
+                gltf::camera::Projection::Orthographic(orthographic) => CameraProjection::Orthographic {
+                    xmag: orthographic.xmag(),
+                    ymag: orthographic.ymag(),
+                    znear: orthographic.znear(),
+                    zfar: orthographic.zfar(),
+                },
+            },
+        })
+        .collect();
+
+    let mut nodes: Vec<NodeDesc> = document
+        .nodes()
+        .map(|node| NodeDesc {
+            name: node.name().map(str::to_owned),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            // column major, same as nalgebra
+            local_transform: glm::Matrix4::from(node.transform().matrix()),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            camera: node.camera().map(|camera| camera.index()),
+            skin: node.skin().map(|skin| skin.index()),
+        })
+        .collect();
+
+    for parent in 0..nodes.len() {
+        for child in nodes[parent].children.clone() {
+            nodes[child].parent = Some(parent);
+        }
+    }
+
+    let roots = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => (0..nodes.len()).filter(|node| nodes[*node].parent.is_none()).collect(),
+    };
+
+    let animations = document
+        .animations()
+        .map(|animation| convert_animation(&animation, &buffers))
+        .collect();
+
+    let skins = document
+        .skins()
+        .map(|skin| {
+            let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+            let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+            let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                Some(matrices) => matrices.map(glm::Matrix4::from).collect(),
+                None => vec![glm::Matrix4::identity(); joints.len()],
+            };
+            SkinDesc {
+                name: skin.name().map(str::to_owned),
+                joints,
+                inverse_bind_matrices,
+            }
+        })
+        .collect();
+
+    Ok(GltfScene {
+        meshes,
+        materials,
+        textures,
+        images,
+        cameras,
+        nodes,
+        roots,
+        animations,
+        skins,
+    })
+}
+
+/// Cubic spline channels keep their keys and drop the tangents, morph target weights are skipped.
+fn convert_animation(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> AnimationClip {
+    use gltf::animation::util::ReadOutputs;
+
+    let mut channels = vec![];
+    for channel in animation.channels() {
+        let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+        let (Some(times), Some(outputs)) = (reader.read_inputs(), reader.read_outputs()) else {
+            continue;
+        };
+        let vec3 = |v: [f32; 3]| glm::Vector3::from(v).push(0.0);
+        let (property, values): (_, Vec<glm::Vector4<f32>>) = match outputs {
+            ReadOutputs::Translations(values) => (Property::Translation, values.map(vec3).collect()),
+            ReadOutputs::Rotations(values) => (Property::Rotation, values.into_f32().map(glm::Vector4::from).collect()),
+            ReadOutputs::Scales(values) => (Property::Scale, values.map(vec3).collect()),
+            ReadOutputs::MorphTargetWeights(_) => {
+                log::warn!("Skipping morph target weights of animation {:?}", animation.name());
+                continue;
+            }
+        };
+        let (interpolation, values) = match channel.sampler().interpolation() {
+            gltf::animation::Interpolation::Linear => (Interpolation::Linear, values),
+            gltf::animation::Interpolation::Step => (Interpolation::Step, values),
+            // in tangent, value, out tangent per key
+            gltf::animation::Interpolation::CubicSpline => {
+                (Interpolation::Linear, values.into_iter().skip(1).step_by(3).collect())
+            }
+        };
+        channels.push(Channel {
+            node: channel.target().node().index(),
+            property,
+            interpolation,
+            times: times.collect(),
+            values,
+        });
+    }
+    let duration = channels
+        .iter()
+        .filter_map(|channel| channel.times.last().copied())
+        .fold(0.0, f32::max);
+    AnimationClip {
+        name: animation.name().map(str::to_owned),
+        duration,
+        channels,
+        events: vec![],
+    }
+}
+
+fn convert_sampler(sampler: &gltf::texture::Sampler) -> SamplerDesc {
+    use gltf::texture::{MagFilter, MinFilter, WrappingMode};
+
+    let address_mode = |mode: WrappingMode| match mode {
+        WrappingMode::ClampToEdge => vk::SamplerAddressMode::CLAMP_TO_EDGE,
+        WrappingMode::MirroredRepeat => vk::SamplerAddressMode::MIRRORED_REPEAT,
+        WrappingMode::Repeat => vk::SamplerAddressMode::REPEAT,
+    };
+
+    let mag_filter = match sampler.mag_filter() {
+        Some(MagFilter::Nearest) => vk::Filter::NEAREST,
+        Some(MagFilter::Linear) | None => vk::Filter::LINEAR,
+    };
+
+    let (min_filter, mipmap_mode) = match sampler.min_filter() {
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => {
+            (vk::Filter::NEAREST, vk::SamplerMipmapMode::NEAREST)
+        }
+        Some(MinFilter::NearestMipmapLinear) => (vk::Filter::NEAREST, vk::SamplerMipmapMode::LINEAR),
+        Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => {
+            (vk::Filter::LINEAR, vk::SamplerMipmapMode::NEAREST)
+        }
+        Some(MinFilter::LinearMipmapLinear) | None => (vk::Filter::LINEAR, vk::SamplerMipmapMode::LINEAR),
+    };
+
+    SamplerDesc {
+        mag_filter,
+        min_filter,
+        mipmap_mode,
+        address_mode_u: address_mode(sampler.wrap_s()),
+        address_mode_v: address_mode(sampler.wrap_t()),
+    }
+}
+
+fn convert_image(image: gltf::image::Data) -> ImageData {
+    use gltf::image::Format;
+
+    let pixel_count = (image.width * image.height) as usize;
+    let mut pixels = Vec::with_capacity(pixel_count * 4);
+
+    // 16 bit channels keep their most significant byte, little endian
+    let channel_16 = |bytes: &[u8], channel: usize| bytes[channel * 2 + 1];
+    let channel_f32 = |bytes: &[u8], channel: usize| {
+        let value = f32::from_le_bytes(bytes[channel * 4..channel * 4 + 4].try_into().unwrap());
+        (value.clamp(0.0, 1.0) * 255.0) as u8
+    };
+
+    match image.format {
+        Format::R8G8B8A8 => pixels = image.pixels,
+        Format::R8G8B8 => image
+            .pixels
+            .chunks_exact(3)
+            .for_each(|p| pixels.extend_from_slice(&[p[0], p[1], p[2], 255])),
+        Format::R8G8 => image
+            .pixels
+            .chunks_exact(2)
+            .for_each(|p| pixels.extend_from_slice(&[p[0], p[1], 0, 255])),
+        Format::R8 => image.pixels.iter().for_each(|p| pixels.extend_from_slice(&[*p, *p, *p, 255])),
+        Format::R16G16B16A16 => image.pixels.chunks_exact(8).for_each(|p| {
+            pixels.extend_from_slice(&[channel_16(p, 0), channel_16(p, 1), channel_16(p, 2), channel_16(p, 3)])
+        }),
+        Format::R16G16B16 => image
+            .pixels
+            .chunks_exact(6)
+            .for_each(|p| pixels.extend_from_slice(&[channel_16(p, 0), channel_16(p, 1), channel_16(p, 2), 255])),
+        Format::R16G16 => image
+            .pixels
+            .chunks_exact(4)
+            .for_each(|p| pixels.extend_from_slice(&[channel_16(p, 0), channel_16(p, 1), 0, 255])),
+        Format::R16 => image.pixels.chunks_exact(2).for_each(|p| {
+            let value = channel_16(p, 0);
+            pixels.extend_from_slice(&[value, value, value, 255])
+        }),
+        Format::R32G32B32A32FLOAT => image.pixels.chunks_exact(16).for_each(|p| {
+            pixels.extend_from_slice(&[channel_f32(p, 0), channel_f32(p, 1), channel_f32(p, 2), channel_f32(p, 3)])
+        }),
+        Format::R32G32B32FLOAT => image
+            .pixels
+            .chunks_exact(12)
+            .for_each(|p| pixels.extend_from_slice(&[channel_f32(p, 0), channel_f32(p, 1), channel_f32(p, 2), 255])),
+    }
+
+    ImageData {
+        width: image.width,
+        height: image.height,
+        pixels,
+    }
+}
@@ -0,0 +1,148 @@
+use std::mem::size_of;
+
+use crate::error::Result;
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{blit, pipeline::PipelineBuilder, utility};
+
+/// Look of the editor grid, every viewport keeps its own copy and passes it to `GridPass::record`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GridSettings {
+    pub visible: bool,
+    /// world units between minor lines
+    pub cell_size: f32,
+    /// every n-th line is a major line
+    pub major_every: u32,
+    /// the grid is gone this far from the camera, fading starts at half of it
+    pub fade_distance: f32,
+    /// pixels
+    pub line_width: f32,
+    pub minor_color: glm::Vector4<f32>,
+    pub major_color: glm::Vector4<f32>,
+    /// draws the x axis in `x_axis_color` and the z axis in `z_axis_color`
+    pub show_axes: bool,
+    pub x_axis_color: glm::Vector4<f32>,
+    pub z_axis_color: glm::Vector4<f32>,
+}
+
+impl Default for GridSettings {
+    fn default() -> Self {
+        Self {
+            visible: true,
+            cell_size: 1.0,
+            major_every: 10,
+            fade_distance: 150.0,
+            line_width: 1.0,
+            minor_color: glm::Vector4::new(0.5, 0.5, 0.5, 0.35),
+            major_color: glm::Vector4::new(0.65, 0.65, 0.65, 0.6),
+            show_axes: true,
+            x_axis_color: glm::Vector4::new(0.9, 0.2, 0.2, 1.0),
+            z_axis_color: glm::Vector4::new(0.2, 0.35, 0.9, 1.0),
+        }
+    }
+}
+
+/// Matches the `Grid` push constant block of shaders/grid.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GridPush {
+    minor_color: glm::Vector4<f32>,
+    major_color: glm::Vector4<f32>,
+    x_axis_color: glm::Vector4<f32>,
+    z_axis_color: glm::Vector4<f32>,
+    cell_size: f32,
+    major_every: f32,
+    fade_distance: f32,
+    line_width: f32,
+}
+
+impl GridPush {
+    fn new(settings: &GridSettings) -> Self {
+        // an alpha of 0 hides an axis in the shader
+        let hidden = glm::Vector4::zeros();
+        Self {
+            minor_color: settings.minor_color,
+            major_color: settings.major_color,
+            x_axis_color: if settings.show_axes { settings.x_axis_color } else { hidden },
+            z_axis_color: if settings.show_axes { settings.z_axis_color } else { hidden },
+            cell_size: settings.cell_size.max(f32::EPSILON),
+            major_every: settings.major_every.max(1) as f32,
+            fade_distance: settings.fade_distance,
+            line_width: settings.line_width.max(0.5),
+        }
+    }
+}
+
+/// Infinite grid on the y = 0 plane with the x and z axes, drawn after the opaque geometry of a viewport.
+/// It tests against the depth buffer without writing it, so it belongs before transparent geometry.
+pub struct GridPass {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+}
+
+impl GridPass {
+    /// `frame_set_layout` is the layout of `FrameData`, the grid reads the camera from it at set 0.
+    ///
+    /// # Safety
+    /// `render_pass` has to outlive the pass, `frame_set_layout` has to be a layout of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        frame_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<GridPass> {
+        let (pipeline, pipeline_layout) = PipelineBuilder::new("shaders/spv/grid_vert.spv", "shaders/spv/grid_frag.spv")
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+            .alpha_blending(true)
+            .depth_test(true, false)
+            .descriptor_set_layouts(&[frame_set_layout])
+            .push_constant_range(vk::ShaderStageFlags::FRAGMENT, 0, size_of::<GridPush>() as u32)
+            .build(device, render_pass)?;
+        Ok(GridPass {
+            pipeline,
+            pipeline_layout,
+        })
+    }
+
+    /// Draws the grid inside the current render pass, nothing is recorded when `settings.visible` is off.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside a render pass compatible with the one of `new`,
+    /// `frame_set` has to be written for `FrameData`.
+    pub unsafe fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_set: vk::DescriptorSet,
+        settings: &GridSettings,
+    ) {
+        if !settings.visible {
+            return;
+        }
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[frame_set],
+            &[],
+        );
+        let push = GridPush::new(settings);
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            0,
+            utility::as_bytes(&push),
+        );
+        blit::cmd_draw_fullscreen_triangle(device, command_buffer);
+    }
+
+    /// # Safety
+    /// No frame in flight may draw the grid.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}
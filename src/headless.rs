@@ -0,0 +1,559 @@
+use std::{
+    ffi::{CStr, CString},
+    io::Write,
+    os::raw::c_char,
+    path::Path,
+    ptr,
+};
+
+use crate::error::{Error, Result};
+use ash::{
+    extensions::ext::DebugUtils,
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+
+use crate::{
+    buffer,
+    capabilities::Capabilities,
+    constant::{validation, version},
+    debug::{self, DebugConfig},
+    extensions::{EnabledExtensions, ExtensionRequest},
+    features::DeviceFeatures,
+    frame_hash::{FrameHash, FrameHasher},
+    pipeline,
+    texture::{self, DepthBuffer, Texture},
+};
+
+/// Instance, device and graphics queue without any surface or swapchain extension,
+/// for servers, CI and batch rendering where there is no window system.
+pub struct HeadlessContext {
+    pub entry: ash::Entry,
+    pub instance: ash::Instance,
+    pub physical_device: vk::PhysicalDevice,
+    pub device: ash::Device,
+    pub queue_family: u32,
+    pub queue: vk::Queue,
+    pub command_pool: vk::CommandPool,
+    /// forwards validation messages to `log`, only when the validation layer is enabled
+    pub debug_messenger: Option<(DebugUtils, vk::DebugUtilsMessengerEXT)>,
+    pub instance_extensions: EnabledExtensions,
+    pub device_extensions: EnabledExtensions,
+}
+
+impl HeadlessContext {
+    /// Prefers a discrete GPU, then an integrated one, then anything with a graphics queue,
+    /// so software implementations like lavapipe work in CI.
+    /// `validation` enables the Khronos layer when it is installed, with the messages `DebugConfig::from_env` picks.
+    ///
+    /// # Safety
+    /// Loads the Vulkan library, the context has to be destroyed with `destroy` before it's dropped.
+    pub unsafe fn new(validation: bool) -> Result<HeadlessContext> {
+        Self::with_debug_config(validation.then(DebugConfig::from_env))
+    }
+
+    /// Like `new`, `debug` enables the validation layer and chooses its messages.
+    ///
+    /// # Safety
+    /// As for `new`.
+    pub unsafe fn with_debug_config(debug: Option<DebugConfig>) -> Result<HeadlessContext> {
+        Self::with_extensions(debug, &ExtensionRequest::new(), &ExtensionRequest::new())
+    }
+
+    /// Like `with_debug_config`, devices without the required `device_extensions` aren't considered.
+    ///
+    /// # Safety
+    /// As for `new`, the requested extensions have to be ones vulky knows how to enable.
+    pub unsafe fn with_extensions(
+        debug: Option<DebugConfig>,
+        instance_extensions: &ExtensionRequest,
+        device_extensions: &ExtensionRequest,
+    ) -> Result<HeadlessContext> {
+        let entry = ash::Entry::load()?;
+
+        let app_name = CString::new("vulky headless").unwrap();
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .engine_name(&app_name)
+            .api_version(version::API_VERSION)
+            .engine_version(version::ENGINE_VERSION)
+            .application_version(version::APPLICATION_VERSION)
+            .build();
+
+        let layer_available = entry
+            .enumerate_instance_layer_properties()?
+            .iter()
+            .any(|layer| CStr::from_ptr(layer.layer_name.as_ptr()).to_bytes() == validation::LAYER_NAME.as_bytes());
+        let layer_name = CStr::from_bytes_with_nul_unchecked(validation::LAYER_NAME_BYTES);
+        let validation = debug.is_some() && layer_available;
+        let layers: Vec<*const c_char> = if validation { vec![layer_name.as_ptr()] } else { vec![] };
+        let debug = debug.unwrap_or_default();
+        let validation_features = debug.enabled_validation_features();
+        // the validation layer provides debug utils and validation features itself
+        let mut extensions = instance_extensions.clone();
+        if validation {
+            extensions = extensions.require_name(DebugUtils::name());
+        }
+        let messenger_info = debug.messenger_create_info();
+        let features_info = debug::validation_features_info(
+            &validation_features,
+            &messenger_info as *const vk::DebugUtilsMessengerCreateInfoEXT as *const std::ffi::c_void,
+        );
+        if validation && !validation_features.is_empty() {
+            extensions = extensions.require_name(vk::ExtValidationFeaturesFn::name());
+        }
+        let enabled_layers: &[&CStr] = if validation { &[layer_name] } else { &[] };
+        let instance_extensions = extensions.negotiate_instance(&entry, enabled_layers)?;
+        let extensions = instance_extensions.as_ptrs();
+
+        let instance_info = vk::InstanceCreateInfo {
+            s_type: StructureType::INSTANCE_CREATE_INFO,
+            p_next: if !validation {
+                ptr::null()
+            } else if validation_features.is_empty() {
+                &messenger_info as *const vk::DebugUtilsMessengerCreateInfoEXT as *const std::ffi::c_void
+            } else {
+                &features_info as *const vk::ValidationFeaturesEXT as *const std::ffi::c_void
+            },
+            flags: vk::InstanceCreateFlags::empty(),
+            p_application_info: &app_info,
+            enabled_layer_count: layers.len() as u32,
+            pp_enabled_layer_names: layers.as_ptr(),
+            enabled_extension_count: extensions.len() as u32,
+            pp_enabled_extension_names: extensions.as_ptr(),
+        };
+        let instance = entry.create_instance(&instance_info, None).map_err(Error::InstanceCreation)?;
+        let debug_messenger = if validation {
+            let debug_utils = DebugUtils::new(&entry, &instance);
+            let messenger = debug_utils.create_debug_utils_messenger(&messenger_info, None)?;
+            Some((debug_utils, messenger))
+        } else {
+            None
+        };
+
+        let candidates: Vec<(vk::PhysicalDevice, u32, u32, EnabledExtensions)> = instance
+            .enumerate_physical_devices()?
+            .into_iter()
+            .filter_map(|physical_device| {
+                let family = instance
+                    .get_physical_device_queue_family_properties(physical_device)
+                    .iter()
+                    .position(|family| family.queue_flags.contains(vk::QueueFlags::GRAPHICS))?;
+                let rank = match instance.get_physical_device_properties(physical_device).device_type {
+                    vk::PhysicalDeviceType::DISCRETE_GPU => 0,
+                    vk::PhysicalDeviceType::INTEGRATED_GPU => 1,
+                    vk::PhysicalDeviceType::VIRTUAL_GPU => 2,
+                    _ => 3,
+                };
+                let extensions = match device_extensions.negotiate_device(&instance, physical_device) {
+                    Ok(extensions) => extensions,
+                    Err(error) => {
+                        log::info!("{}", error);
+                        return None;
+                    }
+                };
+                Some((physical_device, family as u32, rank, extensions))
+            })
+            .collect();
+        let Some((physical_device, queue_family, _, device_extensions)) =
+            candidates.into_iter().min_by_key(|candidate| candidate.2)
+        else {
+            if let Some((debug_utils, messenger)) = debug_messenger {
+                debug_utils.destroy_debug_utils_messenger(messenger, None);
+            }
+            instance.destroy_instance(None);
+            return Err(Error::NoSuitableDevice(
+                "No Vulkan device with a graphics queue and the required extensions".to_owned(),
+            ));
+        };
+        let device_extension_names = device_extensions.as_ptrs();
+
+        let queue_priorities = [1.0];
+        let queue_info = vk::DeviceQueueCreateInfo {
+            s_type: StructureType::DEVICE_QUEUE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DeviceQueueCreateFlags::empty(),
+            queue_family_index: queue_family,
+            queue_count: 1,
+            p_queue_priorities: queue_priorities.as_ptr(),
+        };
+        let features = vk::PhysicalDeviceFeatures::default();
+        let device_info = vk::DeviceCreateInfo {
+            s_type: StructureType::DEVICE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DeviceCreateFlags::empty(),
+            queue_create_info_count: 1,
+            p_queue_create_infos: &queue_info,
+            enabled_extension_count: device_extension_names.len() as u32,
+            pp_enabled_extension_names: device_extension_names.as_ptr(),
+            p_enabled_features: &features,
+            // device layers are deprecated, the instance layers apply
+            ..Default::default()
+        };
+        let device = instance
+            .create_device(physical_device, &device_info, None)
+            .map_err(Error::DeviceCreation)?;
+        let queue = device.get_device_queue(queue_family, 0);
+        let command_pool = buffer::create_command_pool(&device, &Some(queue_family))?;
+
+        Ok(HeadlessContext {
+            entry,
+            instance,
+            physical_device,
+            device,
+            queue_family,
+            queue,
+            command_pool,
+            debug_messenger,
+            instance_extensions,
+            device_extensions,
+        })
+    }
+
+    /// Limits, features, memory, queues and format support of the device, `Display` it for bug reports.
+    ///
+    /// # Safety
+    /// The context may not be destroyed yet.
+    pub unsafe fn capabilities(&self) -> Capabilities {
+        // the headless device is created without optional features
+        Capabilities::query(
+            &self.instance,
+            self.physical_device,
+            &DeviceFeatures::default(),
+            &self.device_extensions,
+        )
+    }
+
+    /// Whether the instance or the device was created with `name`, like "VK_KHR_ray_tracing_pipeline".
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.instance_extensions.has_extension(name) || self.device_extensions.has_extension(name)
+    }
+
+    /// # Safety
+    /// Every object created on the device has to be destroyed and the device idle.
+    pub unsafe fn destroy(&self) {
+        self.device.destroy_command_pool(self.command_pool, None);
+        self.device.destroy_device(None);
+        if let Some((debug_utils, messenger)) = &self.debug_messenger {
+            debug_utils.destroy_debug_utils_messenger(*messenger, None);
+            debug::log_suppression_summary();
+        }
+        self.instance.destroy_instance(None);
+    }
+}
+
+/// Bytes per texel of the color formats an `OffscreenTarget` can read back.
+pub fn texel_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+/// Color and depth attachment with a host visible copy of the color image, renders without a swapchain.
+pub struct OffscreenTarget {
+    pub render_pass: vk::RenderPass,
+    pub color: Texture,
+    pub depth: DepthBuffer,
+    pub framebuffer: vk::Framebuffer,
+    pub extent: vk::Extent2D,
+    readback_buffer: vk::Buffer,
+    readback_memory: vk::DeviceMemory,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+}
+
+impl OffscreenTarget {
+    /// # Safety
+    /// The target has to be destroyed with `destroy` of the same `context`.
+    pub unsafe fn new(context: &HeadlessContext, extent: vk::Extent2D, format: vk::Format) -> Result<OffscreenTarget> {
+        let device = &context.device;
+        let color = Texture::new(
+            device,
+            &context.instance,
+            context.physical_device,
+            extent,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSFER_SRC,
+        )?;
+        let Some(texel_size) = texel_size(color.format) else {
+            color.destroy(device);
+            return Err(Error::format(
+                color.format,
+                format!("{:?} can't be read back from an offscreen target", color.format),
+            ));
+        };
+        let depth = DepthBuffer::new(device, &context.instance, context.physical_device, extent)?;
+        let render_pass = pipeline::create_offscreen_render_pass(device, color.format, Some(depth.format), true, false)?;
+        let framebuffer = pipeline::create_framebuffer(device, render_pass, &[color.view, depth.view], extent)?;
+
+        let (readback_buffer, readback_memory) = buffer::create_buffer(
+            device,
+            &context.instance,
+            context.physical_device,
+            (extent.width * extent.height * texel_size) as u64,
+            BufferUsageFlags::TRANSFER_DST,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let alloc_info = vk::CommandBufferAllocateInfo {
+            s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            command_pool: context.command_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            command_buffer_count: 1,
+        };
+        let command_buffer = device.allocate_command_buffers(&alloc_info)?[0];
+        let fence_info = vk::FenceCreateInfo {
+            s_type: StructureType::FENCE_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::FenceCreateFlags::empty(),
+        };
+        let fence = device.create_fence(&fence_info, None)?;
+
+        Ok(OffscreenTarget {
+            render_pass,
+            color,
+            depth,
+            framebuffer,
+            extent,
+            readback_buffer,
+            readback_memory,
+            command_buffer,
+            fence,
+        })
+    }
+
+    /// Clears the target, lets `record` draw inside its render pass and returns the tightly packed texels
+    /// of the result, rows top to bottom. Viewport and scissor are already set to the whole target.
+    /// Blocks until the GPU is done, pipelines have to be built for `render_pass`.
+    ///
+    /// # Safety
+    /// `context` has to be the one the target was created with, `record` may only record valid commands.
+    pub unsafe fn render<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+        &self,
+        context: &HeadlessContext,
+        clear_color: [f32; 4],
+        record: F,
+    ) -> VkResult<Vec<u8>> {
+        let device = &context.device;
+        let command_buffer = self.command_buffer;
+        self.record_pass(device, clear_color, record)?;
+        // the render pass leaves the color image ready to be sampled
+        texture::cmd_transition_image(
+            device,
+            command_buffer,
+            self.color.image,
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+            (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        );
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            self.color.image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            self.readback_buffer,
+            &[region],
+        );
+        let host_barrier = vk::BufferMemoryBarrier {
+            s_type: StructureType::BUFFER_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: self.readback_buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[host_barrier],
+            &[],
+        );
+        self.submit_and_wait(context)?;
+
+        let size = (self.extent.width * self.extent.height * texel_size(self.color.format).unwrap_or(4)) as usize;
+        let mapped = device.map_memory(self.readback_memory, 0, size as u64, MemoryMapFlags::empty())? as *const u8;
+        let pixels = std::slice::from_raw_parts(mapped, size).to_vec();
+        device.unmap_memory(self.readback_memory);
+        Ok(pixels)
+    }
+
+    /// Like `render`, but hashes the result on the GPU instead of reading it back.
+    ///
+    /// # Safety
+    /// As for `render`, `hasher` has to be created on the device of `context`.
+    pub unsafe fn render_hash<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+        &self,
+        context: &HeadlessContext,
+        clear_color: [f32; 4],
+        hasher: &FrameHasher,
+        record: F,
+    ) -> VkResult<FrameHash> {
+        let device = &context.device;
+        let command_buffer = self.command_buffer;
+        self.record_pass(device, clear_color, record)?;
+        let attachment_written = (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        );
+        let compute_read = (
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::PipelineStageFlags::COMPUTE_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        );
+        texture::cmd_transition_image(device, command_buffer, self.color.image, attachment_written, compute_read);
+        hasher.cmd_hash(device, command_buffer, 0, self.color.view, self.extent);
+        self.submit_and_wait(context)?;
+        hasher.read(device, 0)
+    }
+
+    /// Begins the command buffer and records the render pass around `record`.
+    unsafe fn record_pass<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+        &self,
+        device: &ash::Device,
+        clear_color: [f32; 4],
+        record: F,
+    ) -> VkResult<()> {
+        let command_buffer = self.command_buffer;
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+
+        let begin_info = vk::CommandBufferBeginInfo {
+            s_type: StructureType::COMMAND_BUFFER_BEGIN_INFO,
+            p_next: ptr::null(),
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            p_inheritance_info: ptr::null(),
+        };
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue { float32: clear_color },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+            },
+        ];
+        let render_area = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent: self.extent,
+        };
+        let render_pass_info = vk::RenderPassBeginInfo {
+            s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+            p_next: ptr::null(),
+            render_pass: self.render_pass,
+            framebuffer: self.framebuffer,
+            render_area,
+            clear_value_count: clear_values.len() as u32,
+            p_clear_values: clear_values.as_ptr(),
+        };
+        device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: self.extent.width as f32,
+            height: self.extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        record(device, command_buffer);
+        device.cmd_end_render_pass(command_buffer);
+        Ok(())
+    }
+
+    /// Ends the command buffer, submits it and waits for it.
+    unsafe fn submit_and_wait(&self, context: &HeadlessContext) -> VkResult<()> {
+        let device = &context.device;
+        let command_buffer = self.command_buffer;
+        device.end_command_buffer(command_buffer)?;
+
+        let submit_info = vk::SubmitInfo {
+            s_type: StructureType::SUBMIT_INFO,
+            p_next: ptr::null(),
+            wait_semaphore_count: 0,
+            p_wait_semaphores: ptr::null(),
+            p_wait_dst_stage_mask: ptr::null(),
+            command_buffer_count: 1,
+            p_command_buffers: &command_buffer,
+            signal_semaphore_count: 0,
+            p_signal_semaphores: ptr::null(),
+        };
+        device.queue_submit(context.queue, &[submit_info], self.fence)?;
+        device.wait_for_fences(&[self.fence], true, u64::MAX)?;
+        device.reset_fences(&[self.fence])?;
+
+        Ok(())
+    }
+
+    /// Writes pixels of an 8 bit RGBA or BGRA target returned by `render` as a binary PPM, alpha is dropped.
+    pub fn write_ppm<P: AsRef<Path>>(&self, path: P, pixels: &[u8]) -> Result<()> {
+        let bgra = match self.color.format {
+            vk::Format::R8G8B8A8_UNORM | vk::Format::R8G8B8A8_SRGB => false,
+            vk::Format::B8G8R8A8_UNORM | vk::Format::B8G8R8A8_SRGB => true,
+            format => return Err(Error::format(format, format!("Can't write {:?} as PPM", format))),
+        };
+        let mut data = format!("P6\n{} {}\n255\n", self.extent.width, self.extent.height).into_bytes();
+        for texel in pixels.chunks_exact(4) {
+            if bgra {
+                data.extend_from_slice(&[texel[2], texel[1], texel[0]]);
+            } else {
+                data.extend_from_slice(&texel[..3]);
+            }
+        }
+        std::fs::File::create(path.as_ref())?.write_all(&data)?;
+        Ok(())
+    }
+
+    /// # Safety
+    /// `context` has to be the one the target was created with.
+    pub unsafe fn destroy(&self, context: &HeadlessContext) {
+        let device = &context.device;
+        device.destroy_fence(self.fence, None);
+        device.free_command_buffers(context.command_pool, &[self.command_buffer]);
+        device.destroy_buffer(self.readback_buffer, None);
+        device.free_memory(self.readback_memory, None);
+        device.destroy_framebuffer(self.framebuffer, None);
+        self.depth.destroy(device);
+        self.color.destroy(device);
+        device.destroy_render_pass(self.render_pass, None);
+    }
+}
@@ -0,0 +1,341 @@
+use std::{mem::size_of, ptr};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+
+use crate::{
+    buffer::{self, MAX_FRAMES_IN_FLIGHT},
+    texture,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HoverSettings {
+    /// pixels around the cursor searched when it isn't over anything, so thin lines and edges are easier to hit
+    pub radius: u32,
+    /// id the ID buffer is cleared to
+    pub background: u32,
+}
+
+impl Default for HoverSettings {
+    fn default() -> Self {
+        Self {
+            radius: 2,
+            background: 0,
+        }
+    }
+}
+
+impl HoverSettings {
+    pub fn radius(mut self, radius: u32) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    pub fn background(mut self, background: u32) -> Self {
+        self.background = background;
+        self
+    }
+}
+
+/// What was under the cursor in the frame `frame` of `HoverQuery::record`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Hover {
+    /// pixel of the ID buffer
+    pub cursor: (u32, u32),
+    /// None over the background or with the cursor outside of the image
+    pub id: Option<u32>,
+    pub frame: u64,
+}
+
+struct Pending {
+    cursor: (u32, u32),
+    /// copied pixels, x, y, width and height
+    region: (u32, u32, u32, u32),
+    frame: u64,
+}
+
+struct Slot {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    mapped: *const u32,
+    /// set by the GPU once the copy finished, so results are read without waiting on the frame
+    copied: vk::Event,
+    pending: Option<Pending>,
+}
+
+/// The ID buffer `HoverQuery::record` copies from, an R32_UINT image.
+#[derive(Clone, Copy, Debug)]
+pub struct IdImage {
+    pub image: vk::Image,
+    pub extent: vk::Extent2D,
+    /// layout the image is in and the stage and access reading it next, it's left that way
+    pub layout: (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags),
+}
+
+/// Asks the ID buffer what is under the cursor every frame without stalling on the readback. The pixels around
+/// the cursor are copied into host memory of the frame in flight and `poll` picks up the newest copy the GPU
+/// finished, usually the one of the previous frame. Feed the hovered id to the highlight of the tool.
+pub struct HoverQuery {
+    settings: HoverSettings,
+    slots: Vec<Slot>,
+    frame: u64,
+    latest: Option<Hover>,
+}
+
+impl HoverQuery {
+    /// # Safety
+    /// `physical_device` has to be the device of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        settings: HoverSettings,
+    ) -> VkResult<HoverQuery> {
+        let side = 2 * settings.radius as vk::DeviceSize + 1;
+        let size = side * side * size_of::<u32>() as vk::DeviceSize;
+        let mut query = HoverQuery {
+            settings,
+            slots: vec![],
+            frame: 0,
+            latest: None,
+        };
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let slot = create_slot(device, instance, physical_device, size);
+            match slot {
+                Ok(slot) => query.slots.push(slot),
+                Err(e) => {
+                    query.destroy(device);
+                    return Err(e);
+                }
+            }
+        }
+        Ok(query)
+    }
+
+    pub fn settings(&self) -> HoverSettings {
+        self.settings
+    }
+
+    /// Copies the pixels around `cursor` of `id_image` outside of a render pass. The image is moved to
+    /// TRANSFER_SRC_OPTIMAL for the copy and back to its layout for the stage and access given with it. Call it
+    /// after the fence of `current_frame` was waited on. `cursor` is in framebuffer pixels, outside of the image
+    /// or None the next result reports nothing hovered.
+    ///
+    /// # Safety
+    /// `id_image.layout` has to be the layout the image is in when the copy runs.
+    pub unsafe fn record(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        id_image: &IdImage,
+        cursor: Option<(f32, f32)>,
+    ) -> VkResult<()> {
+        let IdImage {
+            image: id_image,
+            extent,
+            layout,
+        } = *id_image;
+        self.frame += 1;
+        let frame = self.frame;
+        let radius = self.settings.radius;
+        let slot = &mut self.slots[current_frame];
+        // the previous copy of the slot finished with its frame
+        device.reset_event(slot.copied)?;
+
+        let inside =
+            cursor.filter(|&(x, y)| x >= 0.0 && y >= 0.0 && (x as u32) < extent.width && (y as u32) < extent.height);
+        let Some((x, y)) = inside else {
+            slot.pending = None;
+            self.latest = Some(Hover {
+                cursor: (0, 0),
+                id: None,
+                frame,
+            });
+            return Ok(());
+        };
+        let cursor = (x as u32, y as u32);
+        let x0 = cursor.0.saturating_sub(radius);
+        let y0 = cursor.1.saturating_sub(radius);
+        let x1 = (cursor.0 + radius + 1).min(extent.width);
+        let y1 = (cursor.1 + radius + 1).min(extent.height);
+        let region = (x0, y0, x1 - x0, y1 - y0);
+
+        let (old_layout, stage, access) = layout;
+        texture::cmd_transition_image(
+            device,
+            command_buffer,
+            id_image,
+            (old_layout, stage, access),
+            (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        );
+        let copy = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D {
+                x: x0 as i32,
+                y: y0 as i32,
+                z: 0,
+            },
+            image_extent: vk::Extent3D {
+                width: region.2,
+                height: region.3,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_image_to_buffer(
+            command_buffer,
+            id_image,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            slot.buffer,
+            &[copy],
+        );
+        texture::cmd_transition_image(
+            device,
+            command_buffer,
+            id_image,
+            (
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+            (old_layout, stage, access),
+        );
+
+        let host_barrier = vk::BufferMemoryBarrier {
+            s_type: StructureType::BUFFER_MEMORY_BARRIER,
+            p_next: ptr::null(),
+            src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+            dst_access_mask: vk::AccessFlags::HOST_READ,
+            src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+            buffer: slot.buffer,
+            offset: 0,
+            size: vk::WHOLE_SIZE,
+        };
+        device.cmd_pipeline_barrier(
+            command_buffer,
+            vk::PipelineStageFlags::TRANSFER,
+            vk::PipelineStageFlags::HOST,
+            vk::DependencyFlags::empty(),
+            &[],
+            &[host_barrier],
+            &[],
+        );
+        device.cmd_set_event(command_buffer, slot.copied, vk::PipelineStageFlags::TRANSFER);
+        slot.pending = Some(Pending { cursor, region, frame });
+        Ok(())
+    }
+
+    /// Reads the copies the GPU finished and returns the newest result, without waiting. Call it once a frame.
+    ///
+    /// # Safety
+    /// `device` has to be the device of `new`.
+    pub unsafe fn poll(&mut self, device: &ash::Device) -> VkResult<Option<Hover>> {
+        let background = self.settings.background;
+        for slot in self.slots.iter_mut() {
+            let Some(pending) = slot.pending.as_ref() else {
+                continue;
+            };
+            if !device.get_event_status(slot.copied)? {
+                continue;
+            }
+            let (x0, y0, width, height) = pending.region;
+            let ids = std::slice::from_raw_parts(slot.mapped, (width * height) as usize);
+            let hover = Hover {
+                cursor: pending.cursor,
+                id: closest_id(ids, (pending.cursor.0 - x0, pending.cursor.1 - y0), width, background),
+                frame: pending.frame,
+            };
+            slot.pending = None;
+            if self.latest.is_none_or(|latest| latest.frame < hover.frame) {
+                self.latest = Some(hover);
+            }
+        }
+        Ok(self.latest)
+    }
+
+    /// The last result of `poll`.
+    pub fn latest(&self) -> Option<Hover> {
+        self.latest
+    }
+
+    /// The hovered id, None over the background or before the first result.
+    pub fn hovered(&self) -> Option<u32> {
+        self.latest.and_then(|hover| hover.id)
+    }
+
+    /// # Safety
+    /// No submission copying into the query may be pending.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for slot in self.slots.iter() {
+            device.unmap_memory(slot.memory);
+            device.destroy_buffer(slot.buffer, None);
+            device.free_memory(slot.memory, None);
+            device.destroy_event(slot.copied, None);
+        }
+    }
+}
+
+unsafe fn create_slot(
+    device: &ash::Device,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    size: vk::DeviceSize,
+) -> VkResult<Slot> {
+    let (buffer, memory) = buffer::create_buffer(
+        device,
+        instance,
+        physical_device,
+        size,
+        BufferUsageFlags::TRANSFER_DST,
+        MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+    )?;
+    let slot = device
+        .map_memory(memory, 0, size, MemoryMapFlags::empty())
+        .and_then(|mapped| {
+            let copied = device.create_event(&vk::EventCreateInfo::default(), None)?;
+            Ok((mapped, copied))
+        });
+    match slot {
+        Ok((mapped, copied)) => Ok(Slot {
+            buffer,
+            memory,
+            mapped: mapped as *const u32,
+            copied,
+            pending: None,
+        }),
+        Err(e) => {
+            device.destroy_buffer(buffer, None);
+            device.free_memory(memory, None);
+            Err(e)
+        }
+    }
+}
+
+/// The id at `center` of the `width` wide pixels `ids`, or the one closest to it that isn't `background`.
+pub fn closest_id(ids: &[u32], center: (u32, u32), width: u32, background: u32) -> Option<u32> {
+    let width = width.max(1) as usize;
+    ids.iter()
+        .enumerate()
+        .filter(|(_, &id)| id != background)
+        .min_by_key(|(index, _)| {
+            let dx = (index % width) as i64 - center.0 as i64;
+            let dy = (index / width) as i64 - center.1 as i64;
+            dx * dx + dy * dy
+        })
+        .map(|(_, &id)| id)
+}
@@ -0,0 +1,280 @@
+use std::ptr;
+
+use ash::vk::{self, StructureType};
+
+use crate::{
+    error::{Error, Result},
+    format,
+};
+
+/// The image a view is created for, what `create_view` checks the view against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageDesc {
+    pub image: vk::Image,
+    pub format: vk::Format,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    /// MUTABLE_FORMAT to view it in other formats, CUBE_COMPATIBLE for cube views
+    pub flags: vk::ImageCreateFlags,
+}
+
+impl ImageDesc {
+    /// A single mip and layer, like the images of `buffer::create_image`.
+    pub fn new(image: vk::Image, format: vk::Format) -> Self {
+        Self {
+            image,
+            format,
+            mip_levels: 1,
+            array_layers: 1,
+            flags: vk::ImageCreateFlags::empty(),
+        }
+    }
+
+    pub fn mip_levels(mut self, mip_levels: u32) -> Self {
+        self.mip_levels = mip_levels;
+        self
+    }
+
+    pub fn array_layers(mut self, array_layers: u32) -> Self {
+        self.array_layers = array_layers;
+        self
+    }
+
+    pub fn flags(mut self, flags: vk::ImageCreateFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Create info of a 2D image matching the description, for `owned::Image::new`. `image` is ignored.
+    pub fn create_info(&self, extent: vk::Extent2D, usage: vk::ImageUsageFlags) -> vk::ImageCreateInfo {
+        vk::ImageCreateInfo {
+            flags: self.flags,
+            image_type: vk::ImageType::TYPE_2D,
+            format: self.format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: self.mip_levels,
+            array_layers: self.array_layers,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            ..Default::default()
+        }
+    }
+}
+
+/// Which part of an image a view shows and how. The default is a 2D view of every mip of the first layer, with
+/// the image's format and all of its aspects.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewDesc {
+    pub view_type: vk::ImageViewType,
+    /// None keeps the image's format
+    pub format: Option<vk::Format>,
+    pub components: vk::ComponentMapping,
+    /// None views every aspect of the format
+    pub aspect: Option<vk::ImageAspectFlags>,
+    pub base_mip_level: u32,
+    /// `vk::REMAINING_MIP_LEVELS` for the mips from `base_mip_level` on
+    pub mip_count: u32,
+    pub base_array_layer: u32,
+    /// `vk::REMAINING_ARRAY_LAYERS` for the layers from `base_array_layer` on
+    pub layer_count: u32,
+}
+
+impl Default for ViewDesc {
+    fn default() -> Self {
+        Self {
+            view_type: vk::ImageViewType::TYPE_2D,
+            format: None,
+            components: vk::ComponentMapping::default(),
+            aspect: None,
+            base_mip_level: 0,
+            mip_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: 1,
+        }
+    }
+}
+
+impl ViewDesc {
+    /// The depth of a depth stencil image, the aspect that is sampled.
+    pub fn depth() -> Self {
+        Self::default().aspect(vk::ImageAspectFlags::DEPTH)
+    }
+
+    /// The stencil of a depth stencil image, sampled as an unsigned integer.
+    pub fn stencil() -> Self {
+        Self::default().aspect(vk::ImageAspectFlags::STENCIL)
+    }
+
+    /// All six faces of a cube compatible image.
+    pub fn cube() -> Self {
+        Self::default().view_type(vk::ImageViewType::CUBE).layers(0, 6)
+    }
+
+    /// One face of a cube, 0..6 in +X -X +Y -Y +Z -Z order, as a 2D view to render into or inspect.
+    pub fn cube_face(face: u32) -> Self {
+        Self::default().layers(face, 1)
+    }
+
+    /// Shows `channel` in red, green and blue with an opaque alpha, to look at a single channel of a texture.
+    pub fn channel(channel: vk::ComponentSwizzle) -> Self {
+        Self::default().swizzle(channel, channel, channel, vk::ComponentSwizzle::ONE)
+    }
+
+    pub fn view_type(mut self, view_type: vk::ImageViewType) -> Self {
+        self.view_type = view_type;
+        self
+    }
+
+    /// Reinterprets the texels, the image needs MUTABLE_FORMAT and `format` the same texel size.
+    pub fn format(mut self, format: vk::Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    pub fn swizzle(
+        mut self,
+        r: vk::ComponentSwizzle,
+        g: vk::ComponentSwizzle,
+        b: vk::ComponentSwizzle,
+        a: vk::ComponentSwizzle,
+    ) -> Self {
+        self.components = vk::ComponentMapping { r, g, b, a };
+        self
+    }
+
+    pub fn aspect(mut self, aspect: vk::ImageAspectFlags) -> Self {
+        self.aspect = Some(aspect);
+        self
+    }
+
+    pub fn mips(mut self, base_mip_level: u32, mip_count: u32) -> Self {
+        self.base_mip_level = base_mip_level;
+        self.mip_count = mip_count;
+        self
+    }
+
+    pub fn layers(mut self, base_array_layer: u32, layer_count: u32) -> Self {
+        self.base_array_layer = base_array_layer;
+        self.layer_count = layer_count;
+        self
+    }
+
+    /// The range of `image` the view covers, with the remaining mips and layers counted out.
+    pub fn subresource_range(&self, image: &ImageDesc) -> vk::ImageSubresourceRange {
+        let remaining = |count: u32, base: u32, total: u32| match count {
+            u32::MAX => total.saturating_sub(base),
+            count => count,
+        };
+        vk::ImageSubresourceRange {
+            aspect_mask: self.aspect.unwrap_or_else(|| format::aspect_flags(image.format)),
+            base_mip_level: self.base_mip_level,
+            level_count: remaining(self.mip_count, self.base_mip_level, image.mip_levels),
+            base_array_layer: self.base_array_layer,
+            layer_count: remaining(self.layer_count, self.base_array_layer, image.array_layers),
+        }
+    }
+}
+
+/// Checks what the validation layers would reject, so a wrong view is an error instead of undefined behavior.
+pub fn validate(image: &ImageDesc, view: &ViewDesc) -> Result<()> {
+    let range = view.subresource_range(image);
+    let image_aspects = format::aspect_flags(image.format);
+    if range.aspect_mask.is_empty() || !image_aspects.contains(range.aspect_mask) {
+        return Err(Error::format(
+            image.format,
+            format!(
+                "A {:?} image has no {:?} aspect, only {:?}",
+                image.format, range.aspect_mask, image_aspects
+            ),
+        ));
+    }
+    if range.level_count == 0 || range.base_mip_level.saturating_add(range.level_count) > image.mip_levels {
+        return Err(Error::msg(format!(
+            "Mips {}..{} are outside of the {} mips of the image",
+            range.base_mip_level,
+            range.base_mip_level.saturating_add(range.level_count),
+            image.mip_levels
+        )));
+    }
+    if range.layer_count == 0 || range.base_array_layer.saturating_add(range.layer_count) > image.array_layers {
+        return Err(Error::msg(format!(
+            "Layers {}..{} are outside of the {} layers of the image",
+            range.base_array_layer,
+            range.base_array_layer.saturating_add(range.layer_count),
+            image.array_layers
+        )));
+    }
+
+    let layers_fit = match view.view_type {
+        vk::ImageViewType::TYPE_1D | vk::ImageViewType::TYPE_2D | vk::ImageViewType::TYPE_3D => range.layer_count == 1,
+        vk::ImageViewType::CUBE => range.layer_count == 6,
+        vk::ImageViewType::CUBE_ARRAY => range.layer_count.is_multiple_of(6),
+        _ => true,
+    };
+    if !layers_fit {
+        return Err(Error::msg(format!(
+            "A {:?} view can't show {} layers",
+            view.view_type, range.layer_count
+        )));
+    }
+    let cube = matches!(view.view_type, vk::ImageViewType::CUBE | vk::ImageViewType::CUBE_ARRAY);
+    if cube && !image.flags.contains(vk::ImageCreateFlags::CUBE_COMPATIBLE) {
+        return Err(Error::msg("Cube views need an image created CUBE_COMPATIBLE"));
+    }
+
+    let Some(format) = view.format.filter(|&format| format != image.format) else {
+        return Ok(());
+    };
+    if !image.flags.contains(vk::ImageCreateFlags::MUTABLE_FORMAT) {
+        return Err(Error::format(
+            format,
+            format!(
+                "Viewing a {:?} image as {:?} needs an image created MUTABLE_FORMAT",
+                image.format, format
+            ),
+        ));
+    }
+    match (format::texel_size(image.format), format::texel_size(format)) {
+        (Some(image_size), Some(view_size)) if image_size == view_size => Ok(()),
+        (Some(image_size), Some(view_size)) => Err(Error::format(
+            format,
+            format!(
+                "{:?} has {} byte texels, {:?} {}, views keep the texel size",
+                image.format, image_size, format, view_size
+            ),
+        )),
+        _ => Err(Error::format(
+            format,
+            format!(
+                "Can't reinterpret {:?} as {:?}, only uncompressed color formats are",
+                image.format, format
+            ),
+        )),
+    }
+}
+
+/// Creates the view after `validate` accepted it. Destroy it with `destroy_image_view`, before the image.
+///
+/// # Safety
+/// `image.image` has to be an image of `device` created with the rest of `image`.
+pub unsafe fn create_view(device: &ash::Device, image: &ImageDesc, view: &ViewDesc) -> Result<vk::ImageView> {
+    validate(image, view)?;
+    let view_info = vk::ImageViewCreateInfo {
+        s_type: StructureType::IMAGE_VIEW_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::ImageViewCreateFlags::empty(),
+        image: image.image,
+        view_type: view.view_type,
+        format: view.format.unwrap_or(image.format),
+        components: view.components,
+        subresource_range: view.subresource_range(image),
+    };
+    Ok(device.create_image_view(&view_info, None)?)
+}
@@ -0,0 +1,617 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+use crate::error::{Error, Result};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+use serde::{Deserialize, Serialize};
+use winit::event::{DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent};
+
+pub use winit::event::VirtualKeyCode as Key;
+
+/// Actions with an absolute value at or above this count as pressed.
+pub const PRESS_THRESHOLD: f32 = 0.5;
+
+/// Pixel based scrolling of touchpads is converted to lines with this.
+pub const PIXELS_PER_SCROLL_LINE: f32 = 20.0;
+
+/// Keyboard and mouse state of the current frame, collected from the event loop.
+#[derive(Default)]
+pub struct InputState {
+    keys_down: HashSet<Key>,
+    keys_pressed: HashSet<Key>,
+    keys_released: HashSet<Key>,
+    mouse_down: HashSet<MouseButton>,
+    mouse_pressed: HashSet<MouseButton>,
+    mouse_released: HashSet<MouseButton>,
+    /// physical pixels from the top left of the window, None while the cursor is outside
+    cursor_position: Option<(f32, f32)>,
+    mouse_delta: (f32, f32),
+    scroll_delta: (f32, f32),
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Forward every event of the event loop.
+    pub fn handle_event<T>(&mut self, event: &Event<T>) {
+        match event {
+            Event::WindowEvent { event, .. } => self.handle_window_event(event),
+            Event::DeviceEvent { event, .. } => self.handle_device_event(event),
+            _ => {}
+        }
+    }
+
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    match input.state {
+                        ElementState::Pressed => {
+                            // key repeats aren't new presses
+                            if self.keys_down.insert(key) {
+                                self.keys_pressed.insert(key);
+                            }
+                        }
+                        ElementState::Released => {
+                            if self.keys_down.remove(&key) {
+                                self.keys_released.insert(key);
+                            }
+                        }
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    if self.mouse_down.insert(*button) {
+                        self.mouse_pressed.insert(*button);
+                    }
+                }
+                ElementState::Released => {
+                    if self.mouse_down.remove(button) {
+                        self.mouse_released.insert(*button);
+                    }
+                }
+            },
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = Some((position.x as f32, position.y as f32));
+            }
+            WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+            WindowEvent::MouseWheel { delta, .. } => {
+                let (x, y) = match delta {
+                    MouseScrollDelta::LineDelta(x, y) => (*x, *y),
+                    MouseScrollDelta::PixelDelta(position) => (
+                        position.x as f32 / PIXELS_PER_SCROLL_LINE,
+                        position.y as f32 / PIXELS_PER_SCROLL_LINE,
+                    ),
+                };
+                self.scroll_delta.0 += x;
+                self.scroll_delta.1 += y;
+            }
+            // release events are lost while unfocused, so everything held is released
+            WindowEvent::Focused(false) => {
+                self.keys_released.extend(self.keys_down.drain());
+                self.mouse_released.extend(self.mouse_down.drain());
+            }
+            _ => {}
+        }
+    }
+
+    /// Raw mouse motion, it keeps coming while the cursor is locked.
+    pub fn handle_device_event(&mut self, event: &DeviceEvent) {
+        if let DeviceEvent::MouseMotion { delta: (x, y) } = event {
+            self.mouse_delta.0 += *x as f32;
+            self.mouse_delta.1 += *y as f32;
+        }
+    }
+
+    /// Clears the per frame changes, call it after the update code ran.
+    pub fn end_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.mouse_pressed.clear();
+        self.mouse_released.clear();
+        self.mouse_delta = (0.0, 0.0);
+        self.scroll_delta = (0.0, 0.0);
+    }
+
+    pub fn key_down(&self, key: Key) -> bool {
+        self.keys_down.contains(&key)
+    }
+
+    /// Pressed since the last `end_frame`.
+    pub fn key_just_pressed(&self, key: Key) -> bool {
+        self.keys_pressed.contains(&key)
+    }
+
+    pub fn key_just_released(&self, key: Key) -> bool {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn mouse_down(&self, button: MouseButton) -> bool {
+        self.mouse_down.contains(&button)
+    }
+
+    pub fn mouse_just_pressed(&self, button: MouseButton) -> bool {
+        self.mouse_pressed.contains(&button)
+    }
+
+    pub fn mouse_just_released(&self, button: MouseButton) -> bool {
+        self.mouse_released.contains(&button)
+    }
+
+    pub fn cursor_position(&self) -> Option<(f32, f32)> {
+        self.cursor_position
+    }
+
+    /// Raw mouse counts since the last `end_frame`, only filled from device events.
+    pub fn mouse_delta(&self) -> (f32, f32) {
+        self.mouse_delta
+    }
+
+    /// Lines scrolled since the last `end_frame`, positive y scrolls up.
+    pub fn scroll_delta(&self) -> (f32, f32) {
+        self.scroll_delta
+    }
+}
+
+/// Connected gamepads, the one that sent the latest input is the active one.
+pub struct Gamepads {
+    gilrs: Gilrs,
+    active: Option<GamepadId>,
+    /// stick values below this are treated as zero
+    pub deadzone: f32,
+    /// inputs of the active gamepad since the last poll, used to capture new bindings
+    just_pressed: Vec<InputSource>,
+}
+
+impl Gamepads {
+    /// Falls back to a context without gamepads on platforms gilrs doesn't support.
+    pub fn new() -> Result<Self> {
+        let gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(gilrs::Error::NotImplemented(dummy)) => {
+                log::warn!("Gamepads aren't supported on this platform");
+                dummy
+            }
+            Err(e) => return Err(Error::msg(format!("Failed to initialize gamepads: {}", e))),
+        };
+
+        let active = gilrs.gamepads().next().map(|(id, _)| id);
+        Ok(Self {
+            gilrs,
+            active,
+            deadzone: 0.15,
+            just_pressed: vec![],
+        })
+    }
+
+    /// Has to be called once per frame before the action maps are updated.
+    pub fn poll(&mut self) {
+        self.just_pressed.clear();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {}", self.gilrs.gamepad(event.id).name());
+                    self.active.get_or_insert(event.id);
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", self.gilrs.gamepad(event.id).name());
+                    if self.active == Some(event.id) {
+                        self.active = self.gilrs.gamepads().next().map(|(id, _)| id);
+                    }
+                }
+                EventType::Dropped => {}
+                EventType::ButtonPressed(button, _) => {
+                    self.active = Some(event.id);
+                    self.just_pressed.push(InputSource::GamepadButton(button));
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    self.active = Some(event.id);
+                    if value.abs() >= PRESS_THRESHOLD {
+                        self.just_pressed.push(InputSource::GamepadAxis(axis));
+                    }
+                }
+                _ => self.active = Some(event.id),
+            }
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.active.is_some()
+    }
+
+    /// 0..1, analog triggers report partial values
+    pub fn button(&self, button: Button) -> f32 {
+        self.active
+            .and_then(|id| self.gilrs.gamepad(id).button_data(button).map(|data| data.value()))
+            .unwrap_or(0.0)
+    }
+
+    /// -1..1, up and right are positive
+    pub fn axis(&self, axis: Axis) -> f32 {
+        let value = self.active.map(|id| self.gilrs.gamepad(id).value(axis)).unwrap_or(0.0);
+        if value.abs() < self.deadzone {
+            0.0
+        } else {
+            value
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum InputSource {
+    Key(VirtualKeyCode),
+    MouseButton(MouseButton),
+    GamepadButton(Button),
+    GamepadAxis(Axis),
+}
+
+/// An input contributing `scale * value` to an action,
+/// a negative scale lets opposite keys drive the same axis action.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    #[serde(flatten)]
+    pub source: InputSource,
+    #[serde(default = "default_scale", skip_serializing_if = "is_default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn is_default_scale(scale: &f32) -> bool {
+    *scale == 1.0
+}
+
+/// Layout of a saved bindings file.
+#[derive(Default, Serialize, Deserialize)]
+struct BindingsFile {
+    #[serde(default)]
+    actions: BTreeMap<String, Vec<Binding>>,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct ActionState {
+    value: f32,
+    previous: f32,
+}
+
+/// Named actions bound to keys, mouse buttons and gamepad buttons or axes.
+/// Every action has a value in -1..1, digital inputs contribute 0 or their scale.
+#[derive(Default)]
+pub struct ActionMap {
+    bindings: HashMap<String, Vec<Binding>>,
+    states: HashMap<String, ActionState>,
+    keys_down: HashSet<VirtualKeyCode>,
+    mouse_buttons_down: HashSet<MouseButton>,
+    /// Some while waiting for the next input to rebind, holds it once captured
+    capture: Option<Option<InputSource>>,
+}
+
+impl ActionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bind(&mut self, action: &str, source: InputSource) {
+        self.bind_scaled(action, source, 1.0);
+    }
+
+    pub fn bind_scaled(&mut self, action: &str, source: InputSource, scale: f32) {
+        self.bindings
+            .entry(action.to_owned())
+            .or_default()
+            .push(Binding { source, scale });
+        self.states.entry(action.to_owned()).or_default();
+    }
+
+    pub fn unbind_all(&mut self, action: &str) {
+        self.bindings.remove(action);
+    }
+
+    /// Replaces the input of an existing binding and keeps its scale.
+    pub fn rebind(&mut self, action: &str, index: usize, source: InputSource) -> Result<()> {
+        let binding = self
+            .bindings
+            .get_mut(action)
+            .and_then(|bindings| bindings.get_mut(index))
+            .ok_or_else(|| Error::msg(format!("Action {} has no binding {}", action, index)))?;
+        binding.source = source;
+        Ok(())
+    }
+
+    pub fn remove_binding(&mut self, action: &str, index: usize) -> Result<Binding> {
+        let bindings = self
+            .bindings
+            .get_mut(action)
+            .filter(|bindings| index < bindings.len())
+            .ok_or_else(|| Error::msg(format!("Action {} has no binding {}", action, index)))?;
+        Ok(bindings.remove(index))
+    }
+
+    /// Every action that has or had bindings, sorted by name.
+    pub fn actions(&self) -> Vec<&str> {
+        let mut actions: Vec<&str> = self.states.keys().map(|action| action.as_str()).collect();
+        actions.sort_unstable();
+        actions
+    }
+
+    /// Actions `source` is bound to, a settings screen can warn about duplicates with it.
+    pub fn actions_bound_to(&self, source: InputSource) -> Vec<&str> {
+        let mut actions: Vec<&str> = self
+            .bindings
+            .iter()
+            .filter(|(_, bindings)| bindings.iter().any(|binding| binding.source == source))
+            .map(|(action, _)| action.as_str())
+            .collect();
+        actions.sort_unstable();
+        actions
+    }
+
+    /// Starts listening for the next pressed key, mouse button, gamepad button or axis.
+    pub fn begin_capture(&mut self) {
+        self.capture = Some(None);
+    }
+
+    pub fn cancel_capture(&mut self) {
+        self.capture = None;
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    /// The captured input, ends the capture once one was pressed.
+    pub fn take_captured(&mut self) -> Option<InputSource> {
+        match self.capture {
+            Some(Some(source)) => {
+                self.capture = None;
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+
+    fn captured(&mut self, source: InputSource) {
+        if let Some(captured @ None) = &mut self.capture {
+            *captured = Some(source);
+        }
+    }
+
+    /// Writes every binding as TOML.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    /// Actions in the file replace their current bindings, actions missing from it keep theirs
+    /// so bindings added by a newer version still work with an old file.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).map_err(|e| Error::asset(path, format!("Failed to read the bindings: {}", e)))?;
+        self.load_toml(&text)
+    }
+
+    pub fn to_toml(&self) -> Result<String> {
+        let file = BindingsFile {
+            actions: self
+                .bindings
+                .iter()
+                .map(|(action, bindings)| (action.clone(), bindings.clone()))
+                .collect(),
+        };
+        Ok(toml::to_string_pretty(&file)?)
+    }
+
+    pub fn load_toml(&mut self, text: &str) -> Result<()> {
+        let file: BindingsFile = toml::from_str(text)?;
+        for (action, bindings) in file.actions {
+            self.states.entry(action.clone()).or_default();
+            self.bindings.insert(action, bindings);
+        }
+        Ok(())
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map(|bindings| bindings.as_slice()).unwrap_or(&[])
+    }
+
+    /// Tracks the keyboard and mouse buttons, forward every window event.
+    pub fn handle_window_event(&mut self, event: &WindowEvent) {
+        match event {
+            WindowEvent::KeyboardInput { input, .. } => {
+                if let Some(key) = input.virtual_keycode {
+                    match input.state {
+                        ElementState::Pressed => {
+                            // key repeats aren't new presses
+                            if self.keys_down.insert(key) {
+                                self.captured(InputSource::Key(key));
+                            }
+                        }
+                        ElementState::Released => {
+                            self.keys_down.remove(&key);
+                        }
+                    }
+                }
+            }
+            WindowEvent::MouseInput { state, button, .. } => match state {
+                ElementState::Pressed => {
+                    self.mouse_buttons_down.insert(*button);
+                    self.captured(InputSource::MouseButton(*button));
+                }
+                ElementState::Released => {
+                    self.mouse_buttons_down.remove(button);
+                }
+            },
+            // release events are lost while unfocused, so nothing may stay held down
+            WindowEvent::Focused(false) => {
+                self.keys_down.clear();
+                self.mouse_buttons_down.clear();
+            }
+            _ => {}
+        }
+    }
+
+    /// Computes the action values for this frame, call it once per frame after `Gamepads::poll`.
+    pub fn update(&mut self, gamepads: &Gamepads) {
+        if let Some(source) = gamepads.just_pressed.first() {
+            self.captured(*source);
+        }
+
+        for (action, state) in self.states.iter_mut() {
+            let value: f32 = self
+                .bindings
+                .get(action)
+                .map(|bindings| {
+                    bindings
+                        .iter()
+                        .map(|binding| {
+                            let raw = match binding.source {
+                                InputSource::Key(key) => self.keys_down.contains(&key) as u8 as f32,
+                                InputSource::MouseButton(button) => self.mouse_buttons_down.contains(&button) as u8 as f32,
+                                InputSource::GamepadButton(button) => gamepads.button(button),
+                                InputSource::GamepadAxis(axis) => gamepads.axis(axis),
+                            };
+                            raw * binding.scale
+                        })
+                        .sum()
+                })
+                .unwrap_or(0.0);
+
+            state.previous = state.value;
+            state.value = value.clamp(-1.0, 1.0);
+        }
+    }
+
+    pub fn value(&self, action: &str) -> f32 {
+        self.states.get(action).map(|state| state.value).unwrap_or(0.0)
+    }
+
+    pub fn pressed(&self, action: &str) -> bool {
+        self.value(action).abs() >= PRESS_THRESHOLD
+    }
+
+    pub fn just_pressed(&self, action: &str) -> bool {
+        self.states
+            .get(action)
+            .map(|state| state.value.abs() >= PRESS_THRESHOLD && state.previous.abs() < PRESS_THRESHOLD)
+            .unwrap_or(false)
+    }
+
+    pub fn just_released(&self, action: &str) -> bool {
+        self.states
+            .get(action)
+            .map(|state| state.value.abs() < PRESS_THRESHOLD && state.previous.abs() >= PRESS_THRESHOLD)
+            .unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::{DeviceId, KeyboardInput};
+
+    use super::*;
+
+    #[allow(deprecated)]
+    fn key(key: Key, state: ElementState) -> WindowEvent<'static> {
+        WindowEvent::KeyboardInput {
+            device_id: unsafe { DeviceId::dummy() },
+            input: KeyboardInput {
+                scancode: 0,
+                state,
+                virtual_keycode: Some(key),
+                modifiers: Default::default(),
+            },
+            is_synthetic: false,
+        }
+    }
+
+    #[test]
+    fn repeats_are_not_new_presses_and_focus_loss_releases() {
+        let mut input = InputState::new();
+        input.handle_window_event(&key(Key::W, ElementState::Pressed));
+        assert!(input.key_down(Key::W) && input.key_just_pressed(Key::W));
+        input.end_frame();
+        input.handle_window_event(&key(Key::W, ElementState::Pressed));
+        assert!(input.key_down(Key::W));
+        assert!(!input.key_just_pressed(Key::W));
+
+        input.handle_window_event(&WindowEvent::Focused(false));
+        assert!(!input.key_down(Key::W));
+        assert!(input.key_just_released(Key::W));
+        input.end_frame();
+        // the release after focus came back was already reported
+        input.handle_window_event(&key(Key::W, ElementState::Released));
+        assert!(!input.key_just_released(Key::W));
+    }
+
+    #[test]
+    fn opposite_keys_drive_one_axis_action() {
+        let gamepads = Gamepads::new().unwrap();
+        let mut actions = ActionMap::new();
+        actions.bind("forward", InputSource::Key(Key::W));
+        actions.bind_scaled("forward", InputSource::Key(Key::S), -1.0);
+        actions.bind("forward", InputSource::Key(Key::Up));
+
+        actions.handle_window_event(&key(Key::S, ElementState::Pressed));
+        actions.update(&gamepads);
+        assert_eq!(actions.value("forward"), -1.0);
+        assert!(actions.just_pressed("forward"));
+
+        actions.handle_window_event(&key(Key::W, ElementState::Pressed));
+        actions.update(&gamepads);
+        assert_eq!(actions.value("forward"), 0.0);
+        assert!(actions.just_released("forward"));
+
+        // two bindings of the same direction are clamped
+        actions.handle_window_event(&key(Key::S, ElementState::Released));
+        actions.handle_window_event(&key(Key::Up, ElementState::Pressed));
+        actions.update(&gamepads);
+        assert_eq!(actions.value("forward"), 1.0);
+        assert!(actions.pressed("forward"));
+        assert_eq!(actions.value("unbound"), 0.0);
+    }
+
+    #[test]
+    fn capture_takes_the_first_new_input() {
+        let mut actions = ActionMap::new();
+        actions.bind("jump", InputSource::Key(Key::Space));
+        actions.begin_capture();
+        assert_eq!(actions.take_captured(), None);
+        actions.handle_window_event(&key(Key::J, ElementState::Pressed));
+        actions.handle_window_event(&key(Key::K, ElementState::Pressed));
+        let captured = actions.take_captured().unwrap();
+        assert_eq!(captured, InputSource::Key(Key::J));
+        assert!(!actions.is_capturing());
+
+        actions.rebind("jump", 0, captured).unwrap();
+        assert_eq!(actions.actions_bound_to(InputSource::Key(Key::J)), ["jump"]);
+        assert!(actions.rebind("jump", 1, captured).is_err());
+        assert!(actions.remove_binding("missing", 0).is_err());
+    }
+
+    #[test]
+    fn loading_bindings_keeps_actions_missing_from_the_file() {
+        let mut saved = ActionMap::new();
+        saved.bind("jump", InputSource::Key(Key::J));
+        saved.bind_scaled("back", InputSource::GamepadAxis(Axis::LeftStickY), -0.5);
+        let text = saved.to_toml().unwrap();
+
+        let mut actions = ActionMap::new();
+        actions.bind("jump", InputSource::Key(Key::Space));
+        actions.bind("crouch", InputSource::Key(Key::C));
+        actions.load_toml(&text).unwrap();
+        assert_eq!(actions.bindings("jump"), saved.bindings("jump"));
+        assert_eq!(actions.bindings("back"), saved.bindings("back"));
+        assert_eq!(actions.bindings("crouch").len(), 1);
+        assert_eq!(actions.actions(), ["back", "crouch", "jump"]);
+        assert!(actions.load_toml("actions = 3").is_err());
+    }
+}
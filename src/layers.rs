@@ -0,0 +1,145 @@
+use std::ops::{BitAnd, BitOr, Not};
+
+use crate::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+
+/// Set of up to 32 render layers, objects are on some layers and cameras draw the objects on theirs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+#[serde(transparent)]
+pub struct RenderLayers(pub u32);
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl RenderLayers {
+    pub const COUNT: u32 = 32;
+    pub const NONE: RenderLayers = RenderLayers(0);
+    pub const ALL: RenderLayers = RenderLayers(u32::MAX);
+    /// what objects are on unless they say otherwise
+    pub const DEFAULT: RenderLayers = RenderLayers::layer(0);
+    /// editor helpers like the grid, selection outlines and gizmos, hidden from game cameras
+    pub const GIZMOS: RenderLayers = RenderLayers::layer(1);
+    pub const UI: RenderLayers = RenderLayers::layer(2);
+
+    /// Just layer `index`, which has to be below `COUNT`.
+    pub const fn layer(index: u32) -> RenderLayers {
+        RenderLayers(1 << index)
+    }
+
+    pub const fn with(self, other: RenderLayers) -> RenderLayers {
+        RenderLayers(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: RenderLayers) -> RenderLayers {
+        RenderLayers(self.0 & !other.0)
+    }
+
+    /// True when the sets share a layer, a camera with mask `self` draws an object on `other`.
+    pub const fn intersects(self, other: RenderLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub const fn contains(self, other: RenderLayers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Indices of the layers in the set, lowest first.
+    pub fn indices(self) -> impl Iterator<Item = u32> {
+        (0..Self::COUNT).filter(move |index| self.0 & (1 << index) != 0)
+    }
+}
+
+impl BitOr for RenderLayers {
+    type Output = RenderLayers;
+
+    fn bitor(self, other: RenderLayers) -> RenderLayers {
+        self.with(other)
+    }
+}
+
+impl BitAnd for RenderLayers {
+    type Output = RenderLayers;
+
+    fn bitand(self, other: RenderLayers) -> RenderLayers {
+        RenderLayers(self.0 & other.0)
+    }
+}
+
+impl Not for RenderLayers {
+    type Output = RenderLayers;
+
+    fn not(self) -> RenderLayers {
+        RenderLayers(!self.0)
+    }
+}
+
+/// Names of the layers an application uses, "default", "gizmos" and "ui" are predefined.
+#[derive(Clone, Debug)]
+pub struct LayerNames {
+    names: [Option<String>; RenderLayers::COUNT as usize],
+}
+
+impl Default for LayerNames {
+    fn default() -> Self {
+        let mut names: [Option<String>; RenderLayers::COUNT as usize] = Default::default();
+        names[0] = Some("default".to_owned());
+        names[1] = Some("gizmos".to_owned());
+        names[2] = Some("ui".to_owned());
+        Self { names }
+    }
+}
+
+impl LayerNames {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The layer called `name`, takes the lowest unnamed layer the first time a name is used.
+    pub fn define(&mut self, name: &str) -> Result<RenderLayers> {
+        if let Some(layer) = self.get(name) {
+            return Ok(layer);
+        }
+        let index = self.names.iter().position(Option::is_none).ok_or_else(|| {
+            Error::msg(format!(
+                "All {} render layers are named, can't add {}",
+                RenderLayers::COUNT,
+                name
+            ))
+        })?;
+        self.names[index] = Some(name.to_owned());
+        Ok(RenderLayers::layer(index as u32))
+    }
+
+    pub fn get(&self, name: &str) -> Option<RenderLayers> {
+        self.names
+            .iter()
+            .position(|existing| existing.as_deref() == Some(name))
+            .map(|index| RenderLayers::layer(index as u32))
+    }
+
+    /// The set of the named layers, fails on names that were never defined.
+    pub fn mask(&self, names: &[&str]) -> Result<RenderLayers> {
+        names.iter().try_fold(RenderLayers::NONE, |mask, name| {
+            self.get(name)
+                .map(|layer| mask | layer)
+                .ok_or_else(|| Error::msg(format!("Unknown render layer {}", name)))
+        })
+    }
+
+    pub fn name(&self, index: u32) -> Option<&str> {
+        self.names.get(index as usize)?.as_deref()
+    }
+
+    /// Names of the layers in `layers`, unnamed ones are skipped.
+    pub fn names_of(&self, layers: RenderLayers) -> Vec<&str> {
+        layers.indices().filter_map(|index| self.name(index)).collect()
+    }
+}
@@ -0,0 +1,286 @@
+use std::mem::size_of;
+
+use crate::error::Result;
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{
+    postfx::{EffectComposite, EffectDesc, EffectResolution, PostFxChain},
+    utility,
+};
+
+/// Ghosts and a halo mirrored from the bright parts of the image through the center.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LensFlare {
+    pub enabled: bool,
+    pub intensity: f32,
+    /// pixels brighter than this cause flares, the scene color is already tonemapped to 0..1
+    pub threshold: f32,
+    pub ghost_count: u32,
+    /// distance between ghosts as a share of the way to the center
+    pub ghost_spacing: f32,
+    /// share of the screen diagonal
+    pub halo_radius: f32,
+    pub halo_width: f32,
+    /// uv offset between the color channels of the ghosts, 0 for white ghosts
+    pub chromatic_distortion: f32,
+}
+
+impl Default for LensFlare {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.6,
+            threshold: 0.85,
+            ghost_count: 4,
+            ghost_spacing: 0.35,
+            halo_radius: 0.5,
+            halo_width: 0.1,
+            chromatic_distortion: 0.004,
+        }
+    }
+}
+
+/// Red and blue drift apart towards the edges like with a cheap lens.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChromaticAberration {
+    pub enabled: bool,
+    /// uv offset of red and blue in the corners
+    pub strength: f32,
+    /// 1 shifts in proportion to the distance from the center, higher values keep more of the center sharp
+    pub falloff: f32,
+}
+
+impl Default for ChromaticAberration {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            strength: 0.003,
+            falloff: 2.0,
+        }
+    }
+}
+
+/// Darkened edges.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Vignette {
+    pub enabled: bool,
+    pub intensity: f32,
+    pub color: glm::Vector3<f32>,
+    /// distance from the center where the fade starts, 1 is the middle of an edge
+    pub radius: f32,
+    pub softness: f32,
+    /// 0 follows the shape of the screen, 1 is a circle
+    pub roundness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.5,
+            color: glm::Vector3::zeros(),
+            radius: 0.6,
+            softness: 0.8,
+            roundness: 1.0,
+        }
+    }
+}
+
+/// Noise that changes every frame, stronger in the shadows like film.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FilmGrain {
+    pub enabled: bool,
+    pub intensity: f32,
+    /// pixels per grain
+    pub size: f32,
+    /// 0 grains everything evenly, 1 leaves the highlights clean
+    pub luminance_response: f32,
+}
+
+impl Default for FilmGrain {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            intensity: 0.06,
+            size: 1.5,
+            luminance_response: 0.8,
+        }
+    }
+}
+
+/// Every lens effect, all start disabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LensSettings {
+    pub flare: LensFlare,
+    pub chromatic_aberration: ChromaticAberration,
+    pub vignette: Vignette,
+    pub film_grain: FilmGrain,
+}
+
+/// Matches the `Flare` push constant block of shaders/lens_flare.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FlarePush {
+    intensity: f32,
+    threshold: f32,
+    ghost_spacing: f32,
+    ghost_count: i32,
+    halo_radius: f32,
+    halo_width: f32,
+    chromatic_distortion: f32,
+    pad: f32,
+}
+
+/// Matches the `Aberration` push constant block of shaders/chromatic_aberration.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct AberrationPush {
+    strength: f32,
+    falloff: f32,
+}
+
+/// Matches the `Vignette` push constant block of shaders/vignette.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct VignettePush {
+    color: glm::Vector4<f32>,
+    radius: f32,
+    softness: f32,
+    roundness: f32,
+    aspect: f32,
+}
+
+/// Matches the `Grain` push constant block of shaders/film_grain.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct GrainPush {
+    intensity: f32,
+    size: f32,
+    luminance_response: f32,
+    seed: f32,
+}
+
+fn effect<T>(fragment_shader: &str, resolution: EffectResolution, composite: EffectComposite) -> EffectDesc {
+    EffectDesc {
+        fragment_shader: fragment_shader.to_owned(),
+        resolution,
+        composite,
+        push_constant_size: size_of::<T>() as u32,
+    }
+}
+
+/// The lens effects as effects of a `PostFxChain`, in the order a camera produces them:
+/// flare, chromatic aberration, vignette and grain. The flare renders at half resolution and is added on top.
+/// Effects added to the chain afterwards run after the grain, add them before for tonemapping or color grading.
+pub struct LensEffects {
+    flare: usize,
+    chromatic_aberration: usize,
+    vignette: usize,
+    film_grain: usize,
+    frame: u32,
+}
+
+impl LensEffects {
+    /// # Safety
+    /// `chain` has to be a chain of `device`, the effects are destroyed with it.
+    pub unsafe fn new(device: &ash::Device, chain: &mut PostFxChain) -> Result<LensEffects> {
+        let flare = chain.add_effect(
+            device,
+            effect::<FlarePush>(
+                "shaders/spv/lens_flare_frag.spv",
+                EffectResolution::Half,
+                EffectComposite::Add,
+            ),
+        )?;
+        let chromatic_aberration = chain.add_effect(
+            device,
+            effect::<AberrationPush>(
+                "shaders/spv/chromatic_aberration_frag.spv",
+                EffectResolution::Full,
+                EffectComposite::Replace,
+            ),
+        )?;
+        let vignette = chain.add_effect(
+            device,
+            effect::<VignettePush>(
+                "shaders/spv/vignette_frag.spv",
+                EffectResolution::Full,
+                EffectComposite::Replace,
+            ),
+        )?;
+        let film_grain = chain.add_effect(
+            device,
+            effect::<GrainPush>(
+                "shaders/spv/film_grain_frag.spv",
+                EffectResolution::Full,
+                EffectComposite::Replace,
+            ),
+        )?;
+        let effects = LensEffects {
+            flare,
+            chromatic_aberration,
+            vignette,
+            film_grain,
+            frame: 0,
+        };
+        for index in effects.indices() {
+            chain.set_enabled(index, false);
+        }
+        Ok(effects)
+    }
+
+    /// Indices of the effects in the chain.
+    pub fn indices(&self) -> [usize; 4] {
+        [self.flare, self.chromatic_aberration, self.vignette, self.film_grain]
+    }
+
+    /// Applies `settings` to the chain, call it every frame before `PostFxChain::record` so the grain moves.
+    pub fn update(&mut self, chain: &mut PostFxChain, settings: &LensSettings, extent: vk::Extent2D) {
+        self.frame = self.frame.wrapping_add(1);
+
+        let flare = &settings.flare;
+        chain.set_enabled(self.flare, flare.enabled && flare.intensity > 0.0);
+        let push = FlarePush {
+            intensity: flare.intensity,
+            threshold: flare.threshold,
+            ghost_spacing: flare.ghost_spacing,
+            ghost_count: flare.ghost_count as i32,
+            halo_radius: flare.halo_radius,
+            halo_width: flare.halo_width,
+            chromatic_distortion: flare.chromatic_distortion,
+            pad: 0.0,
+        };
+        chain.set_push_constants(self.flare, utility::as_bytes(&push));
+
+        let aberration = &settings.chromatic_aberration;
+        chain.set_enabled(self.chromatic_aberration, aberration.enabled && aberration.strength != 0.0);
+        let push = AberrationPush {
+            strength: aberration.strength,
+            falloff: aberration.falloff.max(0.0),
+        };
+        chain.set_push_constants(self.chromatic_aberration, utility::as_bytes(&push));
+
+        let vignette = &settings.vignette;
+        chain.set_enabled(self.vignette, vignette.enabled && vignette.intensity > 0.0);
+        let push = VignettePush {
+            color: vignette.color.push(vignette.intensity.clamp(0.0, 1.0)),
+            radius: vignette.radius,
+            softness: vignette.softness,
+            roundness: vignette.roundness.clamp(0.0, 1.0),
+            aspect: extent.width as f32 / extent.height.max(1) as f32,
+        };
+        chain.set_push_constants(self.vignette, utility::as_bytes(&push));
+
+        let grain = &settings.film_grain;
+        chain.set_enabled(self.film_grain, grain.enabled && grain.intensity > 0.0);
+        let push = GrainPush {
+            intensity: grain.intensity,
+            size: grain.size,
+            luminance_response: grain.luminance_response.clamp(0.0, 1.0),
+            // small integers keep the hash in the shader precise
+            seed: (self.frame % 1024) as f32,
+        };
+        chain.set_push_constants(self.film_grain, utility::as_bytes(&push));
+    }
+}
@@ -1,16 +1,90 @@
-#![feature(offset_of)]
-use anyhow::Result;
+#![feature(portable_simd)]
 use ash::{
     prelude::VkResult,
     vk::{self, QueueFlags},
 };
+pub use error::{Error, Result};
 
+pub mod animation;
+pub mod assets;
+pub mod barrier;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod blit;
 pub mod buffer;
+pub mod cache;
+pub mod camera;
+pub mod capabilities;
+pub mod capture;
+pub mod checkerboard;
+pub mod config;
 pub mod constant;
+pub mod context;
+pub mod crowd;
+pub mod debug;
+pub mod deletion;
 pub mod device;
+pub mod device_group;
+pub mod device_lost;
+pub mod draw_cache;
+/// Extraction of renderable entities from a `hecs::World` or a bevy_ecs `World`.
+#[cfg(any(feature = "hecs", feature = "bevy_ecs"))]
+pub mod ecs;
+pub mod error;
+pub mod extensions;
+pub mod features;
+pub mod format;
+pub mod frame;
+pub mod frame_hash;
+pub mod gltf_import;
+pub mod grid;
+pub mod headless;
+pub mod hover;
+pub mod image_view;
+/// Keyboard, mouse and gamepad state fed by winit events.
+#[cfg(feature = "winit")]
+pub mod input;
+pub mod layers;
+pub mod lens;
+pub mod loader;
+pub mod material;
+pub mod material_cost;
+pub mod memory;
+pub mod mesh;
+pub mod occlusion;
+pub mod outline;
+pub mod owned;
+pub mod parallel;
+pub mod pbr;
+pub mod permutation;
 pub mod pipeline;
 pub mod platform;
+pub mod postfx;
+pub mod prefab;
+pub mod profiler;
+pub mod query;
+pub mod reflect;
+pub mod scene;
+pub mod secondary;
+pub mod selection;
+pub mod socket;
+pub mod sync;
+pub mod sync_pool;
+pub mod temporal;
+pub mod texture;
+pub mod time;
+pub mod time_of_day;
+pub mod timeline;
+pub mod ui;
+pub mod undo;
 pub mod utility;
+pub mod watchdog;
+pub mod weather;
+
+/// Used by `cpu_scope!`.
+#[cfg(feature = "tracy")]
+#[doc(hidden)]
+pub use tracy_client;
 
 pub struct QueueFamilyIndices {
     pub graphics_family: Option<u32>,
@@ -22,11 +96,23 @@ impl QueueFamilyIndices {
     pub fn is_completed(&self) -> bool {
         self.graphics_family.is_some() && self.present_family.is_some() && self.transfer_family.is_some()
     }
+
+    /// Whether uploads run on a queue of their own, `sync::QueueTransfer` hands the resources to graphics then.
+    pub fn has_dedicated_transfer(&self) -> bool {
+        self.transfer_family != self.graphics_family
+    }
+
+    /// Whether presenting needs its own queue, swapchain images are then shared or transferred between the two.
+    pub fn presents_separately(&self) -> bool {
+        self.graphics_family != self.present_family
+    }
     // GRAPHICS | COMPUTE | TRANSFER | SPARSE_BINDING
     // TRANSFER | SPARSE_BINDING
     // COMPUTE | TRANSFER | SPARSE_BINDING
     // TRANSFER | SPARSE_BINDING | VIDEO_DECODE_KHR
 
+    /// # Safety
+    /// `surface` has to be a surface of `instance`, `physical_device` enumerated from it.
     pub unsafe fn find_queue_family(
         physical_device: vk::PhysicalDevice,
         instance: &ash::Instance,
@@ -45,28 +131,57 @@ impl QueueFamilyIndices {
 
         instance.get_physical_device_queue_family_properties2(physical_device, &mut queue_families);
 
-        for (index, queue) in queue_families.iter().enumerate() {
-            if queue.queue_family_properties.queue_flags & vk::QueueFlags::GRAPHICS != vk::QueueFlags::empty() {
-                queue_family_ret.graphics_family = Some(index as u32);
-            }
-            let present_support =
-                surface_loader.get_physical_device_surface_support(physical_device, index as u32, surface.to_owned())?;
+        let mut present_support = vec![];
+        for index in 0..queue_families.len() {
+            present_support.push(surface_loader.get_physical_device_surface_support(
+                physical_device,
+                index as u32,
+                surface.to_owned(),
+            )?);
+        }
+        let graphics = |index: &usize| {
+            queue_families[*index].queue_family_properties.queue_flags & vk::QueueFlags::GRAPHICS != vk::QueueFlags::empty()
+        };
+        let presents =
+            |index: &usize| queue_families[*index].queue_family_properties.queue_count > 0 && present_support[*index];
 
-            if queue.queue_family_properties.queue_count > 0 && present_support {
-                queue_family_ret.present_family = Some(index as u32);
-            }
+        // one family for both saves the swapchain images from being shared between queues
+        let families = 0..queue_families.len();
+        let graphics_family = families
+            .clone()
+            .find(|index| graphics(index) && presents(index))
+            .or_else(|| families.clone().find(graphics));
+        queue_family_ret.graphics_family = graphics_family.map(|index| index as u32);
+        queue_family_ret.present_family = match graphics_family {
+            Some(index) if presents(&index) => Some(index as u32),
+            _ => families.clone().find(presents).map(|index| index as u32),
+        };
+        // a copy engine of its own first, then an async compute family, uploads share the graphics queue otherwise
+        let transfer_without = |excluded: QueueFlags| {
+            families.clone().find(|index| {
+                let flags = queue_families[*index].queue_family_properties.queue_flags;
+                flags & (QueueFlags::TRANSFER | excluded) == QueueFlags::TRANSFER
+            })
+        };
+        queue_family_ret.transfer_family = transfer_without(QueueFlags::GRAPHICS | QueueFlags::COMPUTE)
+            .or_else(|| transfer_without(QueueFlags::GRAPHICS))
+            .or(graphics_family)
+            .map(|index| index as u32);
 
-            if queue.queue_family_properties.queue_flags & (QueueFlags::TRANSFER | QueueFlags::GRAPHICS)
-                == QueueFlags::TRANSFER
-            {
-                queue_family_ret.transfer_family = Some(index as u32);
+        if queue_family_ret.is_completed() {
+            if queue_family_ret.presents_separately() {
+                log::info!(
+                    "Graphics family {} can't present, presenting on family {}",
+                    queue_family_ret.graphics_family.unwrap(),
+                    queue_family_ret.present_family.unwrap()
+                );
             }
-
-            if queue_family_ret.is_completed() {
-                return Ok(queue_family_ret);
+            if !queue_family_ret.has_dedicated_transfer() {
+                log::info!("No transfer only queue family, uploads use the graphics queue");
             }
+            return Ok(queue_family_ret);
         }
-        return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+        Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED)
     }
 }
 
@@ -74,6 +189,90 @@ impl QueueFamilyIndices {
 // Surface formats (pixel format, color space)
 // Available presentation modes
 
+/// Number of swapchain images to ask for, more images smooth out frame time spikes but add latency.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapchainImageCount {
+    /// one more than the surface minimum
+    #[default]
+    Default,
+    Double,
+    Triple,
+    Exact(u32),
+}
+
+/// How swapchain images move between the graphics and the present queue when their families differ.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SwapchainSharing {
+    /// both families use the images without transfers
+    #[default]
+    Concurrent,
+    /// the images are EXCLUSIVE, every frame releases them on the graphics queue and acquires them on the
+    /// present queue, which can be faster on hardware that compresses render targets
+    Exclusive,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SwapchainConfig {
+    pub image_count: SwapchainImageCount,
+    /// only matters with `QueueFamilyIndices::presents_separately`
+    pub sharing: SwapchainSharing,
+    /// FIFO is vsync, MAILBOX and IMMEDIATE don't wait for the display. None prefers MAILBOX.
+    /// Unsupported modes fall back to the other unsynchronized mode and then FIFO, which is always available.
+    pub present_mode: Option<vk::PresentModeKHR>,
+}
+
+/// What the swapchain ended up with after clamping the request to the surface capabilities.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SwapchainLatency {
+    /// image count asked for before clamping
+    pub requested_image_count: u32,
+    /// images the driver created, can be more than requested
+    pub image_count: u32,
+    pub surface_min_image_count: u32,
+    /// 0 means no limit
+    pub surface_max_image_count: u32,
+    pub present_mode: vk::PresentModeKHR,
+    pub frames_in_flight: u32,
+}
+
+impl SwapchainLatency {
+    /// Images the application can hold acquired at once without `acquire_next_image` blocking.
+    pub fn acquirable_images(&self) -> u32 {
+        self.image_count.saturating_sub(self.surface_min_image_count) + 1
+    }
+
+    /// Worst case number of frames between recording a frame and it reaching the screen.
+    /// FIFO queues every finished image, MAILBOX and IMMEDIATE replace or show them right away.
+    pub fn max_latency_frames(&self) -> u32 {
+        match self.present_mode {
+            vk::PresentModeKHR::FIFO | vk::PresentModeKHR::FIFO_RELAXED => {
+                self.frames_in_flight.min(self.acquirable_images()) + self.image_count.saturating_sub(1)
+            }
+            _ => self.frames_in_flight.min(self.acquirable_images()),
+        }
+    }
+}
+
+/// The surface `SwapChainSupportDetails::create_swapchain_with` creates a swapchain for.
+#[derive(Clone, Copy)]
+pub struct SurfaceTarget<'a> {
+    pub loader: &'a ash::extensions::khr::Surface,
+    pub surface: vk::SurfaceKHR,
+    /// used when the surface leaves the extent to the swapchain
+    pub window_extent: vk::Extent2D,
+}
+
+/// What `SwapChainSupportDetails::create_swapchain_with` created, `image_views` view `images` in order.
+pub struct CreatedSwapchain {
+    pub loader: ash::extensions::khr::Swapchain,
+    pub swapchain: vk::SwapchainKHR,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+    pub images: Vec<vk::Image>,
+    pub image_views: Vec<vk::ImageView>,
+    pub latency: SwapchainLatency,
+}
+
 pub struct SwapChainSupportDetails {
     capabilities: vk::SurfaceCapabilitiesKHR,
     formats: Vec<vk::SurfaceFormatKHR>,
@@ -108,7 +307,24 @@ impl SwapChainSupportDetails {
                 break;
             }
         }
-        return available_formats[index];
+        available_formats[index]
+    }
+
+    fn choose_requested_present_mode(
+        present_modes: &[vk::PresentModeKHR],
+        requested: vk::PresentModeKHR,
+    ) -> vk::PresentModeKHR {
+        let fallbacks: &[vk::PresentModeKHR] = match requested {
+            vk::PresentModeKHR::IMMEDIATE => &[vk::PresentModeKHR::IMMEDIATE, vk::PresentModeKHR::MAILBOX],
+            vk::PresentModeKHR::MAILBOX => &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE],
+            vk::PresentModeKHR::FIFO_RELAXED => &[vk::PresentModeKHR::FIFO_RELAXED],
+            _ => &[],
+        };
+        fallbacks
+            .iter()
+            .copied()
+            .find(|mode| present_modes.contains(mode))
+            .unwrap_or(vk::PresentModeKHR::FIFO)
     }
 
     unsafe fn choose_present_mode(present_modes: Vec<vk::PresentModeKHR>) -> vk::PresentModeKHR {
@@ -119,18 +335,23 @@ impl SwapChainSupportDetails {
                 break;
             }
         }
-        return present_ret;
+        present_ret
     }
 
-    unsafe fn choose_extent(capabilities: vk::SurfaceCapabilitiesKHR) -> vk::Extent2D {
+    unsafe fn choose_extent(capabilities: vk::SurfaceCapabilitiesKHR, window_extent: vk::Extent2D) -> vk::Extent2D {
+        // the surface dictates the extent unless it reports u32::MAX, then it follows the window, which is how
+        // Wayland surfaces leave the size of resized and fullscreen windows to the app
+        if capabilities.current_extent.width != u32::MAX {
+            return capabilities.current_extent;
+        }
         vk::Extent2D {
             width: num::clamp(
-                constant::Window_Info::WIDTH,
+                window_extent.width,
                 capabilities.min_image_extent.width,
                 capabilities.max_image_extent.width,
             ),
             height: num::clamp(
-                constant::Window_Info::HEIGHT,
+                window_extent.height,
                 capabilities.min_image_extent.height,
                 capabilities.max_image_extent.height,
             ),
@@ -144,22 +365,26 @@ impl SwapChainSupportDetails {
     ) -> Result<Vec<vk::ImageView>, vk::Result> {
         let mut image_views = vec![];
         for image in swapchain_images {
-            let mut image_view_info = vk::ImageViewCreateInfo::default();
-            image_view_info.s_type = vk::StructureType::IMAGE_VIEW_CREATE_INFO;
-            image_view_info.image = image.clone();
-            image_view_info.view_type = vk::ImageViewType::TYPE_2D;
-            image_view_info.format = swapchain_format;
-
-            image_view_info.components.r = vk::ComponentSwizzle::IDENTITY;
-            image_view_info.components.g = vk::ComponentSwizzle::IDENTITY;
-            image_view_info.components.b = vk::ComponentSwizzle::IDENTITY;
-            image_view_info.components.a = vk::ComponentSwizzle::IDENTITY;
-
-            image_view_info.subresource_range.aspect_mask = vk::ImageAspectFlags::COLOR;
-            image_view_info.subresource_range.base_mip_level = 0;
-            image_view_info.subresource_range.level_count = 1;
-            image_view_info.subresource_range.base_array_layer = 0;
-            image_view_info.subresource_range.layer_count = 1;
+            let image_view_info = vk::ImageViewCreateInfo {
+                s_type: vk::StructureType::IMAGE_VIEW_CREATE_INFO,
+                image: *image,
+                view_type: vk::ImageViewType::TYPE_2D,
+                format: swapchain_format,
+                components: vk::ComponentMapping {
+                    r: vk::ComponentSwizzle::IDENTITY,
+                    g: vk::ComponentSwizzle::IDENTITY,
+                    b: vk::ComponentSwizzle::IDENTITY,
+                    a: vk::ComponentSwizzle::IDENTITY,
+                },
+                subresource_range: vk::ImageSubresourceRange {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    base_mip_level: 0,
+                    level_count: 1,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                ..Default::default()
+            };
 
             let image_view = device.create_image_view(&image_view_info, None)?;
             image_views.push(image_view);
@@ -167,35 +392,73 @@ impl SwapChainSupportDetails {
         Ok(image_views)
     }
 
+    fn choose_image_count(capabilities: &vk::SurfaceCapabilitiesKHR, requested: SwapchainImageCount) -> (u32, u32) {
+        let requested = match requested {
+            SwapchainImageCount::Default => capabilities.min_image_count + 1,
+            SwapchainImageCount::Double => 2,
+            SwapchainImageCount::Triple => 3,
+            SwapchainImageCount::Exact(count) => count,
+        };
+        let mut image_count = requested.max(capabilities.min_image_count);
+        if capabilities.max_image_count > 0 {
+            image_count = image_count.min(capabilities.max_image_count);
+        }
+        (requested, image_count)
+    }
+
+    /// # Safety
+    /// `device` has to be created from `physical_device`, which has to support `surface`.
     pub unsafe fn create_swapchain(
         instance: &ash::Instance,
         device: &ash::Device,
         surface_loader: &ash::extensions::khr::Surface,
         surface: vk::SurfaceKHR,
+        window_extent: vk::Extent2D,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<CreatedSwapchain, vk::Result> {
+        let target = SurfaceTarget {
+            loader: surface_loader,
+            surface,
+            window_extent,
+        };
+        Self::create_swapchain_with(
+            instance,
+            device,
+            physical_device,
+            target,
+            &SwapchainConfig::default(),
+            vk::SwapchainKHR::null(),
+        )
+    }
+
+    /// `create_swapchain` with a requested image count, also returns what the request turned into.
+    /// `old_swapchain` is retired even when creation fails, destroy it afterwards. Null for a new surface.
+    ///
+    /// # Safety
+    /// As for `create_swapchain`, `old_swapchain` has to be null or a swapchain of `target.surface`.
+    pub unsafe fn create_swapchain_with(
+        instance: &ash::Instance,
+        device: &ash::Device,
         physical_device: vk::PhysicalDevice,
-    ) -> Result<
-        (
-            ash::extensions::khr::Swapchain,
-            vk::SwapchainKHR,
-            vk::Extent2D,
-            vk::Format,
-            Vec<vk::Image>,
-            Vec<vk::ImageView>,
-        ),
-        vk::Result,
-    > {
+        target: SurfaceTarget,
+        config: &SwapchainConfig,
+        old_swapchain: vk::SwapchainKHR,
+    ) -> Result<CreatedSwapchain, vk::Result> {
+        let SurfaceTarget {
+            loader: surface_loader,
+            surface,
+            window_extent,
+        } = target;
         let swap_chain_support = SwapChainSupportDetails::query_swapchain_support(surface_loader, surface, physical_device)?;
+        let capabilities = swap_chain_support.capabilities;
 
-        let extent = SwapChainSupportDetails::choose_extent(swap_chain_support.capabilities);
+        let extent = SwapChainSupportDetails::choose_extent(swap_chain_support.capabilities, window_extent);
         let surface_format = SwapChainSupportDetails::choose_format(swap_chain_support.formats);
-        let present_mode = SwapChainSupportDetails::choose_present_mode(swap_chain_support.present_modes);
-        let mut image_count = swap_chain_support.capabilities.min_image_count + 1;
-
-        if swap_chain_support.capabilities.max_image_count > 0
-            && image_count > swap_chain_support.capabilities.max_image_count
-        {
-            image_count = swap_chain_support.capabilities.max_image_count;
-        }
+        let present_mode = match config.present_mode {
+            Some(requested) => Self::choose_requested_present_mode(&swap_chain_support.present_modes, requested),
+            None => SwapChainSupportDetails::choose_present_mode(swap_chain_support.present_modes),
+        };
+        let (requested_image_count, image_count) = Self::choose_image_count(&capabilities, config.image_count);
         let mut swapchain_info = vk::SwapchainCreateInfoKHR::default();
         let family_queue = QueueFamilyIndices::find_queue_family(physical_device, instance, surface_loader, &surface)?;
 
@@ -211,20 +474,17 @@ impl SwapChainSupportDetails {
         swapchain_info.composite_alpha = vk::CompositeAlphaFlagsKHR::OPAQUE;
         swapchain_info.present_mode = present_mode;
         swapchain_info.clipped = vk::TRUE;
-        swapchain_info.old_swapchain = vk::SwapchainKHR::null();
+        swapchain_info.old_swapchain = old_swapchain;
         // VK_SHARING_MODE_EXCLUSIVE: An image is owned by one queue family at a time and ownership must be explicitly transferred before using it in another queue family. This option offers the best performance.
         // VK_SHARING_MODE_CONCURRENT: Images can be used across multiple queue families without explicit ownership transfers.
-        swapchain_info.image_sharing_mode = vk::SharingMode::CONCURRENT;
-
-        let mut queues_indices = vec![];
-
-        queues_indices.push(family_queue.graphics_family.unwrap());
-        if family_queue.graphics_family.unwrap() != family_queue.present_family.unwrap() {
-            queues_indices.push(family_queue.present_family.unwrap());
-        }
-
-        queues_indices.push(family_queue.transfer_family.unwrap());
-
+        // only the graphics and the present queue touch the images
+        let (sharing_mode, queues_indices) = match config.sharing {
+            SwapchainSharing::Concurrent => {
+                sync::sharing_mode(&[family_queue.graphics_family.unwrap(), family_queue.present_family.unwrap()])
+            }
+            SwapchainSharing::Exclusive => (vk::SharingMode::EXCLUSIVE, vec![]),
+        };
+        swapchain_info.image_sharing_mode = sharing_mode;
         swapchain_info.queue_family_index_count = queues_indices.len() as u32;
         swapchain_info.p_queue_family_indices = queues_indices.as_ptr();
 
@@ -233,13 +493,22 @@ impl SwapChainSupportDetails {
         let swapchain_images = swapchain_loader.get_swapchain_images(swapchain)?;
         let swapchain_image_views =
             SwapChainSupportDetails::create_image_views(&swapchain_images, surface_format.format, device)?;
-        Ok((
-            swapchain_loader,
+        let latency = SwapchainLatency {
+            requested_image_count,
+            image_count: swapchain_images.len() as u32,
+            surface_min_image_count: capabilities.min_image_count,
+            surface_max_image_count: capabilities.max_image_count,
+            present_mode,
+            frames_in_flight: buffer::MAX_FRAMES_IN_FLIGHT as u32,
+        };
+        Ok(CreatedSwapchain {
+            loader: swapchain_loader,
             swapchain,
             extent,
-            surface_format.format,
-            swapchain_images,
-            swapchain_image_views,
-        ))
+            format: surface_format.format,
+            images: swapchain_images,
+            image_views: swapchain_image_views,
+            latency,
+        })
     }
 }
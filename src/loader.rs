@@ -0,0 +1,559 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::error::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::{
+    assets::{AssetManager, Handle},
+    buffer::{self, OneTimeCommands},
+    format,
+    mesh::{GpuMesh, Mesh},
+    sync::{self, ImageTransfer, QueueTransfer},
+    texture::{self, Texture},
+};
+
+/// CPU side result of a worker.
+enum Decoded {
+    Texture {
+        pixels: Vec<u8>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    },
+    Mesh(Mesh),
+}
+
+enum Target {
+    Texture(Handle<Texture>),
+    Mesh(Handle<GpuMesh>),
+}
+
+impl Target {
+    fn fail(self, assets: &mut AssetManager, e: Error) {
+        match self {
+            Target::Texture(handle) => assets.textures.finish_load(&handle, Err(e)),
+            Target::Mesh(handle) => assets.meshes.finish_load(&handle, Err(e)),
+        }
+    }
+}
+
+enum Uploaded {
+    Texture(Texture),
+    Mesh(GpuMesh),
+}
+
+type Job = (u64, Box<dyn FnOnce() -> Result<Decoded> + Send>);
+
+/// A transfer submitted to the GPU, the asset becomes ready once the fence signals and it was acquired.
+struct PendingUpload {
+    target: Target,
+    asset: Uploaded,
+    command_buffer: vk::CommandBuffer,
+    fence: vk::Fence,
+    staging: Vec<(vk::Buffer, vk::DeviceMemory)>,
+    queues: QueueTransfer,
+    /// signaled by the transfer for the graphics submission acquiring the asset, None within one family
+    semaphore: Option<vk::Semaphore>,
+    acquired: bool,
+}
+
+impl Uploaded {
+    unsafe fn cmd_release(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, queues: QueueTransfer) {
+        let src = (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE);
+        match self {
+            Uploaded::Texture(texture) => {
+                let transfer = Self::image_transfer(queues);
+                sync::cmd_release_image(device, command_buffer, texture.image, &transfer, src);
+            }
+            Uploaded::Mesh(mesh) => {
+                sync::cmd_release_buffer(device, command_buffer, mesh.vertex_buffer, queues, src);
+                sync::cmd_release_buffer(device, command_buffer, mesh.index_buffer, queues, src);
+            }
+        }
+    }
+
+    /// Returns the first stage using the asset.
+    unsafe fn cmd_acquire(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        queues: QueueTransfer,
+    ) -> vk::PipelineStageFlags {
+        match self {
+            Uploaded::Texture(texture) => {
+                let dst = (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ);
+                sync::cmd_acquire_image(device, command_buffer, texture.image, &Self::image_transfer(queues), dst);
+                dst.0
+            }
+            Uploaded::Mesh(mesh) => {
+                let stage = vk::PipelineStageFlags::VERTEX_INPUT;
+                let vertex_dst = (stage, vk::AccessFlags::VERTEX_ATTRIBUTE_READ);
+                sync::cmd_acquire_buffer(device, command_buffer, mesh.vertex_buffer, queues, vertex_dst);
+                let index_dst = (stage, vk::AccessFlags::INDEX_READ);
+                sync::cmd_acquire_buffer(device, command_buffer, mesh.index_buffer, queues, index_dst);
+                stage
+            }
+        }
+    }
+
+    unsafe fn destroy(self, device: &ash::Device) {
+        match self {
+            Uploaded::Texture(texture) => texture.destroy(device),
+            Uploaded::Mesh(mesh) => mesh.destroy(device),
+        }
+    }
+
+    /// The upload already left the image in SHADER_READ_ONLY_OPTIMAL.
+    fn image_transfer(queues: QueueTransfer) -> ImageTransfer {
+        ImageTransfer::color(
+            queues,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        )
+    }
+}
+
+/// Loads files on worker threads and uploads them on the transfer queue without blocking the render loop.
+/// The handles are `LoadState::Loading` until `update` finished their upload.
+/// With a transfer family of its own the graphics queue takes the assets over through `cmd_acquire`.
+pub struct AsyncLoader {
+    jobs: Option<Sender<Job>>,
+    results: Receiver<(u64, Result<Decoded>)>,
+    workers: Vec<JoinHandle<()>>,
+    waiting: HashMap<u64, Target>,
+    uploads: Vec<PendingUpload>,
+    /// semaphores of acquired uploads with the frame whose submission waits on them
+    acquiring: Vec<(usize, vk::Semaphore)>,
+    next_job: u64,
+}
+
+impl AsyncLoader {
+    pub fn new(worker_count: usize) -> Self {
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..worker_count.max(1))
+            .map(|index| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                thread::Builder::new()
+                    .name(format!("asset loader {}", index))
+                    .spawn(move || loop {
+                        // the lock is released before decoding, so the other workers can take jobs
+                        let job = jobs.lock().unwrap().recv();
+                        match job {
+                            Ok((id, decode)) => {
+                                if results.send((id, decode())).is_err() {
+                                    break;
+                                }
+                            }
+                            // the loader was destroyed
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Failed to spawn asset loader thread")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_sender),
+            results,
+            workers,
+            waiting: HashMap::new(),
+            uploads: vec![],
+            acquiring: vec![],
+            next_job: 0,
+        }
+    }
+
+    fn submit(&mut self, target: Target, decode: Box<dyn FnOnce() -> Result<Decoded> + Send>) {
+        let id = self.next_job;
+        self.next_job += 1;
+        self.waiting.insert(id, target);
+        if let Some(jobs) = &self.jobs {
+            let _ = jobs.send((id, decode));
+        }
+    }
+
+    /// Files already loaded or loading return the same handle, evicted files are streamed in again.
+    pub fn load_texture<P: AsRef<Path>>(
+        &mut self,
+        assets: &mut AssetManager,
+        path: P,
+        format: vk::Format,
+    ) -> Handle<Texture> {
+        let (handle, is_new) = assets.textures.reserve(&path);
+        if is_new {
+            let path: PathBuf = path.as_ref().to_owned();
+            self.submit(
+                Target::Texture(handle.clone()),
+                Box::new(move || {
+                    let (pixels, extent) = texture::load_image_rgba8(path)?;
+                    Ok(Decoded::Texture { pixels, extent, format })
+                }),
+            );
+        }
+        handle
+    }
+
+    pub fn load_obj<P: AsRef<Path>>(&mut self, assets: &mut AssetManager, path: P) -> Handle<GpuMesh> {
+        let (handle, is_new) = assets.meshes.reserve(&path);
+        if is_new {
+            let path: PathBuf = path.as_ref().to_owned();
+            self.submit(
+                Target::Mesh(handle.clone()),
+                Box::new(move || Ok(Decoded::Mesh(Mesh::load_obj(path)?))),
+            );
+        }
+        handle
+    }
+
+    /// Jobs that are decoding or uploading.
+    pub fn pending(&self) -> usize {
+        self.waiting.len() + self.uploads.len()
+    }
+
+    /// Starts uploads of decoded files and marks finished uploads as ready, call it once per frame.
+    /// `transfer` may belong to a transfer only family, `queues` goes from it to the graphics family. When they differ the uploads only finish after `cmd_acquire` took them over.
+    ///
+    /// # Safety
+    /// `queues` has to go from the family of `transfer` to the graphics family.
+    pub unsafe fn update(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        transfer: OneTimeCommands,
+        queues: QueueTransfer,
+        assets: &mut AssetManager,
+    ) -> VkResult<()> {
+        let mut index = 0;
+        while index < self.uploads.len() {
+            if self.uploads[index].acquired && device.get_fence_status(self.uploads[index].fence)? {
+                let upload = self.uploads.swap_remove(index);
+                device.destroy_fence(upload.fence, None);
+                device.free_command_buffers(transfer.command_pool, &[upload.command_buffer]);
+                for (buffer, memory) in upload.staging {
+                    device.destroy_buffer(buffer, None);
+                    device.free_memory(memory, None);
+                }
+                match (upload.target, upload.asset) {
+                    (Target::Texture(handle), Uploaded::Texture(texture)) => {
+                        assets.textures.finish_load(&handle, Ok(texture))
+                    }
+                    (Target::Mesh(handle), Uploaded::Mesh(mesh)) => assets.meshes.finish_load(&handle, Ok(mesh)),
+                    _ => unreachable!(),
+                }
+            } else {
+                index += 1;
+            }
+        }
+
+        while let Ok((id, result)) = self.results.try_recv() {
+            let Some(target) = self.waiting.remove(&id) else {
+                continue;
+            };
+            match result {
+                Ok(decoded) => match start_upload(device, instance, physical_device, transfer, queues, target, decoded) {
+                    Ok(upload) => self.uploads.push(upload),
+                    // a failed upload only fails its own asset, the other results still get drained
+                    Err((target, e)) => target.fail(assets, e),
+                },
+                Err(e) => target.fail(assets, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the acquire half of the uploads the transfer queue released into `command_buffer`, which has to
+    /// be submitted on the graphics queue before the assets are drawn with. Returns the semaphores and stages
+    /// that submission has to wait on. `frame` is the frame in flight whose fence was waited on before recording,
+    /// the semaphores its previous submission waited on are destroyed.
+    ///
+    /// # Safety
+    /// The fence of `frame` has to be waited on, `command_buffer` has to be submitted on the graphics queue.
+    pub unsafe fn cmd_acquire(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame: usize,
+    ) -> Vec<(vk::Semaphore, vk::PipelineStageFlags)> {
+        self.acquiring.retain(|(acquired_in, semaphore)| {
+            let done = *acquired_in == frame;
+            if done {
+                device.destroy_semaphore(*semaphore, None);
+            }
+            !done
+        });
+
+        let mut waits = vec![];
+        for upload in self.uploads.iter_mut().filter(|upload| !upload.acquired) {
+            let Some(semaphore) = upload.semaphore.take() else {
+                continue;
+            };
+            let stage = upload.asset.cmd_acquire(device, command_buffer, upload.queues);
+            upload.acquired = true;
+            waits.push((semaphore, stage));
+            self.acquiring.push((frame, semaphore));
+        }
+        waits
+    }
+
+    /// Waits for the running uploads and stops the workers, unfinished assets stay `LoadState::Loading`.
+    /// The device has to be idle.
+    ///
+    /// # Safety
+    /// The device has to be idle, `transfer_pool` has to be the pool of the uploads.
+    pub unsafe fn destroy(&mut self, device: &ash::Device, transfer_pool: vk::CommandPool) {
+        for (_, semaphore) in self.acquiring.drain(..) {
+            device.destroy_semaphore(semaphore, None);
+        }
+        for upload in self.uploads.drain(..) {
+            let _ = device.wait_for_fences(&[upload.fence], true, u64::MAX);
+            device.destroy_fence(upload.fence, None);
+            if let Some(semaphore) = upload.semaphore {
+                device.destroy_semaphore(semaphore, None);
+            }
+            device.free_command_buffers(transfer_pool, &[upload.command_buffer]);
+            for (buffer, memory) in upload.staging {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+            }
+            upload.asset.destroy(device);
+        }
+
+        // closing the channel ends the worker loops
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.waiting.clear();
+    }
+}
+
+/// Gives the target back with the error when the upload couldn't start, everything it created is freed again.
+unsafe fn start_upload(
+    device: &ash::Device,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    transfer: OneTimeCommands,
+    queues: QueueTransfer,
+    target: Target,
+    decoded: Decoded,
+) -> Result<PendingUpload, (Target, Error)> {
+    let command_buffer = match buffer::begin_single_commands(device, transfer.command_pool) {
+        Ok(command_buffer) => command_buffer,
+        Err(e) => return Err((target, e.into())),
+    };
+    let mut staging = vec![];
+
+    let submitted =
+        record_upload(device, instance, physical_device, command_buffer, decoded, &mut staging).and_then(|asset| {
+            match submit_upload(device, command_buffer, transfer.queue, queues, &asset) {
+                Ok(submission) => Ok((asset, submission)),
+                Err(e) => {
+                    asset.destroy(device);
+                    Err(e.into())
+                }
+            }
+        });
+    match submitted {
+        Ok((asset, (fence, semaphore))) => Ok(PendingUpload {
+            target,
+            asset,
+            command_buffer,
+            fence,
+            staging,
+            queues,
+            semaphore,
+            acquired: semaphore.is_none(),
+        }),
+        Err(e) => {
+            device.free_command_buffers(transfer.command_pool, &[command_buffer]);
+            for (buffer, memory) in staging {
+                device.destroy_buffer(buffer, None);
+                device.free_memory(memory, None);
+            }
+            Err((target, e))
+        }
+    }
+}
+
+/// Creates the asset and records its copies, the asset is destroyed again when a later step fails. The staging
+/// buffers land in `staging` even then, the caller frees them.
+unsafe fn record_upload(
+    device: &ash::Device,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    command_buffer: vk::CommandBuffer,
+    decoded: Decoded,
+    staging: &mut Vec<(vk::Buffer, vk::DeviceMemory)>,
+) -> Result<Uploaded> {
+    match decoded {
+        Decoded::Texture { pixels, extent, format } => {
+            let texture = Texture::new(
+                device,
+                instance,
+                physical_device,
+                extent,
+                format,
+                vk::ImageUsageFlags::TRANSFER_DST,
+            )?;
+            let Some(pixels) = format::convert_texels(format, texture.format, &pixels) else {
+                texture.destroy(device);
+                let message = format!("Texels in {:?} can't be converted to {:?}", format, texture.format);
+                return Err(Error::format(format, message));
+            };
+            let (staging_buffer, staging_memory) =
+                match buffer::create_staging_buffer(device, instance, physical_device, &pixels) {
+                    Ok(staging_buffer) => staging_buffer,
+                    Err(e) => {
+                        texture.destroy(device);
+                        return Err(e.into());
+                    }
+                };
+            staging.push((staging_buffer, staging_memory));
+
+            // the transfer queue can't name fragment shader stages, the release or the fence orders the first use
+            texture.cmd_upload(
+                device,
+                command_buffer,
+                staging_buffer,
+                (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::empty()),
+            );
+            Ok(Uploaded::Texture(texture))
+        }
+        Decoded::Mesh(mesh) => {
+            let (vertex_buffer, vertex_memory) = cmd_upload_buffer(
+                device,
+                instance,
+                physical_device,
+                command_buffer,
+                &mesh.vertices,
+                vk::BufferUsageFlags::VERTEX_BUFFER,
+                staging,
+            )?;
+            let index = cmd_upload_buffer(
+                device,
+                instance,
+                physical_device,
+                command_buffer,
+                &mesh.indices,
+                vk::BufferUsageFlags::INDEX_BUFFER,
+                staging,
+            );
+            let (index_buffer, index_memory) = match index {
+                Ok(index) => index,
+                Err(e) => {
+                    device.destroy_buffer(vertex_buffer, None);
+                    device.free_memory(vertex_memory, None);
+                    return Err(e.into());
+                }
+            };
+            Ok(Uploaded::Mesh(GpuMesh {
+                vertex_buffer,
+                vertex_memory,
+                index_buffer,
+                index_memory,
+                index_count: mesh.indices.len() as u32,
+                bounds: mesh.bounds(),
+                submeshes: mesh.submeshes,
+            }))
+        }
+    }
+}
+
+/// Releases `asset` to the graphics family and submits the recorded commands. Returns the fence and the
+/// semaphore the acquire waits on, nothing is left behind when it fails.
+unsafe fn submit_upload(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    transfer_queue: vk::Queue,
+    queues: QueueTransfer,
+    asset: &Uploaded,
+) -> VkResult<(vk::Fence, Option<vk::Semaphore>)> {
+    asset.cmd_release(device, command_buffer, queues);
+    device.end_command_buffer(command_buffer)?;
+
+    let fence_info = vk::FenceCreateInfo {
+        s_type: StructureType::FENCE_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::FenceCreateFlags::empty(),
+    };
+    let fence = device.create_fence(&fence_info, None)?;
+    // within one family the graphics queue is the transfer queue, submission order is enough
+    let semaphore = if queues.is_needed() {
+        match device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None) {
+            Ok(semaphore) => Some(semaphore),
+            Err(e) => {
+                device.destroy_fence(fence, None);
+                return Err(e);
+            }
+        }
+    } else {
+        None
+    };
+    let signal_semaphores: Vec<vk::Semaphore> = semaphore.into_iter().collect();
+
+    let submit_info = vk::SubmitInfo {
+        s_type: StructureType::SUBMIT_INFO,
+        p_next: ptr::null(),
+        wait_semaphore_count: 0,
+        p_wait_semaphores: ptr::null(),
+        p_wait_dst_stage_mask: ptr::null(),
+        command_buffer_count: 1,
+        p_command_buffers: &command_buffer,
+        signal_semaphore_count: signal_semaphores.len() as u32,
+        p_signal_semaphores: signal_semaphores.as_ptr(),
+    };
+    if let Err(e) = device.queue_submit(transfer_queue, &[submit_info], fence) {
+        device.destroy_fence(fence, None);
+        if let Some(semaphore) = semaphore {
+            device.destroy_semaphore(semaphore, None);
+        }
+        return Err(e);
+    }
+    Ok((fence, semaphore))
+}
+
+unsafe fn cmd_upload_buffer<T>(
+    device: &ash::Device,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    command_buffer: vk::CommandBuffer,
+    data: &[T],
+    usage: vk::BufferUsageFlags,
+    staging: &mut Vec<(vk::Buffer, vk::DeviceMemory)>,
+) -> VkResult<(vk::Buffer, vk::DeviceMemory)> {
+    let size = std::mem::size_of_val(data) as u64;
+    let (staging_buffer, staging_memory) = buffer::create_staging_buffer(device, instance, physical_device, data)?;
+    staging.push((staging_buffer, staging_memory));
+
+    let (buffer, memory) = buffer::create_buffer(
+        device,
+        instance,
+        physical_device,
+        size,
+        usage | vk::BufferUsageFlags::TRANSFER_DST,
+        vk::MemoryPropertyFlags::DEVICE_LOCAL,
+    )?;
+    let region = vk::BufferCopy {
+        src_offset: 0,
+        dst_offset: 0,
+        size,
+    };
+    device.cmd_copy_buffer(command_buffer, staging_buffer, buffer, &[region]);
+    Ok((buffer, memory))
+}
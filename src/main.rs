@@ -1,68 +1,231 @@
-#![feature(try_blocks, offset_of)]
-use anyhow::Result;
-use ash::{
-    prelude::VkResult,
-    vk::{self, DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCreateInfoEXT},
-    Entry, Instance,
-};
+use ash::{prelude::VkResult, vk, Entry};
+use raw_window_handle::{HasRawDisplayHandle, RawDisplayHandle};
 use std::ptr::{self};
 use std::{
     ffi::{c_void, CStr, CString},
     os::raw::c_char,
+    sync::Arc,
+    time::Instant,
 };
+use vulky::error::{Error, Result};
 use winit::{
     event::{Event, WindowEvent},
     event_loop::EventLoop,
-    window::{Window, WindowBuilder},
+    window::{Window, WindowBuilder, WindowId},
 };
 
+use demos::{Demo, DemoScene, Launch};
 use vulky::{
     buffer::{
-        create_command_buffers, create_command_pool, create_frame_buffer, create_index_buffer, create_sync_objects,
-        create_vertex_buffer, record_command_buffer, MAX_FRAMES_IN_FLIGHT,
+        create_command_buffers, create_command_pool, create_frame_buffer, create_index_buffer, create_vertex_buffer,
+        record_command_buffer, QuadBuffers, MAX_FRAMES_IN_FLIGHT,
     },
-    constant::{validation, version},
-    device::{create_logical_device, pick_physical_device},
+    capabilities::Capabilities,
+    capture::FrameCapture,
+    config::AppConfig,
+    constant::{validation, version, window_info, INDICES},
+    context::Device,
+    debug::{self, DebugConfig},
+    device::{create_logical_device_with, pick_physical_device_with},
+    device_lost::{self, CrashReport, DeviceLostTracker, DiagnosticExtensions, RecoveryPolicy},
+    extensions::ExtensionRequest,
+    features::DeviceRequirements,
+    frame_hash::{FrameHash, FrameHasher},
+    headless::{HeadlessContext, OffscreenTarget},
+    input::{InputState, Key},
+    owned,
     pipeline::{create_pipeline_layout, create_render_pass},
-    platform, utility, SwapChainSupportDetails,
+    platform::{self, FullscreenMode},
+    profiler,
+    sync::{self, ImageTransfer, QueueTransfer},
+    texture::DepthBuffer,
+    time::{FrameCadence, Time},
+    timeline::{self, QueueId, Submit, SyncMode, SyncPoint, Timelines},
+    utility,
+    watchdog::{GpuWatchdog, WatchdogSettings},
+    SurfaceTarget, SwapChainSupportDetails, SwapchainConfig, SwapchainLatency, SwapchainSharing,
 };
 
-mod texture;
+mod demos;
 mod types;
 
 /// The Vulkan SDK version that started requiring the portability subset extension for macOS.
 pub const PORTABILITY_MACOS_VERSION: u32 = vk::make_api_version(0, 1, 3, 216);
+/// Prints log records to stderr, the library itself only talks to the `log` facade.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("[{}][{}] {}", record.level(), record.target(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// VULKY_LOG picks the most verbose level shown, info when unset.
+fn init_logger() {
+    let level = std::env::var("VULKY_LOG")
+        .ok()
+        .and_then(|level| level.parse().ok())
+        .unwrap_or(log::LevelFilter::Info);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
 fn main() {
+    init_logger();
+    #[cfg(feature = "tracy")]
+    let _tracy = vulky::profiler::start_tracy();
+
+    let launch = match Launch::parse(std::env::args().skip(1)) {
+        Ok(launch) => launch,
+        Err(e) => panic!("{e}"),
+    };
+    // VULKY_VALIDATION, VULKY_GPU, VULKY_PRESENT_MODE, VULKY_SWAPCHAIN_IMAGES and VULKY_WATCHDOG
+    let config = AppConfig::from_env();
+    let demo = match launch {
+        Launch::Help => {
+            println!("{}", demos::usage());
+            return;
+        }
+        // --headless renders one frame without a window, into headless.ppm
+        Launch::Default { headless: true } => {
+            if let Err(e) = unsafe { render_headless("headless.ppm", config.validation) } {
+                panic!("{e}");
+            }
+            return;
+        }
+        Launch::Default { headless: false } => None,
+        // every demo once, failing on the first one that can't be drawn
+        Launch::Demos {
+            demos,
+            headless: true,
+            frames,
+            hash,
+        } => {
+            for demo in demos {
+                if let Err(e) = unsafe { render_demo_headless(demo, frames, hash, config.validation) } {
+                    panic!("{e}");
+                }
+            }
+            return;
+        }
+        Launch::Demos { demos, .. } => {
+            if demos.len() > 1 {
+                log::warn!("Only the first demo opens in the window, add --headless to render all of them");
+            }
+            demos.first().copied()
+        }
+    };
+
     // Create an event loop and window using winit
     unsafe {
         let event_loop = EventLoop::new();
         let window = WindowBuilder::new().with_title("Vulkan Window").build(&event_loop).unwrap();
+        let main_window = window.id();
 
-        let mut app: VulkanApp = match VulkanApp::new(&window) {
+        let mut app: VulkanApp = match VulkanApp::new(window, config.clone()) {
             Ok(el) => el,
             Err(e) => panic!("{e}"),
         };
+        if let Some(demo) = demo {
+            if let Err(e) = app.load_demo(demo) {
+                panic!("{e}");
+            }
+        }
         let mut quit = false;
+        // VULKY_DEVICE_LOST_RECOVERIES rebuilds the device after a driver reset instead of quitting
+        let mut recovery = RecoveryPolicy::from_env();
         let _resize = false;
+        let mut input = InputState::new();
+        let mut time = Time::new();
 
-        event_loop.run(move |event, _, control_flow| {
+        event_loop.run(move |event, window_target, control_flow| {
             // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
             // dispatched any events. This is ideal for games and similar applications.
-            control_flow.set_poll();
+            // When every window has a frame rate the loop sleeps until the next one is due.
+            match app.next_frame_due() {
+                Some(due) => control_flow.set_wait_until(due),
+                None => control_flow.set_poll(),
+            }
+            input.handle_event(&event);
 
             match event {
                 Event::WindowEvent {
+                    window_id,
                     event: WindowEvent::CloseRequested,
-                    ..
-                } => {
-                    println!("The close button was pressed; stopping");
+                } if window_id == main_window => {
+                    log::info!("The close button was pressed; stopping");
                     let _ = app.device.device_wait_idle();
                     app.destroy();
                     quit = true;
                     control_flow.set_exit();
                 }
+                Event::WindowEvent {
+                    window_id,
+                    event: WindowEvent::CloseRequested,
+                } => {
+                    if let Err(e) = app.remove_window(window_id) {
+                        log::error!("Failed to close the window: {}", e);
+                    }
+                }
                 Event::MainEventsCleared => {
-                    // Application update code.
+                    time.tick();
+                    if let Some(demo) = &mut app.demo {
+                        demo.update(time.delta_seconds());
+                    }
+                    let focused = app.focused_index();
+                    if app.alt_enter_fullscreen && platform::fullscreen_toggle_pressed(&input) {
+                        let mode = match app.windows[focused].fullscreen {
+                            FullscreenMode::Windowed => FullscreenMode::Borderless,
+                            _ => FullscreenMode::Windowed,
+                        };
+                        app.set_fullscreen(focused, mode);
+                    }
+                    // V toggles vsync of the focused window
+                    if input.key_just_pressed(Key::V) {
+                        let present_mode = match app.windows[focused].swapchain_latency.present_mode {
+                            vk::PresentModeKHR::FIFO => vk::PresentModeKHR::MAILBOX,
+                            _ => vk::PresentModeKHR::FIFO,
+                        };
+                        if let Err(e) = app.set_present_mode(focused, present_mode) {
+                            log::error!("Failed to switch the present mode: {}", e);
+                        }
+                    }
+                    // L caps the focused window at 10 Hz, like a preview pane, or uncaps it again
+                    if input.key_just_pressed(Key::L) {
+                        let hz = match app.windows[focused].cadence.interval() {
+                            Some(_) => None,
+                            None => Some(10.0),
+                        };
+                        app.set_frame_rate(focused, hz);
+                    }
+                    // C captures the next frame in RenderDoc
+                    if input.key_just_pressed(Key::C) {
+                        app.trigger_capture();
+                    }
+                    // N opens another window on the same device
+                    if !quit && input.key_just_pressed(Key::N) {
+                        let title = format!("Vulkan Window {}", app.windows.len() + 1);
+                        match WindowBuilder::new().with_title(title).build(window_target) {
+                            Ok(window) => {
+                                if let Err(e) = app.add_window(window, app.config.swapchain) {
+                                    log::error!("Failed to open a window: {}", e);
+                                }
+                            }
+                            Err(e) => log::error!("Failed to open a window: {}", e),
+                        }
+                    }
+                    // Application update code, scale movement by `time.delta_seconds()`.
                     // Queue a RedrawRequested event.
                     //
                     // You only need to call this if you've determined that you need to redraw, in
@@ -72,13 +235,32 @@ fn main() {
                     if !quit {
                         match app.draw_frame() {
                             Ok(_x) => {}
-                            Err(_e) => {
-                                panic!("recreates");
+                            Err(e) if device_lost::is_device_lost(e) => {
+                                let report = app.device_lost_report(e);
+                                log::error!("{}", report);
+                                match report.write(std::path::Path::new(".")) {
+                                    Ok(path) => log::error!("Wrote the crash report to {}", path.display()),
+                                    Err(e) => log::error!("Failed to write the crash report: {}", e),
+                                }
+                                if !recovery.should_recover() {
+                                    panic!("The device was lost");
+                                }
+                                if let Err(e) = app.recover() {
+                                    panic!("Failed to recreate the lost device: {e}");
+                                }
+                                log::warn!("Recreated the lost device");
+                            }
+                            Err(e) => {
+                                panic!("recreates: {e}");
                             }
                         }
                     } //app.draw_frame();
+                    profiler::frame_mark();
 
-                    window.request_redraw();
+                    input.end_frame();
+                    for target in app.windows.iter() {
+                        target.window.request_redraw();
+                    }
                 }
                 Event::RedrawRequested(_) => {
 
@@ -88,15 +270,19 @@ fn main() {
                     // this event rather than in MainEventsCleared, since rendering in here allows
                     // the program to gracefully handle redraws requested by the OS.
                 }
-                Event::WindowEvent { window_id: _, event } => match event {
+                Event::WindowEvent { window_id, event } => match event {
                     WindowEvent::Resized(x) => {
-                        if x.width == 0 && x.height == 0 {
-                            app.minimized = true;
-                        } else {
-                            window.set_inner_size(x);
-                            app.minimized = false;
+                        if let Some(target) = app.window_mut(window_id) {
+                            if x.width == 0 && x.height == 0 {
+                                target.minimized = true;
+                            } else {
+                                target.window.set_inner_size(x);
+                                target.minimized = false;
+                                target.framebuffer_resized = true;
+                            }
                         }
                     }
+                    WindowEvent::Focused(true) => app.focused = Some(window_id),
 
                     _ => {}
                 },
@@ -106,12 +292,67 @@ fn main() {
     }
 }
 
+/// A window with its own surface, swapchain and frames in flight, drawn with the shared device.
+struct WindowTarget {
+    window: Window,
+    /// Is the surface used when drawing, platform specific.
+    surface: owned::Surface,
+
+    //Swapchain, with the views of its images
+    swapchain: owned::Swapchain,
+    swapchain_format: vk::Format,
+    swapchain_extent: vk::Extent2D,
+    swapchain_images: Vec<vk::Image>,
+    swapchain_config: SwapchainConfig,
+    /// image count and present mode the surface granted
+    swapchain_latency: SwapchainLatency,
+    swapchain_framebuffers: Vec<owned::Framebuffer>,
+    depth_buffer: WindowDepthBuffer,
+
+    command_buffers: owned::CommandBuffers,
+
+    // semaphore
+    image_availables: Vec<owned::Semaphore>,
+    render_finisheds: Vec<owned::Semaphore>,
+    /// reached when the GPU finished the frame, None before its first submission
+    frame_points: Vec<Option<SyncPoint>>,
+
+    current_frame: usize,
+    framebuffer_resized: bool,
+    minimized: bool,
+    fullscreen: FullscreenMode,
+    /// how often the window is drawn, a slower window skips the loop iterations in between
+    cadence: FrameCadence,
+    /// with `SwapchainSharing::Exclusive` and a present family of its own
+    present_transfer: Option<PresentTransfer>,
+}
+
+/// Depth attachment of a window, recreated with its swapchain.
+struct WindowDepthBuffer {
+    view: owned::ImageView,
+    /// goes after its view
+    _image: owned::Image,
+    format: vk::Format,
+}
+
+/// Moves the EXCLUSIVE swapchain images from the graphics to the present family every frame.
+/// Nothing moves them back, the render pass discards what was presented before.
+struct PresentTransfer {
+    queues: QueueTransfer,
+    /// release barriers, submitted after the main pass
+    release_command_buffers: owned::CommandBuffers,
+    /// acquire barriers, submitted on the present queue
+    acquire_command_buffers: owned::CommandBuffers,
+    /// signaled by the acquire, presenting waits on it
+    acquireds: Vec<owned::Semaphore>,
+    acquire_fences: Vec<owned::Fence>,
+}
+
 struct VulkanApp {
     /// Global state for the app
     /// includes application specific info, including layers and extensions
-    instance: ash::Instance,
-    /// Used for loading vulkan statically or during runtime
-    entry: ash::Entry,
+    /// also has the entry used for loading vulkan statically or during runtime
+    instance: owned::Instance,
 
     /// debug extension
     debug_util_loader: ash::extensions::ext::DebugUtils,
@@ -123,297 +364,1018 @@ struct VulkanApp {
     /// Serves as a handle to interact with Vulkan API
     /// like managing vulkan resources, like (command buffers, queue handles, swapchain, pipeline, etc)
     /// Also used to enable extensions
-    device: ash::Device,
+    /// the owned objects below keep a clone, the last one destroys it
+    device: Device,
 
     //The interfacce with the surface
     surface_loader: ash::extensions::khr::Surface,
-    /// Is the surface used when drawing, platform specific.
-    surface: vk::SurfaceKHR,
 
     // Queues
     graphics_queue: vk::Queue,
     present_queue: vk::Queue,
-    transfer_queue: vk::Queue,
-
-    //Swapchain
-    swapchain: vk::SwapchainKHR,
-    swapchain_loader: ash::extensions::khr::Swapchain,
-    swapchain_format: vk::Format,
-    swapchain_extent: vk::Extent2D,
-    swapchain_images: Vec<vk::Image>,
-    swapchain_image_views: Vec<vk::ImageView>,
-
-    // Pipeline
-    render_pass: vk::RenderPass,
-    pipeline_layout: vk::PipelineLayout,
-    pipeline: vk::Pipeline,
-
-    //CommandPool
-    graphic_command_pool: vk::CommandPool,
-    transfer_command_pool: vk::CommandPool,
-
-    // buffers
-    swapchain_framebuffers: Vec<vk::Framebuffer>,
-    command_buffers: Vec<vk::CommandBuffer>,
-
-    // semaphore
-    image_availables: Vec<vk::Semaphore>,
-    render_finisheds: Vec<vk::Semaphore>,
-    in_flights: Vec<vk::Fence>,
-
-    current_frame: usize,
-    framebuffer_resized: bool,
-    minimized: bool,
-
-    vertex_buffer: vk::Buffer,
-    vertex_memory: vk::DeviceMemory,
-
-    index_buffer: vk::Buffer,
-    index_memory: vk::DeviceMemory,
+    graphics_family: u32,
+    present_family: u32,
+
+    /// the first one is the main window, closing it quits
+    windows: Vec<WindowTarget>,
+    /// window Alt+Enter and V apply to, the main window until another one gets focus
+    focused: Option<WindowId>,
+
+    // Pipeline, shared by every window, so all surfaces have to use the format of the main window
+    render_pass: owned::RenderPass,
+    /// only kept to be destroyed with the app
+    _pipeline_layout: owned::PipelineLayout,
+    pipeline: owned::Pipeline,
+
+    //CommandPool, after the windows whose command buffers come from them
+    graphic_command_pool: owned::CommandPool,
+    /// only kept to be destroyed with the app, the buffers are uploaded from it in `new`
+    _transfer_command_pool: owned::CommandPool,
+    /// only when presenting has its own family, for the acquire barriers of `SwapchainSharing::Exclusive`
+    present_command_pool: Option<owned::CommandPool>,
+
+    /// Alt+Enter switches between windowed and borderless fullscreen
+    alt_enter_fullscreen: bool,
+    /// RenderDoc captures, C captures the next frame
+    capture: FrameCapture,
+    /// breadcrumbs for the crash report when the device is lost
+    device_lost: Arc<DeviceLostTracker>,
+    /// reports frames that don't finish, when `AppConfig::watchdog` is set
+    watchdog: Option<GpuWatchdog>,
+    /// paces the frames of every window, with timeline semaphores where the device has them
+    timelines: Timelines,
+    graphics_timeline: QueueId,
+    /// drawn in the main window instead of the quad
+    demo: Option<DemoScene>,
+    /// what the app started with, `validation` is off when the layer wasn't installed
+    config: AppConfig,
+
+    vertex_buffer: owned::Buffer,
+    index_buffer: owned::Buffer,
 }
 impl VulkanApp {
-    unsafe fn new(window: &Window) -> Result<Self> {
+    unsafe fn new(window: Window, mut config: AppConfig) -> Result<Self> {
         let entry = ash::Entry::load()?;
-        let instance = create_instance(&entry)?;
+        // asking for validation in the field shouldn't stop machines without the SDK from starting
+        config.validation = config.validation && check_validation_support(&entry)?;
+        let instance = create_instance(&entry, window.raw_display_handle(), config.validation)?;
+        let instance = owned::Instance::from_raw(entry, instance);
 
-        let (surface, surface_loader) = create_surface(&entry, &instance, window)?;
-        let (debug_util_loader, debug_messenger) = setup_debug_utils(&entry, &instance)?;
+        let (surface, surface_loader) = create_surface(&instance, &window)?;
+        let (debug_util_loader, debug_messenger) = setup_debug_utils(instance.entry(), &instance, config.validation)?;
 
-        let physical_device = pick_physical_device(&instance, &surface_loader, &surface)?;
-        let (device, queue_family) = create_logical_device(physical_device, &instance, surface, &surface_loader)?;
+        let physical_device = pick_physical_device_with(&instance, &surface_loader, &surface.raw(), &config.gpu)?;
+        let diagnostics = DiagnosticExtensions::supported(&instance, physical_device)?;
+        let (device, queue_family, features, extensions) = create_logical_device_with(
+            physical_device,
+            &instance,
+            surface.raw(),
+            &surface_loader,
+            &DeviceRequirements::default().request(timeline::required_features()),
+            &diagnostics,
+        )?;
+        let device = Device::with_instance(&instance, physical_device, device);
+        // VULKY_LOG=debug puts the hardware into bug reports
+        log::debug!("{}", Capabilities::query(&instance, physical_device, &features, &extensions));
+        let device_lost = Arc::new(DeviceLostTracker::new(
+            &instance,
+            physical_device,
+            &device,
+            diagnostics,
+            Some(debug_util_loader.clone()),
+        )?);
         let graphics_queue = device.get_device_queue(queue_family.graphics_family.unwrap(), 0);
         let present_queue = device.get_device_queue(queue_family.present_family.unwrap(), 0);
+        let watchdog = config.watchdog.map(|timeout| {
+            let queues = [("graphics", graphics_queue), ("present", present_queue)];
+            let settings = WatchdogSettings::default().timeout(timeout);
+            GpuWatchdog::new(&instance, &device, device_lost.clone(), &queues, settings)
+        });
         let transfer_queue = device.get_device_queue(queue_family.transfer_family.unwrap(), 0);
+        let mut timelines = Timelines::new(SyncMode::for_features(&features), Some(debug_util_loader.clone()));
+        let graphics_timeline = timelines.add_queue(&device, graphics_queue, "graphics")?;
+        if timelines.mode() == SyncMode::Binary {
+            log::info!("No timeline semaphores, frames are paced with fences");
+        }
 
-        let (swapchain_loader, swapchain, swapchain_extent, swapchain_format, swapchain_images, swapchain_image_views) =
-            SwapChainSupportDetails::create_swapchain(&instance, &device, &surface_loader, surface, physical_device)?;
+        let graphic_command_pool =
+            owned::CommandPool::from_raw(&device, create_command_pool(&device, &queue_family.graphics_family)?);
+        let transfer_command_pool =
+            owned::CommandPool::from_raw(&device, create_command_pool(&device, &queue_family.transfer_family)?);
+        let present_command_pool = if queue_family.presents_separately() {
+            let pool = create_command_pool(&device, &queue_family.present_family)?;
+            Some(owned::CommandPool::from_raw(&device, pool))
+        } else {
+            None
+        };
+        let queues = QueueTransfer::new(queue_family.graphics_family.unwrap(), queue_family.present_family.unwrap());
 
-        let render_pass = create_render_pass(swapchain_format, &device)?;
-        let swapchain_framebuffers = create_frame_buffer(&device, &swapchain_image_views, render_pass, swapchain_extent)?;
-        let (pipeline, pipeline_layout) = create_pipeline_layout(&device, swapchain_extent, render_pass)?;
+        let mut main_window = WindowTarget::new(
+            &device,
+            &surface_loader,
+            graphic_command_pool.raw(),
+            window,
+            surface,
+            config.swapchain,
+        )?;
 
-        let graphic_command_pool = create_command_pool(&device, &queue_family.graphics_family)?;
-        let transfer_command_pool = create_command_pool(&device, &queue_family.transfer_family)?;
-        let (vertex_buffer, vertex_memory) =
-            create_vertex_buffer(&device, physical_device, &instance, transfer_command_pool, transfer_queue)?;
-        let (index_buffer, index_memory) =
-            create_index_buffer(&device, &instance, physical_device, transfer_command_pool, transfer_queue)?;
+        main_window.present_transfer = PresentTransfer::new_for(
+            &device,
+            &config.swapchain,
+            queues,
+            graphic_command_pool.raw(),
+            present_command_pool.as_ref().map(owned::CommandPool::raw),
+        )?;
+
+        let render_pass = create_render_pass(main_window.swapchain_format, main_window.depth_buffer.format, &device)?;
+        let render_pass = owned::RenderPass::from_raw(&device, render_pass);
+        main_window.create_framebuffers(&device, render_pass.raw())?;
+        let (pipeline, pipeline_layout) = create_pipeline_layout(&device, render_pass.raw())?;
+        let (pipeline, pipeline_layout) = (
+            owned::Pipeline::from_raw(&device, pipeline),
+            owned::PipelineLayout::from_raw(&device, pipeline_layout),
+        );
+
+        let (vertex_buffer, vertex_memory) = create_vertex_buffer(
+            &device,
+            physical_device,
+            &instance,
+            transfer_command_pool.raw(),
+            transfer_queue,
+        )?;
+        let vertex_buffer = owned::Buffer::from_raw(&device, vertex_buffer, Some(vertex_memory));
+        let (index_buffer, index_memory) = create_index_buffer(
+            &device,
+            &instance,
+            physical_device,
+            transfer_command_pool.raw(),
+            transfer_queue,
+        )?;
+        let index_buffer = owned::Buffer::from_raw(&device, index_buffer, Some(index_memory));
 
-        let command_buffers = create_command_buffers(&device, graphic_command_pool)?;
-        let (in_flights, image_availables, render_finisheds) = create_sync_objects(&device)?;
         Ok(Self {
             instance,
-            entry,
             physical_device,
             device,
             graphics_queue,
             present_queue,
-            transfer_queue,
-            transfer_command_pool,
-            surface,
+            graphics_family: queue_family.graphics_family.unwrap(),
+            present_family: queue_family.present_family.unwrap(),
+            _transfer_command_pool: transfer_command_pool,
+            present_command_pool,
             surface_loader,
-            swapchain,
-            swapchain_loader,
-            swapchain_extent,
-            swapchain_format,
-            swapchain_images,
-            swapchain_image_views,
-            swapchain_framebuffers,
+            windows: vec![main_window],
+            focused: None,
             render_pass,
-            pipeline_layout,
+            _pipeline_layout: pipeline_layout,
             pipeline,
             graphic_command_pool,
-            command_buffers,
             debug_util_loader,
             debug_messenger,
-            in_flights,
-            image_availables,
-            render_finisheds,
-            current_frame: 0,
-            framebuffer_resized: false,
-            minimized: false,
+            alt_enter_fullscreen: true,
+            capture: FrameCapture::new(),
+            device_lost,
+            watchdog,
+            timelines,
+            graphics_timeline,
+            demo: None,
+            config,
             vertex_buffer,
-            vertex_memory,
             index_buffer,
-            index_memory,
         })
     }
 
+    /// Builds `demo` for the shared render pass, replacing the one shown before.
+    pub unsafe fn load_demo(&mut self, demo: Demo) -> Result<()> {
+        self.device.device_wait_idle()?;
+        if let Some(previous) = self.demo.take() {
+            previous.destroy(&self.device);
+        }
+        self.demo = Some(DemoScene::new(
+            demo,
+            &self.device,
+            &self.instance,
+            self.physical_device,
+            self.graphic_command_pool.raw(),
+            self.graphics_queue,
+            self.render_pass.raw(),
+        )?);
+        Ok(())
+    }
+
+    /// Opens `window` as another render target sharing the device and pipeline.
+    pub unsafe fn add_window(&mut self, window: Window, swapchain_config: SwapchainConfig) -> Result<WindowId> {
+        let surface = owned::Surface::new(&self.instance, &window)?;
+        let supported = self.surface_loader.get_physical_device_surface_support(
+            self.physical_device,
+            self.present_family,
+            surface.raw(),
+        );
+        if supported != Ok(true) {
+            return Err(Error::msg("The present queue can't present to the new window"));
+        }
+
+        let mut target = WindowTarget::new(
+            &self.device,
+            &self.surface_loader,
+            self.graphic_command_pool.raw(),
+            window,
+            surface,
+            swapchain_config,
+        )?;
+
+        let main_format = self.windows[0].swapchain_format;
+        let result = if target.swapchain_format != main_format {
+            Err(Error::msg(format!(
+                "The new window uses {:?}, the shared render pass {:?}",
+                target.swapchain_format, main_format
+            )))
+        } else {
+            target
+                .create_framebuffers(&self.device, self.render_pass.raw())
+                .map_err(Error::from)
+        };
+        let result = result.and_then(|_| {
+            target.present_transfer = PresentTransfer::new_for(
+                &self.device,
+                &swapchain_config,
+                QueueTransfer::new(self.graphics_family, self.present_family),
+                self.graphic_command_pool.raw(),
+                self.present_command_pool.as_ref().map(owned::CommandPool::raw),
+            )?;
+            Ok(())
+        });
+        // a target that failed half way is dropped with what it created
+        result?;
+
+        let id = target.window.id();
+        self.windows.push(target);
+        Ok(id)
+    }
+
+    /// Closes a window opened with `add_window`, the main window is only destroyed with the app.
+    pub unsafe fn remove_window(&mut self, window_id: WindowId) -> VkResult<()> {
+        let Some(index) = self.windows.iter().skip(1).position(|target| target.window.id() == window_id) else {
+            return Ok(());
+        };
+        self.device.device_wait_idle()?;
+        self.windows.remove(index + 1);
+        if self.focused == Some(window_id) {
+            self.focused = None;
+        }
+        Ok(())
+    }
+
+    /// Draws window `index` at most `hz` times a second, None draws it every iteration of the loop.
+    pub fn set_frame_rate(&mut self, index: usize, hz: Option<f64>) {
+        self.windows[index].cadence = hz.map_or(FrameCadence::unlimited(), FrameCadence::hz);
+    }
+
+    /// When the next window is due, None while a visible window draws every iteration.
+    fn next_frame_due(&self) -> Option<Instant> {
+        let mut due: Option<Instant> = None;
+        for target in self.windows.iter().filter(|target| !target.minimized) {
+            let next = target.cadence.next_due()?;
+            due = Some(due.map_or(next, |due| due.min(next)));
+        }
+        due
+    }
+
+    fn window_mut(&mut self, window_id: WindowId) -> Option<&mut WindowTarget> {
+        self.windows.iter_mut().find(|target| target.window.id() == window_id)
+    }
+
+    /// Index of the focused window, 0 when it is closed or nothing had focus yet.
+    fn focused_index(&self) -> usize {
+        self.windows
+            .iter()
+            .position(|target| Some(target.window.id()) == self.focused)
+            .unwrap_or(0)
+    }
+
+    /// Draws every window that isn't minimized and whose next frame is due. Each window only waits on its own
+    /// frames in flight, so a slow window doesn't hold back the others.
     pub unsafe fn draw_frame(&mut self) -> VkResult<()> {
+        let now = Instant::now();
+        for index in 0..self.windows.len() {
+            let target = &mut self.windows[index];
+            if !target.minimized && target.cadence.due(now) {
+                self.draw_window(index)?;
+            }
+        }
+        self.device_lost.end_frame();
+        Ok(())
+    }
+
+    unsafe fn draw_window(&mut self, index: usize) -> VkResult<()> {
         // a render pass, is a sequence of rendering operations, organized as series of subpasses
         // each subpass describes, image, rendering commands
-        let wait_fences = [self.in_flights[self.current_frame]];
-
-        self.device
-            .wait_for_fences(&wait_fences, true, std::u64::MAX)
-            .expect("Failed to wait for Fence!");
+        let target = &mut self.windows[index];
+
+        vulky::cpu_scope!("draw window");
+        if let Some(frame_point) = target.frame_points[target.current_frame] {
+            vulky::cpu_scope!("wait for frame");
+            let watch = self
+                .watchdog
+                .as_ref()
+                .map(|watchdog| watchdog.watch("graphics", &["main pass"]));
+            let waited = self.timelines.wait(&self.device, &frame_point, u64::MAX);
+            if let (Some(watchdog), Some(watch)) = (self.watchdog.as_ref(), watch) {
+                watchdog.signaled(watch);
+            }
+            waited?;
+        }
 
         let (image_index, _is_sub_optimal) = unsafe {
-            let result = self.swapchain_loader.acquire_next_image(
-                self.swapchain,
-                std::u64::MAX,
-                self.image_availables[self.current_frame],
+            let result = target.swapchain.loader().acquire_next_image(
+                target.swapchain.raw(),
+                u64::MAX,
+                target.image_availables[target.current_frame].raw(),
                 vk::Fence::null(),
             );
             match result {
                 Ok(image_index) => image_index,
                 Err(vk_result) => match vk_result {
                     vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                        self.recreate_swapchain()?;
+                        self.recreate_swapchain(index)?;
                         return Ok(());
                     }
-                    _ => panic!("Failed to acquire Swap Chain Image!"),
+                    _ => return Err(vk_result),
                 },
             }
         };
 
-        self.device.reset_fences(&wait_fences)?;
+        let command_buffer = target.command_buffers.raw()[target.current_frame];
         self.device
-            .reset_command_buffer(self.command_buffers[self.current_frame], vk::CommandBufferResetFlags::empty())?;
-        record_command_buffer(
-            &self.device,
-            self.command_buffers[self.current_frame],
-            self.render_pass,
-            &self.swapchain_framebuffers,
-            image_index,
-            self.swapchain_extent,
-            self.pipeline,
-            self.vertex_buffer,
-            self.index_buffer,
-        )?;
-
-        let wait_semaphores = [self.image_availables[self.current_frame]];
-        let wait_stages = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let signal_semaphores = [self.render_finisheds[self.current_frame]];
-
-        let submit_infos = [vk::SubmitInfo {
-            s_type: vk::StructureType::SUBMIT_INFO,
-            p_next: ptr::null(),
-            wait_semaphore_count: wait_semaphores.len() as u32,
-            p_wait_semaphores: wait_semaphores.as_ptr(),
-            p_wait_dst_stage_mask: wait_stages.as_ptr(),
-            command_buffer_count: 1,
-            p_command_buffers: &self.command_buffers[self.current_frame],
-            signal_semaphore_count: signal_semaphores.len() as u32,
-            p_signal_semaphores: signal_semaphores.as_ptr(),
-        }];
+            .reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        {
+            vulky::cpu_scope!("record commands");
+            // the frame uniforms of the demo only have one set per frame in flight, so it stays in one window
+            match &mut self.demo {
+                Some(demo) if index == 0 => record_demo_command_buffer(
+                    &self.device,
+                    command_buffer,
+                    self.render_pass.raw(),
+                    target.swapchain_framebuffers[image_index as usize].raw(),
+                    target.swapchain_extent,
+                    target.current_frame,
+                    demo,
+                )?,
+                _ => record_command_buffer(
+                    &self.device,
+                    command_buffer,
+                    self.render_pass.raw(),
+                    target.swapchain_framebuffers[image_index as usize].raw(),
+                    target.swapchain_extent,
+                    self.pipeline.raw(),
+                    QuadBuffers {
+                        vertex_buffer: self.vertex_buffer.raw(),
+                        index_buffer: self.index_buffer.raw(),
+                    },
+                )?,
+            }
+        }
+        let mut command_buffers = vec![command_buffer];
+        if let Some(transfer) = &target.present_transfer {
+            let image = target.swapchain_images[image_index as usize];
+            command_buffers.push(transfer.record_release(&self.device, target.current_frame, image)?);
+        }
 
-        self.device
-            .queue_submit(self.graphics_queue, &submit_infos, wait_fences[0])
-            .expect("Failed to execute queue submit.");
+        let wait_semaphores = [(
+            target.image_availables[target.current_frame].raw(),
+            vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        )];
+        let signal_semaphores = [target.render_finisheds[target.current_frame].raw()];
+
+        self.device_lost.submitted("graphics", &["main pass"]);
+        let submit = Submit {
+            command_buffers: &command_buffers,
+            wait_semaphores: &wait_semaphores,
+            signal_semaphores: &signal_semaphores,
+            ..Default::default()
+        };
+        target.frame_points[target.current_frame] =
+            Some(self.timelines.submit(&self.device, self.graphics_timeline, &submit)?);
+
+        // without a transfer the present queue waits on the main pass itself
+        let present_wait = match &target.present_transfer {
+            Some(transfer) => {
+                let image = target.swapchain_images[image_index as usize];
+                self.device_lost.submitted("present", &["ownership acquire"]);
+                transfer.submit_acquire(
+                    &self.device,
+                    self.present_queue,
+                    target.current_frame,
+                    image,
+                    signal_semaphores[0],
+                )?
+            }
+            None => signal_semaphores[0],
+        };
 
-        let swapchains = self.swapchain;
+        let swapchains = target.swapchain.raw();
 
         let present_info = vk::PresentInfoKHR {
             s_type: vk::StructureType::PRESENT_INFO_KHR,
             p_next: ptr::null(),
             wait_semaphore_count: 1,
-            p_wait_semaphores: signal_semaphores.as_ptr(),
+            p_wait_semaphores: &present_wait,
             swapchain_count: 1,
             p_swapchains: &swapchains,
             p_image_indices: &image_index,
             p_results: ptr::null_mut(),
         };
 
-        let result = unsafe { self.swapchain_loader.queue_present(self.present_queue, &present_info) };
+        let result = {
+            vulky::cpu_scope!("present");
+            unsafe { target.swapchain.loader().queue_present(self.present_queue, &present_info) }
+        };
 
         let is_resized = match result {
-            Ok(_) => self.framebuffer_resized,
+            Ok(_) => target.framebuffer_resized,
             Err(vk_result) => match vk_result {
                 vk::Result::ERROR_OUT_OF_DATE_KHR | vk::Result::SUBOPTIMAL_KHR => true,
-                _ => panic!("Failed to execute queue present."),
+                _ => return Err(vk_result),
             },
         };
+        target.current_frame = (target.current_frame + 1) % MAX_FRAMES_IN_FLIGHT as usize;
         if is_resized {
-            self.framebuffer_resized = false;
-            self.recreate_swapchain()?;
+            target.framebuffer_resized = false;
+            self.recreate_swapchain(index)?;
         }
+        Ok(())
+    }
+
+    /// What is known about why the device was lost.
+    pub unsafe fn device_lost_report(&self, error: vk::Result) -> CrashReport {
+        let queues = [("graphics", self.graphics_queue), ("present", self.present_queue)];
+        self.device_lost.report(&self.instance, &self.device, error, &queues)
+    }
 
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT as usize;
+    /// Rebuilds the instance, device and every resource after the device was lost, the windows stay open.
+    /// When it fails the app is left destroyed.
+    pub unsafe fn recover(&mut self) -> Result<()> {
+        // a lost device returns at once, the old objects can be destroyed afterwards
+        let _ = self.device.device_wait_idle();
+        let mut windows = vec![];
+        // the rest of each target is dropped here
+        for target in std::mem::take(&mut self.windows) {
+            windows.push((target.window, target.swapchain_config, target.fullscreen, target.cadence));
+        }
+        let demo = self.demo.as_ref().map(DemoScene::demo);
+        self.destroy();
+
+        let mut windows = windows.into_iter();
+        let (window, swapchain_config, fullscreen, cadence) = windows
+            .next()
+            .ok_or_else(|| Error::msg("No window left to recreate the device with"))?;
+        let config = AppConfig {
+            swapchain: swapchain_config,
+            ..self.config.clone()
+        };
+        let mut app = VulkanApp::new(window, config)?;
+        app.windows[0].fullscreen = fullscreen;
+        app.windows[0].cadence = cadence;
+        for (window, swapchain_config, fullscreen, cadence) in windows {
+            app.add_window(window, swapchain_config)?;
+            let target = app.windows.last_mut().unwrap();
+            target.fullscreen = fullscreen;
+            target.cadence = cadence;
+        }
+        app.focused = self.focused;
+        app.alt_enter_fullscreen = self.alt_enter_fullscreen;
+        app.capture = std::mem::take(&mut self.capture);
+        if let Some(demo) = demo {
+            app.load_demo(demo)?;
+        }
+        *self = app;
         Ok(())
     }
 
+    /// Captures the next frame when running under RenderDoc, nothing happens otherwise.
+    pub fn trigger_capture(&mut self) {
+        self.capture.trigger_capture();
+    }
+
+    /// Recreates the swapchain of window `index` with `present_mode`, or the closest mode the surface supports.
+    /// Returns the mode in use afterwards.
+    pub unsafe fn set_present_mode(
+        &mut self,
+        index: usize,
+        present_mode: vk::PresentModeKHR,
+    ) -> VkResult<vk::PresentModeKHR> {
+        self.windows[index].swapchain_config.present_mode = Some(present_mode);
+        self.recreate_swapchain(index)?;
+        Ok(self.windows[index].swapchain_latency.present_mode)
+    }
+
+    /// Switches window `index`, its swapchain is recreated with the new size after the next present.
+    pub fn set_fullscreen(&mut self, index: usize, mode: FullscreenMode) {
+        let target = &mut self.windows[index];
+        if mode == target.fullscreen {
+            return;
+        }
+        platform::set_fullscreen(&target.window, mode);
+        target.fullscreen = mode;
+        target.framebuffer_resized = true;
+    }
+
     unsafe fn destroy(&mut self) {
-        if validation::ENABLED {
+        // joins the watchdog thread, it uses the device
+        self.watchdog = None;
+        if self.config.validation {
             self.debug_util_loader
                 .destroy_debug_utils_messenger(self.debug_messenger, None);
+            debug::log_suppression_summary();
         }
-        for i in 0..MAX_FRAMES_IN_FLIGHT as usize {
-            self.device.destroy_fence(self.in_flights[i], None);
-            self.device.destroy_semaphore(self.image_availables[i], None);
-            self.device.destroy_semaphore(self.render_finisheds[i], None);
+        if let Some(demo) = self.demo.take() {
+            demo.destroy(&self.device);
         }
-        self.device.destroy_command_pool(self.graphic_command_pool, None);
-        self.device.destroy_command_pool(self.transfer_command_pool, None);
-
-        self.clean_swapchain();
-
-        self.device.destroy_buffer(self.vertex_buffer, None);
-        self.device.destroy_buffer(self.index_buffer, None);
-        self.device.free_memory(self.vertex_memory, None);
-        self.device.free_memory(self.index_memory, None);
-
-        self.device.destroy_pipeline(self.pipeline, None);
-        self.device.destroy_pipeline_layout(self.pipeline_layout, None);
-        self.device.destroy_render_pass(self.render_pass, None);
 
-        self.surface_loader.destroy_surface(self.surface, None);
-        self.device.destroy_device(None);
-        self.instance.destroy_instance(None);
+        self.device_lost.destroy(&self.device);
+        self.timelines.destroy(&self.device);
+        // the owned objects, the windows with their swapchains and frames in flight, the command pools, the device
+        // and the instance go with the app, each after everything that uses it
     }
 
-    pub unsafe fn recreate_swapchain(&mut self) -> VkResult<()> {
+    pub unsafe fn recreate_swapchain(&mut self, index: usize) -> VkResult<()> {
         self.device.device_wait_idle()?;
-        self.clean_swapchain();
+        let target = &mut self.windows[index];
+        target.swapchain_framebuffers.clear();
 
-        (
-            self.swapchain_loader,
-            self.swapchain,
-            self.swapchain_extent,
-            self.swapchain_format,
-            self.swapchain_images,
-            self.swapchain_image_views,
-        ) = SwapChainSupportDetails::create_swapchain(
+        let created = SwapChainSupportDetails::create_swapchain_with(
             &self.instance,
             &self.device,
-            &self.surface_loader,
-            self.surface,
             self.physical_device,
+            SurfaceTarget {
+                loader: &self.surface_loader,
+                surface: target.surface.raw(),
+                window_extent: window_extent(&target.window),
+            },
+            &target.swapchain_config,
+            target.swapchain.raw(),
         )?;
-
-        self.swapchain_framebuffers = create_frame_buffer(
+        // destroys the retired swapchain
+        target.swapchain = owned::Swapchain::from_raw(
             &self.device,
-            &self.swapchain_image_views,
-            self.render_pass,
-            self.swapchain_extent,
+            &target.surface,
+            created.loader,
+            created.swapchain,
+            created.image_views,
+        );
+        target.swapchain_extent = created.extent;
+        target.swapchain_format = created.format;
+        target.swapchain_images = created.images;
+        target.swapchain_latency = created.latency;
+
+        target.depth_buffer =
+            WindowDepthBuffer::new(&self.device, &self.instance, self.physical_device, target.swapchain_extent)?;
+        target.create_framebuffers(&self.device, self.render_pass.raw())?;
+
+        Ok(())
+    }
+}
+
+impl WindowTarget {
+    /// Creates the swapchain and frames in flight of `window`, the framebuffers follow with `create_framebuffers`
+    /// once the render pass exists.
+    unsafe fn new(
+        device: &Device,
+        surface_loader: &ash::extensions::khr::Surface,
+        command_pool: vk::CommandPool,
+        window: Window,
+        surface: owned::Surface,
+        swapchain_config: SwapchainConfig,
+    ) -> Result<Self> {
+        let created = SwapChainSupportDetails::create_swapchain_with(
+            device.instance(),
+            device,
+            device.physical_device(),
+            SurfaceTarget {
+                loader: surface_loader,
+                surface: surface.raw(),
+                window_extent: window_extent(&window),
+            },
+            &swapchain_config,
+            vk::SwapchainKHR::null(),
         )?;
+        let swapchain_extent = created.extent;
+        let swapchain = owned::Swapchain::from_raw(device, &surface, created.loader, created.swapchain, created.image_views);
+
+        let depth_buffer = WindowDepthBuffer::new(device, device.instance(), device.physical_device(), swapchain_extent)?;
+        let command_buffers =
+            owned::CommandBuffers::from_raw(device, command_pool, create_command_buffers(device, command_pool)?);
+        let mut image_availables = vec![];
+        let mut render_finisheds = vec![];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            image_availables.push(create_semaphore(device)?);
+            render_finisheds.push(create_semaphore(device)?);
+        }
 
+        Ok(Self {
+            window,
+            surface,
+            swapchain,
+            swapchain_format: created.format,
+            swapchain_extent,
+            swapchain_images: created.images,
+            swapchain_config,
+            swapchain_latency: created.latency,
+            swapchain_framebuffers: vec![],
+            depth_buffer,
+            command_buffers,
+            image_availables,
+            render_finisheds,
+            frame_points: vec![None; MAX_FRAMES_IN_FLIGHT as usize],
+            current_frame: 0,
+            framebuffer_resized: false,
+            minimized: false,
+            fullscreen: FullscreenMode::Windowed,
+            cadence: FrameCadence::unlimited(),
+            present_transfer: None,
+        })
+    }
+
+    unsafe fn create_framebuffers(&mut self, device: &Device, render_pass: vk::RenderPass) -> VkResult<()> {
+        let framebuffers = create_frame_buffer(
+            device,
+            self.swapchain.image_views(),
+            self.depth_buffer.view.raw(),
+            render_pass,
+            self.swapchain_extent,
+        )?;
+        self.swapchain_framebuffers = framebuffers
+            .into_iter()
+            .map(|framebuffer| owned::Framebuffer::from_raw(device, framebuffer))
+            .collect();
         Ok(())
     }
+}
 
-    unsafe fn clean_swapchain(&mut self) {
-        while self.swapchain_framebuffers.len() > 0 {
-            let e = self.swapchain_framebuffers.pop().unwrap();
-            self.device.destroy_framebuffer(e, None);
+impl WindowDepthBuffer {
+    unsafe fn new(
+        device: &Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+    ) -> VkResult<Self> {
+        let depth = DepthBuffer::new(device, instance, physical_device, extent)?;
+        Ok(Self {
+            view: owned::ImageView::from_raw(device, depth.view),
+            _image: owned::Image::from_raw(device, depth.image, Some(depth.memory)),
+            format: depth.format,
+        })
+    }
+}
+
+unsafe fn create_semaphore(device: &Device) -> VkResult<owned::Semaphore> {
+    let semaphore = device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None)?;
+    Ok(owned::Semaphore::from_raw(device, semaphore))
+}
+
+impl PresentTransfer {
+    /// None unless `config` asks for EXCLUSIVE images and a present pool exists, which it only does when
+    /// the families differ.
+    unsafe fn new_for(
+        device: &Device,
+        config: &SwapchainConfig,
+        queues: QueueTransfer,
+        graphics_command_pool: vk::CommandPool,
+        present_command_pool: Option<vk::CommandPool>,
+    ) -> Result<Option<Self>> {
+        let Some(present_command_pool) = present_command_pool else {
+            return Ok(None);
+        };
+        if config.sharing != SwapchainSharing::Exclusive || !queues.is_needed() {
+            return Ok(None);
+        }
+
+        let release_command_buffers = owned::CommandBuffers::from_raw(
+            device,
+            graphics_command_pool,
+            create_command_buffers(device, graphics_command_pool)?,
+        );
+        let acquire_command_buffers = owned::CommandBuffers::from_raw(
+            device,
+            present_command_pool,
+            create_command_buffers(device, present_command_pool)?,
+        );
+        let mut acquireds = vec![];
+        let mut acquire_fences = vec![];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            acquireds.push(create_semaphore(device)?);
+            let fence_info = vk::FenceCreateInfo {
+                flags: vk::FenceCreateFlags::SIGNALED,
+                ..Default::default()
+            };
+            acquire_fences.push(owned::Fence::from_raw(device, device.create_fence(&fence_info, None)?));
         }
+        Ok(Some(Self {
+            queues,
+            release_command_buffers,
+            acquire_command_buffers,
+            acquireds,
+            acquire_fences,
+        }))
+    }
+
+    fn image_transfer(&self) -> ImageTransfer {
+        ImageTransfer::color(
+            self.queues,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            vk::ImageLayout::PRESENT_SRC_KHR,
+        )
+    }
+
+    /// The release of `image` after the main pass of `frame`, whose fence has to be waited on.
+    unsafe fn record_release(&self, device: &ash::Device, frame: usize, image: vk::Image) -> VkResult<vk::CommandBuffer> {
+        let command_buffer = self.release_command_buffers.raw()[frame];
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+        sync::cmd_release_image(
+            device,
+            command_buffer,
+            image,
+            &self.image_transfer(),
+            (
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ),
+        );
+        device.end_command_buffer(command_buffer)?;
+        Ok(command_buffer)
+    }
+
+    /// Acquires `image` on `present_queue` once `released` is signaled, returns the semaphore to present with.
+    unsafe fn submit_acquire(
+        &self,
+        device: &ash::Device,
+        present_queue: vk::Queue,
+        frame: usize,
+        image: vk::Image,
+        released: vk::Semaphore,
+    ) -> VkResult<vk::Semaphore> {
+        let fence = self.acquire_fences[frame].raw();
+        device.wait_for_fences(&[fence], true, u64::MAX)?;
+        device.reset_fences(&[fence])?;
+
+        let command_buffer = self.acquire_command_buffers.raw()[frame];
+        let acquired = self.acquireds[frame].raw();
+        device.reset_command_buffer(command_buffer, vk::CommandBufferResetFlags::empty())?;
+        let begin_info = vk::CommandBufferBeginInfo {
+            flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+            ..Default::default()
+        };
+        device.begin_command_buffer(command_buffer, &begin_info)?;
+        // presenting is ordered by the semaphore, nothing on this queue touches the image afterwards
+        sync::cmd_acquire_image(
+            device,
+            command_buffer,
+            image,
+            &self.image_transfer(),
+            (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty()),
+        );
+        device.end_command_buffer(command_buffer)?;
+
+        let wait_stage = vk::PipelineStageFlags::ALL_COMMANDS;
+        let submit_info = vk::SubmitInfo {
+            wait_semaphore_count: 1,
+            p_wait_semaphores: &released,
+            p_wait_dst_stage_mask: &wait_stage,
+            command_buffer_count: 1,
+            p_command_buffers: &command_buffer,
+            signal_semaphore_count: 1,
+            p_signal_semaphores: &acquired,
+            ..Default::default()
+        };
+        device.queue_submit(present_queue, &[submit_info], fence)?;
+        Ok(acquired)
+    }
+}
+
+/// Draws the demo quad into an offscreen target and writes it to `path`.
+unsafe fn render_headless(path: &str, validation: bool) -> Result<()> {
+    let context = HeadlessContext::new(validation)?;
+    let extent = vk::Extent2D {
+        width: window_info::WIDTH,
+        height: window_info::HEIGHT,
+    };
+    let target = OffscreenTarget::new(&context, extent, vk::Format::R8G8B8A8_UNORM)?;
+    let (pipeline, pipeline_layout) = create_pipeline_layout(&context.device, target.render_pass)?;
+    let (vertex_buffer, vertex_memory) = create_vertex_buffer(
+        &context.device,
+        context.physical_device,
+        &context.instance,
+        context.command_pool,
+        context.queue,
+    )?;
+    let (index_buffer, index_memory) = create_index_buffer(
+        &context.device,
+        &context.instance,
+        context.physical_device,
+        context.command_pool,
+        context.queue,
+    )?;
+
+    // nothing is presented, so a RenderDoc capture has to be opened and closed by hand
+    let mut capture = FrameCapture::new();
+    let pixels = capture.capture(|| {
+        target.render(&context, [0.0, 0.0, 0.0, 1.0], |device, command_buffer| {
+            device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, pipeline);
+            device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer], &[0]);
+            device.cmd_bind_index_buffer(command_buffer, index_buffer, 0, vk::IndexType::UINT16);
+            device.cmd_draw_indexed(command_buffer, INDICES.len() as u32, 1, 0, 0, 0);
+        })
+    })?;
+    target.write_ppm(path, &pixels)?;
+    log::info!("Wrote {}", path);
+
+    let device = &context.device;
+    device.destroy_buffer(vertex_buffer, None);
+    device.free_memory(vertex_memory, None);
+    device.destroy_buffer(index_buffer, None);
+    device.free_memory(index_memory, None);
+    device.destroy_pipeline(pipeline, None);
+    device.destroy_pipeline_layout(pipeline_layout, None);
+    target.destroy(&context);
+    context.destroy();
+    Ok(())
+}
+
+/// Like `record_command_buffer` with the draws of `demo` instead of the quad.
+unsafe fn record_demo_command_buffer(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+    current_frame: usize,
+    demo: &mut DemoScene,
+) -> VkResult<()> {
+    let begin_info = vk::CommandBufferBeginInfo {
+        s_type: vk::StructureType::COMMAND_BUFFER_BEGIN_INFO,
+        p_next: ptr::null(),
+        flags: vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT,
+        p_inheritance_info: ptr::null(),
+    };
+    device.begin_command_buffer(command_buffer, &begin_info)?;
+
+    let clear_values = [
+        vk::ClearValue {
+            color: vk::ClearColorValue {
+                float32: DEMO_CLEAR_COLOR,
+            },
+        },
+        vk::ClearValue {
+            depth_stencil: vk::ClearDepthStencilValue { depth: 1.0, stencil: 0 },
+        },
+    ];
+    let render_area = vk::Rect2D {
+        offset: vk::Offset2D { x: 0, y: 0 },
+        extent,
+    };
+    let render_pass_info = vk::RenderPassBeginInfo {
+        s_type: vk::StructureType::RENDER_PASS_BEGIN_INFO,
+        p_next: ptr::null(),
+        render_pass,
+        framebuffer,
+        render_area,
+        clear_value_count: clear_values.len() as u32,
+        p_clear_values: clear_values.as_ptr(),
+    };
+    device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+    let viewport = vk::Viewport {
+        x: 0.0,
+        y: 0.0,
+        width: extent.width as f32,
+        height: extent.height as f32,
+        min_depth: 0.0,
+        max_depth: 1.0,
+    };
+    device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+    device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+
+    demo.record(device, command_buffer, current_frame, extent);
+
+    device.cmd_end_render_pass(command_buffer);
+    device.end_command_buffer(command_buffer)
+}
+
+const DEMO_CLEAR_COLOR: [f32; 4] = [0.02, 0.02, 0.03, 1.0];
 
-        for _ in 0..self.swapchain_image_views.len() {
-            let image_view = self.swapchain_image_views.pop().unwrap();
-            self.device.destroy_image_view(image_view, None);
+/// Renders `frames` frames of `demo` offscreen and writes the last one to `<name>.ppm`.
+/// Fails when every pixel is still the clear color, so a run over all demos works as an integration test.
+/// With `hash` the last frame is only hashed on the GPU and the hash logged, to compare runs without readback.
+unsafe fn render_demo_headless(demo: Demo, frames: u32, hash: bool, validation: bool) -> Result<()> {
+    let context = HeadlessContext::new(validation)?;
+    let extent = vk::Extent2D {
+        width: window_info::WIDTH,
+        height: window_info::HEIGHT,
+    };
+    let target = OffscreenTarget::new(&context, extent, vk::Format::R8G8B8A8_UNORM)?;
+    let hasher = match hash {
+        true => match FrameHasher::new(&context.device, &context.instance, context.physical_device) {
+            Ok(hasher) => Some(hasher),
+            Err(e) => {
+                target.destroy(&context);
+                context.destroy();
+                return Err(e);
+            }
+        },
+        false => None,
+    };
+    let result = render_demo_frames(&context, &target, demo, frames, hasher.as_ref()).and_then(|frame| match frame {
+        DemoFrame::Hash(hash) => {
+            log::info!("The last frame of {} hashes to {}", demo.name(), hash);
+            Ok(())
         }
+        DemoFrame::Pixels(pixels) => {
+            let clear = DEMO_CLEAR_COLOR.map(|channel| (channel * 255.0).round() as i32);
+            let drawn = pixels
+                .chunks_exact(4)
+                .filter(|texel| {
+                    texel
+                        .iter()
+                        .zip(clear)
+                        .any(|(value, clear)| (*value as i32 - clear).abs() > 1)
+                })
+                .count();
+            let path = format!("{}.ppm", demo.name());
+            target.write_ppm(&path, &pixels)?;
+            if drawn == 0 {
+                return Err(Error::msg(format!("The {} demo drew nothing", demo.name())));
+            }
+            log::info!("Wrote {}, {} of {} pixels drawn", path, drawn, pixels.len() / 4);
+            Ok(())
+        }
+    });
+    if let Some(hasher) = hasher.as_ref() {
+        hasher.destroy(&context.device);
+    }
+    target.destroy(&context);
+    context.destroy();
+    result
+}
+
+enum DemoFrame {
+    Pixels(Vec<u8>),
+    Hash(FrameHash),
+}
 
-        self.swapchain_loader.destroy_swapchain(self.swapchain, None);
+/// The texels of the last of `frames` frames of `demo`, or its hash when there is a `hasher`.
+unsafe fn render_demo_frames(
+    context: &HeadlessContext,
+    target: &OffscreenTarget,
+    demo: Demo,
+    frames: u32,
+    hasher: Option<&FrameHasher>,
+) -> Result<DemoFrame> {
+    let mut scene = DemoScene::new(
+        demo,
+        &context.device,
+        &context.instance,
+        context.physical_device,
+        context.command_pool,
+        context.queue,
+        target.render_pass,
+    )?;
+    let extent = target.extent;
+    let mut result = Ok(DemoFrame::Pixels(vec![]));
+    for frame in 0..frames {
+        scene.update(1.0 / 60.0);
+        let current_frame = frame as usize % MAX_FRAMES_IN_FLIGHT as usize;
+        let record = |device: &ash::Device, command_buffer| scene.record(device, command_buffer, current_frame, extent);
+        // rendering blocks, so the frame data of the frame before is free again
+        result = match hasher {
+            Some(hasher) => target
+                .render_hash(context, DEMO_CLEAR_COLOR, hasher, record)
+                .map(DemoFrame::Hash),
+            None => target.render(context, DEMO_CLEAR_COLOR, record).map(DemoFrame::Pixels),
+        };
+        if result.is_err() {
+            break;
+        }
     }
+    scene.destroy(&context.device);
+    Ok(result?)
 }
 
-unsafe fn create_instance(entry: &ash::Entry) -> Result<ash::Instance> {
+/// `validation` loads the Khronos layer, which has to be installed.
+unsafe fn create_instance(entry: &ash::Entry, display: RawDisplayHandle, validation: bool) -> Result<ash::Instance> {
     let app_name = CString::new("window_title").unwrap();
     let engine_name = CString::new("Vulkan Engine").unwrap();
 
-    if validation::ENABLED && !check_validation_support(&entry)? {
-        panic!("Validation layer is requested, but no available");
-    }
-    let debug_utils_create_info = debug_create_info()?;
+    let debug_config = DebugConfig::from_env();
+    let debug_utils_create_info = debug_config.messenger_create_info();
+    let validation_features = debug_config.enabled_validation_features();
+    let validation_features_info = debug::validation_features_info(
+        &validation_features,
+        &debug_utils_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT as *const c_void,
+    );
 
     let app_info = vk::ApplicationInfo::builder()
         .engine_name(&engine_name)
@@ -423,55 +1385,70 @@ unsafe fn create_instance(entry: &ash::Entry) -> Result<ash::Instance> {
         .application_version(version::APPLICATION_VERSION)
         .build();
 
-    let mut extension = vulky::platform::required_extension_names();
+    let mut extensions = ExtensionRequest::new();
+    for name in vulky::platform::required_extension_names(display)? {
+        extensions = extensions.require_name(CStr::from_ptr(name));
+    }
+    // VULKY_SHADER_PRINTF
+    if validation && !validation_features.is_empty() {
+        extensions = extensions.require_name(vk::ExtValidationFeaturesFn::name());
+    }
 
     let layer_names = [CStr::from_bytes_with_nul_unchecked(validation::LAYER_NAME_BYTES)];
     let layers_names_raw: Vec<*const c_char> = layer_names.iter().map(|raw_name| raw_name.as_ptr()).collect();
 
     //macos portability
     let flags = if cfg!(target_os = "macos") && PORTABILITY_MACOS_VERSION >= version::API_VERSION {
-        extension.push(ash::vk::KhrGetPhysicalDeviceProperties2Fn::name().as_ptr());
-        extension.push(ash::vk::KhrPortabilityEnumerationFn::name().as_ptr());
+        extensions = extensions
+            .require_name(ash::vk::KhrGetPhysicalDeviceProperties2Fn::name())
+            .require_name(ash::vk::KhrPortabilityEnumerationFn::name());
         vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR
     } else {
         vk::InstanceCreateFlags::empty()
     };
+    let enabled_layers: &[&CStr] = if validation { &layer_names } else { &[] };
+    let extension = extensions.negotiate_instance(entry, enabled_layers)?.as_ptrs();
 
     let instance_info = vk::InstanceCreateInfo {
         s_type: vk::StructureType::INSTANCE_CREATE_INFO,
-        p_next: if validation::ENABLED {
+        p_next: if !validation {
+            ptr::null()
+        } else if validation_features.is_empty() {
             &debug_utils_create_info as *const vk::DebugUtilsMessengerCreateInfoEXT as *const c_void
         } else {
-            ptr::null()
+            &validation_features_info as *const vk::ValidationFeaturesEXT as *const c_void
         },
         flags,
         p_application_info: &app_info,
-        pp_enabled_layer_names: if validation::ENABLED {
-            println!("validation enabled");
-            layers_names_raw.as_ptr()
-        } else {
-            ptr::null()
-        },
-        enabled_layer_count: if validation::ENABLED { 1 } else { 0 },
+        pp_enabled_layer_names: if validation { layers_names_raw.as_ptr() } else { ptr::null() },
+        enabled_layer_count: if validation { 1 } else { 0 },
         enabled_extension_count: extension.len() as u32,
         pp_enabled_extension_names: extension.as_ptr(),
     };
 
-    let instance = entry.create_instance(&instance_info, None)?;
+    let instance = entry.create_instance(&instance_info, None).map_err(Error::InstanceCreation)?;
     Ok(instance)
 }
 
 unsafe fn create_surface(
-    entry: &Entry,
-    instance: &Instance,
+    instance: &owned::Instance,
     window: &Window,
-) -> Result<(ash::vk::SurfaceKHR, ash::extensions::khr::Surface)> {
-    let surface = platform::create_surface(entry, instance, window)?;
-    let surface_loader = ash::extensions::khr::Surface::new(entry, instance);
+) -> Result<(owned::Surface, ash::extensions::khr::Surface)> {
+    let surface = owned::Surface::new(instance, window)?;
+    let surface_loader = surface.loader().clone();
 
     Ok((surface, surface_loader))
 }
 
+/// The size the swapchain clamps to when the surface leaves its extent to the window.
+fn window_extent(window: &Window) -> vk::Extent2D {
+    let size = window.inner_size();
+    vk::Extent2D {
+        width: size.width,
+        height: size.height,
+    }
+}
+
 unsafe fn check_validation_support(entry: &Entry) -> Result<bool> {
     let layer_properties = entry.enumerate_instance_layer_properties()?;
     let mut is_layer_found = false;
@@ -482,10 +1459,10 @@ unsafe fn check_validation_support(entry: &Entry) -> Result<bool> {
         if s == validation::LAYER_NAME {
             is_layer_found = true;
         }
-        println!("layer: {}", s);
+        log::debug!("layer: {}", s);
     }
     if !is_layer_found {
-        eprintln!("Required Layer is not found");
+        log::warn!("Required layer {} is not found", validation::LAYER_NAME);
         return Ok(false);
     }
 
@@ -495,14 +1472,13 @@ unsafe fn check_validation_support(entry: &Entry) -> Result<bool> {
 fn setup_debug_utils(
     entry: &ash::Entry,
     instance: &ash::Instance,
+    validation: bool,
 ) -> Result<(ash::extensions::ext::DebugUtils, vk::DebugUtilsMessengerEXT)> {
     let debug_utils_loader = ash::extensions::ext::DebugUtils::new(entry, instance);
-    if !validation::ENABLED {
+    if !validation {
         Ok((debug_utils_loader, ash::vk::DebugUtilsMessengerEXT::null()))
     } else {
-        let messenger_ci = debug_create_info()?;
-
-        println!("debug enabled setup");
+        let messenger_ci = DebugConfig::from_env().messenger_create_info();
 
         let utils_messenger = unsafe {
             debug_utils_loader
@@ -512,45 +1488,3 @@ fn setup_debug_utils(
         Ok((debug_utils_loader, utils_messenger))
     }
 }
-
-unsafe extern "system" fn debug_callback(
-    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _p_user_data: *mut c_void,
-) -> vk::Bool32 {
-    let severity = match message_severity {
-        vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE => "[Verbose]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING => "[Warning]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::ERROR => "[Error]",
-        vk::DebugUtilsMessageSeverityFlagsEXT::INFO => "[Info]",
-        _ => "[Unknown]",
-    };
-    let types = match message_type {
-        vk::DebugUtilsMessageTypeFlagsEXT::GENERAL => "[General]",
-        vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE => "[Performance]",
-        vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION => "[Validation]",
-        _ => "[Unknown]",
-    };
-    let message = CStr::from_ptr((*p_callback_data).p_message);
-    println!("[Debug]{}{}{:?}", severity, types, message);
-
-    vk::FALSE
-}
-
-fn debug_create_info() -> Result<DebugUtilsMessengerCreateInfoEXT> {
-    Ok(vk::DebugUtilsMessengerCreateInfoEXT {
-        s_type: vk::StructureType::DEBUG_UTILS_MESSENGER_CREATE_INFO_EXT,
-        p_next: ptr::null(),
-        flags: vk::DebugUtilsMessengerCreateFlagsEXT::empty(),
-        message_severity: DebugUtilsMessageSeverityFlagsEXT::WARNING
-            | DebugUtilsMessageSeverityFlagsEXT::ERROR
-            | DebugUtilsMessageSeverityFlagsEXT::VERBOSE,
-
-        message_type: DebugUtilsMessageTypeFlagsEXT::GENERAL
-            | DebugUtilsMessageTypeFlagsEXT::PERFORMANCE
-            | DebugUtilsMessageTypeFlagsEXT::VALIDATION,
-        pfn_user_callback: Some(debug_callback),
-        p_user_data: ptr::null_mut(),
-    })
-}
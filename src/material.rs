@@ -0,0 +1,726 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    mem::size_of,
+    ops::Range,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use crate::error::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+use nalgebra as glm;
+
+use crate::{
+    buffer::{self, MAX_FRAMES_IN_FLIGHT},
+    mesh::GpuMesh,
+    pipeline::PipelineBuilder,
+    reflect::{self, ShaderBinding},
+    utility,
+};
+
+pub const MAX_MATERIAL_INSTANCES: u32 = 256;
+pub const MAX_MATERIAL_TEXTURES: u32 = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParamType {
+    Float,
+    Int,
+    Vec2,
+    Vec3,
+    Vec4,
+    Mat4,
+}
+
+impl ParamType {
+    /// (alignment, size) following the std140 rules
+    const fn std140(&self) -> (usize, usize) {
+        match self {
+            ParamType::Float | ParamType::Int => (4, 4),
+            ParamType::Vec2 => (8, 8),
+            ParamType::Vec3 => (16, 12),
+            ParamType::Vec4 => (16, 16),
+            ParamType::Mat4 => (16, 64),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParamValue {
+    Float(f32),
+    Int(i32),
+    Vec2(glm::Vector2<f32>),
+    Vec3(glm::Vector3<f32>),
+    Vec4(glm::Vector4<f32>),
+    Mat4(glm::Matrix4<f32>),
+}
+
+impl ParamValue {
+    pub const fn param_type(&self) -> ParamType {
+        match self {
+            ParamValue::Float(_) => ParamType::Float,
+            ParamValue::Int(_) => ParamType::Int,
+            ParamValue::Vec2(_) => ParamType::Vec2,
+            ParamValue::Vec3(_) => ParamType::Vec3,
+            ParamValue::Vec4(_) => ParamType::Vec4,
+            ParamValue::Mat4(_) => ParamType::Mat4,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct ParamField {
+    ty: ParamType,
+    offset: usize,
+}
+
+/// Layout of a uniform block, the shader side has to declare the fields in the same order.
+#[derive(Clone, Debug, Default)]
+pub struct ParameterLayout {
+    fields: HashMap<String, ParamField>,
+    size: usize,
+}
+
+impl ParameterLayout {
+    pub fn new(fields: &[(&str, ParamType)]) -> Self {
+        let mut layout = ParameterLayout::default();
+        for (name, ty) in fields {
+            let (alignment, size) = ty.std140();
+            let offset = layout.size.next_multiple_of(alignment);
+            layout.fields.insert(name.to_string(), ParamField { ty: *ty, offset });
+            layout.size = offset + size;
+        }
+        // a uniform block is padded to the alignment of a vec4
+        layout.size = layout.size.next_multiple_of(16);
+        layout
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn field_type(&self, name: &str) -> Option<ParamType> {
+        self.fields.get(name).map(|field| field.ty)
+    }
+}
+
+/// CPU copy of the uniform values of a material.
+#[derive(Clone, Debug)]
+pub struct ParameterBlock {
+    layout: Arc<ParameterLayout>,
+    data: Vec<u8>,
+}
+
+impl ParameterBlock {
+    pub fn new(layout: Arc<ParameterLayout>) -> Self {
+        let data = vec![0; layout.size.max(16)];
+        Self { layout, data }
+    }
+
+    pub fn set(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        let field = self
+            .layout
+            .fields
+            .get(name)
+            .ok_or_else(|| Error::msg(format!("Unknown material parameter {}", name)))?;
+
+        if field.ty != value.param_type() {
+            return Err(Error::msg(format!(
+                "Material parameter {} is {:?}, got {:?}",
+                name,
+                field.ty,
+                value.param_type()
+            )));
+        }
+
+        let bytes = match &value {
+            ParamValue::Float(v) => utility::as_bytes(v),
+            ParamValue::Int(v) => utility::as_bytes(v),
+            ParamValue::Vec2(v) => utility::as_bytes(v),
+            ParamValue::Vec3(v) => utility::as_bytes(v),
+            ParamValue::Vec4(v) => utility::as_bytes(v),
+            ParamValue::Mat4(v) => utility::as_bytes(v),
+        };
+        self.data[field.offset..field.offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextureBinding {
+    pub image_view: vk::ImageView,
+    pub sampler: vk::Sampler,
+}
+
+/// Size of the per draw push constant, the model matrix.
+pub const TRANSFORM_PUSH_CONSTANT_SIZE: u32 = size_of::<glm::Matrix4<f32>>() as u32;
+
+/// A pipeline together with the layout of its parameters and textures.
+/// set `shared_set_layouts.len()` of the pipeline layout is owned by the material:
+/// binding 0 is the parameter uniform block, binding 1.. are the textures in slot order.
+pub struct Material {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+    set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    set_index: u32,
+    texture_slots: Vec<String>,
+    defaults: ParameterBlock,
+    default_textures: Vec<TextureBinding>,
+    uniform_alignment: u64,
+    /// what the shaders declare, only reflected in debug builds
+    shader_bindings: Vec<ShaderBinding>,
+}
+
+/// The sets, parameters and default textures `Material::new` lays a material out with.
+pub struct MaterialLayout<'a> {
+    /// bound by the renderer before the material set, for example per frame data
+    pub shared_set_layouts: &'a [vk::DescriptorSetLayout],
+    pub parameters: ParameterLayout,
+    /// slot names with the texture bound to them by default
+    pub textures: &'a [(&'a str, TextureBinding)],
+}
+
+impl Material {
+    /// # Safety
+    /// `physical_device` has to be the device of `device`, `render_pass` has to be compatible with `pipeline`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        pipeline: PipelineBuilder,
+        layout: MaterialLayout,
+    ) -> Result<Material> {
+        let MaterialLayout {
+            shared_set_layouts,
+            parameters,
+            textures,
+        } = layout;
+        if textures.len() as u32 > MAX_MATERIAL_TEXTURES {
+            return Err(Error::msg(format!(
+                "A material can have at most {} textures",
+                MAX_MATERIAL_TEXTURES
+            )));
+        }
+
+        let mut bindings = vec![vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: ptr::null(),
+        }];
+        for (index, _) in textures.iter().enumerate() {
+            bindings.push(vk::DescriptorSetLayoutBinding {
+                binding: index as u32 + 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: 1,
+                stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                p_immutable_samplers: ptr::null(),
+            });
+        }
+        let set_index = shared_set_layouts.len() as u32;
+        let shader_bindings = if cfg!(debug_assertions) {
+            let shader_bindings = pipeline.reflect_bindings()?;
+            reflect::validate_set_layout(set_index, &shader_bindings, &bindings).map_err(|error| {
+                let (vertex_shader, fragment_shader) = pipeline.shader_names();
+                Error::shader(
+                    vertex_shader,
+                    format!(
+                        "The material descriptor set doesn't match it and {}:\n{}",
+                        fragment_shader, error
+                    ),
+                )
+            })?;
+            shader_bindings
+        } else {
+            vec![]
+        };
+
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: bindings.len() as u32,
+            p_bindings: bindings.as_ptr(),
+        };
+        let set_layout = device.create_descriptor_set_layout(&set_layout_info, None)?;
+
+        let max_sets = MAX_MATERIAL_INSTANCES * MAX_FRAMES_IN_FLIGHT as u32;
+        let mut pool_sizes = vec![vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::UNIFORM_BUFFER,
+            descriptor_count: max_sets,
+        }];
+        if !textures.is_empty() {
+            pool_sizes.push(vk::DescriptorPoolSize {
+                ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                descriptor_count: max_sets * textures.len() as u32,
+            });
+        }
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            max_sets,
+            pool_size_count: pool_sizes.len() as u32,
+            p_pool_sizes: pool_sizes.as_ptr(),
+        };
+        let descriptor_pool = match device.create_descriptor_pool(&pool_info, None) {
+            Ok(descriptor_pool) => descriptor_pool,
+            Err(e) => {
+                device.destroy_descriptor_set_layout(set_layout, None);
+                return Err(e.into());
+            }
+        };
+
+        let mut set_layouts = shared_set_layouts.to_vec();
+        set_layouts.push(set_layout);
+        let built = pipeline
+            .descriptor_set_layouts(&set_layouts)
+            .push_constant_range(vk::ShaderStageFlags::VERTEX, 0, TRANSFORM_PUSH_CONSTANT_SIZE)
+            .build(device, render_pass);
+        let (pipeline, pipeline_layout) = match built {
+            Ok(built) => built,
+            Err(e) => {
+                device.destroy_descriptor_pool(descriptor_pool, None);
+                device.destroy_descriptor_set_layout(set_layout, None);
+                return Err(e);
+            }
+        };
+
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+
+        Ok(Material {
+            pipeline,
+            pipeline_layout,
+            set_layout,
+            descriptor_pool,
+            set_index,
+            texture_slots: textures.iter().map(|(name, _)| name.to_string()).collect(),
+            defaults: ParameterBlock::new(Arc::new(parameters)),
+            default_textures: textures.iter().map(|(_, binding)| *binding).collect(),
+            uniform_alignment: limits.min_uniform_buffer_offset_alignment.max(1),
+            shader_bindings,
+        })
+    }
+
+    /// Parameter values new instances start with, set them before the material is shared.
+    pub fn set_default(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        self.defaults.set(name, value)
+    }
+
+    /// Set of the pipeline layout the instances are bound at, after the shared sets.
+    pub fn set_index(&self) -> u32 {
+        self.set_index
+    }
+
+    pub fn texture_slot(&self, name: &str) -> Option<usize> {
+        self.texture_slots.iter().position(|slot| slot == name)
+    }
+
+    pub fn parameter_type(&self, name: &str) -> Option<ParamType> {
+        self.defaults.layout.field_type(name)
+    }
+
+    /// # Safety
+    /// No instance of the material may be alive.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.set_layout, None);
+    }
+}
+
+/// Parameters and textures of a `MaterialInstance`, without any of its Vulkan objects.
+#[derive(Clone, Debug)]
+pub struct MaterialState {
+    params: ParameterBlock,
+    textures: Vec<TextureBinding>,
+}
+
+/// Per object overrides of a material, owns its uniform buffer and descriptor sets.
+pub struct MaterialInstance {
+    material: Arc<Material>,
+    params: ParameterBlock,
+    textures: Vec<TextureBinding>,
+    uniform_buffer: vk::Buffer,
+    uniform_memory: vk::DeviceMemory,
+    mapped: *mut u8,
+    /// size of the parameter block for one frame, aligned to minUniformBufferOffsetAlignment
+    frame_stride: u64,
+    descriptor_sets: Vec<vk::DescriptorSet>,
+    /// frames whose uniform data or descriptor set is out of date
+    dirty: [AtomicBool; MAX_FRAMES_IN_FLIGHT as usize],
+}
+
+// the mapped pointer is only written by `flush`, one frame region at a time
+unsafe impl Send for MaterialInstance {}
+unsafe impl Sync for MaterialInstance {}
+
+impl MaterialInstance {
+    /// # Safety
+    /// `material` has to be a material of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        material: Arc<Material>,
+    ) -> VkResult<MaterialInstance> {
+        let params = material.defaults.clone();
+        let block_size = params.bytes().len() as u64;
+        let frame_stride = block_size.next_multiple_of(material.uniform_alignment);
+        let buffer_size = frame_stride * MAX_FRAMES_IN_FLIGHT as u64;
+
+        let (uniform_buffer, uniform_memory) = buffer::create_buffer(
+            device,
+            instance,
+            physical_device,
+            buffer_size,
+            BufferUsageFlags::UNIFORM_BUFFER,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let mapped = device.map_memory(uniform_memory, 0, buffer_size, MemoryMapFlags::empty())? as *mut u8;
+
+        let set_layouts = [material.set_layout; MAX_FRAMES_IN_FLIGHT as usize];
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool: material.descriptor_pool,
+            descriptor_set_count: set_layouts.len() as u32,
+            p_set_layouts: set_layouts.as_ptr(),
+        };
+        let descriptor_sets = device.allocate_descriptor_sets(&alloc_info)?;
+
+        Ok(MaterialInstance {
+            textures: material.default_textures.clone(),
+            material,
+            params,
+            uniform_buffer,
+            uniform_memory,
+            mapped,
+            frame_stride,
+            descriptor_sets,
+            dirty: std::array::from_fn(|_| AtomicBool::new(true)),
+        })
+    }
+
+    pub fn material(&self) -> &Arc<Material> {
+        &self.material
+    }
+
+    /// The set to bind at `Material::set_index` for `current_frame`, flushed or not.
+    pub fn descriptor_set(&self, current_frame: usize) -> vk::DescriptorSet {
+        self.descriptor_sets[current_frame]
+    }
+
+    pub fn set_param(&mut self, name: &str, value: ParamValue) -> Result<()> {
+        self.params.set(name, value)?;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    pub fn set_texture(&mut self, slot: &str, texture: TextureBinding) -> Result<()> {
+        let index = self
+            .material
+            .texture_slot(slot)
+            .ok_or_else(|| Error::msg(format!("Unknown material texture slot {}", slot)))?;
+        self.textures[index] = texture;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    pub fn state(&self) -> MaterialState {
+        MaterialState {
+            params: self.params.clone(),
+            textures: self.textures.clone(),
+        }
+    }
+
+    /// Restores a state taken from an instance of the same material, the next `flush` uploads it.
+    pub fn restore(&mut self, state: MaterialState) -> Result<()> {
+        if !Arc::ptr_eq(&state.params.layout, &self.params.layout) || state.textures.len() != self.textures.len() {
+            return Err(Error::msg("Material state is from a different material"));
+        }
+        self.params = state.params;
+        self.textures = state.textures;
+        self.mark_dirty();
+        Ok(())
+    }
+
+    fn mark_dirty(&mut self) {
+        for dirty in self.dirty.iter_mut() {
+            *dirty.get_mut() = true;
+        }
+    }
+
+    /// Writes the parameters and textures of `current_frame` if they changed,
+    /// the fence of that frame has to be waited on first.
+    /// Takes `&self` so instances shared through an `Arc` can still be flushed by the renderer.
+    ///
+    /// # Safety
+    /// The fence of `current_frame` has to be waited on.
+    pub unsafe fn flush(&self, device: &ash::Device, current_frame: usize) {
+        if !self.dirty[current_frame].swap(false, Ordering::AcqRel) {
+            return;
+        }
+
+        let offset = self.frame_stride * current_frame as u64;
+        let bytes = self.params.bytes();
+        self.mapped
+            .add(offset as usize)
+            .copy_from_nonoverlapping(bytes.as_ptr(), bytes.len());
+
+        let buffer_info = vk::DescriptorBufferInfo {
+            buffer: self.uniform_buffer,
+            offset,
+            range: bytes.len() as u64,
+        };
+        let image_infos: Vec<vk::DescriptorImageInfo> = self
+            .textures
+            .iter()
+            .map(|texture| vk::DescriptorImageInfo {
+                sampler: texture.sampler,
+                image_view: texture.image_view,
+                image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            })
+            .collect();
+
+        let descriptor_set = self.descriptor_sets[current_frame];
+        let mut writes = vec![vk::WriteDescriptorSet {
+            s_type: StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            p_image_info: ptr::null(),
+            p_buffer_info: &buffer_info,
+            p_texel_buffer_view: ptr::null(),
+        }];
+        for (index, image_info) in image_infos.iter().enumerate() {
+            writes.push(vk::WriteDescriptorSet {
+                s_type: StructureType::WRITE_DESCRIPTOR_SET,
+                p_next: ptr::null(),
+                dst_set: descriptor_set,
+                dst_binding: index as u32 + 1,
+                dst_array_element: 0,
+                descriptor_count: 1,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                p_image_info: image_info,
+                p_buffer_info: ptr::null(),
+                p_texel_buffer_view: ptr::null(),
+            });
+        }
+        if cfg!(debug_assertions) {
+            let material = &self.material;
+            if let Err(error) = reflect::validate_descriptor_writes(material.set_index, &material.shader_bindings, &writes) {
+                panic!("Invalid material descriptor writes:\n{}", error);
+            }
+        }
+        device.update_descriptor_sets(&writes, &[]);
+    }
+
+    /// # Safety
+    /// No frame in flight may draw with the instance.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        let _ = device.free_descriptor_sets(self.material.descriptor_pool, &self.descriptor_sets);
+        device.unmap_memory(self.uniform_memory);
+        device.destroy_buffer(self.uniform_buffer, None);
+        device.free_memory(self.uniform_memory, None);
+    }
+}
+
+pub struct DrawCall<'a> {
+    pub mesh: &'a GpuMesh,
+    /// None draws every submesh of the mesh
+    pub submesh: Option<usize>,
+    pub material: &'a MaterialInstance,
+    pub transform: glm::Matrix4<f32>,
+}
+
+/// Draws of a frame expressed as (mesh, material, transform).
+#[derive(Default)]
+pub struct DrawList<'a> {
+    draws: Vec<DrawCall<'a>>,
+}
+
+impl<'a> DrawList<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mesh: &'a GpuMesh, material: &'a MaterialInstance, transform: glm::Matrix4<f32>) {
+        self.draws.push(DrawCall {
+            mesh,
+            submesh: None,
+            material,
+            transform,
+        });
+    }
+
+    pub fn push_submesh(
+        &mut self,
+        mesh: &'a GpuMesh,
+        submesh: usize,
+        material: &'a MaterialInstance,
+        transform: glm::Matrix4<f32>,
+    ) {
+        self.draws.push(DrawCall {
+            mesh,
+            submesh: Some(submesh),
+            material,
+            transform,
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.draws.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.draws.is_empty()
+    }
+
+    /// Records every draw inside the current render pass, sorted to reduce pipeline and descriptor set changes.
+    /// The material instances have to be flushed for `current_frame` before.
+    /// `shared_sets` are bound at set 0.. for every pipeline layout.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside a render pass compatible with the materials.
+    pub unsafe fn record(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        shared_sets: &[vk::DescriptorSet],
+    ) {
+        self.sort();
+        self.record_range(device, command_buffer, current_frame, shared_sets, 0..self.draws.len());
+    }
+
+    /// Pipeline, then material instance, then mesh. Stable, so equal lists pushed in the same order sort the same.
+    pub(crate) fn sort(&mut self) {
+        self.draws.sort_by_key(|draw| {
+            (
+                draw.material.material.pipeline,
+                draw.material as *const MaterialInstance as usize,
+                draw.mesh as *const GpuMesh as usize,
+            )
+        });
+    }
+
+    /// Records `range` of the sorted draws, binding everything they need from scratch.
+    pub(crate) unsafe fn record_range(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        shared_sets: &[vk::DescriptorSet],
+        range: Range<usize>,
+    ) {
+        let mut bound_pipeline = vk::Pipeline::null();
+        let mut bound_instance: *const MaterialInstance = ptr::null();
+        let mut bound_mesh: *const GpuMesh = ptr::null();
+
+        for draw in self.draws[range].iter() {
+            let material = &draw.material.material;
+
+            if material.pipeline != bound_pipeline {
+                device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, material.pipeline);
+                if !shared_sets.is_empty() {
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        material.pipeline_layout,
+                        0,
+                        shared_sets,
+                        &[],
+                    );
+                }
+                bound_pipeline = material.pipeline;
+                bound_instance = ptr::null();
+            }
+
+            if !ptr::eq(draw.material, bound_instance) {
+                device.cmd_bind_descriptor_sets(
+                    command_buffer,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    material.pipeline_layout,
+                    material.set_index,
+                    &[draw.material.descriptor_sets[current_frame]],
+                    &[],
+                );
+                bound_instance = draw.material;
+            }
+
+            if !ptr::eq(draw.mesh, bound_mesh) {
+                draw.mesh.bind(device, command_buffer);
+                bound_mesh = draw.mesh;
+            }
+
+            device.cmd_push_constants(
+                command_buffer,
+                material.pipeline_layout,
+                vk::ShaderStageFlags::VERTEX,
+                0,
+                utility::as_bytes(&draw.transform),
+            );
+
+            match draw.submesh {
+                Some(submesh) => draw.mesh.draw_submesh(device, command_buffer, submesh),
+                None => {
+                    for submesh in 0..draw.mesh.submeshes.len() {
+                        draw.mesh.draw_submesh(device, command_buffer, submesh);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ranges of consecutive draws with the same material instance, with the instance, after `sort`.
+    pub(crate) fn material_runs(&self) -> Vec<(&'a MaterialInstance, Range<usize>)> {
+        let mut runs: Vec<(&'a MaterialInstance, Range<usize>)> = vec![];
+        for (index, draw) in self.draws.iter().enumerate() {
+            match runs.last_mut() {
+                Some((material, range)) if ptr::eq(*material, draw.material) => range.end = index + 1,
+                _ => runs.push((draw.material, index..index + 1)),
+            }
+        }
+        runs
+    }
+
+    /// Hash of everything `record_range` puts into a command buffer for `current_frame`.
+    /// Material parameters aren't part of it, they live in buffers and change without re-recording.
+    pub(crate) fn signature(&self, current_frame: usize, range: Range<usize>) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for draw in self.draws[range].iter() {
+            (draw.mesh as *const GpuMesh as usize).hash(&mut hasher);
+            draw.mesh.vertex_buffer.hash(&mut hasher);
+            draw.mesh.index_buffer.hash(&mut hasher);
+            draw.submesh.hash(&mut hasher);
+            draw.material.material.pipeline.hash(&mut hasher);
+            draw.material.descriptor_sets[current_frame].hash(&mut hasher);
+            for value in draw.transform.iter() {
+                value.to_bits().hash(&mut hasher);
+            }
+        }
+        hasher.finish()
+    }
+
+    pub fn clear(&mut self) {
+        self.draws.clear();
+    }
+}
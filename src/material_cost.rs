@@ -0,0 +1,333 @@
+use std::{collections::HashMap, fmt};
+
+use ash::{prelude::VkResult, vk};
+use nalgebra as glm;
+
+use crate::{
+    buffer::MAX_FRAMES_IN_FLIGHT,
+    material::{DrawList, MaterialInstance},
+    query::{QueryPool, ReadMode, Timestamp},
+    ui::{Rect, RoundedRect, UiBatch},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaterialCostSettings {
+    /// material instances measured per frame, the draws of any further ones are recorded unmeasured
+    pub max_materials: u32,
+    /// counts fragment shader invocations per material, needs the pipelineStatisticsQuery feature
+    pub fragment_invocations: bool,
+}
+
+impl Default for MaterialCostSettings {
+    fn default() -> Self {
+        Self {
+            max_materials: 256,
+            fragment_invocations: false,
+        }
+    }
+}
+
+impl MaterialCostSettings {
+    pub fn max_materials(mut self, max_materials: u32) -> Self {
+        self.max_materials = max_materials;
+        self
+    }
+
+    pub fn fragment_invocations(mut self, enabled: bool) -> Self {
+        self.fragment_invocations = enabled;
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MaterialCost {
+    /// stable for the lifetime of the profiler, given in the order the instances were first drawn
+    pub id: u32,
+    pub name: String,
+    pub draws: u32,
+    pub gpu_ms: f64,
+    pub fragment_invocations: Option<u64>,
+}
+
+/// Costs of the material instances of one frame, most expensive first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MaterialCostReport {
+    pub materials: Vec<MaterialCost>,
+    /// time of every measured draw together
+    pub total_ms: f64,
+    /// draws past `MaterialCostSettings::max_materials`
+    pub unmeasured_draws: u32,
+}
+
+impl MaterialCostReport {
+    /// Share of the measured time `cost` took, 0.0 to 1.0.
+    pub fn share(&self, cost: &MaterialCost) -> f64 {
+        if self.total_ms > 0.0 {
+            cost.gpu_ms / self.total_ms
+        } else {
+            0.0
+        }
+    }
+
+    /// Draws a bar per material into `rect`, in report order, long and red for the expensive ones.
+    /// The UI has no text, the rows line up with the table the report prints.
+    pub fn push_heatmap(&self, batch: &mut UiBatch, rect: Rect) {
+        if self.materials.is_empty() {
+            return;
+        }
+        let row_height = rect.height / self.materials.len() as f32;
+        let max_ms = self.materials[0].gpu_ms.max(f64::EPSILON);
+        batch.push_rounded_rect(&RoundedRect::new(rect, 4.0, glm::Vector4::new(0.0, 0.0, 0.0, 0.6)));
+        for (row, cost) in self.materials.iter().enumerate() {
+            let heat = (cost.gpu_ms / max_ms) as f32;
+            let bar = Rect::new(
+                rect.x,
+                rect.y + row as f32 * row_height,
+                (rect.width * heat).max(1.0),
+                (row_height - 1.0).max(1.0),
+            );
+            batch.push_rounded_rect(&RoundedRect::new(bar, 0.0, heat_color(heat)));
+        }
+    }
+}
+
+/// Green for cheap through yellow to red for the most expensive.
+fn heat_color(heat: f32) -> glm::Vector4<f32> {
+    let heat = heat.clamp(0.0, 1.0);
+    glm::Vector4::new((heat * 2.0).min(1.0), ((1.0 - heat) * 2.0).min(1.0), 0.0, 0.9)
+}
+
+impl fmt::Display for MaterialCostReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Material costs, {:.3} ms measured", self.total_ms)?;
+        for cost in self.materials.iter() {
+            write!(
+                f,
+                "  #{:<4} {:<24} {:>5} draws {:>8.3} ms {:>5.1}%",
+                cost.id,
+                cost.name,
+                cost.draws,
+                cost.gpu_ms,
+                self.share(cost) * 100.0
+            )?;
+            if let Some(invocations) = cost.fragment_invocations {
+                write!(f, " {:>10} fragments", invocations)?;
+            }
+            writeln!(f)?;
+        }
+        if self.unmeasured_draws > 0 {
+            writeln!(
+                f,
+                "  {} draws past the material limit weren't measured",
+                self.unmeasured_draws
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Queries of one frame in flight.
+struct FrameQueries {
+    timestamps: QueryPool,
+    statistics: Option<QueryPool>,
+    /// instance key and draw count of every measured run
+    runs: Vec<(usize, u32)>,
+    unmeasured_draws: u32,
+}
+
+/// Debug mode that estimates what each material instance costs on the GPU. Draws are recorded grouped by
+/// instance with a timestamp between the groups, so a group's time is the slice from the end of the group
+/// before to its own end. GPUs overlap neighboring draws, so the slices are estimates, fragment invocation
+/// counts are exact. Grouping rebinds the pipeline per instance, the measured frame is a little slower.
+/// Like `GpuProfiler` the report lags `MAX_FRAMES_IN_FLIGHT` frames behind.
+pub struct MaterialCostProfiler {
+    settings: MaterialCostSettings,
+    frames: Vec<FrameQueries>,
+    current: usize,
+    ids: HashMap<usize, u32>,
+    names: HashMap<usize, String>,
+    report: Option<MaterialCostReport>,
+}
+
+impl MaterialCostProfiler {
+    /// None when the queue family of `queue_family` has no timestamps.
+    ///
+    /// # Safety
+    /// `queue_family` has to be a family of `device`, the command buffers of the profiler are submitted to it.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        queue_family: u32,
+        settings: MaterialCostSettings,
+    ) -> VkResult<Option<MaterialCostProfiler>> {
+        let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT as usize);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            // every frame asks the same family, only the first can come back empty
+            let Some(timestamps) =
+                QueryPool::timestamps(device, instance, physical_device, queue_family, settings.max_materials + 1)?
+            else {
+                return Ok(None);
+            };
+            let statistics = if settings.fragment_invocations {
+                Some(QueryPool::pipeline_statistics(
+                    device,
+                    settings.max_materials,
+                    vk::QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+                )?)
+            } else {
+                None
+            };
+            frames.push(FrameQueries {
+                timestamps,
+                statistics,
+                runs: vec![],
+                unmeasured_draws: 0,
+            });
+        }
+
+        Ok(Some(MaterialCostProfiler {
+            settings,
+            frames,
+            current: 0,
+            ids: HashMap::new(),
+            names: HashMap::new(),
+            report: None,
+        }))
+    }
+
+    /// Name `material` is listed under, unnamed instances show up as "material #<id>".
+    pub fn set_name(&mut self, material: &MaterialInstance, name: &str) {
+        self.names.insert(Self::key(material), name.to_owned());
+    }
+
+    fn key(material: &MaterialInstance) -> usize {
+        material as *const MaterialInstance as usize
+    }
+
+    /// Reads the costs `frame_index` measured last time and resets its queries on `command_buffer`.
+    /// Call it after waiting on the fence of the frame, outside of a render pass.
+    ///
+    /// # Safety
+    /// The fence of frame `frame_index` has to be waited on.
+    pub unsafe fn begin_frame(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_index: usize,
+    ) -> VkResult<()> {
+        self.current = frame_index;
+        let frame = &mut self.frames[frame_index];
+        if !frame.runs.is_empty() {
+            let timestamps: Vec<Timestamp> = frame
+                .timestamps
+                .read_timestamps(device, 0, frame.runs.len() as u32 + 1, ReadMode::Wait)?
+                .into_iter()
+                .map(|timestamp| timestamp.ready().unwrap_or_default())
+                .collect();
+            let invocations: Vec<u64> = match frame.statistics.as_ref() {
+                Some(statistics) => statistics
+                    .read_statistics(device, 0, frame.runs.len() as u32, ReadMode::Wait)?
+                    .into_iter()
+                    .map(|statistics| statistics.ready().and_then(|s| s.fragment_shader_invocations).unwrap_or(0))
+                    .collect(),
+                None => vec![0; frame.runs.len()],
+            };
+
+            let mut costs: HashMap<usize, MaterialCost> = HashMap::new();
+            for (run, &(key, draws)) in frame.runs.iter().enumerate() {
+                let (start, end) = (timestamps[run], timestamps[run + 1]);
+                let id = self.ids[&key];
+                let cost = costs.entry(key).or_insert_with(|| MaterialCost {
+                    id,
+                    name: self.names.get(&key).cloned().unwrap_or_else(|| format!("material #{}", id)),
+                    draws: 0,
+                    gpu_ms: 0.0,
+                    fragment_invocations: frame.statistics.as_ref().map(|_| 0),
+                });
+                cost.draws += draws;
+                cost.gpu_ms += frame.timestamps.duration_ms(start, end);
+                if let Some(fragments) = cost.fragment_invocations.as_mut() {
+                    *fragments += invocations[run];
+                }
+            }
+            let mut materials: Vec<MaterialCost> = costs.into_values().collect();
+            materials.sort_by(|a, b| b.gpu_ms.total_cmp(&a.gpu_ms).then(a.id.cmp(&b.id)));
+            self.report = Some(MaterialCostReport {
+                total_ms: materials.iter().map(|cost| cost.gpu_ms).sum(),
+                materials,
+                unmeasured_draws: frame.unmeasured_draws,
+            });
+        }
+        frame.runs.clear();
+        frame.unmeasured_draws = 0;
+
+        frame.timestamps.cmd_reset_all(device, command_buffer);
+        if let Some(statistics) = frame.statistics.as_ref() {
+            statistics.cmd_reset_all(device, command_buffer);
+        }
+        Ok(())
+    }
+
+    /// `DrawList::record` with a timestamp, and a fragment count, around the draws of each material instance.
+    /// Draws of a list recorded across several calls in a frame are added up per instance.
+    ///
+    /// # Safety
+    /// As for `DrawList::record`, `command_buffer` has to be submitted to the family of `new`.
+    pub unsafe fn record(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        draw_list: &mut DrawList,
+        shared_sets: &[vk::DescriptorSet],
+    ) {
+        draw_list.sort();
+        let frame = &mut self.frames[self.current];
+        for (material, range) in draw_list.material_runs() {
+            let run = frame.runs.len() as u32;
+            if run >= self.settings.max_materials {
+                frame.unmeasured_draws += range.len() as u32;
+                draw_list.record_range(device, command_buffer, current_frame, shared_sets, range);
+                continue;
+            }
+            if run == 0 {
+                frame
+                    .timestamps
+                    .cmd_write_timestamp(device, command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, 0);
+            }
+            let key = Self::key(material);
+            let next_id = self.ids.len() as u32;
+            self.ids.entry(key).or_insert(next_id);
+
+            if let Some(statistics) = frame.statistics.as_ref() {
+                statistics.cmd_begin(device, command_buffer, run, vk::QueryControlFlags::empty());
+            }
+            let draws = range.len() as u32;
+            draw_list.record_range(device, command_buffer, current_frame, shared_sets, range);
+            if let Some(statistics) = frame.statistics.as_ref() {
+                statistics.cmd_end(device, command_buffer, run);
+            }
+            frame
+                .timestamps
+                .cmd_write_timestamp(device, command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, run + 1);
+            frame.runs.push((key, draws));
+        }
+    }
+
+    /// Costs of the newest frame that finished on the GPU.
+    pub fn report(&self) -> Option<&MaterialCostReport> {
+        self.report.as_ref()
+    }
+
+    /// # Safety
+    /// No submission writing the queries of the profiler may be pending.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for frame in self.frames.iter() {
+            frame.timestamps.destroy(device);
+            if let Some(statistics) = frame.statistics.as_ref() {
+                statistics.destroy(device);
+            }
+        }
+    }
+}
@@ -0,0 +1,630 @@
+use std::{collections::HashMap, ptr};
+
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+
+use crate::buffer::{find_memory_type, MAX_FRAMES_IN_FLIGHT};
+
+/// Size of the device memory blocks allocations are placed in, bigger allocations get their own block.
+pub const BLOCK_SIZE: vk::DeviceSize = 64 * 1024 * 1024;
+
+/// Handle to an allocation of `GpuAllocator`, stays the same when the allocation is moved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AllocationId(u64);
+
+/// Where an allocation currently lives, may change after `GpuAllocator::finish_defragment`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Allocation {
+    pub id: AllocationId,
+    pub memory: vk::DeviceMemory,
+    pub offset: vk::DeviceSize,
+    pub size: vk::DeviceSize,
+}
+
+struct Block {
+    memory: vk::DeviceMemory,
+    memory_type: u32,
+    size: vk::DeviceSize,
+    /// free (offset, size) ranges sorted by offset
+    free: Vec<(vk::DeviceSize, vk::DeviceSize)>,
+    used: vk::DeviceSize,
+    allocations: usize,
+}
+
+impl Block {
+    fn allocate(&mut self, size: vk::DeviceSize, alignment: vk::DeviceSize) -> Option<vk::DeviceSize> {
+        for index in 0..self.free.len() {
+            let (start, free_size) = self.free[index];
+            let offset = start.next_multiple_of(alignment);
+            let padding = offset - start;
+            if padding + size > free_size {
+                continue;
+            }
+
+            // the padding before and the rest after stay free
+            let rest = free_size - padding - size;
+            self.free.remove(index);
+            if rest > 0 {
+                self.free.insert(index, (offset + size, rest));
+            }
+            if padding > 0 {
+                self.free.insert(index, (start, padding));
+            }
+            self.used += size;
+            self.allocations += 1;
+            return Some(offset);
+        }
+        None
+    }
+
+    fn free(&mut self, offset: vk::DeviceSize, size: vk::DeviceSize) {
+        let index = self.free.partition_point(|(start, _)| *start < offset);
+        self.free.insert(index, (offset, size));
+
+        // merges with the following and the previous range
+        if index + 1 < self.free.len() && offset + size == self.free[index + 1].0 {
+            self.free[index].1 += self.free[index + 1].1;
+            self.free.remove(index + 1);
+        }
+        if index > 0 && self.free[index - 1].0 + self.free[index - 1].1 == offset {
+            self.free[index - 1].1 += self.free[index].1;
+            self.free.remove(index);
+        }
+        self.used -= size;
+        self.allocations -= 1;
+    }
+
+    fn largest_free(&self) -> vk::DeviceSize {
+        self.free.iter().map(|(_, size)| *size).max().unwrap_or(0)
+    }
+}
+
+/// A buffer the allocator may move to another block, it has TRANSFER_SRC and TRANSFER_DST usage.
+struct MovableBuffer {
+    buffer: vk::Buffer,
+    size: vk::DeviceSize,
+    usage: vk::BufferUsageFlags,
+}
+
+struct AllocationInfo {
+    block: usize,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    alignment: vk::DeviceSize,
+    movable: Option<MovableBuffer>,
+}
+
+struct PendingDestroy {
+    buffer: vk::Buffer,
+    block: usize,
+    offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+    frames_left: u32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AllocatorStats {
+    pub blocks: usize,
+    pub block_bytes: vk::DeviceSize,
+    pub used_bytes: vk::DeviceSize,
+    pub allocations: usize,
+}
+
+/// A buffer `GpuAllocator::create_buffer` creates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BufferDesc {
+    pub size: vk::DeviceSize,
+    pub usage: vk::BufferUsageFlags,
+    pub properties: vk::MemoryPropertyFlags,
+    pub movable: bool,
+}
+
+/// One movable buffer being copied to its new place.
+pub struct Move {
+    pub id: AllocationId,
+    new_buffer: vk::Buffer,
+    new_block: usize,
+    new_offset: vk::DeviceSize,
+    size: vk::DeviceSize,
+}
+
+/// Copies recorded by `GpuAllocator::defragment`, pass them to `finish_defragment` once they executed.
+#[derive(Default)]
+pub struct Defragmentation {
+    pub moves: Vec<Move>,
+    /// bytes being copied
+    pub bytes: vk::DeviceSize,
+}
+
+impl Defragmentation {
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+}
+
+/// Sub-allocates device memory from big blocks instead of one `vkAllocateMemory` per resource.
+/// Long running sessions with a lot of streaming leave holes in the blocks, `defragment` moves
+/// movable buffers out of the emptiest block so it can be freed.
+pub struct GpuAllocator {
+    blocks: Vec<Option<Block>>,
+    allocations: HashMap<AllocationId, AllocationInfo>,
+    next_id: u64,
+    /// every allocation is aligned to it, so linear and optimal resources never share a page
+    granularity: vk::DeviceSize,
+    /// buffers replaced by a defragmentation with their old range and the number of `maintain` calls
+    /// until no frame in flight can use them
+    pending_destroy: Vec<PendingDestroy>,
+}
+
+impl GpuAllocator {
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance`, every later call has to pass the same device.
+    pub unsafe fn new(instance: &ash::Instance, physical_device: vk::PhysicalDevice) -> Self {
+        let properties = instance.get_physical_device_properties(physical_device);
+        Self {
+            blocks: vec![],
+            allocations: HashMap::new(),
+            next_id: 0,
+            granularity: properties.limits.buffer_image_granularity.max(1),
+            pending_destroy: vec![],
+        }
+    }
+
+    unsafe fn allocate_in(
+        &mut self,
+        device: &ash::Device,
+        memory_type: u32,
+        size: vk::DeviceSize,
+        alignment: vk::DeviceSize,
+        skip_block: Option<usize>,
+    ) -> VkResult<(usize, vk::DeviceSize)> {
+        let alignment = alignment.max(self.granularity);
+        let size = size.next_multiple_of(self.granularity);
+
+        for (index, block) in self.blocks.iter_mut().enumerate() {
+            if Some(index) == skip_block {
+                continue;
+            }
+            if let Some(block) = block.as_mut().filter(|block| block.memory_type == memory_type) {
+                if let Some(offset) = block.allocate(size, alignment) {
+                    return Ok((index, offset));
+                }
+            }
+        }
+
+        let block_size = size.max(BLOCK_SIZE);
+        let alloc_info = vk::MemoryAllocateInfo {
+            s_type: StructureType::MEMORY_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            allocation_size: block_size,
+            memory_type_index: memory_type,
+        };
+        let memory = device.allocate_memory(&alloc_info, None)?;
+
+        let mut block = Block {
+            memory,
+            memory_type,
+            size: block_size,
+            free: vec![(0, block_size)],
+            used: 0,
+            allocations: 0,
+        };
+        let offset = block.allocate(size, alignment).unwrap();
+
+        let index = match self.blocks.iter().position(|block| block.is_none()) {
+            Some(index) => {
+                self.blocks[index] = Some(block);
+                index
+            }
+            None => {
+                self.blocks.push(Some(block));
+                self.blocks.len() - 1
+            }
+        };
+        Ok((index, offset))
+    }
+
+    /// Memory for `requirements`, bind it at `Allocation::offset` of `Allocation::memory`.
+    ///
+    /// # Safety
+    /// The allocation has to be given back with `free` before `destroy`, `requirements` have to be of `device`.
+    pub unsafe fn allocate(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> VkResult<Allocation> {
+        let memory_type = find_memory_type(requirements.memory_type_bits, properties, physical_device, instance);
+        let (block, offset) = self.allocate_in(device, memory_type, requirements.size, requirements.alignment, None)?;
+
+        let id = AllocationId(self.next_id);
+        self.next_id += 1;
+        self.allocations.insert(
+            id,
+            AllocationInfo {
+                block,
+                offset,
+                size: requirements.size.next_multiple_of(self.granularity),
+                alignment: requirements.alignment,
+                movable: None,
+            },
+        );
+        Ok(self.allocation(id))
+    }
+
+    pub fn allocation(&self, id: AllocationId) -> Allocation {
+        let info = &self.allocations[&id];
+        Allocation {
+            id,
+            memory: self.blocks[info.block].as_ref().unwrap().memory,
+            offset: info.offset,
+            size: info.size,
+        }
+    }
+
+    /// The range is reused right away, the GPU must be done with the resource bound to it.
+    pub fn free(&mut self, id: AllocationId) {
+        if let Some(info) = self.allocations.remove(&id) {
+            self.blocks[info.block].as_mut().unwrap().free(info.offset, info.size);
+        }
+    }
+
+    unsafe fn create_raw_buffer(
+        device: &ash::Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+    ) -> VkResult<vk::Buffer> {
+        let buffer_info = vk::BufferCreateInfo {
+            s_type: StructureType::BUFFER_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::BufferCreateFlags::empty(),
+            size,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            queue_family_index_count: 0,
+            p_queue_family_indices: ptr::null(),
+        };
+        device.create_buffer(&buffer_info, None)
+    }
+
+    /// Creates a buffer bound to allocator memory. Movable buffers may be relocated by `defragment`,
+    /// so they must be looked up with `buffer` when recording instead of keeping the handle, and they
+    /// must not stay mapped.
+    ///
+    /// # Safety
+    /// As for `allocate`, the buffer has to be destroyed with `destroy_buffer`.
+    pub unsafe fn create_buffer(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        desc: BufferDesc,
+    ) -> VkResult<(vk::Buffer, AllocationId)> {
+        let BufferDesc {
+            size,
+            usage,
+            properties,
+            movable,
+        } = desc;
+        let usage = if movable {
+            usage | vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST
+        } else {
+            usage
+        };
+        let buffer = Self::create_raw_buffer(device, size, usage)?;
+        let requirements = device.get_buffer_memory_requirements(buffer);
+        let allocation = self.allocate(device, instance, physical_device, requirements, properties)?;
+        device.bind_buffer_memory(buffer, allocation.memory, allocation.offset)?;
+
+        if movable {
+            self.allocations.get_mut(&allocation.id).unwrap().movable = Some(MovableBuffer { buffer, size, usage });
+        }
+        Ok((buffer, allocation.id))
+    }
+
+    /// The current handle of a buffer from `create_buffer`, None for buffers that aren't movable.
+    pub fn buffer(&self, id: AllocationId) -> Option<vk::Buffer> {
+        self.allocations[&id].movable.as_ref().map(|movable| movable.buffer)
+    }
+
+    /// Destroys a buffer from `create_buffer` and frees its memory, the GPU must be done with it.
+    ///
+    /// # Safety
+    /// `buffer` and `id` have to come from one `create_buffer`.
+    pub unsafe fn destroy_buffer(&mut self, device: &ash::Device, buffer: vk::Buffer, id: AllocationId) {
+        let buffer = self.buffer(id).unwrap_or(buffer);
+        device.destroy_buffer(buffer, None);
+        self.free(id);
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats::default();
+        for block in self.blocks.iter().flatten() {
+            stats.blocks += 1;
+            stats.block_bytes += block.size;
+            stats.used_bytes += block.used;
+            stats.allocations += block.allocations;
+        }
+        stats
+    }
+
+    /// 0 when the free memory of every block is in one piece, towards 1 the more it is split up.
+    pub fn fragmentation(&self) -> f32 {
+        let (free, largest) = self.blocks.iter().flatten().fold((0, 0), |(free, largest), block| {
+            (free + block.size - block.used, largest + block.largest_free())
+        });
+        if free == 0 {
+            0.0
+        } else {
+            1.0 - largest as f32 / free as f32
+        }
+    }
+
+    /// Records copies moving the movable buffers out of the least used block, up to `max_bytes` per call.
+    /// Call it at idle moments with a command buffer of the transfer queue, nothing may write the moved
+    /// buffers until `finish_defragment`. On an error nothing is moved and the command buffer must be reset
+    /// instead of submitted.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording on the transfer queue, outside of a render pass.
+    pub unsafe fn defragment(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        max_bytes: vk::DeviceSize,
+    ) -> VkResult<Defragmentation> {
+        let mut defragmentation = Defragmentation::default();
+
+        // blocks holding only movable allocations can be emptied completely
+        let source = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| block.as_ref().map(|block| (index, block)))
+            .filter(|(index, block)| {
+                let movable = self
+                    .allocations
+                    .values()
+                    .filter(|info| info.block == *index && info.movable.is_some())
+                    .count();
+                // ranges of buffers waiting for `maintain` count as allocations too
+                let pending = self.pending_destroy.iter().filter(|pending| pending.block == *index).count();
+                movable > 0 && movable + pending == block.allocations
+            })
+            .min_by_key(|(_, block)| block.used)
+            .map(|(index, _)| index);
+        let Some(source) = source else {
+            return Ok(defragmentation);
+        };
+        let memory_type = self.blocks[source].as_ref().unwrap().memory_type;
+
+        let mut candidates: Vec<AllocationId> = self
+            .allocations
+            .iter()
+            .filter(|(_, info)| info.block == source)
+            .map(|(id, _)| *id)
+            .collect();
+        candidates.sort();
+
+        for id in candidates {
+            let info = &self.allocations[&id];
+            if defragmentation.bytes + info.size > max_bytes {
+                break;
+            }
+            let (size, alignment) = (info.size, info.alignment);
+
+            // a move that would need a new block doesn't reduce the number of blocks
+            if !self.blocks.iter().enumerate().any(|(index, block)| {
+                index != source
+                    && block
+                        .as_ref()
+                        .is_some_and(|block| block.memory_type == memory_type && block.largest_free() >= size + alignment)
+            }) {
+                break;
+            }
+
+            let m = match self.record_move(device, command_buffer, id, memory_type, source) {
+                Ok(m) => m,
+                Err(err) => {
+                    for m in defragmentation.moves {
+                        self.discard_move(device, m);
+                    }
+                    return Err(err);
+                }
+            };
+            defragmentation.bytes += size;
+            defragmentation.moves.push(m);
+        }
+        Ok(defragmentation)
+    }
+
+    /// Reserves the new range of `id` outside of `source` and records the copy into a buffer bound to it.
+    unsafe fn record_move(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        id: AllocationId,
+        memory_type: u32,
+        source: usize,
+    ) -> VkResult<Move> {
+        let info = &self.allocations[&id];
+        let (size, alignment) = (info.size, info.alignment);
+        let movable = info.movable.as_ref().unwrap();
+        let (old_buffer, buffer_size, usage) = (movable.buffer, movable.size, movable.usage);
+        let (new_block, new_offset) = self.allocate_in(device, memory_type, size, alignment, Some(source))?;
+        let free_range = |allocator: &mut Self| allocator.blocks[new_block].as_mut().unwrap().free(new_offset, size);
+
+        let new_buffer = match Self::create_raw_buffer(device, buffer_size, usage) {
+            Ok(buffer) => buffer,
+            Err(err) => {
+                free_range(self);
+                return Err(err);
+            }
+        };
+        let memory = self.blocks[new_block].as_ref().unwrap().memory;
+        if let Err(err) = device.bind_buffer_memory(new_buffer, memory, new_offset) {
+            device.destroy_buffer(new_buffer, None);
+            free_range(self);
+            return Err(err);
+        }
+
+        let region = vk::BufferCopy {
+            src_offset: 0,
+            dst_offset: 0,
+            size: buffer_size,
+        };
+        device.cmd_copy_buffer(command_buffer, old_buffer, new_buffer, &[region]);
+        Ok(Move {
+            id,
+            new_buffer,
+            new_block,
+            new_offset,
+            size,
+        })
+    }
+
+    /// Destroys the new buffer of a move that won't be finished and frees its new range.
+    unsafe fn discard_move(&mut self, device: &ash::Device, m: Move) {
+        device.destroy_buffer(m.new_buffer, None);
+        self.blocks[m.new_block].as_mut().unwrap().free(m.new_offset, m.size);
+    }
+
+    /// Switches the moved allocations to their new buffers, call it after the copies executed.
+    /// The old buffers are destroyed by `maintain` once no frame in flight can use them.
+    /// Queues other than the transfer queue have to wait for the copies, a fence wait does that.
+    ///
+    /// # Safety
+    /// `defragmentation` has to come from `defragment` of this allocator and its copies have to be finished.
+    pub unsafe fn finish_defragment(&mut self, device: &ash::Device, defragmentation: Defragmentation) {
+        for m in defragmentation.moves {
+            let Some(info) = self.allocations.get_mut(&m.id) else {
+                // the buffer was destroyed while it was copied
+                self.discard_move(device, m);
+                continue;
+            };
+            let (old_block, old_offset, size) = (info.block, info.offset, info.size);
+            info.block = m.new_block;
+            info.offset = m.new_offset;
+            let movable = info.movable.as_mut().unwrap();
+            let old_buffer = std::mem::replace(&mut movable.buffer, m.new_buffer);
+
+            self.pending_destroy.push(PendingDestroy {
+                buffer: old_buffer,
+                block: old_block,
+                offset: old_offset,
+                size,
+                frames_left: MAX_FRAMES_IN_FLIGHT as u32,
+            });
+        }
+    }
+
+    /// Destroys replaced buffers and frees empty blocks, call it once per frame after waiting on the frame's fence.
+    ///
+    /// # Safety
+    /// The fence of the frame has to be waited on.
+    pub unsafe fn maintain(&mut self, device: &ash::Device) {
+        let blocks = &mut self.blocks;
+        self.pending_destroy.retain_mut(|pending| {
+            pending.frames_left -= 1;
+            if pending.frames_left == 0 {
+                device.destroy_buffer(pending.buffer, None);
+                blocks[pending.block].as_mut().unwrap().free(pending.offset, pending.size);
+            }
+            pending.frames_left > 0
+        });
+
+        for block in self.blocks.iter_mut() {
+            if block.as_ref().is_some_and(|block| block.allocations == 0) {
+                device.free_memory(block.take().unwrap().memory, None);
+            }
+        }
+    }
+
+    /// Frees every block, resources bound to them have to be destroyed already.
+    ///
+    /// # Safety
+    /// The device has to be idle.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for pending in self.pending_destroy.drain(..) {
+            device.destroy_buffer(pending.buffer, None);
+        }
+        for block in self.blocks.drain(..).flatten() {
+            device.free_memory(block.memory, None);
+        }
+        self.allocations.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(size: vk::DeviceSize) -> Block {
+        Block {
+            memory: vk::DeviceMemory::null(),
+            memory_type: 0,
+            size,
+            free: vec![(0, size)],
+            used: 0,
+            allocations: 0,
+        }
+    }
+
+    #[test]
+    fn alloc_free_round_trip_leaves_one_range() {
+        let mut block = block(1024);
+        let a = block.allocate(256, 1).unwrap();
+        let b = block.allocate(256, 1).unwrap();
+        assert_eq!((a, b), (0, 256));
+        assert_eq!(block.free, vec![(512, 512)]);
+        assert_eq!((block.used, block.allocations), (512, 2));
+
+        block.free(a, 256);
+        block.free(b, 256);
+        assert_eq!(block.free, vec![(0, 1024)]);
+        assert_eq!((block.used, block.allocations), (0, 0));
+    }
+
+    #[test]
+    fn free_merges_with_previous_and_following_range() {
+        let mut block = block(768);
+        let offsets: Vec<_> = (0..3).map(|_| block.allocate(256, 1).unwrap()).collect();
+        assert!(block.free.is_empty());
+
+        block.free(offsets[0], 256);
+        block.free(offsets[2], 256);
+        assert_eq!(block.free, vec![(0, 256), (512, 256)]);
+        block.free(offsets[1], 256);
+        assert_eq!(block.free, vec![(0, 768)]);
+        assert_eq!(block.largest_free(), 768);
+    }
+
+    #[test]
+    fn alignment_padding_stays_free() {
+        let mut block = block(1024);
+        assert_eq!(block.allocate(8, 1), Some(0));
+        assert_eq!(block.allocate(64, 256), Some(256));
+        assert_eq!(block.free, vec![(8, 248), (320, 704)]);
+
+        // the padding is handed out again to allocations that fit into it
+        assert_eq!(block.allocate(100, 4), Some(8));
+        assert_eq!(block.free, vec![(108, 148), (320, 704)]);
+
+        block.free(256, 64);
+        assert_eq!(block.free, vec![(108, 916)]);
+    }
+
+    #[test]
+    fn allocation_that_does_not_fit_fails() {
+        let mut block = block(512);
+        assert_eq!(block.allocate(256, 1), Some(0));
+        assert_eq!(block.allocate(8, 512), None);
+        assert_eq!(block.allocate(257, 1), None);
+        assert_eq!(block.allocate(256, 256), Some(256));
+        assert_eq!(block.largest_free(), 0);
+    }
+}
@@ -0,0 +1,417 @@
+use std::{collections::BTreeMap, mem::offset_of, path::Path};
+
+use crate::error::Result;
+use ash::{prelude::VkResult, vk};
+use nalgebra as glm;
+
+use crate::buffer;
+
+/// Uv sets a vertex carries, materials pick one per texture.
+pub const UV_SETS: usize = 4;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct MeshVertex {
+    pub position: glm::Vector3<f32>,
+    pub normal: glm::Vector3<f32>,
+    /// sets a mesh doesn't have repeat the first one
+    pub uvs: [glm::Vector2<f32>; UV_SETS],
+    /// linear rgba, multiplies the base color
+    pub color: glm::Vector4<f32>,
+}
+
+impl MeshVertex {
+    /// White vertex with `uv` in every set.
+    pub fn new(position: glm::Vector3<f32>, normal: glm::Vector3<f32>, uv: glm::Vector2<f32>) -> Self {
+        Self {
+            position,
+            normal,
+            uvs: [uv; UV_SETS],
+            color: glm::Vector4::repeat(1.0),
+        }
+    }
+
+    pub const fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: std::mem::size_of::<MeshVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    const fn uv_offset(set: usize) -> u32 {
+        (offset_of!(MeshVertex, uvs) + set * std::mem::size_of::<glm::Vector2<f32>>()) as u32
+    }
+
+    /// Locations 0 position, 1 normal, 2 to 5 the uv sets and 6 color.
+    pub const fn get_input_attribute_description() -> [vk::VertexInputAttributeDescription; 7] {
+        [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(MeshVertex, position) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32B32_SFLOAT,
+                offset: offset_of!(MeshVertex, normal) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: Self::uv_offset(0),
+            },
+            vk::VertexInputAttributeDescription {
+                location: 3,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: Self::uv_offset(1),
+            },
+            vk::VertexInputAttributeDescription {
+                location: 4,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: Self::uv_offset(2),
+            },
+            vk::VertexInputAttributeDescription {
+                location: 5,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: Self::uv_offset(3),
+            },
+            vk::VertexInputAttributeDescription {
+                location: 6,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(MeshVertex, color) as u32,
+            },
+        ]
+    }
+}
+
+/// Joints and weights of a skinned vertex, the joints index into the joints of the skin it is drawn with.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkinVertex {
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+}
+
+impl Default for SkinVertex {
+    /// Follows the first joint only, for vertices of unskinned primitives in a skinned mesh.
+    fn default() -> Self {
+        Self {
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl SkinVertex {
+    pub const fn binding_description(binding: u32) -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding,
+            stride: std::mem::size_of::<SkinVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    /// The joints at `location` as a uvec4 and the weights after them.
+    pub const fn input_attribute_descriptions(binding: u32, location: u32) -> [vk::VertexInputAttributeDescription; 2] {
+        [
+            vk::VertexInputAttributeDescription {
+                location,
+                binding,
+                format: vk::Format::R16G16B16A16_UINT,
+                offset: offset_of!(SkinVertex, joints) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: location + 1,
+                binding,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(SkinVertex, weights) as u32,
+            },
+        ]
+    }
+}
+
+/// Axis aligned bounding box.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: glm::Vector3<f32>,
+    pub max: glm::Vector3<f32>,
+}
+
+impl Aabb {
+    /// Zero sized box at the origin when there are no points.
+    pub fn from_points<I: IntoIterator<Item = glm::Vector3<f32>>>(points: I) -> Aabb {
+        let mut points = points.into_iter();
+        let Some(first) = points.next() else {
+            return Aabb {
+                min: glm::Vector3::zeros(),
+                max: glm::Vector3::zeros(),
+            };
+        };
+        points.fold(Aabb { min: first, max: first }, |aabb, point| Aabb {
+            min: aabb.min.inf(&point),
+            max: aabb.max.sup(&point),
+        })
+    }
+
+    pub fn center(&self) -> glm::Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn corners(&self) -> [glm::Vector3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            glm::Vector3::new(min.x, min.y, min.z),
+            glm::Vector3::new(max.x, min.y, min.z),
+            glm::Vector3::new(min.x, max.y, min.z),
+            glm::Vector3::new(max.x, max.y, min.z),
+            glm::Vector3::new(min.x, min.y, max.z),
+            glm::Vector3::new(max.x, min.y, max.z),
+            glm::Vector3::new(min.x, max.y, max.z),
+            glm::Vector3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Box around the transformed corners, bigger than the transformed contents under rotation.
+    pub fn transformed(&self, transform: &glm::Matrix4<f32>) -> Aabb {
+        Aabb::from_points(
+            self.corners()
+                .iter()
+                .map(|corner| transform.transform_point(&glm::Point3::from(*corner)).coords),
+        )
+    }
+}
+
+/// A range of the index buffer drawn with a single material.
+#[derive(Clone, Copy, Debug)]
+pub struct SubMesh {
+    pub index_offset: u32,
+    pub index_count: u32,
+    /// index into the material list of the file the mesh came from (`Mesh::materials` for OBJ,
+    /// `GltfScene::materials` for glTF), None when the file didn't assign one
+    pub material: Option<usize>,
+}
+
+/// Material data as described by an OBJ .mtl file.
+#[derive(Clone, Debug, Default)]
+pub struct ObjMaterial {
+    pub name: String,
+    pub diffuse: [f32; 3],
+    pub diffuse_texture: Option<String>,
+    pub normal_texture: Option<String>,
+}
+
+/// CPU side mesh, vertices are interleaved and submeshes are sorted by material.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<SubMesh>,
+    pub materials: Vec<ObjMaterial>,
+    /// one per vertex for skinned glTF meshes, empty otherwise
+    pub skin: Vec<SkinVertex>,
+}
+
+impl Mesh {
+    pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<Mesh> {
+        let path = path.as_ref();
+        let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+
+        // a missing .mtl file should not stop the geometry from loading
+        let materials = match materials {
+            Ok(materials) => materials,
+            Err(e) => {
+                log::warn!("Failed to load materials for {}: {}", path.display(), e);
+                vec![]
+            }
+        };
+
+        let mut vertices = vec![];
+        let mut indices_per_material: BTreeMap<Option<usize>, Vec<u32>> = BTreeMap::new();
+
+        for model in models.iter() {
+            let mesh = &model.mesh;
+            let base_vertex = vertices.len() as u32;
+            let vertex_count = mesh.positions.len() / 3;
+            let has_normals = !mesh.normals.is_empty();
+
+            for i in 0..vertex_count {
+                let position =
+                    glm::Vector3::new(mesh.positions[i * 3], mesh.positions[i * 3 + 1], mesh.positions[i * 3 + 2]);
+                let normal = if has_normals {
+                    glm::Vector3::new(mesh.normals[i * 3], mesh.normals[i * 3 + 1], mesh.normals[i * 3 + 2])
+                } else {
+                    glm::Vector3::zeros()
+                };
+                // obj has the origin of the texture in the bottom left corner, vulkan in top left
+                let uv = if mesh.texcoords.is_empty() {
+                    glm::Vector2::zeros()
+                } else {
+                    glm::Vector2::new(mesh.texcoords[i * 2], 1.0 - mesh.texcoords[i * 2 + 1])
+                };
+                let mut vertex = MeshVertex::new(position, normal, uv);
+                // the "v x y z r g b" extension some exporters write
+                if !mesh.vertex_color.is_empty() {
+                    let color = &mesh.vertex_color[i * 3..i * 3 + 3];
+                    vertex.color = glm::Vector4::new(color[0], color[1], color[2], 1.0);
+                }
+                vertices.push(vertex);
+            }
+
+            let model_indices: Vec<u32> = mesh.indices.iter().map(|index| index + base_vertex).collect();
+            if !has_normals {
+                generate_normals(&mut vertices[base_vertex as usize..], &mesh.indices);
+            }
+
+            indices_per_material
+                .entry(mesh.material_id)
+                .or_default()
+                .extend(model_indices);
+        }
+
+        let mut indices = vec![];
+        let mut submeshes = vec![];
+        for (material, material_indices) in indices_per_material {
+            submeshes.push(SubMesh {
+                index_offset: indices.len() as u32,
+                index_count: material_indices.len() as u32,
+                material,
+            });
+            indices.extend(material_indices);
+        }
+
+        let materials = materials
+            .into_iter()
+            .map(|material| ObjMaterial {
+                name: material.name,
+                diffuse: material.diffuse.unwrap_or([1.0, 1.0, 1.0]),
+                diffuse_texture: material.diffuse_texture,
+                normal_texture: material.normal_texture,
+            })
+            .collect();
+
+        Ok(Mesh {
+            vertices,
+            indices,
+            submeshes,
+            materials,
+            skin: vec![],
+        })
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        Aabb::from_points(self.vertices.iter().map(|vertex| vertex.position))
+    }
+
+    /// # Safety
+    /// `transfer_queue` has to be of the family of `transfer_pool`, it's waited on for the copy.
+    pub unsafe fn upload(
+        &self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        transfer_pool: vk::CommandPool,
+        transfer_queue: vk::Queue,
+    ) -> VkResult<GpuMesh> {
+        let (vertex_buffer, vertex_memory) = buffer::create_device_local_buffer(
+            device,
+            instance,
+            physical_device,
+            transfer_pool,
+            transfer_queue,
+            &self.vertices,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+        )?;
+        let (index_buffer, index_memory) = buffer::create_device_local_buffer(
+            device,
+            instance,
+            physical_device,
+            transfer_pool,
+            transfer_queue,
+            &self.indices,
+            vk::BufferUsageFlags::INDEX_BUFFER,
+        )?;
+
+        Ok(GpuMesh {
+            vertex_buffer,
+            vertex_memory,
+            index_buffer,
+            index_memory,
+            index_count: self.indices.len() as u32,
+            submeshes: self.submeshes.clone(),
+            bounds: self.bounds(),
+        })
+    }
+}
+
+/// Computes smooth vertex normals by accumulating the area weighted face normals of every triangle.
+/// `indices` are relative to the start of `vertices`.
+pub fn generate_normals(vertices: &mut [MeshVertex], indices: &[u32]) {
+    for vertex in vertices.iter_mut() {
+        vertex.normal = glm::Vector3::zeros();
+    }
+
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0] as usize, triangle[1] as usize, triangle[2] as usize);
+        let edge_1 = vertices[b].position - vertices[a].position;
+        let edge_2 = vertices[c].position - vertices[a].position;
+        // not normalized, so bigger triangles contribute more
+        let face_normal = edge_1.cross(&edge_2);
+
+        vertices[a].normal += face_normal;
+        vertices[b].normal += face_normal;
+        vertices[c].normal += face_normal;
+    }
+
+    for vertex in vertices.iter_mut() {
+        if vertex.normal.norm_squared() > 0.0 {
+            vertex.normal.normalize_mut();
+        }
+    }
+}
+
+/// Mesh uploaded to device local memory.
+pub struct GpuMesh {
+    pub vertex_buffer: vk::Buffer,
+    pub vertex_memory: vk::DeviceMemory,
+    pub index_buffer: vk::Buffer,
+    pub index_memory: vk::DeviceMemory,
+    pub index_count: u32,
+    pub submeshes: Vec<SubMesh>,
+    /// object space bounds of the vertices, for picking and culling
+    pub bounds: Aabb,
+}
+
+impl GpuMesh {
+    /// # Safety
+    /// `command_buffer` has to be recording.
+    pub unsafe fn bind(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &[self.vertex_buffer], &[0]);
+        device.cmd_bind_index_buffer(command_buffer, self.index_buffer, 0, vk::IndexType::UINT32);
+    }
+
+    /// Expects the mesh to be bound and the pipeline for the submesh material to be set.
+    ///
+    /// # Safety
+    /// `submesh` has to be an index of the submeshes.
+    pub unsafe fn draw_submesh(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, submesh: usize) {
+        let submesh = &self.submeshes[submesh];
+        device.cmd_draw_indexed(command_buffer, submesh.index_count, 1, submesh.index_offset, 0, 0);
+    }
+
+    /// # Safety
+    /// No frame in flight may draw the mesh.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_buffer(self.vertex_buffer, None);
+        device.destroy_buffer(self.index_buffer, None);
+        device.free_memory(self.vertex_memory, None);
+        device.free_memory(self.index_memory, None);
+    }
+}
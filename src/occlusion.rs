@@ -0,0 +1,245 @@
+use std::simd::prelude::*;
+
+use nalgebra as glm;
+
+use crate::mesh::{Aabb, Mesh};
+
+/// Pixels rasterized at once.
+const LANES: usize = 8;
+type Lanes = f32x8;
+
+/// Objects closer than this in clip space w can't be projected and always count as visible.
+const MIN_W: f32 = 1e-5;
+
+/// Size of the depth buffer occluders are drawn into, a few hundred pixels wide is plenty for culling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OcclusionSettings {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for OcclusionSettings {
+    fn default() -> Self {
+        Self { width: 256, height: 128 }
+    }
+}
+
+/// Positions and triangles of a simplified mesh that hides what is behind it, like a box inside a building.
+/// It has to stay inside the rendered mesh, or objects it hides could still be seen.
+#[derive(Clone, Debug, Default)]
+pub struct OccluderMesh {
+    pub positions: Vec<glm::Vector3<f32>>,
+    pub indices: Vec<u32>,
+}
+
+impl OccluderMesh {
+    /// Uses every triangle of `mesh`, simplified meshes cost less to rasterize.
+    pub fn from_mesh(mesh: &Mesh) -> Self {
+        Self {
+            positions: mesh.vertices.iter().map(|vertex| vertex.position).collect(),
+            indices: mesh.indices.clone(),
+        }
+    }
+
+    /// The solid box `aabb`.
+    pub fn from_aabb(aabb: &Aabb) -> Self {
+        #[rustfmt::skip]
+        let indices = vec![
+            0, 1, 3, 0, 3, 2, // -z
+            4, 6, 7, 4, 7, 5, // +z
+            0, 4, 5, 0, 5, 1, // -y
+            2, 3, 7, 2, 7, 6, // +y
+            0, 2, 6, 0, 6, 4, // -x
+            1, 5, 7, 1, 7, 3, // +x
+        ];
+        Self {
+            positions: aabb.corners().to_vec(),
+            indices,
+        }
+    }
+}
+
+/// Occlusion culling on the CPU: occluders are rasterized into a small depth buffer with SIMD and object bounds are
+/// tested against it. Independent of the GPU, so it also works where reading back depth is too slow.
+/// The test is conservative, an object is only reported hidden when its nearest point is behind the occluders
+/// everywhere its screen rectangle covers.
+pub struct SoftwareOcclusion {
+    width: usize,
+    height: usize,
+    /// `width` rounded up to whole lanes
+    stride: usize,
+    /// 0 at the near plane and 1 at the far plane, like Vulkan depth
+    depth: Vec<f32>,
+    view_projection: glm::Matrix4<f32>,
+}
+
+impl SoftwareOcclusion {
+    pub fn new(settings: OcclusionSettings) -> Self {
+        let width = settings.width.max(1) as usize;
+        let height = settings.height.max(1) as usize;
+        let stride = width.next_multiple_of(LANES);
+        Self {
+            width,
+            height,
+            stride,
+            depth: vec![1.0; stride * height],
+            view_projection: glm::Matrix4::identity(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Clears the depth buffer for a frame seen through `view_projection`, for example `Camera::view_projection`.
+    pub fn begin(&mut self, view_projection: glm::Matrix4<f32>) {
+        self.view_projection = view_projection;
+        self.depth.fill(1.0);
+    }
+
+    /// Depth of pixel `x`, `y`, y points down.
+    pub fn depth_at(&self, x: usize, y: usize) -> f32 {
+        self.depth[y * self.stride + x]
+    }
+
+    /// Draws both sides of the triangles of `occluder` placed with `model`.
+    /// Triangles reaching behind the camera are skipped, they would only hide less.
+    pub fn add_occluder(&mut self, occluder: &OccluderMesh, model: &glm::Matrix4<f32>) {
+        let model_view_projection = self.view_projection * model;
+        let screen: Vec<Option<glm::Vector3<f32>>> = occluder
+            .positions
+            .iter()
+            .map(|position| self.to_screen(&(model_view_projection * position.push(1.0))))
+            .collect();
+
+        for triangle in occluder.indices.chunks_exact(3) {
+            let corners = [
+                screen.get(triangle[0] as usize),
+                screen.get(triangle[1] as usize),
+                screen.get(triangle[2] as usize),
+            ];
+            if let [Some(Some(a)), Some(Some(b)), Some(Some(c))] = corners {
+                self.rasterize_triangle(*a, *b, *c);
+            }
+        }
+    }
+
+    /// Whether any part of `aabb` placed with `model` may be seen past the occluders.
+    /// Boxes outside the view are reported hidden.
+    pub fn is_visible(&self, aabb: &Aabb, model: &glm::Matrix4<f32>) -> bool {
+        let model_view_projection = self.view_projection * model;
+        let mut min = glm::Vector3::repeat(f32::MAX);
+        let mut max = glm::Vector3::repeat(f32::MIN);
+        for corner in aabb.corners() {
+            match self.to_screen(&(model_view_projection * corner.push(1.0))) {
+                Some(point) => {
+                    min = min.inf(&point);
+                    max = max.sup(&point);
+                }
+                // crosses the camera plane
+                None => return true,
+            }
+        }
+        if max.x < 0.0 || max.y < 0.0 || min.x > self.width as f32 || min.y > self.height as f32 || min.z > 1.0 {
+            return false;
+        }
+
+        // rounded outwards, the box covers every pixel it touches
+        let x0 = (min.x.floor().max(0.0) as usize).min(self.width - 1);
+        let y0 = (min.y.floor().max(0.0) as usize).min(self.height - 1);
+        let x1 = (max.x.ceil() as usize).clamp(x0 + 1, self.width);
+        let y1 = (max.y.ceil() as usize).clamp(y0 + 1, self.height);
+        let nearest = Lanes::splat(min.z);
+        for y in y0..y1 {
+            let row = y * self.stride;
+            let mut x = x0 - x0 % LANES;
+            while x < x1 {
+                let inside = Self::lane_range(x, x0, x1);
+                let depth = Lanes::from_slice(&self.depth[row + x..row + x + LANES]);
+                if (inside & nearest.simd_le(depth)).any() {
+                    return true;
+                }
+                x += LANES;
+            }
+        }
+        false
+    }
+
+    /// Pixel coordinates and depth, None behind the camera.
+    fn to_screen(&self, clip: &glm::Vector4<f32>) -> Option<glm::Vector3<f32>> {
+        if clip.w < MIN_W {
+            return None;
+        }
+        let ndc = clip.xyz() / clip.w;
+        Some(glm::Vector3::new(
+            (ndc.x * 0.5 + 0.5) * self.width as f32,
+            (ndc.y * 0.5 + 0.5) * self.height as f32,
+            ndc.z,
+        ))
+    }
+
+    /// Lanes of the run starting at `x` that lie in `start..end`.
+    fn lane_range(x: usize, start: usize, end: usize) -> mask32x8 {
+        let lane_x = Simd::<i32, LANES>::splat(x as i32) + Simd::from_array([0, 1, 2, 3, 4, 5, 6, 7]);
+        lane_x.simd_ge(Simd::splat(start as i32)) & lane_x.simd_lt(Simd::splat(end as i32))
+    }
+
+    /// Covers the pixels whose centers are inside the triangle, keeping the nearer depth.
+    fn rasterize_triangle(&mut self, a: glm::Vector3<f32>, b: glm::Vector3<f32>, c: glm::Vector3<f32>) {
+        let area = (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x);
+        if area.abs() < f32::EPSILON {
+            return;
+        }
+        // either winding, the edge functions are positive inside
+        let (b, c, area) = if area < 0.0 { (c, b, -area) } else { (b, c, area) };
+
+        let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as usize;
+        let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as usize;
+        let max_x = (a.x.max(b.x).max(c.x).ceil().max(0.0) as usize).min(self.width);
+        let max_y = (a.y.max(b.y).max(c.y).ceil().max(0.0) as usize).min(self.height);
+        if min_x >= max_x || min_y >= max_y {
+            return;
+        }
+
+        // edge i is opposite of vertex i: value = dx * (py - y) - dy * (px - x)
+        let edge = |from: glm::Vector3<f32>, to: glm::Vector3<f32>| {
+            let (dx, dy) = (to.x - from.x, to.y - from.y);
+            // value at (x, y) = step_x * x + step_y * y + offset
+            (-dy, dx, dy * from.x - dx * from.y)
+        };
+        let edges = [edge(b, c), edge(c, a), edge(a, b)];
+        // depth is affine in screen space, interpolated with the barycentrics of pixel (0, 0) and their steps
+        let depth_step_x = (edges[0].0 * a.z + edges[1].0 * b.z + edges[2].0 * c.z) / area;
+        let depth_step_y = (edges[0].1 * a.z + edges[1].1 * b.z + edges[2].1 * c.z) / area;
+        let depth_offset = (edges[0].2 * a.z + edges[1].2 * b.z + edges[2].2 * c.z) / area;
+
+        let lane_offsets = Lanes::from_array([0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        let zero = Lanes::splat(0.0);
+        for y in min_y..max_y {
+            let center_y = y as f32 + 0.5;
+            let row = y * self.stride;
+            let mut x = min_x - min_x % LANES;
+            while x < max_x {
+                let center_x = Lanes::splat(x as f32 + 0.5) + lane_offsets;
+                let mut inside = Self::lane_range(x, min_x, max_x);
+                for (step_x, step_y, offset) in edges {
+                    let value = center_x * Lanes::splat(step_x) + Lanes::splat(step_y * center_y + offset);
+                    inside &= value.simd_ge(zero);
+                }
+                if inside.any() {
+                    let depth = center_x * Lanes::splat(depth_step_x) + Lanes::splat(depth_step_y * center_y + depth_offset);
+                    let pixels = &mut self.depth[row + x..row + x + LANES];
+                    let stored = Lanes::from_slice(pixels);
+                    // clamped to the near plane, occluders in front of it still hide everything
+                    let depth = depth.simd_max(zero);
+                    inside.select(depth.simd_min(stored), stored).copy_to_slice(pixels);
+                }
+                x += LANES;
+            }
+        }
+    }
+}
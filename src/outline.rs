@@ -0,0 +1,102 @@
+use std::mem::size_of;
+
+use crate::error::Result;
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{
+    camera::Camera,
+    postfx::{EffectComposite, EffectDesc, EffectResolution, PostFxChain},
+    utility,
+};
+
+/// Lines where the depth jumps or the surface bends, drawn over the finished image.
+/// Normals are reconstructed from the depth, so it needs no normal buffer and outlines everything with depth.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutlineSettings {
+    pub enabled: bool,
+    pub color: glm::Vector3<f32>,
+    pub opacity: f32,
+    /// pixels between the samples compared, about the width of the line
+    pub thickness: f32,
+    /// depth difference relative to the distance that makes an edge, smaller values find more edges
+    pub depth_threshold: f32,
+    /// angle between surfaces that makes an edge as 1 - cos, 0.3 is about 45 degrees
+    pub normal_threshold: f32,
+    /// outlines fade out until this distance from the camera, 0 never fades
+    pub fade_distance: f32,
+}
+
+impl Default for OutlineSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: glm::Vector3::zeros(),
+            opacity: 1.0,
+            thickness: 1.0,
+            depth_threshold: 0.05,
+            normal_threshold: 0.3,
+            fade_distance: 0.0,
+        }
+    }
+}
+
+/// Matches the `Outline` push constant block of shaders/outline.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct OutlinePush {
+    inverse_projection: glm::Matrix4<f32>,
+    color: glm::Vector4<f32>,
+    params: glm::Vector4<f32>,
+}
+
+pub fn outline_effect() -> EffectDesc {
+    EffectDesc {
+        fragment_shader: "shaders/spv/outline_frag.spv".to_owned(),
+        resolution: EffectResolution::Full,
+        composite: EffectComposite::Replace,
+        push_constant_size: size_of::<OutlinePush>() as u32,
+    }
+}
+
+/// The outline effect of a `PostFxChain`, starts disabled. Add it before tonemapping so the lines keep their color,
+/// or after to outline over every other effect.
+pub struct Outlines {
+    effect: usize,
+}
+
+impl Outlines {
+    /// # Safety
+    /// `chain` has to be a chain of `device`, the effects are destroyed with it.
+    pub unsafe fn new(device: &ash::Device, chain: &mut PostFxChain) -> Result<Outlines> {
+        let effect = chain.add_effect(device, outline_effect())?;
+        chain.set_enabled(effect, false);
+        Ok(Outlines { effect })
+    }
+
+    /// Index of the effect in the chain.
+    pub fn index(&self) -> usize {
+        self.effect
+    }
+
+    /// Applies `settings` for the depth `camera` rendered, call it again when the camera's projection changes.
+    pub fn update(&self, chain: &mut PostFxChain, settings: &OutlineSettings, camera: &Camera, extent: vk::Extent2D) {
+        let aspect_ratio = extent.width as f32 / extent.height.max(1) as f32;
+        let inverse_projection = camera
+            .projection_matrix(aspect_ratio)
+            .try_inverse()
+            .unwrap_or_else(glm::Matrix4::identity);
+        chain.set_enabled(self.effect, settings.enabled && settings.opacity > 0.0);
+        let push = OutlinePush {
+            inverse_projection,
+            color: settings.color.push(settings.opacity.clamp(0.0, 1.0)),
+            params: glm::Vector4::new(
+                settings.thickness.max(0.0),
+                settings.depth_threshold.max(0.0),
+                settings.normal_threshold.max(0.0),
+                settings.fade_distance.max(0.0),
+            ),
+        };
+        chain.set_push_constants(self.effect, utility::as_bytes(&push));
+    }
+}
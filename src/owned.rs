@@ -0,0 +1,447 @@
+use std::{mem::ManuallyDrop, ops::Deref, ptr, sync::Arc};
+
+use ash::{
+    extensions::{ext, khr},
+    prelude::VkResult,
+    vk,
+};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
+
+use crate::{
+    context::Device,
+    memory::{AllocationId, GpuAllocator},
+    platform,
+};
+
+struct InstanceInner {
+    entry: ash::Entry,
+    instance: ash::Instance,
+}
+
+impl Drop for InstanceInner {
+    fn drop(&mut self) {
+        unsafe { self.instance.destroy_instance(None) };
+    }
+}
+
+/// An instance destroyed by its last clone. Surfaces, messengers and devices from `Device::with_instance`
+/// keep a clone, so it always goes last.
+#[derive(Clone)]
+pub struct Instance {
+    inner: Arc<InstanceInner>,
+}
+
+impl Instance {
+    /// Takes ownership of `instance`.
+    ///
+    /// # Safety
+    /// `instance` has to come from `entry`, nothing else may destroy it.
+    pub unsafe fn from_raw(entry: ash::Entry, instance: ash::Instance) -> Self {
+        Self {
+            inner: Arc::new(InstanceInner { entry, instance }),
+        }
+    }
+
+    pub fn entry(&self) -> &ash::Entry {
+        &self.inner.entry
+    }
+
+    pub fn raw(&self) -> &ash::Instance {
+        &self.inner.instance
+    }
+}
+
+impl Deref for Instance {
+    type Target = ash::Instance;
+
+    fn deref(&self) -> &ash::Instance {
+        &self.inner.instance
+    }
+}
+
+struct SurfaceInner {
+    loader: khr::Surface,
+    surface: vk::SurfaceKHR,
+    _instance: Instance,
+}
+
+impl Drop for SurfaceInner {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_surface(self.surface, None) };
+    }
+}
+
+/// A window surface, swapchains keep a clone so it outlives them.
+#[derive(Clone)]
+pub struct Surface {
+    inner: Arc<SurfaceInner>,
+}
+
+impl Surface {
+    /// # Safety
+    /// `window` has to outlive the surface.
+    pub unsafe fn new<W: HasRawWindowHandle + HasRawDisplayHandle>(instance: &Instance, window: &W) -> VkResult<Self> {
+        let surface = platform::create_surface(instance.entry(), instance, window)?;
+        Ok(Self::from_raw(instance, surface))
+    }
+
+    /// Takes ownership of `surface`, which has to come from `instance`.
+    ///
+    /// # Safety
+    /// `surface` may not be destroyed elsewhere and its window has to outlive the wrapper.
+    pub unsafe fn from_raw(instance: &Instance, surface: vk::SurfaceKHR) -> Self {
+        Self {
+            inner: Arc::new(SurfaceInner {
+                loader: khr::Surface::new(instance.entry(), instance),
+                surface,
+                _instance: instance.clone(),
+            }),
+        }
+    }
+
+    pub fn raw(&self) -> vk::SurfaceKHR {
+        self.inner.surface
+    }
+
+    pub fn loader(&self) -> &khr::Surface {
+        &self.inner.loader
+    }
+}
+
+pub struct DebugMessenger {
+    loader: ext::DebugUtils,
+    messenger: vk::DebugUtilsMessengerEXT,
+    _instance: Instance,
+}
+
+impl DebugMessenger {
+    /// # Safety
+    /// `create_info` has to be valid, its user data has to outlive the messenger.
+    pub unsafe fn new(instance: &Instance, create_info: &vk::DebugUtilsMessengerCreateInfoEXT) -> VkResult<Self> {
+        let loader = ext::DebugUtils::new(instance.entry(), instance);
+        let messenger = loader.create_debug_utils_messenger(create_info, None)?;
+        Ok(Self {
+            loader,
+            messenger,
+            _instance: instance.clone(),
+        })
+    }
+
+    pub fn loader(&self) -> &ext::DebugUtils {
+        &self.loader
+    }
+}
+
+impl Drop for DebugMessenger {
+    fn drop(&mut self) {
+        unsafe { self.loader.destroy_debug_utils_messenger(self.messenger, None) };
+    }
+}
+
+macro_rules! owned_handles {
+    ($($(#[$doc:meta])* $name:ident($handle:ty) => $destroy:ident,)*) => {
+        $(
+            $(#[$doc])*
+            pub struct $name {
+                device: Device,
+                handle: $handle,
+            }
+
+            impl $name {
+                /// Takes ownership of `handle`, which has to come from `device`.
+                /// `device` outlives the wrapper, the drop waits for the frames in flight through `Device::defer`.
+                ///
+                /// # Safety
+                /// `handle` may not be destroyed anywhere else.
+                pub unsafe fn from_raw(device: &Device, handle: $handle) -> Self {
+                    Self {
+                        device: device.clone(),
+                        handle,
+                    }
+                }
+
+                pub fn raw(&self) -> $handle {
+                    self.handle
+                }
+
+                /// Gives the handle back without destroying it.
+                pub fn into_raw(self) -> $handle {
+                    let this = ManuallyDrop::new(self);
+                    // the device clone still has to be released
+                    drop(unsafe { ptr::read(&this.device) });
+                    this.handle
+                }
+            }
+
+            impl Drop for $name {
+                fn drop(&mut self) {
+                    let handle = self.handle;
+                    unsafe { self.device.defer(move |device, _| device.$destroy(handle, None)) };
+                }
+            }
+        )*
+    };
+}
+
+owned_handles! {
+    Pipeline(vk::Pipeline) => destroy_pipeline,
+    PipelineLayout(vk::PipelineLayout) => destroy_pipeline_layout,
+    RenderPass(vk::RenderPass) => destroy_render_pass,
+    Framebuffer(vk::Framebuffer) => destroy_framebuffer,
+    ImageView(vk::ImageView) => destroy_image_view,
+    Sampler(vk::Sampler) => destroy_sampler,
+    ShaderModule(vk::ShaderModule) => destroy_shader_module,
+    DescriptorSetLayout(vk::DescriptorSetLayout) => destroy_descriptor_set_layout,
+    /// Destroying the pool frees its descriptor sets.
+    DescriptorPool(vk::DescriptorPool) => destroy_descriptor_pool,
+    Semaphore(vk::Semaphore) => destroy_semaphore,
+    Fence(vk::Fence) => destroy_fence,
+    QueryPool(vk::QueryPool) => destroy_query_pool,
+    /// Destroying the pool frees its command buffers.
+    CommandPool(vk::CommandPool) => destroy_command_pool,
+}
+
+/// Command buffers freed back to their pool on drop, the pool has to outlive them.
+pub struct CommandBuffers {
+    device: Device,
+    pool: vk::CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+}
+
+impl CommandBuffers {
+    /// Takes ownership of `command_buffers`, which have to come from `pool`.
+    ///
+    /// # Safety
+    /// The command buffers may not be freed anywhere else and `pool` has to outlive the wrapper.
+    pub unsafe fn from_raw(device: &Device, pool: vk::CommandPool, command_buffers: Vec<vk::CommandBuffer>) -> Self {
+        Self {
+            device: device.clone(),
+            pool,
+            command_buffers,
+        }
+    }
+
+    pub fn raw(&self) -> &[vk::CommandBuffer] {
+        &self.command_buffers
+    }
+}
+
+impl Drop for CommandBuffers {
+    fn drop(&mut self) {
+        let (pool, command_buffers) = (self.pool, std::mem::take(&mut self.command_buffers));
+        unsafe {
+            self.device
+                .defer(move |device, _| device.free_command_buffers(pool, &command_buffers))
+        };
+    }
+}
+
+/// Where the memory of a buffer or image comes from.
+enum Memory {
+    Allocator(AllocationId),
+    Dedicated(vk::DeviceMemory),
+    /// bound elsewhere, like sparse or aliased resources
+    External,
+}
+
+impl Memory {
+    unsafe fn free(&self, device: &ash::Device, allocator: &mut GpuAllocator) {
+        match self {
+            Memory::Allocator(id) => allocator.free(*id),
+            Memory::Dedicated(memory) => device.free_memory(*memory, None),
+            Memory::External => {}
+        }
+    }
+}
+
+/// A buffer that frees its memory with it.
+pub struct Buffer {
+    device: Device,
+    buffer: vk::Buffer,
+    memory: Memory,
+}
+
+impl Buffer {
+    /// A buffer in memory of the device allocator.
+    ///
+    /// # Safety
+    /// `usage` and `properties` have to be supported by the device.
+    pub unsafe fn new(
+        device: &Device,
+        size: vk::DeviceSize,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> VkResult<Self> {
+        let (buffer, id) = device.create_buffer(size, usage, properties, false)?;
+        Ok(Self {
+            device: device.clone(),
+            buffer,
+            memory: Memory::Allocator(id),
+        })
+    }
+
+    /// Takes ownership of `buffer` and of `memory` when there is one.
+    ///
+    /// # Safety
+    /// `buffer` and `memory` may not be freed elsewhere, the memory has to be the one bound to the buffer.
+    pub unsafe fn from_raw(device: &Device, buffer: vk::Buffer, memory: Option<vk::DeviceMemory>) -> Self {
+        Self {
+            device: device.clone(),
+            buffer,
+            memory: memory.map_or(Memory::External, Memory::Dedicated),
+        }
+    }
+
+    pub fn raw(&self) -> vk::Buffer {
+        self.buffer
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let (buffer, memory) = (self.buffer, std::mem::replace(&mut self.memory, Memory::External));
+        unsafe {
+            self.device.defer(move |device, allocator| {
+                device.destroy_buffer(buffer, None);
+                memory.free(device, allocator);
+            })
+        };
+    }
+}
+
+/// An image that frees its memory with it.
+pub struct Image {
+    device: Device,
+    image: vk::Image,
+    memory: Memory,
+}
+
+impl Image {
+    /// Creates the image from `create_info` and binds memory of the device allocator to it.
+    ///
+    /// # Safety
+    /// `create_info` has to be a valid image of the device.
+    pub unsafe fn new(
+        device: &Device,
+        create_info: &vk::ImageCreateInfo,
+        properties: vk::MemoryPropertyFlags,
+    ) -> VkResult<Self> {
+        let image = device.create_image(create_info, None)?;
+        let requirements = device.get_image_memory_requirements(image);
+        let allocation = device.allocator().allocate(
+            device.raw(),
+            device.instance(),
+            device.physical_device(),
+            requirements,
+            properties,
+        );
+        let bound = allocation.and_then(|allocation| {
+            if let Err(e) = device.bind_image_memory(image, allocation.memory, allocation.offset) {
+                device.allocator().free(allocation.id);
+                return Err(e);
+            }
+            Ok(allocation.id)
+        });
+        match bound {
+            Ok(id) => Ok(Self {
+                device: device.clone(),
+                image,
+                memory: Memory::Allocator(id),
+            }),
+            Err(e) => {
+                device.destroy_image(image, None);
+                Err(e)
+            }
+        }
+    }
+
+    /// Takes ownership of `image` and of `memory` when there is one.
+    ///
+    /// # Safety
+    /// `image` and `memory` may not be freed elsewhere, the memory has to be the one bound to the image.
+    pub unsafe fn from_raw(device: &Device, image: vk::Image, memory: Option<vk::DeviceMemory>) -> Self {
+        Self {
+            device: device.clone(),
+            image,
+            memory: memory.map_or(Memory::External, Memory::Dedicated),
+        }
+    }
+
+    pub fn raw(&self) -> vk::Image {
+        self.image
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        let (image, memory) = (self.image, std::mem::replace(&mut self.memory, Memory::External));
+        unsafe {
+            self.device.defer(move |device, allocator| {
+                device.destroy_image(image, None);
+                memory.free(device, allocator);
+            })
+        };
+    }
+}
+
+/// A swapchain with the views of its images, it keeps its surface alive.
+pub struct Swapchain {
+    device: Device,
+    loader: khr::Swapchain,
+    swapchain: vk::SwapchainKHR,
+    image_views: Vec<vk::ImageView>,
+    surface: Surface,
+}
+
+impl Swapchain {
+    /// Takes ownership of `swapchain`, created for `surface`, and of `image_views`.
+    ///
+    /// # Safety
+    /// `loader` has to be of `device`, the views have to view the images of `swapchain`.
+    pub unsafe fn from_raw(
+        device: &Device,
+        surface: &Surface,
+        loader: khr::Swapchain,
+        swapchain: vk::SwapchainKHR,
+        image_views: Vec<vk::ImageView>,
+    ) -> Self {
+        Self {
+            device: device.clone(),
+            loader,
+            swapchain,
+            image_views,
+            surface: surface.clone(),
+        }
+    }
+
+    pub fn raw(&self) -> vk::SwapchainKHR {
+        self.swapchain
+    }
+
+    pub fn loader(&self) -> &khr::Swapchain {
+        &self.loader
+    }
+
+    pub fn image_views(&self) -> &[vk::ImageView] {
+        &self.image_views
+    }
+}
+
+impl Drop for Swapchain {
+    fn drop(&mut self) {
+        let loader = self.loader.clone();
+        let swapchain = self.swapchain;
+        let image_views = std::mem::take(&mut self.image_views);
+        // the surface goes after the swapchain
+        let surface = self.surface.clone();
+        unsafe {
+            self.device.defer(move |device, _| {
+                for image_view in image_views.iter() {
+                    device.destroy_image_view(*image_view, None);
+                }
+                loader.destroy_swapchain(swapchain, None);
+                drop(surface);
+            })
+        };
+    }
+}
@@ -0,0 +1,237 @@
+use std::{
+    any::Any,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        mpsc::{self, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    context::{CommandPoolPerThread, Device},
+    draw_cache::{self, PassTarget},
+    material::DrawList,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// Threads that stay alive between frames, so their `CommandPoolPerThread` pools are reused every frame.
+pub struct RecordWorkers {
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl RecordWorkers {
+    pub fn new(worker_count: usize) -> Self {
+        let (job_sender, job_receiver) = mpsc::channel::<Job>();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        let workers = (0..worker_count.max(1))
+            .map(|index| {
+                let jobs = job_receiver.clone();
+                thread::Builder::new()
+                    .name(format!("command recorder {}", index))
+                    .spawn(move || loop {
+                        // the lock is released before running, so the other workers can take jobs
+                        let job = jobs.lock().unwrap_or_else(|e| e.into_inner()).recv();
+                        match job {
+                            Ok(job) => job(),
+                            // the workers were dropped
+                            Err(_) => break,
+                        }
+                    })
+                    .expect("Failed to spawn command recorder thread")
+            })
+            .collect();
+
+        Self {
+            jobs: Some(job_sender),
+            workers,
+        }
+    }
+
+    pub fn worker_count(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs every job on the workers and returns once all of them finished, so they can borrow from the caller.
+    /// A panic in a job is resumed on the calling thread after the others finished.
+    pub fn run<'scope>(&self, jobs: Vec<Box<dyn FnOnce() + Send + 'scope>>) {
+        let (done_sender, done) = mpsc::channel::<Option<Box<dyn Any + Send>>>();
+        let count = jobs.len();
+        for job in jobs {
+            let done_sender = done_sender.clone();
+            let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+                let panicked = panic::catch_unwind(AssertUnwindSafe(job)).err();
+                let _ = done_sender.send(panicked);
+            });
+            // SAFETY: the job is run or dropped before this function returns, every job signals `done` or drops its
+            // sender and the loop below waits for all of them
+            let job: Job = unsafe { std::mem::transmute(job) };
+            self.jobs
+                .as_ref()
+                .unwrap()
+                .send(job)
+                .expect("Command recorder threads stopped");
+        }
+        drop(done_sender);
+
+        let mut panicked = None;
+        for _ in 0..count {
+            match done.recv() {
+                Ok(Some(payload)) => panicked = panicked.or(Some(payload)),
+                Ok(None) => {}
+                // every remaining job was dropped without running
+                Err(_) => break,
+            }
+        }
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+    }
+}
+
+impl Drop for RecordWorkers {
+    fn drop(&mut self) {
+        // closing the channel stops the workers once they finished their job
+        self.jobs = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParallelRecordSettings {
+    pub worker_count: usize,
+    /// fewest draws a secondary command buffer records, smaller lists use fewer threads
+    pub min_chunk_size: usize,
+}
+
+impl Default for ParallelRecordSettings {
+    fn default() -> Self {
+        Self {
+            worker_count: thread::available_parallelism().map_or(4, |count| count.get()).clamp(1, 8),
+            min_chunk_size: 128,
+        }
+    }
+}
+
+impl ParallelRecordSettings {
+    pub fn worker_count(mut self, worker_count: usize) -> Self {
+        self.worker_count = worker_count;
+        self
+    }
+
+    pub fn min_chunk_size(mut self, min_chunk_size: usize) -> Self {
+        self.min_chunk_size = min_chunk_size;
+        self
+    }
+}
+
+/// Records a draw list into secondary command buffers on worker threads, one chunk of the sorted draws each,
+/// so large scenes don't record everything on the render thread. Unlike `DrawCache` every chunk is recorded
+/// again each frame. The render pass has to be begun with `vk::SubpassContents::SECONDARY_COMMAND_BUFFERS`.
+pub struct ParallelRecorder {
+    settings: ParallelRecordSettings,
+    workers: RecordWorkers,
+    pools: CommandPoolPerThread,
+}
+
+impl ParallelRecorder {
+    /// `queue_family_index` is the family the primary command buffers are submitted to.
+    pub fn new(device: &Device, queue_family_index: u32, settings: ParallelRecordSettings) -> Self {
+        Self {
+            settings: ParallelRecordSettings {
+                worker_count: settings.worker_count.max(1),
+                min_chunk_size: settings.min_chunk_size.max(1),
+            },
+            workers: RecordWorkers::new(settings.worker_count),
+            pools: device.create_command_pool_per_thread(queue_family_index),
+        }
+    }
+
+    pub fn settings(&self) -> ParallelRecordSettings {
+        self.settings
+    }
+
+    /// Recycles the command buffers recorded for `current_frame`, once the fence of the frame was waited on.
+    ///
+    /// # Safety
+    /// The fence of `current_frame` has to be waited on.
+    pub unsafe fn begin_frame(&self, current_frame: usize) -> VkResult<()> {
+        self.pools.begin_frame(current_frame)
+    }
+
+    /// Sorts `draws` and records them on the workers. Returns the secondary command buffers in draw order,
+    /// to pass to `cmd_execute_commands`. They stay valid until the next `begin_frame` of `current_frame`.
+    ///
+    /// # Safety
+    /// `target` has to name live objects of `device` and the draws have to use pipelines compatible with it.
+    pub unsafe fn record(
+        &self,
+        device: &ash::Device,
+        current_frame: usize,
+        draws: &mut DrawList,
+        shared_sets: &[vk::DescriptorSet],
+        target: &PassTarget,
+    ) -> VkResult<Vec<vk::CommandBuffer>> {
+        draws.sort();
+        if draws.is_empty() {
+            return Ok(vec![]);
+        }
+        let chunk_size = draws
+            .len()
+            .div_ceil(self.workers.worker_count())
+            .max(self.settings.min_chunk_size);
+        let chunk_count = draws.len().div_ceil(chunk_size);
+
+        let draws: &DrawList = draws;
+        let pools = &self.pools;
+        let mut recorded: Vec<VkResult<vk::CommandBuffer>> = vec![Ok(vk::CommandBuffer::null()); chunk_count];
+        let jobs: Vec<Box<dyn FnOnce() + Send + '_>> = recorded
+            .iter_mut()
+            .enumerate()
+            .map(|(index, slot)| {
+                let start = index * chunk_size;
+                let range = start..(start + chunk_size).min(draws.len());
+                let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+                    *slot = pools
+                        .allocate(current_frame, vk::CommandBufferLevel::SECONDARY)
+                        .and_then(|command_buffer| {
+                            draw_cache::record_chunk(device, command_buffer, target, |command_buffer| {
+                                draws.record_range(device, command_buffer, current_frame, shared_sets, range)
+                            })?;
+                            Ok(command_buffer)
+                        });
+                });
+                job
+            })
+            .collect();
+        self.workers.run(jobs);
+        recorded.into_iter().collect()
+    }
+
+    /// `record` followed by executing the chunks from `primary`, inside the render pass of `target`.
+    ///
+    /// # Safety
+    /// As for `record`, `primary` has to be inside the render pass of `target`.
+    pub unsafe fn record_and_execute(
+        &self,
+        device: &ash::Device,
+        primary: vk::CommandBuffer,
+        current_frame: usize,
+        draws: &mut DrawList,
+        shared_sets: &[vk::DescriptorSet],
+        target: &PassTarget,
+    ) -> VkResult<()> {
+        let command_buffers = self.record(device, current_frame, draws, shared_sets, target)?;
+        if !command_buffers.is_empty() {
+            device.cmd_execute_commands(primary, &command_buffers);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,789 @@
+use std::{collections::HashMap, ptr, sync::Arc};
+
+use crate::error::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
+use nalgebra as glm;
+
+use crate::{
+    buffer::OneTimeCommands,
+    gltf_import::{AlphaMode, GltfScene, PbrMaterialDesc, ShadingId, ShadingModel, TextureRef, ToonParams},
+    material::{Material, MaterialInstance, MaterialLayout, ParamType, ParamValue, ParameterLayout, TextureBinding},
+    mesh::{self, MeshVertex, SkinVertex},
+    permutation::{Permutation, PermutationSet, ShaderManager, VariantReport},
+    pipeline::{Multisampling, PipelineBuilder},
+    texture::{SamplerDesc, Texture},
+};
+
+pub const BASE_COLOR_SLOT: &str = "base_color";
+pub const METALLIC_ROUGHNESS_SLOT: &str = "metallic_roughness";
+pub const NORMAL_SLOT: &str = "normal";
+pub const OCCLUSION_SLOT: &str = "occlusion";
+pub const EMISSIVE_SLOT: &str = "emissive";
+/// Toon materials only, replace it on an instance for other bands.
+pub const RAMP_SLOT: &str = "ramp";
+
+/// Samples `NORMAL_SLOT` to perturb the normal, the glTF import adds it to materials with a normal texture.
+pub const NORMAL_MAP: &str = "NORMAL_MAP";
+/// Skins the vertices with `SkinVertex` at binding 1 and the joint matrices of set 1, see `skin_set_layout`.
+/// The material set moves to 2.
+pub const SKINNED: &str = "SKINNED";
+
+/// The keywords of shaders/pbr.vert and shaders/pbr.frag metallic roughness materials select from.
+pub fn permutations() -> PermutationSet {
+    PermutationSet::new(&[NORMAL_MAP, SKINNED])
+}
+
+/// Texels of the default toon ramp, a dark and a lit band.
+const RAMP_WIDTH: u32 = 16;
+
+/// Has to match the `MaterialParams` block of shaders/pbr.frag.
+pub fn parameter_layout() -> ParameterLayout {
+    ParameterLayout::new(&[
+        ("base_color_factor", ParamType::Vec4),
+        ("emissive_factor", ParamType::Vec3),
+        ("metallic_factor", ParamType::Float),
+        ("roughness_factor", ParamType::Float),
+        ("normal_scale", ParamType::Float),
+        ("occlusion_strength", ParamType::Float),
+        ("alpha_cutoff", ParamType::Float),
+        ("base_color_uv_set", ParamType::Int),
+        ("metallic_roughness_uv_set", ParamType::Int),
+        ("normal_uv_set", ParamType::Int),
+        ("occlusion_uv_set", ParamType::Int),
+        ("emissive_uv_set", ParamType::Int),
+    ])
+}
+
+/// Has to match the `MaterialParams` block of shaders/toon.frag.
+pub fn toon_parameter_layout() -> ParameterLayout {
+    ParameterLayout::new(&[
+        ("base_color_factor", ParamType::Vec4),
+        ("shadow_color", ParamType::Vec4),
+        ("specular_color", ParamType::Vec4),
+        ("rim_color", ParamType::Vec4),
+        ("emissive_factor", ParamType::Vec3),
+        ("specular_size", ParamType::Float),
+        ("rim_width", ParamType::Float),
+        ("alpha_cutoff", ParamType::Float),
+        ("base_color_uv_set", ParamType::Int),
+        ("emissive_uv_set", ParamType::Int),
+    ])
+}
+
+/// The shaders of a variant, a `ShadingModel` without its parameters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShadingKind {
+    /// shaders/pbr.frag
+    Pbr,
+    /// shaders/toon.frag
+    Toon,
+    Custom(ShadingId),
+}
+
+impl ShadingKind {
+    pub fn of(shading: &ShadingModel) -> Self {
+        match shading {
+            ShadingModel::Pbr => ShadingKind::Pbr,
+            ShadingModel::Toon(_) => ShadingKind::Toon,
+            ShadingModel::Custom(id) => ShadingKind::Custom(*id),
+        }
+    }
+}
+
+/// Fixed function state, shading model and shader keywords that need their own pipeline.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct PbrVariant {
+    pub double_sided: bool,
+    pub blend: bool,
+    /// alpha tested, only a pipeline of its own when `PbrMaterials` renders multisampled
+    pub alpha_to_coverage: bool,
+    pub shading: ShadingKind,
+    /// keywords of `permutations`, only metallic roughness materials have any
+    pub permutation: Permutation,
+}
+
+impl PbrVariant {
+    pub fn of(desc: &PbrMaterialDesc) -> Self {
+        Self {
+            double_sided: desc.double_sided,
+            blend: desc.alpha_mode == AlphaMode::Blend,
+            alpha_to_coverage: matches!(desc.alpha_mode, AlphaMode::Mask(_)),
+            shading: ShadingKind::of(&desc.shading),
+            permutation: desc
+                .keywords
+                .iter()
+                .fold(Permutation::new(), |permutation, keyword| permutation.with(keyword)),
+        }
+    }
+}
+
+/// Fixed function state of the variants `PbrMaterials` builds, with alpha to coverage only when multisampled.
+fn built_variants(multisampling: &Multisampling) -> Vec<(bool, bool, bool)> {
+    let mut variants = vec![];
+    for double_sided in [false, true] {
+        for blend in [false, true] {
+            variants.push((double_sided, blend, false));
+            if !blend && multisampling.is_multisampled() {
+                variants.push((double_sided, blend, true));
+            }
+        }
+    }
+    variants
+}
+
+/// A shading model from the application. Its fragment shader includes shaders/surface.glsl and defines
+/// `shade_surface`, which gets the surface and the engine's light data, see shaders/custom_surface.frag.
+/// A full override that doesn't include it has to declare the same `Frame` block and vertex outputs.
+#[derive(Clone, Debug)]
+pub struct CustomShadingDesc {
+    pub name: String,
+    pub fragment_shader: String,
+    /// None uses shaders/spv/pbr_vert.spv, a replacement reads `MeshVertex` and writes the same outputs
+    pub vertex_shader: Option<String>,
+    /// the `MaterialParams` block at set 1 binding 0
+    pub parameters: ParameterLayout,
+    pub defaults: Vec<(String, ParamValue)>,
+    /// bound from set 1 binding 1 on in this order, with the texture instances start with.
+    /// Slots named like `BASE_COLOR_SLOT` get the texture of the glTF material,
+    /// and an `Int` parameter named like "base_color_uv_set" its uv set.
+    pub textures: Vec<(String, TextureBinding)>,
+}
+
+/// The built-in materials, one per `PbrVariant`: metallic roughness, toon shading and the registered ones.
+/// Set 0 is the `FrameData` set, set 1 the material parameters and textures, SKINNED materials move them to
+/// set 2 behind their joint matrices.
+/// Metallic roughness variants are compiled from their GLSL sources by a `ShaderManager`, the ones with
+/// keywords the first time an instance asks for them.
+/// Blended variants don't write depth, the draw list doesn't sort them back to front.
+/// Multisampled ones draw alpha tested materials with alpha to coverage, so foliage and fences antialias.
+pub struct PbrMaterials {
+    variants: HashMap<PbrVariant, Arc<Material>>,
+    multisampling: Multisampling,
+    /// names of the registered shading models by `ShadingId`
+    custom_names: Vec<String>,
+    render_pass: vk::RenderPass,
+    frame_set_layout: vk::DescriptorSetLayout,
+    /// the joint matrices of SKINNED materials at set 1
+    skin_set_layout: vk::DescriptorSetLayout,
+    shaders: ShaderManager,
+    /// bound to every slot without a texture, factors are multiplied with it
+    white: Texture,
+    /// tangent space +z
+    flat_normal: Texture,
+    /// two hard bands, the light starts a little before the terminator
+    toon_ramp: Texture,
+}
+
+impl PbrMaterials {
+    /// `upload` has to belong to the graphics family.
+    ///
+    /// # Safety
+    /// `physical_device` has to be the device of `device`, `render_pass` has to outlive the materials.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        upload: OneTimeCommands,
+        render_pass: vk::RenderPass,
+        frame_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<PbrMaterials> {
+        Self::with_multisampling(
+            device,
+            instance,
+            physical_device,
+            upload,
+            render_pass,
+            frame_set_layout,
+            Multisampling::default(),
+        )
+    }
+
+    /// `new` for a render pass with `multisampling.samples` samples.
+    ///
+    /// # Safety
+    /// As for `new`.
+    pub unsafe fn with_multisampling(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        upload: OneTimeCommands,
+        render_pass: vk::RenderPass,
+        frame_set_layout: vk::DescriptorSetLayout,
+        multisampling: Multisampling,
+    ) -> Result<PbrMaterials> {
+        let one_texel = vk::Extent2D { width: 1, height: 1 };
+        let white = Texture::from_pixels(
+            device,
+            instance,
+            physical_device,
+            upload,
+            one_texel,
+            vk::Format::R8G8B8A8_UNORM,
+            &[255, 255, 255, 255],
+        )?;
+        let flat_normal = Texture::from_pixels(
+            device,
+            instance,
+            physical_device,
+            upload,
+            one_texel,
+            vk::Format::R8G8B8A8_UNORM,
+            &[128, 128, 255, 255],
+        )?;
+
+        let ramp_pixels: Vec<u8> = (0..RAMP_WIDTH)
+            .flat_map(|texel| {
+                let value = if texel < RAMP_WIDTH * 7 / 16 { 0 } else { 255 };
+                [value, value, value, 255]
+            })
+            .collect();
+        let mut toon_ramp = Texture::from_pixels(
+            device,
+            instance,
+            physical_device,
+            upload,
+            vk::Extent2D {
+                width: RAMP_WIDTH,
+                height: 1,
+            },
+            vk::Format::R8G8B8A8_UNORM,
+            &ramp_pixels,
+        )?;
+        // nearest keeps the bands hard
+        toon_ramp.set_sampler(
+            device,
+            &SamplerDesc::new(vk::Filter::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE),
+        )?;
+
+        let skin_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            p_immutable_samplers: ptr::null(),
+        };
+        let skin_set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: 1,
+            p_bindings: &skin_binding,
+        };
+        let skin_set_layout = device.create_descriptor_set_layout(&skin_set_layout_info, None)?;
+
+        let mut materials = PbrMaterials {
+            variants: HashMap::new(),
+            multisampling,
+            custom_names: vec![],
+            render_pass,
+            frame_set_layout,
+            skin_set_layout,
+            shaders: ShaderManager::new(),
+            white,
+            flat_normal,
+            toon_ramp,
+        };
+        if let Err(e) = materials.build_variants(device, instance, physical_device) {
+            materials.destroy(device);
+            return Err(e);
+        }
+        Ok(materials)
+    }
+
+    /// The metallic roughness and toon variants without keywords.
+    unsafe fn build_variants(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+    ) -> Result<()> {
+        let toon_textures = [
+            (BASE_COLOR_SLOT, self.white.binding()),
+            (RAMP_SLOT, self.toon_ramp.binding()),
+            (EMISSIVE_SLOT, self.white.binding()),
+        ];
+        for (double_sided, blend, alpha_to_coverage) in built_variants(&self.multisampling) {
+            let variant = PbrVariant {
+                double_sided,
+                blend,
+                alpha_to_coverage,
+                shading: ShadingKind::Pbr,
+                permutation: Permutation::new(),
+            };
+            let material = self.build_pbr(device, instance, physical_device, &variant)?;
+            self.variants.insert(variant.clone(), Arc::new(material));
+
+            let pipeline = PipelineBuilder::new(PBR_VERTEX_SHADER, "shaders/spv/toon_frag.spv");
+            let pipeline = variant_pipeline(pipeline, double_sided, blend, false)
+                .multisampling(self.multisampling)
+                .alpha_to_coverage(alpha_to_coverage);
+            let mut material = Material::new(
+                device,
+                instance,
+                physical_device,
+                self.render_pass,
+                pipeline,
+                MaterialLayout {
+                    shared_set_layouts: &[self.frame_set_layout],
+                    parameters: toon_parameter_layout(),
+                    textures: &toon_textures,
+                },
+            )?;
+            if let Err(e) = set_defaults(&mut material, true) {
+                material.destroy(device);
+                return Err(e);
+            }
+            let variant = PbrVariant {
+                shading: ShadingKind::Toon,
+                ..variant
+            };
+            self.variants.insert(variant, Arc::new(material));
+        }
+        Ok(())
+    }
+
+    /// The material of a metallic roughness variant, its shaders compiled for the keywords of the variant.
+    unsafe fn build_pbr(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        variant: &PbrVariant,
+    ) -> Result<Material> {
+        let keywords: Vec<&str> = variant.permutation.keywords().collect();
+        let skinned = variant.permutation.has(SKINNED);
+        let pipeline = self
+            .shaders
+            .material_pipeline(PBR_VERTEX_SOURCE, PBR_FRAGMENT_SOURCE, &permutations(), &keywords)?;
+        let pipeline = variant_pipeline(pipeline, variant.double_sided, variant.blend, skinned)
+            .multisampling(self.multisampling)
+            .alpha_to_coverage(variant.alpha_to_coverage);
+        let shared_set_layouts = if skinned {
+            vec![self.frame_set_layout, self.skin_set_layout]
+        } else {
+            vec![self.frame_set_layout]
+        };
+        let textures = [
+            (BASE_COLOR_SLOT, self.white.binding()),
+            (METALLIC_ROUGHNESS_SLOT, self.white.binding()),
+            (NORMAL_SLOT, self.flat_normal.binding()),
+            (OCCLUSION_SLOT, self.white.binding()),
+            (EMISSIVE_SLOT, self.white.binding()),
+        ];
+        let mut material = Material::new(
+            device,
+            instance,
+            physical_device,
+            self.render_pass,
+            pipeline,
+            MaterialLayout {
+                shared_set_layouts: &shared_set_layouts,
+                parameters: parameter_layout(),
+                textures: &textures,
+            },
+        )?;
+        if let Err(e) = set_defaults(&mut material, false) {
+            material.destroy(device);
+            return Err(e);
+        }
+        Ok(material)
+    }
+
+    /// A variant `new` built or an earlier `create_instance` asked for.
+    pub fn material(&self, variant: PbrVariant) -> &Arc<Material> {
+        &self.variants[&self.built_variant(variant)]
+    }
+
+    /// Layout of the set SKINNED materials read their joint matrices from, one storage buffer at binding 0 with
+    /// the 3 rows of a 3x4 matrix per joint.
+    pub fn skin_set_layout(&self) -> vk::DescriptorSetLayout {
+        self.skin_set_layout
+    }
+
+    /// The shader variants compiled or loaded so far.
+    pub fn shader_variants(&self) -> VariantReport {
+        self.shaders.report()
+    }
+
+    /// Alpha tested materials share the opaque pipelines on single sampled render passes.
+    fn built_variant(&self, variant: PbrVariant) -> PbrVariant {
+        PbrVariant {
+            alpha_to_coverage: variant.alpha_to_coverage && !variant.blend && self.multisampling.is_multisampled(),
+            ..variant
+        }
+    }
+
+    /// Builds the variants of a custom shading model, materials select it with `ShadingModel::Custom`.
+    ///
+    /// # Safety
+    /// As for `new`, the shaders of `desc` have to match the layout of the PBR materials.
+    pub unsafe fn register_shading(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        desc: CustomShadingDesc,
+    ) -> Result<ShadingId> {
+        let id = ShadingId(self.custom_names.len() as u32);
+        let vertex_shader = desc.vertex_shader.as_deref().unwrap_or(PBR_VERTEX_SHADER);
+        let textures: Vec<(&str, TextureBinding)> = desc
+            .textures
+            .iter()
+            .map(|(slot, binding)| (slot.as_str(), *binding))
+            .collect();
+
+        let mut materials = vec![];
+        for (double_sided, blend, alpha_to_coverage) in built_variants(&self.multisampling) {
+            let pipeline = PipelineBuilder::new(vertex_shader, &desc.fragment_shader);
+            let pipeline = variant_pipeline(pipeline, double_sided, blend, false)
+                .multisampling(self.multisampling)
+                .alpha_to_coverage(alpha_to_coverage);
+            let material = Material::new(
+                device,
+                instance,
+                physical_device,
+                self.render_pass,
+                pipeline,
+                MaterialLayout {
+                    shared_set_layouts: &[self.frame_set_layout],
+                    parameters: desc.parameters.clone(),
+                    textures: &textures,
+                },
+            )
+            .map_err(|e| match e {
+                // these already name the shader of the model
+                Error::Shader { .. } => e,
+                e => Error::msg(format!("Shading model {}: {}", desc.name, e)),
+            });
+            let mut material = match material {
+                Ok(material) => material,
+                Err(e) => {
+                    for (_, material) in materials {
+                        Material::destroy(&material, device);
+                    }
+                    return Err(e);
+                }
+            };
+            for (name, value) in desc.defaults.iter() {
+                material.set_default(name, *value)?;
+            }
+            materials.push((
+                PbrVariant {
+                    double_sided,
+                    blend,
+                    alpha_to_coverage,
+                    shading: ShadingKind::Custom(id),
+                    permutation: Permutation::new(),
+                },
+                material,
+            ));
+        }
+
+        self.variants
+            .extend(materials.into_iter().map(|(variant, material)| (variant, Arc::new(material))));
+        self.custom_names.push(desc.name);
+        Ok(id)
+    }
+
+    /// Name `register_shading` got for `id`.
+    pub fn shading_name(&self, id: ShadingId) -> Option<&str> {
+        self.custom_names.get(id.0 as usize).map(String::as_str)
+    }
+
+    /// 1x1 white, for example as the default of a custom color slot.
+    pub fn white_texture(&self) -> TextureBinding {
+        self.white.binding()
+    }
+
+    /// 1x1 tangent space +z, the default of a custom normal map slot.
+    pub fn flat_normal_texture(&self) -> TextureBinding {
+        self.flat_normal.binding()
+    }
+
+    /// Instance with the factors and textures of a glTF material,
+    /// `textures` are the ones returned by `upload_gltf_textures` for the same scene.
+    /// Builds the variant for the keywords of `desc` when no instance used them yet.
+    ///
+    /// # Safety
+    /// `textures` have to be alive as long as the instance.
+    pub unsafe fn create_instance(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        desc: &PbrMaterialDesc,
+        textures: &[Texture],
+    ) -> Result<MaterialInstance> {
+        let variant = self.built_variant(PbrVariant::of(desc));
+        if !self.variants.contains_key(&variant) {
+            if variant.shading != ShadingKind::Pbr && !variant.permutation.is_empty() {
+                return Err(Error::msg(format!(
+                    "Only metallic roughness materials have shader keywords, {:?} has {}",
+                    desc.shading, variant.permutation
+                )));
+            }
+            if variant.shading != ShadingKind::Pbr {
+                return Err(Error::msg(format!("{:?} isn't a registered shading model", desc.shading)));
+            }
+            let material = self.build_pbr(device, instance, physical_device, &variant)?;
+            self.variants.insert(variant.clone(), Arc::new(material));
+        }
+        let material = self.material(variant).clone();
+        Self::create_instance_of(device, instance, physical_device, material, desc, textures)
+    }
+
+    /// Like `create_instance` with a material of the same layout as the variant of `desc`,
+    /// for example one drawing crowds with other shaders.
+    ///
+    /// # Safety
+    /// As for `create_instance`, `material` has to be a material of `device`.
+    pub unsafe fn create_instance_of(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        material: Arc<Material>,
+        desc: &PbrMaterialDesc,
+        textures: &[Texture],
+    ) -> Result<MaterialInstance> {
+        let mut material_instance = MaterialInstance::new(device, instance, physical_device, material)?;
+
+        if let ShadingModel::Custom(_) = desc.shading {
+            fill_custom_instance(&mut material_instance, desc, textures)?;
+            return Ok(material_instance);
+        }
+        material_instance.set_param(
+            "base_color_factor",
+            ParamValue::Vec4(glm::Vector4::from(desc.base_color_factor)),
+        )?;
+        material_instance.set_param("emissive_factor", ParamValue::Vec3(glm::Vector3::from(desc.emissive_factor)))?;
+        material_instance.set_param("alpha_cutoff", ParamValue::Float(alpha_cutoff(desc)))?;
+
+        let slots = match desc.shading {
+            ShadingModel::Pbr => {
+                material_instance.set_param("metallic_factor", ParamValue::Float(desc.metallic_factor))?;
+                material_instance.set_param("roughness_factor", ParamValue::Float(desc.roughness_factor))?;
+                material_instance.set_param("normal_scale", ParamValue::Float(desc.normal_scale))?;
+                material_instance.set_param("occlusion_strength", ParamValue::Float(desc.occlusion_strength))?;
+                vec![
+                    (BASE_COLOR_SLOT, &desc.base_color_texture),
+                    (METALLIC_ROUGHNESS_SLOT, &desc.metallic_roughness_texture),
+                    (NORMAL_SLOT, &desc.normal_texture),
+                    (OCCLUSION_SLOT, &desc.occlusion_texture),
+                    (EMISSIVE_SLOT, &desc.emissive_texture),
+                ]
+            }
+            ShadingModel::Toon(params) => {
+                set_toon_params(&mut |name, value| material_instance.set_param(name, value), &params)?;
+                vec![
+                    (BASE_COLOR_SLOT, &desc.base_color_texture),
+                    (EMISSIVE_SLOT, &desc.emissive_texture),
+                ]
+            }
+            ShadingModel::Custom(_) => unreachable!("custom shading models are filled above"),
+        };
+        for (slot, texture_ref) in slots {
+            if let Some(texture_ref) = texture_ref {
+                material_instance.set_texture(slot, lookup_texture(textures, texture_ref)?)?;
+                material_instance.set_param(&uv_set_param(slot), ParamValue::Int(uv_set(texture_ref)))?;
+            }
+        }
+
+        Ok(material_instance)
+    }
+
+    /// # Safety
+    /// No frame in flight may draw with the materials.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for material in self.variants.values() {
+            material.destroy(device);
+        }
+        device.destroy_descriptor_set_layout(self.skin_set_layout, None);
+        self.white.destroy(device);
+        self.flat_normal.destroy(device);
+        self.toon_ramp.destroy(device);
+    }
+}
+
+const PBR_VERTEX_SHADER: &str = "shaders/spv/pbr_vert.spv";
+/// Sources of the metallic roughness variants, compile.sh precompiles every permutation of them.
+const PBR_VERTEX_SOURCE: &str = "shaders/pbr.vert";
+const PBR_FRAGMENT_SOURCE: &str = "shaders/pbr.frag";
+
+/// `pipeline` with the vertex input and fixed function state of a variant.
+fn variant_pipeline(pipeline: PipelineBuilder, double_sided: bool, blend: bool, skinned: bool) -> PipelineBuilder {
+    // glTF winds front faces counter clockwise
+    let cull_mode = if double_sided {
+        vk::CullModeFlags::NONE
+    } else {
+        vk::CullModeFlags::BACK
+    };
+    let mut pipeline = pipeline.vertex_input(
+        &[MeshVertex::get_binding_description()],
+        &MeshVertex::get_input_attribute_description(),
+    );
+    if skinned {
+        pipeline = pipeline.vertex_input(
+            &[SkinVertex::binding_description(1)],
+            &SkinVertex::input_attribute_descriptions(1, 7),
+        );
+    }
+    pipeline
+        .cull_mode(cull_mode, vk::FrontFace::COUNTER_CLOCKWISE)
+        .depth_test(true, !blend)
+        .alpha_blending(blend)
+}
+
+/// Factors instances start with, neutral towards the default textures.
+pub(crate) fn set_defaults(material: &mut Material, toon: bool) -> Result<()> {
+    material.set_default("base_color_factor", ParamValue::Vec4(glm::Vector4::repeat(1.0)))?;
+    material.set_default("emissive_factor", ParamValue::Vec3(glm::Vector3::zeros()))?;
+    material.set_default("alpha_cutoff", ParamValue::Float(0.0))?;
+    if toon {
+        set_toon_params(&mut |name, value| material.set_default(name, value), &Default::default())?;
+    } else {
+        material.set_default("metallic_factor", ParamValue::Float(1.0))?;
+        material.set_default("roughness_factor", ParamValue::Float(1.0))?;
+        material.set_default("normal_scale", ParamValue::Float(1.0))?;
+        material.set_default("occlusion_strength", ParamValue::Float(1.0))?;
+    }
+    Ok(())
+}
+
+fn alpha_cutoff(desc: &PbrMaterialDesc) -> f32 {
+    match desc.alpha_mode {
+        AlphaMode::Mask(cutoff) => cutoff,
+        AlphaMode::Opaque | AlphaMode::Blend => 0.0,
+    }
+}
+
+/// Sets the glTF factors and textures a custom shading model has parameters and slots for, the rest is skipped.
+fn fill_custom_instance(
+    material_instance: &mut MaterialInstance,
+    desc: &PbrMaterialDesc,
+    textures: &[Texture],
+) -> Result<()> {
+    let params = [
+        (
+            "base_color_factor",
+            ParamValue::Vec4(glm::Vector4::from(desc.base_color_factor)),
+        ),
+        ("emissive_factor", ParamValue::Vec3(glm::Vector3::from(desc.emissive_factor))),
+        ("metallic_factor", ParamValue::Float(desc.metallic_factor)),
+        ("roughness_factor", ParamValue::Float(desc.roughness_factor)),
+        ("normal_scale", ParamValue::Float(desc.normal_scale)),
+        ("occlusion_strength", ParamValue::Float(desc.occlusion_strength)),
+        ("alpha_cutoff", ParamValue::Float(alpha_cutoff(desc))),
+    ];
+    for (name, value) in params {
+        if material_instance.material().parameter_type(name) == Some(value.param_type()) {
+            material_instance.set_param(name, value)?;
+        }
+    }
+    let slots = [
+        (BASE_COLOR_SLOT, &desc.base_color_texture),
+        (METALLIC_ROUGHNESS_SLOT, &desc.metallic_roughness_texture),
+        (NORMAL_SLOT, &desc.normal_texture),
+        (OCCLUSION_SLOT, &desc.occlusion_texture),
+        (EMISSIVE_SLOT, &desc.emissive_texture),
+    ];
+    for (slot, texture_ref) in slots {
+        if let Some(texture_ref) = texture_ref {
+            if material_instance.material().texture_slot(slot).is_some() {
+                material_instance.set_texture(slot, lookup_texture(textures, texture_ref)?)?;
+            }
+            let uv_param = uv_set_param(slot);
+            if material_instance.material().parameter_type(&uv_param) == Some(ParamType::Int) {
+                material_instance.set_param(&uv_param, ParamValue::Int(uv_set(texture_ref)))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The toon parameters that aren't shared with the metallic roughness layout, through `set`.
+fn set_toon_params(set: &mut dyn FnMut(&str, ParamValue) -> Result<()>, params: &ToonParams) -> Result<()> {
+    let with_strength = |color: [f32; 3], strength: f32| ParamValue::Vec4(glm::Vector3::from(color).push(strength));
+    set("shadow_color", with_strength(params.shadow_color, 1.0))?;
+    set(
+        "specular_color",
+        with_strength(params.specular_color, params.specular_strength),
+    )?;
+    set("rim_color", with_strength(params.rim_color, params.rim_strength))?;
+    set("specular_size", ParamValue::Float(params.specular_size.max(0.0)))?;
+    set("rim_width", ParamValue::Float(params.rim_width.clamp(0.0, 1.0)))?;
+    Ok(())
+}
+
+/// The parameter holding which uv set `slot` is sampled with.
+pub fn uv_set_param(slot: &str) -> String {
+    format!("{}_uv_set", slot)
+}
+
+fn uv_set(texture_ref: &TextureRef) -> i32 {
+    // the mesh vertices only carry the first few uv sets
+    if texture_ref.tex_coord as usize >= mesh::UV_SETS {
+        log::warn!("Uv set {} isn't supported, using uv set 0", texture_ref.tex_coord);
+        return 0;
+    }
+    texture_ref.tex_coord as i32
+}
+
+fn lookup_texture(textures: &[Texture], texture_ref: &TextureRef) -> Result<TextureBinding> {
+    textures
+        .get(texture_ref.texture)
+        .map(|texture| texture.binding())
+        .ok_or_else(|| Error::msg(format!("Material references missing texture {}", texture_ref.texture)))
+}
+
+/// Uploads every texture of the scene, color textures are sampled as sRGB and data textures as linear.
+///
+/// # Safety
+/// `upload` has to belong to the graphics family of `device`.
+pub unsafe fn upload_gltf_textures(
+    device: &ash::Device,
+    instance: &ash::Instance,
+    physical_device: vk::PhysicalDevice,
+    upload: OneTimeCommands,
+    scene: &GltfScene,
+) -> VkResult<Vec<Texture>> {
+    let mut srgb = vec![false; scene.textures.len()];
+    for material in scene.materials.iter() {
+        for texture_ref in [&material.base_color_texture, &material.emissive_texture]
+            .into_iter()
+            .flatten()
+        {
+            if let Some(is_srgb) = srgb.get_mut(texture_ref.texture) {
+                *is_srgb = true;
+            }
+        }
+    }
+
+    let mut textures = vec![];
+    for (index, desc) in scene.textures.iter().enumerate() {
+        let image = &scene.images[desc.image];
+        let format = if srgb[index] {
+            vk::Format::R8G8B8A8_SRGB
+        } else {
+            vk::Format::R8G8B8A8_UNORM
+        };
+
+        let mut texture = Texture::from_pixels(
+            device,
+            instance,
+            physical_device,
+            upload,
+            vk::Extent2D {
+                width: image.width,
+                height: image.height,
+            },
+            format,
+            &image.pixels,
+        )?;
+        texture.set_sampler(device, &desc.sampler)?;
+        textures.push(texture);
+    }
+    Ok(textures)
+}
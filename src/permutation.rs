@@ -0,0 +1,437 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::{Error, Result},
+    pipeline::PipelineBuilder,
+};
+
+/// Compiler `ShaderManager` runs, `glslc` on the path when it isn't set.
+pub const GLSLC_ENV: &str = "VULKY_GLSLC";
+/// Where `ShaderManager::new` writes the compiled variants.
+pub const VARIANT_DIRECTORY: &str = "shaders/spv/variants";
+
+/// Keywords a shader is compiled with, each one is `#define`d so the source can `#ifdef NORMAL_MAP`.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Permutation {
+    keywords: BTreeSet<String>,
+}
+
+impl Permutation {
+    /// The shader without any keyword.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, keyword: &str) -> Self {
+        self.keywords.insert(keyword.to_owned());
+        self
+    }
+
+    pub fn has(&self, keyword: &str) -> bool {
+        self.keywords.contains(keyword)
+    }
+
+    /// Sorted, so the same keywords always make the same variant.
+    pub fn keywords(&self) -> impl Iterator<Item = &str> {
+        self.keywords.iter().map(String::as_str)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keywords.is_empty()
+    }
+}
+
+impl fmt::Display for Permutation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.keywords.is_empty() {
+            return write!(f, "default");
+        }
+        write!(f, "{}", self.keywords().collect::<Vec<_>>().join(" "))
+    }
+}
+
+/// The keywords a material picks its permutation from, others are rejected so the number of variants stays
+/// bounded by the combinations of the set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PermutationSet {
+    keywords: Vec<String>,
+}
+
+impl PermutationSet {
+    /// Keywords are preprocessor identifiers, `SKINNED` for example.
+    pub fn new(keywords: &[&str]) -> Self {
+        for keyword in keywords {
+            let identifier = keyword.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && keyword.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            assert!(identifier, "Shader keyword {:?} isn't an identifier", keyword);
+        }
+        Self {
+            keywords: keywords.iter().map(|keyword| keyword.to_string()).collect(),
+        }
+    }
+
+    pub fn keywords(&self) -> &[String] {
+        &self.keywords
+    }
+
+    pub fn contains(&self, keyword: &str) -> bool {
+        self.keywords.iter().any(|known| known == keyword)
+    }
+
+    /// The permutation with `keywords` enabled, errors for keywords that aren't in the set.
+    pub fn permutation(&self, keywords: &[&str]) -> Result<Permutation> {
+        let unknown: Vec<&str> = keywords.iter().copied().filter(|keyword| !self.contains(keyword)).collect();
+        if !unknown.is_empty() {
+            return Err(Error::msg(format!(
+                "Unknown shader keywords {}, the permutation set has {}",
+                unknown.join(", "),
+                self.keywords.join(", ")
+            )));
+        }
+        Ok(keywords
+            .iter()
+            .fold(Permutation::new(), |permutation, keyword| permutation.with(keyword)))
+    }
+
+    /// Every combination of the keywords, to compile all of them ahead of time.
+    pub fn all(&self) -> Vec<Permutation> {
+        (0..1usize << self.keywords.len())
+            .map(|mask| {
+                self.keywords
+                    .iter()
+                    .enumerate()
+                    .filter(|(index, _)| mask & (1 << index) != 0)
+                    .fold(Permutation::new(), |permutation, (_, keyword)| permutation.with(keyword))
+            })
+            .collect()
+    }
+}
+
+/// How `ShaderManager` got a variant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VariantOrigin {
+    Compiled(Duration),
+    /// the spv file of an earlier run was newer than the source
+    OnDisk,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Variant {
+    pub source: String,
+    pub permutation: Permutation,
+    pub spv: String,
+    pub origin: VariantOrigin,
+}
+
+/// The variants a `ShaderManager` provided, `Display` lists them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct VariantReport {
+    /// sorted by source and permutation
+    pub variants: Vec<Variant>,
+}
+
+impl VariantReport {
+    pub fn compiled(&self) -> usize {
+        self.variants
+            .iter()
+            .filter(|variant| matches!(variant.origin, VariantOrigin::Compiled(_)))
+            .count()
+    }
+
+    pub fn compile_time(&self) -> Duration {
+        self.variants
+            .iter()
+            .map(|variant| match variant.origin {
+                VariantOrigin::Compiled(duration) => duration,
+                VariantOrigin::OnDisk => Duration::ZERO,
+            })
+            .sum()
+    }
+}
+
+impl fmt::Display for VariantReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} shader variants, {} compiled in {} ms",
+            self.variants.len(),
+            self.compiled(),
+            self.compile_time().as_millis()
+        )?;
+        for variant in self.variants.iter() {
+            let origin = match variant.origin {
+                VariantOrigin::Compiled(duration) => format!("compiled in {} ms", duration.as_millis()),
+                VariantOrigin::OnDisk => "on disk".to_owned(),
+            };
+            writeln!(
+                f,
+                "  {} [{}]: {}, {}",
+                variant.source, variant.permutation, variant.spv, origin
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compiles GLSL sources for a permutation with glslc the first time it is asked for and caches the spv files,
+/// across runs too. Only the source itself is compared against the spv file, after changing an included file
+/// delete the variant directory.
+pub struct ShaderManager {
+    compiler: String,
+    directory: PathBuf,
+    variants: HashMap<(String, Permutation), Variant>,
+}
+
+impl Default for ShaderManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShaderManager {
+    /// Writes to `VARIANT_DIRECTORY` with the compiler of `VULKY_GLSLC`.
+    pub fn new() -> Self {
+        let compiler = std::env::var(GLSLC_ENV)
+            .ok()
+            .filter(|compiler| !compiler.trim().is_empty())
+            .unwrap_or_else(|| "glslc".to_owned());
+        Self {
+            compiler,
+            directory: PathBuf::from(VARIANT_DIRECTORY),
+            variants: HashMap::new(),
+        }
+    }
+
+    pub fn compiler(mut self, compiler: &str) -> Self {
+        self.compiler = compiler.to_owned();
+        self
+    }
+
+    pub fn directory(mut self, directory: &Path) -> Self {
+        self.directory = directory.to_owned();
+        self
+    }
+
+    /// Path of the spv file of `source`, a `.vert` or `.frag` file, compiled with the keywords of `permutation`.
+    pub fn variant(&mut self, source: &str, permutation: &Permutation) -> Result<String> {
+        let key = (source.to_owned(), permutation.clone());
+        if let Some(variant) = self.variants.get(&key) {
+            return Ok(variant.spv.clone());
+        }
+
+        let spv = self.directory.join(variant_file_name(source, permutation));
+        let origin = if is_newer(&spv, Path::new(source)) {
+            VariantOrigin::OnDisk
+        } else {
+            fs::create_dir_all(&self.directory)?;
+            let start = Instant::now();
+            let mut command = Command::new(&self.compiler);
+            for keyword in permutation.keywords() {
+                command.arg(format!("-D{}", keyword));
+            }
+            let output = command
+                .arg(source)
+                .arg("-o")
+                .arg(&spv)
+                .output()
+                .map_err(|e| Error::shader(source, format!("Failed to run {}: {}", self.compiler, e)))?;
+            if !output.status.success() {
+                return Err(Error::shader(
+                    source,
+                    format!(
+                        "[{}] failed to compile:\n{}",
+                        permutation,
+                        String::from_utf8_lossy(&output.stderr).trim_end()
+                    ),
+                ));
+            }
+            VariantOrigin::Compiled(start.elapsed())
+        };
+
+        let spv = spv.to_string_lossy().into_owned();
+        log::debug!("Shader variant {} [{}] is {}", source, permutation, spv);
+        self.variants.insert(
+            key,
+            Variant {
+                source: source.to_owned(),
+                permutation: permutation.clone(),
+                spv: spv.clone(),
+                origin,
+            },
+        );
+        Ok(spv)
+    }
+
+    /// A builder for the variant of both stages, the start of a material's pipeline.
+    pub fn pipeline(
+        &mut self,
+        vertex_source: &str,
+        fragment_source: &str,
+        permutation: &Permutation,
+    ) -> Result<PipelineBuilder> {
+        let vertex_shader = self.variant(vertex_source, permutation)?;
+        let fragment_shader = self.variant(fragment_source, permutation)?;
+        Ok(PipelineBuilder::new(&vertex_shader, &fragment_shader))
+    }
+
+    /// `pipeline` for the permutation a material selected from `set`.
+    pub fn material_pipeline(
+        &mut self,
+        vertex_source: &str,
+        fragment_source: &str,
+        set: &PermutationSet,
+        keywords: &[&str],
+    ) -> Result<PipelineBuilder> {
+        let permutation = set.permutation(keywords)?;
+        self.pipeline(vertex_source, fragment_source, &permutation)
+    }
+
+    /// Compiles every permutation of `set` for `source`, at build time or during a loading screen.
+    pub fn precompile(&mut self, source: &str, set: &PermutationSet) -> Result<()> {
+        for permutation in set.all() {
+            self.variant(source, &permutation)?;
+        }
+        Ok(())
+    }
+
+    pub fn report(&self) -> VariantReport {
+        let mut variants: Vec<Variant> = self.variants.values().cloned().collect();
+        variants.sort_by(|a, b| (&a.source, &a.permutation).cmp(&(&b.source, &b.permutation)));
+        VariantReport { variants }
+    }
+}
+
+/// `shaders/pbr.frag` with NORMAL_MAP is `pbr_frag-<hash of the path>-NORMAL_MAP.spv`, the hash tells sources with
+/// the same name in different directories apart. compile.sh precompiles the variants of the engine's shaders.
+fn variant_file_name(source: &str, permutation: &Permutation) -> String {
+    let path = Path::new(source);
+    let stem = path.file_stem().map_or("shader".into(), |stem| stem.to_string_lossy());
+    let mut name = match path.extension() {
+        Some(extension) => format!("{}_{}", stem, extension.to_string_lossy()),
+        None => stem.into_owned(),
+    };
+    name.push_str(&format!("-{:016x}", path_hash(source)));
+    for keyword in permutation.keywords() {
+        name.push('-');
+        name.push_str(keyword);
+    }
+    name.push_str(".spv");
+    name
+}
+
+/// 64 bit FNV-1a, stable across runs and Rust versions so the variants on disk stay valid.
+fn path_hash(source: &str) -> u64 {
+    source.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+fn is_newer(path: &Path, than: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+    match (modified(path), modified(than)) {
+        (Some(path), Some(than)) => path >= than,
+        (Some(_), None) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_covers_every_combination_once() {
+        let set = PermutationSet::new(&["NORMAL_MAP", "SKINNED", "FOG"]);
+        let all = set.all();
+        assert_eq!(all.len(), 8);
+        let unique: BTreeSet<&Permutation> = all.iter().collect();
+        assert_eq!(unique.len(), 8);
+        assert!(all.contains(&Permutation::new()));
+        assert!(all.contains(&Permutation::new().with("FOG").with("NORMAL_MAP").with("SKINNED")));
+        assert_eq!(PermutationSet::new(&[]).all(), vec![Permutation::new()]);
+    }
+
+    #[test]
+    fn permutation_rejects_unknown_keywords() {
+        let set = PermutationSet::new(&["NORMAL_MAP", "SKINNED"]);
+        assert_eq!(
+            set.permutation(&["SKINNED", "NORMAL_MAP"]).unwrap(),
+            Permutation::new().with("NORMAL_MAP").with("SKINNED")
+        );
+        assert!(set.permutation(&["NORMAL_MAP", "FOG"]).is_err());
+        assert!(set.permutation(&["normal_map"]).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn keywords_have_to_be_identifiers() {
+        PermutationSet::new(&["NORMAL MAP"]);
+    }
+
+    #[test]
+    fn file_names_keep_keyword_order_and_case() {
+        let permutation = Permutation::new().with("SKINNED").with("NORMAL_MAP");
+        let name = variant_file_name("shaders/pbr.frag", &permutation);
+        assert!(name.starts_with("pbr_frag-"));
+        assert!(name.ends_with("-NORMAL_MAP-SKINNED.spv"));
+        assert_ne!(
+            variant_file_name("shaders/pbr.frag", &Permutation::new().with("NORMAL_MAP")),
+            variant_file_name("shaders/pbr.frag", &Permutation::new().with("normal_map"))
+        );
+        assert_ne!(
+            variant_file_name("shaders/pbr.frag", &Permutation::new()),
+            variant_file_name("shaders/pbr.frag", &Permutation::new().with("NORMAL_MAP"))
+        );
+    }
+
+    #[test]
+    fn file_names_tell_directories_apart() {
+        let permutation = Permutation::new().with("NORMAL_MAP");
+        assert_ne!(
+            variant_file_name("shaders/pbr.frag", &permutation),
+            variant_file_name("shaders/terrain/pbr.frag", &permutation)
+        );
+        assert_eq!(
+            variant_file_name("shaders/pbr.frag", &permutation),
+            variant_file_name("shaders/pbr.frag", &permutation)
+        );
+    }
+
+    #[test]
+    fn path_hash_is_fnv1a() {
+        assert_eq!(path_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(path_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn report_counts_compiled_variants() {
+        let report = VariantReport {
+            variants: vec![
+                Variant {
+                    source: "shaders/pbr.frag".to_owned(),
+                    permutation: Permutation::new(),
+                    spv: "a.spv".to_owned(),
+                    origin: VariantOrigin::OnDisk,
+                },
+                Variant {
+                    source: "shaders/pbr.frag".to_owned(),
+                    permutation: Permutation::new().with("NORMAL_MAP"),
+                    spv: "b.spv".to_owned(),
+                    origin: VariantOrigin::Compiled(Duration::from_millis(30)),
+                },
+            ],
+        };
+        assert_eq!(report.compiled(), 1);
+        assert_eq!(report.compile_time(), Duration::from_millis(30));
+        let text = report.to_string();
+        assert!(text.starts_with("2 shader variants, 1 compiled in 30 ms"));
+        assert!(text.contains("[NORMAL_MAP]"));
+        assert!(text.contains("[default]"));
+    }
+}
@@ -1,201 +1,1096 @@
-use std::ptr;
+use std::{ffi::c_void, ptr};
 
-use ash::vk::{self, StructureType};
+use ash::{
+    prelude::VkResult,
+    vk::{self, StructureType},
+};
 
-use crate::{constant::Vertex, utility};
-use anyhow::Result;
+use crate::error::{Error, Result};
+use crate::{constant::Vertex, features::DeviceFeatures, reflect, utility};
 
+/// # Safety
+/// `render_pass` has to be a render pass of `device` with one color and one depth attachment.
 pub unsafe fn create_pipeline_layout(
     device: &ash::Device,
-    swapchain_extent: vk::Extent2D,
     render_pass: vk::RenderPass,
 ) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
-    let frag_bytes = utility::read_file("shaders/spv/frag.spv")?;
-    let vert_bytes = utility::read_file("shaders/spv/vert.spv")?;
+    PipelineBuilder::new("shaders/spv/vert.spv", "shaders/spv/frag.spv")
+        .vertex_input(
+            &[Vertex::get_binding_description()],
+            &Vertex::get_input_attribute_description(),
+        )
+        .build(device, render_pass)
+}
+
+/// `PipelineBuilder::sample_shading` needs the sampleRateShading feature.
+pub fn sample_shading_features() -> DeviceFeatures {
+    DeviceFeatures {
+        sample_rate_shading: true,
+        ..Default::default()
+    }
+}
+
+/// `PipelineBuilder::depth_clamp` needs the depthClamp feature.
+pub fn depth_clamp_features() -> DeviceFeatures {
+    DeviceFeatures {
+        depth_clamp: true,
+        ..Default::default()
+    }
+}
+
+/// `PipelineBuilder::depth_bounds` needs the depthBounds feature.
+pub fn depth_bounds_features() -> DeviceFeatures {
+    DeviceFeatures {
+        depth_bounds: true,
+        ..Default::default()
+    }
+}
+
+/// `PipelineBuilder::geometry_shader` needs the geometryShader feature.
+pub fn geometry_shader_features() -> DeviceFeatures {
+    DeviceFeatures {
+        geometry_shader: true,
+        ..Default::default()
+    }
+}
+
+/// `PipelineBuilder::tessellation` needs the tessellationShader feature.
+pub fn tessellation_features() -> DeviceFeatures {
+    DeviceFeatures {
+        tessellation_shader: true,
+        ..Default::default()
+    }
+}
+
+/// `PipelineBuilder::line_width` wider than 1 needs the wideLines feature, points written with a
+/// gl_PointSize above 1 need largePoints.
+pub fn wide_lines_features() -> DeviceFeatures {
+    DeviceFeatures {
+        wide_lines: true,
+        large_points: true,
+        ..Default::default()
+    }
+}
+
+/// `width` clamped to what the device draws, 1 without the wideLines feature.
+/// Dynamic line widths passed to `vkCmdSetLineWidth` have to go through it too.
+pub fn supported_line_width(width: f32, enabled: &DeviceFeatures, limits: &vk::PhysicalDeviceLimits) -> f32 {
+    if !enabled.wide_lines {
+        return 1.0;
+    }
+    let [min, max] = limits.line_width_range;
+    width.clamp(min, max)
+}
+
+/// Width of lines drawn with a line topology or the LINE polygon mode.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LineWidth {
+    Static(f32),
+    /// set with `vkCmdSetLineWidth` before drawing, plots for example change it per series
+    Dynamic,
+}
+
+impl Default for LineWidth {
+    fn default() -> Self {
+        LineWidth::Static(1.0)
+    }
+}
+
+/// Control and evaluation shaders between the vertex and the next stage, spv files like the other stages.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Tessellation {
+    pub control_shader: String,
+    pub evaluation_shader: String,
+    /// vertices of a patch, `maxTessellationPatchSize` is at least 32
+    pub patch_control_points: u32,
+}
+
+/// Range of depth buffer values fragments are kept in, tested against the depth already stored.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DepthBounds {
+    Static {
+        min: f32,
+        max: f32,
+    },
+    /// set with `vkCmdSetDepthBounds` before drawing, light volumes for example pass the depth range of each light
+    Dynamic,
+}
+
+/// Samples per pixel of the render pass a pipeline draws into and how many of them are shaded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Multisampling {
+    /// has to match the attachments of the render pass
+    pub samples: vk::SampleCountFlags,
+    /// shades at least this fraction of the samples separately instead of once per pixel, see
+    /// `PipelineBuilder::sample_shading`
+    pub min_sample_shading: Option<f32>,
+}
+
+impl Default for Multisampling {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            min_sample_shading: None,
+        }
+    }
+}
+
+impl Multisampling {
+    pub fn new(samples: vk::SampleCountFlags) -> Self {
+        Self {
+            samples,
+            ..Default::default()
+        }
+    }
+
+    pub fn sample_shading(mut self, min_fraction: f32) -> Self {
+        self.min_sample_shading = Some(min_fraction);
+        self
+    }
+
+    pub fn is_multisampled(&self) -> bool {
+        self.samples != vk::SampleCountFlags::TYPE_1
+    }
+}
+
+/// Value of a specialization constant, its type has to match the `layout(constant_id = N)` declaration.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpecValue {
+    Bool(bool),
+    I32(i32),
+    U32(u32),
+    F32(f32),
+}
+
+impl SpecValue {
+    /// The type reflection reports for it, None for a bool.
+    pub fn numeric(&self) -> Option<reflect::NumericType> {
+        match self {
+            SpecValue::Bool(_) => None,
+            SpecValue::I32(_) => Some(reflect::NumericType::Sint),
+            SpecValue::U32(_) => Some(reflect::NumericType::Uint),
+            SpecValue::F32(_) => Some(reflect::NumericType::Float),
+        }
+    }
+
+    /// Bools are 32 bit `VkBool32`s like every other value.
+    fn bytes(&self) -> [u8; 4] {
+        match *self {
+            SpecValue::Bool(value) => (value as vk::Bool32).to_ne_bytes(),
+            SpecValue::I32(value) => value.to_ne_bytes(),
+            SpecValue::U32(value) => value.to_ne_bytes(),
+            SpecValue::F32(value) => value.to_ne_bytes(),
+        }
+    }
+}
+
+impl From<bool> for SpecValue {
+    fn from(value: bool) -> Self {
+        SpecValue::Bool(value)
+    }
+}
+
+impl From<i32> for SpecValue {
+    fn from(value: i32) -> Self {
+        SpecValue::I32(value)
+    }
+}
+
+impl From<u32> for SpecValue {
+    fn from(value: u32) -> Self {
+        SpecValue::U32(value)
+    }
+}
+
+impl From<f32> for SpecValue {
+    fn from(value: f32) -> Self {
+        SpecValue::F32(value)
+    }
+}
+
+/// Values for the constant ids of a shader, constants left out keep the default of the shader.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpecializationConstants {
+    values: Vec<(u32, SpecValue)>,
+}
+
+impl SpecializationConstants {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the value of `constant_id` when it was set before.
+    pub fn set(mut self, constant_id: u32, value: impl Into<SpecValue>) -> Self {
+        self.insert(constant_id, value.into());
+        self
+    }
+
+    pub fn insert(&mut self, constant_id: u32, value: SpecValue) {
+        match self.values.iter_mut().find(|(id, _)| *id == constant_id) {
+            Some(entry) => entry.1 = value,
+            None => self.values.push((constant_id, value)),
+        }
+    }
+
+    pub fn get(&self, constant_id: u32) -> Option<SpecValue> {
+        self.values.iter().find(|(id, _)| *id == constant_id).map(|(_, value)| *value)
+    }
+
+    pub fn values(&self) -> &[(u32, SpecValue)] {
+        &self.values
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Map entries and the data they point into, for a `vk::SpecializationInfo`.
+    pub fn map_entries(&self) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+        let mut data = Vec::with_capacity(self.values.len() * 4);
+        let entries = self
+            .values
+            .iter()
+            .map(|(constant_id, value)| {
+                let offset = data.len() as u32;
+                data.extend_from_slice(&value.bytes());
+                vk::SpecializationMapEntry {
+                    constant_id: *constant_id,
+                    offset,
+                    size: 4,
+                }
+            })
+            .collect();
+        (entries, data)
+    }
+}
+
+/// Pipeline a derivative is created from, drivers can create derivatives and switch between them faster.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PipelineBase {
+    /// an existing pipeline built with `allow_derivatives`
+    Handle(vk::Pipeline),
+    /// an earlier entry of the same `build_pipelines` call
+    Index(usize),
+}
+
+/// Describes a graphics pipeline, viewport and scissor are always dynamic state.
+/// Clone it to describe variants that only differ in a few states or constants.
+#[derive(Clone)]
+pub struct PipelineBuilder {
+    vertex_shader: String,
+    fragment_shader: String,
+    geometry_shader: Option<String>,
+    tessellation: Option<Tessellation>,
+    binding_descriptions: Vec<vk::VertexInputBindingDescription>,
+    attribute_descriptions: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    polygon_mode: vk::PolygonMode,
+    primitive_restart: bool,
+    line_width: LineWidth,
+    /// `limits.line_width_range`, None trusts the width
+    line_width_range: Option<[f32; 2]>,
+    cull_mode: vk::CullModeFlags,
+    front_face: vk::FrontFace,
+    alpha_blending: bool,
+    depth_test: bool,
+    depth_write: bool,
+    multisampling: Multisampling,
+    alpha_to_coverage: bool,
+    depth_clamp: bool,
+    depth_bounds: Option<DepthBounds>,
+    /// None trusts that every requested feature is enabled
+    enabled_features: Option<DeviceFeatures>,
+    set_layouts: Vec<vk::DescriptorSetLayout>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    /// applied to every stage in the flags, later entries override earlier ones
+    specializations: Vec<(vk::ShaderStageFlags, SpecializationConstants)>,
+    allow_derivatives: bool,
+    base: Option<PipelineBase>,
+    subpass: u32,
+}
+
+impl PipelineBuilder {
+    /// shader paths are spv files relative to the project
+    pub fn new(vertex_shader: &str, fragment_shader: &str) -> Self {
+        Self {
+            vertex_shader: vertex_shader.to_owned(),
+            fragment_shader: fragment_shader.to_owned(),
+            geometry_shader: None,
+            tessellation: None,
+            binding_descriptions: vec![],
+            attribute_descriptions: vec![],
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            polygon_mode: vk::PolygonMode::FILL,
+            primitive_restart: false,
+            line_width: LineWidth::default(),
+            line_width_range: None,
+            cull_mode: vk::CullModeFlags::BACK,
+            front_face: vk::FrontFace::CLOCKWISE,
+            alpha_blending: false,
+            depth_test: false,
+            depth_write: false,
+            multisampling: Multisampling::default(),
+            alpha_to_coverage: false,
+            depth_clamp: false,
+            depth_bounds: None,
+            enabled_features: None,
+            set_layouts: vec![],
+            push_constant_ranges: vec![],
+            specializations: vec![],
+            allow_derivatives: false,
+            base: None,
+            subpass: 0,
+        }
+    }
+
+    pub fn vertex_input(
+        mut self,
+        bindings: &[vk::VertexInputBindingDescription],
+        attributes: &[vk::VertexInputAttributeDescription],
+    ) -> Self {
+        self.binding_descriptions.extend_from_slice(bindings);
+        self.attribute_descriptions.extend_from_slice(attributes);
+        self
+    }
+
+    /// Runs `geometry_shader` on every primitive after the vertex or tessellation stage, for expanding points
+    /// into sprites or rendering to several layers. Needs `geometry_shader_features`.
+    pub fn geometry_shader(mut self, geometry_shader: &str) -> Self {
+        self.geometry_shader = Some(geometry_shader.to_owned());
+        self
+    }
+
+    /// Tessellates patches of `patch_control_points` vertices, terrain for example, and switches the topology
+    /// to PATCH_LIST. Needs `tessellation_features`.
+    pub fn tessellation(mut self, control_shader: &str, evaluation_shader: &str, patch_control_points: u32) -> Self {
+        self.tessellation = Some(Tessellation {
+            control_shader: control_shader.to_owned(),
+            evaluation_shader: evaluation_shader.to_owned(),
+            patch_control_points,
+        });
+        self.topology = vk::PrimitiveTopology::PATCH_LIST;
+        self
+    }
+
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+
+    /// POINT_LIST, the vertex shader sets gl_PointSize. Point clouds and markers use it.
+    pub fn points(self) -> Self {
+        self.topology(vk::PrimitiveTopology::POINT_LIST)
+    }
+
+    /// LINE_LIST, or LINE_STRIP with `strip`, for debug drawing and plots.
+    pub fn lines(self, strip: bool, width: LineWidth) -> Self {
+        let topology = match strip {
+            true => vk::PrimitiveTopology::LINE_STRIP,
+            false => vk::PrimitiveTopology::LINE_LIST,
+        };
+        self.topology(topology).line_width(width)
+    }
+
+    /// Widths above 1 need `wide_lines_features`, without them lines are drawn 1 pixel wide with a warning.
+    pub fn line_width(mut self, width: LineWidth) -> Self {
+        self.line_width = width;
+        self
+    }
+
+    /// Clamps a static line width to `limits.line_width_range` of the device.
+    pub fn line_width_range(mut self, range: [f32; 2]) -> Self {
+        self.line_width_range = Some(range);
+        self
+    }
+
+    /// An index of 0xffff or 0xffffffff starts a new strip, so several plotted series draw in one call.
+    /// Only strip and fan topologies restart, it is ignored for the others.
+    pub fn primitive_restart(mut self, enabled: bool) -> Self {
+        self.primitive_restart = enabled;
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: vk::CullModeFlags, front_face: vk::FrontFace) -> Self {
+        self.cull_mode = cull_mode;
+        self.front_face = front_face;
+        self
+    }
+
+    /// standard "over" blending, src_alpha * src + (1 - src_alpha) * dst
+    pub fn alpha_blending(mut self, enabled: bool) -> Self {
+        self.alpha_blending = enabled;
+        self
+    }
+
+    /// closer fragments win, `write` is usually off for transparent geometry
+    pub fn depth_test(mut self, enabled: bool, write: bool) -> Self {
+        self.depth_test = enabled;
+        self.depth_write = enabled && write;
+        self
+    }
+
+    /// has to match the sample count of the render pass attachments
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.multisampling.samples = samples;
+        self
+    }
+
+    /// Shades at least `min_fraction` of the samples of a pixel separately, so detail inside triangles like
+    /// alpha tested texels antialiases too, at up to the sample count times the fragment work.
+    /// None shades once per pixel. Needs `sample_shading_features`.
+    pub fn sample_shading(mut self, min_fraction: Option<f32>) -> Self {
+        self.multisampling.min_sample_shading = min_fraction;
+        self
+    }
+
+    pub fn multisampling(mut self, multisampling: Multisampling) -> Self {
+        self.multisampling = multisampling;
+        self
+    }
+
+    /// The alpha of the first color output decides how many samples a fragment covers, so alpha tested
+    /// geometry like foliage and fences gets smooth edges under MSAA without sorting. Does nothing useful on
+    /// single sampled render passes.
+    pub fn alpha_to_coverage(mut self, enabled: bool) -> Self {
+        self.alpha_to_coverage = enabled;
+        self
+    }
+
+    /// Clamps depth to the viewport range instead of clipping at the near and far plane, shadow maps use it to
+    /// pancake casters in front of the light frustum onto the near plane. Needs `depth_clamp_features`.
+    pub fn depth_clamp(mut self, enabled: bool) -> Self {
+        self.depth_clamp = enabled;
+        self
+    }
+
+    /// Discards fragments whose pixel already has a depth outside of `bounds`, so light volumes skip pixels
+    /// out of their reach. Only an optimization, without `depth_bounds_features` every fragment is shaded.
+    pub fn depth_bounds(mut self, bounds: Option<DepthBounds>) -> Self {
+        self.depth_bounds = bounds;
+        self
+    }
+
+    /// The features the device was created with, requested options it lacks are left out with a warning.
+    pub fn enabled_features(mut self, enabled: DeviceFeatures) -> Self {
+        self.enabled_features = Some(enabled);
+        self
+    }
+
+    fn has_feature(&self, enabled: impl Fn(&DeviceFeatures) -> bool, option: &str) -> bool {
+        match self.enabled_features.as_ref() {
+            Some(features) if !enabled(features) => {
+                log::warn!(
+                    "{} isn't enabled on the device, building {} without it",
+                    option,
+                    self.fragment_shader
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    /// Whether the pipeline gets depth clamping, clipped shadow casters need another way to reach the map
+    /// without it, like clamping gl_Position.z in the vertex shader.
+    pub fn uses_depth_clamp(&self) -> bool {
+        self.depth_clamp && self.enabled_features.is_none_or(|features| features.depth_clamp)
+    }
+
+    /// Whether the pipeline gets the depth bounds test, and with `DepthBounds::Dynamic` the dynamic state.
+    pub fn uses_depth_bounds(&self) -> bool {
+        self.depth_bounds.is_some() && self.enabled_features.is_none_or(|features| features.depth_bounds)
+    }
+
+    pub fn descriptor_set_layouts(mut self, set_layouts: &[vk::DescriptorSetLayout]) -> Self {
+        self.set_layouts.extend_from_slice(set_layouts);
+        self
+    }
 
-    let vert_shader = create_shader_module(device, vert_bytes)?;
-    let frag_shader = create_shader_module(device, frag_bytes)?;
+    pub fn push_constant_range(mut self, stage_flags: vk::ShaderStageFlags, offset: u32, size: u32) -> Self {
+        self.push_constant_ranges.push(vk::PushConstantRange {
+            stage_flags,
+            offset,
+            size,
+        });
+        self
+    }
+
+    pub fn subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
+
+    /// Lets other pipelines derive from this one.
+    pub fn allow_derivatives(mut self, enabled: bool) -> Self {
+        self.allow_derivatives = enabled;
+        self
+    }
+
+    /// Creates the pipeline as a derivative of `base`, which was built with `allow_derivatives`.
+    pub fn derive_from(mut self, base: vk::Pipeline) -> Self {
+        self.base = Some(PipelineBase::Handle(base));
+        self
+    }
+
+    /// Derives from entry `index` of the `build_pipelines` call this builder is passed to.
+    pub fn derive_from_index(mut self, index: usize) -> Self {
+        self.base = Some(PipelineBase::Index(index));
+        self
+    }
+
+    /// Specializes the shaders of `stages`, one shader builds pipelines for different light counts or with
+    /// features toggled instead of a source permutation each. The ids are checked against the shaders when building.
+    pub fn specialization(mut self, stages: vk::ShaderStageFlags, constants: SpecializationConstants) -> Self {
+        self.specializations.push((stages, constants));
+        self
+    }
+
+    /// Sets one constant of the shaders in `stages`, see `specialization`.
+    pub fn specialize(self, stages: vk::ShaderStageFlags, constant_id: u32, value: impl Into<SpecValue>) -> Self {
+        self.specialization(stages, SpecializationConstants::new().set(constant_id, value))
+    }
+
+    /// The constants set for `stage`.
+    pub fn specialization_for(&self, stage: vk::ShaderStageFlags) -> SpecializationConstants {
+        let mut constants = SpecializationConstants::new();
+        for (_, values) in self.specializations.iter().filter(|(stages, _)| stages.contains(stage)) {
+            for &(constant_id, value) in values.values() {
+                constants.insert(constant_id, value);
+            }
+        }
+        constants
+    }
+
+    /// Descriptors the shaders declare, stages that share a binding are merged.
+    pub fn reflect_bindings(&self) -> Result<Vec<reflect::ShaderBinding>> {
+        let mut stages = vec![];
+        for (shader, stage) in self.stages() {
+            let bindings = utility::read_file(shader).and_then(|bytes| reflect::descriptor_bindings(&bytes, stage));
+            stages.push(bindings.map_err(|e| in_shader(shader, e))?);
+        }
+        Ok(reflect::merge_bindings(&stages))
+    }
 
-    let entry_point_name = std::ffi::CString::new("main").expect("CString::new failed");
+    /// Shader files with their stage, in pipeline order.
+    fn stages(&self) -> Vec<(&str, vk::ShaderStageFlags)> {
+        let mut stages = vec![(self.vertex_shader.as_str(), vk::ShaderStageFlags::VERTEX)];
+        if let Some(tessellation) = self.tessellation.as_ref() {
+            stages.push((&tessellation.control_shader, vk::ShaderStageFlags::TESSELLATION_CONTROL));
+            stages.push((&tessellation.evaluation_shader, vk::ShaderStageFlags::TESSELLATION_EVALUATION));
+        }
+        if let Some(geometry_shader) = self.geometry_shader.as_deref() {
+            stages.push((geometry_shader, vk::ShaderStageFlags::GEOMETRY));
+        }
+        stages.push((&self.fragment_shader, vk::ShaderStageFlags::FRAGMENT));
+        stages
+    }
+
+    /// Errors for stages the device was created without, they have no fallback.
+    fn check_stage_features(&self) -> Result<()> {
+        let Some(features) = self.enabled_features.as_ref() else {
+            return Ok(());
+        };
+        let mut missing = vec![];
+        if self.tessellation.is_some() && !features.tessellation_shader {
+            missing.push("tessellationShader");
+        }
+        if self.geometry_shader.is_some() && !features.geometry_shader {
+            missing.push("geometryShader");
+        }
+        if !missing.is_empty() {
+            return Err(Error::MissingFeatures {
+                what: format!("The device building {}", self.vertex_shader),
+                missing,
+            });
+        }
+        Ok(())
+    }
+
+    /// Errors for constants none of their stages declares and for values of the wrong type, the driver would
+    /// ignore the first and read the second as garbage.
+    fn check_specializations(&self, stage_bytes: &[(&str, Vec<u8>, vk::ShaderStageFlags)]) -> Result<()> {
+        if self.specializations.is_empty() {
+            return Ok(());
+        }
+        let mut declared = vec![];
+        for (shader, bytes, stage) in stage_bytes {
+            let constants = reflect::spec_constants(bytes).map_err(|e| in_shader(shader, e))?;
+            declared.push((*shader, *stage, constants));
+        }
+        for (stages, constants) in self.specializations.iter() {
+            for &(constant_id, value) in constants.values() {
+                let mut found = false;
+                for (shader, _, shader_constants) in declared.iter().filter(|(_, stage, _)| stages.contains(*stage)) {
+                    let Some(constant) = shader_constants.iter().find(|c| c.constant_id == constant_id) else {
+                        continue;
+                    };
+                    found = true;
+                    if constant.numeric != value.numeric() {
+                        let declared_as = constant.numeric.map_or("bool".to_owned(), |numeric| {
+                            reflect::ComponentLayout { numeric, components: 1 }.to_string()
+                        });
+                        return Err(Error::shader(
+                            shader,
+                            format!("constant_id {} is a {}, not {:?}", constant_id, declared_as, value),
+                        ));
+                    }
+                }
+                if !found {
+                    return Err(Error::shader(
+                        &self.vertex_shader,
+                        format!("No {:?} shader of the pipeline declares constant_id {}", stages, constant_id),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn shader_names(&self) -> (&str, &str) {
+        (&self.vertex_shader, &self.fragment_shader)
+    }
+
+    /// # Safety
+    /// `render_pass` has to be compatible with the attachments and samples of the builder.
+    pub unsafe fn build(
+        &self,
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+    ) -> Result<(vk::Pipeline, vk::PipelineLayout)> {
+        let mut built = build_pipelines(device, vk::PipelineCache::null(), &[(self, render_pass)])?;
+        Ok(built.remove(0))
+    }
 
-    let mut shader_stages = [
-        vk::PipelineShaderStageCreateInfo {
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+    /// Reads the shaders and creates the modules and layout, the state `vkCreateGraphicsPipelines` needs.
+    unsafe fn prepare(&self, device: &ash::Device, render_pass: vk::RenderPass) -> Result<Prepared> {
+        self.check_stage_features()?;
+        let mut stage_bytes = vec![];
+        for (shader, stage) in self.stages() {
+            stage_bytes.push((shader, utility::read_file(shader).map_err(|e| in_shader(shader, e))?, stage));
+        }
+
+        // a mismatch would otherwise silently read garbage or zeros
+        let inputs = reflect::vertex_inputs(&stage_bytes[0].1).map_err(|e| in_shader(&self.vertex_shader, e))?;
+        reflect::validate_vertex_layout(&self.vertex_shader, &inputs, &self.attribute_descriptions)?;
+        self.check_specializations(&stage_bytes)?;
+
+        let mut modules = vec![];
+        for (shader, bytes, stage) in stage_bytes {
+            match create_shader_module(device, bytes).map_err(|e| in_shader(shader, e)) {
+                Ok(module) => modules.push((module, stage)),
+                Err(e) => {
+                    for (module, _) in modules {
+                        device.destroy_shader_module(module, None);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let entry_point_name = std::ffi::CString::new("main").expect("CString::new failed");
+
+        // the map entries and data have to stay alive until the pipeline is created
+        let specialization_data: Vec<(Vec<vk::SpecializationMapEntry>, Vec<u8>)> = modules
+            .iter()
+            .map(|(_, stage)| self.specialization_for(*stage).map_entries())
+            .collect();
+        let specialization_infos: Vec<vk::SpecializationInfo> = specialization_data
+            .iter()
+            .map(|(entries, data)| vk::SpecializationInfo {
+                map_entry_count: entries.len() as u32,
+                p_map_entries: entries.as_ptr(),
+                data_size: data.len(),
+                p_data: data.as_ptr() as *const c_void,
+            })
+            .collect();
+
+        let shader_stages: Vec<vk::PipelineShaderStageCreateInfo> = modules
+            .iter()
+            .zip(specialization_infos.iter())
+            .map(|((module, stage), specialization_info)| vk::PipelineShaderStageCreateInfo {
+                s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+                p_next: ptr::null(),
+                flags: vk::PipelineShaderStageCreateFlags::empty(),
+                stage: *stage,
+                module: *module,
+                p_name: entry_point_name.as_ptr(),
+                p_specialization_info: if specialization_info.map_entry_count == 0 {
+                    ptr::null()
+                } else {
+                    specialization_info
+                },
+            })
+            .collect();
+
+        let tessellation_state = vk::PipelineTessellationStateCreateInfo {
+            patch_control_points: self
+                .tessellation
+                .as_ref()
+                .map_or(0, |tessellation| tessellation.patch_control_points),
+            ..Default::default()
+        };
+
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
             p_next: ptr::null(),
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            stage: vk::ShaderStageFlags::VERTEX,
-            module: vert_shader,
-            p_name: entry_point_name.as_ptr(),
-            p_specialization_info: ptr::null(),
-        },
-        vk::PipelineShaderStageCreateInfo {
-            s_type: vk::StructureType::PIPELINE_SHADER_STAGE_CREATE_INFO,
+            flags: vk::PipelineVertexInputStateCreateFlags::empty(),
+            vertex_binding_description_count: self.binding_descriptions.len() as u32,
+            p_vertex_binding_descriptions: self.binding_descriptions.as_ptr(),
+            vertex_attribute_description_count: self.attribute_descriptions.len() as u32,
+            p_vertex_attribute_descriptions: self.attribute_descriptions.as_ptr(),
+        };
+
+        // viewport and scissor are set when recording the command buffer
+        let view_state = vk::PipelineViewportStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
             p_next: ptr::null(),
-            flags: vk::PipelineShaderStageCreateFlags::empty(),
-            stage: vk::ShaderStageFlags::FRAGMENT,
-            module: frag_shader,
-            p_name: entry_point_name.as_ptr(),
-            p_specialization_info: ptr::null(),
-        },
-    ];
+            flags: vk::PipelineViewportStateCreateFlags::empty(),
+            viewport_count: 1,
+            p_viewports: ptr::null(),
+            scissor_count: 1,
+            p_scissors: ptr::null(),
+        };
 
-    let binding_description = Vertex::get_binding_description();
-    let attribute_description = Vertex::get_input_attribute_description();
+        let depth_clamp = self.depth_clamp && self.has_feature(|features| features.depth_clamp, "depthClamp");
+        let depth_bounds = self
+            .depth_bounds
+            .filter(|_| self.has_feature(|features| features.depth_bounds, "depthBounds"));
+        let min_sample_shading = self
+            .multisampling
+            .min_sample_shading
+            .filter(|_| self.has_feature(|features| features.sample_rate_shading, "sampleRateShading"));
+        let line_width = match self.line_width {
+            LineWidth::Static(width) if width > 1.0 && !self.has_feature(|features| features.wide_lines, "wideLines") => 1.0,
+            LineWidth::Static(width) => match self.line_width_range {
+                Some([min, max]) => width.clamp(min, max),
+                None => width,
+            },
+            LineWidth::Dynamic => 1.0,
+        };
+        let primitive_restart = self.primitive_restart && is_strip(self.topology);
+        if self.primitive_restart && !primitive_restart {
+            log::warn!(
+                "{:?} doesn't restart primitives, building {} without it",
+                self.topology,
+                self.vertex_shader
+            );
+        }
+        let mut states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
+        if depth_bounds == Some(DepthBounds::Dynamic) {
+            states.push(vk::DynamicState::DEPTH_BOUNDS);
+        }
+        if self.line_width == LineWidth::Dynamic {
+            states.push(vk::DynamicState::LINE_WIDTH);
+        }
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO,
+            dynamic_state_count: states.len() as u32,
+            p_dynamic_states: states.as_ptr(),
+            ..Default::default()
+        };
 
-    let vertex_input = vk::PipelineVertexInputStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineVertexInputStateCreateFlags::empty(),
-        vertex_binding_description_count: 1,
-        p_vertex_binding_descriptions: &binding_description,
-        vertex_attribute_description_count: attribute_description.len() as u32,
-        p_vertex_attribute_descriptions: attribute_description.as_ptr(),
-    };
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo {
+            s_type: StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO,
+            topology: self.topology,
+            primitive_restart_enable: primitive_restart as vk::Bool32,
+            ..Default::default()
+        };
 
-    let vertex_assembly_info = vk::PipelineInputAssemblyStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VERTEX_INPUT_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineInputAssemblyStateCreateFlags::empty(),
-        topology: vk::PrimitiveTopology::TRIANGLE_LIST,
-        primitive_restart_enable: vk::FALSE,
-    };
+        let rasterizer = vk::PipelineRasterizationStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO,
+            depth_clamp_enable: depth_clamp as vk::Bool32,
+            rasterizer_discard_enable: vk::FALSE,
+            // fills the primitive triangle
+            polygon_mode: self.polygon_mode,
+            line_width,
+            // face is forward or whatever
+            cull_mode: self.cull_mode,
+            front_face: self.front_face,
+            // can be used for shadow mapping
+            depth_bias_enable: vk::FALSE,
+            depth_bias_constant_factor: 0.0,
+            depth_bias_clamp: 0.0,
+            depth_bias_slope_factor: 0.0,
+            ..Default::default()
+        };
 
-    let mut view_port = vk::Viewport::default();
-    view_port.x = 0.0;
-    view_port.y = 0.0;
-    view_port.width = swapchain_extent.width as f32;
-    view_port.height = swapchain_extent.height as f32;
-    view_port.min_depth = 0.0;
-    view_port.max_depth = 1.0;
+        let multi_sampling = vk::PipelineMultisampleStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO,
+            sample_shading_enable: min_sample_shading.is_some() as vk::Bool32,
+            rasterization_samples: self.multisampling.samples,
+            min_sample_shading: min_sample_shading.unwrap_or(1.0).clamp(0.0, 1.0),
+            p_sample_mask: std::ptr::null(),
+            alpha_to_coverage_enable: self.alpha_to_coverage as vk::Bool32,
+            alpha_to_one_enable: vk::FALSE,
+            ..Default::default()
+        };
 
-    let mut scissor = vk::Rect2D::default();
-    scissor.offset = vk::Offset2D { x: 0, y: 0 };
-    scissor.extent = swapchain_extent;
+        let (blend_enable, src_color_blend_factor, dst_color_blend_factor, dst_alpha_blend_factor) = if self.alpha_blending {
+            (
+                vk::TRUE,
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            )
+        } else {
+            (vk::FALSE, vk::BlendFactor::ONE, vk::BlendFactor::ZERO, vk::BlendFactor::ZERO)
+        };
+        let color_blend_attachment = vk::PipelineColorBlendAttachmentState {
+            color_write_mask: vk::ColorComponentFlags::R
+                | vk::ColorComponentFlags::G
+                | vk::ColorComponentFlags::B
+                | vk::ColorComponentFlags::A,
+            blend_enable,
+            src_color_blend_factor,
+            dst_color_blend_factor,
+            src_alpha_blend_factor: vk::BlendFactor::ONE,
+            dst_alpha_blend_factor,
+            color_blend_op: vk::BlendOp::ADD,
+            alpha_blend_op: vk::BlendOp::ADD,
+        };
 
-    let mut view_state = vk::PipelineViewportStateCreateInfo {
-        s_type: vk::StructureType::PIPELINE_VIEWPORT_STATE_CREATE_INFO,
-        p_next: ptr::null(),
-        flags: vk::PipelineViewportStateCreateFlags::empty(),
-        viewport_count: 1,
-        p_viewports: &view_port,
-        scissor_count: 1,
-        p_scissors: &scissor,
-    };
+        let color_blend_attachments = vec![color_blend_attachment];
+        let color_blending = vk::PipelineColorBlendStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO,
+            logic_op_enable: vk::FALSE,
+            logic_op: vk::LogicOp::COPY,
+            attachment_count: 1,
+            p_attachments: color_blend_attachments.as_ptr(),
+            blend_constants: [0.0; 4],
+            ..Default::default()
+        };
 
-    let states = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
-    let mut dynamic_state = vk::PipelineDynamicStateCreateInfo::default();
-
-    dynamic_state.s_type = vk::StructureType::PIPELINE_DYNAMIC_STATE_CREATE_INFO;
-    dynamic_state.dynamic_state_count = states.len() as u32;
-    dynamic_state.p_dynamic_states = states.as_ptr();
-
-    let mut input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default();
-    input_assembly.s_type = StructureType::PIPELINE_INPUT_ASSEMBLY_STATE_CREATE_INFO;
-    input_assembly.topology = vk::PrimitiveTopology::TRIANGLE_LIST;
-    input_assembly.primitive_restart_enable = vk::FALSE;
-
-    let mut rasterizer = vk::PipelineRasterizationStateCreateInfo::default();
-    rasterizer.s_type = vk::StructureType::PIPELINE_RASTERIZATION_STATE_CREATE_INFO;
-    rasterizer.depth_clamp_enable = vk::FALSE;
-    rasterizer.rasterizer_discard_enable = vk::FALSE;
-    // fills the primitive triangle
-    rasterizer.polygon_mode = vk::PolygonMode::FILL;
-    rasterizer.line_width = 1.0;
-
-    // face is forward or whatever
-    rasterizer.cull_mode = vk::CullModeFlags::BACK;
-    rasterizer.front_face = vk::FrontFace::CLOCKWISE;
-
-    // can be used for shadow mapping
-    rasterizer.depth_bias_enable = vk::FALSE;
-    rasterizer.depth_bias_constant_factor = 0.0;
-    rasterizer.depth_bias_clamp = 0.0;
-    rasterizer.depth_bias_slope_factor = 0.0;
-
-    let mut multi_sampling = vk::PipelineMultisampleStateCreateInfo::default();
-    multi_sampling.s_type = vk::StructureType::PIPELINE_MULTISAMPLE_STATE_CREATE_INFO;
-    multi_sampling.sample_shading_enable = vk::FALSE;
-    multi_sampling.rasterization_samples = vk::SampleCountFlags::TYPE_1;
-    multi_sampling.min_sample_shading = 1.0;
-    multi_sampling.p_sample_mask = std::ptr::null();
-    multi_sampling.alpha_to_coverage_enable = vk::FALSE;
-    multi_sampling.alpha_to_one_enable = vk::FALSE;
-
-    let mut color_blend_attachment = vk::PipelineColorBlendAttachmentState::default();
-    color_blend_attachment.color_write_mask =
-        vk::ColorComponentFlags::R | vk::ColorComponentFlags::G | vk::ColorComponentFlags::B | vk::ColorComponentFlags::A;
-    color_blend_attachment.blend_enable = vk::FALSE;
-    color_blend_attachment.src_color_blend_factor = vk::BlendFactor::ONE;
-    color_blend_attachment.dst_color_blend_factor = vk::BlendFactor::ZERO;
-    color_blend_attachment.src_alpha_blend_factor = vk::BlendFactor::ONE;
-    color_blend_attachment.dst_alpha_blend_factor = vk::BlendFactor::ZERO;
-
-    color_blend_attachment.color_blend_op = vk::BlendOp::ADD;
-    color_blend_attachment.alpha_blend_op = vk::BlendOp::ADD;
-
-    let mut color_blending = vk::PipelineColorBlendStateCreateInfo::default();
-    color_blending.s_type = vk::StructureType::PIPELINE_COLOR_BLEND_STATE_CREATE_INFO;
-    color_blending.logic_op_enable = vk::FALSE;
-    color_blending.logic_op = vk::LogicOp::COPY;
-    color_blending.attachment_count = 1;
-    color_blending.p_attachments = &color_blend_attachment;
-    color_blending.blend_constants[0] = 0.0;
-    color_blending.blend_constants[1] = 0.0;
-    color_blending.blend_constants[2] = 0.0;
-    color_blending.blend_constants[3] = 0.0;
-
-    let mut pipeline_layout_info = vk::PipelineLayoutCreateInfo::default();
-    pipeline_layout_info.s_type = vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO;
-    pipeline_layout_info.set_layout_count = 0;
-    pipeline_layout_info.p_set_layouts = std::ptr::null();
-    pipeline_layout_info.push_constant_range_count = 0;
-    pipeline_layout_info.p_push_constant_ranges = std::ptr::null();
-
-    let layout = device.create_pipeline_layout(&pipeline_layout_info, None)?;
-
-    let mut info = vk::GraphicsPipelineCreateInfo::default();
-    info.s_type = vk::StructureType::GRAPHICS_PIPELINE_CREATE_INFO;
-
-    info.stage_count = 2;
-    info.p_stages = shader_stages.as_ptr();
-    info.p_vertex_input_state = &vertex_input;
-    info.p_viewport_state = &view_state;
-    info.p_rasterization_state = &rasterizer;
-    info.p_multisample_state = &multi_sampling;
-    info.p_depth_stencil_state = std::ptr::null();
-    info.p_color_blend_state = &color_blending;
-    info.p_dynamic_state = &dynamic_state;
-    info.layout = layout;
-    info.render_pass = render_pass;
-    info.subpass = 0;
-    info.base_pipeline_handle = vk::Pipeline::null();
-    info.base_pipeline_index = -1;
-    info.p_input_assembly_state = &input_assembly;
-
-    let pipeline = device
-        .create_graphics_pipelines(vk::PipelineCache::null(), &[info], None)
-        .expect("Error creating the graphic pipeline");
-
-    device.destroy_shader_module(vert_shader, None);
-    device.destroy_shader_module(frag_shader, None);
-
-    Ok((pipeline[0], layout))
-}
-
-unsafe fn create_shader_module(device: &ash::Device, bytes: Vec<u8>) -> Result<vk::ShaderModule> {
-    let mut create_info = vk::ShaderModuleCreateInfo::default();
-
-    create_info.s_type = vk::StructureType::SHADER_MODULE_CREATE_INFO;
-    create_info.p_next = std::ptr::null();
-    create_info.flags = vk::ShaderModuleCreateFlags::empty();
-    create_info.code_size = bytes.len();
-    create_info.p_code = bytes.as_ptr() as *const u32; // MAY HAVE TO DO SOMETHING HERE
+        let (min_depth_bounds, max_depth_bounds) = match depth_bounds {
+            Some(DepthBounds::Static { min, max }) => (min, max),
+            _ => (0.0, 1.0),
+        };
+        let depth_stencil = vk::PipelineDepthStencilStateCreateInfo {
+            s_type: vk::StructureType::PIPELINE_DEPTH_STENCIL_STATE_CREATE_INFO,
+            depth_test_enable: self.depth_test as vk::Bool32,
+            depth_write_enable: self.depth_write as vk::Bool32,
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+            depth_bounds_test_enable: depth_bounds.is_some() as vk::Bool32,
+            stencil_test_enable: vk::FALSE,
+            min_depth_bounds,
+            max_depth_bounds,
+            ..Default::default()
+        };
+
+        let pipeline_layout_info = vk::PipelineLayoutCreateInfo {
+            s_type: vk::StructureType::PIPELINE_LAYOUT_CREATE_INFO,
+            set_layout_count: self.set_layouts.len() as u32,
+            p_set_layouts: self.set_layouts.as_ptr(),
+            push_constant_range_count: self.push_constant_ranges.len() as u32,
+            p_push_constant_ranges: self.push_constant_ranges.as_ptr(),
+            ..Default::default()
+        };
+
+        let layout = match device.create_pipeline_layout(&pipeline_layout_info, None) {
+            Ok(layout) => layout,
+            Err(e) => {
+                for (module, _) in modules {
+                    device.destroy_shader_module(module, None);
+                }
+                return Err(e.into());
+            }
+        };
+
+        let mut flags = vk::PipelineCreateFlags::empty();
+        if self.allow_derivatives {
+            flags |= vk::PipelineCreateFlags::ALLOW_DERIVATIVES;
+        }
+        let (base_pipeline_handle, base_pipeline_index) = match self.base {
+            Some(PipelineBase::Handle(base)) => (base, -1),
+            Some(PipelineBase::Index(index)) => (vk::Pipeline::null(), index as i32),
+            None => (vk::Pipeline::null(), -1),
+        };
+        if self.base.is_some() {
+            flags |= vk::PipelineCreateFlags::DERIVATIVE;
+        }
+
+        Ok(Prepared {
+            modules: modules.into_iter().map(|(module, _)| module).collect(),
+            _entry_point_name: entry_point_name,
+            _specialization_data: specialization_data,
+            _specialization_infos: specialization_infos,
+            shader_stages,
+            tessellation_state: self.tessellation.is_some().then_some(tessellation_state),
+            vertex_input,
+            view_state,
+            _dynamic_states: states,
+            dynamic_state,
+            input_assembly,
+            rasterizer,
+            multi_sampling,
+            _color_blend_attachments: color_blend_attachments,
+            color_blending,
+            depth_stencil,
+            layout,
+            render_pass,
+            subpass: self.subpass,
+            flags,
+            base_pipeline_handle,
+            base_pipeline_index,
+        })
+    }
+}
+
+/// Create info state of one pipeline, kept in one place so the pointers of `info` stay valid until
+/// `vkCreateGraphicsPipelines` returned.
+struct Prepared {
+    modules: Vec<vk::ShaderModule>,
+    _entry_point_name: std::ffi::CString,
+    _specialization_data: Vec<(Vec<vk::SpecializationMapEntry>, Vec<u8>)>,
+    _specialization_infos: Vec<vk::SpecializationInfo>,
+    shader_stages: Vec<vk::PipelineShaderStageCreateInfo>,
+    tessellation_state: Option<vk::PipelineTessellationStateCreateInfo>,
+    vertex_input: vk::PipelineVertexInputStateCreateInfo,
+    view_state: vk::PipelineViewportStateCreateInfo,
+    _dynamic_states: Vec<vk::DynamicState>,
+    dynamic_state: vk::PipelineDynamicStateCreateInfo,
+    input_assembly: vk::PipelineInputAssemblyStateCreateInfo,
+    rasterizer: vk::PipelineRasterizationStateCreateInfo,
+    multi_sampling: vk::PipelineMultisampleStateCreateInfo,
+    _color_blend_attachments: Vec<vk::PipelineColorBlendAttachmentState>,
+    color_blending: vk::PipelineColorBlendStateCreateInfo,
+    depth_stencil: vk::PipelineDepthStencilStateCreateInfo,
+    layout: vk::PipelineLayout,
+    render_pass: vk::RenderPass,
+    subpass: u32,
+    flags: vk::PipelineCreateFlags,
+    base_pipeline_handle: vk::Pipeline,
+    base_pipeline_index: i32,
+}
+
+impl Prepared {
+    fn info(&self) -> vk::GraphicsPipelineCreateInfo {
+        vk::GraphicsPipelineCreateInfo {
+            flags: self.flags,
+            stage_count: self.shader_stages.len() as u32,
+            p_stages: self.shader_stages.as_ptr(),
+            p_vertex_input_state: &self.vertex_input,
+            p_input_assembly_state: &self.input_assembly,
+            p_tessellation_state: self.tessellation_state.as_ref().map_or(ptr::null(), |state| state),
+            p_viewport_state: &self.view_state,
+            p_rasterization_state: &self.rasterizer,
+            p_multisample_state: &self.multi_sampling,
+            p_depth_stencil_state: &self.depth_stencil,
+            p_color_blend_state: &self.color_blending,
+            p_dynamic_state: &self.dynamic_state,
+            layout: self.layout,
+            render_pass: self.render_pass,
+            subpass: self.subpass,
+            base_pipeline_handle: self.base_pipeline_handle,
+            base_pipeline_index: self.base_pipeline_index,
+            ..Default::default()
+        }
+    }
+
+    unsafe fn destroy_modules(&self, device: &ash::Device) {
+        for module in self.modules.iter() {
+            device.destroy_shader_module(*module, None);
+        }
+    }
+}
+
+/// Builds the pipelines of `builds` with one `vkCreateGraphicsPipelines` call, so drivers can compile the variants
+/// in parallel and derivatives can name their base by index. Returns pipelines and layouts in the order of `builds`,
+/// when one fails none of them are kept.
+///
+/// # Safety
+/// As for `PipelineBuilder::build`, `cache` has to be null or a cache of `device`.
+pub unsafe fn build_pipelines(
+    device: &ash::Device,
+    cache: vk::PipelineCache,
+    builds: &[(&PipelineBuilder, vk::RenderPass)],
+) -> Result<Vec<(vk::Pipeline, vk::PipelineLayout)>> {
+    for (index, (builder, _)) in builds.iter().enumerate() {
+        if let Some(PipelineBase::Index(base)) = builder.base {
+            if base >= index || !builds[base].0.allow_derivatives {
+                return Err(Error::shader(
+                    &builder.vertex_shader,
+                    format!(
+                        "Derives from pipeline {} of the batch, it has to come earlier and allow derivatives",
+                        base
+                    ),
+                ));
+            }
+        }
+    }
+
+    let mut prepared: Vec<Prepared> = Vec::with_capacity(builds.len());
+    for (builder, render_pass) in builds {
+        match builder.prepare(device, *render_pass) {
+            Ok(pipeline) => prepared.push(pipeline),
+            Err(e) => {
+                for pipeline in prepared.iter() {
+                    pipeline.destroy_modules(device);
+                    device.destroy_pipeline_layout(pipeline.layout, None);
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    let infos: Vec<vk::GraphicsPipelineCreateInfo> = prepared.iter().map(Prepared::info).collect();
+    let created = device.create_graphics_pipelines(cache, &infos, None);
+    for pipeline in prepared.iter() {
+        pipeline.destroy_modules(device);
+    }
+    match created {
+        Ok(pipelines) => Ok(pipelines
+            .into_iter()
+            .zip(prepared.iter().map(|pipeline| pipeline.layout))
+            .collect()),
+        Err((pipelines, e)) => {
+            for pipeline in pipelines.into_iter().filter(|pipeline| *pipeline != vk::Pipeline::null()) {
+                device.destroy_pipeline(pipeline, None);
+            }
+            for pipeline in prepared.iter() {
+                device.destroy_pipeline_layout(pipeline.layout, None);
+            }
+            Err(e.into())
+        }
+    }
+}
+
+/// `error` as an `Error::Shader` of `path`, errors that already name their shader are kept.
+fn in_shader(path: &str, error: Error) -> Error {
+    match error {
+        Error::Shader { .. } => error,
+        error => Error::Shader {
+            path: path.to_owned(),
+            message: error.to_string(),
+            result: error.vk_result(),
+        },
+    }
+}
+
+pub(crate) unsafe fn create_shader_module(device: &ash::Device, bytes: Vec<u8>) -> Result<vk::ShaderModule> {
+    let create_info = vk::ShaderModuleCreateInfo {
+        s_type: vk::StructureType::SHADER_MODULE_CREATE_INFO,
+        p_next: std::ptr::null(),
+        flags: vk::ShaderModuleCreateFlags::empty(),
+        code_size: bytes.len(),
+        p_code: bytes.as_ptr() as *const u32, // MAY HAVE TO DO SOMETHING HERE
+    };
 
     let shader_module = device.create_shader_module(&create_info, None)?;
     Ok(shader_module)
 }
 
-pub unsafe fn create_render_pass(swapchain_format: vk::Format, device: &ash::Device) -> Result<vk::RenderPass> {
+/// # Safety
+/// `swapchain_format` and `depth_format` have to be supported as attachments by the device.
+pub unsafe fn create_render_pass(
+    swapchain_format: vk::Format,
+    depth_format: vk::Format,
+    device: &ash::Device,
+) -> Result<vk::RenderPass> {
     let color_attachment = vk::AttachmentDescription {
         format: swapchain_format,
         flags: vk::AttachmentDescriptionFlags::empty(),
@@ -208,15 +1103,33 @@ pub unsafe fn create_render_pass(swapchain_format: vk::Format, device: &ash::Dev
         final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
     };
 
+    // the depth is only needed while rendering, so it isn't stored
+    let depth_attachment = vk::AttachmentDescription {
+        format: depth_format,
+        flags: vk::AttachmentDescriptionFlags::empty(),
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: vk::AttachmentLoadOp::CLEAR,
+        store_op: vk::AttachmentStoreOp::DONT_CARE,
+        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
     let color_attachment_ref = vk::AttachmentReference {
         attachment: 0,
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
     };
 
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
     let subpasses = [vk::SubpassDescription {
         color_attachment_count: 1,
         p_color_attachments: &color_attachment_ref,
-        p_depth_stencil_attachment: ptr::null(),
+        p_depth_stencil_attachment: &depth_attachment_ref,
         flags: vk::SubpassDescriptionFlags::empty(),
         pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
         input_attachment_count: 0,
@@ -226,15 +1139,16 @@ pub unsafe fn create_render_pass(swapchain_format: vk::Format, device: &ash::Dev
         p_preserve_attachments: ptr::null(),
     }];
 
-    let render_pass_attachments = [color_attachment];
+    let render_pass_attachments = [color_attachment, depth_attachment];
 
     let subpass_dependencies = [vk::SubpassDependency {
         src_subpass: vk::SUBPASS_EXTERNAL,
         dst_subpass: 0,
-        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-        src_access_mask: vk::AccessFlags::empty(),
-        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        // the depth buffer is shared by the frames in flight, the previous frame has to finish its depth tests
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+        dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+        src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+        dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
         dependency_flags: vk::DependencyFlags::empty(),
     }];
 
@@ -256,3 +1170,152 @@ pub unsafe fn create_render_pass(swapchain_format: vk::Format, device: &ash::Dev
             .expect("Failed to create render pass!"))
     }
 }
+
+/// Render pass drawing into an image that is sampled afterwards, the color attachment ends up in
+/// SHADER_READ_ONLY_OPTIMAL layout. The color is cleared when `clear` is true and left undefined otherwise.
+/// With `store_depth` the depth is kept in SHADER_READ_ONLY_OPTIMAL layout as well, so later passes can sample it.
+///
+/// # Safety
+/// `color_format` and `depth_format` have to be supported as attachments by the device.
+pub unsafe fn create_offscreen_render_pass(
+    device: &ash::Device,
+    color_format: vk::Format,
+    depth_format: Option<vk::Format>,
+    clear: bool,
+    store_depth: bool,
+) -> VkResult<vk::RenderPass> {
+    let mut attachments = vec![vk::AttachmentDescription {
+        format: color_format,
+        flags: vk::AttachmentDescriptionFlags::empty(),
+        samples: vk::SampleCountFlags::TYPE_1,
+        load_op: if clear {
+            vk::AttachmentLoadOp::CLEAR
+        } else {
+            vk::AttachmentLoadOp::DONT_CARE
+        },
+        store_op: vk::AttachmentStoreOp::STORE,
+        stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+        stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+        initial_layout: vk::ImageLayout::UNDEFINED,
+        final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+    }];
+    if let Some(depth_format) = depth_format {
+        attachments.push(vk::AttachmentDescription {
+            format: depth_format,
+            flags: vk::AttachmentDescriptionFlags::empty(),
+            samples: vk::SampleCountFlags::TYPE_1,
+            load_op: vk::AttachmentLoadOp::CLEAR,
+            store_op: if store_depth {
+                vk::AttachmentStoreOp::STORE
+            } else {
+                vk::AttachmentStoreOp::DONT_CARE
+            },
+            stencil_load_op: vk::AttachmentLoadOp::DONT_CARE,
+            stencil_store_op: vk::AttachmentStoreOp::DONT_CARE,
+            initial_layout: vk::ImageLayout::UNDEFINED,
+            final_layout: if store_depth {
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+            } else {
+                vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            },
+        });
+    }
+
+    let color_attachment_ref = vk::AttachmentReference {
+        attachment: 0,
+        layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+    };
+    let depth_attachment_ref = vk::AttachmentReference {
+        attachment: 1,
+        layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+    };
+
+    let subpass = vk::SubpassDescription {
+        color_attachment_count: 1,
+        p_color_attachments: &color_attachment_ref,
+        p_depth_stencil_attachment: if depth_format.is_some() {
+            &depth_attachment_ref
+        } else {
+            ptr::null()
+        },
+        flags: vk::SubpassDescriptionFlags::empty(),
+        pipeline_bind_point: vk::PipelineBindPoint::GRAPHICS,
+        input_attachment_count: 0,
+        p_input_attachments: ptr::null(),
+        p_resolve_attachments: ptr::null(),
+        preserve_attachment_count: 0,
+        p_preserve_attachments: ptr::null(),
+    };
+
+    let subpass_dependencies = [
+        // earlier passes may still sample the image or test against the depth
+        vk::SubpassDependency {
+            src_subpass: vk::SUBPASS_EXTERNAL,
+            dst_subpass: 0,
+            src_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER
+                | vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT
+                | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            dst_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+            src_access_mask: vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dependency_flags: vk::DependencyFlags::empty(),
+        },
+        vk::SubpassDependency {
+            src_subpass: 0,
+            dst_subpass: vk::SUBPASS_EXTERNAL,
+            src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+            dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+            src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE | vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            dst_access_mask: vk::AccessFlags::SHADER_READ,
+            dependency_flags: vk::DependencyFlags::empty(),
+        },
+    ];
+
+    let renderpass_create_info = vk::RenderPassCreateInfo {
+        s_type: vk::StructureType::RENDER_PASS_CREATE_INFO,
+        flags: vk::RenderPassCreateFlags::empty(),
+        p_next: ptr::null(),
+        attachment_count: attachments.len() as u32,
+        p_attachments: attachments.as_ptr(),
+        subpass_count: 1,
+        p_subpasses: &subpass,
+        dependency_count: subpass_dependencies.len() as u32,
+        p_dependencies: subpass_dependencies.as_ptr(),
+    };
+    device.create_render_pass(&renderpass_create_info, None)
+}
+
+/// Framebuffer for one set of attachments, in the order of the render pass.
+///
+/// # Safety
+/// `attachments` have to be views of `extent` big images that outlive the framebuffer.
+pub unsafe fn create_framebuffer(
+    device: &ash::Device,
+    render_pass: vk::RenderPass,
+    attachments: &[vk::ImageView],
+    extent: vk::Extent2D,
+) -> VkResult<vk::Framebuffer> {
+    let info = vk::FramebufferCreateInfo {
+        s_type: vk::StructureType::FRAMEBUFFER_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::FramebufferCreateFlags::empty(),
+        render_pass,
+        attachment_count: attachments.len() as u32,
+        p_attachments: attachments.as_ptr(),
+        width: extent.width,
+        height: extent.height,
+        layers: 1,
+    };
+    device.create_framebuffer(&info, None)
+}
+
+fn is_strip(topology: vk::PrimitiveTopology) -> bool {
+    matches!(
+        topology,
+        vk::PrimitiveTopology::LINE_STRIP
+            | vk::PrimitiveTopology::TRIANGLE_STRIP
+            | vk::PrimitiveTopology::TRIANGLE_FAN
+            | vk::PrimitiveTopology::LINE_STRIP_WITH_ADJACENCY
+            | vk::PrimitiveTopology::TRIANGLE_STRIP_WITH_ADJACENCY
+    )
+}
@@ -1,133 +1,87 @@
-// LINUX
-
-#[cfg(target_os = "macos")]
-use ash::extensions::mvk::MacOSSurface;
-#[cfg(target_os = "macos")]
-use cocoa::appkit::{NSView, NSWindow};
-#[cfg(target_os = "macos")]
-use cocoa::base::id as cocoa_id;
-#[cfg(target_os = "macos")]
-use metal::CoreAnimationLayer;
-#[cfg(target_os = "macos")]
-use objc::runtime::YES;
-
-use ash::vk;
-#[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
-pub unsafe fn create_surface(
+use ash::{extensions::ext::DebugUtils, prelude::VkResult, vk};
+use raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle, RawDisplayHandle};
+use std::os::raw::c_char;
+
+/// Creates a surface for any window that exposes raw handles, winit, SDL2 and glfw windows all do.
+/// The instance needs the extensions of `required_extension_names` for the same display.
+///
+/// # Safety
+/// The window has to outlive the surface, `instance` has to be created from `entry`.
+pub unsafe fn create_surface<W: HasRawWindowHandle + HasRawDisplayHandle>(
     entry: &ash::Entry,
     instance: &ash::Instance,
-    window: &winit::window::Window,
+    window: &W,
 ) -> Result<vk::SurfaceKHR, vk::Result> {
-    use ash::extensions::khr::XlibSurface;
-    use std::ptr;
-    use winit::platform::x11::WindowExtX11;
-
-    let x11_display = window.xlib_display().unwrap();
-    let x11_window = window.xlib_window().unwrap();
-    let x11_create_info = vk::XlibSurfaceCreateInfoKHR {
-        s_type: vk::StructureType::XLIB_SURFACE_CREATE_INFO_KHR,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        window: x11_window as vk::Window,
-        dpy: x11_display as *mut vk::Display,
-    };
-    let xlib_surface_loader = XlibSurface::new(entry, instance);
-    xlib_surface_loader.create_xlib_surface(&x11_create_info, None)
+    ash_window::create_surface(entry, instance, window.raw_display_handle(), window.raw_window_handle(), None)
 }
 
-#[cfg(target_os = "macos")]
-pub unsafe fn create_surface<E: EntryV1_0, I: InstanceV1_0>(
-    entry: &E,
-    instance: &I,
-    window: &winit::window::Window,
-) -> Result<vk::SurfaceKHR, vk::Result> {
-    use std::mem;
-    use std::os::raw::c_void;
-    use std::ptr;
-    use winit::platform::macos::WindowExtMacOS;
-
-    let wnd: cocoa_id = mem::transmute(window.ns_window());
-
-    let layer = CoreAnimationLayer::new();
-
-    layer.set_edge_antialiasing_mask(0);
-    layer.set_presents_with_transaction(false);
-    layer.remove_all_animations();
-
-    let view = wnd.contentView();
-
-    layer.set_contents_scale(view.backingScaleFactor());
-    view.setLayer(mem::transmute(layer.as_ref()));
-    view.setWantsLayer(YES);
-
-    let create_info = vk::MacOSSurfaceCreateInfoMVK {
-        s_type: vk::StructureType::MACOS_SURFACE_CREATE_INFO_M,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        p_view: window.ns_view() as *const c_void,
-    };
-
-    let macos_surface_loader = MacOSSurface::new(entry, instance);
-    macos_surface_loader.create_mac_os_surface_mvk(&create_info, None)
+/// Instance extensions to present on `display`, take it from `raw_display_handle` of the window,
+/// the display handle of a winit event loop works too.
+pub fn required_extension_names(display: RawDisplayHandle) -> VkResult<Vec<*const c_char>> {
+    let mut names = ash_window::enumerate_required_extensions(display)?.to_vec();
+    names.push(DebugUtils::name().as_ptr());
+    Ok(names)
 }
 
-#[cfg(windows)]
-pub unsafe fn create_surface(
-    entry: &ash::Entry,
-    instance: &ash::Instance,
-    window: &winit::window::Window,
-) -> Result<vk::SurfaceKHR, vk::Result> {
-    use ash::{extensions::khr::Win32Surface, vk};
-    use std::ptr;
-    use winit::platform::windows::WindowExtWindows;
-
-    // Assuming you're using the winit crate and the window is on the Windows platform
-    let hwnd = window.hwnd() as *const std::ffi::c_void;
+/// How the window covers the screen, see `set_fullscreen`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullscreenMode {
+    #[default]
+    Windowed,
+    /// a borderless window the size of the current monitor, switching is instant
+    Borderless,
+    /// takes over the current monitor with its best video mode, can flicker while switching
+    Exclusive,
+}
 
-    let win32_create_info = vk::Win32SurfaceCreateInfoKHR {
-        s_type: vk::StructureType::WIN32_SURFACE_CREATE_INFO_KHR,
-        p_next: ptr::null(),
-        flags: Default::default(),
-        hinstance: std::ptr::null_mut(), // You might need to provide the HINSTANCE of your application
-        hwnd: hwnd as *mut std::ffi::c_void,
+/// Switches `window` to `mode` on its current monitor. Exclusive falls back to borderless
+/// when the monitor reports no video modes. The swapchain has to be recreated afterwards.
+#[cfg(feature = "winit")]
+pub fn set_fullscreen(window: &winit::window::Window, mode: FullscreenMode) {
+    use winit::window::Fullscreen;
+
+    let fullscreen = match mode {
+        FullscreenMode::Windowed => None,
+        FullscreenMode::Borderless => Some(Fullscreen::Borderless(None)),
+        FullscreenMode::Exclusive => {
+            let monitor = window.current_monitor();
+            // prefer the desktop resolution, then the highest refresh rate and bit depth
+            let video_mode = monitor.as_ref().and_then(|monitor| {
+                let desktop_size = monitor.size();
+                monitor.video_modes().max_by_key(|video_mode| {
+                    let size = video_mode.size();
+                    (
+                        size == desktop_size,
+                        size.width * size.height,
+                        video_mode.refresh_rate_millihertz(),
+                        video_mode.bit_depth(),
+                    )
+                })
+            });
+            match video_mode {
+                Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                None => Some(Fullscreen::Borderless(monitor)),
+            }
+        }
     };
-
-    let win32_surface_loader = Win32Surface::new(entry, instance);
-    win32_surface_loader.create_win32_surface(&win32_create_info, None)
+    window.set_fullscreen(fullscreen);
 }
 
-#[cfg(all(unix, not(target_os = "android"), not(target_os = "macos")))]
-pub fn required_extension_names() -> Vec<*const i8> {
-    use ash::extensions::{
-        ext::DebugUtils,
-        khr::{Surface, XlibSurface},
-    };
+#[cfg(feature = "winit")]
+pub fn fullscreen_mode(window: &winit::window::Window) -> FullscreenMode {
+    use winit::window::Fullscreen;
 
-    vec![
-        Surface::name().as_ptr(),
-        XlibSurface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
-    ]
+    match window.fullscreen() {
+        None => FullscreenMode::Windowed,
+        Some(Fullscreen::Borderless(_)) => FullscreenMode::Borderless,
+        Some(Fullscreen::Exclusive(_)) => FullscreenMode::Exclusive,
+    }
 }
 
-#[cfg(target_os = "macos")]
-pub fn required_extension_names() -> Vec<*const i8> {
-    vec![
-        Surface::name().as_ptr(),
-        MacOSSurface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
-    ]
-}
+/// Alt+Enter, the usual fullscreen toggle.
+#[cfg(feature = "winit")]
+pub fn fullscreen_toggle_pressed(input: &crate::input::InputState) -> bool {
+    use crate::input::Key;
 
-#[cfg(windows)]
-pub fn required_extension_names() -> Vec<*const i8> {
-    use ash::extensions::{
-        ext::DebugUtils,
-        khr::{Surface, Win32Surface, XlibSurface},
-    };
-    vec![
-        Surface::name().as_ptr(),
-        DebugUtils::name().as_ptr(),
-        Win32Surface::name().as_ptr(),
-    ]
+    input.key_just_pressed(Key::Return) && (input.key_down(Key::LAlt) || input.key_down(Key::RAlt))
 }
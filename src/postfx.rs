@@ -0,0 +1,522 @@
+use std::ptr;
+
+use crate::error::Result;
+use ash::vk::{self, StructureType};
+
+use crate::{
+    blit::FullscreenPass,
+    pipeline,
+    texture::{self, SamplerDesc, Texture},
+    utility,
+};
+
+/// Format of the targets of half resolution effects.
+pub const HALF_RESOLUTION_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+const HALF_DEPTH_FORMAT: vk::Format = vk::Format::R32_SFLOAT;
+
+/// Inputs of an effect, 0 is the scene color and 1 and 2 the ping pong targets.
+const INPUT_COUNT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectResolution {
+    Full,
+    /// renders a quarter of the pixels and is brought back to full resolution with a depth aware upsample
+    Half,
+}
+
+/// How the upsampled result of a half resolution effect is combined with the color.
+/// Full resolution effects always replace the color.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EffectComposite {
+    Replace,
+    /// for example ambient occlusion
+    Multiply,
+    /// for example volumetric light
+    Add,
+    /// blends by the alpha of the result, for example reflections
+    AlphaBlend,
+}
+
+/// An effect is a fullscreen fragment shader, see `FullscreenPass`.
+/// It samples the current color at binding 0 and the depth of its resolution at binding 1.
+#[derive(Clone, Debug)]
+pub struct EffectDesc {
+    pub fragment_shader: String,
+    pub resolution: EffectResolution,
+    pub composite: EffectComposite,
+    pub push_constant_size: u32,
+}
+
+struct Effect {
+    desc: EffectDesc,
+    enabled: bool,
+    push_constants: Vec<u8>,
+    pass: FullscreenPass,
+    sets: [vk::DescriptorSet; INPUT_COUNT],
+}
+
+/// Push constants of shaders/bilateral_upsample.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct UpsamplePush {
+    composite: u32,
+    depth_sigma: f32,
+}
+
+/// Images that change with the extent.
+struct Targets {
+    scene_color: vk::ImageView,
+    scene_depth: vk::ImageView,
+    ping: [Texture; 2],
+    half_result: Texture,
+    half_depth: Texture,
+    ping_framebuffers: [vk::Framebuffer; 2],
+    half_result_framebuffer: vk::Framebuffer,
+    half_depth_framebuffer: vk::Framebuffer,
+    upsample_sets: [vk::DescriptorSet; INPUT_COUNT],
+    downsample_set: vk::DescriptorSet,
+}
+
+/// Objects that don't depend on the extent.
+struct Passes {
+    /// of the scene color and the full resolution targets
+    format: vk::Format,
+    full_render_pass: vk::RenderPass,
+    half_render_pass: vk::RenderPass,
+    depth_render_pass: vk::RenderPass,
+    downsample: FullscreenPass,
+    upsample: FullscreenPass,
+    linear_sampler: vk::Sampler,
+    nearest_sampler: vk::Sampler,
+}
+
+/// Chain of post processing effects running after the scene, in the order they were added.
+///
+/// The scene has to be rendered into `scene_color` with a depth buffer that was created with SAMPLED usage
+/// and stored, see `pipeline::create_offscreen_render_pass` and `DepthBuffer::with_usage`.
+pub struct PostFxChain {
+    passes: Passes,
+    targets: Targets,
+    effects: Vec<Effect>,
+    extent: vk::Extent2D,
+    /// input index holding the result of the last `record`
+    output: usize,
+    /// depth difference at which half resolution samples stop contributing, in 0..1 depth units
+    pub depth_sigma: f32,
+}
+
+fn half_extent(extent: vk::Extent2D) -> vk::Extent2D {
+    vk::Extent2D {
+        width: extent.width.div_ceil(2).max(1),
+        height: extent.height.div_ceil(2).max(1),
+    }
+}
+
+unsafe fn begin_pass(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    render_pass: vk::RenderPass,
+    framebuffer: vk::Framebuffer,
+    extent: vk::Extent2D,
+) {
+    let render_pass_info = vk::RenderPassBeginInfo {
+        s_type: StructureType::RENDER_PASS_BEGIN_INFO,
+        p_next: ptr::null(),
+        render_pass,
+        framebuffer,
+        render_area: vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        },
+        clear_value_count: 0,
+        p_clear_values: ptr::null(),
+    };
+    device.cmd_begin_render_pass(command_buffer, &render_pass_info, vk::SubpassContents::INLINE);
+}
+
+impl Targets {
+    unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        passes: &Passes,
+        extent: vk::Extent2D,
+        scene_color: vk::ImageView,
+        scene_depth: vk::ImageView,
+    ) -> Result<Targets> {
+        let half = half_extent(extent);
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+
+        let ping = [
+            Texture::new(device, instance, physical_device, extent, passes.format, usage)?,
+            Texture::new(device, instance, physical_device, extent, passes.format, usage)?,
+        ];
+        let half_result = Texture::new(device, instance, physical_device, half, HALF_RESOLUTION_FORMAT, usage)?;
+        let half_depth = Texture::new(device, instance, physical_device, half, HALF_DEPTH_FORMAT, usage)?;
+
+        let ping_framebuffers = [
+            pipeline::create_framebuffer(device, passes.full_render_pass, &[ping[0].view], extent)?,
+            pipeline::create_framebuffer(device, passes.full_render_pass, &[ping[1].view], extent)?,
+        ];
+        let half_result_framebuffer =
+            pipeline::create_framebuffer(device, passes.half_render_pass, &[half_result.view], half)?;
+        let half_depth_framebuffer =
+            pipeline::create_framebuffer(device, passes.depth_render_pass, &[half_depth.view], half)?;
+
+        let mut targets = Targets {
+            scene_color,
+            scene_depth,
+            ping,
+            half_result,
+            half_depth,
+            ping_framebuffers,
+            half_result_framebuffer,
+            half_depth_framebuffer,
+            upsample_sets: [vk::DescriptorSet::null(); INPUT_COUNT],
+            downsample_set: vk::DescriptorSet::null(),
+        };
+
+        // every pass uses texelFetch here, so the filter doesn't matter
+        for input in 0..INPUT_COUNT {
+            targets.upsample_sets[input] = passes.upsample.create_sources_set(
+                device,
+                &[
+                    (targets.input_view(input), passes.nearest_sampler),
+                    (scene_depth, passes.nearest_sampler),
+                    (targets.half_result.view, passes.nearest_sampler),
+                    (targets.half_depth.view, passes.nearest_sampler),
+                ],
+            )?;
+        }
+        targets.downsample_set = passes
+            .downsample
+            .create_source_set(device, scene_depth, passes.nearest_sampler)?;
+
+        Ok(targets)
+    }
+
+    fn input_view(&self, input: usize) -> vk::ImageView {
+        match input {
+            0 => self.scene_color,
+            _ => self.ping[input - 1].view,
+        }
+    }
+
+    unsafe fn destroy(&self, device: &ash::Device, passes: &Passes) {
+        for set in self.upsample_sets {
+            let _ = passes.upsample.free_source_set(device, set);
+        }
+        let _ = passes.downsample.free_source_set(device, self.downsample_set);
+
+        for framebuffer in self.ping_framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        device.destroy_framebuffer(self.half_result_framebuffer, None);
+        device.destroy_framebuffer(self.half_depth_framebuffer, None);
+
+        for ping in self.ping.iter() {
+            ping.destroy(device);
+        }
+        self.half_result.destroy(device);
+        self.half_depth.destroy(device);
+    }
+}
+
+impl PostFxChain {
+    /// `format` is the format of `scene_color` and of the targets the effects write to.
+    ///
+    /// # Safety
+    /// `scene_color` and `scene_depth` have to be views of `extent` big images that outlive the chain.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        scene_color: vk::ImageView,
+        scene_depth: vk::ImageView,
+    ) -> Result<PostFxChain> {
+        let full_render_pass = pipeline::create_offscreen_render_pass(device, format, None, false, false)?;
+        let half_render_pass = pipeline::create_offscreen_render_pass(device, HALF_RESOLUTION_FORMAT, None, false, false)?;
+        let depth_render_pass = pipeline::create_offscreen_render_pass(device, HALF_DEPTH_FORMAT, None, false, false)?;
+
+        let downsample = FullscreenPass::new(device, depth_render_pass, Some("shaders/spv/depth_downsample_frag.spv"), 0)?;
+        let upsample = FullscreenPass::with_sources(
+            device,
+            full_render_pass,
+            Some("shaders/spv/bilateral_upsample_frag.spv"),
+            4,
+            std::mem::size_of::<UpsamplePush>() as u32,
+        )?;
+
+        let linear_sampler = texture::create_sampler(device, &SamplerDesc::default())?;
+        let nearest_sampler = texture::create_sampler(
+            device,
+            &SamplerDesc::new(vk::Filter::NEAREST, vk::SamplerAddressMode::CLAMP_TO_EDGE),
+        )?;
+
+        let passes = Passes {
+            format,
+            full_render_pass,
+            half_render_pass,
+            depth_render_pass,
+            downsample,
+            upsample,
+            linear_sampler,
+            nearest_sampler,
+        };
+        let targets = Targets::new(device, instance, physical_device, &passes, extent, scene_color, scene_depth)?;
+
+        Ok(PostFxChain {
+            passes,
+            targets,
+            effects: vec![],
+            extent,
+            output: 0,
+            depth_sigma: 0.01,
+        })
+    }
+
+    unsafe fn create_effect_sets(
+        &self,
+        device: &ash::Device,
+        pass: &FullscreenPass,
+        resolution: EffectResolution,
+    ) -> Result<[vk::DescriptorSet; INPUT_COUNT]> {
+        let depth = match resolution {
+            EffectResolution::Full => self.targets.scene_depth,
+            EffectResolution::Half => self.targets.half_depth.view,
+        };
+        let mut sets = [vk::DescriptorSet::null(); INPUT_COUNT];
+        for (input, set) in sets.iter_mut().enumerate() {
+            *set = pass.create_sources_set(
+                device,
+                &[
+                    (self.targets.input_view(input), self.passes.linear_sampler),
+                    (depth, self.passes.nearest_sampler),
+                ],
+            )?;
+        }
+        Ok(sets)
+    }
+
+    unsafe fn create_effect_pass(&self, device: &ash::Device, desc: &EffectDesc) -> Result<FullscreenPass> {
+        let render_pass = match desc.resolution {
+            EffectResolution::Full => self.passes.full_render_pass,
+            EffectResolution::Half => self.passes.half_render_pass,
+        };
+        FullscreenPass::with_sources(device, render_pass, Some(&desc.fragment_shader), 2, desc.push_constant_size)
+    }
+
+    /// Appends an effect, it starts enabled with zeroed push constants. Returns its index.
+    ///
+    /// # Safety
+    /// The shader of `desc` has to read the chain's inputs with their layout.
+    pub unsafe fn add_effect(&mut self, device: &ash::Device, desc: EffectDesc) -> Result<usize> {
+        let pass = self.create_effect_pass(device, &desc)?;
+        let sets = self.create_effect_sets(device, &pass, desc.resolution)?;
+        self.effects.push(Effect {
+            push_constants: vec![0; desc.push_constant_size as usize],
+            desc,
+            enabled: true,
+            pass,
+            sets,
+        });
+        Ok(self.effects.len() - 1)
+    }
+
+    pub fn effect(&self, effect: usize) -> &EffectDesc {
+        &self.effects[effect].desc
+    }
+
+    pub fn set_enabled(&mut self, effect: usize, enabled: bool) {
+        self.effects[effect].enabled = enabled;
+    }
+
+    pub fn is_enabled(&self, effect: usize) -> bool {
+        self.effects[effect].enabled
+    }
+
+    /// `bytes` has to be as long as the effect's `push_constant_size`.
+    pub fn set_push_constants(&mut self, effect: usize, bytes: &[u8]) {
+        self.effects[effect].push_constants.copy_from_slice(bytes);
+    }
+
+    pub fn set_composite(&mut self, effect: usize, composite: EffectComposite) {
+        self.effects[effect].desc.composite = composite;
+    }
+
+    /// Rebuilds the effect's pipeline for the other resolution, no frame in flight may use the effect.
+    ///
+    /// # Safety
+    /// `effect` has to be an index `add_effect` returned.
+    pub unsafe fn set_resolution(
+        &mut self,
+        device: &ash::Device,
+        effect: usize,
+        resolution: EffectResolution,
+    ) -> Result<()> {
+        if self.effects[effect].desc.resolution == resolution {
+            return Ok(());
+        }
+
+        let mut desc = self.effects[effect].desc.clone();
+        desc.resolution = resolution;
+        let pass = self.create_effect_pass(device, &desc)?;
+        let sets = self.create_effect_sets(device, &pass, resolution)?;
+
+        let effect = &mut self.effects[effect];
+        effect.pass.destroy(device);
+        effect.pass = pass;
+        effect.sets = sets;
+        effect.desc = desc;
+        Ok(())
+    }
+
+    /// Recreates the targets for a new scene color and depth, the device has to be idle.
+    ///
+    /// # Safety
+    /// As for `new`, no frame in flight may use the chain.
+    pub unsafe fn resize(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        scene_color: vk::ImageView,
+        scene_depth: vk::ImageView,
+    ) -> Result<()> {
+        for effect in self.effects.iter() {
+            for set in effect.sets {
+                effect.pass.free_source_set(device, set)?;
+            }
+        }
+        self.targets.destroy(device, &self.passes);
+        self.targets = Targets::new(
+            device,
+            instance,
+            physical_device,
+            &self.passes,
+            extent,
+            scene_color,
+            scene_depth,
+        )?;
+        self.extent = extent;
+
+        for index in 0..self.effects.len() {
+            let effect = &self.effects[index];
+            let sets = self.create_effect_sets(device, &effect.pass, effect.desc.resolution)?;
+            self.effects[index].sets = sets;
+        }
+        Ok(())
+    }
+
+    /// Records the enabled effects, has to be recorded outside of a render pass after the scene.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording, the scene color has to be written before.
+    pub unsafe fn record(&mut self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        let half = half_extent(self.extent);
+        let mut input = 0;
+        let mut half_depth_ready = false;
+
+        for effect in self.effects.iter().filter(|effect| effect.enabled) {
+            // writes the ping pong target that isn't read
+            let target = if input == 1 { 1 } else { 0 };
+
+            match effect.desc.resolution {
+                EffectResolution::Full => {
+                    begin_pass(
+                        device,
+                        command_buffer,
+                        self.passes.full_render_pass,
+                        self.targets.ping_framebuffers[target],
+                        self.extent,
+                    );
+                    effect.pass.record(
+                        device,
+                        command_buffer,
+                        effect.sets[input],
+                        self.extent,
+                        &effect.push_constants,
+                    );
+                    device.cmd_end_render_pass(command_buffer);
+                }
+                EffectResolution::Half => {
+                    // the depth doesn't change between effects, so it's only downsampled once
+                    if !half_depth_ready {
+                        begin_pass(
+                            device,
+                            command_buffer,
+                            self.passes.depth_render_pass,
+                            self.targets.half_depth_framebuffer,
+                            half,
+                        );
+                        self.passes
+                            .downsample
+                            .record(device, command_buffer, self.targets.downsample_set, half, &[]);
+                        device.cmd_end_render_pass(command_buffer);
+                        half_depth_ready = true;
+                    }
+
+                    begin_pass(
+                        device,
+                        command_buffer,
+                        self.passes.half_render_pass,
+                        self.targets.half_result_framebuffer,
+                        half,
+                    );
+                    effect
+                        .pass
+                        .record(device, command_buffer, effect.sets[input], half, &effect.push_constants);
+                    device.cmd_end_render_pass(command_buffer);
+
+                    let push = UpsamplePush {
+                        composite: effect.desc.composite as u32,
+                        depth_sigma: self.depth_sigma,
+                    };
+                    begin_pass(
+                        device,
+                        command_buffer,
+                        self.passes.full_render_pass,
+                        self.targets.ping_framebuffers[target],
+                        self.extent,
+                    );
+                    self.passes.upsample.record(
+                        device,
+                        command_buffer,
+                        self.targets.upsample_sets[input],
+                        self.extent,
+                        utility::as_bytes(&push),
+                    );
+                    device.cmd_end_render_pass(command_buffer);
+                }
+            }
+            input = target + 1;
+        }
+        self.output = input;
+    }
+
+    /// The image view with the result of the last `record` in SHADER_READ_ONLY_OPTIMAL layout.
+    /// It's the scene color when no effect is enabled, so descriptor sets sampling it have to follow enabling.
+    pub fn output(&self) -> vk::ImageView {
+        self.targets.input_view(self.output)
+    }
+
+    /// # Safety
+    /// No frame in flight may use the chain.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for effect in self.effects.iter() {
+            effect.pass.destroy(device);
+        }
+        self.targets.destroy(device, &self.passes);
+        let passes = &self.passes;
+        passes.downsample.destroy(device);
+        passes.upsample.destroy(device);
+        device.destroy_sampler(passes.linear_sampler, None);
+        device.destroy_sampler(passes.nearest_sampler, None);
+        device.destroy_render_pass(passes.full_render_pass, None);
+        device.destroy_render_pass(passes.half_render_pass, None);
+        device.destroy_render_pass(passes.depth_render_pass, None);
+    }
+}
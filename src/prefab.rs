@@ -0,0 +1,631 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use crate::error::{Error, Result};
+use nalgebra as glm;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    layers::RenderLayers,
+    scene::{Node, NodeId, Scene, Transform},
+};
+
+/// Prefabs may instance other prefabs, this deep at most, which also catches prefabs instancing themselves.
+const MAX_NESTING: usize = 16;
+
+/// A node and its subtree as written in a scene file, defaults are left out to keep files small.
+/// With `prefab` set it is an instance: the subtree of the prefab is added, the fields of this node replace
+/// those of the prefab root, `overrides` change nodes below it and `children` are added after the prefab's.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct NodeDesc {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefab: Option<String>,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub translation: [f32; 3],
+    /// quaternion as x, y, z, w
+    #[serde(default = "identity_rotation", skip_serializing_if = "is_identity_rotation")]
+    pub rotation: [f32; 4],
+    #[serde(default = "unit_scale", skip_serializing_if = "is_unit_scale")]
+    pub scale: [f32; 3],
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<usize>,
+    #[serde(default, skip_serializing_if = "is_default_layers")]
+    pub layers: RenderLayers,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<String, toml::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<NodeOverride>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<NodeDesc>,
+}
+
+fn is_zero(translation: &[f32; 3]) -> bool {
+    *translation == [0.0; 3]
+}
+
+fn is_default_layers(layers: &RenderLayers) -> bool {
+    *layers == RenderLayers::DEFAULT
+}
+
+fn identity_rotation() -> [f32; 4] {
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+fn is_identity_rotation(rotation: &[f32; 4]) -> bool {
+    *rotation == identity_rotation()
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn is_unit_scale(scale: &[f32; 3]) -> bool {
+    *scale == unit_scale()
+}
+
+fn to_rotation(rotation: [f32; 4]) -> glm::UnitQuaternion<f32> {
+    let [x, y, z, w] = rotation;
+    glm::UnitQuaternion::from_quaternion(glm::Quaternion::new(w, x, y, z))
+}
+
+fn from_rotation(rotation: &glm::UnitQuaternion<f32>) -> [f32; 4] {
+    let coords = rotation.coords;
+    [coords.x, coords.y, coords.z, coords.w]
+}
+
+impl Default for NodeDesc {
+    fn default() -> Self {
+        Self {
+            name: None,
+            prefab: None,
+            translation: [0.0; 3],
+            rotation: identity_rotation(),
+            scale: unit_scale(),
+            mesh: None,
+            camera: None,
+            layers: RenderLayers::DEFAULT,
+            components: BTreeMap::new(),
+            overrides: vec![],
+            children: vec![],
+        }
+    }
+}
+
+impl NodeDesc {
+    pub fn transform(&self) -> Transform {
+        Transform {
+            translation: self.translation.into(),
+            rotation: to_rotation(self.rotation),
+            scale: self.scale.into(),
+        }
+    }
+
+    pub fn set_transform(&mut self, transform: &Transform) {
+        self.translation = transform.translation.into();
+        self.rotation = from_rotation(&transform.rotation);
+        self.scale = transform.scale.into();
+    }
+
+    /// The node alone, without children or instance data.
+    fn of(node: &Node) -> Self {
+        let mut desc = NodeDesc {
+            name: node.name.clone(),
+            mesh: node.mesh,
+            camera: node.camera,
+            layers: node.layers,
+            components: node.components.clone(),
+            ..Default::default()
+        };
+        desc.set_transform(node.local_transform());
+        desc
+    }
+}
+
+/// Changes to one node of a prefab instance, the fields that are set replace those of the prefab.
+/// Components are merged by name. Clearing a mesh, camera, name or component can't be expressed,
+/// `Prefabs::describe` writes such instances out as plain nodes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct NodeOverride {
+    /// child indices from the instance root down to the node
+    pub path: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<[f32; 4]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale: Option<[f32; 3]>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mesh: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layers: Option<RenderLayers>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub components: BTreeMap<String, toml::Value>,
+}
+
+impl NodeOverride {
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.translation.is_none()
+            && self.rotation.is_none()
+            && self.scale.is_none()
+            && self.mesh.is_none()
+            && self.camera.is_none()
+            && self.layers.is_none()
+            && self.components.is_empty()
+    }
+
+    fn apply(&self, scene: &mut Scene, id: NodeId) {
+        let mut transform = *scene.node(id).local_transform();
+        if let Some(translation) = self.translation {
+            transform.translation = translation.into();
+        }
+        if let Some(rotation) = self.rotation {
+            transform.rotation = to_rotation(rotation);
+        }
+        if let Some(scale) = self.scale {
+            transform.scale = scale.into();
+        }
+        scene.set_local_transform(id, transform);
+
+        let node = scene.node_mut(id);
+        if self.name.is_some() {
+            node.name = self.name.clone();
+        }
+        if self.mesh.is_some() {
+            node.mesh = self.mesh;
+        }
+        if self.camera.is_some() {
+            node.camera = self.camera;
+        }
+        if let Some(layers) = self.layers {
+            node.layers = layers;
+        }
+        node.components
+            .extend(self.components.iter().map(|(key, value)| (key.clone(), value.clone())));
+    }
+
+    /// What turns `template` into `node`, None when that needs clearing something.
+    fn between(template: &Node, node: &Node, path: Vec<u32>) -> Option<NodeOverride> {
+        fn changed<T: PartialEq + Clone>(template: &Option<T>, node: &Option<T>) -> Option<Option<T>> {
+            match (template, node) {
+                (Some(_), None) => None,
+                _ if template == node => Some(None),
+                _ => Some(node.clone()),
+            }
+        }
+        if node.prefab != template.prefab || template.components.keys().any(|key| !node.components.contains_key(key)) {
+            return None;
+        }
+
+        let (template_transform, transform) = (template.local_transform(), node.local_transform());
+        Some(NodeOverride {
+            path,
+            name: changed(&template.name, &node.name)?,
+            translation: (template_transform.translation != transform.translation).then(|| transform.translation.into()),
+            rotation: (template_transform.rotation != transform.rotation).then(|| from_rotation(&transform.rotation)),
+            scale: (template_transform.scale != transform.scale).then(|| transform.scale.into()),
+            mesh: changed(&template.mesh, &node.mesh)?,
+            camera: changed(&template.camera, &node.camera)?,
+            layers: (template.layers != node.layers).then_some(node.layers),
+            components: node
+                .components
+                .iter()
+                .filter(|(key, value)| template.components.get(*key) != Some(*value))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect(),
+        })
+    }
+}
+
+/// Named node subtrees that are instanced into scenes, every instance root remembers its prefab in
+/// `Node::prefab` so scenes are saved as references plus the overrides of each instance.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Prefabs {
+    prefabs: BTreeMap<String, NodeDesc>,
+}
+
+impl Prefabs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds or replaces a prefab, instances created before keep their nodes.
+    pub fn define(&mut self, name: &str, root: NodeDesc) {
+        self.prefabs.insert(name.to_owned(), root);
+    }
+
+    /// Turns the subtree of `id` into a prefab, the nodes stay as they are.
+    pub fn define_from_scene(&mut self, name: &str, scene: &Scene, id: NodeId) -> Result<()> {
+        let root = self.describe(scene, id)?;
+        self.define(name, root);
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NodeDesc> {
+        self.prefabs.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<NodeDesc> {
+        self.prefabs.remove(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.prefabs.keys().map(String::as_str)
+    }
+
+    /// Adds an instance of the prefab `name` under `parent` at `transform`, `overrides` change nodes below its root.
+    pub fn instantiate(
+        &self,
+        scene: &mut Scene,
+        name: &str,
+        parent: Option<NodeId>,
+        transform: &Transform,
+        overrides: &[NodeOverride],
+    ) -> Result<NodeId> {
+        let mut desc = NodeDesc {
+            prefab: Some(name.to_owned()),
+            overrides: overrides.to_vec(),
+            ..Default::default()
+        };
+        desc.set_transform(transform);
+        self.add(scene, &desc, parent)
+    }
+
+    /// Adds `desc` and its subtree, expanding prefab instances.
+    pub fn add(&self, scene: &mut Scene, desc: &NodeDesc, parent: Option<NodeId>) -> Result<NodeId> {
+        self.add_nested(scene, desc, parent, 0)
+    }
+
+    /// `depth` counts the prefab expansions above `desc`.
+    fn add_nested(&self, scene: &mut Scene, desc: &NodeDesc, parent: Option<NodeId>, depth: usize) -> Result<NodeId> {
+        if depth > MAX_NESTING {
+            return Err(Error::msg(format!(
+                "Prefabs are nested more than {} deep, one probably instances itself",
+                MAX_NESTING
+            )));
+        }
+
+        let Some(prefab_name) = &desc.prefab else {
+            let id = scene.add_node(desc.name.clone(), parent, desc.transform());
+            let node = scene.node_mut(id);
+            node.mesh = desc.mesh;
+            node.camera = desc.camera;
+            node.layers = desc.layers;
+            node.components = desc.components.clone();
+            for child in desc.children.iter() {
+                self.add_nested(scene, child, Some(id), depth)?;
+            }
+            return Ok(id);
+        };
+
+        let template = self
+            .get(prefab_name)
+            .ok_or_else(|| Error::msg(format!("Unknown prefab {}", prefab_name)))?;
+        let id = self.add_nested(scene, template, parent, depth + 1)?;
+        scene.set_local_transform(id, desc.transform());
+        let node = scene.node_mut(id);
+        node.prefab = Some(prefab_name.clone());
+        if desc.name.is_some() {
+            node.name = desc.name.clone();
+        }
+        if desc.mesh.is_some() {
+            node.mesh = desc.mesh;
+        }
+        if desc.camera.is_some() {
+            node.camera = desc.camera;
+        }
+        // like the other fields only a value that differs from the default replaces the prefab's
+        if desc.layers != RenderLayers::DEFAULT {
+            node.layers = desc.layers;
+        }
+        node.components
+            .extend(desc.components.iter().map(|(key, value)| (key.clone(), value.clone())));
+
+        for node_override in desc.overrides.iter() {
+            let mut target = id;
+            for &index in node_override.path.iter() {
+                target = *scene.node(target).children().get(index as usize).ok_or_else(|| {
+                    Error::msg(format!(
+                        "Override path {:?} doesn't exist in prefab {}",
+                        node_override.path, prefab_name
+                    ))
+                })?;
+            }
+            node_override.apply(scene, target);
+        }
+        for child in desc.children.iter() {
+            self.add_nested(scene, child, Some(id), depth)?;
+        }
+        Ok(id)
+    }
+
+    /// The subtree of `id` as it would be saved, prefab instances become a reference and their overrides.
+    /// Instances whose structure changed below the root, or that cleared something, are written out as plain nodes.
+    pub fn describe(&self, scene: &Scene, id: NodeId) -> Result<NodeDesc> {
+        let node = scene.node(id);
+        if let Some(prefab) = node.prefab.as_ref().filter(|prefab| self.prefabs.contains_key(*prefab)) {
+            if let Some(desc) = self.describe_instance(scene, id, prefab)? {
+                return Ok(desc);
+            }
+        }
+
+        let mut desc = NodeDesc::of(node);
+        for child in node.children() {
+            desc.children.push(self.describe(scene, *child)?);
+        }
+        Ok(desc)
+    }
+
+    fn describe_instance(&self, scene: &Scene, id: NodeId, prefab: &str) -> Result<Option<NodeDesc>> {
+        let node = scene.node(id);
+        let mut template_scene = Scene::new();
+        let template_id = self.instantiate(&mut template_scene, prefab, None, node.local_transform(), &[])?;
+
+        let Some(root) = NodeOverride::between(template_scene.node(template_id), node, vec![]) else {
+            return Ok(None);
+        };
+        if root.layers == Some(RenderLayers::DEFAULT) {
+            return Ok(None);
+        }
+        let mut desc = NodeDesc {
+            name: root.name,
+            prefab: Some(prefab.to_owned()),
+            mesh: root.mesh,
+            camera: root.camera,
+            layers: root.layers.unwrap_or_default(),
+            components: root.components,
+            ..Default::default()
+        };
+        desc.set_transform(node.local_transform());
+
+        let template_children = template_scene.node(template_id).children();
+        let children = node.children();
+        if children.len() < template_children.len() {
+            return Ok(None);
+        }
+        for (index, (child, template_child)) in children.iter().zip(template_children).enumerate() {
+            if !diff_subtree(
+                scene,
+                *child,
+                &template_scene,
+                *template_child,
+                vec![index as u32],
+                &mut desc.overrides,
+            ) {
+                return Ok(None);
+            }
+        }
+        // children added to the instance root are saved as children of the instance
+        for child in &children[template_children.len()..] {
+            desc.children.push(self.describe(scene, *child)?);
+        }
+        Ok(Some(desc))
+    }
+}
+
+/// Pushes the overrides turning the template subtree into the scene subtree, false when their structure differs.
+fn diff_subtree(
+    scene: &Scene,
+    id: NodeId,
+    template_scene: &Scene,
+    template_id: NodeId,
+    path: Vec<u32>,
+    overrides: &mut Vec<NodeOverride>,
+) -> bool {
+    let (node, template) = (scene.node(id), template_scene.node(template_id));
+    if node.children().len() != template.children().len() {
+        return false;
+    }
+    let Some(node_override) = NodeOverride::between(template, node, path.clone()) else {
+        return false;
+    };
+    if !node_override.is_empty() {
+        overrides.push(node_override);
+    }
+    node.children()
+        .iter()
+        .zip(template.children())
+        .enumerate()
+        .all(|(index, (child, template_child))| {
+            let mut child_path = path.clone();
+            child_path.push(index as u32);
+            diff_subtree(scene, *child, template_scene, *template_child, child_path, overrides)
+        })
+}
+
+/// Layout of a saved scene: the prefabs it uses and its root nodes.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SceneFile {
+    #[serde(default, skip_serializing_if = "is_empty")]
+    pub prefabs: Prefabs,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nodes: Vec<NodeDesc>,
+}
+
+fn is_empty(prefabs: &Prefabs) -> bool {
+    prefabs.prefabs.is_empty()
+}
+
+impl SceneFile {
+    /// Every root of `scene`, prefab instances refer to `prefabs`, which are saved along.
+    pub fn from_scene(scene: &Scene, prefabs: &Prefabs) -> Result<SceneFile> {
+        let nodes = scene
+            .roots()
+            .iter()
+            .map(|root| prefabs.describe(scene, *root))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SceneFile {
+            prefabs: prefabs.clone(),
+            nodes,
+        })
+    }
+
+    /// Adds the nodes to `scene` and returns the new roots, call `Scene::update_transforms` after.
+    pub fn add_to(&self, scene: &mut Scene) -> Result<Vec<NodeId>> {
+        self.nodes.iter().map(|node| self.prefabs.add(scene, node, None)).collect()
+    }
+
+    /// Arrays stay on one line, transforms would take a dozen lines each otherwise.
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    pub fn from_toml(text: &str) -> Result<SceneFile> {
+        Ok(toml::from_str(text)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, self.to_toml()?)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SceneFile> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|e| Error::asset(path, format!("Failed to read the scene: {}", e)))?;
+        Self::from_toml(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A lamp with a shade child and a bulb below the shade.
+    fn prefabs() -> Prefabs {
+        let mut prefabs = Prefabs::new();
+        prefabs.define(
+            "lamp",
+            NodeDesc {
+                name: Some("lamp".to_owned()),
+                mesh: Some(0),
+                children: vec![NodeDesc {
+                    name: Some("shade".to_owned()),
+                    translation: [0.0, 2.0, 0.0],
+                    mesh: Some(1),
+                    children: vec![NodeDesc {
+                        name: Some("bulb".to_owned()),
+                        components: BTreeMap::from([("light".to_owned(), toml::Value::Float(100.0))]),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        prefabs
+    }
+
+    fn bulb_override() -> NodeOverride {
+        NodeOverride {
+            path: vec![0, 0],
+            components: BTreeMap::from([("light".to_owned(), toml::Value::Float(40.0))]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn instances_apply_their_overrides() {
+        let prefabs = prefabs();
+        let mut scene = Scene::new();
+        let at = Transform::from_translation(glm::Vector3::new(5.0, 0.0, 0.0));
+        let lamp = prefabs
+            .instantiate(&mut scene, "lamp", None, &at, &[bulb_override()])
+            .unwrap();
+        assert_eq!(scene.node(lamp).prefab.as_deref(), Some("lamp"));
+        assert_eq!(scene.node(lamp).local_transform(), &at);
+
+        let bulb = scene.find_descendant(lamp, "bulb").unwrap();
+        assert_eq!(scene.node(bulb).components["light"], toml::Value::Float(40.0));
+        let shade = scene.find_descendant(lamp, "shade").unwrap();
+        assert_eq!(
+            scene.node(shade).local_transform().translation,
+            glm::Vector3::new(0.0, 2.0, 0.0)
+        );
+
+        let missing = NodeOverride {
+            path: vec![3],
+            ..Default::default()
+        };
+        assert!(prefabs.instantiate(&mut scene, "lamp", None, &at, &[missing]).is_err());
+        assert!(prefabs.instantiate(&mut scene, "chair", None, &at, &[]).is_err());
+    }
+
+    #[test]
+    fn described_instances_keep_only_their_changes() {
+        let prefabs = prefabs();
+        let mut scene = Scene::new();
+        let lamp = prefabs
+            .instantiate(&mut scene, "lamp", None, &Transform::IDENTITY, &[bulb_override()])
+            .unwrap();
+        let shade = scene.find_descendant(lamp, "shade").unwrap();
+        scene.node_mut(shade).mesh = Some(7);
+
+        let desc = prefabs.describe(&scene, lamp).unwrap();
+        assert_eq!(desc.prefab.as_deref(), Some("lamp"));
+        assert_eq!(desc.name, None);
+        assert!(desc.children.is_empty());
+        let shade_override = NodeOverride {
+            path: vec![0],
+            mesh: Some(7),
+            ..Default::default()
+        };
+        assert_eq!(desc.overrides, [shade_override, bulb_override()]);
+
+        // a node removed below the root can't be an override, the instance is saved as plain nodes
+        let bulb = scene.find_descendant(lamp, "bulb").unwrap();
+        scene.remove_node(bulb);
+        let desc = prefabs.describe(&scene, lamp).unwrap();
+        assert_eq!(desc.prefab, None);
+        assert_eq!(desc.children[0].mesh, Some(7));
+        assert!(desc.children[0].children.is_empty());
+    }
+
+    #[test]
+    fn scene_files_round_trip_through_toml() {
+        let prefabs = prefabs();
+        let mut scene = Scene::new();
+        let lamp = prefabs
+            .instantiate(&mut scene, "lamp", None, &Transform::IDENTITY, &[bulb_override()])
+            .unwrap();
+        scene.add_node(Some("extra".to_owned()), Some(lamp), Transform::IDENTITY);
+        scene.add_node(Some("floor".to_owned()), None, Transform::IDENTITY);
+
+        let file = SceneFile::from_scene(&scene, &prefabs).unwrap();
+        let text = file.to_toml().unwrap();
+        let loaded = SceneFile::from_toml(&text).unwrap();
+        assert_eq!(loaded, file);
+
+        let mut copy = Scene::new();
+        let roots = loaded.add_to(&mut copy).unwrap();
+        assert_eq!(roots.len(), 2);
+        assert_eq!(SceneFile::from_scene(&copy, &prefabs).unwrap(), file);
+        assert!(copy.find_descendant(roots[0], "extra").is_some());
+    }
+
+    #[test]
+    fn prefabs_instancing_themselves_fail() {
+        let mut prefabs = Prefabs::new();
+        prefabs.define(
+            "loop",
+            NodeDesc {
+                children: vec![NodeDesc {
+                    prefab: Some("loop".to_owned()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            },
+        );
+        let mut scene = Scene::new();
+        assert!(prefabs
+            .instantiate(&mut scene, "loop", None, &Transform::IDENTITY, &[])
+            .is_err());
+    }
+}
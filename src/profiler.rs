@@ -0,0 +1,515 @@
+use std::{fmt, sync::Mutex};
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{
+    buffer::MAX_FRAMES_IN_FLIGHT,
+    query::{QueryPool, ReadMode},
+};
+
+/// Queue a span ran on, spans of different queues may overlap in time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum QueueKind {
+    Graphics,
+    Compute,
+    Transfer,
+}
+
+impl QueueKind {
+    pub const ALL: [QueueKind; 3] = [QueueKind::Graphics, QueueKind::Compute, QueueKind::Transfer];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueKind::Graphics => "graphics",
+            QueueKind::Compute => "compute",
+            QueueKind::Transfer => "transfer",
+        }
+    }
+}
+
+/// Handle to an open span, pass it to `GpuProfiler::end_span`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SpanId {
+    queue: QueueKind,
+    index: usize,
+}
+
+struct Span {
+    name: String,
+    first_query: u32,
+    ended: bool,
+}
+
+/// Timestamps of one queue in one frame in flight.
+struct QueueQueries {
+    pool: QueryPool,
+    spans: Vec<Span>,
+    recording: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpanTiming {
+    pub name: String,
+    pub queue: QueueKind,
+    /// relative to the earliest timestamp of the frame
+    pub start_ms: f64,
+    pub duration_ms: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueUtilization {
+    pub queue: QueueKind,
+    /// time covered by at least one span of the queue, nested and overlapping spans count once
+    pub busy_ms: f64,
+    /// busy intervals relative to the start of the frame
+    intervals: Vec<(f64, f64)>,
+}
+
+/// GPU timings of one frame, the `Display` output lists every queue with its overlap with the others.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FrameReport {
+    pub spans: Vec<SpanTiming>,
+    pub queues: Vec<QueueUtilization>,
+    /// from the earliest to the latest timestamp of any queue
+    pub frame_ms: f64,
+}
+
+impl FrameReport {
+    pub fn queue(&self, queue: QueueKind) -> Option<&QueueUtilization> {
+        self.queues.iter().find(|utilization| utilization.queue == queue)
+    }
+
+    /// Share of the frame `queue` was busy, 0..1.
+    pub fn utilization(&self, queue: QueueKind) -> f64 {
+        match self.queue(queue) {
+            Some(utilization) if self.frame_ms > 0.0 => utilization.busy_ms / self.frame_ms,
+            _ => 0.0,
+        }
+    }
+
+    /// Total time of the spans called `name`, a pass recorded several times in the frame counts every time.
+    pub fn pass_ms(&self, name: &str) -> f64 {
+        self.spans
+            .iter()
+            .filter(|span| span.name == name)
+            .map(|span| span.duration_ms)
+            .sum()
+    }
+
+    /// Time both queues were busy at once, the work async compute hides behind graphics for example.
+    pub fn overlap_ms(&self, a: QueueKind, b: QueueKind) -> f64 {
+        let (Some(a), Some(b)) = (self.queue(a), self.queue(b)) else {
+            return 0.0;
+        };
+        let (mut i, mut j, mut overlap) = (0, 0, 0.0);
+        while i < a.intervals.len() && j < b.intervals.len() {
+            let (a_start, a_end) = a.intervals[i];
+            let (b_start, b_end) = b.intervals[j];
+            overlap += (a_end.min(b_end) - a_start.max(b_start)).max(0.0);
+            if a_end < b_end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        overlap
+    }
+}
+
+impl fmt::Display for FrameReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "GPU frame {:.3} ms", self.frame_ms)?;
+        for utilization in self.queues.iter() {
+            write!(
+                f,
+                "  {:<8} {:>8.3} ms busy {:>5.1}%",
+                utilization.queue.name(),
+                utilization.busy_ms,
+                self.utilization(utilization.queue) * 100.0
+            )?;
+            for other in self.queues.iter().filter(|other| other.queue != utilization.queue) {
+                write!(
+                    f,
+                    ", {:.3} ms with {}",
+                    self.overlap_ms(utilization.queue, other.queue),
+                    other.queue.name()
+                )?;
+            }
+            writeln!(f)?;
+            for span in self.spans.iter().filter(|span| span.queue == utilization.queue) {
+                writeln!(
+                    f,
+                    "    {:<24} {:>8.3} ms at {:.3} ms",
+                    span.name, span.duration_ms, span.start_ms
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Timestamp profiler for spans recorded on the graphics, compute and transfer queues.
+/// Every frame in flight has its own queries, they are read back when the frame comes around again,
+/// so the report lags `MAX_FRAMES_IN_FLIGHT` frames behind.
+///
+/// Timestamps of different queues are compared directly, which holds on current desktop drivers
+/// but isn't guaranteed by the spec.
+pub struct GpuProfiler {
+    /// nanoseconds per timestamp tick
+    timestamp_period: f64,
+    max_spans: u32,
+    /// behind a lock so spans can be opened through `&self`, which lets `GpuScope` guards nest
+    frames: Vec<[Option<Mutex<QueueQueries>>; 3]>,
+    current: usize,
+    report: Option<FrameReport>,
+    /// one Tracy GPU context per queue, created the first time the queue has timings
+    #[cfg(feature = "tracy")]
+    tracy_contexts: [Option<tracy_client::GpuContext>; 3],
+}
+
+impl GpuProfiler {
+    /// `queues` maps the profiled queues to their family, `max_spans` is the limit per queue and frame.
+    ///
+    /// # Safety
+    /// `physical_device` has to be enumerated from `instance` and be the device of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        queues: &[(QueueKind, u32)],
+        max_spans: u32,
+    ) -> VkResult<GpuProfiler> {
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+
+        let mut frames = Vec::with_capacity(MAX_FRAMES_IN_FLIGHT as usize);
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let mut frame: [Option<Mutex<QueueQueries>>; 3] = [None, None, None];
+            for &(queue, family) in queues {
+                let Some(pool) = QueryPool::timestamps(device, instance, physical_device, family, max_spans * 2)? else {
+                    continue;
+                };
+                frame[queue.index()] = Some(Mutex::new(QueueQueries {
+                    pool,
+                    spans: vec![],
+                    recording: false,
+                }));
+            }
+            frames.push(frame);
+        }
+
+        Ok(GpuProfiler {
+            timestamp_period: limits.timestamp_period as f64,
+            max_spans,
+            frames,
+            current: 0,
+            report: None,
+            #[cfg(feature = "tracy")]
+            tracy_contexts: [None, None, None],
+        })
+    }
+
+    /// Reads the timings `frame_index` recorded last time and starts recording it again.
+    /// Call it after waiting on the fence of the frame.
+    ///
+    /// # Safety
+    /// The fence of frame `frame_index` has to be waited on.
+    pub unsafe fn begin_frame(&mut self, device: &ash::Device, frame_index: usize) -> VkResult<()> {
+        self.current = frame_index;
+        let frame = &mut self.frames[frame_index];
+
+        let mut spans = vec![];
+        for queue in QueueKind::ALL {
+            let Some(queries) = frame[queue.index()].as_mut() else {
+                continue;
+            };
+            let queries = queries.get_mut().unwrap();
+            // spans that were never ended have no second timestamp, waiting on it would never return
+            for span in queries.spans.iter().filter(|span| span.ended) {
+                let timestamps = queries.pool.read_timestamps(device, span.first_query, 2, ReadMode::Wait)?;
+                let (Some(start), Some(end)) = (timestamps[0].ready(), timestamps[1].ready()) else {
+                    continue;
+                };
+                spans.push((span.name.clone(), queue, start.0, end.0.max(start.0)));
+            }
+            queries.spans.clear();
+            queries.recording = false;
+        }
+
+        if !spans.is_empty() {
+            #[cfg(feature = "tracy")]
+            self.emit_tracy_zones(&spans);
+            self.report = Some(self.build_report(spans));
+        }
+        Ok(())
+    }
+
+    /// Sends the read back spans to Tracy as GPU zones, nested spans stay nested.
+    /// The context of a queue is started with the newest timestamp of its first frame, which finished just before
+    /// the fence wait, so Tracy lines the GPU zones up with CPU time only approximately.
+    #[cfg(feature = "tracy")]
+    fn emit_tracy_zones(&mut self, spans: &[(String, QueueKind, u64, u64)]) {
+        let Some(client) = tracy_client::Client::running() else {
+            return;
+        };
+        for queue in QueueKind::ALL {
+            let mut queue_spans: Vec<&(String, QueueKind, u64, u64)> = spans.iter().filter(|span| span.1 == queue).collect();
+            if queue_spans.is_empty() {
+                continue;
+            }
+            // outer spans first when two start together
+            queue_spans.sort_by(|a, b| a.2.cmp(&b.2).then(b.3.cmp(&a.3)));
+
+            let context = match self.tracy_contexts[queue.index()].as_mut() {
+                Some(context) => context,
+                None => {
+                    let newest = queue_spans.iter().map(|span| span.3).max().unwrap_or(0);
+                    let name = format!("vulky {}", queue.name());
+                    let Ok(context) = client.clone().new_gpu_context(
+                        Some(&name),
+                        tracy_client::GpuContextType::Vulkan,
+                        newest as i64,
+                        self.timestamp_period as f32,
+                    ) else {
+                        continue;
+                    };
+                    self.tracy_contexts[queue.index()].insert(context)
+                }
+            };
+
+            let mut open: Vec<(tracy_client::GpuSpan, u64, u64)> = vec![];
+            let close = |(mut span, start, end): (tracy_client::GpuSpan, u64, u64)| {
+                span.end_zone();
+                span.upload_timestamp(start as i64, end as i64);
+            };
+            for &(ref name, _, start, end) in queue_spans {
+                while open.last().is_some_and(|parent| parent.2 <= start) {
+                    close(open.pop().unwrap());
+                }
+                // fails only with tens of thousands of zones waiting for timestamps
+                if let Ok(span) = context.span_alloc(name, "GpuProfiler", file!(), line!()) {
+                    open.push((span, start, end));
+                }
+            }
+            while let Some(span) = open.pop() {
+                close(span);
+            }
+        }
+    }
+
+    fn build_report(&self, spans: Vec<(String, QueueKind, u64, u64)>) -> FrameReport {
+        let origin = spans.iter().map(|span| span.2).min().unwrap_or(0);
+        let end = spans.iter().map(|span| span.3).max().unwrap_or(0);
+        let to_ms = |ticks: u64| ticks as f64 * self.timestamp_period / 1_000_000.0;
+
+        let spans: Vec<SpanTiming> = spans
+            .into_iter()
+            .map(|(name, queue, start, end)| SpanTiming {
+                name,
+                queue,
+                start_ms: to_ms(start - origin),
+                duration_ms: to_ms(end - start),
+            })
+            .collect();
+
+        let queues = QueueKind::ALL
+            .into_iter()
+            .filter_map(|queue| {
+                let mut intervals: Vec<(f64, f64)> = spans
+                    .iter()
+                    .filter(|span| span.queue == queue)
+                    .map(|span| (span.start_ms, span.start_ms + span.duration_ms))
+                    .collect();
+                if intervals.is_empty() {
+                    return None;
+                }
+                intervals.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                let mut merged: Vec<(f64, f64)> = vec![];
+                for (start, end) in intervals {
+                    match merged.last_mut() {
+                        Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                        _ => merged.push((start, end)),
+                    }
+                }
+                Some(QueueUtilization {
+                    queue,
+                    busy_ms: merged.iter().map(|(start, end)| end - start).sum(),
+                    intervals: merged,
+                })
+            })
+            .collect();
+
+        FrameReport {
+            spans,
+            queues,
+            frame_ms: to_ms(end - origin),
+        }
+    }
+
+    /// Resets the queries of `queue` for this frame, record it before the first span of the queue in the frame.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording, outside of a render pass, and submitted to `queue`.
+    pub unsafe fn begin_queue(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, queue: QueueKind) {
+        if let Some(queries) = self.frames[self.current][queue.index()].as_ref() {
+            let mut queries = queries.lock().unwrap();
+            queries.pool.cmd_reset_all(device, command_buffer);
+            queries.recording = true;
+        }
+    }
+
+    /// Starts a span on a command buffer submitted to `queue`, None if the queue has no timestamps,
+    /// wasn't reset with `begin_queue` or ran out of queries.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording and submitted to `queue` after `begin_queue`.
+    pub unsafe fn begin_span(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        queue: QueueKind,
+        name: &str,
+    ) -> Option<SpanId> {
+        let mut queries = self.frames[self.current][queue.index()].as_ref()?.lock().unwrap();
+        if !queries.recording || queries.spans.len() as u32 >= self.max_spans {
+            return None;
+        }
+
+        let index = queries.spans.len();
+        let first_query = index as u32 * 2;
+        queries
+            .pool
+            .cmd_write_timestamp(device, command_buffer, vk::PipelineStageFlags::TOP_OF_PIPE, first_query);
+        queries.spans.push(Span {
+            name: name.to_owned(),
+            first_query,
+            ended: false,
+        });
+        Some(SpanId { queue, index })
+    }
+
+    /// Ends `span`, recorded on the same queue as its `begin_span`.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording and submitted to the queue of `span`.
+    pub unsafe fn end_span(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, span: Option<SpanId>) {
+        let Some(span) = span else {
+            return;
+        };
+        if let Some(queries) = self.frames[self.current][span.queue.index()].as_ref() {
+            let mut queries = queries.lock().unwrap();
+            let span = &mut queries.spans[span.index];
+            span.ended = true;
+            let query = span.first_query + 1;
+            queries
+                .pool
+                .cmd_write_timestamp(device, command_buffer, vk::PipelineStageFlags::BOTTOM_OF_PIPE, query);
+        }
+    }
+
+    /// Span that ends when the returned guard is dropped, usually opened through `gpu_scope!`.
+    ///
+    /// # Safety
+    /// As for `begin_span`, the guard's command buffer has to be recording when it's dropped.
+    pub unsafe fn scope<'a>(
+        &'a self,
+        device: &'a ash::Device,
+        command_buffer: vk::CommandBuffer,
+        queue: QueueKind,
+        name: &str,
+    ) -> GpuScope<'a> {
+        GpuScope {
+            profiler: self,
+            device,
+            command_buffer,
+            span: self.begin_span(device, command_buffer, queue, name),
+        }
+    }
+
+    /// Timings of the newest frame that finished on the GPU.
+    pub fn report(&self) -> Option<&FrameReport> {
+        self.report.as_ref()
+    }
+
+    /// # Safety
+    /// No submission writing the queries of the profiler may be pending.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for frame in self.frames.iter() {
+            for queries in frame.iter().flatten() {
+                queries.lock().unwrap().pool.destroy(device);
+            }
+        }
+    }
+}
+
+/// Ends its span on drop, the command buffer has to still be recording then.
+pub struct GpuScope<'a> {
+    profiler: &'a GpuProfiler,
+    device: &'a ash::Device,
+    command_buffer: vk::CommandBuffer,
+    span: Option<SpanId>,
+}
+
+impl Drop for GpuScope<'_> {
+    fn drop(&mut self) {
+        unsafe { self.profiler.end_span(self.device, self.command_buffer, self.span) };
+    }
+}
+
+/// Times the rest of the enclosing block on the GPU, scopes nest:
+/// `gpu_scope!(profiler, device, command_buffer, "shadow pass");`
+/// The queue defaults to graphics, pass it first for others:
+/// `gpu_scope!(QueueKind::Compute, profiler, device, command_buffer, "culling");`
+#[macro_export]
+macro_rules! gpu_scope {
+    ($queue:expr, $profiler:expr, $device:expr, $command_buffer:expr, $name:expr) => {
+        let _gpu_scope = unsafe { $profiler.scope($device, $command_buffer, $queue, $name) };
+    };
+    ($profiler:expr, $device:expr, $command_buffer:expr, $name:expr) => {
+        $crate::gpu_scope!(
+            $crate::profiler::QueueKind::Graphics,
+            $profiler,
+            $device,
+            $command_buffer,
+            $name
+        )
+    };
+}
+
+/// Starts the Tracy client, profiling data is collected from then on until the returned client is dropped.
+/// Zones from `cpu_scope!` and the `GpuProfiler` are only sent while a client is running.
+#[cfg(feature = "tracy")]
+pub fn start_tracy() -> tracy_client::Client {
+    tracy_client::Client::start()
+}
+
+/// Marks the end of a frame in Tracy, call it once after presenting. Does nothing without the `tracy` feature.
+pub fn frame_mark() {
+    #[cfg(feature = "tracy")]
+    if let Some(client) = tracy_client::Client::running() {
+        client.frame_mark();
+    }
+}
+
+/// Times the rest of the enclosing block on the CPU as a Tracy zone, `cpu_scope!("record commands");`
+/// Expands to nothing without the `tracy` feature.
+#[cfg(feature = "tracy")]
+#[macro_export]
+macro_rules! cpu_scope {
+    ($name:literal) => {
+        let _cpu_scope = $crate::tracy_client::Client::running()
+            .map(|client| client.span($crate::tracy_client::span_location!($name), 0));
+    };
+}
+
+/// Times the rest of the enclosing block on the CPU as a Tracy zone, `cpu_scope!("record commands");`
+/// Expands to nothing without the `tracy` feature.
+#[cfg(not(feature = "tracy"))]
+#[macro_export]
+macro_rules! cpu_scope {
+    ($name:literal) => {};
+}
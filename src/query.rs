@@ -0,0 +1,381 @@
+use std::ffi::c_void;
+
+use ash::{prelude::VkResult, vk};
+
+use crate::features::DeviceFeatures;
+
+/// `QueryPool::pipeline_statistics` needs the pipelineStatisticsQuery feature.
+pub fn statistics_features() -> DeviceFeatures {
+    DeviceFeatures {
+        pipeline_statistics_query: true,
+        ..Default::default()
+    }
+}
+
+/// `QueryPool::reset` needs the hostQueryReset feature.
+pub fn host_reset_features() -> DeviceFeatures {
+    DeviceFeatures {
+        host_query_reset: true,
+        ..Default::default()
+    }
+}
+
+/// How reading results handles queries the GPU hasn't finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadMode {
+    /// blocks until every query is available, a query that is never written blocks forever
+    Wait,
+    /// unfinished queries are `QueryResult::NotReady`
+    Available,
+    /// unfinished occlusion and statistics queries return what they counted so far, timestamps are read like
+    /// `Available`
+    Partial,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QueryResult<T> {
+    Ready(T),
+    /// `ReadMode::Partial` of an unfinished query, at most the final value
+    Partial(T),
+    NotReady,
+}
+
+impl<T> QueryResult<T> {
+    pub fn is_ready(&self) -> bool {
+        matches!(self, QueryResult::Ready(_))
+    }
+
+    pub fn ready(self) -> Option<T> {
+        match self {
+            QueryResult::Ready(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The final or the partial value.
+    pub fn value(self) -> Option<T> {
+        match self {
+            QueryResult::Ready(value) | QueryResult::Partial(value) => Some(value),
+            QueryResult::NotReady => None,
+        }
+    }
+}
+
+/// Ticks of the GPU clock, masked to the bits the queue family writes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(pub u64);
+
+/// Counters of a statistics query, None for the ones the pool wasn't created with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: Option<u64>,
+    pub input_assembly_primitives: Option<u64>,
+    pub vertex_shader_invocations: Option<u64>,
+    pub geometry_shader_invocations: Option<u64>,
+    pub geometry_shader_primitives: Option<u64>,
+    pub clipping_invocations: Option<u64>,
+    pub clipping_primitives: Option<u64>,
+    pub fragment_shader_invocations: Option<u64>,
+    pub tessellation_control_shader_patches: Option<u64>,
+    pub tessellation_evaluation_shader_invocations: Option<u64>,
+    pub compute_shader_invocations: Option<u64>,
+}
+
+impl PipelineStatistics {
+    /// `values` are written in the order of the flag bits, one for each flag in `flags`.
+    fn from_values(flags: vk::QueryPipelineStatisticFlags, values: &[u64]) -> Self {
+        use vk::QueryPipelineStatisticFlags as F;
+        let mut values = values.iter().copied();
+        let mut next = |flag: F| if flags.contains(flag) { values.next() } else { None };
+        Self {
+            input_assembly_vertices: next(F::INPUT_ASSEMBLY_VERTICES),
+            input_assembly_primitives: next(F::INPUT_ASSEMBLY_PRIMITIVES),
+            vertex_shader_invocations: next(F::VERTEX_SHADER_INVOCATIONS),
+            geometry_shader_invocations: next(F::GEOMETRY_SHADER_INVOCATIONS),
+            geometry_shader_primitives: next(F::GEOMETRY_SHADER_PRIMITIVES),
+            clipping_invocations: next(F::CLIPPING_INVOCATIONS),
+            clipping_primitives: next(F::CLIPPING_PRIMITIVES),
+            fragment_shader_invocations: next(F::FRAGMENT_SHADER_INVOCATIONS),
+            tessellation_control_shader_patches: next(F::TESSELLATION_CONTROL_SHADER_PATCHES),
+            tessellation_evaluation_shader_invocations: next(F::TESSELLATION_EVALUATION_SHADER_INVOCATIONS),
+            compute_shader_invocations: next(F::COMPUTE_SHADER_INVOCATIONS),
+        }
+    }
+}
+
+/// A query pool of one type that reads its results as typed values, with availability instead of
+/// NOT_READY errors. Queries have to be reset before they are written, on a command buffer or with `reset`.
+pub struct QueryPool {
+    pool: vk::QueryPool,
+    query_type: vk::QueryType,
+    count: u32,
+    statistics: vk::QueryPipelineStatisticFlags,
+    /// timestamps only, mask of the bits the queue family writes
+    valid_mask: u64,
+    /// nanoseconds per timestamp tick
+    timestamp_period: f64,
+}
+
+impl QueryPool {
+    unsafe fn create(
+        device: &ash::Device,
+        query_type: vk::QueryType,
+        count: u32,
+        statistics: vk::QueryPipelineStatisticFlags,
+    ) -> VkResult<Self> {
+        let pool_info = vk::QueryPoolCreateInfo {
+            query_type,
+            query_count: count,
+            pipeline_statistics: statistics,
+            ..Default::default()
+        };
+        Ok(Self {
+            pool: device.create_query_pool(&pool_info, None)?,
+            query_type,
+            count,
+            statistics,
+            valid_mask: u64::MAX,
+            timestamp_period: 1.0,
+        })
+    }
+
+    /// None when `queue_family` doesn't write timestamps.
+    ///
+    /// # Safety
+    /// `device` has to be created from `physical_device` with a queue of `queue_family`.
+    pub unsafe fn timestamps(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        queue_family: u32,
+        count: u32,
+    ) -> VkResult<Option<Self>> {
+        let limits = instance.get_physical_device_properties(physical_device).limits;
+        let valid_bits = instance.get_physical_device_queue_family_properties(physical_device)[queue_family as usize]
+            .timestamp_valid_bits;
+        if valid_bits == 0 {
+            return Ok(None);
+        }
+        let mut pool = Self::create(
+            device,
+            vk::QueryType::TIMESTAMP,
+            count,
+            vk::QueryPipelineStatisticFlags::empty(),
+        )?;
+        pool.valid_mask = if valid_bits >= 64 { u64::MAX } else { (1 << valid_bits) - 1 };
+        pool.timestamp_period = limits.timestamp_period as f64;
+        Ok(Some(pool))
+    }
+
+    /// # Safety
+    /// The pool has to be destroyed on `device` with `destroy`.
+    pub unsafe fn occlusion(device: &ash::Device, count: u32) -> VkResult<Self> {
+        Self::create(
+            device,
+            vk::QueryType::OCCLUSION,
+            count,
+            vk::QueryPipelineStatisticFlags::empty(),
+        )
+    }
+
+    /// Counts `statistics` between `cmd_begin` and `cmd_end`, needs `statistics_features`.
+    ///
+    /// # Safety
+    /// The device has to be created with `pipeline_statistics_query` enabled.
+    pub unsafe fn pipeline_statistics(
+        device: &ash::Device,
+        count: u32,
+        statistics: vk::QueryPipelineStatisticFlags,
+    ) -> VkResult<Self> {
+        Self::create(device, vk::QueryType::PIPELINE_STATISTICS, count, statistics)
+    }
+
+    pub fn raw(&self) -> vk::QueryPool {
+        self.pool
+    }
+
+    pub fn query_type(&self) -> vk::QueryType {
+        self.query_type
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn ticks_to_ms(&self, ticks: u64) -> f64 {
+        ticks as f64 * self.timestamp_period / 1_000_000.0
+    }
+
+    /// Time from `start` to `end`, 0 when the clock wrapped in between.
+    pub fn duration_ms(&self, start: Timestamp, end: Timestamp) -> f64 {
+        self.ticks_to_ms(end.0.saturating_sub(start.0))
+    }
+
+    /// # Safety
+    /// `command_buffer` has to be recording outside of a render pass, `first + count` within the pool.
+    pub unsafe fn cmd_reset(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, first: u32, count: u32) {
+        device.cmd_reset_query_pool(command_buffer, self.pool, first, count);
+    }
+
+    /// # Safety
+    /// As for `cmd_reset`, no submission still in flight may use the queries.
+    pub unsafe fn cmd_reset_all(&self, device: &ash::Device, command_buffer: vk::CommandBuffer) {
+        self.cmd_reset(device, command_buffer, 0, self.count);
+    }
+
+    /// Resets on the host, once the submissions using the queries finished. Needs `host_reset_features`.
+    ///
+    /// # Safety
+    /// `host_query_reset` has to be enabled and no pending submission may use the queries.
+    pub unsafe fn reset(&self, device: &ash::Device, first: u32, count: u32) {
+        device.reset_query_pool(self.pool, first, count);
+    }
+
+    /// # Safety
+    /// `query` has to be reset since its last write and within the pool, `stage` supported by the queue.
+    pub unsafe fn cmd_write_timestamp(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        stage: vk::PipelineStageFlags,
+        query: u32,
+    ) {
+        debug_assert_eq!(self.query_type, vk::QueryType::TIMESTAMP);
+        device.cmd_write_timestamp(command_buffer, stage, self.pool, query);
+    }
+
+    /// PRECISE in `flags` counts exact samples for occlusion queries instead of any non-zero value.
+    ///
+    /// # Safety
+    /// `query` has to be reset and not active, `cmd_end` has to follow in the same subpass.
+    pub unsafe fn cmd_begin(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        query: u32,
+        flags: vk::QueryControlFlags,
+    ) {
+        device.cmd_begin_query(command_buffer, self.pool, query, flags);
+    }
+
+    /// # Safety
+    /// `query` has to be begun in the same command buffer and subpass.
+    pub unsafe fn cmd_end(&self, device: &ash::Device, command_buffer: vk::CommandBuffer, query: u32) {
+        device.cmd_end_query(command_buffer, self.pool, query);
+    }
+
+    /// # Safety
+    /// With a waiting `mode` the queries have to be written by a submitted command buffer, or this never returns.
+    pub unsafe fn read_timestamps(
+        &self,
+        device: &ash::Device,
+        first: u32,
+        count: u32,
+        mode: ReadMode,
+    ) -> VkResult<Vec<QueryResult<Timestamp>>> {
+        assert_eq!(self.query_type, vk::QueryType::TIMESTAMP, "Not a timestamp query pool");
+        self.read(device, first, count, mode, |values| Timestamp(values[0] & self.valid_mask))
+    }
+
+    /// Samples that passed the depth and stencil tests, only zero or not without PRECISE queries.
+    ///
+    /// # Safety
+    /// As for `read_timestamps`.
+    pub unsafe fn read_occlusion(
+        &self,
+        device: &ash::Device,
+        first: u32,
+        count: u32,
+        mode: ReadMode,
+    ) -> VkResult<Vec<QueryResult<u64>>> {
+        assert_eq!(self.query_type, vk::QueryType::OCCLUSION, "Not an occlusion query pool");
+        self.read(device, first, count, mode, |values| values[0])
+    }
+
+    /// # Safety
+    /// As for `read_timestamps`, the pool has to be created with `pipeline_statistics`.
+    pub unsafe fn read_statistics(
+        &self,
+        device: &ash::Device,
+        first: u32,
+        count: u32,
+        mode: ReadMode,
+    ) -> VkResult<Vec<QueryResult<PipelineStatistics>>> {
+        assert_eq!(
+            self.query_type,
+            vk::QueryType::PIPELINE_STATISTICS,
+            "Not a pipeline statistics query pool"
+        );
+        self.read(device, first, count, mode, |values| {
+            PipelineStatistics::from_values(self.statistics, values)
+        })
+    }
+
+    /// Results of `count` queries from `first` with their availability appended, NOT_READY isn't an error.
+    unsafe fn read<T>(
+        &self,
+        device: &ash::Device,
+        first: u32,
+        count: u32,
+        mode: ReadMode,
+        parse: impl Fn(&[u64]) -> T,
+    ) -> VkResult<Vec<QueryResult<T>>> {
+        assert!(
+            first + count <= self.count,
+            "Queries {}..{} are out of the {} of the pool",
+            first,
+            first + count,
+            self.count
+        );
+        let values = match self.query_type {
+            vk::QueryType::PIPELINE_STATISTICS => self.statistics.as_raw().count_ones() as usize,
+            _ => 1,
+        };
+        let stride = values + 1;
+        let mut data = vec![0u64; stride * count as usize];
+
+        // timestamps can't be read partially
+        let partial = mode == ReadMode::Partial && self.query_type != vk::QueryType::TIMESTAMP;
+        let mut flags = vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY;
+        if mode == ReadMode::Wait {
+            flags |= vk::QueryResultFlags::WAIT;
+        }
+        if partial {
+            flags |= vk::QueryResultFlags::PARTIAL;
+        }
+        let result = (device.fp_v1_0().get_query_pool_results)(
+            device.handle(),
+            self.pool,
+            first,
+            count,
+            std::mem::size_of_val(data.as_slice()),
+            data.as_mut_ptr() as *mut c_void,
+            (stride * std::mem::size_of::<u64>()) as vk::DeviceSize,
+            flags,
+        );
+        match result {
+            vk::Result::SUCCESS | vk::Result::NOT_READY => {}
+            e => return Err(e),
+        }
+
+        Ok(data
+            .chunks_exact(stride)
+            .map(|query| {
+                let (query_values, available) = query.split_at(values);
+                if available[0] != 0 {
+                    QueryResult::Ready(parse(query_values))
+                } else if partial {
+                    QueryResult::Partial(parse(query_values))
+                } else {
+                    QueryResult::NotReady
+                }
+            })
+            .collect())
+    }
+
+    /// # Safety
+    /// The submissions writing the queries have to be finished.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_query_pool(self.pool, None);
+    }
+}
@@ -0,0 +1,674 @@
+use std::{collections::HashMap, fmt};
+
+use crate::error::{Error, Result};
+use ash::vk;
+
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT_TRUE: u32 = 48;
+const OP_SPEC_CONSTANT_FALSE: u32 = 49;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_SPEC_ID: u32 = 1;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+
+/// Component type of a vertex input or attribute format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NumericType {
+    Float,
+    Sint,
+    Uint,
+    /// 64 bit float
+    Double,
+}
+
+/// Components of one location, a `vec3` is `{ Float, 3 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ComponentLayout {
+    pub numeric: NumericType,
+    pub components: u32,
+}
+
+impl fmt::Display for ComponentLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let prefix = match self.numeric {
+            NumericType::Float => "",
+            NumericType::Sint => "i",
+            NumericType::Uint => "u",
+            NumericType::Double => "d",
+        };
+        match (self.numeric, self.components) {
+            (NumericType::Float, 1) => write!(f, "float"),
+            (NumericType::Sint, 1) => write!(f, "int"),
+            (NumericType::Uint, 1) => write!(f, "uint"),
+            (NumericType::Double, 1) => write!(f, "double"),
+            (_, components) => write!(f, "{}vec{}", prefix, components),
+        }
+    }
+}
+
+/// An `in` variable of a vertex shader, matrices take one location per column.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VertexInput {
+    pub location: u32,
+    pub layout: ComponentLayout,
+    pub name: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum Type {
+    Bool,
+    Scalar(NumericType),
+    Vector(NumericType, u32),
+    /// column type and column count
+    Matrix(u32, u32),
+    /// pointee
+    Pointer(u32),
+    /// dim and sampled, 1 for sampled images and 2 for storage images
+    Image {
+        dim: u32,
+        sampled: u32,
+    },
+    Sampler,
+    SampledImage,
+    /// element type and the id of the length constant
+    Array(u32, u32),
+    RuntimeArray(u32),
+    Struct,
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct Decorations {
+    location: Option<u32>,
+    set: Option<u32>,
+    binding: Option<u32>,
+    built_in: bool,
+    buffer_block: bool,
+    spec_id: Option<u32>,
+}
+
+/// The parts of a SPIR-V module needed to reflect its interface.
+struct Module {
+    names: HashMap<u32, String>,
+    decorations: HashMap<u32, Decorations>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    /// type and id of the specialization constants
+    spec_constants: Vec<(u32, u32)>,
+    /// pointer type, id and storage class
+    variables: Vec<(u32, u32, u32)>,
+    /// interface of the vertex entry point
+    vertex_interface: Option<Vec<u32>>,
+}
+
+fn words(spirv: &[u8]) -> Result<Vec<u32>> {
+    if !spirv.len().is_multiple_of(4) || spirv.len() < 20 {
+        return Err(Error::msg("SPIR-V has to be a whole number of words with a header"));
+    }
+    let mut words: Vec<u32> = spirv
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect();
+    if words[0] == SPIRV_MAGIC.swap_bytes() {
+        for word in words.iter_mut() {
+            *word = word.swap_bytes();
+        }
+    } else if words[0] != SPIRV_MAGIC {
+        return Err(Error::msg("Not a SPIR-V module"));
+    }
+    Ok(words)
+}
+
+fn string(operands: &[u32]) -> String {
+    let bytes: Vec<u8> = operands
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+impl Module {
+    fn parse(spirv: &[u8]) -> Result<Module> {
+        let words = words(spirv)?;
+        let mut module = Module {
+            names: HashMap::new(),
+            decorations: HashMap::new(),
+            types: HashMap::new(),
+            constants: HashMap::new(),
+            spec_constants: vec![],
+            variables: vec![],
+            vertex_interface: None,
+        };
+
+        let mut offset = 5;
+        while offset < words.len() {
+            let word_count = (words[offset] >> 16) as usize;
+            let opcode = words[offset] & 0xffff;
+            if word_count == 0 || offset + word_count > words.len() {
+                return Err(Error::msg("Truncated SPIR-V instruction"));
+            }
+            let operands = &words[offset + 1..offset + word_count];
+            offset += word_count;
+
+            let types = &mut module.types;
+            match opcode {
+                OP_NAME if !operands.is_empty() => {
+                    module.names.insert(operands[0], string(&operands[1..]));
+                }
+                OP_ENTRY_POINT if operands.len() >= 2 && operands[0] == EXECUTION_MODEL_VERTEX => {
+                    // the name is followed by the interface variables
+                    let name_words = operands[2..].iter().position(|word| word.to_le_bytes().contains(&0));
+                    if let Some(name_words) = name_words {
+                        module.vertex_interface = Some(operands[2 + name_words + 1..].to_vec());
+                    }
+                }
+                OP_DECORATE if operands.len() >= 2 => {
+                    let decorations = module.decorations.entry(operands[0]).or_default();
+                    let value = operands.get(2).copied();
+                    match operands[1] {
+                        DECORATION_LOCATION => decorations.location = value,
+                        DECORATION_DESCRIPTOR_SET => decorations.set = value,
+                        DECORATION_BINDING => decorations.binding = value,
+                        DECORATION_BUILT_IN => decorations.built_in = true,
+                        DECORATION_BUFFER_BLOCK => decorations.buffer_block = true,
+                        DECORATION_SPEC_ID => decorations.spec_id = value,
+                        _ => {}
+                    }
+                }
+                OP_TYPE_BOOL if !operands.is_empty() => {
+                    types.insert(operands[0], Type::Bool);
+                }
+                OP_TYPE_INT if operands.len() >= 3 => {
+                    let numeric = if operands[2] == 1 {
+                        NumericType::Sint
+                    } else {
+                        NumericType::Uint
+                    };
+                    types.insert(operands[0], Type::Scalar(numeric));
+                }
+                OP_TYPE_FLOAT if operands.len() >= 2 => {
+                    let numeric = if operands[1] == 64 {
+                        NumericType::Double
+                    } else {
+                        NumericType::Float
+                    };
+                    types.insert(operands[0], Type::Scalar(numeric));
+                }
+                OP_TYPE_VECTOR if operands.len() >= 3 => {
+                    if let Some(Type::Scalar(numeric)) = types.get(&operands[1]) {
+                        types.insert(operands[0], Type::Vector(*numeric, operands[2]));
+                    }
+                }
+                OP_TYPE_MATRIX if operands.len() >= 3 => {
+                    types.insert(operands[0], Type::Matrix(operands[1], operands[2]));
+                }
+                OP_TYPE_IMAGE if operands.len() >= 7 => {
+                    types.insert(
+                        operands[0],
+                        Type::Image {
+                            dim: operands[2],
+                            sampled: operands[6],
+                        },
+                    );
+                }
+                OP_TYPE_SAMPLER if !operands.is_empty() => {
+                    types.insert(operands[0], Type::Sampler);
+                }
+                OP_TYPE_SAMPLED_IMAGE if !operands.is_empty() => {
+                    types.insert(operands[0], Type::SampledImage);
+                }
+                OP_TYPE_ARRAY if operands.len() >= 3 => {
+                    types.insert(operands[0], Type::Array(operands[1], operands[2]));
+                }
+                OP_TYPE_RUNTIME_ARRAY if operands.len() >= 2 => {
+                    types.insert(operands[0], Type::RuntimeArray(operands[1]));
+                }
+                OP_TYPE_STRUCT if !operands.is_empty() => {
+                    types.insert(operands[0], Type::Struct);
+                }
+                OP_TYPE_ACCELERATION_STRUCTURE if !operands.is_empty() => {
+                    types.insert(operands[0], Type::AccelerationStructure);
+                }
+                OP_TYPE_POINTER if operands.len() >= 3 => {
+                    types.insert(operands[0], Type::Pointer(operands[2]));
+                }
+                OP_CONSTANT if operands.len() >= 3 => {
+                    module.constants.insert(operands[1], operands[2]);
+                }
+                OP_SPEC_CONSTANT_TRUE | OP_SPEC_CONSTANT_FALSE | OP_SPEC_CONSTANT if operands.len() >= 2 => {
+                    module.spec_constants.push((operands[0], operands[1]));
+                }
+                OP_VARIABLE if operands.len() >= 3 => {
+                    module.variables.push((operands[0], operands[1], operands[2]));
+                }
+                _ => {}
+            }
+        }
+        Ok(module)
+    }
+
+    fn decorations(&self, id: u32) -> Option<&Decorations> {
+        self.decorations.get(&id)
+    }
+
+    fn name(&self, id: u32) -> Option<String> {
+        self.names.get(&id).filter(|name| !name.is_empty()).cloned()
+    }
+
+    fn component_layout(&self, id: u32) -> Option<ComponentLayout> {
+        match self.types.get(&id) {
+            Some(Type::Scalar(numeric)) => Some(ComponentLayout {
+                numeric: *numeric,
+                components: 1,
+            }),
+            Some(Type::Vector(numeric, components)) => Some(ComponentLayout {
+                numeric: *numeric,
+                components: *components,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Location inputs of the vertex entry point of `spirv`, built-ins like `gl_VertexIndex` are left out.
+pub fn vertex_inputs(spirv: &[u8]) -> Result<Vec<VertexInput>> {
+    let module = Module::parse(spirv)?;
+    let Some(interface) = &module.vertex_interface else {
+        return Err(Error::msg("SPIR-V module has no vertex entry point"));
+    };
+
+    let mut inputs = vec![];
+    for &(pointer, id, storage_class) in module.variables.iter() {
+        if storage_class != STORAGE_CLASS_INPUT || !interface.contains(&id) {
+            continue;
+        }
+        let Some(decorations) = module.decorations(id).filter(|decorations| !decorations.built_in) else {
+            continue;
+        };
+        let (Some(location), Some(Type::Pointer(pointee))) = (decorations.location, module.types.get(&pointer)) else {
+            continue;
+        };
+        let name = module.name(id);
+        match module.types.get(pointee) {
+            Some(Type::Matrix(column, columns)) => {
+                if let Some(layout) = module.component_layout(*column) {
+                    for index in 0..*columns {
+                        inputs.push(VertexInput {
+                            location: location + index,
+                            layout,
+                            name: name.as_ref().map(|name| format!("{}[{}]", name, index)),
+                        });
+                    }
+                }
+            }
+            _ => {
+                if let Some(layout) = module.component_layout(*pointee) {
+                    inputs.push(VertexInput { location, layout, name });
+                }
+            }
+        }
+    }
+    inputs.sort_by_key(|input| input.location);
+    Ok(inputs)
+}
+
+/// A `layout(constant_id = N)` constant of a shader, `numeric` is None for a bool.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpecConstant {
+    pub constant_id: u32,
+    pub numeric: Option<NumericType>,
+    pub name: Option<String>,
+}
+
+/// Specialization constants `spirv` declares, sorted by constant id. Constants that are only used to compute
+/// other ones, `OpSpecConstantOp`, have no id and are left out.
+pub fn spec_constants(spirv: &[u8]) -> Result<Vec<SpecConstant>> {
+    let module = Module::parse(spirv)?;
+    let mut constants = vec![];
+    for &(type_id, id) in module.spec_constants.iter() {
+        let Some(constant_id) = module.decorations(id).and_then(|decorations| decorations.spec_id) else {
+            continue;
+        };
+        let numeric = match module.types.get(&type_id) {
+            Some(Type::Bool) => None,
+            Some(Type::Scalar(numeric)) => Some(*numeric),
+            _ => continue,
+        };
+        constants.push(SpecConstant {
+            constant_id,
+            numeric,
+            name: module.name(id),
+        });
+    }
+    constants.sort_by_key(|constant| constant.constant_id);
+    Ok(constants)
+}
+
+/// A descriptor a shader declares, `count` is 0 for runtime sized arrays.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+    /// every stage of the pipeline that declares it
+    pub stages: vk::ShaderStageFlags,
+    pub name: Option<String>,
+}
+
+/// Descriptors declared by `spirv`, which is the `stage` shader of a pipeline.
+pub fn descriptor_bindings(spirv: &[u8], stage: vk::ShaderStageFlags) -> Result<Vec<ShaderBinding>> {
+    let module = Module::parse(spirv)?;
+
+    let mut bindings = vec![];
+    for &(pointer, id, storage_class) in module.variables.iter() {
+        if !matches!(
+            storage_class,
+            STORAGE_CLASS_UNIFORM_CONSTANT | STORAGE_CLASS_UNIFORM | STORAGE_CLASS_STORAGE_BUFFER
+        ) {
+            continue;
+        }
+        let Some(decorations) = module.decorations(id) else {
+            continue;
+        };
+        let (Some(set), Some(binding), Some(Type::Pointer(pointee))) =
+            (decorations.set, decorations.binding, module.types.get(&pointer))
+        else {
+            continue;
+        };
+
+        // arrays of descriptors are unwrapped to their element
+        let (element, count) = match module.types.get(pointee) {
+            Some(Type::Array(element, length)) => (*element, module.constants.get(length).copied().unwrap_or(1)),
+            Some(Type::RuntimeArray(element)) => (*element, 0),
+            _ => (*pointee, 1),
+        };
+        let element_decorations = module.decorations(element);
+        let descriptor_type = match (storage_class, module.types.get(&element)) {
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => vk::DescriptorType::STORAGE_BUFFER,
+            (STORAGE_CLASS_UNIFORM, _) if element_decorations.is_some_and(|decorations| decorations.buffer_block) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (STORAGE_CLASS_UNIFORM, _) => vk::DescriptorType::UNIFORM_BUFFER,
+            (_, Some(Type::SampledImage)) => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (_, Some(Type::Sampler)) => vk::DescriptorType::SAMPLER,
+            (_, Some(Type::AccelerationStructure)) => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            (_, Some(Type::Image { dim, sampled })) => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            _ => continue,
+        };
+
+        bindings.push(ShaderBinding {
+            set,
+            binding,
+            descriptor_type,
+            count,
+            stages: stage,
+            name: module.name(id),
+        });
+    }
+    bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    Ok(bindings)
+}
+
+/// Combines the bindings of the stages of a pipeline, bindings declared by several stages get all of them.
+pub fn merge_bindings(stages: &[Vec<ShaderBinding>]) -> Vec<ShaderBinding> {
+    let mut merged: Vec<ShaderBinding> = vec![];
+    for binding in stages.iter().flatten() {
+        match merged
+            .iter_mut()
+            .find(|other| other.set == binding.set && other.binding == binding.binding)
+        {
+            Some(other) => {
+                other.stages |= binding.stages;
+                other.count = other.count.max(binding.count);
+            }
+            None => merged.push(binding.clone()),
+        }
+    }
+    merged.sort_by_key(|binding| (binding.set, binding.binding));
+    merged
+}
+
+/// The name used in validation errors, "combined image sampler" for COMBINED_IMAGE_SAMPLER.
+pub fn descriptor_type_name(descriptor_type: vk::DescriptorType) -> String {
+    match descriptor_type {
+        vk::DescriptorType::SAMPLER => "sampler".to_owned(),
+        vk::DescriptorType::COMBINED_IMAGE_SAMPLER => "combined image sampler".to_owned(),
+        vk::DescriptorType::SAMPLED_IMAGE => "sampled image".to_owned(),
+        vk::DescriptorType::STORAGE_IMAGE => "storage image".to_owned(),
+        vk::DescriptorType::UNIFORM_TEXEL_BUFFER => "uniform texel buffer".to_owned(),
+        vk::DescriptorType::STORAGE_TEXEL_BUFFER => "storage texel buffer".to_owned(),
+        vk::DescriptorType::UNIFORM_BUFFER => "uniform buffer".to_owned(),
+        vk::DescriptorType::STORAGE_BUFFER => "storage buffer".to_owned(),
+        vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC => "dynamic uniform buffer".to_owned(),
+        vk::DescriptorType::STORAGE_BUFFER_DYNAMIC => "dynamic storage buffer".to_owned(),
+        vk::DescriptorType::INPUT_ATTACHMENT => "input attachment".to_owned(),
+        vk::DescriptorType::ACCELERATION_STRUCTURE_KHR => "acceleration structure".to_owned(),
+        other => format!("{:?}", other),
+    }
+}
+
+fn compatible(shader: vk::DescriptorType, provided: vk::DescriptorType) -> bool {
+    // dynamic buffers read the same in the shader
+    shader == provided
+        || (shader == vk::DescriptorType::UNIFORM_BUFFER && provided == vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+        || (shader == vk::DescriptorType::STORAGE_BUFFER && provided == vk::DescriptorType::STORAGE_BUFFER_DYNAMIC)
+}
+
+fn binding_label(binding: &ShaderBinding) -> String {
+    match &binding.name {
+        Some(name) => format!("set {} binding {} ({})", binding.set, binding.binding, name),
+        None => format!("set {} binding {}", binding.set, binding.binding),
+    }
+}
+
+/// Checks the layout of descriptor set `set` against what the shaders declare in it.
+/// Every declared binding has to exist with the same type, enough descriptors and its stages enabled.
+pub fn validate_set_layout(
+    set: u32,
+    shader_bindings: &[ShaderBinding],
+    layout: &[vk::DescriptorSetLayoutBinding],
+) -> Result<()> {
+    let mut errors = vec![];
+    for binding in shader_bindings.iter().filter(|binding| binding.set == set) {
+        let label = binding_label(binding);
+        let Some(layout_binding) = layout.iter().find(|layout| layout.binding == binding.binding) else {
+            errors.push(format!(
+                "{} expects {}, the set layout has no such binding",
+                label,
+                descriptor_type_name(binding.descriptor_type)
+            ));
+            continue;
+        };
+        if !compatible(binding.descriptor_type, layout_binding.descriptor_type) {
+            errors.push(format!(
+                "{} expects {}, the set layout has {}",
+                label,
+                descriptor_type_name(binding.descriptor_type),
+                descriptor_type_name(layout_binding.descriptor_type)
+            ));
+        }
+        if layout_binding.descriptor_count < binding.count {
+            errors.push(format!(
+                "{} expects {} descriptors, the set layout has {}",
+                label, binding.count, layout_binding.descriptor_count
+            ));
+        }
+        if !layout_binding.stage_flags.contains(binding.stages) {
+            errors.push(format!(
+                "{} is used by {:?}, the set layout only enables {:?}",
+                label, binding.stages, layout_binding.stage_flags
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::msg(errors.join("\n")))
+    }
+}
+
+/// Checks `writes` to a set the pipeline binds at index `set` against the shader declarations.
+pub fn validate_descriptor_writes(
+    set: u32,
+    shader_bindings: &[ShaderBinding],
+    writes: &[vk::WriteDescriptorSet],
+) -> Result<()> {
+    let mut errors = vec![];
+    for write in writes {
+        let Some(binding) = shader_bindings
+            .iter()
+            .find(|binding| binding.set == set && binding.binding == write.dst_binding)
+        else {
+            // writes to bindings no shader reads are harmless
+            continue;
+        };
+        let label = binding_label(binding);
+        if !compatible(binding.descriptor_type, write.descriptor_type) {
+            errors.push(format!(
+                "{} expects {}, got {}",
+                label,
+                descriptor_type_name(binding.descriptor_type),
+                descriptor_type_name(write.descriptor_type)
+            ));
+        }
+        let last = write.dst_array_element + write.descriptor_count;
+        if binding.count != 0 && last > binding.count {
+            errors.push(format!(
+                "{} has {} descriptors, the write ends at element {}",
+                label, binding.count, last
+            ));
+        }
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::msg(errors.join("\n")))
+    }
+}
+
+/// Layout the vertex input stage delivers for `format`, None for formats without a known layout.
+pub fn format_layout(format: vk::Format) -> Option<ComponentLayout> {
+    use NumericType::*;
+
+    let (numeric, components) = match format {
+        vk::Format::R32_SFLOAT | vk::Format::R16_SFLOAT | vk::Format::R8_UNORM | vk::Format::R8_SNORM => (Float, 1),
+        vk::Format::R32G32_SFLOAT | vk::Format::R16G16_SFLOAT | vk::Format::R8G8_UNORM | vk::Format::R16G16_UNORM => {
+            (Float, 2)
+        }
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R8G8B8_UNORM => (Float, 3),
+        vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R16G16B16A16_UNORM
+        | vk::Format::A2B10G10R10_UNORM_PACK32 => (Float, 4),
+        vk::Format::R32_SINT | vk::Format::R16_SINT | vk::Format::R8_SINT => (Sint, 1),
+        vk::Format::R32G32_SINT | vk::Format::R16G16_SINT => (Sint, 2),
+        vk::Format::R32G32B32_SINT => (Sint, 3),
+        vk::Format::R32G32B32A32_SINT | vk::Format::R16G16B16A16_SINT | vk::Format::R8G8B8A8_SINT => (Sint, 4),
+        vk::Format::R32_UINT | vk::Format::R16_UINT | vk::Format::R8_UINT => (Uint, 1),
+        vk::Format::R32G32_UINT | vk::Format::R16G16_UINT => (Uint, 2),
+        vk::Format::R32G32B32_UINT => (Uint, 3),
+        vk::Format::R32G32B32A32_UINT | vk::Format::R16G16B16A16_UINT | vk::Format::R8G8B8A8_UINT => (Uint, 4),
+        vk::Format::R64_SFLOAT => (Double, 1),
+        vk::Format::R64G64_SFLOAT => (Double, 2),
+        vk::Format::R64G64B64_SFLOAT => (Double, 3),
+        vk::Format::R64G64B64A64_SFLOAT => (Double, 4),
+        _ => return None,
+    };
+    Some(ComponentLayout { numeric, components })
+}
+
+/// Checks that every input of the vertex shader gets an attribute of the same component type and count.
+/// The error lists every location with what the shader reads and what the attributes provide.
+pub fn validate_vertex_layout(
+    shader_name: &str,
+    inputs: &[VertexInput],
+    attributes: &[vk::VertexInputAttributeDescription],
+) -> Result<()> {
+    let mut lines = vec![];
+    let mut mismatch = false;
+
+    for input in inputs {
+        let shader = match &input.name {
+            Some(name) => format!("{} {}", input.layout, name),
+            None => input.layout.to_string(),
+        };
+        let line = match attributes.iter().find(|attribute| attribute.location == input.location) {
+            None => {
+                mismatch = true;
+                format!("- location {}: shader reads {}, no attribute", input.location, shader)
+            }
+            Some(attribute) => match format_layout(attribute.format) {
+                Some(layout) if layout != input.layout => {
+                    mismatch = true;
+                    format!(
+                        "- location {}: shader reads {}, attribute is {:?} ({})",
+                        input.location, shader, attribute.format, layout
+                    )
+                }
+                // unknown formats aren't checked
+                _ => format!("  location {}: {} from {:?}", input.location, shader, attribute.format),
+            },
+        };
+        lines.push(line);
+    }
+    for attribute in attributes {
+        if !inputs.iter().any(|input| input.location == attribute.location) {
+            lines.push(format!(
+                "  location {}: {:?} isn't read by the shader",
+                attribute.location, attribute.format
+            ));
+        }
+    }
+
+    if mismatch {
+        return Err(Error::shader(
+            shader_name,
+            format!("Vertex attributes don't match its inputs:\n{}", lines.join("\n")),
+        ));
+    }
+    Ok(())
+}
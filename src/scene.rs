@@ -0,0 +1,487 @@
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+use nalgebra as glm;
+
+use crate::{gltf_import::GltfScene, layers::RenderLayers};
+
+/// Handle to a node, stays invalid after the node is removed even if its slot is reused.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId {
+    index: u32,
+    generation: u32,
+}
+
+/// Translation, rotation and scale relative to the parent node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "bevy_ecs", derive(bevy_ecs::component::Component))]
+pub struct Transform {
+    pub translation: glm::Vector3<f32>,
+    pub rotation: glm::UnitQuaternion<f32>,
+    pub scale: glm::Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        translation: glm::Vector3::new(0.0, 0.0, 0.0),
+        rotation: glm::UnitQuaternion::new_unchecked(glm::Quaternion::new(1.0, 0.0, 0.0, 0.0)),
+        scale: glm::Vector3::new(1.0, 1.0, 1.0),
+    };
+
+    pub fn from_translation(translation: glm::Vector3<f32>) -> Self {
+        Self {
+            translation,
+            ..Self::IDENTITY
+        }
+    }
+
+    /// Splits an affine matrix without shear, a negative determinant is put into the x scale.
+    pub fn from_matrix(matrix: &glm::Matrix4<f32>) -> Self {
+        let translation = matrix.fixed_view::<3, 1>(0, 3).into_owned();
+        let mut basis = matrix.fixed_view::<3, 3>(0, 0).into_owned();
+
+        let mut scale = glm::Vector3::new(basis.column(0).norm(), basis.column(1).norm(), basis.column(2).norm());
+        if basis.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        for axis in 0..3 {
+            if scale[axis] != 0.0 {
+                let column = basis.column(axis) / scale[axis];
+                basis.set_column(axis, &column);
+            }
+        }
+        let rotation = glm::UnitQuaternion::from_matrix(&basis);
+
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub fn matrix(&self) -> glm::Matrix4<f32> {
+        glm::Matrix4::new_translation(&self.translation)
+            * self.rotation.to_homogeneous()
+            * glm::Matrix4::new_nonuniform_scaling(&self.scale)
+    }
+
+    /// Blends towards `other`, `t` of 0 is `self` and 1 is `other`. Rotations take the shortest arc.
+    pub fn interpolate(&self, other: &Transform, t: f32) -> Transform {
+        // opposite rotations have no unique arc, snap to the closer end
+        let rotation = self
+            .rotation
+            .try_slerp(&other.rotation, t, f32::EPSILON)
+            .unwrap_or(if t < 0.5 { self.rotation } else { other.rotation });
+        Transform {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation,
+            scale: self.scale.lerp(&other.scale, t),
+        }
+    }
+}
+
+/// Transform written by fixed timestep updates and read by the renderer in between them.
+/// Rendering `interpolated(FixedTimestep::alpha)` hides the stutter of a simulation running at another rate.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InterpolatedTransform {
+    previous: Transform,
+    current: Transform,
+}
+
+impl InterpolatedTransform {
+    pub fn new(transform: Transform) -> Self {
+        Self {
+            previous: transform,
+            current: transform,
+        }
+    }
+
+    /// Stores the result of a fixed update, the last one becomes the start of the interpolation.
+    pub fn set(&mut self, transform: Transform) {
+        self.previous = self.current;
+        self.current = transform;
+    }
+
+    /// Moves without blending from the old position, for spawns and teleports.
+    pub fn teleport(&mut self, transform: Transform) {
+        self.previous = transform;
+        self.current = transform;
+    }
+
+    pub fn current(&self) -> &Transform {
+        &self.current
+    }
+
+    pub fn previous(&self) -> &Transform {
+        &self.previous
+    }
+
+    pub fn interpolated(&self, alpha: f32) -> Transform {
+        self.previous.interpolate(&self.current, alpha)
+    }
+}
+
+#[derive(Clone)]
+pub struct Node {
+    pub name: Option<String>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Transform,
+    world: glm::Matrix4<f32>,
+    /// the local transform changed since the last `update_transforms`
+    dirty: bool,
+    /// index into the meshes of whoever built the scene, for example `GltfScene::meshes`
+    pub mesh: Option<usize>,
+    /// index into the cameras of whoever built the scene
+    pub camera: Option<usize>,
+    pub layers: RenderLayers,
+    /// free form component data keyed by component name, for example from a prefab
+    pub components: BTreeMap<String, toml::Value>,
+    /// name of the prefab this node is the root of an instance of, see `prefab::Prefabs`
+    pub prefab: Option<String>,
+}
+
+impl Node {
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    pub fn local_transform(&self) -> &Transform {
+        &self.local
+    }
+
+    /// Only up to date after `Scene::update_transforms`.
+    pub fn world_transform(&self) -> &glm::Matrix4<f32> {
+        &self.world
+    }
+}
+
+#[derive(Clone)]
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+/// Node hierarchy with world transforms that are only recomputed for changed subtrees.
+/// Accessing a removed node through its old id panics.
+/// Clones keep the ids valid, which is what undo snapshots rely on.
+#[derive(Clone, Default)]
+pub struct Scene {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builds the hierarchy of the default scene, node mesh and camera indices point into `gltf`.
+    /// Returns the ids of the glTF nodes in file order, nodes outside the default scene are None.
+    pub fn from_gltf(gltf: &GltfScene) -> (Scene, Vec<Option<NodeId>>) {
+        let mut scene = Scene::new();
+        let mut ids = vec![None; gltf.nodes.len()];
+
+        let mut stack: Vec<(usize, Option<NodeId>)> = gltf.roots.iter().rev().map(|root| (*root, None)).collect();
+        while let Some((index, parent)) = stack.pop() {
+            let desc = &gltf.nodes[index];
+            let id = scene.add_node(desc.name.clone(), parent, Transform::from_matrix(&desc.local_transform));
+            let node = scene.node_mut(id);
+            node.mesh = desc.mesh;
+            node.camera = desc.camera;
+            ids[index] = Some(id);
+
+            stack.extend(desc.children.iter().rev().map(|child| (*child, Some(id))));
+        }
+
+        scene.update_transforms();
+        (scene, ids)
+    }
+
+    pub fn add_node(&mut self, name: Option<String>, parent: Option<NodeId>, transform: Transform) -> NodeId {
+        let node = Node {
+            name,
+            parent,
+            children: vec![],
+            local: transform,
+            world: glm::Matrix4::identity(),
+            dirty: true,
+            mesh: None,
+            camera: None,
+            layers: RenderLayers::DEFAULT,
+            components: BTreeMap::new(),
+            prefab: None,
+        };
+
+        let id = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.node = Some(node);
+                NodeId {
+                    index,
+                    generation: slot.generation,
+                }
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    node: Some(node),
+                });
+                NodeId {
+                    index: self.slots.len() as u32 - 1,
+                    generation: 0,
+                }
+            }
+        };
+
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Removes the node and its whole subtree.
+    pub fn remove_node(&mut self, id: NodeId) {
+        let parent = self.node(id).parent;
+        self.detach(id, parent);
+
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let slot = &mut self.slots[id.index as usize];
+            let node = slot.node.take().unwrap();
+            slot.generation = slot.generation.wrapping_add(1);
+            self.free.push(id.index);
+            stack.extend(node.children);
+        }
+    }
+
+    pub fn contains(&self, id: NodeId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.slots
+            .get(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        self.get(id).expect("Node was removed from the scene")
+    }
+
+    /// Transforms can only be changed through `set_local_transform`, so they are tracked.
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.slots
+            .get_mut(id.index as usize)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+            .expect("Node was removed from the scene")
+    }
+
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    pub fn set_local_transform(&mut self, id: NodeId, transform: Transform) {
+        let node = self.node_mut(id);
+        node.local = transform;
+        node.dirty = true;
+    }
+
+    /// Moves the node under `parent`, None makes it a root. The local transform is kept.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(current) = ancestor {
+            if current == id {
+                return Err(Error::msg("A node can't be parented to itself or its descendants"));
+            }
+            ancestor = self.node(current).parent;
+        }
+
+        let old_parent = self.node(id).parent;
+        self.detach(id, old_parent);
+        match parent {
+            Some(parent) => self.node_mut(parent).children.push(id),
+            None => self.roots.push(id),
+        }
+
+        let node = self.node_mut(id);
+        node.parent = parent;
+        node.dirty = true;
+        Ok(())
+    }
+
+    fn detach(&mut self, id: NodeId, parent: Option<NodeId>) {
+        let siblings = match parent {
+            Some(parent) => &mut self.node_mut(parent).children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|sibling| *sibling != id);
+    }
+
+    /// Recomputes the world transform of every changed node and its descendants.
+    pub fn update_transforms(&mut self) {
+        // (node, world transform of the parent, an ancestor changed)
+        let mut stack: Vec<(NodeId, glm::Matrix4<f32>, bool)> = self
+            .roots
+            .iter()
+            .map(|root| (*root, glm::Matrix4::identity(), false))
+            .collect();
+
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = self.node_mut(id);
+            let changed = node.dirty || parent_changed;
+            if changed {
+                node.world = parent_world * node.local.matrix();
+                node.dirty = false;
+            }
+
+            let world = node.world;
+            stack.extend(node.children.iter().map(|child| (*child, world, changed)));
+        }
+    }
+
+    /// The first node named `name` in the subtree of `root`, depth first and including `root`.
+    pub fn find_descendant(&self, root: NodeId, name: &str) -> Option<NodeId> {
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let node = self.node(id);
+            if node.name.as_deref() == Some(name) {
+                return Some(id);
+            }
+            stack.extend(node.children.iter().rev());
+        }
+        None
+    }
+
+    /// Visits every node depth first, parents before their children.
+    pub fn traverse<F: FnMut(NodeId, &Node)>(&self, mut visit: F) {
+        let mut stack: Vec<NodeId> = self.roots.iter().rev().copied().collect();
+        while let Some(id) = stack.pop() {
+            let node = self.node(id);
+            visit(id, node);
+            stack.extend(node.children.iter().rev());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(world: &glm::Matrix4<f32>) -> glm::Vector3<f32> {
+        world.fixed_view::<3, 1>(0, 3).into_owned()
+    }
+
+    #[test]
+    fn transforms_propagate_to_the_whole_subtree() {
+        let mut scene = Scene::new();
+        let root = scene.add_node(None, None, Transform::from_translation(glm::Vector3::new(1.0, 0.0, 0.0)));
+        let child = scene.add_node(
+            None,
+            Some(root),
+            Transform::from_translation(glm::Vector3::new(0.0, 2.0, 0.0)),
+        );
+        let grandchild = scene.add_node(
+            None,
+            Some(child),
+            Transform::from_translation(glm::Vector3::new(0.0, 0.0, 3.0)),
+        );
+        scene.update_transforms();
+        assert_eq!(
+            translation(scene.node(grandchild).world_transform()),
+            glm::Vector3::new(1.0, 2.0, 3.0)
+        );
+
+        // only the root changed, its descendants are still recomputed
+        let turned = Transform {
+            rotation: glm::UnitQuaternion::from_axis_angle(&glm::Vector3::z_axis(), std::f32::consts::FRAC_PI_2),
+            scale: glm::Vector3::new(2.0, 2.0, 2.0),
+            ..Transform::IDENTITY
+        };
+        scene.set_local_transform(root, turned);
+        scene.update_transforms();
+        let world = translation(scene.node(grandchild).world_transform());
+        assert!((world - glm::Vector3::new(-4.0, 0.0, 6.0)).norm() < 1e-5, "{}", world);
+    }
+
+    #[test]
+    fn reparenting_keeps_the_local_transform() {
+        let mut scene = Scene::new();
+        let a = scene.add_node(None, None, Transform::from_translation(glm::Vector3::new(5.0, 0.0, 0.0)));
+        let b = scene.add_node(None, None, Transform::from_translation(glm::Vector3::new(0.0, 5.0, 0.0)));
+        let child = scene.add_node(None, Some(a), Transform::from_translation(glm::Vector3::new(1.0, 0.0, 0.0)));
+        scene.update_transforms();
+
+        scene.set_parent(child, Some(b)).unwrap();
+        scene.update_transforms();
+        assert_eq!(scene.node(a).children(), &[]);
+        assert_eq!(scene.node(b).children(), &[child]);
+        assert_eq!(
+            translation(scene.node(child).world_transform()),
+            glm::Vector3::new(1.0, 5.0, 0.0)
+        );
+
+        assert!(scene.set_parent(b, Some(child)).is_err());
+        assert!(scene.set_parent(b, Some(b)).is_err());
+        scene.set_parent(child, None).unwrap();
+        assert_eq!(scene.roots(), &[a, b, child]);
+    }
+
+    #[test]
+    fn removed_ids_stay_invalid_when_the_slot_is_reused() {
+        let mut scene = Scene::new();
+        let root = scene.add_node(Some("root".to_owned()), None, Transform::IDENTITY);
+        let child = scene.add_node(Some("child".to_owned()), Some(root), Transform::IDENTITY);
+        let other = scene.add_node(Some("other".to_owned()), None, Transform::IDENTITY);
+        scene.remove_node(root);
+        assert!(!scene.contains(root));
+        assert!(!scene.contains(child));
+        assert_eq!(scene.roots(), &[other]);
+
+        let reused = scene.add_node(None, None, Transform::IDENTITY);
+        assert!(reused.index == root.index || reused.index == child.index);
+        assert!(scene.get(root).is_none() && scene.get(child).is_none());
+        assert!(scene.contains(reused));
+    }
+
+    #[test]
+    fn traversal_and_search_visit_parents_first() {
+        let mut scene = Scene::new();
+        let root = scene.add_node(Some("root".to_owned()), None, Transform::IDENTITY);
+        let arm = scene.add_node(Some("arm".to_owned()), Some(root), Transform::IDENTITY);
+        let hand = scene.add_node(Some("hand".to_owned()), Some(arm), Transform::IDENTITY);
+        let leg = scene.add_node(Some("leg".to_owned()), Some(root), Transform::IDENTITY);
+
+        let mut order = vec![];
+        scene.traverse(|id, _| order.push(id));
+        assert_eq!(order, [root, arm, hand, leg]);
+        assert_eq!(scene.find_descendant(root, "hand"), Some(hand));
+        assert_eq!(scene.find_descendant(leg, "hand"), None);
+    }
+
+    #[test]
+    fn matrix_round_trips_through_transform() {
+        let transform = Transform {
+            translation: glm::Vector3::new(1.0, -2.0, 3.0),
+            rotation: glm::UnitQuaternion::from_euler_angles(0.3, -1.1, 0.7),
+            scale: glm::Vector3::new(-2.0, 0.5, 1.5),
+        };
+        let split = Transform::from_matrix(&transform.matrix());
+        assert!((split.matrix() - transform.matrix()).norm() < 1e-5);
+        assert!((split.translation - transform.translation).norm() < 1e-6);
+    }
+}
@@ -0,0 +1,255 @@
+use std::ffi::c_void;
+
+use ash::{prelude::VkResult, vk};
+
+use crate::{draw_cache::PassTarget, format};
+
+/// Attachments of a `vkCmdBeginRendering` pass secondary command buffers execute in, needs the
+/// dynamicRendering feature.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RenderingTarget {
+    pub color_formats: Vec<vk::Format>,
+    /// UNDEFINED without a depth attachment
+    pub depth_format: vk::Format,
+    /// UNDEFINED without a stencil attachment
+    pub stencil_format: vk::Format,
+    pub samples: vk::SampleCountFlags,
+    /// viewport and scissor cover it
+    pub extent: vk::Extent2D,
+}
+
+impl RenderingTarget {
+    pub fn new(color_formats: &[vk::Format], extent: vk::Extent2D) -> Self {
+        Self {
+            color_formats: color_formats.to_vec(),
+            depth_format: vk::Format::UNDEFINED,
+            stencil_format: vk::Format::UNDEFINED,
+            samples: vk::SampleCountFlags::TYPE_1,
+            extent,
+        }
+    }
+
+    /// Sets the stencil format too when `format` has a stencil aspect.
+    pub fn depth(mut self, format: vk::Format) -> Self {
+        self.depth_format = format;
+        if format::aspect_flags(format).contains(vk::ImageAspectFlags::STENCIL) {
+            self.stencil_format = format;
+        }
+        self
+    }
+
+    pub fn samples(mut self, samples: vk::SampleCountFlags) -> Self {
+        self.samples = samples;
+        self
+    }
+}
+
+/// What a secondary command buffer is executed inside of, a change means recording it again.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Inheritance {
+    /// outside of any render pass, for transfer and compute work
+    None,
+    RenderPass(PassTarget),
+    Rendering(RenderingTarget),
+}
+
+impl Inheritance {
+    pub fn extent(&self) -> Option<vk::Extent2D> {
+        match self {
+            Inheritance::None => None,
+            Inheritance::RenderPass(target) => Some(target.extent),
+            Inheritance::Rendering(target) => Some(target.extent),
+        }
+    }
+}
+
+/// # Safety
+/// `command_pool` has to be a pool of `device`, the buffers are freed with it.
+pub unsafe fn allocate_secondary(
+    device: &ash::Device,
+    command_pool: vk::CommandPool,
+    count: u32,
+) -> VkResult<Vec<vk::CommandBuffer>> {
+    let alloc_info = vk::CommandBufferAllocateInfo {
+        command_pool,
+        level: vk::CommandBufferLevel::SECONDARY,
+        command_buffer_count: count,
+        ..Default::default()
+    };
+    device.allocate_command_buffers(&alloc_info)
+}
+
+/// Begins `command_buffer` for executing inside `inheritance`. Inside a render pass the viewport and scissor
+/// are set to its extent, dynamic state isn't inherited from the primary command buffer.
+///
+/// # Safety
+/// `command_buffer` has to be a secondary command buffer that isn't pending,
+/// `inheritance` has to name live objects of `device`.
+pub unsafe fn begin_secondary(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    inheritance: &Inheritance,
+    flags: vk::CommandBufferUsageFlags,
+) -> VkResult<()> {
+    let mut rendering_info = vk::CommandBufferInheritanceRenderingInfo::default();
+    let mut inheritance_info = vk::CommandBufferInheritanceInfo::default();
+    let mut flags = flags;
+    match inheritance {
+        Inheritance::None => {}
+        Inheritance::RenderPass(target) => {
+            inheritance_info.render_pass = target.render_pass;
+            inheritance_info.subpass = target.subpass;
+            inheritance_info.framebuffer = target.framebuffer;
+            flags |= vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE;
+        }
+        Inheritance::Rendering(target) => {
+            rendering_info.color_attachment_count = target.color_formats.len() as u32;
+            rendering_info.p_color_attachment_formats = target.color_formats.as_ptr();
+            rendering_info.depth_attachment_format = target.depth_format;
+            rendering_info.stencil_attachment_format = target.stencil_format;
+            rendering_info.rasterization_samples = target.samples;
+            inheritance_info.p_next = &rendering_info as *const vk::CommandBufferInheritanceRenderingInfo as *const c_void;
+            flags |= vk::CommandBufferUsageFlags::RENDER_PASS_CONTINUE;
+        }
+    }
+    let begin_info = vk::CommandBufferBeginInfo {
+        flags,
+        p_inheritance_info: &inheritance_info,
+        ..Default::default()
+    };
+    device.begin_command_buffer(command_buffer, &begin_info)?;
+
+    if let Some(extent) = inheritance.extent() {
+        let viewport = vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        };
+        device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+        let scissor = vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        };
+        device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+    }
+    Ok(())
+}
+
+/// `begin_secondary`, `record` and ending the command buffer.
+///
+/// # Safety
+/// As for `begin_secondary`.
+pub unsafe fn record_secondary<F: FnOnce(vk::CommandBuffer)>(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    inheritance: &Inheritance,
+    flags: vk::CommandBufferUsageFlags,
+    record: F,
+) -> VkResult<()> {
+    begin_secondary(device, command_buffer, inheritance, flags)?;
+    record(command_buffer);
+    device.end_command_buffer(command_buffer)
+}
+
+/// Commands recorded once and executed every frame, static geometry for example.
+/// Recorded for simultaneous use, so every frame in flight can execute them at the same time.
+pub struct StaticCommands {
+    command_pool: vk::CommandPool,
+    command_buffer: vk::CommandBuffer,
+    inheritance: Inheritance,
+}
+
+impl StaticCommands {
+    /// `queue_family_index` is the family the primary command buffers are submitted to.
+    ///
+    /// # Safety
+    /// `inheritance` has to name live objects, its render pass has to outlive the recording.
+    pub unsafe fn record<F: FnOnce(vk::CommandBuffer)>(
+        device: &ash::Device,
+        queue_family_index: u32,
+        inheritance: Inheritance,
+        record: F,
+    ) -> VkResult<Self> {
+        let pool_info = vk::CommandPoolCreateInfo {
+            flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
+            queue_family_index,
+            ..Default::default()
+        };
+        let command_pool = device.create_command_pool(&pool_info, None)?;
+        let recorded = allocate_secondary(device, command_pool, 1).and_then(|command_buffers| {
+            record_secondary(
+                device,
+                command_buffers[0],
+                &inheritance,
+                vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+                record,
+            )?;
+            Ok(command_buffers[0])
+        });
+        match recorded {
+            Ok(command_buffer) => Ok(Self {
+                command_pool,
+                command_buffer,
+                inheritance,
+            }),
+            Err(e) => {
+                device.destroy_command_pool(command_pool, None);
+                Err(e)
+            }
+        }
+    }
+
+    /// Replaces the commands, after the last submission executing them finished.
+    ///
+    /// # Safety
+    /// No submission executing the commands may be pending.
+    pub unsafe fn rerecord<F: FnOnce(vk::CommandBuffer)>(
+        &mut self,
+        device: &ash::Device,
+        inheritance: Inheritance,
+        record: F,
+    ) -> VkResult<()> {
+        self.inheritance = inheritance;
+        record_secondary(
+            device,
+            self.command_buffer,
+            &self.inheritance,
+            vk::CommandBufferUsageFlags::SIMULTANEOUS_USE,
+            record,
+        )
+    }
+
+    pub fn command_buffer(&self) -> vk::CommandBuffer {
+        self.command_buffer
+    }
+
+    pub fn inheritance(&self) -> &Inheritance {
+        &self.inheritance
+    }
+
+    /// False when the commands have to be recorded again to execute inside `inheritance`, after a resize
+    /// or a new render pass.
+    pub fn is_compatible(&self, inheritance: &Inheritance) -> bool {
+        self.inheritance == *inheritance
+    }
+
+    /// Executes the commands from `primary`, inside a render pass matching `inheritance` with secondary command
+    /// buffer contents.
+    ///
+    /// # Safety
+    /// `primary` has to be recording, inside a render pass compatible with `inheritance` when it has one.
+    pub unsafe fn execute(&self, device: &ash::Device, primary: vk::CommandBuffer) {
+        device.cmd_execute_commands(primary, &[self.command_buffer]);
+    }
+
+    /// The device has to be idle.
+    ///
+    /// # Safety
+    /// No submission executing the commands may be pending.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_command_pool(self.command_pool, None);
+    }
+}
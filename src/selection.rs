@@ -0,0 +1,160 @@
+use std::collections::BTreeSet;
+
+use ash::vk;
+use nalgebra as glm;
+
+use crate::mesh::Aabb;
+
+/// Rectangle in framebuffer pixels, y points down like Vulkan screen space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionRect {
+    pub min: glm::Vector2<f32>,
+    pub max: glm::Vector2<f32>,
+}
+
+impl SelectionRect {
+    /// The rectangle between the press and the current cursor position, in any drag direction.
+    pub fn from_corners(a: glm::Vector2<f32>, b: glm::Vector2<f32>) -> Self {
+        Self {
+            min: a.inf(&b),
+            max: a.sup(&b),
+        }
+    }
+
+    pub fn width(&self) -> f32 {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> f32 {
+        self.max.y - self.min.y
+    }
+
+    /// Drags shorter than `threshold` pixels are clicks, handle them with single picking.
+    pub fn is_click(&self, threshold: f32) -> bool {
+        self.width() < threshold && self.height() < threshold
+    }
+
+    pub fn intersects(&self, other: &SelectionRect) -> bool {
+        self.min.x <= other.max.x && other.min.x <= self.max.x && self.min.y <= other.max.y && other.min.y <= self.max.y
+    }
+
+    pub fn contains_rect(&self, other: &SelectionRect) -> bool {
+        self.min.x <= other.min.x && other.max.x <= self.max.x && self.min.y <= other.min.y && other.max.y <= self.max.y
+    }
+
+    /// Pixel range clamped to `extent`, max exclusive, None when nothing of it is on screen.
+    pub fn pixels(&self, extent: vk::Extent2D) -> Option<(u32, u32, u32, u32)> {
+        let x0 = self.min.x.floor().max(0.0) as u32;
+        let y0 = self.min.y.floor().max(0.0) as u32;
+        let x1 = (self.max.x.ceil().max(0.0) as u32).min(extent.width);
+        let y1 = (self.max.y.ceil().max(0.0) as u32).min(extent.height);
+        (x0 < x1 && y0 < y1).then_some((x0, y0, x1, y1))
+    }
+}
+
+/// What counts as inside the marquee.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum SelectionMode {
+    /// the projected bounds touch the rectangle
+    #[default]
+    Intersect,
+    /// the projected bounds lie completely inside, like dragging right to left in most CAD tools
+    Contain,
+}
+
+/// Screen rectangle the world space `bounds` cover, None when they are completely behind the camera.
+/// Parts behind the near plane are clipped away first, so boxes around the camera still project sensibly.
+pub fn project_bounds(bounds: &Aabb, view_projection: &glm::Matrix4<f32>, extent: vk::Extent2D) -> Option<SelectionRect> {
+    const EDGES: [(usize, usize); 12] = [
+        (0, 1),
+        (2, 3),
+        (4, 5),
+        (6, 7),
+        (0, 2),
+        (1, 3),
+        (4, 6),
+        (5, 7),
+        (0, 4),
+        (1, 5),
+        (2, 6),
+        (3, 7),
+    ];
+    // the near plane in Vulkan clip space is z = 0
+    let clip: Vec<glm::Vector4<f32>> = bounds
+        .corners()
+        .iter()
+        .map(|corner| view_projection * corner.push(1.0))
+        .collect();
+
+    let mut min = glm::Vector2::repeat(f32::INFINITY);
+    let mut max = glm::Vector2::repeat(f32::NEG_INFINITY);
+    let mut add = |point: &glm::Vector4<f32>| {
+        let w = point.w.max(f32::EPSILON);
+        let ndc = glm::Vector2::new(point.x / w, point.y / w);
+        let pixel = glm::Vector2::new(
+            (ndc.x * 0.5 + 0.5) * extent.width as f32,
+            (ndc.y * 0.5 + 0.5) * extent.height as f32,
+        );
+        min = min.inf(&pixel);
+        max = max.sup(&pixel);
+    };
+
+    let mut any_visible = false;
+    for point in clip.iter().filter(|point| point.z >= 0.0) {
+        add(point);
+        any_visible = true;
+    }
+    for &(a, b) in EDGES.iter() {
+        let (a, b) = (clip[a], clip[b]);
+        if (a.z >= 0.0) != (b.z >= 0.0) {
+            let t = a.z / (a.z - b.z);
+            add(&a.lerp(&b, t));
+            any_visible = true;
+        }
+    }
+    any_visible.then_some(SelectionRect { min, max })
+}
+
+/// Items whose world space bounds project into `rect`, keeps the order of `items`.
+/// Works on the CPU, so it also finds objects hidden behind others, unlike `ids_in_rect`.
+pub fn select_in_rect<T, I>(
+    rect: &SelectionRect,
+    view_projection: &glm::Matrix4<f32>,
+    extent: vk::Extent2D,
+    mode: SelectionMode,
+    items: I,
+) -> Vec<T>
+where
+    I: IntoIterator<Item = (T, Aabb)>,
+{
+    items
+        .into_iter()
+        .filter_map(|(item, bounds)| {
+            let projected = project_bounds(&bounds, view_projection, extent)?;
+            let inside = match mode {
+                SelectionMode::Intersect => rect.intersects(&projected),
+                SelectionMode::Contain => rect.contains_rect(&projected),
+            };
+            inside.then_some(item)
+        })
+        .collect()
+}
+
+/// The distinct ids inside `rect` of an ID buffer read back as `extent` tightly packed u32 texels,
+/// sorted and without `background`. Only finds what is visible in the frame the buffer is from.
+pub fn ids_in_rect(ids: &[u32], extent: vk::Extent2D, rect: &SelectionRect, background: u32) -> Vec<u32> {
+    let Some((x0, y0, x1, y1)) = rect.pixels(extent) else {
+        return vec![];
+    };
+    let width = extent.width as usize;
+    let mut found = BTreeSet::new();
+    for y in y0 as usize..y1 as usize {
+        let row = &ids[y * width..(y + 1) * width];
+        for &id in &row[x0 as usize..x1 as usize] {
+            if id != background {
+                found.insert(id);
+            }
+        }
+    }
+    found.into_iter().collect()
+}
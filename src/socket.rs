@@ -0,0 +1,181 @@
+use crate::error::{Error, Result};
+use nalgebra as glm;
+
+use crate::scene::{NodeId, Scene, Transform};
+
+/// A named attachment point, a scene node under a skeleton joint whose local transform is the offset.
+/// Nodes attached to it are its children, so they follow the animated joint with every `Scene::update_transforms`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Socket {
+    pub name: String,
+    pub joint: NodeId,
+    pub node: NodeId,
+}
+
+#[derive(Clone, Debug)]
+struct Attachment {
+    node: NodeId,
+    socket: String,
+    /// where `detach` puts the node back
+    previous_parent: Option<NodeId>,
+    previous_local: Transform,
+}
+
+/// The sockets of one skeleton, like a "hand_r" socket on the right hand joint holding a weapon.
+/// Removing a joint from the scene removes its sockets and what is attached with it.
+#[derive(Clone, Debug)]
+pub struct Sockets {
+    /// joints are looked up by name below it
+    root: NodeId,
+    sockets: Vec<Socket>,
+    attachments: Vec<Attachment>,
+}
+
+impl Sockets {
+    pub fn new(root: NodeId) -> Self {
+        Self {
+            root,
+            sockets: vec![],
+            attachments: vec![],
+        }
+    }
+
+    /// Adds socket `name` on the joint named `joint` with `offset` relative to it.
+    pub fn add(&mut self, scene: &mut Scene, name: &str, joint: &str, offset: Transform) -> Result<&Socket> {
+        self.prune(scene);
+        if self.socket(name).is_some() {
+            return Err(Error::msg(format!("The socket {} already exists", name)));
+        }
+        let joint = scene
+            .find_descendant(self.root, joint)
+            .ok_or_else(|| Error::msg(format!("The skeleton has no joint {} for the socket {}", joint, name)))?;
+        let node = scene.add_node(Some(name.to_owned()), Some(joint), offset);
+        self.sockets.push(Socket {
+            name: name.to_owned(),
+            joint,
+            node,
+        });
+        Ok(self.sockets.last().unwrap())
+    }
+
+    /// Removes socket `name`, the nodes attached to it are detached first.
+    pub fn remove(&mut self, scene: &mut Scene, name: &str) -> Result<()> {
+        self.prune(scene);
+        let socket = self.find(name)?.clone();
+        for node in self.attached(name).collect::<Vec<_>>() {
+            self.detach(scene, node, false)?;
+        }
+        scene.remove_node(socket.node);
+        self.sockets.retain(|existing| existing.name != name);
+        Ok(())
+    }
+
+    pub fn socket(&self, name: &str) -> Option<&Socket> {
+        self.sockets.iter().find(|socket| socket.name == name)
+    }
+
+    pub fn sockets(&self) -> &[Socket] {
+        &self.sockets
+    }
+
+    pub fn offset(&self, scene: &Scene, name: &str) -> Result<Transform> {
+        Ok(*scene.node(self.find(name)?.node).local_transform())
+    }
+
+    pub fn set_offset(&self, scene: &mut Scene, name: &str, offset: Transform) -> Result<()> {
+        scene.set_local_transform(self.find(name)?.node, offset);
+        Ok(())
+    }
+
+    /// Where socket `name` is in world space, only up to date after `Scene::update_transforms`.
+    pub fn world_transform(&self, scene: &Scene, name: &str) -> Result<glm::Matrix4<f32>> {
+        Ok(*scene.node(self.find(name)?.node).world_transform())
+    }
+
+    /// Parents `node` to socket `name`, a node attached elsewhere moves over.
+    /// With `keep_world` it stays where it is and needs up to date world transforms,
+    /// otherwise it snaps onto the socket.
+    pub fn attach(&mut self, scene: &mut Scene, name: &str, node: NodeId, keep_world: bool) -> Result<()> {
+        self.prune(scene);
+        let socket = self.find(name)?.node;
+
+        let (previous_parent, previous_local) = match self.attachments.iter().position(|a| a.node == node) {
+            Some(index) => {
+                let attachment = self.attachments.remove(index);
+                (attachment.previous_parent, attachment.previous_local)
+            }
+            None => (scene.node(node).parent(), *scene.node(node).local_transform()),
+        };
+        let local = if keep_world {
+            relative_to(scene, Some(socket), node)
+        } else {
+            Transform::IDENTITY
+        };
+        scene.set_parent(node, Some(socket))?;
+        scene.set_local_transform(node, local);
+
+        self.attachments.push(Attachment {
+            node,
+            socket: name.to_owned(),
+            previous_parent,
+            previous_local,
+        });
+        Ok(())
+    }
+
+    /// Moves `node` back to the parent it had before `attach`, or makes it a root when that parent is gone.
+    /// With `keep_world` it stays where it is, otherwise it gets its old local transform back.
+    pub fn detach(&mut self, scene: &mut Scene, node: NodeId, keep_world: bool) -> Result<()> {
+        self.prune(scene);
+        let index = self
+            .attachments
+            .iter()
+            .position(|attachment| attachment.node == node)
+            .ok_or_else(|| Error::msg("The node isn't attached to a socket"))?;
+        let attachment = self.attachments.remove(index);
+
+        let parent = attachment.previous_parent.filter(|parent| scene.contains(*parent));
+        let local = if keep_world {
+            relative_to(scene, parent, node)
+        } else {
+            attachment.previous_local
+        };
+        scene.set_parent(node, parent)?;
+        scene.set_local_transform(node, local);
+        Ok(())
+    }
+
+    /// The nodes attached to socket `name`.
+    pub fn attached<'a>(&'a self, name: &'a str) -> impl Iterator<Item = NodeId> + 'a {
+        self.attachments
+            .iter()
+            .filter(move |attachment| attachment.socket == name)
+            .map(|attachment| attachment.node)
+    }
+
+    /// Name of the socket `node` is attached to.
+    pub fn socket_of(&self, node: NodeId) -> Option<&str> {
+        self.attachments
+            .iter()
+            .find(|attachment| attachment.node == node)
+            .map(|attachment| attachment.socket.as_str())
+    }
+
+    fn find(&self, name: &str) -> Result<&Socket> {
+        self.socket(name)
+            .ok_or_else(|| Error::msg(format!("There is no socket {}", name)))
+    }
+
+    /// Forgets sockets and attachments whose nodes were removed from the scene.
+    fn prune(&mut self, scene: &Scene) {
+        self.sockets.retain(|socket| scene.contains(socket.node));
+        self.attachments.retain(|attachment| scene.contains(attachment.node));
+    }
+}
+
+/// The local transform that keeps `node` at its world transform under `parent`.
+fn relative_to(scene: &Scene, parent: Option<NodeId>, node: NodeId) -> Transform {
+    let parent_world = parent.map_or(glm::Matrix4::identity(), |parent| *scene.node(parent).world_transform());
+    let inverse = parent_world.try_inverse().unwrap_or_else(glm::Matrix4::identity);
+    Transform::from_matrix(&(inverse * scene.node(node).world_transform()))
+}
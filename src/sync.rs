@@ -0,0 +1,218 @@
+use std::ptr;
+
+use ash::vk::{self, StructureType};
+
+/// Move of an EXCLUSIVE resource from the queue family that last used it to the one using it next.
+/// The release half is recorded on the source queue, the acquire half on the destination queue, and the
+/// destination submission has to wait for the source one, with a semaphore for example.
+/// Without both halves the content of the resource is undefined on the new queue.
+/// Within one family the release records nothing and the acquire is a plain barrier after all earlier work.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueTransfer {
+    pub src_family: u32,
+    pub dst_family: u32,
+}
+
+impl QueueTransfer {
+    pub fn new(src_family: u32, dst_family: u32) -> Self {
+        Self { src_family, dst_family }
+    }
+
+    /// False when both queues are of the same family, a normal barrier is enough then.
+    pub fn is_needed(&self) -> bool {
+        self.src_family != self.dst_family
+    }
+
+    fn families(&self) -> (u32, u32) {
+        if self.is_needed() {
+            (self.src_family, self.dst_family)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        }
+    }
+}
+
+/// Sharing mode and family list for a resource used by `families`.
+/// CONCURRENT needs no ownership transfers but can be slower on some hardware, it fits resources that are
+/// written once and read by several queues. Duplicate families are removed, one family stays EXCLUSIVE.
+pub fn sharing_mode(families: &[u32]) -> (vk::SharingMode, Vec<u32>) {
+    let mut unique = families.to_vec();
+    unique.sort_unstable();
+    unique.dedup();
+    if unique.len() > 1 {
+        (vk::SharingMode::CONCURRENT, unique)
+    } else {
+        (vk::SharingMode::EXCLUSIVE, vec![])
+    }
+}
+
+/// The semaphore orders an acquire after the release, within one family the barrier has to wait itself.
+fn acquire_src_scope(transfer: &QueueTransfer) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+    if transfer.is_needed() {
+        (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty())
+    } else {
+        (vk::PipelineStageFlags::ALL_COMMANDS, vk::AccessFlags::MEMORY_WRITE)
+    }
+}
+
+unsafe fn cmd_buffer_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    (src_family, dst_family): (u32, u32),
+    (src_stage, src_access_mask): (vk::PipelineStageFlags, vk::AccessFlags),
+    (dst_stage, dst_access_mask): (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barrier = vk::BufferMemoryBarrier {
+        s_type: StructureType::BUFFER_MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask,
+        dst_access_mask,
+        src_queue_family_index: src_family,
+        dst_queue_family_index: dst_family,
+        buffer,
+        offset: 0,
+        size: vk::WHOLE_SIZE,
+    };
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[barrier],
+        &[],
+    );
+}
+
+/// Records the release half of `transfer` for the whole buffer on the source queue.
+/// `src` is the stage and access of the last use on that queue.
+///
+/// # Safety
+/// `command_buffer` has to be recording for the source family of `transfer`.
+pub unsafe fn cmd_release_buffer(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    transfer: QueueTransfer,
+    src: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    if !transfer.is_needed() {
+        return;
+    }
+    // the destination access is ignored for a release, the acquire makes the writes visible
+    let dst = (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty());
+    cmd_buffer_barrier(device, command_buffer, buffer, transfer.families(), src, dst);
+}
+
+/// Records the acquire half of `transfer` for the whole buffer on the destination queue.
+/// `dst` is the stage and access of the first use on that queue.
+///
+/// # Safety
+/// `command_buffer` has to be recording for the destination family of `transfer`,
+/// submitted after the release.
+pub unsafe fn cmd_acquire_buffer(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    buffer: vk::Buffer,
+    transfer: QueueTransfer,
+    dst: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let src = acquire_src_scope(&transfer);
+    cmd_buffer_barrier(device, command_buffer, buffer, transfer.families(), src, dst);
+}
+
+/// Layout change that happens together with an image ownership transfer.
+/// Both halves have to name the same layouts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ImageTransfer {
+    pub queues: QueueTransfer,
+    pub old_layout: vk::ImageLayout,
+    pub new_layout: vk::ImageLayout,
+    pub aspect_mask: vk::ImageAspectFlags,
+}
+
+impl ImageTransfer {
+    /// Transfer of a color image.
+    pub fn color(queues: QueueTransfer, old_layout: vk::ImageLayout, new_layout: vk::ImageLayout) -> Self {
+        Self {
+            queues,
+            old_layout,
+            new_layout,
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+        }
+    }
+}
+
+unsafe fn cmd_image_barrier(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    transfer: &ImageTransfer,
+    (src_stage, src_access_mask): (vk::PipelineStageFlags, vk::AccessFlags),
+    (dst_stage, dst_access_mask): (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let (src_family, dst_family) = transfer.queues.families();
+    let barrier = vk::ImageMemoryBarrier {
+        s_type: StructureType::IMAGE_MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask,
+        dst_access_mask,
+        old_layout: transfer.old_layout,
+        new_layout: transfer.new_layout,
+        src_queue_family_index: src_family,
+        dst_queue_family_index: dst_family,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: transfer.aspect_mask,
+            base_mip_level: 0,
+            level_count: vk::REMAINING_MIP_LEVELS,
+            base_array_layer: 0,
+            layer_count: vk::REMAINING_ARRAY_LAYERS,
+        },
+    };
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier],
+    );
+}
+
+/// Records the release half of `transfer` for every mip and layer on the source queue.
+/// `src` is the stage and access of the last use on that queue.
+///
+/// # Safety
+/// As for `cmd_release_buffer`, the image has to be in the old layout of `transfer`.
+pub unsafe fn cmd_release_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    transfer: &ImageTransfer,
+    src: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    if !transfer.queues.is_needed() {
+        return;
+    }
+    let dst = (vk::PipelineStageFlags::BOTTOM_OF_PIPE, vk::AccessFlags::empty());
+    cmd_image_barrier(device, command_buffer, image, transfer, src, dst);
+}
+
+/// Records the acquire half of `transfer` for every mip and layer on the destination queue.
+/// `dst` is the stage and access of the first use on that queue.
+///
+/// # Safety
+/// As for `cmd_acquire_buffer`.
+pub unsafe fn cmd_acquire_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    transfer: &ImageTransfer,
+    dst: (vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let src = acquire_src_scope(&transfer.queues);
+    cmd_image_barrier(device, command_buffer, image, transfer, src, dst);
+}
@@ -0,0 +1,315 @@
+use ash::{extensions::ext::DebugUtils, prelude::VkResult, vk};
+
+use crate::{buffer::MAX_FRAMES_IN_FLIGHT, debug};
+
+/// How much a pool recycled, `created` stops growing once the pool covers the frames in flight.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncPoolStats {
+    pub created: usize,
+    pub reused: usize,
+    /// ready to be handed out again
+    pub free: usize,
+    /// handed out and not recycled yet
+    pub in_use: usize,
+}
+
+/// Free list with the handles handed out during each frame slot.
+struct Pool<H> {
+    label: String,
+    debug_utils: Option<DebugUtils>,
+    free: Vec<H>,
+    slots: Vec<Vec<H>>,
+    frame: usize,
+    created: usize,
+    reused: usize,
+}
+
+impl<H: vk::Handle + Copy + PartialEq> Pool<H> {
+    fn new(label: &str, debug_utils: Option<DebugUtils>) -> Self {
+        Self {
+            label: label.to_owned(),
+            debug_utils,
+            free: vec![],
+            slots: vec![vec![]; MAX_FRAMES_IN_FLIGHT as usize],
+            frame: 0,
+            created: 0,
+            reused: 0,
+        }
+    }
+
+    fn acquire<F>(&mut self, device: &ash::Device, create: F) -> VkResult<H>
+    where
+        F: FnOnce() -> VkResult<H>,
+    {
+        let handle = match self.free.pop() {
+            Some(handle) => {
+                self.reused += 1;
+                handle
+            }
+            None => {
+                let handle = create()?;
+                if let Some(debug_utils) = self.debug_utils.as_ref() {
+                    let name = format!("{} {}", self.label, self.created);
+                    unsafe { debug::set_object_name(debug_utils, device, handle, &name) };
+                }
+                self.created += 1;
+                handle
+            }
+        };
+        self.slots[self.frame].push(handle);
+        Ok(handle)
+    }
+
+    /// Moves to `frame`, returns what the slot handed out the last time it was current.
+    fn begin_frame(&mut self, frame: usize) -> Vec<H> {
+        self.frame = frame % self.slots.len();
+        std::mem::take(&mut self.slots[self.frame])
+    }
+
+    /// Takes `handle` out of its slot, false when the pool didn't hand it out.
+    fn take(&mut self, handle: H) -> bool {
+        for slot in self.slots.iter_mut() {
+            if let Some(index) = slot.iter().position(|in_use| *in_use == handle) {
+                slot.swap_remove(index);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn stats(&self) -> SyncPoolStats {
+        SyncPoolStats {
+            created: self.created,
+            reused: self.reused,
+            free: self.free.len(),
+            in_use: self.slots.iter().map(Vec::len).sum(),
+        }
+    }
+
+    fn drain(&mut self) -> Vec<H> {
+        let mut handles = std::mem::take(&mut self.free);
+        for slot in self.slots.iter_mut() {
+            handles.append(slot);
+        }
+        handles
+    }
+}
+
+/// Recycles fences instead of creating one per submission. A fence handed out during frame slot N comes back
+/// unsignaled when `begin_frame` returns to slot N, MAX_FRAMES_IN_FLIGHT frames later.
+/// Fences are named "<label> <index>" when the pool has debug utils.
+pub struct FencePool {
+    pool: Pool<vk::Fence>,
+}
+
+impl FencePool {
+    pub fn new(label: &str, debug_utils: Option<DebugUtils>) -> Self {
+        Self {
+            pool: Pool::new(label, debug_utils),
+        }
+    }
+
+    /// An unsignaled fence, every one has to be submitted or given back with `release`.
+    ///
+    /// # Safety
+    /// `device` has to be the device of every earlier call.
+    pub unsafe fn acquire(&mut self, device: &ash::Device) -> VkResult<vk::Fence> {
+        self.pool
+            .acquire(device, || device.create_fence(&vk::FenceCreateInfo::default(), None))
+    }
+
+    /// Call after waiting on the fence of the frame, waits for the fences slot `frame` handed out
+    /// MAX_FRAMES_IN_FLIGHT frames ago and resets them for reuse.
+    ///
+    /// # Safety
+    /// `frame` has to be below MAX_FRAMES_IN_FLIGHT and its fence waited on.
+    pub unsafe fn begin_frame(&mut self, device: &ash::Device, frame: usize) -> VkResult<()> {
+        let fences = self.pool.begin_frame(frame);
+        if fences.is_empty() {
+            return Ok(());
+        }
+        let reset = device
+            .wait_for_fences(&fences, true, u64::MAX)
+            .and_then(|_| device.reset_fences(&fences));
+        if let Err(e) = reset {
+            // handed back out they could still be signaled, a later wait would pass too early
+            for fence in fences {
+                device.destroy_fence(fence, None);
+            }
+            return Err(e);
+        }
+        self.pool.free.extend(fences);
+        Ok(())
+    }
+
+    /// Gives `fence` back before its slot comes around, it has to be waited on or never submitted.
+    ///
+    /// # Safety
+    /// `fence` has to come from `acquire` of this pool.
+    pub unsafe fn release(&mut self, device: &ash::Device, fence: vk::Fence) -> VkResult<()> {
+        if !self.pool.take(fence) {
+            log::warn!("Fence {:?} was not handed out by pool \"{}\"", fence, self.pool.label);
+            return Ok(());
+        }
+        if let Err(e) = device.reset_fences(&[fence]) {
+            device.destroy_fence(fence, None);
+            return Err(e);
+        }
+        self.pool.free.push(fence);
+        Ok(())
+    }
+
+    pub fn stats(&self) -> SyncPoolStats {
+        self.pool.stats()
+    }
+
+    /// Destroys every fence of the pool, none may be pending anymore.
+    ///
+    /// # Safety
+    /// No fence of the pool may be in use by the GPU.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for fence in self.pool.drain() {
+            device.destroy_fence(fence, None);
+        }
+    }
+}
+
+/// Recycles binary semaphores the same way `FencePool` recycles fences. A semaphore comes back when its slot
+/// comes around, by then the frame that waited on it finished and left it unsignaled.
+/// One that was signaled but never waited on, like after a failed present, has to be `discard`ed.
+pub struct SemaphorePool {
+    pool: Pool<vk::Semaphore>,
+}
+
+impl SemaphorePool {
+    pub fn new(label: &str, debug_utils: Option<DebugUtils>) -> Self {
+        Self {
+            pool: Pool::new(label, debug_utils),
+        }
+    }
+
+    /// An unsignaled binary semaphore.
+    ///
+    /// # Safety
+    /// The semaphore has to be waited on by the submission it's signaled for, or it can't be recycled.
+    pub unsafe fn acquire(&mut self, device: &ash::Device) -> VkResult<vk::Semaphore> {
+        self.pool
+            .acquire(device, || device.create_semaphore(&vk::SemaphoreCreateInfo::default(), None))
+    }
+
+    /// Call after waiting on the fence of the frame, recycles what slot `frame` handed out before.
+    pub fn begin_frame(&mut self, frame: usize) {
+        let semaphores = self.pool.begin_frame(frame);
+        self.pool.free.extend(semaphores);
+    }
+
+    /// Gives `semaphore` back early, no pending submission may signal or wait on it.
+    pub fn release(&mut self, semaphore: vk::Semaphore) {
+        if self.pool.take(semaphore) {
+            self.pool.free.push(semaphore);
+        } else {
+            log::warn!("Semaphore {:?} was not handed out by pool \"{}\"", semaphore, self.pool.label);
+        }
+    }
+
+    /// Destroys `semaphore` instead of recycling it, for one that stays signaled.
+    ///
+    /// # Safety
+    /// `semaphore` has to come from `acquire` and no submission may wait on it anymore.
+    pub unsafe fn discard(&mut self, device: &ash::Device, semaphore: vk::Semaphore) {
+        self.pool.take(semaphore);
+        device.destroy_semaphore(semaphore, None);
+    }
+
+    pub fn stats(&self) -> SyncPoolStats {
+        self.pool.stats()
+    }
+
+    /// Destroys every semaphore of the pool, none may be pending anymore.
+    ///
+    /// # Safety
+    /// No semaphore of the pool may be in use by the GPU.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for semaphore in self.pool.drain() {
+            device.destroy_semaphore(semaphore, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::c_char, ptr};
+
+    use ash::vk::Handle;
+
+    use super::*;
+
+    /// A device whose functions all panic, pools without debug utils only call it in their public wrappers.
+    fn no_device() -> ash::Device {
+        unsafe extern "system" fn get_device_proc_addr(_: vk::Device, _: *const c_char) -> vk::PFN_vkVoidFunction {
+            None
+        }
+        let mut instance_fn = vk::InstanceFnV1_0::load(|_| ptr::null());
+        instance_fn.get_device_proc_addr = get_device_proc_addr;
+        unsafe { ash::Device::load(&instance_fn, vk::Device::null()) }
+    }
+
+    fn acquire(pool: &mut Pool<vk::Fence>, device: &ash::Device) -> vk::Fence {
+        let raw = pool.created as u64 + 1;
+        pool.acquire(device, || Ok(vk::Fence::from_raw(raw))).unwrap()
+    }
+
+    /// What the wrappers do after waiting on the frame.
+    fn recycle(pool: &mut Pool<vk::Fence>, frame: usize) {
+        let handles = pool.begin_frame(frame);
+        pool.free.extend(handles);
+    }
+
+    #[test]
+    fn a_slot_gets_its_handles_back_when_it_comes_around() {
+        let device = no_device();
+        let mut pool = Pool::new("fences", None);
+        let a = acquire(&mut pool, &device);
+        let b = acquire(&mut pool, &device);
+        recycle(&mut pool, 1);
+        let c = acquire(&mut pool, &device);
+        assert_eq!(pool.stats().created, 3);
+
+        // slot 0 comes around again, its fences are free while slot 1 keeps its own
+        recycle(&mut pool, MAX_FRAMES_IN_FLIGHT as usize);
+        let reused = [acquire(&mut pool, &device), acquire(&mut pool, &device)];
+        assert!(reused.contains(&a) && reused.contains(&b));
+        assert!(!reused.contains(&c));
+        let stats = pool.stats();
+        assert_eq!(
+            stats,
+            SyncPoolStats {
+                created: 3,
+                reused: 2,
+                free: 0,
+                in_use: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn taken_handles_leave_their_slot() {
+        let device = no_device();
+        let mut pool = Pool::new("fences", None);
+        let a = acquire(&mut pool, &device);
+        assert!(pool.take(a));
+        assert!(!pool.take(a));
+        assert!(!pool.take(vk::Fence::from_raw(99)));
+        pool.free.push(a);
+        assert_eq!(acquire(&mut pool, &device), a);
+        assert_eq!(pool.stats().reused, 1);
+
+        // a released handle isn't handed back a second time by its slot
+        assert!(pool.take(a));
+        pool.free.push(a);
+        recycle(&mut pool, 0);
+        assert_eq!(pool.stats().free, 1);
+        assert_eq!(pool.drain(), [a]);
+        assert_eq!(pool.stats().in_use + pool.stats().free, 0);
+    }
+}
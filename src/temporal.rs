@@ -0,0 +1,264 @@
+use crate::error::Result;
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{camera::Camera, format, pipeline, texture::Texture};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HistoryDesc {
+    pub format: vk::Format,
+    /// 1 for full resolution, 2 for half and so on
+    pub downscale: u32,
+}
+
+/// Handle to a history of `TemporalHistories`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HistoryId(usize);
+
+/// Two alternating textures of one effect, this frame writes `current` and reads `previous`.
+pub struct History {
+    pub desc: HistoryDesc,
+    /// render pass writing `current`, pipelines of the effect are built for it
+    pub render_pass: vk::RenderPass,
+    textures: [Texture; 2],
+    framebuffers: [vk::Framebuffer; 2],
+    current: usize,
+    /// frames since the history was added or invalidated
+    frames: u32,
+    valid: bool,
+}
+
+impl History {
+    unsafe fn create_targets(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+        desc: &HistoryDesc,
+        extent: vk::Extent2D,
+    ) -> Result<([Texture; 2], [vk::Framebuffer; 2])> {
+        let extent = vk::Extent2D {
+            width: extent.width.div_ceil(desc.downscale).max(1),
+            height: extent.height.div_ceil(desc.downscale).max(1),
+        };
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT;
+        let textures = [
+            Texture::new(device, instance, physical_device, extent, desc.format, usage)?,
+            Texture::new(device, instance, physical_device, extent, desc.format, usage)?,
+        ];
+        let framebuffers = [
+            pipeline::create_framebuffer(device, render_pass, &[textures[0].view], extent)?,
+            pipeline::create_framebuffer(device, render_pass, &[textures[1].view], extent)?,
+        ];
+        Ok((textures, framebuffers))
+    }
+
+    unsafe fn destroy_targets(&self, device: &ash::Device) {
+        for framebuffer in self.framebuffers {
+            device.destroy_framebuffer(framebuffer, None);
+        }
+        for texture in self.textures.iter() {
+            texture.destroy(device);
+        }
+    }
+
+    /// Written this frame, in SHADER_READ_ONLY_OPTIMAL layout after the render pass.
+    pub fn current(&self) -> &Texture {
+        &self.textures[self.current]
+    }
+
+    pub fn current_framebuffer(&self) -> vk::Framebuffer {
+        self.framebuffers[self.current]
+    }
+
+    /// Written last frame, its content is only meaningful when `is_valid`.
+    pub fn previous(&self) -> &Texture {
+        &self.textures[self.current ^ 1]
+    }
+
+    /// False on the first frame, after resizes and after camera cuts.
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+
+    pub fn extent(&self) -> vk::Extent2D {
+        self.textures[0].extent
+    }
+}
+
+/// Matrices to find where a pixel of this frame was last frame, laid out for a uniform buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ReprojectionUniforms {
+    /// clip space of this frame to clip space of the previous frame, see shaders/reprojection.glsl
+    pub current_to_previous: glm::Matrix4<f32>,
+    pub inverse_view_projection: glm::Matrix4<f32>,
+    pub previous_view_projection: glm::Matrix4<f32>,
+}
+
+impl Default for ReprojectionUniforms {
+    fn default() -> Self {
+        Self {
+            current_to_previous: glm::Matrix4::identity(),
+            inverse_view_projection: glm::Matrix4::identity(),
+            previous_view_projection: glm::Matrix4::identity(),
+        }
+    }
+}
+
+/// The histories of every temporal effect, TAA, SSR or volumetrics for example.
+/// Reallocates them when the extent changes and drops their content on camera cuts, see `Camera::notify_cut`.
+pub struct TemporalHistories {
+    histories: Vec<History>,
+    extent: vk::Extent2D,
+    previous_view_projection: Option<glm::Matrix4<f32>>,
+    reprojection: ReprojectionUniforms,
+    generation: u64,
+    /// `Camera::cut_count` of the last `begin_frame`
+    camera_cuts: Option<u64>,
+}
+
+impl TemporalHistories {
+    /// `extent` is the full resolution, histories scale it down by their `downscale`.
+    pub fn new(extent: vk::Extent2D) -> Self {
+        Self {
+            histories: vec![],
+            extent,
+            previous_view_projection: None,
+            reprojection: ReprojectionUniforms::default(),
+            generation: 0,
+            camera_cuts: None,
+        }
+    }
+
+    /// # Safety
+    /// `physical_device` has to be the device of `device`.
+    pub unsafe fn add(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        desc: HistoryDesc,
+    ) -> Result<HistoryId> {
+        assert!(desc.downscale > 0, "History downscale has to be at least 1");
+
+        // `History::desc` has the format the textures ended up with
+        let usage = vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED;
+        let desc = HistoryDesc {
+            format: format::resolve(instance, physical_device, desc.format, usage)?,
+            ..desc
+        };
+        let render_pass = pipeline::create_offscreen_render_pass(device, desc.format, None, false, false)?;
+        let (textures, framebuffers) =
+            History::create_targets(device, instance, physical_device, render_pass, &desc, self.extent)?;
+        self.histories.push(History {
+            desc,
+            render_pass,
+            textures,
+            framebuffers,
+            current: 0,
+            frames: 0,
+            valid: false,
+        });
+        Ok(HistoryId(self.histories.len() - 1))
+    }
+
+    pub fn get(&self, id: HistoryId) -> &History {
+        &self.histories[id.0]
+    }
+
+    /// Bumped whenever the textures are reallocated, descriptor sets sampling them have to be recreated.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Reallocates every history when `extent` changed, call it every frame with the render resolution.
+    /// Returns true when the textures were reallocated, the device has to be idle in that case.
+    ///
+    /// # Safety
+    /// As for `add`.
+    pub unsafe fn ensure_extent(
+        &mut self,
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+    ) -> Result<bool> {
+        if extent == self.extent {
+            return Ok(false);
+        }
+
+        self.extent = extent;
+        for history in self.histories.iter_mut() {
+            history.destroy_targets(device);
+            let (textures, framebuffers) =
+                History::create_targets(device, instance, physical_device, history.render_pass, &history.desc, extent)?;
+            history.textures = textures;
+            history.framebuffers = framebuffers;
+        }
+        self.generation += 1;
+        self.invalidate();
+        Ok(true)
+    }
+
+    /// Drops the content of every history, the next frame starts without temporal data.
+    pub fn invalidate(&mut self) {
+        self.previous_view_projection = None;
+        for history in self.histories.iter_mut() {
+            history.frames = 0;
+            history.valid = false;
+        }
+    }
+
+    /// Swaps the current and previous textures and updates the reprojection for `camera`.
+    /// Call it once per frame before recording the temporal effects, cuts of the camera invalidate the histories.
+    pub fn begin_frame(&mut self, camera: &Camera, aspect_ratio: f32) {
+        if self.camera_cuts.is_some_and(|cuts| cuts != camera.cut_count()) {
+            self.invalidate();
+        }
+        self.camera_cuts = Some(camera.cut_count());
+
+        let view_projection = camera.view_projection(aspect_ratio);
+        let inverse_view_projection = view_projection.try_inverse().unwrap_or_else(glm::Matrix4::identity);
+        // without a previous frame the pixels reproject onto themselves
+        let previous_view_projection = self.previous_view_projection.unwrap_or(view_projection);
+
+        self.reprojection = ReprojectionUniforms {
+            current_to_previous: previous_view_projection * inverse_view_projection,
+            inverse_view_projection,
+            previous_view_projection,
+        };
+        self.previous_view_projection = Some(view_projection);
+
+        for history in self.histories.iter_mut() {
+            history.current ^= 1;
+            history.valid = history.frames > 0;
+            history.frames = history.frames.saturating_add(1);
+        }
+    }
+
+    pub fn reprojection(&self) -> &ReprojectionUniforms {
+        &self.reprojection
+    }
+
+    /// Where a point at `uv` (0..1, y down) with `depth` in this frame was on screen last frame.
+    pub fn reproject_uv(&self, uv: glm::Vector2<f32>, depth: f32) -> glm::Vector2<f32> {
+        reproject_uv(&self.reprojection.current_to_previous, uv, depth)
+    }
+
+    /// # Safety
+    /// No frame in flight may use the histories.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for history in self.histories.iter() {
+            history.destroy_targets(device);
+            device.destroy_render_pass(history.render_pass, None);
+        }
+    }
+}
+
+/// The CPU version of `reprojectUv` in shaders/reprojection.glsl.
+pub fn reproject_uv(current_to_previous: &glm::Matrix4<f32>, uv: glm::Vector2<f32>, depth: f32) -> glm::Vector2<f32> {
+    let clip = glm::Vector4::new(uv.x * 2.0 - 1.0, uv.y * 2.0 - 1.0, depth, 1.0);
+    let previous = current_to_previous * clip;
+    glm::Vector2::new(previous.x, previous.y) / previous.w * 0.5 + glm::Vector2::new(0.5, 0.5)
+}
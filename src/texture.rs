@@ -0,0 +1,711 @@
+use std::{
+    path::Path,
+    ptr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::error::{Error, Result};
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+
+use crate::{
+    buffer::{self, OneTimeCommands, MAX_FRAMES_IN_FLIGHT},
+    format,
+    material::TextureBinding,
+};
+
+/// A sampled 2D image with its view and sampler.
+pub struct Texture {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    pub sampler: vk::Sampler,
+    pub extent: vk::Extent2D,
+    pub format: vk::Format,
+}
+
+impl Texture {
+    /// Creates an uninitialized texture, the image is left in UNDEFINED layout.
+    /// Falls back to a supported format when the device lacks `format`, see `format::resolve`.
+    ///
+    /// # Safety
+    /// `device` has to be created from `physical_device` of `instance`, the texture goes with `destroy`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> VkResult<Texture> {
+        let usage = usage | vk::ImageUsageFlags::SAMPLED;
+        let format = format::resolve(instance, physical_device, format, usage)?;
+        let (image, memory) = buffer::create_image(
+            device,
+            instance,
+            physical_device,
+            extent,
+            format,
+            usage,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        let view = create_image_view(device, image, format, vk::ImageAspectFlags::COLOR)?;
+        let sampler = create_sampler(device, &SamplerDesc::default())?;
+
+        Ok(Texture {
+            image,
+            memory,
+            view,
+            sampler,
+            extent,
+            format,
+        })
+    }
+
+    /// Creates a texture from tightly packed texels and leaves it ready to be sampled.
+    /// `upload` has to belong to the graphics family. The texels are repacked when the texture falls back to
+    /// another format, ERROR_FORMAT_NOT_SUPPORTED when `format::convert_texels` can't.
+    ///
+    /// # Safety
+    /// Blocks on `upload.queue` until the copy finished, no other thread may use the queue meanwhile.
+    pub unsafe fn from_pixels(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        upload: OneTimeCommands,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        pixels: &[u8],
+    ) -> VkResult<Texture> {
+        let texture = Texture::new(
+            device,
+            instance,
+            physical_device,
+            extent,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST,
+        )?;
+        let pixels = match format::convert_texels(format, texture.format, pixels) {
+            Some(pixels) => pixels,
+            None => {
+                log::error!("Texels in {:?} can't be converted to {:?}", format, texture.format);
+                texture.destroy(device);
+                return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+            }
+        };
+
+        let (staging_buffer, staging_memory) = buffer::create_staging_buffer(device, instance, physical_device, &pixels)?;
+
+        let command_buffer = buffer::begin_single_commands(device, upload.command_pool)?;
+        texture.cmd_upload(
+            device,
+            command_buffer,
+            staging_buffer,
+            (vk::PipelineStageFlags::FRAGMENT_SHADER, vk::AccessFlags::SHADER_READ),
+        );
+        buffer::end_single_time_command(device, command_buffer, upload.command_pool, upload.queue)?;
+
+        device.destroy_buffer(staging_buffer, None);
+        device.free_memory(staging_memory, None);
+        Ok(texture)
+    }
+
+    /// Records the copy of tightly packed texels in `staging` to the whole image, which ends up in
+    /// SHADER_READ_ONLY_OPTIMAL layout. The last tuple is the stage and access reading it next on the same queue,
+    /// queues without graphics support have to pass BOTTOM_OF_PIPE with no access.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be recording outside of a render pass and `staging` hold `extent` worth of texels
+    /// until it executed.
+    pub unsafe fn cmd_upload(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        staging: vk::Buffer,
+        (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+    ) {
+        cmd_transition_image(
+            device,
+            command_buffer,
+            self.image,
+            (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::AccessFlags::empty(),
+            ),
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        );
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: self.extent.width,
+                height: self.extent.height,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging,
+            self.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+        cmd_transition_image(
+            device,
+            command_buffer,
+            self.image,
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            (vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL, dst_stage, dst_access),
+        );
+    }
+
+    /// Loads an image file, `format` has to be a 4 byte per texel format like R8G8B8A8_SRGB.
+    ///
+    /// # Safety
+    /// As for `from_pixels`, the queue of `upload` is waited on.
+    pub unsafe fn load<P: AsRef<Path>>(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        upload: OneTimeCommands,
+        path: P,
+        format: vk::Format,
+    ) -> Result<Texture> {
+        let (pixels, extent) = load_image_rgba8(path)?;
+        Ok(Texture::from_pixels(
+            device,
+            instance,
+            physical_device,
+            upload,
+            extent,
+            format,
+            &pixels,
+        )?)
+    }
+
+    /// Replaces the default linear clamp sampler.
+    ///
+    /// # Safety
+    /// The old sampler is destroyed right away, no pending command buffer may still use it.
+    pub unsafe fn set_sampler(&mut self, device: &ash::Device, desc: &SamplerDesc) -> VkResult<()> {
+        let sampler = create_sampler(device, desc)?;
+        device.destroy_sampler(self.sampler, None);
+        self.sampler = sampler;
+        Ok(())
+    }
+
+    pub fn binding(&self) -> TextureBinding {
+        TextureBinding {
+            image_view: self.view,
+            sampler: self.sampler,
+        }
+    }
+
+    /// # Safety
+    /// The GPU has to be done with the image, its view and sampler.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_sampler(self.sampler, None);
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// Decodes an image file into tightly packed RGBA8 texels.
+pub fn load_image_rgba8<P: AsRef<Path>>(path: P) -> Result<(Vec<u8>, vk::Extent2D)> {
+    let path = path.as_ref();
+    match stb_image::image::load_with_depth(path, 4, false) {
+        stb_image::image::LoadResult::ImageU8(image) => Ok((
+            image.data,
+            vk::Extent2D {
+                width: image.width as u32,
+                height: image.height as u32,
+            },
+        )),
+        stb_image::image::LoadResult::ImageF32(_) => Err(Error::asset(
+            path,
+            "Floating point images aren't supported, only 8 bit images are",
+        )),
+        stb_image::image::LoadResult::Error(e) => Err(Error::asset(path, format!("Failed to load the image: {}", e))),
+    }
+}
+
+/// Preferred depth format, `format::depth_format` is what the device ends up with.
+pub const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+/// Depth attachment matching the swapchain, recreated together with it.
+pub struct DepthBuffer {
+    pub image: vk::Image,
+    pub memory: vk::DeviceMemory,
+    pub view: vk::ImageView,
+    /// `DEPTH_FORMAT` or its fallback
+    pub format: vk::Format,
+}
+
+impl DepthBuffer {
+    /// # Safety
+    /// `device` has to be created from `physical_device`, the depth buffer goes with `destroy`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+    ) -> VkResult<DepthBuffer> {
+        Self::with_usage(device, instance, physical_device, extent, vk::ImageUsageFlags::empty())
+    }
+
+    /// `usage` is added to DEPTH_STENCIL_ATTACHMENT, for example SAMPLED for effects reading the depth.
+    ///
+    /// # Safety
+    /// As for `new`.
+    pub unsafe fn with_usage(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        extent: vk::Extent2D,
+        usage: vk::ImageUsageFlags,
+    ) -> VkResult<DepthBuffer> {
+        let usage = vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT | usage;
+        let format = format::resolve(instance, physical_device, DEPTH_FORMAT, usage)?;
+        let (image, memory) = buffer::create_image(
+            device,
+            instance,
+            physical_device,
+            extent,
+            format,
+            usage,
+            MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+        // depth only even with a stencil fallback, the view can be sampled that way
+        let view = create_image_view(device, image, format, vk::ImageAspectFlags::DEPTH)?;
+
+        Ok(DepthBuffer {
+            image,
+            memory,
+            view,
+            format,
+        })
+    }
+
+    /// # Safety
+    /// No framebuffer or descriptor set in use may still reference the view.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_image_view(self.view, None);
+        device.destroy_image(self.image, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+/// # Safety
+/// `image` has to be an image of `device` created with `format` and a 2D, single layer view type.
+pub unsafe fn create_image_view(
+    device: &ash::Device,
+    image: vk::Image,
+    format: vk::Format,
+    aspect_mask: vk::ImageAspectFlags,
+) -> VkResult<vk::ImageView> {
+    let view_info = vk::ImageViewCreateInfo {
+        s_type: StructureType::IMAGE_VIEW_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::ImageViewCreateFlags::empty(),
+        image,
+        view_type: vk::ImageViewType::TYPE_2D,
+        format,
+        components: vk::ComponentMapping::default(),
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+    };
+    device.create_image_view(&view_info, None)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerDesc {
+    pub mag_filter: vk::Filter,
+    pub min_filter: vk::Filter,
+    pub mipmap_mode: vk::SamplerMipmapMode,
+    pub address_mode_u: vk::SamplerAddressMode,
+    pub address_mode_v: vk::SamplerAddressMode,
+}
+
+impl SamplerDesc {
+    pub const fn new(filter: vk::Filter, address_mode: vk::SamplerAddressMode) -> Self {
+        Self {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode_u: address_mode,
+            address_mode_v: address_mode,
+        }
+    }
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self::new(vk::Filter::LINEAR, vk::SamplerAddressMode::CLAMP_TO_EDGE)
+    }
+}
+
+/// # Safety
+/// The sampler has to be destroyed on `device` once nothing samples with it.
+pub unsafe fn create_sampler(device: &ash::Device, desc: &SamplerDesc) -> VkResult<vk::Sampler> {
+    let sampler_info = vk::SamplerCreateInfo {
+        s_type: StructureType::SAMPLER_CREATE_INFO,
+        p_next: ptr::null(),
+        flags: vk::SamplerCreateFlags::empty(),
+        mag_filter: desc.mag_filter,
+        min_filter: desc.min_filter,
+        mipmap_mode: desc.mipmap_mode,
+        address_mode_u: desc.address_mode_u,
+        address_mode_v: desc.address_mode_v,
+        address_mode_w: desc.address_mode_u,
+        mip_lod_bias: 0.0,
+        anisotropy_enable: vk::FALSE,
+        max_anisotropy: 1.0,
+        compare_enable: vk::FALSE,
+        compare_op: vk::CompareOp::ALWAYS,
+        min_lod: 0.0,
+        max_lod: vk::LOD_CLAMP_NONE,
+        border_color: vk::BorderColor::INT_OPAQUE_BLACK,
+        unnormalized_coordinates: vk::FALSE,
+    };
+    device.create_sampler(&sampler_info, None)
+}
+
+/// Records a layout transition of the first mip and layer of a color image.
+///
+/// # Safety
+/// `command_buffer` has to be recording, and outside of a render pass unless the pass has a matching
+/// self-dependency.
+pub unsafe fn cmd_transition_image(
+    device: &ash::Device,
+    command_buffer: vk::CommandBuffer,
+    image: vk::Image,
+    (old_layout, src_stage, src_access_mask): (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags),
+    (new_layout, dst_stage, dst_access_mask): (vk::ImageLayout, vk::PipelineStageFlags, vk::AccessFlags),
+) {
+    let barrier = vk::ImageMemoryBarrier {
+        s_type: StructureType::IMAGE_MEMORY_BARRIER,
+        p_next: ptr::null(),
+        src_access_mask,
+        dst_access_mask,
+        old_layout,
+        new_layout,
+        src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+        image,
+        subresource_range: vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        },
+    };
+
+    device.cmd_pipeline_barrier(
+        command_buffer,
+        src_stage,
+        dst_stage,
+        vk::DependencyFlags::empty(),
+        &[],
+        &[],
+        &[barrier],
+    );
+}
+
+struct FrameSlot {
+    latest: Mutex<Option<Vec<u8>>>,
+    dropped: AtomicU64,
+}
+
+/// Hands frames from a producer thread (webcam, video decoder, ...) to a `StreamingTexture`.
+/// Only the newest frame is kept, frames the renderer didn't get to are dropped.
+#[derive(Clone)]
+pub struct FrameSender {
+    slot: Arc<FrameSlot>,
+    frame_size: usize,
+}
+
+impl FrameSender {
+    /// `pixels` are tightly packed rows of 4 byte texels
+    pub fn send(&self, pixels: Vec<u8>) -> Result<()> {
+        if pixels.len() != self.frame_size {
+            return Err(Error::msg(format!(
+                "Frame has {} bytes, the texture expects {}",
+                pixels.len(),
+                self.frame_size
+            )));
+        }
+
+        let mut latest = self.slot.latest.lock().unwrap();
+        if latest.replace(pixels).is_some() {
+            self.slot.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+struct StagingBuffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    data: *mut u8,
+}
+
+/// Texture that is continuously updated from CPU produced frames.
+/// Every frame in flight has its own persistently mapped staging buffer, so writing
+/// a new frame never waits on the GPU reading the previous one.
+pub struct StreamingTexture {
+    pub texture: Texture,
+    staging: Vec<StagingBuffer>,
+    slot: Arc<FrameSlot>,
+    frame_size: usize,
+    pub frames_uploaded: u64,
+}
+
+unsafe impl Send for StreamingTexture {}
+
+impl StreamingTexture {
+    /// `format` has to be a 4 byte per texel format, like R8G8B8A8_SRGB or B8G8R8A8_UNORM, supported by the
+    /// device as frames aren't repacked. The image is cleared to black and ready to be sampled when this
+    /// returns, `command_pool` and `queue` have to belong to the graphics family.
+    ///
+    /// # Safety
+    /// Blocks on `queue` while clearing the image, no other thread may submit to it meanwhile.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        command_pool: vk::CommandPool,
+        queue: vk::Queue,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> VkResult<StreamingTexture> {
+        let texture = Texture::new(
+            device,
+            instance,
+            physical_device,
+            extent,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST,
+        )?;
+        if texture.format != format {
+            log::error!("Streaming textures can't fall back from {:?} to {:?}", format, texture.format);
+            texture.destroy(device);
+            return Err(vk::Result::ERROR_FORMAT_NOT_SUPPORTED);
+        }
+        let frame_size = (extent.width * extent.height * 4) as usize;
+
+        let mut staging = vec![];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            let (buffer, memory) = buffer::create_buffer(
+                device,
+                instance,
+                physical_device,
+                frame_size as u64,
+                BufferUsageFlags::TRANSFER_SRC,
+                MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let data = device.map_memory(memory, 0, frame_size as u64, MemoryMapFlags::empty())? as *mut u8;
+            staging.push(StagingBuffer { buffer, memory, data });
+        }
+
+        // sampling an image in UNDEFINED layout is invalid, so start with a black frame
+        let command_buffer = buffer::begin_single_commands(device, command_pool)?;
+        cmd_transition_image(
+            device,
+            command_buffer,
+            texture.image,
+            (
+                vk::ImageLayout::UNDEFINED,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::AccessFlags::empty(),
+            ),
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        );
+        let clear_color = vk::ClearColorValue {
+            float32: [0.0, 0.0, 0.0, 1.0],
+        };
+        let range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        device.cmd_clear_color_image(
+            command_buffer,
+            texture.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &clear_color,
+            &[range],
+        );
+        cmd_transition_image(
+            device,
+            command_buffer,
+            texture.image,
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+        );
+        buffer::end_single_time_command(device, command_buffer, command_pool, queue)?;
+
+        Ok(StreamingTexture {
+            texture,
+            staging,
+            slot: Arc::new(FrameSlot {
+                latest: Mutex::new(None),
+                dropped: AtomicU64::new(0),
+            }),
+            frame_size,
+            frames_uploaded: 0,
+        })
+    }
+
+    pub fn sender(&self) -> FrameSender {
+        FrameSender {
+            slot: self.slot.clone(),
+            frame_size: self.frame_size,
+        }
+    }
+
+    /// Same as sending through `sender()`, for frames produced on the render thread.
+    pub fn update(&self, pixels: Vec<u8>) -> Result<()> {
+        self.sender().send(pixels)
+    }
+
+    /// Frames replaced by a newer one before they were uploaded.
+    pub fn frames_dropped(&self) -> u64 {
+        self.slot.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Records the upload of the newest frame, if there is one, into `command_buffer`.
+    /// Has to be recorded outside of a render pass, after the fence of `current_frame` was waited on.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be submitted before the next `record_upload` of `current_frame`.
+    pub unsafe fn record_upload(
+        &mut self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+    ) -> bool {
+        // keep the lock short, the producer should never wait on the renderer
+        let pixels = match self.slot.latest.lock().unwrap().take() {
+            Some(pixels) => pixels,
+            None => return false,
+        };
+
+        let staging = &self.staging[current_frame];
+        staging.data.copy_from_nonoverlapping(pixels.as_ptr(), self.frame_size);
+
+        // the previous frame may still be sampling the image
+        cmd_transition_image(
+            device,
+            command_buffer,
+            self.texture.image,
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        );
+
+        let region = vk::BufferImageCopy {
+            buffer_offset: 0,
+            buffer_row_length: 0,
+            buffer_image_height: 0,
+            image_subresource: vk::ImageSubresourceLayers {
+                aspect_mask: vk::ImageAspectFlags::COLOR,
+                mip_level: 0,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+            image_extent: vk::Extent3D {
+                width: self.texture.extent.width,
+                height: self.texture.extent.height,
+                depth: 1,
+            },
+        };
+        device.cmd_copy_buffer_to_image(
+            command_buffer,
+            staging.buffer,
+            self.texture.image,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            &[region],
+        );
+
+        cmd_transition_image(
+            device,
+            command_buffer,
+            self.texture.image,
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+            (
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::AccessFlags::SHADER_READ,
+            ),
+        );
+
+        self.frames_uploaded += 1;
+        true
+    }
+
+    /// # Safety
+    /// The GPU has to be done with every frame in flight that sampled or uploaded the texture.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for staging in self.staging.iter() {
+            device.unmap_memory(staging.memory);
+            device.destroy_buffer(staging.buffer, None);
+            device.free_memory(staging.memory, None);
+        }
+        self.texture.destroy(device);
+    }
+}
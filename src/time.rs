@@ -0,0 +1,211 @@
+use std::time::{Duration, Instant};
+
+/// Longest delta a frame reports by default, see `Time::max_delta`.
+pub const DEFAULT_MAX_DELTA: Duration = Duration::from_millis(250);
+
+/// Frame timing, `tick` it once per loop iteration and hand it to the update code,
+/// so movement and animation scale with `delta_seconds` instead of the frame rate.
+#[derive(Clone, Debug)]
+pub struct Time {
+    startup: Instant,
+    last_tick: Option<Instant>,
+    delta: Duration,
+    elapsed: Duration,
+    frame_count: u64,
+    /// deltas are clamped to this, so a breakpoint or a dragged window doesn't make simulations jump
+    pub max_delta: Duration,
+}
+
+impl Default for Time {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Time {
+    pub fn new() -> Self {
+        Self {
+            startup: Instant::now(),
+            last_tick: None,
+            delta: Duration::ZERO,
+            elapsed: Duration::ZERO,
+            frame_count: 0,
+            max_delta: DEFAULT_MAX_DELTA,
+        }
+    }
+
+    /// Starts a new frame, the first tick has a delta of zero.
+    pub fn tick(&mut self) {
+        self.tick_at(Instant::now());
+    }
+
+    /// `tick` with an explicit timestamp, for replays and tests of time dependent code.
+    pub fn tick_at(&mut self, now: Instant) {
+        self.delta = match self.last_tick {
+            Some(last) => now.saturating_duration_since(last).min(self.max_delta),
+            None => Duration::ZERO,
+        };
+        self.last_tick = Some(now);
+        self.elapsed += self.delta;
+        self.frame_count += 1;
+    }
+
+    /// Time between the last two ticks.
+    pub fn delta(&self) -> Duration {
+        self.delta
+    }
+
+    pub fn delta_seconds(&self) -> f32 {
+        self.delta.as_secs_f32()
+    }
+
+    /// Sum of the deltas, it doesn't include the time lost to clamping.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn elapsed_seconds(&self) -> f64 {
+        self.elapsed.as_secs_f64()
+    }
+
+    /// Wall clock time since `new`.
+    pub fn since_startup(&self) -> Duration {
+        self.startup.elapsed()
+    }
+
+    /// Number of ticks, 1 during the first frame.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+}
+
+/// Runs simulation code at a constant rate independent of the render frame rate.
+/// Each frame `advance` returns how many fixed updates to run, afterwards `alpha` says how far the frame
+/// is between the last two updates, see `scene::InterpolatedTransform`.
+#[derive(Clone, Debug)]
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    steps: u64,
+    /// more steps than this in one frame are dropped, so a slow simulation can't fall further and further behind
+    pub max_steps_per_frame: u32,
+}
+
+impl FixedTimestep {
+    /// `hz` updates per second, 60 for example.
+    pub fn new(hz: f64) -> Self {
+        assert!(hz > 0.0, "Fixed timestep rate has to be positive");
+        Self::from_step(Duration::from_secs_f64(1.0 / hz))
+    }
+
+    pub fn from_step(step: Duration) -> Self {
+        assert!(!step.is_zero(), "Fixed timestep can't be zero");
+        Self {
+            step,
+            accumulator: Duration::ZERO,
+            steps: 0,
+            max_steps_per_frame: 8,
+        }
+    }
+
+    /// Adds the delta of `time` and returns the number of fixed updates to run this frame.
+    pub fn advance(&mut self, time: &Time) -> u32 {
+        self.advance_by(time.delta())
+    }
+
+    pub fn advance_by(&mut self, delta: Duration) -> u32 {
+        self.accumulator += delta;
+        let mut steps = 0;
+        while self.accumulator >= self.step {
+            if steps == self.max_steps_per_frame {
+                // keep the fraction, so alpha stays continuous
+                self.accumulator = Duration::from_nanos((self.accumulator.as_nanos() % self.step.as_nanos()) as u64);
+                break;
+            }
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+        self.steps += steps as u64;
+        steps
+    }
+
+    /// Calls `update` with the step in seconds once per fixed update due this frame.
+    pub fn run<F: FnMut(f32)>(&mut self, time: &Time, mut update: F) {
+        let step = self.step_seconds();
+        for _ in 0..self.advance(time) {
+            update(step);
+        }
+    }
+
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    pub fn step_seconds(&self) -> f32 {
+        self.step.as_secs_f32()
+    }
+
+    /// Fraction of a step since the last fixed update, in 0..1. Blend the last two simulated states with it.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+    }
+
+    /// Fixed updates run so far.
+    pub fn step_count(&self) -> u64 {
+        self.steps
+    }
+}
+
+/// Paces work that runs at its own rate inside a faster loop, like a preview window presenting at 10 Hz
+/// while the main window runs at 60. Unlimited is due every time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameCadence {
+    interval: Option<Duration>,
+    next: Option<Instant>,
+}
+
+impl FrameCadence {
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// `hz` frames per second at most.
+    pub fn hz(hz: f64) -> Self {
+        assert!(hz > 0.0, "Frame rate has to be positive");
+        Self::from_interval(Duration::from_secs_f64(1.0 / hz))
+    }
+
+    pub fn from_interval(interval: Duration) -> Self {
+        Self {
+            interval: Some(interval),
+            next: None,
+        }
+    }
+
+    /// None when unlimited.
+    pub fn interval(&self) -> Option<Duration> {
+        self.interval
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.next.is_none_or(|next| now >= next)
+    }
+
+    /// When the next frame is due, None when unlimited or before the first frame.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.next
+    }
+
+    /// Starts a frame when one is due. Frames are scheduled from the previous due time so the rate doesn't drift,
+    /// after falling more than a frame behind the schedule restarts from `now` instead of catching up.
+    pub fn due(&mut self, now: Instant) -> bool {
+        if !self.is_due(now) {
+            return false;
+        }
+        if let Some(interval) = self.interval {
+            let next = self.next.unwrap_or(now) + interval;
+            self.next = Some(if next <= now { now + interval } else { next });
+        }
+        true
+    }
+}
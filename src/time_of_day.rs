@@ -0,0 +1,339 @@
+use std::time::Duration;
+
+use nalgebra as glm;
+
+use crate::frame::FrameUniforms;
+
+pub const HOURS_PER_DAY: f32 = 24.0;
+
+/// Values a `Curve` can blend between.
+pub trait Keyframe: Copy {
+    fn lerp(&self, other: &Self, t: f32) -> Self;
+}
+
+impl Keyframe for f32 {
+    fn lerp(&self, other: &f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl Keyframe for glm::Vector3<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        glm::Vector3::lerp(self, other, t)
+    }
+}
+
+impl Keyframe for glm::Vector4<f32> {
+    fn lerp(&self, other: &Self, t: f32) -> Self {
+        glm::Vector4::lerp(self, other, t)
+    }
+}
+
+/// Values keyed by the hour of the day, blended linearly and wrapping around midnight.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T> {
+    /// (hour in 0..24, value), kept sorted by hour
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Keyframe> Curve<T> {
+    pub fn new(keys: &[(f32, T)]) -> Self {
+        let mut curve = Self { keys: vec![] };
+        for (hour, value) in keys {
+            curve.insert(*hour, *value);
+        }
+        curve
+    }
+
+    pub fn constant(value: T) -> Self {
+        Self::new(&[(0.0, value)])
+    }
+
+    /// Adds a key or replaces the one at the same hour.
+    pub fn insert(&mut self, hour: f32, value: T) {
+        let hour = hour.rem_euclid(HOURS_PER_DAY);
+        match self.keys.iter().position(|(key_hour, _)| *key_hour >= hour) {
+            Some(index) if self.keys[index].0 == hour => self.keys[index].1 = value,
+            Some(index) => self.keys.insert(index, (hour, value)),
+            None => self.keys.push((hour, value)),
+        }
+    }
+
+    pub fn remove(&mut self, hour: f32) {
+        self.keys.retain(|(key_hour, _)| *key_hour != hour);
+    }
+
+    pub fn keys(&self) -> &[(f32, T)] {
+        &self.keys
+    }
+
+    /// The blended value at `hour`, None for a curve without keys.
+    pub fn sample(&self, hour: f32) -> Option<T> {
+        let hour = hour.rem_euclid(HOURS_PER_DAY);
+        let (first, last) = (self.keys.first()?, self.keys.last()?);
+        let next = self.keys.iter().position(|(key_hour, _)| *key_hour > hour);
+        // between the last key and the first one of the next day
+        let (from, to, span) = match next {
+            Some(0) | None => {
+                let span = first.0 + HOURS_PER_DAY - last.0;
+                (last, first, span)
+            }
+            Some(index) => {
+                let (from, to) = (&self.keys[index - 1], &self.keys[index]);
+                (from, to, to.0 - from.0)
+            }
+        };
+        if span <= 0.0 {
+            return Some(from.1);
+        }
+        let t = (hour - from.0).rem_euclid(HOURS_PER_DAY) / span;
+        Some(from.1.lerp(&to.1, t.clamp(0.0, 1.0)))
+    }
+}
+
+/// Everything the sky, the sun light and the ambient term use at one moment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SkyState {
+    pub hour: f32,
+    /// unit vector from the ground towards the sun, below the horizon at night
+    pub sun_direction: glm::Vector3<f32>,
+    /// degrees above the horizon, negative at night
+    pub sun_elevation: f32,
+    pub sun_color: glm::Vector3<f32>,
+    pub sun_intensity: f32,
+    pub zenith_color: glm::Vector3<f32>,
+    pub horizon_color: glm::Vector3<f32>,
+    pub ambient_color: glm::Vector3<f32>,
+    pub ambient_intensity: f32,
+}
+
+impl SkyState {
+    /// Sets the light and ambient of the built-in shaders, the light travels away from the sun.
+    pub fn write_uniforms(&self, uniforms: &mut FrameUniforms) {
+        uniforms.light_direction = (-self.sun_direction).push(0.0);
+        uniforms.light_color = self.sun_color.push(self.sun_intensity);
+        uniforms.ambient_color = self.ambient_color.push(self.ambient_intensity);
+    }
+}
+
+/// One step of refreshing the image based lighting probe, see `IblSchedule`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IblStep {
+    /// render the sky into this cube face, 0..6 in Vulkan layer order
+    CaptureFace(u32),
+    /// convolve the captured cube into the diffuse irradiance map
+    Irradiance,
+    /// prefilter this mip of the specular map
+    Prefilter { mip: u32 },
+}
+
+/// Spreads a probe refresh over several frames, so a moving sun never costs a full capture in one frame.
+/// A refresh starts once the sun moved `angle_threshold` degrees since the last one and uses the sky state
+/// of its first frame for every step, so all faces match.
+#[derive(Clone, Debug)]
+pub struct IblSchedule {
+    pub angle_threshold: f32,
+    pub steps_per_frame: u32,
+    pub prefilter_mips: u32,
+    /// sun direction of the last finished refresh, None until the first one
+    captured_sun: Option<glm::Vector3<f32>>,
+    /// the refresh in progress: its sky state and the index of its next step
+    pending: Option<(SkyState, u32)>,
+}
+
+impl Default for IblSchedule {
+    fn default() -> Self {
+        Self {
+            angle_threshold: 2.0,
+            steps_per_frame: 1,
+            prefilter_mips: 5,
+            captured_sun: None,
+            pending: None,
+        }
+    }
+}
+
+impl IblSchedule {
+    fn step_count(&self) -> u32 {
+        6 + 1 + self.prefilter_mips
+    }
+
+    fn step(&self, index: u32) -> IblStep {
+        match index {
+            0..=5 => IblStep::CaptureFace(index),
+            6 => IblStep::Irradiance,
+            _ => IblStep::Prefilter { mip: index - 7 },
+        }
+    }
+
+    /// Forces a refresh on the next `update`, after loading a level for example.
+    pub fn invalidate(&mut self) {
+        self.captured_sun = None;
+    }
+
+    pub fn is_updating(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// The steps to record this frame and the sky state to render them with, empty when the probe is current.
+    pub fn update(&mut self, sky: &SkyState) -> (Vec<IblStep>, Option<SkyState>) {
+        if self.pending.is_none() {
+            let moved = match self.captured_sun {
+                Some(captured) => captured.angle(&sky.sun_direction).to_degrees() >= self.angle_threshold,
+                None => true,
+            };
+            if !moved {
+                return (vec![], None);
+            }
+            self.pending = Some((*sky, 0));
+        }
+
+        let (state, next) = self.pending.unwrap();
+        let end = (next + self.steps_per_frame.max(1)).min(self.step_count());
+        let steps = (next..end).map(|index| self.step(index)).collect();
+        if end == self.step_count() {
+            self.captured_sun = Some(state.sun_direction);
+            self.pending = None;
+        } else {
+            self.pending = Some((state, end));
+        }
+        (steps, Some(state))
+    }
+}
+
+/// Result of `TimeOfDay::update`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeOfDayFrame {
+    /// draw the sky with this
+    pub sky: SkyState,
+    /// probe work to record this frame, in order
+    pub ibl_steps: Vec<IblStep>,
+    /// the sky to render `ibl_steps` with, it lags behind `sky` while a refresh is spread over frames
+    pub ibl_sky: Option<SkyState>,
+}
+
+/// Animates the sun and sky over a 24 hour cycle. `hour` 6 is sunrise in the east (+x),
+/// 12 is the highest point in the south (-z), 18 is sunset.
+#[derive(Clone, Debug)]
+pub struct TimeOfDay {
+    /// 0..24
+    pub hour: f32,
+    /// real time a full day takes, zero stops the clock
+    pub day_length: Duration,
+    pub paused: bool,
+    /// degrees the sun stays below the zenith at noon, roughly the latitude
+    pub noon_tilt: f32,
+    pub sun_color: Curve<glm::Vector3<f32>>,
+    pub sun_intensity: Curve<f32>,
+    pub zenith_color: Curve<glm::Vector3<f32>>,
+    pub horizon_color: Curve<glm::Vector3<f32>>,
+    pub ambient_color: Curve<glm::Vector3<f32>>,
+    pub ambient_intensity: Curve<f32>,
+    pub ibl: IblSchedule,
+}
+
+impl Default for TimeOfDay {
+    /// A twenty minute day with a warm dawn and dusk and a dim blue night.
+    fn default() -> Self {
+        let rgb = glm::Vector3::new;
+        Self {
+            hour: 10.0,
+            day_length: Duration::from_secs(20 * 60),
+            paused: false,
+            noon_tilt: 30.0,
+            sun_color: Curve::new(&[
+                (5.5, rgb(1.0, 0.35, 0.15)),
+                (7.5, rgb(1.0, 0.8, 0.6)),
+                (12.0, rgb(1.0, 0.97, 0.92)),
+                (16.5, rgb(1.0, 0.8, 0.6)),
+                (18.5, rgb(1.0, 0.35, 0.15)),
+            ]),
+            sun_intensity: Curve::new(&[(5.5, 0.0), (7.0, 1.5), (12.0, 3.0), (17.0, 1.5), (18.5, 0.0)]),
+            zenith_color: Curve::new(&[
+                (4.5, rgb(0.01, 0.015, 0.04)),
+                (6.5, rgb(0.25, 0.35, 0.6)),
+                (12.0, rgb(0.2, 0.45, 0.9)),
+                (17.5, rgb(0.25, 0.35, 0.6)),
+                (19.5, rgb(0.01, 0.015, 0.04)),
+            ]),
+            horizon_color: Curve::new(&[
+                (4.5, rgb(0.02, 0.03, 0.06)),
+                (6.0, rgb(0.9, 0.5, 0.3)),
+                (8.0, rgb(0.7, 0.8, 0.95)),
+                (16.0, rgb(0.7, 0.8, 0.95)),
+                (18.0, rgb(0.9, 0.45, 0.25)),
+                (19.5, rgb(0.02, 0.03, 0.06)),
+            ]),
+            ambient_color: Curve::new(&[
+                (5.0, rgb(0.3, 0.4, 0.8)),
+                (8.0, rgb(1.0, 1.0, 1.0)),
+                (17.0, rgb(1.0, 1.0, 1.0)),
+                (19.0, rgb(0.3, 0.4, 0.8)),
+            ]),
+            ambient_intensity: Curve::new(&[(5.0, 0.005), (8.0, 0.03), (17.0, 0.03), (19.0, 0.005)]),
+            ibl: IblSchedule::default(),
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Game hours that pass per real second.
+    pub fn hours_per_second(&self) -> f32 {
+        match self.day_length.as_secs_f32() {
+            seconds if seconds > 0.0 => HOURS_PER_DAY / seconds,
+            _ => 0.0,
+        }
+    }
+
+    /// Advances the clock by `delta_seconds` of real time.
+    pub fn advance(&mut self, delta_seconds: f32) {
+        if !self.paused {
+            self.set_hour(self.hour + delta_seconds * self.hours_per_second());
+        }
+    }
+
+    /// Jumps to `hour`, wrapped into 0..24.
+    pub fn set_hour(&mut self, hour: f32) {
+        self.hour = hour.rem_euclid(HOURS_PER_DAY);
+    }
+
+    /// Direction towards the sun at `hour`: a circle through east, the tilted noon point and west.
+    pub fn sun_direction(&self, hour: f32) -> glm::Vector3<f32> {
+        // 0 at sunrise, pi at sunset
+        let angle = (hour - 6.0) / 12.0 * std::f32::consts::PI;
+        let tilt = self.noon_tilt.to_radians();
+        let up = glm::Vector3::new(0.0, tilt.cos(), -tilt.sin());
+        (glm::Vector3::x() * angle.cos() + up * angle.sin()).normalize()
+    }
+
+    /// The sky at the current hour.
+    pub fn state(&self) -> SkyState {
+        let hour = self.hour;
+        let sun_direction = self.sun_direction(hour);
+        let white = glm::Vector3::repeat(1.0);
+        SkyState {
+            hour,
+            sun_direction,
+            sun_elevation: sun_direction.y.clamp(-1.0, 1.0).asin().to_degrees(),
+            sun_color: self.sun_color.sample(hour).unwrap_or(white),
+            sun_intensity: self.sun_intensity.sample(hour).unwrap_or(0.0).max(0.0),
+            zenith_color: self.zenith_color.sample(hour).unwrap_or(white),
+            horizon_color: self.horizon_color.sample(hour).unwrap_or(white),
+            ambient_color: self.ambient_color.sample(hour).unwrap_or(white),
+            ambient_intensity: self.ambient_intensity.sample(hour).unwrap_or(0.0).max(0.0),
+        }
+    }
+
+    /// Advances the clock, writes the light into `uniforms` and returns what to render this frame.
+    pub fn update(&mut self, delta_seconds: f32, uniforms: &mut FrameUniforms) -> TimeOfDayFrame {
+        self.advance(delta_seconds);
+        let sky = self.state();
+        sky.write_uniforms(uniforms);
+        let (ibl_steps, ibl_sky) = self.ibl.update(&sky);
+        TimeOfDayFrame { sky, ibl_steps, ibl_sky }
+    }
+}
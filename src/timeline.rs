@@ -0,0 +1,398 @@
+use std::{collections::VecDeque, ffi::c_void, ptr};
+
+use ash::{extensions::ext::DebugUtils, prelude::VkResult, vk};
+
+use crate::{
+    debug,
+    features::DeviceFeatures,
+    sync_pool::{FencePool, SemaphorePool},
+};
+
+/// Timeline semaphores are core in Vulkan 1.2 but only usable with this feature enabled.
+pub fn required_features() -> DeviceFeatures {
+    DeviceFeatures {
+        timeline_semaphore: true,
+        ..Default::default()
+    }
+}
+
+/// How `Timelines` synchronizes, every queue behaves the same either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// one timeline semaphore per queue, signaled with the value of each submission
+    Timeline,
+    /// a fence per submission and binary semaphores for handoffs between queues
+    Binary,
+}
+
+impl SyncMode {
+    /// Timeline when `enabled` has the timelineSemaphore feature.
+    pub fn for_features(enabled: &DeviceFeatures) -> SyncMode {
+        if enabled.timeline_semaphore {
+            SyncMode::Timeline
+        } else {
+            SyncMode::Binary
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct QueueId(usize);
+
+/// A value on the timeline of a queue, reached once the submission that returned it finished.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SyncPoint {
+    queue: QueueId,
+    value: u64,
+    /// binary mode only, signaled for one other submission to wait on
+    handoff: vk::Semaphore,
+}
+
+impl SyncPoint {
+    pub fn queue(&self) -> QueueId {
+        self.queue
+    }
+
+    pub fn value(&self) -> u64 {
+        self.value
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Submit<'a> {
+    pub command_buffers: &'a [vk::CommandBuffer],
+    /// work of this or other queues to wait for before the stage
+    pub waits: &'a [(SyncPoint, vk::PipelineStageFlags)],
+    /// binary semaphores from outside, like the swapchain acquire
+    pub wait_semaphores: &'a [(vk::Semaphore, vk::PipelineStageFlags)],
+    pub signal_semaphores: &'a [vk::Semaphore],
+    /// another submission waits on the returned point. Binary mode signals a semaphore for it, which exactly
+    /// one submission has to wait on, timeline mode allows any number of waits.
+    pub handoff: bool,
+}
+
+struct QueueTimeline {
+    queue: vk::Queue,
+    /// timeline mode only
+    semaphore: vk::Semaphore,
+    submitted: u64,
+    completed: u64,
+    /// binary mode, the fence of every submission in flight
+    pending: VecDeque<(u64, vk::Fence)>,
+    /// binary mode, handoffs this queue waited on, free again once the waiting submission finished
+    consumed: VecDeque<(u64, vk::Semaphore)>,
+}
+
+impl QueueTimeline {
+    /// Binary mode, finishes the oldest submissions while `signaled` says their fence is.
+    /// Returns their fences and the handoffs they waited on, to recycle.
+    fn retire(&mut self, mut signaled: impl FnMut(vk::Fence) -> bool) -> (Vec<vk::Fence>, Vec<vk::Semaphore>) {
+        let mut fences = vec![];
+        while let Some(&(value, fence)) = self.pending.front() {
+            if !signaled(fence) {
+                break;
+            }
+            self.pending.pop_front();
+            self.completed = value;
+            fences.push(fence);
+        }
+        let mut handoffs = vec![];
+        while self.consumed.front().is_some_and(|(value, _)| *value <= self.completed) {
+            handoffs.push(self.consumed.pop_front().unwrap().1);
+        }
+        (fences, handoffs)
+    }
+}
+
+/// Monotonically increasing submission values per queue, for frame pacing and handoffs between queues.
+/// With VK_KHR_timeline_semaphore a value is a timeline semaphore signal, without it a fence per submission
+/// and pooled binary semaphores stand in, so callers only deal with `SyncPoint`s.
+pub struct Timelines {
+    mode: SyncMode,
+    debug_utils: Option<DebugUtils>,
+    queues: Vec<QueueTimeline>,
+    fences: FencePool,
+    handoffs: SemaphorePool,
+}
+
+impl Timelines {
+    pub fn new(mode: SyncMode, debug_utils: Option<DebugUtils>) -> Self {
+        Self {
+            mode,
+            fences: FencePool::new("timeline fence", debug_utils.clone()),
+            handoffs: SemaphorePool::new("timeline handoff", debug_utils.clone()),
+            debug_utils,
+            queues: vec![],
+        }
+    }
+
+    pub fn mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    /// Starts a timeline at 0 for `queue`, only submissions made through `submit` advance it.
+    ///
+    /// # Safety
+    /// `queue` has to be a queue of `device`.
+    pub unsafe fn add_queue(&mut self, device: &ash::Device, queue: vk::Queue, name: &str) -> VkResult<QueueId> {
+        let semaphore = match self.mode {
+            SyncMode::Timeline => {
+                let mut type_info = vk::SemaphoreTypeCreateInfo {
+                    semaphore_type: vk::SemaphoreType::TIMELINE,
+                    initial_value: 0,
+                    ..Default::default()
+                };
+                let create_info = vk::SemaphoreCreateInfo {
+                    p_next: &mut type_info as *mut vk::SemaphoreTypeCreateInfo as *const c_void,
+                    ..Default::default()
+                };
+                let semaphore = device.create_semaphore(&create_info, None)?;
+                if let Some(debug_utils) = self.debug_utils.as_ref() {
+                    debug::set_object_name(debug_utils, device, semaphore, &format!("{} timeline", name));
+                }
+                semaphore
+            }
+            SyncMode::Binary => vk::Semaphore::null(),
+        };
+        self.queues.push(QueueTimeline {
+            queue,
+            semaphore,
+            submitted: 0,
+            completed: 0,
+            pending: VecDeque::new(),
+            consumed: VecDeque::new(),
+        });
+        Ok(QueueId(self.queues.len() - 1))
+    }
+
+    /// Submits to `queue` and returns the point its work reaches when finished.
+    /// In binary mode a wait on an unfinished point needs `Submit::handoff` on the submission that made it.
+    ///
+    /// # Safety
+    /// The command buffers and semaphores of `submit` have to be objects of `device`.
+    pub unsafe fn submit(&mut self, device: &ash::Device, queue: QueueId, submit: &Submit) -> VkResult<SyncPoint> {
+        let value = self.queues[queue.0].submitted + 1;
+        let mut wait_semaphores = vec![];
+        let mut wait_stages = vec![];
+        let mut wait_values = vec![];
+        let mut consumed = vec![];
+        for &(point, stage) in submit.waits {
+            match self.mode {
+                SyncMode::Timeline => {
+                    wait_semaphores.push(self.queues[point.queue.0].semaphore);
+                    wait_values.push(point.value);
+                }
+                // a handoff has to be waited on even when its work is done, it would stay signaled otherwise
+                SyncMode::Binary if point.handoff != vk::Semaphore::null() => {
+                    wait_semaphores.push(point.handoff);
+                    wait_values.push(0);
+                    consumed.push(point.handoff);
+                }
+                SyncMode::Binary => {
+                    if self.is_complete(device, &point)? {
+                        continue;
+                    }
+                    panic!(
+                        "Waiting on value {} of queue {} needs a handoff without timeline semaphores",
+                        point.value, point.queue.0
+                    );
+                }
+            }
+            wait_stages.push(stage);
+        }
+        for &(semaphore, stage) in submit.wait_semaphores {
+            wait_semaphores.push(semaphore);
+            wait_stages.push(stage);
+            wait_values.push(0);
+        }
+
+        let mut signal_semaphores = submit.signal_semaphores.to_vec();
+        let mut signal_values = vec![0; signal_semaphores.len()];
+        let mut handoff = vk::Semaphore::null();
+        let mut fence = vk::Fence::null();
+        match self.mode {
+            SyncMode::Timeline => {
+                signal_semaphores.push(self.queues[queue.0].semaphore);
+                signal_values.push(value);
+            }
+            SyncMode::Binary => {
+                fence = self.fences.acquire(device)?;
+                if submit.handoff {
+                    handoff = self.handoffs.acquire(device)?;
+                    signal_semaphores.push(handoff);
+                }
+            }
+        }
+
+        let timeline_info = vk::TimelineSemaphoreSubmitInfo {
+            wait_semaphore_value_count: wait_values.len() as u32,
+            p_wait_semaphore_values: wait_values.as_ptr(),
+            signal_semaphore_value_count: signal_values.len() as u32,
+            p_signal_semaphore_values: signal_values.as_ptr(),
+            ..Default::default()
+        };
+        let submit_info = vk::SubmitInfo {
+            p_next: match self.mode {
+                SyncMode::Timeline => &timeline_info as *const vk::TimelineSemaphoreSubmitInfo as *const c_void,
+                SyncMode::Binary => ptr::null(),
+            },
+            wait_semaphore_count: wait_semaphores.len() as u32,
+            p_wait_semaphores: wait_semaphores.as_ptr(),
+            p_wait_dst_stage_mask: wait_stages.as_ptr(),
+            command_buffer_count: submit.command_buffers.len() as u32,
+            p_command_buffers: submit.command_buffers.as_ptr(),
+            signal_semaphore_count: signal_semaphores.len() as u32,
+            p_signal_semaphores: signal_semaphores.as_ptr(),
+            ..Default::default()
+        };
+        if let Err(e) = device.queue_submit(self.queues[queue.0].queue, &[submit_info], fence) {
+            if fence != vk::Fence::null() {
+                self.fences.release(device, fence)?;
+            }
+            if handoff != vk::Semaphore::null() {
+                self.handoffs.release(handoff);
+            }
+            return Err(e);
+        }
+
+        let timeline = &mut self.queues[queue.0];
+        timeline.submitted = value;
+        if self.mode == SyncMode::Binary {
+            timeline.pending.push_back((value, fence));
+            timeline
+                .consumed
+                .extend(consumed.into_iter().map(|semaphore| (value, semaphore)));
+        }
+        Ok(SyncPoint { queue, value, handoff })
+    }
+
+    /// Newest value of `queue` the GPU finished, recycles the fences and handoffs of the finished submissions.
+    ///
+    /// # Safety
+    /// `device` has to be the device of `add_queue`.
+    pub unsafe fn completed(&mut self, device: &ash::Device, queue: QueueId) -> VkResult<u64> {
+        let timeline = &mut self.queues[queue.0];
+        match self.mode {
+            SyncMode::Timeline => {
+                timeline.completed = device.get_semaphore_counter_value(timeline.semaphore)?;
+            }
+            SyncMode::Binary => {
+                let mut status = Ok(());
+                let (fences, handoffs) = timeline.retire(|fence| {
+                    device.get_fence_status(fence).unwrap_or_else(|e| {
+                        status = Err(e);
+                        false
+                    })
+                });
+                for fence in fences {
+                    self.fences.release(device, fence)?;
+                }
+                for semaphore in handoffs {
+                    self.handoffs.release(semaphore);
+                }
+                status?;
+            }
+        }
+        Ok(timeline.completed)
+    }
+
+    /// # Safety
+    /// As for `completed`.
+    pub unsafe fn is_complete(&mut self, device: &ash::Device, point: &SyncPoint) -> VkResult<bool> {
+        if self.queues[point.queue.0].completed >= point.value {
+            return Ok(true);
+        }
+        Ok(self.completed(device, point.queue)? >= point.value)
+    }
+
+    /// Blocks until `point` is reached or `timeout` nanoseconds passed, which returns `vk::Result::TIMEOUT`.
+    ///
+    /// # Safety
+    /// As for `completed`.
+    pub unsafe fn wait(&mut self, device: &ash::Device, point: &SyncPoint, timeout: u64) -> VkResult<()> {
+        if self.queues[point.queue.0].completed >= point.value {
+            return Ok(());
+        }
+        let timeline = &self.queues[point.queue.0];
+        match self.mode {
+            SyncMode::Timeline => {
+                let wait_info = vk::SemaphoreWaitInfo {
+                    semaphore_count: 1,
+                    p_semaphores: &timeline.semaphore,
+                    p_values: &point.value,
+                    ..Default::default()
+                };
+                device.wait_semaphores(&wait_info, timeout)?;
+            }
+            SyncMode::Binary => {
+                // a value is reached once it and every value before it finished
+                let fences: Vec<vk::Fence> = timeline
+                    .pending
+                    .iter()
+                    .take_while(|(value, _)| *value <= point.value)
+                    .map(|(_, fence)| *fence)
+                    .collect();
+                if !fences.is_empty() {
+                    device.wait_for_fences(&fences, true, timeout)?;
+                }
+            }
+        }
+        self.completed(device, point.queue)?;
+        Ok(())
+    }
+
+    /// The device has to be idle.
+    ///
+    /// # Safety
+    /// No submission made through the timelines may be pending.
+    pub unsafe fn destroy(&mut self, device: &ash::Device) {
+        for timeline in self.queues.drain(..) {
+            if timeline.semaphore != vk::Semaphore::null() {
+                device.destroy_semaphore(timeline.semaphore, None);
+            }
+        }
+        self.fences.destroy(device);
+        self.handoffs.destroy(device);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::Handle;
+
+    use super::*;
+
+    #[test]
+    fn timeline_mode_needs_the_feature() {
+        assert_eq!(SyncMode::for_features(&required_features()), SyncMode::Timeline);
+        assert_eq!(SyncMode::for_features(&DeviceFeatures::default()), SyncMode::Binary);
+    }
+
+    #[test]
+    fn binary_submissions_finish_in_order_and_free_their_handoffs_after() {
+        let fences: Vec<_> = (1..=3).map(vk::Fence::from_raw).collect();
+        let handoffs: Vec<_> = (1..=2).map(vk::Semaphore::from_raw).collect();
+        let mut timeline = QueueTimeline {
+            queue: vk::Queue::null(),
+            semaphore: vk::Semaphore::null(),
+            submitted: 3,
+            completed: 0,
+            pending: (1..=3).zip(fences.iter().copied()).collect(),
+            // submission 2 waited on the first handoff, submission 3 on the second
+            consumed: (2..=3).zip(handoffs.iter().copied()).collect(),
+        };
+
+        assert_eq!(timeline.retire(|_| false), (vec![], vec![]));
+        assert_eq!(timeline.retire(|fence| fence == fences[0]), (vec![fences[0]], vec![]));
+        assert_eq!(timeline.completed, 1);
+        // a later fence that signaled first waits for the ones before it
+        assert_eq!(timeline.retire(|fence| fence == fences[2]), (vec![], vec![]));
+        assert_eq!(
+            timeline.retire(|fence| fence != fences[2]),
+            (vec![fences[1]], vec![handoffs[0]])
+        );
+        assert_eq!(timeline.retire(|_| true), (vec![fences[2]], vec![handoffs[1]]));
+        assert_eq!(timeline.completed, 3);
+        assert!(timeline.pending.is_empty() && timeline.consumed.is_empty());
+    }
+}
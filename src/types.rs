@@ -1,6 +1,5 @@
 use lazy_static::lazy_static;
-use std::ffi::{CStr, CString};
-type Layer<'a> = &'a CStr;
+use std::ffi::CString;
 
 lazy_static! {
     /// Can be used to to assist developers in isolating incorrect usage, and in verifying that applications correctly use the API
@@ -0,0 +1,717 @@
+use std::{
+    mem::{offset_of, size_of},
+    ptr,
+};
+
+use crate::error::Result;
+use ash::{
+    prelude::VkResult,
+    vk::{self, BufferUsageFlags, MemoryMapFlags, MemoryPropertyFlags, StructureType},
+};
+use nalgebra as glm;
+
+use crate::{
+    buffer::{self, MAX_FRAMES_IN_FLIGHT},
+    pipeline::{self, PipelineBuilder},
+    utility,
+};
+
+pub const MAX_UI_VERTICES: usize = 16384;
+pub const MAX_UI_INDICES: usize = MAX_UI_VERTICES / 4 * 6;
+pub const MAX_ROUNDED_RECTS: usize = 4096;
+pub const MAX_UI_TEXTURES: u32 = 64;
+
+/// Rectangle in window pixels, origin in the top left corner.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct UiVertex {
+    pub position: glm::Vector2<f32>,
+    pub uv: glm::Vector2<f32>,
+    pub color: glm::Vector4<f32>,
+}
+
+impl UiVertex {
+    pub const fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<UiVertex>() as u32,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    pub const fn get_input_attribute_description() -> [vk::VertexInputAttributeDescription; 3] {
+        [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(UiVertex, position) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(UiVertex, uv) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(UiVertex, color) as u32,
+            },
+        ]
+    }
+}
+
+/// Describes how a texture is split into 9 regions, the corners keep their size
+/// while the edges and the center stretch with the destination rectangle.
+#[derive(Clone, Copy, Debug)]
+pub struct NineSlice {
+    pub texture_width: f32,
+    pub texture_height: f32,
+    /// left, top, right, bottom border in texture pixels
+    pub borders: [f32; 4],
+}
+
+/// Rectangle with rounded corners, drawn with a signed distance field.
+#[derive(Clone, Copy, Debug)]
+pub struct RoundedRect {
+    pub rect: Rect,
+    pub radius: f32,
+    pub fill_color: glm::Vector4<f32>,
+    /// 0.0 disables the border
+    pub border_width: f32,
+    pub border_color: glm::Vector4<f32>,
+    pub shadow_offset: glm::Vector2<f32>,
+    /// 0.0 disables the shadow
+    pub shadow_blur: f32,
+    pub shadow_spread: f32,
+    pub shadow_color: glm::Vector4<f32>,
+}
+
+impl RoundedRect {
+    pub fn new(rect: Rect, radius: f32, fill_color: glm::Vector4<f32>) -> Self {
+        Self {
+            rect,
+            radius,
+            fill_color,
+            border_width: 0.0,
+            border_color: glm::Vector4::zeros(),
+            shadow_offset: glm::Vector2::zeros(),
+            shadow_blur: 0.0,
+            shadow_spread: 0.0,
+            shadow_color: glm::Vector4::zeros(),
+        }
+    }
+
+    pub fn border(mut self, width: f32, color: glm::Vector4<f32>) -> Self {
+        self.border_width = width;
+        self.border_color = color;
+        self
+    }
+
+    pub fn shadow(mut self, offset: glm::Vector2<f32>, blur: f32, spread: f32, color: glm::Vector4<f32>) -> Self {
+        self.shadow_offset = offset;
+        self.shadow_blur = blur;
+        self.shadow_spread = spread;
+        self.shadow_color = color;
+        self
+    }
+}
+
+/// Per instance data read by shaders/ui_rect.vert
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RoundedRectInstance {
+    rect: glm::Vector4<f32>,
+    fill_color: glm::Vector4<f32>,
+    border_color: glm::Vector4<f32>,
+    shadow_color: glm::Vector4<f32>,
+    /// offset x, offset y, blur, spread
+    shadow: glm::Vector4<f32>,
+    /// radius, border width
+    params: glm::Vector2<f32>,
+}
+
+impl RoundedRectInstance {
+    pub const fn get_binding_description() -> vk::VertexInputBindingDescription {
+        vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: size_of::<RoundedRectInstance>() as u32,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }
+    }
+
+    pub const fn get_input_attribute_description() -> [vk::VertexInputAttributeDescription; 6] {
+        [
+            vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(RoundedRectInstance, rect) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 1,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(RoundedRectInstance, fill_color) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 2,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(RoundedRectInstance, border_color) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 3,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(RoundedRectInstance, shadow_color) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 4,
+                binding: 0,
+                format: vk::Format::R32G32B32A32_SFLOAT,
+                offset: offset_of!(RoundedRectInstance, shadow) as u32,
+            },
+            vk::VertexInputAttributeDescription {
+                location: 5,
+                binding: 0,
+                format: vk::Format::R32G32_SFLOAT,
+                offset: offset_of!(RoundedRectInstance, params) as u32,
+            },
+        ]
+    }
+}
+
+impl From<&RoundedRect> for RoundedRectInstance {
+    fn from(value: &RoundedRect) -> Self {
+        Self {
+            rect: glm::Vector4::new(value.rect.x, value.rect.y, value.rect.width, value.rect.height),
+            fill_color: value.fill_color,
+            border_color: value.border_color,
+            shadow_color: value.shadow_color,
+            shadow: glm::Vector4::new(
+                value.shadow_offset.x,
+                value.shadow_offset.y,
+                value.shadow_blur,
+                value.shadow_spread,
+            ),
+            params: glm::Vector2::new(value.radius, value.border_width),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum UiCommand {
+    Sprites {
+        texture: vk::DescriptorSet,
+        index_offset: u32,
+        index_count: u32,
+    },
+    RoundedRects {
+        first_instance: u32,
+        instance_count: u32,
+    },
+}
+
+/// Collects the ui draws of a frame, in back to front order. Draws past the `MAX_UI_*` buffer sizes are dropped.
+#[derive(Default)]
+pub struct UiBatch {
+    vertices: Vec<UiVertex>,
+    indices: Vec<u32>,
+    rounded_rects: Vec<RoundedRectInstance>,
+    commands: Vec<UiCommand>,
+    /// a draw was dropped since the last clear, only the first one is warned about
+    overflowed: bool,
+}
+
+impl UiBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.rounded_rects.clear();
+        self.commands.clear();
+        self.overflowed = false;
+    }
+
+    /// False and a warning when `vertices`, `indices` and `rounded_rects` more don't fit the renderer's buffers.
+    fn fits(&mut self, vertices: usize, indices: usize, rounded_rects: usize) -> bool {
+        let fits = self.vertices.len() + vertices <= MAX_UI_VERTICES
+            && self.indices.len() + indices <= MAX_UI_INDICES
+            && self.rounded_rects.len() + rounded_rects <= MAX_ROUNDED_RECTS;
+        if !fits && !self.overflowed {
+            log::warn!(
+                "The ui batch is full at {} vertices and {} rounded rects, the draws past it are dropped",
+                self.vertices.len(),
+                self.rounded_rects.len()
+            );
+            self.overflowed = true;
+        }
+        fits
+    }
+
+    pub fn push_rounded_rect(&mut self, rounded_rect: &RoundedRect) {
+        if !self.fits(0, 0, 1) {
+            return;
+        }
+        let first_instance = self.rounded_rects.len() as u32;
+        self.rounded_rects.push(rounded_rect.into());
+
+        match self.commands.last_mut() {
+            Some(UiCommand::RoundedRects { instance_count, .. }) => *instance_count += 1,
+            _ => self.commands.push(UiCommand::RoundedRects {
+                first_instance,
+                instance_count: 1,
+            }),
+        }
+    }
+
+    /// `texture` is a set allocated with `UiRenderer::create_texture_set`
+    pub fn push_sprite(&mut self, texture: vk::DescriptorSet, rect: Rect, color: glm::Vector4<f32>) {
+        let xs = [rect.x, rect.x + rect.width];
+        let ys = [rect.y, rect.y + rect.height];
+        self.push_grid(texture, &xs, &ys, &[0.0, 1.0], &[0.0, 1.0], color);
+    }
+
+    pub fn push_nine_slice(
+        &mut self,
+        texture: vk::DescriptorSet,
+        rect: Rect,
+        nine_slice: &NineSlice,
+        color: glm::Vector4<f32>,
+    ) {
+        let [mut left, mut top, mut right, mut bottom] = nine_slice.borders;
+
+        // shrink the borders when the destination is too small to fit both of them
+        let horizontal = left + right;
+        if horizontal > rect.width && horizontal > 0.0 {
+            left *= rect.width / horizontal;
+            right *= rect.width / horizontal;
+        }
+        let vertical = top + bottom;
+        if vertical > rect.height && vertical > 0.0 {
+            top *= rect.height / vertical;
+            bottom *= rect.height / vertical;
+        }
+
+        let [border_left, border_top, border_right, border_bottom] = nine_slice.borders;
+        let xs = [rect.x, rect.x + left, rect.x + rect.width - right, rect.x + rect.width];
+        let ys = [rect.y, rect.y + top, rect.y + rect.height - bottom, rect.y + rect.height];
+        let us = [
+            0.0,
+            border_left / nine_slice.texture_width,
+            1.0 - border_right / nine_slice.texture_width,
+            1.0,
+        ];
+        let vs = [
+            0.0,
+            border_top / nine_slice.texture_height,
+            1.0 - border_bottom / nine_slice.texture_height,
+            1.0,
+        ];
+        self.push_grid(texture, &xs, &ys, &us, &vs, color);
+    }
+
+    /// Pushes a grid of quads, one per cell between consecutive coordinates.
+    fn push_grid(
+        &mut self,
+        texture: vk::DescriptorSet,
+        xs: &[f32],
+        ys: &[f32],
+        us: &[f32],
+        vs: &[f32],
+        color: glm::Vector4<f32>,
+    ) {
+        let cells = (xs.len() - 1) * (ys.len() - 1);
+        if !self.fits(xs.len() * ys.len(), cells * 6, 0) {
+            return;
+        }
+        let base_vertex = self.vertices.len() as u32;
+        let index_offset = self.indices.len() as u32;
+        let columns = xs.len() as u32;
+
+        for (y, v) in ys.iter().zip(vs) {
+            for (x, u) in xs.iter().zip(us) {
+                self.vertices.push(UiVertex {
+                    position: glm::Vector2::new(*x, *y),
+                    uv: glm::Vector2::new(*u, *v),
+                    color,
+                });
+            }
+        }
+
+        for row in 0..ys.len() as u32 - 1 {
+            for column in 0..columns - 1 {
+                let top_left = base_vertex + row * columns + column;
+                let bottom_left = top_left + columns;
+                self.indices.extend_from_slice(&[
+                    top_left,
+                    top_left + 1,
+                    bottom_left + 1,
+                    bottom_left + 1,
+                    bottom_left,
+                    top_left,
+                ]);
+            }
+        }
+        let index_count = self.indices.len() as u32 - index_offset;
+
+        match self.commands.last_mut() {
+            Some(UiCommand::Sprites {
+                texture: last_texture,
+                index_count: last_count,
+                ..
+            }) if *last_texture == texture => *last_count += index_count,
+            _ => self.commands.push(UiCommand::Sprites {
+                texture,
+                index_offset,
+                index_count,
+            }),
+        }
+    }
+}
+
+struct MappedBuffer<T> {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    data: *mut T,
+    capacity: usize,
+}
+
+impl<T> MappedBuffer<T> {
+    unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        capacity: usize,
+        usage: vk::BufferUsageFlags,
+    ) -> VkResult<Self> {
+        let size = (capacity * size_of::<T>()) as vk::DeviceSize;
+        let (buffer, memory) = buffer::create_buffer(
+            device,
+            instance,
+            physical_device,
+            size,
+            usage,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let data = device.map_memory(memory, 0, size, MemoryMapFlags::empty())? as *mut T;
+
+        Ok(Self {
+            buffer,
+            memory,
+            data,
+            capacity,
+        })
+    }
+
+    unsafe fn write(&self, values: &[T]) {
+        // `UiBatch` drops the draws that don't fit
+        debug_assert!(values.len() <= self.capacity);
+        let count = values.len().min(self.capacity);
+        self.data.copy_from_nonoverlapping(values.as_ptr(), count);
+    }
+
+    unsafe fn destroy(&self, device: &ash::Device) {
+        device.unmap_memory(self.memory);
+        device.destroy_buffer(self.buffer, None);
+        device.free_memory(self.memory, None);
+    }
+}
+
+struct UiFrame {
+    vertices: MappedBuffer<UiVertex>,
+    indices: MappedBuffer<u32>,
+    rounded_rects: MappedBuffer<RoundedRectInstance>,
+}
+
+/// Draws a `UiBatch` on top of the current render pass.
+pub struct UiRenderer {
+    sprite_pipeline: vk::Pipeline,
+    sprite_layout: vk::PipelineLayout,
+    rect_pipeline: vk::Pipeline,
+    rect_layout: vk::PipelineLayout,
+    texture_set_layout: vk::DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    frames: Vec<UiFrame>,
+}
+
+impl UiRenderer {
+    /// # Safety
+    /// `physical_device` has to be the device of `device`, `render_pass` has to outlive the renderer.
+    pub unsafe fn new(
+        device: &ash::Device,
+        instance: &ash::Instance,
+        physical_device: vk::PhysicalDevice,
+        render_pass: vk::RenderPass,
+    ) -> Result<Self> {
+        let sampler_binding = vk::DescriptorSetLayoutBinding {
+            binding: 0,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: 1,
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            p_immutable_samplers: ptr::null(),
+        };
+        let set_layout_info = vk::DescriptorSetLayoutCreateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_LAYOUT_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+            binding_count: 1,
+            p_bindings: &sampler_binding,
+        };
+        let texture_set_layout = device.create_descriptor_set_layout(&set_layout_info, None)?;
+
+        let pool_size = vk::DescriptorPoolSize {
+            ty: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            descriptor_count: MAX_UI_TEXTURES,
+        };
+        let pool_info = vk::DescriptorPoolCreateInfo {
+            s_type: StructureType::DESCRIPTOR_POOL_CREATE_INFO,
+            p_next: ptr::null(),
+            flags: vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+            max_sets: MAX_UI_TEXTURES,
+            pool_size_count: 1,
+            p_pool_sizes: &pool_size,
+        };
+        let descriptor_pool = device.create_descriptor_pool(&pool_info, None)?;
+
+        let screen_size = size_of::<glm::Vector2<f32>>() as u32;
+
+        let sprite = PipelineBuilder::new("shaders/spv/ui_sprite_vert.spv", "shaders/spv/ui_sprite_frag.spv")
+            .vertex_input(
+                &[UiVertex::get_binding_description()],
+                &UiVertex::get_input_attribute_description(),
+            )
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+            .alpha_blending(true)
+            .descriptor_set_layouts(&[texture_set_layout])
+            .push_constant_range(vk::ShaderStageFlags::VERTEX, 0, screen_size);
+        let rect = PipelineBuilder::new("shaders/spv/ui_rect_vert.spv", "shaders/spv/ui_rect_frag.spv")
+            .vertex_input(
+                &[RoundedRectInstance::get_binding_description()],
+                &RoundedRectInstance::get_input_attribute_description(),
+            )
+            .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::CLOCKWISE)
+            .alpha_blending(true)
+            .push_constant_range(vk::ShaderStageFlags::VERTEX, 0, screen_size);
+        // one call, so the driver can compile both at once
+        let pipelines = pipeline::build_pipelines(
+            device,
+            vk::PipelineCache::null(),
+            &[(&sprite, render_pass), (&rect, render_pass)],
+        )?;
+        let (sprite_pipeline, sprite_layout) = pipelines[0];
+        let (rect_pipeline, rect_layout) = pipelines[1];
+
+        let mut frames = vec![];
+        for _ in 0..MAX_FRAMES_IN_FLIGHT {
+            frames.push(UiFrame {
+                vertices: MappedBuffer::new(
+                    device,
+                    instance,
+                    physical_device,
+                    MAX_UI_VERTICES,
+                    BufferUsageFlags::VERTEX_BUFFER,
+                )?,
+                indices: MappedBuffer::new(
+                    device,
+                    instance,
+                    physical_device,
+                    MAX_UI_INDICES,
+                    BufferUsageFlags::INDEX_BUFFER,
+                )?,
+                rounded_rects: MappedBuffer::new(
+                    device,
+                    instance,
+                    physical_device,
+                    MAX_ROUNDED_RECTS,
+                    BufferUsageFlags::VERTEX_BUFFER,
+                )?,
+            });
+        }
+
+        Ok(Self {
+            sprite_pipeline,
+            sprite_layout,
+            rect_pipeline,
+            rect_layout,
+            texture_set_layout,
+            descriptor_pool,
+            frames,
+        })
+    }
+
+    /// Allocates a descriptor set for sprites and nine slices sampling `image_view`.
+    ///
+    /// # Safety
+    /// `image_view` and `sampler` have to outlive the set.
+    pub unsafe fn create_texture_set(
+        &self,
+        device: &ash::Device,
+        image_view: vk::ImageView,
+        sampler: vk::Sampler,
+    ) -> VkResult<vk::DescriptorSet> {
+        let alloc_info = vk::DescriptorSetAllocateInfo {
+            s_type: StructureType::DESCRIPTOR_SET_ALLOCATE_INFO,
+            p_next: ptr::null(),
+            descriptor_pool: self.descriptor_pool,
+            descriptor_set_count: 1,
+            p_set_layouts: &self.texture_set_layout,
+        };
+        let descriptor_set = device.allocate_descriptor_sets(&alloc_info)?[0];
+
+        let image_info = vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        };
+        let write = vk::WriteDescriptorSet {
+            s_type: StructureType::WRITE_DESCRIPTOR_SET,
+            p_next: ptr::null(),
+            dst_set: descriptor_set,
+            dst_binding: 0,
+            dst_array_element: 0,
+            descriptor_count: 1,
+            descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            p_image_info: &image_info,
+            p_buffer_info: ptr::null(),
+            p_texel_buffer_view: ptr::null(),
+        };
+        device.update_descriptor_sets(&[write], &[]);
+
+        Ok(descriptor_set)
+    }
+
+    /// Records the batch into `command_buffer`, has to be called inside a render pass.
+    /// The buffers of `current_frame` are overwritten, so the fence of that frame has to be waited on first.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside a render pass compatible with the renderer's.
+    pub unsafe fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        current_frame: usize,
+        batch: &UiBatch,
+        extent: vk::Extent2D,
+    ) {
+        if batch.commands.is_empty() {
+            return;
+        }
+
+        let frame = &self.frames[current_frame];
+        frame.vertices.write(&batch.vertices);
+        frame.indices.write(&batch.indices);
+        frame.rounded_rects.write(&batch.rounded_rects);
+
+        let screen_size = [extent.width as f32, extent.height as f32];
+        let screen_size_bytes = utility::as_bytes(&screen_size);
+
+        for command in batch.commands.iter() {
+            match *command {
+                UiCommand::Sprites {
+                    texture,
+                    index_offset,
+                    index_count,
+                } => {
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.sprite_pipeline);
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.sprite_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        screen_size_bytes,
+                    );
+                    device.cmd_bind_descriptor_sets(
+                        command_buffer,
+                        vk::PipelineBindPoint::GRAPHICS,
+                        self.sprite_layout,
+                        0,
+                        &[texture],
+                        &[],
+                    );
+                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[frame.vertices.buffer], &[0]);
+                    device.cmd_bind_index_buffer(command_buffer, frame.indices.buffer, 0, vk::IndexType::UINT32);
+                    device.cmd_draw_indexed(command_buffer, index_count, 1, index_offset, 0, 0);
+                }
+                UiCommand::RoundedRects {
+                    first_instance,
+                    instance_count,
+                } => {
+                    device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.rect_pipeline);
+                    device.cmd_push_constants(
+                        command_buffer,
+                        self.rect_layout,
+                        vk::ShaderStageFlags::VERTEX,
+                        0,
+                        screen_size_bytes,
+                    );
+                    device.cmd_bind_vertex_buffers(command_buffer, 0, &[frame.rounded_rects.buffer], &[0]);
+                    // the quad is generated from gl_VertexIndex
+                    device.cmd_draw(command_buffer, 6, instance_count, 0, first_instance);
+                }
+            }
+        }
+    }
+
+    /// # Safety
+    /// No frame in flight may use the renderer.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        for frame in self.frames.iter() {
+            frame.vertices.destroy(device);
+            frame.indices.destroy(device);
+            frame.rounded_rects.destroy(device);
+        }
+        device.destroy_pipeline(self.sprite_pipeline, None);
+        device.destroy_pipeline_layout(self.sprite_layout, None);
+        device.destroy_pipeline(self.rect_pipeline, None);
+        device.destroy_pipeline_layout(self.rect_layout, None);
+        device.destroy_descriptor_pool(self.descriptor_pool, None);
+        device.destroy_descriptor_set_layout(self.texture_set_layout, None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprites_past_the_buffers_are_dropped() {
+        let mut batch = UiBatch::new();
+        let white = glm::Vector4::new(1.0, 1.0, 1.0, 1.0);
+        for index in 0..MAX_UI_VERTICES / 4 + 10 {
+            batch.push_sprite(vk::DescriptorSet::null(), Rect::new(index as f32, 0.0, 1.0, 1.0), white);
+        }
+        assert_eq!(batch.vertices.len(), MAX_UI_VERTICES);
+        assert_eq!(batch.indices.len(), MAX_UI_INDICES);
+        assert!(batch.overflowed);
+        match batch.commands[..] {
+            [UiCommand::Sprites { index_count, .. }] => assert_eq!(index_count as usize, MAX_UI_INDICES),
+            _ => panic!("expected one sprite draw"),
+        }
+
+        batch.clear();
+        assert!(!batch.overflowed);
+        batch.push_sprite(vk::DescriptorSet::null(), Rect::new(0.0, 0.0, 1.0, 1.0), white);
+        assert_eq!(batch.indices.len(), 6);
+    }
+}
@@ -0,0 +1,385 @@
+use crate::error::{Error, Result};
+
+use crate::{
+    material::{MaterialInstance, MaterialState, ParamValue, TextureBinding},
+    scene::{NodeId, Scene, Transform},
+};
+
+/// State from before an edit, restoring it swaps in the state from after the edit for redo.
+enum Snapshot {
+    Scene(Scene),
+    /// index into the materials passed to `undo` and `redo`
+    Material(usize, MaterialState),
+}
+
+struct Entry {
+    label: String,
+    snapshots: Vec<Snapshot>,
+}
+
+impl Entry {
+    fn has_scene(&self) -> bool {
+        self.snapshots.iter().any(|snapshot| matches!(snapshot, Snapshot::Scene(_)))
+    }
+
+    fn has_material(&self, index: usize) -> bool {
+        self.snapshots
+            .iter()
+            .any(|snapshot| matches!(snapshot, Snapshot::Material(i, _) if *i == index))
+    }
+}
+
+fn material_mut(materials: &mut [MaterialInstance], index: usize) -> Result<&mut MaterialInstance> {
+    materials
+        .get_mut(index)
+        .ok_or_else(|| Error::msg(format!("No material instance {}", index)))
+}
+
+/// Undo and redo for edits of a scene and its material instances, made through the methods of this type
+/// instead of calling `Scene` and `MaterialInstance` directly.
+/// Every entry keeps a full copy of what it changed, a scene snapshot costs about as much as the scene.
+/// Materials are addressed by their index in the slice the editor keeps them in,
+/// the same slice has to be passed to `undo` and `redo`.
+pub struct UndoStack {
+    undo: Vec<Entry>,
+    redo: Vec<Entry>,
+    /// edits since `begin_group`, they undo as one entry
+    group: Option<Entry>,
+    /// oldest entries are dropped beyond this
+    pub limit: usize,
+}
+
+impl Default for UndoStack {
+    fn default() -> Self {
+        Self::new(100)
+    }
+}
+
+impl UndoStack {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            undo: vec![],
+            redo: vec![],
+            group: None,
+            limit,
+        }
+    }
+
+    /// Collects the following edits into one entry until `end_group`, for example every
+    /// `set_local_transform` of a gizmo drag. Only the state from before the first edit is kept.
+    pub fn begin_group(&mut self, label: &str) {
+        if self.group.is_none() {
+            self.group = Some(Entry {
+                label: label.to_owned(),
+                snapshots: vec![],
+            });
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(entry) = self.group.take() {
+            self.push(entry);
+        }
+    }
+
+    pub fn is_grouping(&self) -> bool {
+        self.group.is_some()
+    }
+
+    fn push(&mut self, entry: Entry) {
+        if entry.snapshots.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.undo.push(entry);
+        if self.undo.len() > self.limit {
+            let excess = self.undo.len() - self.limit;
+            self.undo.drain(..excess);
+        }
+    }
+
+    /// False when the open group already holds the state from before its first edit of the same target.
+    fn needs_snapshot(&self, has: impl Fn(&Entry) -> bool) -> bool {
+        !self.group.as_ref().is_some_and(has)
+    }
+
+    fn record(&mut self, label: &str, snapshot: Snapshot) {
+        match self.group.as_mut() {
+            Some(group) => group.snapshots.push(snapshot),
+            None => self.push(Entry {
+                label: label.to_owned(),
+                snapshots: vec![snapshot],
+            }),
+        }
+    }
+
+    fn snapshot_scene(&mut self, label: &str, scene: &Scene) {
+        if self.needs_snapshot(Entry::has_scene) {
+            self.record(label, Snapshot::Scene(scene.clone()));
+        }
+    }
+
+    /// Runs any scene edit as one undo entry, the scene is restored and nothing recorded when `edit` fails.
+    pub fn edit_scene<R, F: FnOnce(&mut Scene) -> Result<R>>(
+        &mut self,
+        label: &str,
+        scene: &mut Scene,
+        edit: F,
+    ) -> Result<R> {
+        let before = scene.clone();
+        match edit(scene) {
+            Ok(result) => {
+                if self.needs_snapshot(Entry::has_scene) {
+                    self.record(label, Snapshot::Scene(before));
+                }
+                Ok(result)
+            }
+            Err(e) => {
+                *scene = before;
+                Err(e)
+            }
+        }
+    }
+
+    pub fn add_node(
+        &mut self,
+        scene: &mut Scene,
+        name: Option<String>,
+        parent: Option<NodeId>,
+        transform: Transform,
+    ) -> NodeId {
+        self.snapshot_scene("Add node", scene);
+        scene.add_node(name, parent, transform)
+    }
+
+    pub fn remove_node(&mut self, scene: &mut Scene, id: NodeId) {
+        self.snapshot_scene("Remove node", scene);
+        scene.remove_node(id);
+    }
+
+    pub fn set_local_transform(&mut self, scene: &mut Scene, id: NodeId, transform: Transform) {
+        self.snapshot_scene("Move node", scene);
+        scene.set_local_transform(id, transform);
+    }
+
+    pub fn set_parent(&mut self, scene: &mut Scene, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        self.edit_scene("Reparent node", scene, |scene| scene.set_parent(id, parent))
+    }
+
+    pub fn rename_node(&mut self, scene: &mut Scene, id: NodeId, name: Option<String>) {
+        self.snapshot_scene("Rename node", scene);
+        scene.node_mut(id).name = name;
+    }
+
+    pub fn set_param(
+        &mut self,
+        materials: &mut [MaterialInstance],
+        index: usize,
+        name: &str,
+        value: ParamValue,
+    ) -> Result<()> {
+        let material = material_mut(materials, index)?;
+        let before = material.state();
+        material.set_param(name, value)?;
+        if self.needs_snapshot(|group| group.has_material(index)) {
+            self.record("Change material parameter", Snapshot::Material(index, before));
+        }
+        Ok(())
+    }
+
+    pub fn set_texture(
+        &mut self,
+        materials: &mut [MaterialInstance],
+        index: usize,
+        slot: &str,
+        texture: TextureBinding,
+    ) -> Result<()> {
+        let material = material_mut(materials, index)?;
+        let before = material.state();
+        material.set_texture(slot, texture)?;
+        if self.needs_snapshot(|group| group.has_material(index)) {
+            self.record("Change material texture", Snapshot::Material(index, before));
+        }
+        Ok(())
+    }
+
+    /// Reverts the newest entry and returns its label, None when there is nothing to undo.
+    /// An open group is closed first.
+    pub fn undo(&mut self, scene: &mut Scene, materials: &mut [MaterialInstance]) -> Result<Option<String>> {
+        self.end_group();
+        let Some(entry) = self.undo.pop() else {
+            return Ok(None);
+        };
+        let entry = Self::swap(entry, scene, materials)?;
+        let label = entry.label.clone();
+        self.redo.push(entry);
+        Ok(Some(label))
+    }
+
+    /// Applies the newest undone entry again and returns its label.
+    pub fn redo(&mut self, scene: &mut Scene, materials: &mut [MaterialInstance]) -> Result<Option<String>> {
+        self.end_group();
+        let Some(entry) = self.redo.pop() else {
+            return Ok(None);
+        };
+        let entry = Self::swap(entry, scene, materials)?;
+        let label = entry.label.clone();
+        self.undo.push(entry);
+        Ok(Some(label))
+    }
+
+    /// Restores the snapshots of `entry` and returns it holding the replaced states.
+    fn swap(mut entry: Entry, scene: &mut Scene, materials: &mut [MaterialInstance]) -> Result<Entry> {
+        for snapshot in entry.snapshots.iter_mut().rev() {
+            match snapshot {
+                Snapshot::Scene(state) => std::mem::swap(state, scene),
+                Snapshot::Material(index, state) => {
+                    let material = material_mut(materials, *index)?;
+                    let current = material.state();
+                    material.restore(std::mem::replace(state, current))?;
+                }
+            }
+        }
+        Ok(entry)
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || self.group.as_ref().is_some_and(|group| !group.snapshots.is_empty())
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    /// Label of the entry `undo` would revert, for an "Undo Move node" menu item.
+    pub fn undo_label(&self) -> Option<&str> {
+        self.group
+            .as_ref()
+            .filter(|group| !group.snapshots.is_empty())
+            .or(self.undo.last())
+            .map(|entry| entry.label.as_str())
+    }
+
+    pub fn redo_label(&self) -> Option<&str> {
+        self.redo.last().map(|entry| entry.label.as_str())
+    }
+
+    /// Forgets all history, call it after loading another scene.
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.group = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nalgebra as glm;
+
+    use super::*;
+
+    fn moved(x: f32) -> Transform {
+        Transform::from_translation(glm::Vector3::new(x, 0.0, 0.0))
+    }
+
+    fn x(scene: &Scene, id: NodeId) -> f32 {
+        scene.node(id).local_transform().translation.x
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_stacks_in_order() {
+        let mut scene = Scene::new();
+        let mut undo = UndoStack::default();
+        let node = undo.add_node(&mut scene, None, None, moved(0.0));
+        undo.set_local_transform(&mut scene, node, moved(1.0));
+        undo.rename_node(&mut scene, node, Some("box".to_owned()));
+        assert_eq!(undo.undo_label(), Some("Rename node"));
+
+        assert_eq!(undo.undo(&mut scene, &mut []).unwrap().as_deref(), Some("Rename node"));
+        assert_eq!(scene.node(node).name, None);
+        assert_eq!(undo.undo(&mut scene, &mut []).unwrap().as_deref(), Some("Move node"));
+        assert_eq!(x(&scene, node), 0.0);
+        assert_eq!(undo.redo_label(), Some("Move node"));
+        assert_eq!(undo.redo(&mut scene, &mut []).unwrap().as_deref(), Some("Move node"));
+        assert_eq!(x(&scene, node), 1.0);
+
+        assert_eq!(undo.undo(&mut scene, &mut []).unwrap().as_deref(), Some("Move node"));
+        assert_eq!(undo.undo(&mut scene, &mut []).unwrap().as_deref(), Some("Add node"));
+        assert!(!scene.contains(node));
+        assert_eq!(undo.undo(&mut scene, &mut []).unwrap(), None);
+        assert!(!undo.can_undo());
+        assert_eq!(undo.redo(&mut scene, &mut []).unwrap().as_deref(), Some("Add node"));
+        assert!(scene.contains(node));
+    }
+
+    #[test]
+    fn a_new_edit_drops_the_redo_stack() {
+        let mut scene = Scene::new();
+        let node = scene.add_node(None, None, moved(0.0));
+        let mut undo = UndoStack::default();
+        undo.set_local_transform(&mut scene, node, moved(1.0));
+        undo.undo(&mut scene, &mut []).unwrap();
+        assert!(undo.can_redo());
+        undo.set_local_transform(&mut scene, node, moved(2.0));
+        assert!(!undo.can_redo());
+        assert_eq!(undo.redo(&mut scene, &mut []).unwrap(), None);
+        assert_eq!(x(&scene, node), 2.0);
+    }
+
+    #[test]
+    fn a_group_undoes_to_the_state_before_its_first_edit() {
+        let mut scene = Scene::new();
+        let node = scene.add_node(None, None, moved(0.0));
+        let mut undo = UndoStack::default();
+        undo.begin_group("Drag");
+        assert!(!undo.can_undo());
+        for step in 1..=10 {
+            undo.set_local_transform(&mut scene, node, moved(step as f32));
+        }
+        assert_eq!(undo.undo_label(), Some("Drag"));
+
+        // undo closes the open group
+        assert_eq!(undo.undo(&mut scene, &mut []).unwrap().as_deref(), Some("Drag"));
+        assert!(!undo.is_grouping());
+        assert_eq!(x(&scene, node), 0.0);
+        assert!(!undo.can_undo());
+        undo.redo(&mut scene, &mut []).unwrap();
+        assert_eq!(x(&scene, node), 10.0);
+
+        // an empty group leaves no entry
+        undo.begin_group("Nothing");
+        undo.end_group();
+        assert_eq!(undo.undo_label(), Some("Drag"));
+    }
+
+    #[test]
+    fn the_limit_drops_the_oldest_entries() {
+        let mut scene = Scene::new();
+        let node = scene.add_node(None, None, moved(0.0));
+        let mut undo = UndoStack::new(3);
+        for step in 1..=5 {
+            undo.set_local_transform(&mut scene, node, moved(step as f32));
+        }
+        while undo.undo(&mut scene, &mut []).unwrap().is_some() {}
+        assert_eq!(x(&scene, node), 2.0);
+    }
+
+    #[test]
+    fn a_failed_edit_restores_the_scene_and_records_nothing() {
+        let mut scene = Scene::new();
+        let parent = scene.add_node(None, None, moved(0.0));
+        let child = scene.add_node(None, Some(parent), moved(1.0));
+        let mut undo = UndoStack::default();
+        let result = undo.edit_scene("Break", &mut scene, |scene| {
+            scene.set_local_transform(child, moved(5.0));
+            scene.set_parent(parent, Some(child))
+        });
+        assert!(result.is_err());
+        assert_eq!(x(&scene, child), 1.0);
+        assert!(!undo.can_undo());
+
+        assert!(undo.set_parent(&mut scene, parent, Some(child)).is_err());
+        assert!(!undo.can_undo());
+        assert!(undo.undo(&mut scene, &mut []).unwrap().is_none());
+    }
+}
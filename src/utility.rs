@@ -1,10 +1,5 @@
-use anyhow::Result;
-use std::{
-    ffi::CStr,
-    fs::{File},
-    io::{Read},
-    os::raw::c_char,
-};
+use crate::error::Result;
+use std::{ffi::CStr, fs::File, io::Read, os::raw::c_char};
 
 use crate::constant::PATH_TO_PROJECT;
 
@@ -17,15 +12,19 @@ pub fn vk_to_string(raw_string_array: &[c_char]) -> String {
     raw_string.to_str().expect("Failed to convert vulkan raw string.").to_owned()
 }
 
+/// Views a plain old data value as bytes, used for push constants and uniform uploads.
+pub fn as_bytes<T: Copy>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts(value as *const T as *const u8, std::mem::size_of::<T>()) }
+}
+
 pub fn read_file(file_path: &str) -> Result<Vec<u8>> {
-    let path = format!("{}{}", PATH_TO_PROJECT.to_string(), file_path);
+    let path = format!("{}{}", *PATH_TO_PROJECT, file_path);
 
     let mut file = File::open(file_path)?;
     let file_length = file.metadata()?.len();
     let mut buffer = Vec::with_capacity(file_length as usize);
     file.read_to_end(&mut buffer)?;
 
-    println!("bytes: {}", buffer.len());
-    println!("Constant: {}", path);
+    log::trace!("read {} bytes from {}", buffer.len(), path);
     Ok(buffer)
 }
@@ -0,0 +1,238 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use ash::vk;
+
+use crate::device_lost::{self, CrashReport, DeviceLostTracker};
+
+type HangCallback = Box<dyn Fn(&CrashReport) + Send + 'static>;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchdogSettings {
+    /// longest a watched fence may take to signal, below the 2 seconds Windows waits before resetting the GPU
+    pub timeout: Duration,
+    pub poll_interval: Duration,
+    /// where the crash report of a hang is written, None only logs it
+    pub report_directory: Option<PathBuf>,
+}
+
+impl Default for WatchdogSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_millis(1500),
+            poll_interval: Duration::from_millis(100),
+            report_directory: Some(PathBuf::from(".")),
+        }
+    }
+}
+
+impl WatchdogSettings {
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn report_directory(mut self, report_directory: Option<PathBuf>) -> Self {
+        self.report_directory = report_directory;
+        self
+    }
+}
+
+/// Returned by `GpuWatchdog::watch`, hand it back to `signaled`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WatchId(u64);
+
+struct Watch {
+    id: u64,
+    queue: String,
+    passes: Vec<String>,
+    /// null when the caller waits on the work itself and calls `signaled`
+    fence: vk::Fence,
+    since: Instant,
+    reported: bool,
+}
+
+#[derive(Default)]
+struct Shared {
+    watches: Mutex<Vec<Watch>>,
+    on_hang: Mutex<Option<HangCallback>>,
+    stop: Mutex<bool>,
+    wake: Condvar,
+    next_id: AtomicU64,
+}
+
+/// Reports GPU work that doesn't finish within a timeout, from its own thread since the render thread is stuck
+/// waiting on it. The OS resets a hung GPU after a few seconds and often takes the process with it without
+/// `ERROR_DEVICE_LOST` ever being returned, so the crash report is written before that happens.
+pub struct GpuWatchdog {
+    shared: Arc<Shared>,
+    settings: WatchdogSettings,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl GpuWatchdog {
+    /// `queues` are the named queues the report reads NV checkpoints of. Drop the watchdog before destroying
+    /// `device`.
+    pub fn new(
+        instance: &ash::Instance,
+        device: &ash::Device,
+        tracker: Arc<DeviceLostTracker>,
+        queues: &[(&str, vk::Queue)],
+        settings: WatchdogSettings,
+    ) -> Self {
+        let shared = Arc::new(Shared::default());
+        let thread_shared = shared.clone();
+        let thread_settings = settings.clone();
+        let instance = instance.clone();
+        let device = device.clone();
+        let queues: Vec<(String, vk::Queue)> = queues.iter().map(|&(name, queue)| (name.to_owned(), queue)).collect();
+
+        let thread = thread::Builder::new()
+            .name("gpu watchdog".to_owned())
+            .spawn(move || {
+                let shared = thread_shared;
+                let mut stop = shared.stop.lock().unwrap_or_else(|e| e.into_inner());
+                while !*stop {
+                    stop = shared
+                        .wake
+                        .wait_timeout(stop, thread_settings.poll_interval)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0;
+                    if *stop {
+                        break;
+                    }
+                    if let Some(error) = unsafe { check(&shared, &device, thread_settings.timeout) } {
+                        let queues: Vec<(&str, vk::Queue)> =
+                            queues.iter().map(|(name, queue)| (name.as_str(), *queue)).collect();
+                        let report = unsafe { tracker.report(&instance, &device, error, &queues) };
+                        log::error!("{}", report);
+                        if let Some(directory) = thread_settings.report_directory.as_ref() {
+                            match report.write(directory) {
+                                Ok(path) => log::error!("Wrote the crash report to {}", path.display()),
+                                Err(e) => log::error!("Failed to write the crash report: {}", e),
+                            }
+                        }
+                        if let Some(on_hang) = shared.on_hang.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+                            on_hang(&report);
+                        }
+                    }
+                }
+            })
+            .expect("Failed to spawn gpu watchdog thread");
+
+        Self {
+            shared,
+            settings,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn settings(&self) -> &WatchdogSettings {
+        &self.settings
+    }
+
+    /// Called on the watchdog thread after the report of a hang was written, to save state or abort the process.
+    pub fn on_hang<F: Fn(&CrashReport) + Send + 'static>(&self, on_hang: F) {
+        *self.shared.on_hang.lock().unwrap_or_else(|e| e.into_inner()) = Some(Box::new(on_hang));
+    }
+
+    /// Starts the timeout of work submitted to `queue`, call it before waiting on the work and `signaled` once
+    /// the wait returned.
+    pub fn watch(&self, queue: &str, passes: &[&str]) -> WatchId {
+        self.push(queue, passes, vk::Fence::null())
+    }
+
+    /// Starts the timeout of `fence`, the watchdog forgets it on its own once it signaled. Call `signaled` before
+    /// resetting or destroying the fence all the same.
+    pub fn watch_fence(&self, fence: vk::Fence, queue: &str, passes: &[&str]) -> WatchId {
+        self.push(queue, passes, fence)
+    }
+
+    fn push(&self, queue: &str, passes: &[&str], fence: vk::Fence) -> WatchId {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        self.shared.watches.lock().unwrap_or_else(|e| e.into_inner()).push(Watch {
+            id,
+            queue: queue.to_owned(),
+            passes: passes.iter().map(|pass| pass.to_string()).collect(),
+            fence,
+            since: Instant::now(),
+            reported: false,
+        });
+        WatchId(id)
+    }
+
+    pub fn signaled(&self, id: WatchId) {
+        self.shared
+            .watches
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|watch| watch.id != id.0);
+    }
+
+    /// Number of watches that didn't signal yet.
+    pub fn in_flight(&self) -> usize {
+        self.shared.watches.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+impl Drop for GpuWatchdog {
+    fn drop(&mut self) {
+        *self.shared.stop.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.shared.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Forgets the signaled fences and logs the watches that hang, the result to report when a new one does.
+unsafe fn check(shared: &Shared, device: &ash::Device, timeout: Duration) -> Option<vk::Result> {
+    let mut watches = shared.watches.lock().unwrap_or_else(|e| e.into_inner());
+    let mut lost = false;
+    watches.retain(|watch| {
+        if watch.fence == vk::Fence::null() {
+            return true;
+        }
+        match device.get_fence_status(watch.fence) {
+            Ok(signaled) => !signaled,
+            Err(e) => {
+                lost |= device_lost::is_device_lost(e);
+                true
+            }
+        }
+    });
+
+    let mut hung = false;
+    for watch in watches.iter_mut().filter(|watch| !watch.reported) {
+        let elapsed = watch.since.elapsed();
+        if elapsed < timeout && !lost {
+            continue;
+        }
+        watch.reported = true;
+        hung = true;
+        log::error!(
+            "The GPU hangs, {} didn't finish {} after {} ms",
+            watch.queue,
+            watch.passes.join(", "),
+            elapsed.as_millis()
+        );
+    }
+    let error = if lost {
+        vk::Result::ERROR_DEVICE_LOST
+    } else {
+        vk::Result::TIMEOUT
+    };
+    hung.then_some(error)
+}
@@ -0,0 +1,357 @@
+use std::mem::size_of;
+
+use crate::error::Result;
+use ash::vk;
+use nalgebra as glm;
+
+use crate::{
+    frame::FrameUniforms,
+    pipeline::PipelineBuilder,
+    postfx::{EffectComposite, EffectDesc, EffectResolution, PostFxChain},
+    utility,
+};
+
+/// The weather animation clock starts over after this many seconds, which keeps it precise in 32 bit floats.
+const TIME_WRAP: f32 = 10_000.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Precipitation {
+    #[default]
+    Clear,
+    Rain,
+    Snow,
+}
+
+/// How the weather looks and how fast surfaces react to it, changeable at any time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeatherSettings {
+    /// particles at full intensity, fewer are drawn when it rains or snows lightly
+    pub max_particles: u32,
+    /// half width of the box around the camera the particles fall in
+    pub radius: f32,
+    pub height: f32,
+    /// meters per second
+    pub rain_speed: f32,
+    pub snow_speed: f32,
+    /// world units
+    pub rain_streak_length: f32,
+    pub rain_width: f32,
+    pub snow_size: f32,
+    pub rain_color: glm::Vector4<f32>,
+    pub snow_color: glm::Vector4<f32>,
+    /// meters snowflakes drift sideways while falling
+    pub snow_sway: f32,
+    /// seconds of full rain until everything is soaked
+    pub wetting_time: f32,
+    pub drying_time: f32,
+    /// seconds of full snowfall until surfaces facing up are covered
+    pub snow_cover_time: f32,
+    /// rain melts snow twice as fast
+    pub melt_time: f32,
+    /// seconds intensity takes to go from 0 to 1, a change of precipitation fades out first
+    pub transition_time: f32,
+    /// drops on the lens while it rains, they stay for `droplet_fade_time` seconds after it stops
+    pub droplets: bool,
+    pub droplet_fade_time: f32,
+    /// how much of the sun light full precipitation hides behind clouds, 0..1
+    pub overcast_dimming: f32,
+}
+
+impl Default for WeatherSettings {
+    fn default() -> Self {
+        Self {
+            max_particles: 16_384,
+            radius: 20.0,
+            height: 16.0,
+            rain_speed: 9.0,
+            snow_speed: 1.2,
+            rain_streak_length: 0.5,
+            rain_width: 0.012,
+            snow_size: 0.04,
+            rain_color: glm::Vector4::new(0.7, 0.75, 0.8, 0.3),
+            snow_color: glm::Vector4::new(1.0, 1.0, 1.0, 0.9),
+            snow_sway: 0.3,
+            wetting_time: 60.0,
+            drying_time: 300.0,
+            snow_cover_time: 600.0,
+            melt_time: 900.0,
+            transition_time: 5.0,
+            droplets: true,
+            droplet_fade_time: 8.0,
+            overcast_dimming: 0.6,
+        }
+    }
+}
+
+/// Precipitation and its effect on the world, updated every frame with `update`.
+/// Set what it should do with `set`, it fades there over `WeatherSettings::transition_time`.
+#[derive(Clone, Debug, Default)]
+pub struct Weather {
+    pub settings: WeatherSettings,
+    /// meters per second, pushes rain and snow sideways
+    pub wind: glm::Vector3<f32>,
+    precipitation: Precipitation,
+    intensity: f32,
+    target: Precipitation,
+    target_intensity: f32,
+    /// 0..1, lowers the roughness and darkens the albedo of the built-in PBR materials
+    wetness: f32,
+    snow_cover: f32,
+    droplets: f32,
+    time: f32,
+}
+
+impl Weather {
+    pub fn new(settings: WeatherSettings) -> Self {
+        Self {
+            settings,
+            ..Self::default()
+        }
+    }
+
+    /// Fades to `precipitation` at `intensity` 0..1.
+    pub fn set(&mut self, precipitation: Precipitation, intensity: f32) {
+        self.target = precipitation;
+        self.target_intensity = match precipitation {
+            Precipitation::Clear => 0.0,
+            _ => intensity.clamp(0.0, 1.0),
+        };
+    }
+
+    /// Switches without fading, for loading a level that starts in the rain for example.
+    pub fn set_immediately(&mut self, precipitation: Precipitation, intensity: f32) {
+        self.set(precipitation, intensity);
+        self.precipitation = self.target;
+        self.intensity = self.target_intensity;
+    }
+
+    /// Overrides the accumulated state, a level can start soaked or snowed in.
+    pub fn set_surfaces(&mut self, wetness: f32, snow_cover: f32) {
+        self.wetness = wetness.clamp(0.0, 1.0);
+        self.snow_cover = snow_cover.clamp(0.0, 1.0);
+    }
+
+    /// What is falling right now, may still differ from what `set` asked for while fading.
+    pub fn precipitation(&self) -> Precipitation {
+        self.precipitation
+    }
+
+    pub fn intensity(&self) -> f32 {
+        self.intensity
+    }
+
+    pub fn wetness(&self) -> f32 {
+        self.wetness
+    }
+
+    pub fn snow_cover(&self) -> f32 {
+        self.snow_cover
+    }
+
+    /// Strength of the lens drops, 0..1.
+    pub fn droplets(&self) -> f32 {
+        self.droplets
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let settings = &self.settings;
+        let step = dt / settings.transition_time.max(f32::EPSILON);
+        if self.precipitation != self.target {
+            self.intensity = (self.intensity - step).max(0.0);
+            if self.intensity == 0.0 {
+                self.precipitation = self.target;
+            }
+        } else if self.intensity < self.target_intensity {
+            self.intensity = (self.intensity + step).min(self.target_intensity);
+        } else {
+            self.intensity = (self.intensity - step).max(self.target_intensity);
+        }
+
+        let rate = |time: f32| dt / time.max(f32::EPSILON);
+        let (wetting, snowing) = match self.precipitation {
+            Precipitation::Clear => (0.0, 0.0),
+            Precipitation::Rain => (self.intensity, 0.0),
+            Precipitation::Snow => (0.0, self.intensity),
+        };
+        self.wetness = if wetting > 0.0 {
+            self.wetness + wetting * rate(settings.wetting_time)
+        } else {
+            self.wetness - rate(settings.drying_time)
+        }
+        .clamp(0.0, 1.0);
+        self.snow_cover = if snowing > 0.0 {
+            self.snow_cover + snowing * rate(settings.snow_cover_time)
+        } else {
+            self.snow_cover - (1.0 + wetting) * rate(settings.melt_time)
+        }
+        .clamp(0.0, 1.0);
+
+        let rain = if settings.droplets { wetting } else { 0.0 };
+        self.droplets = rain.max(self.droplets - rate(settings.droplet_fade_time)).clamp(0.0, 1.0);
+        self.time = (self.time + dt) % TIME_WRAP;
+    }
+
+    /// Writes wetness, snow cover and the animation time and dims the sun behind the clouds.
+    /// Call it after `TimeOfDay::update`, which sets the light this scales.
+    pub fn write_uniforms(&self, uniforms: &mut FrameUniforms) {
+        let falling = match self.precipitation {
+            Precipitation::Clear => 0.0,
+            _ => self.intensity,
+        };
+        uniforms.weather = glm::Vector4::new(self.wetness, self.snow_cover, falling, self.time);
+        uniforms.light_color.w *= 1.0 - falling * self.settings.overcast_dimming.clamp(0.0, 1.0);
+    }
+
+    /// Particles to draw this frame.
+    pub fn particle_count(&self) -> u32 {
+        match self.precipitation {
+            Precipitation::Clear => 0,
+            _ => (self.settings.max_particles as f32 * self.intensity) as u32,
+        }
+    }
+
+    fn particle_push(&self) -> ParticlePush {
+        let settings = &self.settings;
+        let area = |size: f32| glm::Vector4::new(settings.radius, settings.height, size, 0.0);
+        match self.precipitation {
+            Precipitation::Snow => ParticlePush {
+                color: settings.snow_color,
+                velocity: (self.wind * 0.5 - glm::Vector3::y() * settings.snow_speed).push(0.0),
+                area: area(settings.snow_size),
+                sway: glm::Vector4::new(settings.snow_sway, 1.3, 0.0, 0.0),
+            },
+            _ => ParticlePush {
+                color: settings.rain_color,
+                velocity: (self.wind - glm::Vector3::y() * settings.rain_speed).push(settings.rain_streak_length),
+                area: area(settings.rain_width),
+                sway: glm::Vector4::zeros(),
+            },
+        }
+    }
+
+    /// Turns the effect made from `droplet_effect` on while there are drops and feeds it.
+    pub fn update_droplet_effect(&self, chain: &mut PostFxChain, effect: usize, extent: vk::Extent2D) {
+        chain.set_enabled(effect, self.droplets > 0.0);
+        let push = DropletPush {
+            time: self.time,
+            intensity: self.droplets * 0.35,
+            scale: 9.0,
+            aspect: extent.width as f32 / extent.height.max(1) as f32,
+        };
+        chain.set_push_constants(effect, utility::as_bytes(&push));
+    }
+}
+
+/// Matches the `Particles` push constant block of shaders/weather_particles.vert.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ParticlePush {
+    color: glm::Vector4<f32>,
+    velocity: glm::Vector4<f32>,
+    area: glm::Vector4<f32>,
+    sway: glm::Vector4<f32>,
+}
+
+/// Matches the `Droplets` push constant block of shaders/weather_droplets.frag.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DropletPush {
+    time: f32,
+    intensity: f32,
+    scale: f32,
+    aspect: f32,
+}
+
+/// Post processing effect of water drops running down the lens, add it to a `PostFxChain`
+/// and keep it in sync with `Weather::update_droplet_effect`.
+pub fn droplet_effect() -> EffectDesc {
+    EffectDesc {
+        fragment_shader: "shaders/spv/weather_droplets_frag.spv".to_owned(),
+        resolution: EffectResolution::Full,
+        composite: EffectComposite::Replace,
+        push_constant_size: size_of::<DropletPush>() as u32,
+    }
+}
+
+/// Rain streaks and snowflakes falling in a box that follows the camera.
+/// The particles are placed in the vertex shader from their index and the time, there is no simulation state,
+/// so they don't collide with the scene. Draw it after the opaque geometry, it tests depth without writing it.
+pub struct WeatherPass {
+    pub pipeline: vk::Pipeline,
+    pub pipeline_layout: vk::PipelineLayout,
+}
+
+impl WeatherPass {
+    /// `frame_set_layout` is the layout of `FrameData`, the particles read the camera and time from it at set 0.
+    ///
+    /// # Safety
+    /// `render_pass` has to outlive the pass, `frame_set_layout` has to be a layout of `device`.
+    pub unsafe fn new(
+        device: &ash::Device,
+        render_pass: vk::RenderPass,
+        frame_set_layout: vk::DescriptorSetLayout,
+    ) -> Result<WeatherPass> {
+        let (pipeline, pipeline_layout) = PipelineBuilder::new(
+            "shaders/spv/weather_particles_vert.spv",
+            "shaders/spv/weather_particles_frag.spv",
+        )
+        .cull_mode(vk::CullModeFlags::NONE, vk::FrontFace::COUNTER_CLOCKWISE)
+        .alpha_blending(true)
+        .depth_test(true, false)
+        .descriptor_set_layouts(&[frame_set_layout])
+        .push_constant_range(
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            size_of::<ParticlePush>() as u32,
+        )
+        .build(device, render_pass)?;
+        Ok(WeatherPass {
+            pipeline,
+            pipeline_layout,
+        })
+    }
+
+    /// Draws the particles inside the current render pass, nothing is recorded while it is clear.
+    ///
+    /// # Safety
+    /// `command_buffer` has to be inside a render pass compatible with the one of `new`,
+    /// `frame_set` has to be written for `FrameData`.
+    pub unsafe fn record(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        frame_set: vk::DescriptorSet,
+        weather: &Weather,
+    ) {
+        let count = weather.particle_count();
+        if count == 0 {
+            return;
+        }
+        device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+        device.cmd_bind_descriptor_sets(
+            command_buffer,
+            vk::PipelineBindPoint::GRAPHICS,
+            self.pipeline_layout,
+            0,
+            &[frame_set],
+            &[],
+        );
+        let push = weather.particle_push();
+        device.cmd_push_constants(
+            command_buffer,
+            self.pipeline_layout,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+            0,
+            utility::as_bytes(&push),
+        );
+        // two triangles per particle
+        device.cmd_draw(command_buffer, 6, count, 0, 0);
+    }
+
+    /// # Safety
+    /// No frame in flight may draw the particles.
+    pub unsafe fn destroy(&self, device: &ash::Device) {
+        device.destroy_pipeline(self.pipeline, None);
+        device.destroy_pipeline_layout(self.pipeline_layout, None);
+    }
+}